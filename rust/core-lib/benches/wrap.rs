@@ -18,11 +18,15 @@ extern crate test;
 extern crate xi_core_lib as xi_core;
 extern crate xi_rope;
 
+use crate::xi_core::client::Client;
 use crate::xi_core::line_offset::LineOffset;
+use crate::xi_core::selection::InsertDrift;
 use crate::xi_core::tabs::BufferId;
 use crate::xi_core::view::View;
+use crate::xi_core::width_cache::WidthCache;
 use test::Bencher;
-use xi_rope::Rope;
+use xi_rope::{DeltaBuilder, Rope};
+use xi_rpc::test_utils::DummyPeer;
 
 fn build_short_lines(n: usize) -> String {
     let line =
@@ -85,3 +89,50 @@ fn offset_of_line_col_breaks(b: &mut Bencher) {
         }
     })
 }
+
+#[bench]
+fn rewrap_500k_lines_full(b: &mut Bencher) {
+    let text = Rope::from(build_short_lines(500_000));
+    let mut view = View::new(1.into(), BufferId::new(2));
+
+    b.iter(|| {
+        view.debug_force_rewrap_cols(&text, 20);
+    })
+}
+
+/// A single line of about 1MB, like minified JSON or a long log line.
+fn build_long_line() -> String {
+    "{\"key\": \"value\", \"n\": 12345}, ".repeat(30_000)
+}
+
+#[bench]
+fn rewrap_long_line_full(b: &mut Bencher) {
+    let text = Rope::from(build_long_line());
+    let mut view = View::new(1.into(), BufferId::new(2));
+
+    b.iter(|| {
+        view.debug_force_rewrap_cols(&text, 80);
+    })
+}
+
+#[bench]
+fn keystroke_long_line(b: &mut Bencher) {
+    let text = Rope::from(build_long_line());
+    let mut view = View::new(1.into(), BufferId::new(2));
+    view.debug_force_rewrap_cols(&text, 80);
+    let client = Client::new(Box::new(DummyPeer));
+    let mut width_cache = WidthCache::new();
+
+    let mut builder = DeltaBuilder::new(text.len());
+    builder.replace(text.len() / 2..text.len() / 2, "x".into());
+    let insert = builder.build();
+    let inserted = insert.apply(&text);
+    let mut builder = DeltaBuilder::new(inserted.len());
+    builder.delete(text.len() / 2..text.len() / 2 + 1);
+    let delete = builder.build();
+
+    b.iter(|| {
+        view.after_edit(&inserted, &text, &insert, &client, &mut width_cache, InsertDrift::Default);
+        view.after_edit(&text, &inserted, &delete, &client, &mut width_cache, InsertDrift::Default);
+    })
+}
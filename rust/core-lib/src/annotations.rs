@@ -24,23 +24,192 @@ use crate::line_offset::LineOffset;
 use crate::plugins::PluginId;
 use crate::view::View;
 use crate::xi_rope::spans::Spans;
-use crate::xi_rope::{Interval, Rope};
+use crate::xi_rope::{Interval, Rope, RopeDelta};
 
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum AnnotationType {
     Selection,
     Find,
-    Other(String),
+    Bookmark,
+    /// The text of an input method composition, at the selections it
+    /// will replace.
+    Composition,
+    /// Ranges of a highlight kind registered with the view.
+    Highlight(HighlightKindId),
+    /// A type defined by a plugin, such as "lsp.diagnostics", which core
+    /// forwards to clients without interpreting. It is serialized as its
+    /// name; names of the other types are not custom types.
+    Custom(String),
 }
 
 impl AnnotationType {
     fn as_str(&self) -> &str {
         match self {
-            AnnotationType::Find => "find",
-            AnnotationType::Selection => "selection",
-            AnnotationType::Other(ref s) => s,
+            AnnotationType::Bookmark => "bookmark",
+            AnnotationType::Composition => "composition",
+            AnnotationType::Custom(ref s) => s,
+            _ => match self.highlight_kind() {
+                Some(HighlightKindId(id)) if id < BUILTIN_HIGHLIGHT_KINDS.len() => {
+                    BUILTIN_HIGHLIGHT_KINDS[id]
+                }
+                _ => "highlight",
+            },
         }
     }
+
+    /// Returns the highlight kind these annotations are drawn as, if any.
+    pub fn highlight_kind(&self) -> Option<HighlightKindId> {
+        match self {
+            AnnotationType::Selection => Some(HighlightKindId::SELECTION),
+            AnnotationType::Find => Some(HighlightKindId::FIND),
+            AnnotationType::Highlight(id) => Some(*id),
+            _ => None,
+        }
+    }
+}
+
+/// The serialized form of an `AnnotationType`. Custom types are sent as
+/// their name; highlights keep serde's externally tagged form.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum AnnotationTypeRepr {
+    Name(String),
+    Tagged(TaggedAnnotationType),
+}
+
+#[derive(Serialize, Deserialize)]
+enum TaggedAnnotationType {
+    Highlight(HighlightKindId),
+    /// How custom types were serialized before they were sent by name.
+    Other(String),
+}
+
+impl Serialize for AnnotationType {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let repr = match self {
+            AnnotationType::Selection => AnnotationTypeRepr::Name("Selection".into()),
+            AnnotationType::Find => AnnotationTypeRepr::Name("Find".into()),
+            AnnotationType::Bookmark => AnnotationTypeRepr::Name("Bookmark".into()),
+            AnnotationType::Composition => AnnotationTypeRepr::Name("Composition".into()),
+            AnnotationType::Highlight(id) => {
+                AnnotationTypeRepr::Tagged(TaggedAnnotationType::Highlight(*id))
+            }
+            AnnotationType::Custom(name) => AnnotationTypeRepr::Name(name.clone()),
+        };
+        repr.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for AnnotationType {
+    fn deserialize<D>(deserializer: D) -> Result<AnnotationType, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let annotation_type = match AnnotationTypeRepr::deserialize(deserializer)? {
+            // the names sent to clients are also taken for the built-in
+            // types, so that a custom type can't pass for one of them
+            AnnotationTypeRepr::Name(name) => match name.as_str() {
+                "Selection" | "selection" => AnnotationType::Selection,
+                "Find" | "find" => AnnotationType::Find,
+                "Bookmark" | "bookmark" => AnnotationType::Bookmark,
+                "Composition" | "composition" => AnnotationType::Composition,
+                _ => AnnotationType::Custom(name),
+            },
+            AnnotationTypeRepr::Tagged(TaggedAnnotationType::Highlight(id)) => {
+                AnnotationType::Highlight(id)
+            }
+            AnnotationTypeRepr::Tagged(TaggedAnnotationType::Other(name)) => {
+                AnnotationType::Custom(name)
+            }
+        };
+        Ok(annotation_type)
+    }
+}
+
+/// The names of the highlight kinds every view starts out with, in id order.
+const BUILTIN_HIGHLIGHT_KINDS: [&str; 2] = ["selection", "find"];
+
+/// Identifies a kind of highlight registered with a view.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct HighlightKindId(pub(crate) usize);
+
+impl HighlightKindId {
+    pub const SELECTION: HighlightKindId = HighlightKindId(0);
+    pub const FIND: HighlightKindId = HighlightKindId(1);
+}
+
+/// A named class of highlights, such as symbol occurrences, that clients
+/// can theme separately from syntax styles.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct HighlightKind {
+    pub id: HighlightKindId,
+    pub name: String,
+    /// Suggested styling, for clients without a theme entry for `name`.
+    pub style_hints: Value,
+    /// The plugin that registered this kind; `None` for core's own kinds.
+    #[serde(skip)]
+    owner: Option<PluginId>,
+}
+
+/// The highlight kinds registered with a view. Selections and find matches
+/// are pre-registered.
+#[derive(Debug, Clone)]
+pub struct HighlightKinds {
+    kinds: Vec<HighlightKind>,
+}
+
+impl HighlightKinds {
+    pub fn new() -> Self {
+        let kinds = BUILTIN_HIGHLIGHT_KINDS
+            .iter()
+            .enumerate()
+            .map(|(id, name)| HighlightKind {
+                id: HighlightKindId(id),
+                name: (*name).to_owned(),
+                style_hints: Value::Null,
+                owner: None,
+            })
+            .collect();
+        HighlightKinds { kinds }
+    }
+
+    /// Registers a highlight kind for `owner`, returning its id. Registering
+    /// a name the plugin already registered updates its style hints and
+    /// returns the existing id.
+    pub fn register(&mut self, owner: PluginId, name: &str, style_hints: Value) -> HighlightKindId {
+        if let Some(kind) = self.kinds.iter_mut().find(|k| k.owner == Some(owner) && k.name == name)
+        {
+            kind.style_hints = style_hints;
+            return kind.id;
+        }
+        let id = HighlightKindId(self.kinds.len());
+        self.kinds.push(HighlightKind {
+            id,
+            name: name.to_owned(),
+            style_hints,
+            owner: Some(owner),
+        });
+        id
+    }
+
+    pub fn get(&self, id: HighlightKindId) -> Option<&HighlightKind> {
+        self.kinds.get(id.0)
+    }
+
+    /// Returns `true` if `plugin` registered the kind `id`, and so may
+    /// submit ranges for it.
+    pub fn is_owned_by(&self, id: HighlightKindId, plugin: PluginId) -> bool {
+        self.get(id).map(|k| k.owner == Some(plugin)).unwrap_or(false)
+    }
+}
+
+impl Default for HighlightKinds {
+    fn default() -> Self {
+        HighlightKinds::new()
+    }
 }
 
 /// Location and range of an annotation ([start_line, start_col, end_line, end_col]).
@@ -101,6 +270,18 @@ impl Annotations {
     pub fn invalidate(&mut self, interval: Interval) {
         self.items.delete_after(interval);
     }
+
+    /// Updates the annotations for an edit. Highlights that cover the whole
+    /// document move with the text around them; other annotations after the
+    /// edit are removed, since only their source knows what they should become.
+    pub fn apply_edit(&mut self, delta: &RopeDelta) {
+        if self.annotation_type.highlight_kind().is_some() && self.items.len() == delta.base_len {
+            self.items.apply_shape(delta);
+        } else {
+            let (iv, _) = delta.summary();
+            self.invalidate(iv);
+        }
+    }
 }
 
 /// A region of an `Annotation`.
@@ -111,6 +292,8 @@ pub struct AnnotationSlice {
     ranges: Vec<AnnotationRange>,
     /// If present, one payload per range.
     payloads: Option<Vec<Value>>,
+    /// The highlight kind of these annotations, for clients that theme them.
+    kind: Option<HighlightKind>,
 }
 
 impl AnnotationSlice {
@@ -119,17 +302,66 @@ impl AnnotationSlice {
         ranges: Vec<AnnotationRange>,
         payloads: Option<Vec<Value>>,
     ) -> Self {
-        AnnotationSlice { annotation_type, ranges, payloads }
+        AnnotationSlice { annotation_type, ranges, payloads, kind: None }
+    }
+
+    /// Attaches the description of this slice's highlight kind, if it has
+    /// one, so that it is sent to the client.
+    pub fn with_kind(mut self, kinds: &HighlightKinds) -> Self {
+        self.kind = self.annotation_type.highlight_kind().and_then(|id| kinds.get(id)).cloned();
+        self
     }
 
     /// Returns json representation.
     pub fn to_json(&self) -> Value {
-        json!({
+        let mut json = json!({
             "type": self.annotation_type.as_str(),
             "ranges": self.ranges,
             "payloads": self.payloads,
             "n": self.ranges.len()
-        })
+        });
+        if let Some(kind) = self.kind.as_ref() {
+            json["kind"] = json!(kind);
+        }
+        json
+    }
+}
+
+/// Where a set of annotations comes from. Clients that retain annotations
+/// across updates use this to match up the sets in successive updates.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum AnnotationSource {
+    Selection,
+    /// The find query with the given id.
+    Find(usize),
+    Bookmarks,
+    Composition,
+    Plugin(PluginId, AnnotationType),
+}
+
+impl AnnotationSource {
+    /// Returns the identifier sent to the client.
+    pub fn key(&self) -> String {
+        match self {
+            AnnotationSource::Selection => "selection".into(),
+            AnnotationSource::Find(id) => format!("find-{}", id),
+            AnnotationSource::Bookmarks => "bookmarks".into(),
+            AnnotationSource::Composition => "composition".into(),
+            AnnotationSource::Plugin(plugin, ty) => format!("{}-{}", plugin, ty.as_str()),
+        }
+    }
+
+    /// Returns the annotations from this source as sent to the client, with
+    /// `None` standing for annotations the client has already been sent.
+    pub fn to_json(&self, slice: Option<AnnotationSlice>) -> Value {
+        match slice {
+            Some(slice) => {
+                let mut json = slice.to_json();
+                json["source"] = json!(self.key());
+                json
+            }
+            None => json!({ "source": self.key(), "unchanged": true }),
+        }
     }
 }
 
@@ -143,20 +375,48 @@ pub trait ToAnnotation {
 /// All the annotations for a given view
 pub struct AnnotationStore {
     store: HashMap<PluginId, Vec<Annotations>>,
+    /// For each set of annotations, the value of `generation` when it last
+    /// changed.
+    generations: HashMap<AnnotationSource, u64>,
+    generation: u64,
 }
 
 impl AnnotationStore {
     pub fn new() -> Self {
-        AnnotationStore { store: HashMap::new() }
+        AnnotationStore { store: HashMap::new(), generations: HashMap::new(), generation: 0 }
     }
 
-    /// Invalidates and removes all annotations in the range of the interval.
-    pub fn invalidate(&mut self, interval: Interval) {
-        self.store.values_mut().flat_map(|v| v.iter_mut()).for_each(|a| a.invalidate(interval));
+    /// Returns the generation of the annotations from `source`. This changes
+    /// whenever they do.
+    pub fn generation(&self, source: &AnnotationSource) -> u64 {
+        self.generations.get(source).cloned().unwrap_or(0)
+    }
+
+    fn mark_changed(&mut self, plugin: PluginId) {
+        let sources = self
+            .store
+            .get(&plugin)
+            .into_iter()
+            .flatten()
+            .map(|a| AnnotationSource::Plugin(plugin, a.annotation_type.clone()));
+        self.generation += 1;
+        for source in sources.collect::<Vec<_>>() {
+            self.generations.insert(source, self.generation);
+        }
+    }
+
+    /// Updates all annotations for an edit; see `Annotations::apply_edit`.
+    pub fn apply_edit(&mut self, delta: &RopeDelta) {
+        self.store.values_mut().flat_map(|v| v.iter_mut()).for_each(|a| a.apply_edit(delta));
+        let plugins = self.store.keys().cloned().collect::<Vec<_>>();
+        plugins.into_iter().for_each(|plugin| self.mark_changed(plugin));
     }
 
     /// Applies an update from a plugin to a set of annotations
     pub fn update(&mut self, source: PluginId, interval: Interval, item: Annotations) {
+        self.generation += 1;
+        let key = AnnotationSource::Plugin(source, item.annotation_type.clone());
+        self.generations.insert(key, self.generation);
         if !self.store.contains_key(&source) {
             self.store.insert(source, vec![item]);
             return;
@@ -179,8 +439,8 @@ impl AnnotationStore {
         view: &'c View,
         text: &'c Rope,
         interval: Interval,
-    ) -> impl Iterator<Item = AnnotationSlice> + 'c {
-        self.store.iter().flat_map(move |(_plugin, value)| {
+    ) -> impl Iterator<Item = (AnnotationSource, AnnotationSlice)> + 'c {
+        self.store.iter().flat_map(move |(&plugin, value)| {
             value.iter().map(move |annotation| {
                 // .filter() used instead of .subseq() because subseq() filters out spans with length 0
                 let payloads = annotation
@@ -202,18 +462,22 @@ impl AnnotationStore {
                     })
                     .collect::<Vec<AnnotationRange>>();
 
-                AnnotationSlice {
-                    annotation_type: annotation.annotation_type.clone(),
+                let source = AnnotationSource::Plugin(plugin, annotation.annotation_type.clone());
+                let slice = AnnotationSlice::new(
+                    annotation.annotation_type.clone(),
                     ranges,
-                    payloads: Some(payloads),
-                }
+                    Some(payloads),
+                );
+                (source, slice)
             })
         })
     }
 
-    /// Removes any annotations provided by this plugin
-    pub fn clear(&mut self, plugin: PluginId) {
-        self.store.remove(&plugin);
+    /// Removes any annotations provided by this plugin. Returns `true` if
+    /// there were any.
+    pub fn clear(&mut self, plugin: PluginId) -> bool {
+        self.mark_changed(plugin);
+        self.store.remove(&plugin).is_some()
     }
 }
 
@@ -240,11 +504,7 @@ mod tests {
     fn test_annotation_slice_json() {
         let range = AnnotationRange { start_line: 1, start_col: 3, end_line: 4, end_col: 1 };
 
-        let slice = AnnotationSlice {
-            annotation_type: AnnotationType::Find,
-            ranges: vec![range],
-            payloads: None,
-        };
+        let slice = AnnotationSlice::new(AnnotationType::Find, vec![range], None);
 
         assert_eq!(
             slice.to_json().to_string(),
@@ -252,6 +512,204 @@ mod tests {
         )
     }
 
+    #[test]
+    fn test_highlight_kind_registration() {
+        let mut kinds = HighlightKinds::new();
+        assert_eq!(kinds.get(HighlightKindId::SELECTION).unwrap().name, "selection");
+        assert_eq!(kinds.get(HighlightKindId::FIND).unwrap().name, "find");
+
+        let hints = json!({"underline": true});
+        let id = kinds.register(PluginPid(1), "occurrences", hints.clone());
+        assert_eq!(kinds.get(id).unwrap().style_hints, hints);
+        assert!(kinds.is_owned_by(id, PluginPid(1)));
+        assert!(!kinds.is_owned_by(id, PluginPid(2)));
+        assert!(!kinds.is_owned_by(HighlightKindId::FIND, PluginPid(1)));
+
+        // registering again updates the hints but keeps the id
+        assert_eq!(kinds.register(PluginPid(1), "occurrences", json!(null)), id);
+        assert_eq!(kinds.get(id).unwrap().style_hints, json!(null));
+        // other plugins get their own kind
+        assert_ne!(kinds.register(PluginPid(2), "occurrences", json!(null)), id);
+    }
+
+    #[test]
+    fn test_highlight_slice_json() {
+        let mut kinds = HighlightKinds::new();
+        let id = kinds.register(PluginPid(1), "heat", json!({"background": "#ff000040"}));
+        let range = AnnotationRange { start_line: 0, start_col: 1, end_line: 0, end_col: 2 };
+        let slice = AnnotationSlice::new(AnnotationType::Highlight(id), vec![range], None);
+        assert_eq!(slice.to_json()["type"], json!("highlight"));
+        assert!(slice.to_json().get("kind").is_none());
+
+        let json = slice.with_kind(&kinds).to_json();
+        assert_eq!(
+            json["kind"],
+            json!({"id": 2, "name": "heat", "style_hints": {"background": "#ff000040"}})
+        );
+
+        let selection = AnnotationSlice::new(AnnotationType::Selection, vec![], None);
+        let json = selection.with_kind(&kinds).to_json();
+        assert_eq!(json["type"], json!("selection"));
+        assert_eq!(json["kind"]["id"], json!(0));
+    }
+
+    #[test]
+    fn test_highlight_kinds_isolated() {
+        let mut store = AnnotationStore::new();
+        let (a, b) = (HighlightKindId(2), HighlightKindId(3));
+        let spans = |iv: Interval| {
+            let mut sb = SpansBuilder::new(10);
+            sb.add_span(iv, json!(null));
+            sb.build()
+        };
+        let ranges = |store: &AnnotationStore, kind| {
+            store.store[&PluginPid(1)]
+                .iter()
+                .find(|a| a.annotation_type == AnnotationType::Highlight(kind))
+                .unwrap()
+                .items
+                .iter()
+                .map(|(iv, _)| iv)
+                .collect::<Vec<_>>()
+        };
+
+        let items = spans(Interval::new(1, 3));
+        store.update(
+            PluginPid(1),
+            Interval::new(0, 10),
+            Annotations { items, annotation_type: AnnotationType::Highlight(a) },
+        );
+        let items = spans(Interval::new(5, 7));
+        store.update(
+            PluginPid(1),
+            Interval::new(0, 10),
+            Annotations { items, annotation_type: AnnotationType::Highlight(b) },
+        );
+        let generation =
+            store.generation(&AnnotationSource::Plugin(PluginPid(1), AnnotationType::Highlight(a)));
+
+        // replacing the ranges of one kind leaves the other alone
+        let items = spans(Interval::new(8, 9));
+        store.update(
+            PluginPid(1),
+            Interval::new(0, 10),
+            Annotations { items, annotation_type: AnnotationType::Highlight(b) },
+        );
+        assert_eq!(ranges(&store, a), vec![Interval::new(1, 3)]);
+        assert_eq!(ranges(&store, b), vec![Interval::new(8, 9)]);
+        assert_eq!(
+            store.generation(&AnnotationSource::Plugin(PluginPid(1), AnnotationType::Highlight(a))),
+            generation
+        );
+    }
+
+    #[test]
+    fn test_highlights_follow_edits() {
+        use crate::xi_rope::DeltaBuilder;
+
+        let mut store = AnnotationStore::new();
+        let mut sb = SpansBuilder::new(10);
+        sb.add_span(Interval::new(6, 8), json!(null));
+        let kind = AnnotationType::Highlight(HighlightKindId(2));
+        store.update(
+            PluginPid(1),
+            Interval::new(0, 10),
+            Annotations { items: sb.build(), annotation_type: kind },
+        );
+        let mut sb = SpansBuilder::new(10);
+        sb.add_span(Interval::new(4, 6), json!(null));
+        store.update(
+            PluginPid(1),
+            Interval::new(0, 10),
+            Annotations {
+                items: sb.build(),
+                annotation_type: AnnotationType::Custom("lint".into()),
+            },
+        );
+
+        // replace two characters with three, before the highlight
+        let mut builder = DeltaBuilder::new(10);
+        builder.replace(Interval::new(3, 5), Rope::from("xyz"));
+        store.apply_edit(&builder.build());
+
+        let annotations = &store.store[&PluginPid(1)];
+        let highlights =
+            annotations.iter().find(|a| a.annotation_type.highlight_kind().is_some()).unwrap();
+        assert_eq!(
+            highlights.items.iter().map(|(iv, _)| iv).collect::<Vec<_>>(),
+            vec![Interval::new(7, 9)]
+        );
+        let lints =
+            annotations.iter().find(|a| a.annotation_type.highlight_kind().is_none()).unwrap();
+        assert_eq!(lints.items.iter().count(), 0);
+    }
+
+    #[test]
+    fn test_annotation_type_serialization() {
+        let custom = AnnotationType::Custom("lsp.diagnostics".into());
+        assert_eq!(serde_json::to_value(&custom).unwrap(), json!("lsp.diagnostics"));
+        assert_eq!(serde_json::to_value(&AnnotationType::Find).unwrap(), json!("Find"));
+        let highlight = AnnotationType::Highlight(HighlightKindId(2));
+        assert_eq!(serde_json::to_value(&highlight).unwrap(), json!({"Highlight": 2}));
+
+        let types = [
+            AnnotationType::Selection,
+            AnnotationType::Bookmark,
+            AnnotationType::Composition,
+            highlight,
+            custom,
+        ];
+        for ty in types.iter() {
+            let json = serde_json::to_value(ty).unwrap();
+            assert_eq!(&serde_json::from_value::<AnnotationType>(json).unwrap(), ty);
+        }
+        // a custom type can't take the name of a built-in one
+        let find: AnnotationType = serde_json::from_value(json!("find")).unwrap();
+        assert_eq!(find, AnnotationType::Find);
+        let lint: AnnotationType = serde_json::from_value(json!({"Other": "lint"})).unwrap();
+        assert_eq!(lint, AnnotationType::Custom("lint".into()));
+    }
+
+    #[test]
+    fn test_custom_types_per_plugin() {
+        let mut store = AnnotationStore::new();
+        let diagnostics = AnnotationType::Custom("lsp.diagnostics".into());
+        for (plugin, iv) in
+            vec![(PluginPid(1), Interval::new(1, 3)), (PluginPid(2), Interval::new(5, 7))]
+        {
+            let mut sb = SpansBuilder::new(10);
+            sb.add_span(iv, json!({ "plugin": plugin.0 }));
+            let items = sb.build();
+            store.update(
+                plugin,
+                Interval::new(0, 10),
+                Annotations { items, annotation_type: diagnostics.clone() },
+            );
+        }
+        let items = |store: &AnnotationStore, plugin: PluginId| {
+            store.store[&plugin]
+                .iter()
+                .flat_map(|a| a.items.iter().map(|(iv, p)| (iv, p.clone())))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            items(&store, PluginPid(1)),
+            vec![(Interval::new(1, 3), json!({ "plugin": 1 }))]
+        );
+        assert_eq!(
+            items(&store, PluginPid(2)),
+            vec![(Interval::new(5, 7), json!({ "plugin": 2 }))]
+        );
+
+        let source = AnnotationSource::Plugin(PluginPid(1), diagnostics);
+        assert_eq!(source.key(), "plugin-1-lsp.diagnostics");
+        let generation = store.generation(&source);
+        assert!(store.clear(PluginPid(1)));
+        assert!(!store.clear(PluginPid(1)));
+        assert!(store.generation(&source) > generation);
+        assert_eq!(items(&store, PluginPid(2)).len(), 1);
+    }
+
     #[test]
     fn test_annotation_store_update() {
         let mut store = AnnotationStore::new();
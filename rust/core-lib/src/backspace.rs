@@ -13,23 +13,52 @@
 // limitations under the License.
 
 //! Calc start of a backspace delete interval
-use xi_rope::{Cursor, Rope};
+use xi_rope::{Cursor, Interval, Rope};
 
 use crate::config::BufferItems;
 use crate::line_offset::{LineOffset, LogicalLines};
 use crate::selection::SelRegion;
 use xi_unicode::*;
 
+/// If `delete_empty_pairs` is set and `region` is a caret between the two
+/// halves of an empty pair from `empty_pairs`, such as `(|)`, returns the
+/// interval covering the pair, so that backspace deletes both halves.
+pub fn empty_pair_around(
+    region: &SelRegion,
+    text: &Rope,
+    config: &BufferItems,
+) -> Option<Interval> {
+    if !config.delete_empty_pairs || !region.is_caret() {
+        return None;
+    }
+    let caret = region.start;
+    config.empty_pairs.iter().find_map(|(open, close)| {
+        if open.is_empty() || caret < open.len() || caret + close.len() > text.len() {
+            return None;
+        }
+        let iv = Interval::new(caret - open.len(), caret + close.len());
+        if !text.is_codepoint_boundary(iv.start()) || !text.is_codepoint_boundary(iv.end()) {
+            return None;
+        }
+        let found = text.slice_to_cow(iv);
+        if found.starts_with(open.as_str()) && found.ends_with(close.as_str()) {
+            Some(iv)
+        } else {
+            None
+        }
+    })
+}
+
 #[allow(clippy::cognitive_complexity)]
 pub fn offset_for_delete_backwards(region: &SelRegion, text: &Rope, config: &BufferItems) -> usize {
     if !region.is_caret() {
         region.min()
     } else {
-        // backspace deletes max(1, tab_size) contiguous spaces
+        // backspace deletes max(1, indent_size) contiguous spaces
         let (_, c) = LogicalLines.offset_to_line_col(text, region.start);
 
-        let tab_off = c % config.tab_size;
-        let tab_size = config.tab_size;
+        let tab_size = config.indent_size();
+        let tab_off = c % tab_size;
         let tab_size = if tab_off == 0 { tab_size } else { tab_off };
         let tab_start = region.start.saturating_sub(tab_size);
         let preceded_by_spaces =
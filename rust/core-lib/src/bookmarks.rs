@@ -0,0 +1,309 @@
+// Copyright 2020 The xi-editor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Line bookmarks, shared by all the views of a buffer.
+
+use crate::annotations::{AnnotationRange, AnnotationSlice, AnnotationType, ToAnnotation};
+use crate::line_offset::LineOffset;
+use crate::truncate::truncate_graphemes;
+use crate::view::View;
+use xi_rope::{DeltaElement, Interval, LinesMetric, Rope, RopeDelta, Transformer};
+
+/// The maximum number of grapheme clusters of a bookmarked line included in
+/// its snippet.
+const MAX_SNIPPET_CHARS: usize = 80;
+
+/// The set of bookmarked lines in a buffer.
+///
+/// Each bookmark is stored as the offset of the start of its (logical) line,
+/// and is transformed through every delta applied to the buffer, so that it
+/// stays attached to its line as the text around it changes.
+#[derive(Debug, Clone)]
+pub struct Bookmarks {
+    /// Offsets of the start of each bookmarked line. Invariant: sorted and
+    /// free of duplicates.
+    offsets: Vec<usize>,
+    /// Whether a bookmark whose line is deleted moves to the nearest
+    /// surviving line, rather than being removed.
+    keep_deleted: bool,
+    /// Bumped whenever the bookmarks change.
+    generation: u64,
+}
+
+impl Default for Bookmarks {
+    fn default() -> Bookmarks {
+        Bookmarks { offsets: Vec::new(), keep_deleted: true, generation: 0 }
+    }
+}
+
+impl Bookmarks {
+    pub fn is_empty(&self) -> bool {
+        self.offsets.is_empty()
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Returns the offsets of the starts of all bookmarked lines, in order.
+    pub fn offsets(&self) -> &[usize] {
+        &self.offsets
+    }
+
+    /// Sets whether bookmarks on deleted lines survive the deletion.
+    pub(crate) fn set_keep_deleted(&mut self, keep_deleted: bool) {
+        self.keep_deleted = keep_deleted;
+    }
+
+    /// Adds a bookmark to the line containing `offset`, or removes it if the
+    /// line is already bookmarked.
+    pub fn toggle(&mut self, text: &Rope, offset: usize) {
+        let line_start = text.offset_of_line(text.line_of_offset(offset));
+        match self.offsets.binary_search(&line_start) {
+            Ok(ix) => {
+                self.offsets.remove(ix);
+            }
+            Err(ix) => self.offsets.insert(ix, line_start),
+        }
+        self.generation += 1;
+    }
+
+    pub fn clear(&mut self) {
+        self.offsets.clear();
+        self.generation += 1;
+    }
+
+    /// Returns the start of the first bookmarked line after the line containing
+    /// `offset`, wrapping around to the first bookmark in the buffer.
+    pub fn next(&self, text: &Rope, offset: usize) -> Option<usize> {
+        let line_start = text.offset_of_line(text.line_of_offset(offset));
+        self.offsets.iter().find(|&&off| off > line_start).or_else(|| self.offsets.first()).cloned()
+    }
+
+    /// Returns the start of the last bookmarked line before the line containing
+    /// `offset`, wrapping around to the last bookmark in the buffer.
+    pub fn previous(&self, text: &Rope, offset: usize) -> Option<usize> {
+        let line_start = text.offset_of_line(text.line_of_offset(offset));
+        self.offsets
+            .iter()
+            .rev()
+            .find(|&&off| off < line_start)
+            .or_else(|| self.offsets.last())
+            .cloned()
+    }
+
+    /// Returns the text of the line starting at `offset`, trimmed and
+    /// truncated, for display in a list of bookmarks.
+    pub fn snippet(text: &Rope, offset: usize) -> String {
+        let line = text.line_of_offset(offset);
+        let end = text.offset_of_line(line + 1);
+        let line_text = text.slice_to_cow(offset..end);
+        truncate_graphemes(line_text.trim(), MAX_SNIPPET_CHARS).to_owned()
+    }
+
+    /// Updates the bookmarks for an edit. `last_text` is the buffer before the
+    /// edit, and `text` the buffer after it.
+    pub(crate) fn apply_delta(&mut self, delta: &RopeDelta, last_text: &Rope, text: &Rope) {
+        if self.offsets.is_empty() {
+            return;
+        }
+        let mut transformer = Transformer::new(delta);
+        let mut new_offsets: Vec<usize> = Vec::with_capacity(self.offsets.len());
+        for &offset in &self.offsets {
+            if !self.keep_deleted && line_is_deleted(delta, last_text, offset) {
+                continue;
+            }
+            // Landing after inserts keeps the bookmark on its line when text is
+            // inserted above it; the line start is then recomputed in the new text.
+            let new_offset = transformer.transform(offset, true);
+            let line_start = text.offset_of_line(text.line_of_offset(new_offset));
+            if new_offsets.last() != Some(&line_start) {
+                new_offsets.push(line_start);
+            }
+        }
+        self.offsets = new_offsets;
+        self.generation += 1;
+    }
+}
+
+/// Returns `true` if the delta removes the entire line starting at `offset`
+/// in `last_text`, including its line ending.
+fn line_is_deleted(delta: &RopeDelta, last_text: &Rope, offset: usize) -> bool {
+    let n_lines = last_text.measure::<LinesMetric>();
+    let line = last_text.line_of_offset(offset);
+    let iv = if line < n_lines {
+        Interval::new(offset, last_text.offset_of_line(line + 1))
+    } else if offset > 0 {
+        // The last line has no line ending of its own, so it is only gone if
+        // the line ending before it is deleted as well.
+        Interval::new(offset - 1, last_text.len())
+    } else {
+        // The only line of the buffer can never be deleted.
+        return false;
+    };
+    !delta.els.iter().any(|el| match *el {
+        DeltaElement::Copy(beg, end) => beg < iv.end() && end > iv.start(),
+        DeltaElement::Insert(_) => false,
+    })
+}
+
+/// Implementing the `ToAnnotation` trait allows to convert bookmarks to annotations.
+impl ToAnnotation for Bookmarks {
+    fn get_annotations(&self, interval: Interval, view: &View, text: &Rope) -> AnnotationSlice {
+        let ranges = self
+            .offsets
+            .iter()
+            .filter(|&&off| off >= interval.start() && off <= interval.end())
+            .map(|&off| {
+                let (line, col) = view.offset_to_line_col(text, off);
+                AnnotationRange { start_line: line, start_col: col, end_line: line, end_col: col }
+            })
+            .collect::<Vec<AnnotationRange>>();
+        AnnotationSlice::new(AnnotationType::Bookmark, ranges, None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tabs::BufferId;
+    use xi_rope::DeltaBuilder;
+
+    fn edit(bookmarks: &mut Bookmarks, text: &mut Rope, iv: Interval, new: &str) {
+        let mut builder = DeltaBuilder::new(text.len());
+        builder.replace(iv, new.into());
+        let delta = builder.build();
+        let last_text = text.clone();
+        *text = delta.apply(&last_text);
+        bookmarks.apply_delta(&delta, &last_text, text);
+    }
+
+    fn lines(bookmarks: &Bookmarks, text: &Rope) -> Vec<usize> {
+        bookmarks.offsets().iter().map(|&off| text.line_of_offset(off)).collect()
+    }
+
+    #[test]
+    fn toggle() {
+        let text = Rope::from("one\ntwo\nthree");
+        let mut bookmarks = Bookmarks::default();
+        bookmarks.toggle(&text, 6);
+        bookmarks.toggle(&text, 0);
+        assert_eq!(bookmarks.offsets(), &[0, 4]);
+        bookmarks.toggle(&text, 4);
+        assert_eq!(bookmarks.offsets(), &[0]);
+        bookmarks.clear();
+        assert!(bookmarks.is_empty());
+    }
+
+    #[test]
+    fn edits_above_and_on_line() {
+        let mut text = Rope::from("one\ntwo\nthree\n");
+        let mut bookmarks = Bookmarks::default();
+        bookmarks.toggle(&text, 4);
+
+        // insert lines above
+        edit(&mut bookmarks, &mut text, Interval::new(0, 0), "zero\nhalf\n");
+        assert_eq!(lines(&bookmarks, &text), vec![3]);
+        assert_eq!(Bookmarks::snippet(&text, bookmarks.offsets()[0]), "two");
+
+        // edit within the bookmarked line, including at its start
+        let start = bookmarks.offsets()[0];
+        edit(&mut bookmarks, &mut text, Interval::new(start, start), "  ");
+        edit(&mut bookmarks, &mut text, Interval::new(start + 3, start + 4), "");
+        assert_eq!(lines(&bookmarks, &text), vec![3]);
+        assert_eq!(Bookmarks::snippet(&text, bookmarks.offsets()[0]), "to");
+
+        // delete lines above
+        edit(&mut bookmarks, &mut text, Interval::new(0, 10), "");
+        assert_eq!(lines(&bookmarks, &text), vec![1]);
+        assert_eq!(String::from(&text), "one\n  to\nthree\n");
+
+        // join the bookmarked line with the line above
+        edit(&mut bookmarks, &mut text, Interval::new(3, 4), "");
+        assert_eq!(lines(&bookmarks, &text), vec![0]);
+    }
+
+    #[test]
+    fn deleted_line_moves_to_nearest() {
+        let mut text = Rope::from("one\ntwo\nthree\nfour");
+        let mut bookmarks = Bookmarks::default();
+        bookmarks.toggle(&text, 4);
+        bookmarks.toggle(&text, 8);
+        bookmarks.toggle(&text, 14);
+
+        // deleting "two\nthree\n" collapses both bookmarks onto "four"
+        edit(&mut bookmarks, &mut text, Interval::new(4, 14), "");
+        assert_eq!(String::from(&text), "one\nfour");
+        assert_eq!(lines(&bookmarks, &text), vec![1]);
+
+        // deleting the last line moves its bookmark to the line above
+        edit(&mut bookmarks, &mut text, Interval::new(3, 8), "");
+        assert_eq!(lines(&bookmarks, &text), vec![0]);
+    }
+
+    #[test]
+    fn deleted_line_removed() {
+        let mut text = Rope::from("one\ntwo\nthree\nfour");
+        let mut bookmarks = Bookmarks::default();
+        bookmarks.set_keep_deleted(false);
+        bookmarks.toggle(&text, 0);
+        bookmarks.toggle(&text, 4);
+        bookmarks.toggle(&text, 14);
+
+        // emptying a line does not delete it
+        edit(&mut bookmarks, &mut text, Interval::new(0, 3), "");
+        assert_eq!(lines(&bookmarks, &text), vec![0, 1, 3]);
+
+        edit(&mut bookmarks, &mut text, Interval::new(1, 5), "");
+        assert_eq!(String::from(&text), "\nthree\nfour");
+        assert_eq!(lines(&bookmarks, &text), vec![0, 2]);
+
+        edit(&mut bookmarks, &mut text, Interval::new(6, 11), "");
+        assert_eq!(lines(&bookmarks, &text), vec![0]);
+    }
+
+    #[test]
+    fn navigation_wraps() {
+        let text = Rope::from("a\nb\nc\nd\n");
+        let mut bookmarks = Bookmarks::default();
+        assert_eq!(bookmarks.next(&text, 0), None);
+        bookmarks.toggle(&text, 2);
+        bookmarks.toggle(&text, 6);
+
+        assert_eq!(bookmarks.next(&text, 0), Some(2));
+        assert_eq!(bookmarks.next(&text, 3), Some(6));
+        assert_eq!(bookmarks.next(&text, 6), Some(2));
+        assert_eq!(bookmarks.previous(&text, 7), Some(2));
+        assert_eq!(bookmarks.previous(&text, 2), Some(6));
+        assert_eq!(bookmarks.previous(&text, 0), Some(6));
+    }
+
+    #[test]
+    fn annotations() {
+        let text = Rope::from("one\ntwo\nthree\n");
+        let view = View::new(1.into(), BufferId::new(2));
+        let mut bookmarks = Bookmarks::default();
+        bookmarks.toggle(&text, 5);
+        bookmarks.toggle(&text, 9);
+
+        let slice = bookmarks.get_annotations(Interval::new(0, text.len()), &view, &text);
+        assert_eq!(
+            slice.to_json().to_string(),
+            "{\"n\":2,\"payloads\":null,\"ranges\":[[1,0,1,0],[2,0,2,0]],\"type\":\"bookmark\"}"
+        );
+
+        let slice = bookmarks.get_annotations(Interval::new(0, 3), &view, &text);
+        assert_eq!(slice.to_json()["n"], json!(0));
+    }
+}
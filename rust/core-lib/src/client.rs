@@ -14,29 +14,56 @@
 
 //! Requests and notifications from the core to front-ends.
 
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::Path;
 use std::time::Instant;
 
 use serde_json::{self, Value};
 use xi_rpc::{self, RpcPeer};
 
 use crate::config::Table;
-use crate::plugins::rpc::ClientPluginInfo;
+use crate::file::{CharacterEncoding, SaveErrorKind};
+use crate::plugins::rpc::{ClientPluginInfo, CompletionItem, CompletionList};
 use crate::plugins::Command;
+use crate::rpc::ClientCapability;
 use crate::styles::ThemeSettings;
 use crate::syntax::LanguageId;
 use crate::tabs::ViewId;
+use crate::truncate::truncate_with_ellipsis;
+use crate::view::LinePosition;
 use crate::width_cache::{WidthReq, WidthResponse};
 
+/// The maximum width, in columns, of an alert message. Longer messages are
+/// cut short, with an ellipsis.
+const MAX_ALERT_COLS: usize = 500;
+
+/// The maximum width, in columns, of the value of a status item.
+const MAX_STATUS_ITEM_COLS: usize = 80;
+
 /// An interface to the frontend.
-pub struct Client(RpcPeer);
+pub struct Client {
+    peer: RpcPeer,
+    capabilities: Vec<ClientCapability>,
+    /// The `seq` of the last plugin notification sent for each view.
+    plugin_seqs: RefCell<HashMap<ViewId, u64>>,
+}
 
 impl Client {
     pub fn new(peer: RpcPeer) -> Self {
-        Client(peer)
+        Client { peer, capabilities: Vec::new(), plugin_seqs: RefCell::new(HashMap::new()) }
+    }
+
+    pub(crate) fn set_capabilities(&mut self, capabilities: Vec<ClientCapability>) {
+        self.capabilities = capabilities;
+    }
+
+    pub fn has_capability(&self, capability: ClientCapability) -> bool {
+        self.capabilities.contains(&capability)
     }
 
     pub fn update_view(&self, view_id: ViewId, update: &Update) {
-        self.0.send_rpc_notification(
+        self.peer.send_rpc_notification(
             "update",
             &json!({
                 "view_id": view_id,
@@ -46,7 +73,7 @@ impl Client {
     }
 
     pub fn scroll_to(&self, view_id: ViewId, line: usize, col: usize) {
-        self.0.send_rpc_notification(
+        self.peer.send_rpc_notification(
             "scroll_to",
             &json!({
                 "view_id": view_id,
@@ -57,7 +84,7 @@ impl Client {
     }
 
     pub fn config_changed(&self, view_id: ViewId, changes: &Table) {
-        self.0.send_rpc_notification(
+        self.peer.send_rpc_notification(
             "config_changed",
             &json!({
                 "view_id": view_id,
@@ -67,15 +94,15 @@ impl Client {
     }
 
     pub fn available_themes(&self, theme_names: Vec<String>) {
-        self.0.send_rpc_notification("available_themes", &json!({ "themes": theme_names }))
+        self.peer.send_rpc_notification("available_themes", &json!({ "themes": theme_names }))
     }
 
     pub fn available_languages(&self, languages: Vec<LanguageId>) {
-        self.0.send_rpc_notification("available_languages", &json!({ "languages": languages }))
+        self.peer.send_rpc_notification("available_languages", &json!({ "languages": languages }))
     }
 
     pub fn theme_changed(&self, name: &str, theme: &ThemeSettings) {
-        self.0.send_rpc_notification(
+        self.peer.send_rpc_notification(
             "theme_changed",
             &json!({
                 "name": name,
@@ -85,7 +112,7 @@ impl Client {
     }
 
     pub fn language_changed(&self, view_id: ViewId, new_lang: &LanguageId) {
-        self.0.send_rpc_notification(
+        self.peer.send_rpc_notification(
             "language_changed",
             &json!({
                 "view_id": view_id,
@@ -94,13 +121,67 @@ impl Client {
         );
     }
 
+    pub fn bom_changed(&self, view_id: ViewId, present: bool) {
+        self.peer.send_rpc_notification(
+            "bom_changed",
+            &json!({
+                "view_id": view_id,
+                "present": present,
+            }),
+        );
+    }
+
+    /// Notify the client of the encoding of the file of the view's buffer,
+    /// and whether it had invalid bytes that were replaced when it was loaded.
+    pub fn encoding_changed(&self, view_id: ViewId, encoding: CharacterEncoding, lossy: bool) {
+        self.peer.send_rpc_notification(
+            "encoding_changed",
+            &json!({
+                "view_id": view_id,
+                "encoding": encoding,
+                "lossy": lossy,
+            }),
+        );
+    }
+
+    /// Notify the client that saving the view's buffer to `path` failed.
+    pub fn save_failed(&self, view_id: ViewId, path: &Path, kind: SaveErrorKind, message: &str) {
+        self.peer.send_rpc_notification(
+            "save_failed",
+            &json!({
+                "view_id": view_id,
+                "path": path,
+                "error": {
+                    "kind": kind,
+                    "message": message,
+                },
+            }),
+        );
+    }
+
+    /// Returns the `seq` of the next plugin notification for the view.
+    /// Plugin notifications for a view are numbered in the order they are
+    /// sent, so that clients can tell which is the latest.
+    fn next_plugin_seq(&self, view_id: ViewId) -> u64 {
+        let mut plugin_seqs = self.plugin_seqs.borrow_mut();
+        let seq = plugin_seqs.entry(view_id).or_insert(0);
+        *seq += 1;
+        *seq
+    }
+
+    /// Forgets the plugin notifications sent for a closed view.
+    pub(crate) fn view_closed(&self, view_id: ViewId) {
+        self.plugin_seqs.borrow_mut().remove(&view_id);
+    }
+
     /// Notify the client that a plugin has started.
     pub fn plugin_started(&self, view_id: ViewId, plugin: &str) {
-        self.0.send_rpc_notification(
+        self.peer.send_rpc_notification(
             "plugin_started",
             &json!({
                 "view_id": view_id,
                 "plugin": plugin,
+                "seq": self.next_plugin_seq(view_id),
             }),
         );
     }
@@ -110,28 +191,31 @@ impl Client {
     /// `code` is not currently used; in the future may be used to
     /// pass an exit code.
     pub fn plugin_stopped(&self, view_id: ViewId, plugin: &str, code: i32) {
-        self.0.send_rpc_notification(
+        self.peer.send_rpc_notification(
             "plugin_stopped",
             &json!({
                 "view_id": view_id,
                 "plugin": plugin,
                 "code": code,
+                "seq": self.next_plugin_seq(view_id),
             }),
         );
     }
 
     /// Notify the client of the available plugins.
     pub fn available_plugins(&self, view_id: ViewId, plugins: &[ClientPluginInfo]) {
-        self.0.send_rpc_notification(
+        self.peer.send_rpc_notification(
             "available_plugins",
             &json!({
                 "view_id": view_id,
-                "plugins": plugins }),
+                "plugins": plugins,
+                "seq": self.next_plugin_seq(view_id),
+            }),
         );
     }
 
     pub fn update_cmds(&self, view_id: ViewId, plugin: &str, cmds: &[Command]) {
-        self.0.send_rpc_notification(
+        self.peer.send_rpc_notification(
             "update_cmds",
             &json!({
                 "view_id": view_id,
@@ -142,11 +226,11 @@ impl Client {
     }
 
     pub fn def_style(&self, style: &Value) {
-        self.0.send_rpc_notification("def_style", style)
+        self.peer.send_rpc_notification("def_style", style)
     }
 
     pub fn find_status(&self, view_id: ViewId, queries: &Value) {
-        self.0.send_rpc_notification(
+        self.peer.send_rpc_notification(
             "find_status",
             &json!({
                 "view_id": view_id,
@@ -156,7 +240,7 @@ impl Client {
     }
 
     pub fn replace_status(&self, view_id: ViewId, replace: &Value) {
-        self.0.send_rpc_notification(
+        self.peer.send_rpc_notification(
             "replace_status",
             &json!({
                 "view_id": view_id,
@@ -168,12 +252,13 @@ impl Client {
     /// Ask front-end to measure widths of strings.
     pub fn measure_width(&self, reqs: &[WidthReq]) -> Result<WidthResponse, xi_rpc::Error> {
         let req_json = serde_json::to_value(reqs).expect("failed to serialize width req");
-        let resp = self.0.send_rpc_request("measure_width", &req_json)?;
+        let resp = self.peer.send_rpc_request("measure_width", &req_json)?;
         Ok(serde_json::from_value(resp).expect("failed to deserialize width response"))
     }
 
     pub fn alert<S: AsRef<str>>(&self, msg: S) {
-        self.0.send_rpc_notification("alert", &json!({ "msg": msg.as_ref() }));
+        let msg = truncate_with_ellipsis(msg.as_ref(), MAX_ALERT_COLS);
+        self.peer.send_rpc_notification("alert", &json!({ "msg": msg }));
     }
 
     pub fn add_status_item(
@@ -184,7 +269,8 @@ impl Client {
         value: &str,
         alignment: &str,
     ) {
-        self.0.send_rpc_notification(
+        let value = truncate_with_ellipsis(value, MAX_STATUS_ITEM_COLS);
+        self.peer.send_rpc_notification(
             "add_status_item",
             &json!({
                 "view_id": view_id,
@@ -197,7 +283,8 @@ impl Client {
     }
 
     pub fn update_status_item(&self, view_id: ViewId, key: &str, value: &str) {
-        self.0.send_rpc_notification(
+        let value = truncate_with_ellipsis(value, MAX_STATUS_ITEM_COLS);
+        self.peer.send_rpc_notification(
             "update_status_item",
             &json!({
                 "view_id": view_id,
@@ -208,7 +295,7 @@ impl Client {
     }
 
     pub fn remove_status_item(&self, view_id: ViewId, key: &str) {
-        self.0.send_rpc_notification(
+        self.peer.send_rpc_notification(
             "remove_status_item",
             &json!({
                 "view_id": view_id,
@@ -217,23 +304,78 @@ impl Client {
         );
     }
 
-    pub fn show_hover(&self, view_id: ViewId, request_id: usize, result: String) {
-        self.0.send_rpc_notification(
+    /// Sends the result of a hover request. `range` holds the positions of
+    /// the start and end of the hovered text, if known.
+    pub fn show_hover(
+        &self,
+        view_id: ViewId,
+        request_id: usize,
+        result: String,
+        range: Option<(LinePosition, LinePosition)>,
+    ) {
+        let range = range.map(|(start, end)| json!({ "start": start, "end": end }));
+        self.peer.send_rpc_notification(
             "show_hover",
             &json!({
                 "view_id": view_id,
                 "request_id": request_id,
                 "result": result,
+                "range": range,
+            }),
+        )
+    }
+
+    /// Sends the completions found for a `request_completions` request.
+    pub fn show_completions(&self, view_id: ViewId, request_id: usize, list: &CompletionList) {
+        self.peer.send_rpc_notification(
+            "show_completions",
+            &json!({
+                "view_id": view_id,
+                "request_id": request_id,
+                "is_incomplete": list.is_incomplete,
+                "items": list.items,
+            }),
+        )
+    }
+
+    /// Sends the details of the completion at `index` of the list sent for
+    /// `request_id`.
+    pub fn show_resolved_completion(
+        &self,
+        view_id: ViewId,
+        request_id: usize,
+        index: usize,
+        item: &CompletionItem,
+    ) {
+        self.peer.send_rpc_notification(
+            "show_resolved_completion",
+            &json!({
+                "view_id": view_id,
+                "request_id": request_id,
+                "index": index,
+                "item": item,
+            }),
+        )
+    }
+
+    /// Asks the client to display a view it did not open itself, such as
+    /// a scratch view created by a plugin.
+    pub fn show_view(&self, view_id: ViewId, title: &str) {
+        self.peer.send_rpc_notification(
+            "show_view",
+            &json!({
+                "view_id": view_id,
+                "title": title,
             }),
         )
     }
 
     pub fn schedule_idle(&self, token: usize) {
-        self.0.schedule_idle(token)
+        self.peer.schedule_idle(token)
     }
 
     pub fn schedule_timer(&self, timeout: Instant, token: usize) {
-        self.0.schedule_timer(timeout, token);
+        self.peer.schedule_timer(timeout, token);
     }
 }
 
@@ -242,6 +384,19 @@ pub struct Update {
     pub(crate) ops: Vec<UpdateOp>,
     pub(crate) pristine: bool,
     pub(crate) annotations: Vec<Value>,
+    /// Only sent to clients with the `prefetch_hints` capability.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) prefetch_hint: Option<PrefetchHint>,
+}
+
+/// Advises the client how many lines beyond the visible ones to keep in its
+/// line cache, based on how fast the view is scrolling.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct PrefetchHint {
+    /// Lines after the last visible line.
+    pub lines_ahead: usize,
+    /// Lines before the first visible line.
+    pub lines_behind: usize,
 }
 
 #[derive(Debug, Serialize)]
@@ -280,11 +435,19 @@ impl UpdateOp {
             first_line_number: line_opt,
         }
     }
+
+    pub(crate) fn op_type(&self) -> OpType {
+        self.op
+    }
+
+    pub(crate) fn n(&self) -> usize {
+        self.n
+    }
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
 #[serde(rename_all = "lowercase")]
-enum OpType {
+pub enum OpType {
     #[serde(rename = "ins")]
     Insert,
     Skip,
@@ -0,0 +1,95 @@
+// Copyright 2020 The xi-editor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The state of an input method composition in a view.
+//!
+//! While an input method is composing, its preedit text is not part of the
+//! buffer; it is sent to the client as a `"composition"` annotation at each
+//! selection, and only inserted, as a single edit, when it is committed.
+
+use crate::annotations::{AnnotationRange, AnnotationSlice, AnnotationType, ToAnnotation};
+use crate::line_offset::LineOffset;
+use crate::view::View;
+use xi_rope::{Interval, Rope};
+
+/// The preedit text of a composition, and the position of the input
+/// method's caret within it.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Composition {
+    text: String,
+    caret: usize,
+}
+
+impl Composition {
+    pub fn new() -> Self {
+        Composition::default()
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// The byte offset of the caret in the preedit text.
+    pub fn caret(&self) -> usize {
+        self.caret
+    }
+
+    /// Replaces the preedit text. A caret past its end, or inside a
+    /// character, is moved back to the nearest character boundary.
+    pub fn update(&mut self, text: String, caret: usize) {
+        let mut caret = caret.min(text.len());
+        while !text.is_char_boundary(caret) {
+            caret -= 1;
+        }
+        self.text = text;
+        self.caret = caret;
+    }
+}
+
+impl ToAnnotation for Composition {
+    /// Returns the selections the composition will replace when committed,
+    /// each with the preedit text as its payload.
+    fn get_annotations(&self, interval: Interval, view: &View, text: &Rope) -> AnnotationSlice {
+        let ranges = view
+            .sel_regions()
+            .iter()
+            .filter(|region| region.max() >= interval.start() && region.min() <= interval.end())
+            .map(|region| {
+                let (start_line, start_col) = view.offset_to_line_col(text, region.min());
+                let (end_line, end_col) = view.offset_to_line_col(text, region.max());
+                AnnotationRange { start_line, start_col, end_line, end_col }
+            })
+            .collect::<Vec<AnnotationRange>>();
+        let payload = json!({ "text": self.text, "caret": self.caret });
+        let payloads = vec![payload; ranges.len()];
+        AnnotationSlice::new(AnnotationType::Composition, ranges, Some(payloads))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn caret_on_char_boundary() {
+        let mut composition = Composition::new();
+        composition.update("\u{304B}\u{306A}".into(), 2);
+        assert_eq!(composition.text(), "\u{304B}\u{306A}");
+        assert_eq!(composition.caret(), 0);
+        composition.update("\u{304B}\u{306A}".into(), 3);
+        assert_eq!(composition.caret(), 3);
+        composition.update("ab".into(), 10);
+        assert_eq!(composition.caret(), 2);
+    }
+}
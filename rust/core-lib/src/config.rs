@@ -12,7 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 use std::error::Error;
 use std::fmt;
 use std::fs;
@@ -59,12 +59,20 @@ fn load_base_config() -> Table {
 /// A map of config keys to settings
 pub type Table = serde_json::Map<String, Value>;
 
+/// The name of a project config file. The settings in the nearest one found
+/// in the directories containing a buffer's file apply to that buffer.
+pub(crate) const PROJECT_CONFIG_FILE_NAME: &str = "xi-config.toml";
+
 /// A `ConfigDomain` describes a level or category of user settings.
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum ConfigDomain {
     /// The general user preferences
     General,
+    /// The settings in a project config file, identified by its path. Only
+    /// used internally.
+    #[serde(skip_deserializing)]
+    Project(PathBuf),
     /// The overrides for a particular syntax.
     Language(LanguageId),
     /// The user overrides for a particular buffer
@@ -130,6 +138,8 @@ pub struct ConfigManager {
     buffer_tags: HashMap<BufferId, LanguageTag>,
     /// The configs for any open buffers
     buffer_configs: HashMap<BufferId, BufferConfig>,
+    /// The project config file used by each buffer that has one.
+    buffer_projects: HashMap<BufferId, PathBuf>,
     /// If using file-based config, this is the base config directory
     /// (perhaps `$HOME/.config/xi`, by default).
     config_dir: Option<PathBuf>,
@@ -152,6 +162,10 @@ pub struct Config<T> {
     source: TableStack,
     /// The settings themselves, deserialized into some concrete type.
     pub items: T,
+    /// Incremented each time the settings of a buffer change, so that
+    /// work done under an older config can be recognized.
+    #[serde(skip)]
+    generation: u64,
 }
 
 fn deserialize_tab_size<'de, D>(deserializer: D) -> Result<usize, D::Error>
@@ -169,12 +183,35 @@ where
     }
 }
 
+fn deserialize_optional_tab_size<'de, D>(deserializer: D) -> Result<Option<usize>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    deserialize_tab_size(deserializer).map(Some)
+}
+
 /// The concrete type for buffer-related settings.
 #[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
 pub struct BufferItems {
     pub line_ending: String,
     #[serde(deserialize_with = "deserialize_tab_size")]
     pub tab_size: usize,
+    /// The width, in columns, at which tab characters are displayed.
+    /// Falls back to `tab_size` if unset.
+    #[serde(
+        default,
+        deserialize_with = "deserialize_optional_tab_size",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub tab_display_width: Option<usize>,
+    /// The number of columns inserted or removed by indent and outdent.
+    /// Falls back to `tab_size` if unset.
+    #[serde(
+        default,
+        deserialize_with = "deserialize_optional_tab_size",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub indent_size: Option<usize>,
     pub translate_tabs_to_spaces: bool,
     pub use_tab_stops: bool,
     pub font_face: String,
@@ -185,11 +222,65 @@ pub struct BufferItems {
     pub word_wrap: bool,
     pub autodetect_whitespace: bool,
     pub surrounding_pairs: Vec<(String, String)>,
+    /// Whether backspace between the halves of an empty pair from
+    /// `empty_pairs`, such as `(|)`, deletes both of them.
+    pub delete_empty_pairs: bool,
+    /// The pairs whose halves backspace deletes together when empty. These
+    /// are kept apart from `surrounding_pairs`, which wrap selections.
+    pub empty_pairs: Vec<(String, String)>,
     pub save_with_newline: bool,
+    /// Whether a buffer is formatted by a plugin with the `formatting`
+    /// capability before it is saved.
+    pub format_on_save: bool,
+    pub keep_bookmarks_on_deleted_lines: bool,
+    /// Whether pasted text has its line endings converted to `line_ending`.
+    pub normalize_pasted_line_endings: bool,
+    /// Pastes longer than this many bytes are inserted in chunks of this
+    /// size, across several runloop passes. Zero disables chunking.
+    pub paste_chunk_size: usize,
+    /// The maximum number of matches returned by `find_all`.
+    pub find_all_max_results: usize,
+    /// The fewest lines beyond the viewport the client is advised to keep,
+    /// for clients that take prefetch hints.
+    pub prefetch_min_lines: usize,
+    /// The most lines beyond the viewport the client is advised to keep,
+    /// however fast the view scrolls.
+    pub prefetch_max_lines: usize,
+    /// Handling an edit for longer than this many milliseconds logs a
+    /// warning. Zero disables the warning.
+    pub slow_edit_warn_ms: u64,
+    /// How long after the last edit, in milliseconds, a modified buffer is
+    /// saved to its file. Zero disables autosave.
+    pub autosave_delay_ms: u64,
+    /// Pairs of file name patterns used by `toggle_related_file`, such as
+    /// `["*.cpp", "*.h"]`. See `file::related_files`.
+    pub related_files: Vec<(String, String)>,
+    /// Whether every plugin is started when core starts, as if its manifest
+    /// set `preload`.
+    pub preload_plugins: bool,
+    /// How long, in milliseconds, a preloaded plugin is kept running before
+    /// any buffer is opened. Zero keeps it running.
+    pub warm_plugin_timeout_ms: u64,
+    /// Whether an edit or movement during an input method composition
+    /// commits it first. Otherwise such commands are ignored until the
+    /// composition ends.
+    pub commit_interrupted_composition: bool,
 }
 
 pub type BufferConfig = Config<BufferItems>;
 
+impl BufferItems {
+    /// The width, in columns, of a displayed tab character.
+    pub fn tab_display_width(&self) -> usize {
+        self.tab_display_width.unwrap_or(self.tab_size)
+    }
+
+    /// The width, in columns, of a single level of indentation.
+    pub fn indent_size(&self) -> usize {
+        self.indent_size.unwrap_or(self.tab_size)
+    }
+}
+
 impl ConfigPair {
     /// Creates a new `ConfigPair` with the provided base config.
     fn with_base<T: Into<Option<Table>>>(table: T) -> Self {
@@ -247,6 +338,7 @@ impl ConfigManager {
             configs: defaults,
             buffer_tags: HashMap::new(),
             buffer_configs: HashMap::new(),
+            buffer_projects: HashMap::new(),
             languages: Languages::default(),
             config_dir,
             extras_dir,
@@ -275,7 +367,7 @@ impl ConfigManager {
 
     /// Adds a new buffer to the config manager, and returns the initial config
     /// `Table` for that buffer. The `path` argument is used to determine
-    /// the buffer's default language.
+    /// the buffer's default language, and to find its project config file.
     ///
     /// # Note: The caller is responsible for ensuring the config manager is
     /// notified every time a buffer is added or removed.
@@ -288,10 +380,11 @@ impl ConfigManager {
             path.and_then(|p| self.language_for_path(p)).unwrap_or(LanguageId::from("Plain Text"));
         let lang_tag = LanguageTag::new(lang);
         assert!(self.buffer_tags.insert(id, lang_tag).is_none());
+        self.set_buffer_project(id, path);
         self.update_buffer_config(id).expect("new buffer must always have config")
     }
 
-    /// Updates the default language for the given buffer.
+    /// Updates the default language and project config for the given buffer.
     ///
     /// # Panics:
     ///
@@ -299,9 +392,10 @@ impl ConfigManager {
     pub(crate) fn update_buffer_path(&mut self, id: BufferId, path: &Path) -> Option<Table> {
         assert!(self.buffer_tags.contains_key(&id));
         let lang = self.language_for_path(path).unwrap_or_default();
-        let has_changed = self.buffer_tags.get_mut(&id).map(|tag| tag.set_detected(lang)).unwrap();
+        let lang_changed = self.buffer_tags.get_mut(&id).map(|tag| tag.set_detected(lang)).unwrap();
+        let project_changed = self.set_buffer_project(id, Some(path));
 
-        if has_changed {
+        if lang_changed || project_changed {
             self.update_buffer_config(id)
         } else {
             None
@@ -316,9 +410,73 @@ impl ConfigManager {
     pub(crate) fn remove_buffer(&mut self, id: BufferId) {
         self.buffer_tags.remove(&id).expect("remove key must exist");
         self.buffer_configs.remove(&id);
+        self.set_buffer_project(id, None);
         // TODO: remove any overrides
     }
 
+    /// Finds the project config file for a buffer whose file is at `path`,
+    /// loading it if no other buffer uses it. Returns `true` if the buffer's
+    /// project config file has changed.
+    fn set_buffer_project(&mut self, id: BufferId, path: Option<&Path>) -> bool {
+        let project = path.and_then(find_project_config);
+        let previous = match project.as_ref() {
+            Some(project) => self.buffer_projects.insert(id, project.to_owned()),
+            None => self.buffer_projects.remove(&id),
+        };
+        if previous == project {
+            return false;
+        }
+
+        if let Some(project) = project {
+            let domain = ConfigDomain::Project(project.clone());
+            if !self.configs.contains_key(&domain) {
+                let mut pair = ConfigPair::with_base(None);
+                pair.set_table(self.load_project_config(&project));
+                self.configs.insert(domain, pair);
+            }
+        }
+        if let Some(previous) = previous {
+            if !self.buffer_projects.values().any(|p| *p == previous) {
+                self.configs.remove(&ConfigDomain::Project(previous));
+            }
+        }
+        true
+    }
+
+    /// Reads the project config file at `path`. A file that is missing, or
+    /// can't be read or used, contributes no settings.
+    fn load_project_config(&self, path: &Path) -> Table {
+        if !path.exists() {
+            return Table::new();
+        }
+        let table =
+            try_load_from_file(path).and_then(|table| self.check_table(&table).map(|_| table));
+        match table {
+            Ok(table) => table,
+            Err(e) => {
+                warn!("ignoring project config {:?}: {}", path, e);
+                Table::new()
+            }
+        }
+    }
+
+    /// Reloads the project config file at `path` after it changes on disk.
+    /// Returns the config changes for each affected buffer.
+    pub(crate) fn reload_project_config(&mut self, path: &Path) -> Vec<(BufferId, Table)> {
+        let domain = ConfigDomain::Project(path.to_owned());
+        if !self.configs.contains_key(&domain) {
+            return Vec::new();
+        }
+        let table = self.load_project_config(path);
+        self.configs.get_mut(&domain).unwrap().set_table(table);
+        self.update_all_buffer_configs()
+    }
+
+    /// The project config files used by open buffers.
+    pub(crate) fn project_config_paths(&self) -> BTreeSet<PathBuf> {
+        self.buffer_projects.values().cloned().collect()
+    }
+
     /// Sets a specific language for the given buffer. This is used if the
     /// user selects a specific language in the frontend, for instance.
     pub(crate) fn override_language(
@@ -339,8 +497,14 @@ impl ConfigManager {
     }
 
     fn update_buffer_config(&mut self, id: BufferId) -> Option<Table> {
-        let new_config = self.generate_buffer_config(id);
-        let changes = new_config.changes_from(self.buffer_configs.get(&id));
+        let mut new_config = self.generate_buffer_config(id);
+        let old_config = self.buffer_configs.get(&id);
+        let changes = new_config.changes_from(old_config);
+        new_config.generation = match (old_config, changes.is_some()) {
+            (Some(old), true) => old.generation + 1,
+            (Some(old), false) => old.generation,
+            (None, _) => 0,
+        };
         self.buffer_configs.insert(id, new_config);
         changes
     }
@@ -365,6 +529,9 @@ impl ConfigManager {
             .map(|l| l.name.clone());
         let mut configs = vec![self.configs.get(&ConfigDomain::General)];
 
+        if let Some(project) = self.buffer_projects.get(&id) {
+            configs.push(self.configs.get(&ConfigDomain::Project(project.clone())));
+        }
         if let Some(s) = lang {
             configs.push(self.configs.get(&s.into()))
         };
@@ -392,6 +559,13 @@ impl ConfigManager {
         self.buffer_configs.get(&id).unwrap()
     }
 
+    /// Returns the config shared by all buffers, without any language,
+    /// project or buffer specific settings.
+    pub(crate) fn default_buffer_config(&self) -> BufferConfig {
+        let general = self.configs.get(&ConfigDomain::General).map(|c| c.cache.clone());
+        TableStack(general.into_iter().collect()).into_config()
+    }
+
     /// Returns the language associated with this buffer.
     ///
     /// # Panics:
@@ -587,7 +761,7 @@ impl TableStack {
         let out = self.collate();
         let items: T = serde_json::from_value(out.into()).unwrap();
         let source = self;
-        Config { source, items }
+        Config { source, items, generation: 0 }
     }
 
     /// Walks the tables in priority order, returning the first
@@ -620,6 +794,12 @@ impl<T> Config<T> {
     pub fn to_table(&self) -> Table {
         self.source.collate()
     }
+
+    /// The number of times these settings have changed since the buffer
+    /// was opened.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
 }
 
 impl<'de, T: Deserialize<'de>> Config<T> {
@@ -638,7 +818,9 @@ impl ConfigDomain {
         match self {
             ConfigDomain::General => "preferences",
             ConfigDomain::Language(lang) => lang.as_ref(),
-            ConfigDomain::UserOverride(_) | ConfigDomain::SysOverride(_) => "we don't have files",
+            ConfigDomain::Project(_)
+            | ConfigDomain::UserOverride(_)
+            | ConfigDomain::SysOverride(_) => "we don't have files",
         }
     }
 }
@@ -722,6 +904,16 @@ pub(crate) fn init_config_dir(dir: &Path) -> io::Result<()> {
     Ok(())
 }
 
+/// Finds the project config file that applies to the file at `path`, by
+/// looking in each directory containing it, nearest first.
+fn find_project_config(path: &Path) -> Option<PathBuf> {
+    path.ancestors()
+        .skip(1)
+        .map(|dir| dir.join(PROJECT_CONFIG_FILE_NAME))
+        .find(|p| p.is_file())
+        .and_then(|p| p.canonicalize().ok())
+}
+
 /// Attempts to load a config from a file. The config's domain is determined
 /// by the file name.
 pub(crate) fn try_load_from_file(path: &Path) -> Result<Table, ConfigError> {
@@ -770,6 +962,7 @@ fn from_toml_value(value: toml::Value) -> Value {
 mod tests {
     use super::*;
     use crate::syntax::LanguageDefinition;
+    use tempdir::TempDir;
 
     #[test]
     fn test_overrides() {
@@ -811,6 +1004,25 @@ mod tests {
         assert_eq!(config.items.tab_size, 85);
     }
 
+    #[test]
+    fn test_tab_widths() {
+        let mut manager = ConfigManager::new(None, None);
+        let buf_id = BufferId(1);
+        manager.add_buffer(buf_id, None);
+        let config = manager.get_buffer_config(buf_id).to_owned();
+        assert_eq!(config.items.tab_display_width(), 4);
+        assert_eq!(config.items.indent_size(), 4);
+
+        let changes = json!({"tab_size": 8, "indent_size": 2}).as_object().unwrap().to_owned();
+        manager.set_user_config(ConfigDomain::General, changes).unwrap();
+        let config = manager.get_buffer_config(buf_id).to_owned();
+        assert_eq!(config.items.tab_display_width(), 8);
+        assert_eq!(config.items.indent_size(), 2);
+
+        let changes = json!({"indent_size": 0}).as_object().unwrap().to_owned();
+        assert!(manager.set_user_config(ConfigDomain::General, changes).is_err());
+    }
+
     #[test]
     fn test_config_domain_serde() {
         assert_eq!(serde_json::to_string(&ConfigDomain::General).unwrap(), "\"general\"");
@@ -860,6 +1072,25 @@ translate_tabs_to_spaces = true
         assert_eq!(manager.get_buffer_config(buf_id).items.font_face, "nice");
     }
 
+    #[test]
+    fn config_generation() {
+        let mut manager = ConfigManager::new(None, None);
+        let buf_id = BufferId(1);
+        manager.add_buffer(buf_id, None);
+        assert_eq!(manager.get_buffer_config(buf_id).generation(), 0);
+
+        // one batch of changes is one generation
+        let changes = json!({"tab_size": 2, "translate_tabs_to_spaces": true});
+        let table = manager
+            .table_for_update(ConfigDomain::General, changes.as_object().unwrap().to_owned());
+        assert_eq!(manager.set_user_config(ConfigDomain::General, table.clone()).unwrap().len(), 1);
+        assert_eq!(manager.get_buffer_config(buf_id).generation(), 1);
+
+        // setting the same values again changes nothing
+        assert!(manager.set_user_config(ConfigDomain::General, table).unwrap().is_empty());
+        assert_eq!(manager.get_buffer_config(buf_id).generation(), 1);
+    }
+
     #[test]
     fn lang_overrides() {
         let mut manager = ConfigManager::new(None, None);
@@ -913,6 +1144,61 @@ translate_tabs_to_spaces = true
         assert_eq!(config.items.font_size, 14.);
     }
 
+    #[test]
+    fn project_config() {
+        let tmp = TempDir::new("xi-test-project-config").unwrap();
+        let project = tmp.path().join("project");
+        fs::create_dir_all(project.join("src")).unwrap();
+        fs::create_dir_all(tmp.path().join("other")).unwrap();
+        let project_config = project.join(PROJECT_CONFIG_FILE_NAME);
+        fs::write(&project_config, "tab_size = 3\ntranslate_tabs_to_spaces = true\n").unwrap();
+
+        let mut manager = ConfigManager::new(None, None);
+        manager.set_languages(Languages::new(&[rust_lang_def(None)]));
+        let user_config = table_from_toml_str("tab_size = 42\nfont_size = 20").unwrap();
+        manager.set_user_config(ConfigDomain::General, user_config).unwrap();
+        let rust_config = table_from_toml_str("translate_tabs_to_spaces = false").unwrap();
+        manager.set_user_config(LanguageId::from("Rust").into(), rust_config).unwrap();
+
+        // the project config is layered between user and language configs
+        let buf_id = BufferId(1);
+        manager.add_buffer(buf_id, Some(project.join("src/main.rs").as_path()));
+        let items = &manager.get_buffer_config(buf_id).items;
+        assert_eq!(items.tab_size, 3);
+        assert_eq!(items.font_size, 20.);
+        assert!(!items.translate_tabs_to_spaces);
+
+        // buffers without a path never use a project config
+        let untitled = BufferId(2);
+        manager.add_buffer(untitled, None);
+        assert_eq!(manager.get_buffer_config(untitled).items.tab_size, 42);
+        assert_eq!(manager.project_config_paths().len(), 1);
+
+        // saving outside the project drops its config, and back in restores it
+        let changes = manager.update_buffer_path(buf_id, &tmp.path().join("other/main.rs"));
+        assert_eq!(changes.unwrap().get("tab_size"), Some(&42.into()));
+        assert!(manager.project_config_paths().is_empty());
+        let changes = manager.update_buffer_path(buf_id, &project.join("lib.rs"));
+        assert_eq!(changes.unwrap().get("tab_size"), Some(&3.into()));
+        assert!(manager.update_buffer_path(buf_id, &project.join("src/lib.rs")).is_none());
+
+        // changes on disk are picked up; invalid files are ignored
+        let project_config = project_config.canonicalize().unwrap();
+        fs::write(&project_config, "tab_size = 5").unwrap();
+        let changes = manager.reload_project_config(&project_config);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(manager.get_buffer_config(buf_id).items.tab_size, 5);
+        fs::write(&project_config, "tab_size = ").unwrap();
+        manager.reload_project_config(&project_config);
+        assert_eq!(manager.get_buffer_config(buf_id).items.tab_size, 42);
+        fs::write(&project_config, "tab_size = 0").unwrap();
+        manager.reload_project_config(&project_config);
+        assert_eq!(manager.get_buffer_config(buf_id).items.tab_size, 42);
+
+        manager.remove_buffer(buf_id);
+        assert!(manager.project_config_paths().is_empty());
+    }
+
     fn rust_lang_def<T: Into<Option<Table>>>(defaults: T) -> LanguageDefinition {
         LanguageDefinition::simple("Rust", &["rs"], "source.rust", defaults.into())
     }
@@ -14,11 +14,14 @@
 
 use std::io;
 use std::sync::{Arc, Mutex, MutexGuard, Weak};
+use std::time::Duration;
 
 use serde_json::Value;
 
+use xi_rope::engine::RevToken;
 use xi_rpc::{Error as RpcError, Handler, ReadError, RemoteError, RpcCtx};
 
+use crate::maintenance::{MaintenanceTask, TaskPriority};
 use crate::plugin_rpc::{PluginCommand, PluginNotification, PluginRequest};
 use crate::plugins::{Plugin, PluginId};
 use crate::rpc::*;
@@ -68,6 +71,28 @@ impl XiCore {
         }
     }
 
+    /// Registers a task to run while the user is idle; see the `maintenance`
+    /// module.
+    ///
+    /// # Panics
+    ///
+    /// Panics if core has not yet received the `client_started` message.
+    pub(crate) fn register_maintenance(
+        &self,
+        name: &str,
+        priority: TaskPriority,
+        interval_hint: Duration,
+        task: MaintenanceTask,
+    ) {
+        self.inner().register_maintenance(name, priority, interval_hint, task)
+    }
+
+    /// Cancels the maintenance task called `name`. Returns `true` if there
+    /// was one.
+    pub(crate) fn cancel_maintenance(&self, name: &str) -> bool {
+        self.inner().cancel_maintenance(name)
+    }
+
     /// Returns a new reference to the core state, if core is running.
     fn weak_self(&self) -> Option<WeakXiCore> {
         match self {
@@ -98,7 +123,7 @@ impl Handler for XiCore {
         }
 
         // wait for client_started before setting up inner
-        if let ClientStarted { ref config_dir, ref client_extras_dir } = rpc {
+        if let ClientStarted { ref config_dir, ref client_extras_dir, .. } = rpc {
             assert!(self.is_waiting(), "client_started can only be sent once");
             let state =
                 CoreState::new(ctx.get_peer(), config_dir.clone(), client_extras_dir.clone());
@@ -152,11 +177,25 @@ impl WeakXiCore {
         &self,
         plugin: PluginId,
         view: ViewId,
+        rev: RevToken,
         response: Result<Value, RpcError>,
     ) {
         if let Some(core) = self.upgrade() {
             let _t = xi_trace::trace_block("WeakXiCore::plugin_update", &["core"]);
-            core.inner().plugin_update(plugin, view, response);
+            core.inner().plugin_update(plugin, view, rev, response);
+        }
+    }
+
+    /// Handles a plugin's response to a request to format a buffer before
+    /// it is saved.
+    pub fn handle_format_response(
+        &self,
+        view: ViewId,
+        rev: RevToken,
+        response: Result<Value, RpcError>,
+    ) {
+        if let Some(core) = self.upgrade() {
+            core.inner().format_response(view, rev, response);
         }
     }
 }
@@ -193,3 +232,9 @@ pub fn dummy_weak_core() -> WeakXiCore {
     let core = Arc::new(Mutex::new(state));
     WeakXiCore(Arc::downgrade(&core))
 }
+
+#[cfg(test)]
+/// Returns a `WeakXiCore` referencing `core`.
+pub(crate) fn weak_core(core: &Arc<Mutex<CoreState>>) -> WeakXiCore {
+    WeakXiCore(Arc::downgrade(core))
+}
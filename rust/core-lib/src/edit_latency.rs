@@ -0,0 +1,272 @@
+// Copyright 2020 The xi-editor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Timing of the stages of the work done after each edit, for finding out
+//! where the time goes when typing feels slow.
+//!
+//! Each stage keeps a histogram with fixed buckets, so recording an edit is
+//! cheap enough to always be on.
+
+use std::cmp::{max, min};
+use std::time::Duration;
+
+const N_BUCKETS: usize = 16;
+
+/// Upper bounds of the histogram buckets, in microseconds. Slower stages
+/// fall into one more, unbounded bucket.
+const BUCKET_BOUNDS_US: [u64; N_BUCKETS] = [
+    50, 100, 200, 500, 1_000, 2_000, 4_000, 8_000, 16_000, 32_000, 64_000, 125_000, 250_000,
+    500_000, 1_000_000, 2_000_000,
+];
+
+/// When a histogram holds this many edits, its buckets are halved, so that
+/// percentiles follow recent edits.
+const HISTOGRAM_WINDOW: u64 = 1024;
+
+/// The stages of `EventContext::after_edit`, in the order they run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EditStage {
+    /// Committing the edit to the engine.
+    CommitDelta,
+    /// Updating the selections, wrapping and line caches of the views.
+    UpdateViews,
+    /// Sending the edit to plugins, and collecting history.
+    UpdatePlugins,
+    /// Scheduling the render and autosave timers.
+    ScheduleRender,
+}
+
+const N_STAGES: usize = 4;
+
+impl EditStage {
+    pub const ALL: [EditStage; N_STAGES] = [
+        EditStage::CommitDelta,
+        EditStage::UpdateViews,
+        EditStage::UpdatePlugins,
+        EditStage::ScheduleRender,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            EditStage::CommitDelta => "commit_delta",
+            EditStage::UpdateViews => "update_views",
+            EditStage::UpdatePlugins => "update_plugins",
+            EditStage::ScheduleRender => "schedule_render",
+        }
+    }
+}
+
+/// How long each stage of one edit took.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct EditTimings([Duration; N_STAGES]);
+
+impl EditTimings {
+    pub fn set(&mut self, stage: EditStage, duration: Duration) {
+        self.0[stage as usize] = duration;
+    }
+
+    pub fn get(&self, stage: EditStage) -> Duration {
+        self.0[stage as usize]
+    }
+
+    pub fn total(&self) -> Duration {
+        self.0.iter().sum()
+    }
+
+    pub fn slowest(&self) -> EditStage {
+        EditStage::ALL.iter().cloned().max_by_key(|&stage| self.get(stage)).unwrap()
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+struct Histogram {
+    buckets: [u64; N_BUCKETS + 1],
+    /// The number of edits in `buckets`, which only covers recent edits.
+    recent: u64,
+    /// The number of edits, and the slowest, since the last reset.
+    count: u64,
+    max_us: u64,
+    /// The number of slow edits in which this was the slowest stage.
+    slowest_in: u64,
+}
+
+impl Histogram {
+    fn record(&mut self, duration: Duration) {
+        let us = duration.as_micros() as u64;
+        if self.recent == HISTOGRAM_WINDOW {
+            self.buckets.iter_mut().for_each(|n| *n /= 2);
+            self.recent = self.buckets.iter().sum();
+        }
+        let bucket = BUCKET_BOUNDS_US
+            .iter()
+            .position(|&bound| us <= bound)
+            .unwrap_or(BUCKET_BOUNDS_US.len());
+        self.buckets[bucket] += 1;
+        self.recent += 1;
+        self.count += 1;
+        self.max_us = max(self.max_us, us);
+    }
+
+    /// Returns the upper bound of the bucket holding the `p`th fraction of
+    /// recent edits, or the maximum if that is lower.
+    fn percentile(&self, p: f64) -> u64 {
+        let rank = max(1, (self.recent as f64 * p).ceil() as u64);
+        let mut seen = 0;
+        for (i, &n) in self.buckets.iter().enumerate() {
+            seen += n;
+            if seen >= rank {
+                return BUCKET_BOUNDS_US.get(i).map_or(self.max_us, |&b| min(b, self.max_us));
+            }
+        }
+        0
+    }
+}
+
+/// The latencies of the stages of recent edits to a buffer.
+#[derive(Debug, Default)]
+pub struct EditLatency {
+    stages: [Histogram; N_STAGES],
+    edits: u64,
+    slow_edits: u64,
+}
+
+/// A summary of an `EditLatency`, as returned to clients.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct EditLatencyReport {
+    pub edits: u64,
+    /// Edits that took longer than the `slow_edit_warn_ms` setting.
+    pub slow_edits: u64,
+    pub stages: Vec<StageLatency>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StageLatency {
+    pub stage: EditStage,
+    pub count: u64,
+    /// Percentiles of recent edits, rounded up to a bucket boundary.
+    pub p50_us: u64,
+    pub p95_us: u64,
+    pub max_us: u64,
+    /// The number of slow edits in which this was the slowest stage.
+    pub slow_edits: u64,
+}
+
+impl EditLatency {
+    /// Records the timings of one edit. If it took longer than `threshold`,
+    /// returns the stage that took longest; a zero `threshold` disables this.
+    pub fn record(&mut self, timings: &EditTimings, threshold: Duration) -> Option<EditStage> {
+        for &stage in &EditStage::ALL {
+            self.stages[stage as usize].record(timings.get(stage));
+        }
+        self.edits += 1;
+        if threshold == Duration::from_secs(0) || timings.total() <= threshold {
+            return None;
+        }
+        let slowest = timings.slowest();
+        self.stages[slowest as usize].slowest_in += 1;
+        self.slow_edits += 1;
+        Some(slowest)
+    }
+
+    pub fn reset(&mut self) {
+        *self = EditLatency::default();
+    }
+
+    pub fn report(&self) -> EditLatencyReport {
+        let stages = EditStage::ALL
+            .iter()
+            .map(|&stage| {
+                let histogram = &self.stages[stage as usize];
+                StageLatency {
+                    stage,
+                    count: histogram.count,
+                    p50_us: histogram.percentile(0.5),
+                    p95_us: histogram.percentile(0.95),
+                    max_us: histogram.max_us,
+                    slow_edits: histogram.slowest_in,
+                }
+            })
+            .collect();
+        EditLatencyReport { edits: self.edits, slow_edits: self.slow_edits, stages }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    fn timings(stage_ms: [u64; N_STAGES]) -> EditTimings {
+        let mut timings = EditTimings::default();
+        for &stage in &EditStage::ALL {
+            timings.set(stage, ms(stage_ms[stage as usize]));
+        }
+        timings
+    }
+
+    #[test]
+    fn percentiles() {
+        let mut histogram = Histogram::default();
+        assert_eq!(histogram.percentile(0.5), 0);
+        for _ in 0..90 {
+            histogram.record(Duration::from_micros(300));
+        }
+        for _ in 0..10 {
+            histogram.record(ms(3));
+        }
+        assert_eq!(histogram.percentile(0.5), 500);
+        assert_eq!(histogram.percentile(0.95), 3_000);
+        histogram.record(ms(5_000));
+        assert_eq!(histogram.percentile(1.0), 5_000_000);
+        assert_eq!(histogram.max_us, 5_000_000);
+    }
+
+    #[test]
+    fn old_edits_are_forgotten() {
+        let mut histogram = Histogram::default();
+        for _ in 0..HISTOGRAM_WINDOW {
+            histogram.record(ms(20));
+        }
+        for _ in 0..HISTOGRAM_WINDOW {
+            histogram.record(ms(1));
+        }
+        assert_eq!(histogram.percentile(0.5), 1_000);
+        assert_eq!(histogram.count, 2 * HISTOGRAM_WINDOW);
+        assert_eq!(histogram.max_us, 20_000);
+    }
+
+    #[test]
+    fn slow_edits() {
+        let mut latency = EditLatency::default();
+        assert_eq!(latency.record(&timings([1, 2, 3, 0]), ms(16)), None);
+        assert_eq!(latency.record(&timings([1, 2, 30, 0]), ms(16)), Some(EditStage::UpdatePlugins));
+        assert_eq!(latency.record(&timings([1, 2, 30, 0]), ms(0)), None);
+
+        let report = latency.report();
+        assert_eq!(report.edits, 3);
+        assert_eq!(report.slow_edits, 1);
+        assert_eq!(report.stages[2].stage, EditStage::UpdatePlugins);
+        assert_eq!(report.stages[2].slow_edits, 1);
+        assert_eq!(report.stages[2].max_us, 30_000);
+        assert_eq!(report.stages[0].slow_edits, 0);
+
+        latency.reset();
+        assert_eq!(latency.report().edits, 0);
+        assert_eq!(latency.report().stages[2].count, 0);
+    }
+}
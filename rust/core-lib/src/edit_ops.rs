@@ -17,13 +17,16 @@
 use std::borrow::Cow;
 use std::collections::BTreeSet;
 
+use xi_rope::rope::count_newlines;
 use xi_rope::{Cursor, DeltaBuilder, Interval, LinesMetric, Rope, RopeDelta};
+use xi_unicode::{normalize_nfc, normalize_nfd};
 
-use crate::backspace::offset_for_delete_backwards;
+use crate::backspace::{empty_pair_around, offset_for_delete_backwards};
 use crate::config::BufferItems;
 use crate::line_offset::{LineOffset, LogicalLines};
 use crate::linewrap::Lines;
-use crate::movement::{region_movement, Movement};
+use crate::movement::{region_movement_repeated, Movement};
+use crate::rpc::NormalizationForm;
 use crate::selection::{SelRegion, Selection};
 use crate::word_boundaries::WordCursor;
 
@@ -45,6 +48,185 @@ pub fn insert<T: Into<Rope>>(base: &Rope, regions: &[SelRegion], text: T) -> Rop
     builder.build()
 }
 
+/// Replaces each of `matches` with `chars`, as a single delta. With
+/// `preserve_case`, each replacement takes on the case of the text it
+/// replaces; see `match_case`.
+pub fn replace_all(
+    base: &Rope,
+    matches: &[SelRegion],
+    chars: &str,
+    preserve_case: bool,
+) -> RopeDelta {
+    let mut builder = DeltaBuilder::new(base.len());
+    for region in matches {
+        let iv = Interval::new(region.min(), region.max());
+        let replacement = if preserve_case {
+            match_case(&base.slice_to_cow(iv), chars)
+        } else {
+            Cow::from(chars)
+        };
+        builder.replace(iv, Rope::from(replacement.as_ref()));
+    }
+    builder.build()
+}
+
+/// Changes the case of `replacement` to follow `original`: all caps if it
+/// is all caps, capitalized if it starts with an uppercase letter, and
+/// unchanged otherwise.
+fn match_case<'a>(original: &str, replacement: &'a str) -> Cow<'a, str> {
+    let has_upper = original.chars().any(char::is_uppercase);
+    let has_lower = original.chars().any(char::is_lowercase);
+    if has_upper && !has_lower {
+        Cow::from(replacement.to_uppercase())
+    } else if original.chars().next().map_or(false, char::is_uppercase) {
+        let mut chars = replacement.chars();
+        match chars.next() {
+            Some(first) => Cow::from(first.to_uppercase().chain(chars).collect::<String>()),
+            None => Cow::from(replacement),
+        }
+    } else {
+        Cow::from(replacement)
+    }
+}
+
+/// Replaces the selections with pasted text. If there are several selections
+/// and `chars` has as many lines, each selection is replaced by one line.
+///
+/// If `normalize_pasted_line_endings` is set, line endings in `chars` are
+/// converted to the buffer's, to avoid mixing them.
+pub fn paste(base: &Rope, regions: &[SelRegion], config: &BufferItems, chars: &str) -> RopeDelta {
+    let chars = paste_text(config, chars);
+    if regions.len() == 1 || regions.len() != count_lines(&chars) {
+        insert(base, regions, chars.as_ref())
+    } else {
+        let mut builder = DeltaBuilder::new(base.len());
+        for (sel, line) in regions.iter().zip(chars.lines()) {
+            let iv = Interval::new(sel.min(), sel.max());
+            builder.replace(iv, line.into());
+        }
+        builder.build()
+    }
+}
+
+/// Replaces the selections with pasted text, shifting all of its lines by
+/// the same number of columns, so that its first line takes the indentation
+/// of the line the selection starts on. Lines that would be shifted left past
+/// the line start lose all of their indentation, and blank lines lose theirs.
+pub fn paste_with_reindent(
+    base: &Rope,
+    regions: &[SelRegion],
+    config: &BufferItems,
+    chars: &str,
+) -> RopeDelta {
+    let chars = paste_text(config, chars);
+    let tab_size = config.tab_size;
+    let first_indent = chars
+        .split('\n')
+        .find(|line| !line.trim().is_empty())
+        .map_or(0, |line| indent_cols(line, tab_size));
+
+    let mut builder = DeltaBuilder::new(base.len());
+    for region in regions {
+        let line = base.line_of_offset(region.min());
+        let target_indent = indent_cols(&line_text(base, line), tab_size);
+        let mut text = String::with_capacity(chars.len());
+        for (ix, line) in chars.split('\n').enumerate() {
+            let rest = line.trim_start_matches(&[' ', '\t'][..]);
+            if ix > 0 {
+                text.push('\n');
+                if !rest.trim().is_empty() {
+                    let cols =
+                        (indent_cols(line, tab_size) + target_indent).saturating_sub(first_indent);
+                    text.push_str(&indent_text(config, cols));
+                }
+            }
+            text.push_str(rest);
+        }
+        builder.replace(Interval::new(region.min(), region.max()), text.into());
+    }
+    builder.build()
+}
+
+/// The width in columns of the indentation of `line`.
+fn indent_cols(line: &str, tab_size: usize) -> usize {
+    let mut cols = 0;
+    for c in line.chars() {
+        match c {
+            ' ' => cols += 1,
+            '\t' => cols += tab_size - cols % tab_size,
+            _ => break,
+        }
+    }
+    cols
+}
+
+/// Indentation `cols` columns wide, made of spaces or tabs as configured.
+fn indent_text(config: &BufferItems, cols: usize) -> String {
+    if config.translate_tabs_to_spaces {
+        " ".repeat(cols)
+    } else {
+        let mut indent = "\t".repeat(cols / config.tab_size);
+        indent.push_str(&" ".repeat(cols % config.tab_size));
+        indent
+    }
+}
+
+/// Splits pasted text into pieces of at most `paste_chunk_size` bytes, to be
+/// inserted one after another, in order. Each piece holds at least one
+/// character, and line endings are converted as in `paste`.
+pub fn paste_chunks(config: &BufferItems, chars: &str) -> Vec<String> {
+    let chars = paste_text(config, chars);
+    let chunk_size = config.paste_chunk_size.max(1);
+    let mut chunks = Vec::with_capacity(chars.len() / chunk_size + 1);
+    let mut rest = chars.as_ref();
+    while !rest.is_empty() {
+        let mut end = chunk_size.min(rest.len());
+        while !rest.is_char_boundary(end) {
+            end += 1;
+        }
+        let (chunk, tail) = rest.split_at(end);
+        chunks.push(chunk.to_owned());
+        rest = tail;
+    }
+    chunks
+}
+
+fn paste_text<'a>(config: &BufferItems, chars: &'a str) -> Cow<'a, str> {
+    if config.normalize_pasted_line_endings {
+        normalize_line_endings(chars, &config.line_ending)
+    } else {
+        Cow::from(chars)
+    }
+}
+
+/// Converts the "\n" and "\r\n" line endings in `text` to `line_ending`.
+fn normalize_line_endings<'a>(text: &'a str, line_ending: &str) -> Cow<'a, str> {
+    let mut pieces = text.split('\n').peekable();
+    let mut result = String::with_capacity(text.len());
+    while let Some(piece) = pieces.next() {
+        if pieces.peek().is_none() {
+            result.push_str(piece);
+        } else {
+            result.push_str(piece.strip_suffix('\r').unwrap_or(piece));
+            result.push_str(line_ending);
+        }
+    }
+    if result == text {
+        Cow::from(text)
+    } else {
+        Cow::from(result)
+    }
+}
+
+/// Counts the number of lines in the string, not including any trailing newline.
+fn count_lines(s: &str) -> usize {
+    let mut newlines = count_newlines(s);
+    if s.as_bytes().last() == Some(&0xa) {
+        newlines -= 1;
+    }
+    1 + newlines
+}
+
 /// Leaves the current selection untouched, but surrounds it with two insertions.
 pub fn surround<BT, AT>(
     base: &Rope,
@@ -107,14 +289,162 @@ pub fn duplicate_line(base: &Rope, regions: &[SelRegion], config: &BufferItems)
     builder.build()
 }
 
+/// Hard-wraps the markdown list item containing each region to `column`.
+/// The bullet or number stays on the first line, and continuation lines are
+/// indented to line up with the text after it. Only the whitespace between
+/// words is changed, so carets stay with the words they were on.
+pub fn reflow_list_item(
+    base: &Rope,
+    regions: &[SelRegion],
+    column: usize,
+    config: &BufferItems,
+) -> RopeDelta {
+    let mut items = BTreeSet::new();
+    for region in regions {
+        let (line, _) = LogicalLines.offset_to_line_col(base, region.min());
+        if let Some(item) = list_item_lines(base, line) {
+            items.insert(item);
+        }
+    }
+
+    let mut builder = DeltaBuilder::new(base.len());
+    for (first, last) in items {
+        let first_text = line_text(base, first);
+        let (indent, prefix) = list_item_prefix(&first_text).unwrap();
+        let prefix_width = first_text[..prefix].chars().count();
+        let hang = format!("{}{}", &first_text[..indent], " ".repeat(prefix - indent));
+        let hang_width = hang.chars().count();
+
+        let mut words = Vec::new();
+        for line in first..=last {
+            let start = LogicalLines.offset_of_line(base, line);
+            let text = line_text(base, line);
+            let skip = if line == first { prefix } else { 0 };
+            word_spans(&text[skip..], start + skip, &mut words);
+        }
+
+        let mut width = prefix_width;
+        let mut prev_end = None;
+        for &(start, end) in &words {
+            let word_width = base.slice_to_cow(start..end).chars().count();
+            if let Some(prev_end) = prev_end {
+                let sep = if width + 1 + word_width > column {
+                    width = hang_width + word_width;
+                    Cow::from(format!("{}{}", config.line_ending, hang))
+                } else {
+                    width += 1 + word_width;
+                    Cow::from(" ")
+                };
+                if base.slice_to_cow(prev_end..start) != sep {
+                    builder.replace(Interval::new(prev_end, start), Rope::from(sep.as_ref()));
+                }
+            } else {
+                width += word_width;
+            }
+            prev_end = Some(end);
+        }
+
+        // drop whitespace trailing the last word
+        let item_end = LogicalLines.offset_of_line(base, last) + line_text(base, last).len();
+        if let Some(prev_end) = prev_end.filter(|&end| end < item_end) {
+            builder.delete(Interval::new(prev_end, item_end));
+        }
+    }
+
+    builder.build()
+}
+
+/// The first and last lines of the list item that `line` is part of. An
+/// item runs from the line with its marker up to a blank line or the start
+/// of the next item.
+fn list_item_lines(base: &Rope, line: usize) -> Option<(usize, usize)> {
+    let mut first = line;
+    loop {
+        let text = line_text(base, first);
+        if text.trim().is_empty() {
+            return None;
+        }
+        if list_item_prefix(&text).is_some() {
+            break;
+        }
+        if first == 0 {
+            return None;
+        }
+        first -= 1;
+    }
+
+    let n_lines = base.measure::<LinesMetric>() + 1;
+    let mut last = first;
+    while last + 1 < n_lines {
+        let text = line_text(base, last + 1);
+        if text.trim().is_empty() || list_item_prefix(&text).is_some() {
+            break;
+        }
+        last += 1;
+    }
+    Some((first, last))
+}
+
+/// If `line` starts a list item (`- `, `* `, `+ `, `1. ` or `1) `), returns
+/// the length in bytes of its indentation, and of the indentation, marker and
+/// the spaces following the marker.
+fn list_item_prefix(line: &str) -> Option<(usize, usize)> {
+    let indent = line.len() - line.trim_start_matches(&[' ', '\t'][..]).len();
+    let rest = &line[indent..];
+    let marker = match rest.as_bytes().first()? {
+        b'-' | b'*' | b'+' => 1,
+        b'0'..=b'9' => {
+            let digits = rest.bytes().take_while(u8::is_ascii_digit).count();
+            match rest.as_bytes().get(digits) {
+                Some(b'.') | Some(b')') => digits + 1,
+                _ => return None,
+            }
+        }
+        _ => return None,
+    };
+    let after = &rest[marker..];
+    let spaces = after.len() - after.trim_start_matches(' ').len();
+    if spaces == 0 {
+        return None;
+    }
+    Some((indent, indent + marker + spaces))
+}
+
+/// The text of a line, without its line ending.
+fn line_text(base: &Rope, line: usize) -> String {
+    let start = LogicalLines.offset_of_line(base, line);
+    let end = LogicalLines.offset_of_line(base, line + 1);
+    base.slice_to_cow(start..end).trim_end_matches(&['\n', '\r'][..]).to_owned()
+}
+
+/// Appends the offsets of the whitespace-separated words of `text`, which
+/// starts at `offset` in the document, to `spans`.
+fn word_spans(text: &str, offset: usize, spans: &mut Vec<(usize, usize)>) {
+    let mut word_start = None;
+    for (i, c) in text.char_indices() {
+        match (c.is_whitespace(), word_start) {
+            (true, Some(start)) => {
+                spans.push((offset + start, offset + i));
+                word_start = None;
+            }
+            (false, None) => word_start = Some(i),
+            _ => (),
+        }
+    }
+    if let Some(start) = word_start {
+        spans.push((offset + start, offset + text.len()));
+    }
+}
+
 /// Used when the user presses the backspace key. If no delta is returned, then nothing changes.
 pub fn delete_backward(base: &Rope, regions: &[SelRegion], config: &BufferItems) -> RopeDelta {
     // TODO: this function is workable but probably overall code complexity
     // could be improved by implementing a "backspace" movement instead.
     let mut builder = DeltaBuilder::new(base.len());
     for region in regions {
-        let start = offset_for_delete_backwards(region, base, config);
-        let iv = Interval::new(start, region.max());
+        let iv = empty_pair_around(region, base, config).unwrap_or_else(|| {
+            Interval::new(offset_for_delete_backwards(region, base, config), region.max())
+        });
         if !iv.is_empty() {
             builder.delete(iv);
         }
@@ -126,7 +456,7 @@ pub fn delete_backward(base: &Rope, regions: &[SelRegion], config: &BufferItems)
 /// Common logic for a number of delete methods. For each region in the
 /// selection, if the selection is a caret, delete the region between
 /// the caret and the movement applied to the caret, otherwise delete
-/// the region.
+/// the region. The movement is applied `count` times.
 ///
 /// If `save` is set, the tuple will contain a rope with the deleted text.
 ///
@@ -140,6 +470,7 @@ pub(crate) fn delete_by_movement(
     movement: Movement,
     height: usize,
     save: bool,
+    count: usize,
 ) -> (RopeDelta, Option<Rope>) {
     // We compute deletions as a selection because the merge logic
     // is convenient. Another possibility would be to make the delta
@@ -147,7 +478,8 @@ pub(crate) fn delete_by_movement(
     let mut deletions = Selection::new();
     for &r in regions {
         if r.is_caret() {
-            let new_region = region_movement(movement, r, lines, height, base, true);
+            let new_region =
+                region_movement_repeated(movement, r, lines, height, base, true, count);
             deletions.add_region(new_region);
         } else {
             deletions.add_region(r);
@@ -218,8 +550,8 @@ pub fn insert_tab(base: &Rope, regions: &[SelRegion], config: &BufferItems) -> R
             }
         } else {
             let (_, col) = LogicalLines.offset_to_line_col(base, region.start);
-            let mut tab_size = config.tab_size;
-            tab_size = tab_size - (col % tab_size);
+            let indent_size = config.indent_size();
+            let tab_size = indent_size - (col % indent_size);
             let tab_text = get_tab_text(config, Some(tab_size));
 
             let iv = Interval::new(region.min(), region.max());
@@ -324,6 +656,70 @@ pub fn transpose(base: &Rope, regions: &[SelRegion]) -> RopeDelta {
     builder.build()
 }
 
+/// Sorts the contents of the non-caret selection regions, and writes them
+/// back into the regions in position order.
+pub fn sort_selection_contents(base: &Rope, regions: &[SelRegion]) -> RopeDelta {
+    let mut builder = DeltaBuilder::new(base.len());
+    let regions = regions.iter().filter(|r| !r.is_caret()).collect::<Vec<_>>();
+    let contents = regions.iter().map(|r| base.slice_to_cow(*r)).collect::<Vec<_>>();
+    let mut sorted = contents.clone();
+    sorted.sort();
+
+    for ((region, old), new) in regions.iter().zip(contents).zip(sorted) {
+        if old != new {
+            let interval = Interval::new(region.min(), region.max());
+            builder.replace(interval, Rope::from(new));
+        }
+    }
+
+    builder.build()
+}
+
+/// Joins the lines of each non-caret selection region into one, replacing
+/// the line endings between them with `separator`. A line ending at the end
+/// of a region is kept, so that flattening whole lines leaves a line.
+pub fn flatten_selection(base: &Rope, regions: &[SelRegion], separator: &str) -> RopeDelta {
+    let mut builder = DeltaBuilder::new(base.len());
+    for region in regions.iter().filter(|region| !region.is_caret()) {
+        let old = base.slice_to_cow(region);
+        let ending_len = if old.ends_with("\r\n") {
+            2
+        } else if old.ends_with('\n') {
+            1
+        } else {
+            0
+        };
+        let (lines, ending) = old.split_at(old.len() - ending_len);
+        if lines.contains('\n') {
+            let new = lines.replace("\r\n", "\n").replace('\n', separator) + ending;
+            builder.replace(Interval::new(region.min(), region.max()), Rope::from(new));
+        }
+    }
+    builder.build()
+}
+
+/// Splits each non-caret selection region into lines, replacing each
+/// occurrence of `separator` in it with `line_ending`.
+pub fn expand_on_separator(
+    base: &Rope,
+    regions: &[SelRegion],
+    separator: &str,
+    line_ending: &str,
+) -> RopeDelta {
+    let mut builder = DeltaBuilder::new(base.len());
+    if separator.is_empty() {
+        return builder.build();
+    }
+    for region in regions.iter().filter(|region| !region.is_caret()) {
+        let old = base.slice_to_cow(region);
+        if old.contains(separator) {
+            let new = old.replace(separator, line_ending);
+            builder.replace(Interval::new(region.min(), region.max()), Rope::from(new));
+        }
+    }
+    builder.build()
+}
+
 pub fn transform_text<F: Fn(&str) -> String>(
     base: &Rope,
     regions: &[SelRegion],
@@ -340,6 +736,63 @@ pub fn transform_text<F: Fn(&str) -> String>(
     builder.build()
 }
 
+/// Replaces each "\n" or "\r\n" line ending in `base` that differs from
+/// `line_ending`, which must also end with "\n". A lone "\r" is not treated
+/// as a line ending.
+pub fn convert_line_endings(base: &Rope, line_ending: &str) -> RopeDelta {
+    debug_assert!(line_ending.ends_with('\n'));
+    // only what comes before the "\n" is changed
+    let new = Rope::from(&line_ending[..line_ending.len() - 1]);
+    let mut builder = DeltaBuilder::new(base.len());
+    let mut end = 0;
+    // a "\r\n" split between leaves still ends a single raw line
+    for line in base.lines_raw(..) {
+        end += line.len();
+        let old = if line.ends_with("\r\n") {
+            "\r\n"
+        } else if line.ends_with('\n') {
+            "\n"
+        } else {
+            continue;
+        };
+        if old != line_ending {
+            builder.replace(Interval::new(end - old.len(), end - 1), new.clone());
+        }
+    }
+    builder.build()
+}
+
+/// Normalizes the selected text to `form`, or the whole buffer if there are
+/// only carets. Only the selections, or lines, whose text changes are replaced,
+/// so that the other selections keep their positions.
+pub fn normalize_unicode(base: &Rope, regions: &[SelRegion], form: NormalizationForm) -> RopeDelta {
+    let mut builder = DeltaBuilder::new(base.len());
+    let mut normalize = |start: usize, end: usize| {
+        let old = base.slice_to_cow(start..end);
+        let new = match form {
+            NormalizationForm::Nfc => normalize_nfc(&old),
+            NormalizationForm::Nfd => normalize_nfd(&old),
+        };
+        if new != old {
+            builder.replace(Interval::new(start, end), Rope::from(new));
+        }
+    };
+
+    if regions.iter().all(|region| region.is_caret()) {
+        let mut start = 0;
+        for line in base.lines_raw(..) {
+            normalize(start, start + line.len());
+            start += line.len();
+        }
+    } else {
+        for region in regions.iter().filter(|region| !region.is_caret()) {
+            normalize(region.min(), region.max());
+        }
+    }
+
+    builder.build()
+}
+
 /// Changes the number(s) under the cursor(s) with the `transform_function`.
 /// If there is a number next to or on the beginning of the region, then
 /// this number will be replaced with the result of `transform_function` and
@@ -359,24 +812,60 @@ pub fn change_number<F: Fn(i128) -> Option<i128>>(
 ) -> RopeDelta {
     let mut builder = DeltaBuilder::new(base.len());
     for region in regions {
-        let mut cursor = WordCursor::new(base, region.end);
-        let (mut start, end) = cursor.select_word();
-
-        // if the word begins with '-', then it is a negative number
-        if start > 0 && base.byte_at(start - 1) == (b'-') {
-            start -= 1;
+        if let Some((interval, number)) = number_at(base, region.end) {
+            if let Some(number) = transform_function(number) {
+                builder.replace(interval, Rope::from(number.to_string()));
+            }
         }
+    }
 
-        let word = base.slice_to_cow(start..end);
-        if let Some(number) = word.parse::<i128>().ok().and_then(&transform_function) {
-            let interval = Interval::new(start, end);
-            builder.replace(interval, Rope::from(number.to_string()));
-        }
+    builder.build()
+}
+
+/// Renumbers the numbers at the regions, as found by `change_number`: the
+/// first becomes `base`, and each following one `step` more than the one
+/// before. Regions without a number are skipped. Numbers written with leading
+/// zeros keep their width, so that "08" followed by "09" can become "09" and
+/// "10".
+pub fn increment_column(base: &Rope, regions: &[SelRegion], first: i64, step: i64) -> RopeDelta {
+    let mut builder = DeltaBuilder::new(base.len());
+    let mut next = Some(i128::from(first));
+    for region in regions {
+        let (interval, number) = match (number_at(base, region.end), next) {
+            (Some((interval, _)), Some(number)) => (interval, number),
+            _ => continue,
+        };
+        let old = base.slice_to_cow(interval);
+        builder.replace(interval, Rope::from(format_number_like(&old, number)));
+        next = number.checked_add(i128::from(step));
     }
 
     builder.build()
 }
 
+/// Finds the number at or just before `offset`, returning its interval,
+/// including any minus sign, and its value.
+fn number_at(base: &Rope, offset: usize) -> Option<(Interval, i128)> {
+    let mut cursor = WordCursor::new(base, offset);
+    let (mut start, end) = cursor.select_word();
+
+    // if the word begins with '-', then it is a negative number
+    if start > 0 && base.byte_at(start - 1) == (b'-') {
+        start -= 1;
+    }
+
+    let word = base.slice_to_cow(start..end);
+    word.parse::<i128>().ok().map(|number| (Interval::new(start, end), number))
+}
+
+/// Formats `number` with as many digits as `old`, if `old` has leading zeros.
+fn format_number_like(old: &str, number: i128) -> String {
+    let digits = old.trim_start_matches('-');
+    let width = if digits.len() > 1 && digits.starts_with('0') { digits.len() } else { 0 };
+    let sign = if number < 0 { "-" } else { "" };
+    format!("{}{:0width$}", sign, number.abs(), width = width)
+}
+
 // capitalization behaviour is similar to behaviour in XCode
 pub fn capitalize_text(base: &Rope, regions: &[SelRegion]) -> (RopeDelta, Selection) {
     let mut builder = DeltaBuilder::new(base.len());
@@ -425,7 +914,7 @@ fn last_selection_region(regions: &[SelRegion]) -> Option<&SelRegion> {
 }
 
 fn get_tab_text(config: &BufferItems, tab_size: Option<usize>) -> &'static str {
-    let tab_size = tab_size.unwrap_or(config.tab_size);
+    let tab_size = tab_size.unwrap_or_else(|| config.indent_size());
     let tab_text = if config.translate_tabs_to_spaces { n_spaces(tab_size) } else { "\t" };
 
     tab_text
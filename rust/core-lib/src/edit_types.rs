@@ -18,43 +18,83 @@
 //! This simplifies code elsewhere, and makes it easier to route events to
 //! the editor or view as appropriate.
 
+use crate::line_ending::LineEnding;
 use crate::movement::Movement;
 use crate::rpc::{
-    EditNotification, FindQuery, GestureType, LineRange, MouseAction, Position,
+    EditNotification, FindQuery, GestureType, LineRange, MouseAction, NormalizationForm, Position,
     SelectionGranularity, SelectionModifier,
 };
 use crate::view::Size;
 
 /// Events that only modify view state
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub(crate) enum ViewEvent {
-    Move(Movement),
-    ModifySelection(Movement),
+    /// A movement, and the number of times to repeat it.
+    Move(Movement, usize),
+    ModifySelection(Movement, usize),
     SelectAll,
     Scroll(LineRange),
     AddSelectionAbove,
     AddSelectionBelow,
     Click(MouseAction),
     Drag(MouseAction),
-    Gesture { line: u64, col: u64, ty: GestureType },
-    GotoLine { line: u64 },
-    Find { chars: String, case_sensitive: bool, regex: bool, whole_words: bool },
-    MultiFind { queries: Vec<FindQuery> },
-    FindNext { wrap_around: bool, allow_same: bool, modify_selection: SelectionModifier },
-    FindPrevious { wrap_around: bool, allow_same: bool, modify_selection: SelectionModifier },
+    Gesture {
+        line: u64,
+        col: u64,
+        ty: GestureType,
+    },
+    GotoLine {
+        line: u64,
+    },
+    Find {
+        chars: String,
+        case_sensitive: bool,
+        regex: bool,
+        whole_words: bool,
+        find_in_block: bool,
+    },
+    MultiFind {
+        queries: Vec<FindQuery>,
+    },
+    FindNext {
+        wrap_around: bool,
+        allow_same: bool,
+        modify_selection: SelectionModifier,
+    },
+    FindPrevious {
+        wrap_around: bool,
+        allow_same: bool,
+        modify_selection: SelectionModifier,
+    },
     FindAll,
-    HighlightFind { visible: bool },
-    SelectionForFind { case_sensitive: bool },
-    Replace { chars: String, preserve_case: bool },
+    HighlightFind {
+        visible: bool,
+    },
+    SelectionForFind {
+        case_sensitive: bool,
+    },
+    Replace {
+        chars: String,
+        preserve_case: bool,
+    },
     SelectionForReplace,
     SelectionIntoLines,
     CollapseSelections,
+    Fold {
+        line_range: LineRange,
+    },
+    Unfold {
+        line_range: LineRange,
+    },
+    UnfoldAll,
 }
 
 /// Events that modify the buffer
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub(crate) enum BufferEvent {
-    Delete { movement: Movement, kill: bool },
+    Delete { movement: Movement, kill: bool, count: usize },
     Backspace,
     Transpose,
     Undo,
@@ -62,22 +102,30 @@ pub(crate) enum BufferEvent {
     Uppercase,
     Lowercase,
     Capitalize,
+    SortSelectionContents,
+    FlattenSelection { separator: String },
+    ExpandOnSeparator { sep: String },
     Indent,
     Outdent,
     Insert(String),
     Paste(String),
+    PasteWithReindent(String),
     InsertNewline,
     InsertTab,
     Yank,
     ReplaceNext,
-    ReplaceAll,
+    ReplaceAll { chars: Option<String>, preserve_case: bool },
     DuplicateLine,
+    ReflowListItem { column: Option<usize> },
     IncreaseNumber,
     DecreaseNumber,
+    IncrementColumn { base: i64, step: i64 },
+    NormalizeUnicode { form: NormalizationForm },
 }
 
 /// An event that needs special handling
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub(crate) enum SpecialEvent {
     DebugRewrap,
     DebugWrapWidth,
@@ -85,20 +133,54 @@ pub(crate) enum SpecialEvent {
     Resize(Size),
     RequestLines(LineRange),
     RequestHover { request_id: usize, position: Option<Position> },
+    RequestCompletions { request_id: usize, position: Option<Position> },
+    ResolveCompletion { request_id: usize, index: usize },
     DebugToggleComment,
     Reindent,
+    ForceRehighlight,
     ToggleRecording(Option<String>),
     PlayRecording(String),
     ClearRecording(String),
+    ToggleBookmark { line: u64 },
+    NextBookmark,
+    PreviousBookmark,
+    ClearBookmarks,
+    SetLineEnding(LineEnding),
+    NormalizeLineEndings,
+    CompositionStart,
+    CompositionUpdate { text: String, caret: usize },
+    CompositionCommit(String),
+    CompositionCancel,
+    SelectAllOccurrencesAllViews,
 }
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub(crate) enum EventDomain {
     View(ViewEvent),
     Buffer(BufferEvent),
     Special(SpecialEvent),
 }
 
+impl EventDomain {
+    /// Returns `true` if this event edits or moves the selections, and so
+    /// can't be handled while an input method composition is shown at them.
+    pub(crate) fn interrupts_composition(&self) -> bool {
+        match self {
+            EventDomain::View(ViewEvent::Scroll(_))
+            | EventDomain::View(ViewEvent::HighlightFind { .. })
+            | EventDomain::View(ViewEvent::Find { .. })
+            | EventDomain::View(ViewEvent::MultiFind { .. }) => false,
+            EventDomain::View(_) | EventDomain::Buffer(_) => true,
+            EventDomain::Special(SpecialEvent::NextBookmark)
+            | EventDomain::Special(SpecialEvent::PreviousBookmark)
+            | EventDomain::Special(SpecialEvent::PlayRecording(_))
+            | EventDomain::Special(SpecialEvent::SelectAllOccurrencesAllViews) => true,
+            EventDomain::Special(_) => false,
+        }
+    }
+}
+
 impl From<BufferEvent> for EventDomain {
     fn from(src: BufferEvent) -> EventDomain {
         EventDomain::Buffer(src)
@@ -126,93 +208,104 @@ impl From<EditNotification> for EventDomain {
                 BufferEvent::Insert(chars).into(),
             Paste { chars } =>
                 BufferEvent::Paste(chars).into(),
+            PasteWithReindent { chars } =>
+                BufferEvent::PasteWithReindent(chars).into(),
             DeleteForward =>
                 BufferEvent::Delete {
                     movement: Movement::Right,
-                    kill: false
+                    kill: false,
+                    count: 1
                 }.into(),
             DeleteBackward =>
                 BufferEvent::Backspace.into(),
-            DeleteWordForward =>
+            DeleteWordForward { count } =>
                 BufferEvent::Delete {
                     movement: Movement::RightWord,
-                    kill: false
+                    kill: false,
+                    count: count as usize
                 }.into(),
-            DeleteWordBackward =>
+            DeleteWordBackward { count } =>
                 BufferEvent::Delete {
                     movement: Movement::LeftWord,
-                    kill: false
+                    kill: false,
+                    count: count as usize
                 }.into(),
             DeleteToEndOfParagraph =>
                 BufferEvent::Delete {
                     movement: Movement::EndOfParagraphKill,
-                    kill: true
+                    kill: true,
+                    count: 1
                 }.into(),
             DeleteToBeginningOfLine =>
                 BufferEvent::Delete {
                     movement: Movement::LeftOfLine,
-                    kill: false
+                    kill: false,
+                    count: 1
                 }.into(),
             InsertNewline =>
                 BufferEvent::InsertNewline.into(),
             InsertTab =>
                 BufferEvent::InsertTab.into(),
-            MoveUp =>
-                ViewEvent::Move(Movement::Up).into(),
-            MoveUpAndModifySelection =>
-                ViewEvent::ModifySelection(Movement::Up).into(),
-            MoveDown =>
-                ViewEvent::Move(Movement::Down).into(),
-            MoveDownAndModifySelection =>
-                ViewEvent::ModifySelection(Movement::Down).into(),
-            MoveLeft | MoveBackward =>
-                ViewEvent::Move(Movement::Left).into(),
-            MoveLeftAndModifySelection =>
-                ViewEvent::ModifySelection(Movement::Left).into(),
-            MoveRight | MoveForward  =>
-                ViewEvent::Move(Movement::Right).into(),
-            MoveRightAndModifySelection =>
-                ViewEvent::ModifySelection(Movement::Right).into(),
-            MoveWordLeft =>
-                ViewEvent::Move(Movement::LeftWord).into(),
-            MoveWordLeftAndModifySelection =>
-                ViewEvent::ModifySelection(Movement::LeftWord).into(),
-            MoveWordRight =>
-                ViewEvent::Move(Movement::RightWord).into(),
-            MoveWordRightAndModifySelection =>
-                ViewEvent::ModifySelection(Movement::RightWord).into(),
-            MoveToBeginningOfParagraph =>
-                ViewEvent::Move(Movement::StartOfParagraph).into(),
-            MoveToBeginningOfParagraphAndModifySelection =>
-                ViewEvent::ModifySelection(Movement::StartOfParagraph).into(),
-            MoveToEndOfParagraph =>
-                ViewEvent::Move(Movement::EndOfParagraph).into(),
-            MoveToEndOfParagraphAndModifySelection =>
-                ViewEvent::ModifySelection(Movement::EndOfParagraph).into(),
+            MoveUp { count } =>
+                ViewEvent::Move(Movement::Up, count as usize).into(),
+            MoveUpAndModifySelection { count } =>
+                ViewEvent::ModifySelection(Movement::Up, count as usize).into(),
+            MoveDown { count } =>
+                ViewEvent::Move(Movement::Down, count as usize).into(),
+            MoveDownAndModifySelection { count } =>
+                ViewEvent::ModifySelection(Movement::Down, count as usize).into(),
+            MoveCaretColumnUp =>
+                ViewEvent::Move(Movement::ColumnUp, 1).into(),
+            MoveCaretColumnDown =>
+                ViewEvent::Move(Movement::ColumnDown, 1).into(),
+            MoveLeft { count } | MoveBackward { count } =>
+                ViewEvent::Move(Movement::Left, count as usize).into(),
+            MoveLeftAndModifySelection { count } =>
+                ViewEvent::ModifySelection(Movement::Left, count as usize).into(),
+            MoveRight { count } | MoveForward { count } =>
+                ViewEvent::Move(Movement::Right, count as usize).into(),
+            MoveRightAndModifySelection { count } =>
+                ViewEvent::ModifySelection(Movement::Right, count as usize).into(),
+            MoveWordLeft { count } =>
+                ViewEvent::Move(Movement::LeftWord, count as usize).into(),
+            MoveWordLeftAndModifySelection { count } =>
+                ViewEvent::ModifySelection(Movement::LeftWord, count as usize).into(),
+            MoveWordRight { count } =>
+                ViewEvent::Move(Movement::RightWord, count as usize).into(),
+            MoveWordRightAndModifySelection { count } =>
+                ViewEvent::ModifySelection(Movement::RightWord, count as usize).into(),
+            MoveToBeginningOfParagraph { count } =>
+                ViewEvent::Move(Movement::StartOfParagraph, count as usize).into(),
+            MoveToBeginningOfParagraphAndModifySelection { count } =>
+                ViewEvent::ModifySelection(Movement::StartOfParagraph, count as usize).into(),
+            MoveToEndOfParagraph { count } =>
+                ViewEvent::Move(Movement::EndOfParagraph, count as usize).into(),
+            MoveToEndOfParagraphAndModifySelection { count } =>
+                ViewEvent::ModifySelection(Movement::EndOfParagraph, count as usize).into(),
             MoveToLeftEndOfLine =>
-                ViewEvent::Move(Movement::LeftOfLine).into(),
+                ViewEvent::Move(Movement::LeftOfLine, 1).into(),
             MoveToLeftEndOfLineAndModifySelection =>
-                ViewEvent::ModifySelection(Movement::LeftOfLine).into(),
+                ViewEvent::ModifySelection(Movement::LeftOfLine, 1).into(),
             MoveToRightEndOfLine =>
-                ViewEvent::Move(Movement::RightOfLine).into(),
+                ViewEvent::Move(Movement::RightOfLine, 1).into(),
             MoveToRightEndOfLineAndModifySelection =>
-                ViewEvent::ModifySelection(Movement::RightOfLine).into(),
+                ViewEvent::ModifySelection(Movement::RightOfLine, 1).into(),
             MoveToBeginningOfDocument =>
-                ViewEvent::Move(Movement::StartOfDocument).into(),
+                ViewEvent::Move(Movement::StartOfDocument, 1).into(),
             MoveToBeginningOfDocumentAndModifySelection =>
-                ViewEvent::ModifySelection(Movement::StartOfDocument).into(),
+                ViewEvent::ModifySelection(Movement::StartOfDocument, 1).into(),
             MoveToEndOfDocument =>
-                ViewEvent::Move(Movement::EndOfDocument).into(),
+                ViewEvent::Move(Movement::EndOfDocument, 1).into(),
             MoveToEndOfDocumentAndModifySelection =>
-                ViewEvent::ModifySelection(Movement::EndOfDocument).into(),
+                ViewEvent::ModifySelection(Movement::EndOfDocument, 1).into(),
             ScrollPageUp =>
-                ViewEvent::Move(Movement::UpPage).into(),
+                ViewEvent::Move(Movement::UpPage, 1).into(),
             PageUpAndModifySelection =>
-                ViewEvent::ModifySelection(Movement::UpPage).into(),
+                ViewEvent::ModifySelection(Movement::UpPage, 1).into(),
             ScrollPageDown =>
-                ViewEvent::Move(Movement::DownPage).into(),
+                ViewEvent::Move(Movement::DownPage, 1).into(),
             PageDownAndModifySelection =>
-                ViewEvent::ModifySelection(Movement::DownPage).into(),
+                ViewEvent::ModifySelection(Movement::DownPage, 1).into(),
             SelectAll => ViewEvent::SelectAll.into(),
             AddSelectionAbove => ViewEvent::AddSelectionAbove.into(),
             AddSelectionBelow => ViewEvent::AddSelectionBelow.into(),
@@ -261,8 +354,8 @@ impl From<EditNotification> for EventDomain {
             },
             Undo => BufferEvent::Undo.into(),
             Redo => BufferEvent::Redo.into(),
-            Find { chars, case_sensitive, regex, whole_words } =>
-                ViewEvent::Find { chars, case_sensitive, regex, whole_words }.into(),
+            Find { chars, case_sensitive, regex, whole_words, find_in_block } =>
+                ViewEvent::Find { chars, case_sensitive, regex, whole_words, find_in_block }.into(),
             MultiFind { queries } =>
                 ViewEvent::MultiFind { queries }.into(),
             FindNext { wrap_around, allow_same, modify_selection } =>
@@ -276,9 +369,16 @@ impl From<EditNotification> for EventDomain {
             Uppercase => BufferEvent::Uppercase.into(),
             Lowercase => BufferEvent::Lowercase.into(),
             Capitalize => BufferEvent::Capitalize.into(),
+            SortSelectionContents => BufferEvent::SortSelectionContents.into(),
+            FlattenSelection { separator } => {
+                let separator = separator.unwrap_or_else(|| " ".to_owned());
+                BufferEvent::FlattenSelection { separator }.into()
+            }
+            ExpandOnSeparator { sep } => BufferEvent::ExpandOnSeparator { sep }.into(),
             Indent => BufferEvent::Indent.into(),
             Outdent => BufferEvent::Outdent.into(),
             Reindent => SpecialEvent::Reindent.into(),
+            ForceRehighlight => SpecialEvent::ForceRehighlight.into(),
             DebugToggleComment => SpecialEvent::DebugToggleComment.into(),
             HighlightFind { visible } => ViewEvent::HighlightFind { visible }.into(),
             SelectionForFind { case_sensitive } =>
@@ -286,18 +386,41 @@ impl From<EditNotification> for EventDomain {
             Replace { chars, preserve_case } =>
                 ViewEvent::Replace { chars, preserve_case }.into(),
             ReplaceNext => BufferEvent::ReplaceNext.into(),
-            ReplaceAll => BufferEvent::ReplaceAll.into(),
+            ReplaceAll { chars, preserve_case } =>
+                BufferEvent::ReplaceAll { chars, preserve_case }.into(),
             SelectionForReplace => ViewEvent::SelectionForReplace.into(),
             RequestHover { request_id, position } =>
                 SpecialEvent::RequestHover { request_id, position }.into(),
+            RequestCompletions { request_id, position } =>
+                SpecialEvent::RequestCompletions { request_id, position }.into(),
+            ResolveCompletion { request_id, index } =>
+                SpecialEvent::ResolveCompletion { request_id, index }.into(),
             SelectionIntoLines => ViewEvent::SelectionIntoLines.into(),
+            SelectAllOccurrencesAllViews => SpecialEvent::SelectAllOccurrencesAllViews.into(),
             DuplicateLine => BufferEvent::DuplicateLine.into(),
+            ReflowListItem { column } => BufferEvent::ReflowListItem { column }.into(),
             IncreaseNumber => BufferEvent::IncreaseNumber.into(),
             DecreaseNumber => BufferEvent::DecreaseNumber.into(),
+            IncrementColumn { base, step } => BufferEvent::IncrementColumn { base, step }.into(),
+            NormalizeUnicode { form } => BufferEvent::NormalizeUnicode { form }.into(),
+            SetLineEnding { ending } => SpecialEvent::SetLineEnding(ending).into(),
+            NormalizeLineEndings => SpecialEvent::NormalizeLineEndings.into(),
             ToggleRecording { recording_name } => SpecialEvent::ToggleRecording(recording_name).into(),
             PlayRecording { recording_name } => SpecialEvent::PlayRecording(recording_name).into(),
             ClearRecording { recording_name } => SpecialEvent::ClearRecording(recording_name).into(),
             CollapseSelections => ViewEvent::CollapseSelections.into(),
+            ToggleBookmark { line } => SpecialEvent::ToggleBookmark { line }.into(),
+            NextBookmark => SpecialEvent::NextBookmark.into(),
+            PreviousBookmark => SpecialEvent::PreviousBookmark.into(),
+            ClearBookmarks => SpecialEvent::ClearBookmarks.into(),
+            Fold { line_range } => ViewEvent::Fold { line_range }.into(),
+            Unfold { line_range } => ViewEvent::Unfold { line_range }.into(),
+            UnfoldAll => ViewEvent::UnfoldAll.into(),
+            CompositionStart => SpecialEvent::CompositionStart.into(),
+            CompositionUpdate { text, caret_offset_within } =>
+                SpecialEvent::CompositionUpdate { text, caret: caret_offset_within }.into(),
+            CompositionCommit { text } => SpecialEvent::CompositionCommit(text).into(),
+            CompositionCancel => SpecialEvent::CompositionCancel.into(),
         }
     }
 }
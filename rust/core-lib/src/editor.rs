@@ -14,29 +14,38 @@
 
 use std::borrow::{Borrow, Cow};
 use std::cmp::min;
-use std::collections::BTreeSet;
+use std::collections::{BTreeSet, VecDeque};
+use std::mem;
+#[cfg(test)]
+use std::time::Duration;
+use std::time::Instant;
 
 use serde_json::Value;
 
-use xi_rope::diff::{Diff, LineHashDiff};
-use xi_rope::engine::{Engine, RevId, RevToken};
-use xi_rope::rope::count_newlines;
+use xi_rope::diff::{DiffStrategy, RopeDiffBuilder};
+use xi_rope::engine::{Engine, Error as EngineError, RetentionPolicy, RevId, RevToken};
 use xi_rope::spans::SpansBuilder;
-use xi_rope::{DeltaBuilder, Interval, LinesMetric, Rope, RopeDelta, Transformer};
+use xi_rope::{Interval, LinesMetric, Rope, RopeDelta, Transformer};
 use xi_trace::{trace_block, trace_payload};
 
 use crate::annotations::{AnnotationType, Annotations};
+use crate::bookmarks::Bookmarks;
 use crate::config::BufferItems;
+use crate::edit_latency::EditLatency;
+#[cfg(test)]
+use crate::edit_latency::EditStage;
 use crate::edit_ops::{self, IndentDirection};
 use crate::edit_types::BufferEvent;
 use crate::event_context::MAX_SIZE_LIMIT;
 use crate::layers::Layers;
 use crate::line_offset::{LineOffset, LogicalLines};
 use crate::movement::Movement;
-use crate::plugins::rpc::{DataSpan, GetDataResponse, PluginEdit, ScopeSpan, TextUnit};
+use crate::plugins::rpc::{
+    DataSpan, EditRejection, GetDataResponse, PluginEdit, ScopeSpan, TextUnit,
+};
 use crate::plugins::PluginId;
-use crate::rpc::SelectionModifier;
-use crate::selection::{InsertDrift, SelRegion, Selection};
+use crate::rpc::{NormalizationForm, SelectionModifier};
+use crate::selection::{InsertDrift, SelRegion};
 use crate::styles::ThemeStyleMap;
 use crate::view::{Replace, View};
 
@@ -49,6 +58,21 @@ use fuchsia::sync::SyncStore;
 // better to keep it low to expose bugs in the GC during casual testing.
 const MAX_UNDOS: usize = 20;
 
+/// How much edit history to keep by default, besides what is needed for undo
+/// and by plugins.
+const DEFAULT_RETENTION_POLICY: RetentionPolicy = RetentionPolicy::KeepUndoGroups(MAX_UNDOS);
+
+/// Documents at least this large (in bytes) are reloaded by diffing whole lines
+/// first, which is much faster than a fine-grained diff of the whole text.
+const RELOAD_LINE_DIFF_THRESHOLD: usize = 1 << 20;
+
+/// The minimum length of a line used to find unchanged blocks when reloading.
+const RELOAD_LINE_DIFF_MIN_SIZE: usize = 8;
+
+/// The column list items are reflowed to when neither the request nor the
+/// `wrap_width` setting gives one.
+const DEFAULT_REFLOW_COLUMN: usize = 80;
+
 pub struct Editor {
     /// The contents of the buffer.
     text: Rope,
@@ -59,6 +83,9 @@ pub struct Editor {
     last_rev_id: RevId,
     /// The revision of the last save.
     pristine_rev_id: RevId,
+    /// Whether something saved along with the text, such as the byte order
+    /// mark, has changed since the last save.
+    file_format_changed: bool,
     undo_group_id: usize,
     /// Undo groups that may still be toggled
     live_undos: Vec<usize>,
@@ -74,7 +101,11 @@ pub struct Editor {
     this_edit_type: EditType,
     last_edit_type: EditType,
 
-    revs_in_flight: usize,
+    /// The revisions sent to plugins which they have not acknowledged yet,
+    /// oldest first, with the number of acknowledgements outstanding.
+    revs_in_flight: Vec<(RevToken, usize)>,
+    /// Which edit history to keep when collecting it.
+    retention_policy: RetentionPolicy,
 
     /// Used only on Fuchsia for syncing
     #[allow(dead_code)]
@@ -83,6 +114,25 @@ pub struct Editor {
     last_synced_rev: RevId,
 
     layers: Layers,
+
+    /// Bookmarked lines, shared by all views of this buffer.
+    bookmarks: Bookmarks,
+
+    /// If `true`, edits from the user are ignored.
+    read_only: bool,
+
+    /// The chunks of a large paste which have yet to be inserted.
+    pending_paste: VecDeque<String>,
+
+    /// When the buffer should be autosaved, if an autosave is pending. Each
+    /// edit pushes this back, so that only the last timer scheduled saves.
+    autosave_deadline: Option<Instant>,
+
+    /// How long the stages of handling recent edits took.
+    edit_latency: EditLatency,
+    /// A delay added to a stage of handling each edit, for testing.
+    #[cfg(test)]
+    edit_stage_delay: Option<(EditStage, Duration)>,
 }
 
 impl Editor {
@@ -102,6 +152,7 @@ impl Editor {
             engine,
             last_rev_id,
             pristine_rev_id: last_rev_id,
+            file_format_changed: false,
             undo_group_id: 1,
             // GC only works on undone edits or prefixes of the visible edits,
             // but initial file loading can create an edit with undo group 0,
@@ -114,7 +165,15 @@ impl Editor {
             last_edit_type: EditType::Other,
             this_edit_type: EditType::Other,
             layers: Layers::default(),
-            revs_in_flight: 0,
+            bookmarks: Bookmarks::default(),
+            read_only: false,
+            pending_paste: VecDeque::new(),
+            autosave_deadline: None,
+            edit_latency: EditLatency::default(),
+            #[cfg(test)]
+            edit_stage_delay: None,
+            revs_in_flight: Vec::new(),
+            retention_policy: DEFAULT_RETENTION_POLICY,
             sync_store: None,
             last_synced_rev: last_rev_id,
         }
@@ -124,6 +183,12 @@ impl Editor {
         &self.text
     }
 
+    /// Sets whether edits from the user are ignored. The text can still be
+    /// changed by plugins, and by `reload`.
+    pub(crate) fn set_read_only(&mut self, read_only: bool) {
+        self.read_only = read_only;
+    }
+
     pub(crate) fn get_layers(&self) -> &Layers {
         &self.layers
     }
@@ -132,10 +197,27 @@ impl Editor {
         &mut self.layers
     }
 
+    pub(crate) fn get_bookmarks(&self) -> &Bookmarks {
+        &self.bookmarks
+    }
+
+    pub(crate) fn get_bookmarks_mut(&mut self) -> &mut Bookmarks {
+        &mut self.bookmarks
+    }
+
+    /// Toggles the bookmark on the line containing `offset`.
+    pub(crate) fn toggle_bookmark(&mut self, offset: usize) {
+        self.bookmarks.toggle(&self.text, offset);
+    }
+
     pub(crate) fn get_head_rev_token(&self) -> u64 {
         self.engine.get_head_rev_id().token()
     }
 
+    pub(crate) fn get_head_rev_id(&self) -> RevId {
+        self.engine.get_head_rev_id()
+    }
+
     pub(crate) fn get_edit_type(&self) -> EditType {
         self.this_edit_type
     }
@@ -150,11 +232,26 @@ impl Editor {
     }
 
     pub(crate) fn set_pristine(&mut self) {
-        self.pristine_rev_id = self.engine.get_head_rev_id();
+        self.set_pristine_rev(self.engine.get_head_rev_id());
+    }
+
+    /// Marks `rev_id` as the revision that matches the file on disk, for
+    /// saves which complete after the buffer may have been edited.
+    pub(crate) fn set_pristine_rev(&mut self, rev_id: RevId) {
+        self.pristine_rev_id = rev_id;
+        self.file_format_changed = false;
     }
 
     pub(crate) fn is_pristine(&self) -> bool {
-        self.engine.is_equivalent_revision(self.pristine_rev_id, self.engine.get_head_rev_id())
+        let head_rev_id = self.engine.get_head_rev_id();
+        !self.file_format_changed
+            && self.engine.is_equivalent_revision(self.pristine_rev_id, head_rev_id)
+    }
+
+    /// Marks the buffer as modified, although its text is unchanged, because
+    /// saving it would write different bytes (for instance, without a BOM).
+    pub(crate) fn set_file_format_changed(&mut self) {
+        self.file_format_changed = true;
     }
 
     /// Set whether or not edits are forced into the same undo group rather than being split by
@@ -170,23 +267,46 @@ impl Editor {
     /// Sets this Editor's contents to `text`, preserving undo state and cursor
     /// position when possible.
     pub fn reload(&mut self, text: Rope) {
-        let delta = LineHashDiff::compute_delta(self.get_buffer(), &text);
+        let base = self.get_buffer();
+        let mut builder = RopeDiffBuilder::new();
+        if base.len().max(text.len()) >= RELOAD_LINE_DIFF_THRESHOLD {
+            let min_size = RELOAD_LINE_DIFF_MIN_SIZE;
+            builder = builder.strategy(DiffStrategy::LineHashed { min_size });
+        }
+        let delta = builder.compute_delta(base, &text);
         self.add_delta(delta);
         self.set_pristine();
     }
 
-    // each outstanding plugin edit represents a rev_in_flight.
+    // each outstanding plugin edit represents a rev_in_flight, of the head revision.
     pub fn increment_revs_in_flight(&mut self) {
-        self.revs_in_flight += 1;
+        let head_rev = self.engine.get_head_rev_id().token();
+        match self.revs_in_flight.last_mut() {
+            Some((rev, count)) if *rev == head_rev => *count += 1,
+            _ => self.revs_in_flight.push((head_rev, 1)),
+        }
     }
 
     // GC of CRDT engine is deferred until all plugins have acknowledged the new rev,
     // so when the ack comes back, potentially trigger GC.
-    pub fn dec_revs_in_flight(&mut self) {
-        self.revs_in_flight -= 1;
+    pub fn dec_revs_in_flight(&mut self, rev: RevToken) {
+        match self.revs_in_flight.iter().position(|&(r, _)| r == rev) {
+            Some(ix) => {
+                self.revs_in_flight[ix].1 -= 1;
+                if self.revs_in_flight[ix].1 == 0 {
+                    self.revs_in_flight.remove(ix);
+                }
+            }
+            None => warn!("revision {} was not in flight", rev),
+        }
         self.gc_undos();
     }
 
+    /// Sets which edit history `gc_history` keeps.
+    pub fn set_retention_policy(&mut self, policy: RetentionPolicy) {
+        self.retention_policy = policy;
+    }
+
     /// Applies a delta to the text, and updates undo state.
     ///
     /// Records the delta into the CRDT engine so that it can be undone. Also
@@ -230,16 +350,39 @@ impl Editor {
     }
 
     /// generates a delta from a plugin's response and applies it to the buffer.
-    pub fn apply_plugin_edit(&mut self, edit: PluginEdit) {
+    ///
+    /// Edits based on an older revision are transformed to apply to the head;
+    /// a checked edit is rejected instead if the text it changes has been
+    /// edited since its revision.
+    pub fn apply_plugin_edit(&mut self, edit: PluginEdit) -> Result<(), EditRejection> {
         let _t = trace_block("Editor::apply_plugin_edit", &["core"]);
         //TODO: get priority working, so that plugin edits don't necessarily move cursor
-        let PluginEdit { rev, delta, priority, undo_group, .. } = edit;
+        let PluginEdit { rev, delta, priority, undo_group, checked, .. } = edit;
+        if checked && rev != self.get_head_rev_token() {
+            let concurrent =
+                self.engine.try_delta_rev_head(rev).map_err(|_| EditRejection::MissingRevision)?;
+            let (changed, _) = delta.summary();
+            if !Transformer::new(&concurrent).interval_untouched(changed) {
+                return Err(EditRejection::Conflict);
+            }
+        }
         let priority = priority as usize;
         let undo_group = undo_group.unwrap_or_else(|| self.calculate_undo_group());
         match self.engine.try_edit_rev(priority, undo_group, rev, delta) {
-            Err(e) => error!("Error applying plugin edit: {}", e),
-            Ok(_) => self.text = self.engine.get_head().clone(),
-        };
+            Err(e) => {
+                error!("Error applying plugin edit: {}", e);
+                Err(match e {
+                    EngineError::MissingRevision(_) | EngineError::CollectedRevision(_) => {
+                        EditRejection::MissingRevision
+                    }
+                    EngineError::MalformedDelta { .. } => EditRejection::MalformedDelta,
+                })
+            }
+            Ok(_) => {
+                self.text = self.engine.get_head().clone();
+                Ok(())
+            }
+        }
     }
 
     /// Commits the current delta. If the buffer has changed, returns
@@ -264,9 +407,12 @@ impl Editor {
         let drift = match self.this_edit_type {
             EditType::Transpose => InsertDrift::Inside,
             EditType::Surround => InsertDrift::Outside,
+            EditType::LineEnding => InsertDrift::Trailing,
+            EditType::Normalize => InsertDrift::InPlace,
             _ => InsertDrift::Default,
         };
         self.layers.update_all(&delta);
+        self.bookmarks.apply_delta(&delta, &last_text, &self.text);
 
         self.last_rev_id = self.engine.get_head_rev_id();
         self.sync_state_changed();
@@ -282,7 +428,7 @@ impl Editor {
 
     #[cfg(not(target_os = "fuchsia"))]
     fn gc_undos(&mut self) {
-        if self.revs_in_flight == 0 && !self.gc_undos.is_empty() {
+        if self.revs_in_flight.is_empty() && !self.gc_undos.is_empty() {
             self.engine.gc(&self.gc_undos);
             self.undos = &self.undos - &self.gc_undos;
             self.gc_undos.clear();
@@ -295,6 +441,26 @@ impl Editor {
         // last_rev_id and so that merge will work.
     }
 
+    /// Collects the edit history not kept by the retention policy, unless it
+    /// can still be undone, or is needed to resolve the revisions in flight.
+    /// Unlike `gc_undos`, this does not wait for plugins to acknowledge them.
+    #[cfg(not(target_os = "fuchsia"))]
+    pub(crate) fn gc_history(&mut self) {
+        let oldest_rev =
+            self.revs_in_flight.first().map_or_else(|| self.last_rev_id.token(), |&(rev, _)| rev);
+        let live_undos = self.live_undos.iter().cloned().collect();
+        match self.engine.gc_before(oldest_rev, self.retention_policy, &live_undos) {
+            Ok(collected) => {
+                self.undos = &self.undos - &collected;
+                self.gc_undos = &self.gc_undos - &collected;
+            }
+            Err(e) => error!("Error collecting edit history: {}", e),
+        }
+    }
+
+    #[cfg(target_os = "fuchsia")]
+    pub(crate) fn gc_history(&mut self) {}
+
     pub fn merge_new_state(&mut self, new_engine: Engine) {
         self.engine.merge(&new_engine);
         self.text = self.engine.get_head().clone();
@@ -355,21 +521,99 @@ impl Editor {
         }
     }
 
-    fn do_paste(&mut self, view: &View, chars: &str) {
-        if view.sel_regions().len() == 1 || view.sel_regions().len() != count_lines(chars) {
-            self.add_delta(edit_ops::insert(&self.text, view.sel_regions(), chars));
-        } else {
-            let mut builder = DeltaBuilder::new(self.text.len());
-            for (sel, line) in view.sel_regions().iter().zip(chars.lines()) {
-                let iv = Interval::new(sel.min(), sel.max());
-                builder.replace(iv, line.into());
+    /// Inserts the text of an input method composition at the selections.
+    /// Unlike typing, this never surrounds the selections with a pair.
+    pub(crate) fn commit_composition(&mut self, view: &View, chars: &str) {
+        self.this_edit_type = EditType::InsertChars;
+        self.add_delta(edit_ops::insert(&self.text, view.sel_regions(), chars));
+    }
+
+    fn do_paste(&mut self, view: &View, config: &BufferItems, chars: &str) {
+        let chunk_size = config.paste_chunk_size;
+        // pastes split across several selections are never chunked
+        if chunk_size == 0 || chars.len() <= chunk_size || view.sel_regions().len() != 1 {
+            self.add_delta(edit_ops::paste(&self.text, view.sel_regions(), config, chars));
+            return;
+        }
+        self.pending_paste = edit_ops::paste_chunks(config, chars).into();
+        let first = self.pending_paste.pop_front().unwrap_or_default();
+        self.add_delta(edit_ops::insert(&self.text, view.sel_regions(), first));
+    }
+
+    fn do_paste_with_reindent(&mut self, view: &View, config: &BufferItems, chars: &str) {
+        let delta = edit_ops::paste_with_reindent(&self.text, view.sel_regions(), config, chars);
+        self.add_delta(delta);
+    }
+
+    /// Returns `true` if a large paste is being inserted in chunks.
+    pub(crate) fn has_pending_paste(&self) -> bool {
+        !self.pending_paste.is_empty()
+    }
+
+    /// Inserts the next chunk of a pending paste at the selection, in the
+    /// undo group of the paste. Returns `true` if more chunks remain.
+    pub(crate) fn do_paste_chunk(&mut self, view: &View) -> bool {
+        if let Some(chunk) = self.pending_paste.pop_front() {
+            self.add_paste_delta(edit_ops::insert(&self.text, view.sel_regions(), chunk));
+        }
+        self.has_pending_paste()
+    }
+
+    /// Inserts all the remaining chunks of a pending paste at once.
+    pub(crate) fn finish_paste(&mut self, view: &View) {
+        if self.has_pending_paste() {
+            let rest: String = self.pending_paste.drain(..).collect();
+            self.add_paste_delta(edit_ops::insert(&self.text, view.sel_regions(), rest));
+        }
+    }
+
+    /// Sets when the buffer should next be autosaved, replacing any earlier
+    /// deadline.
+    pub(crate) fn set_autosave_deadline(&mut self, deadline: Instant) {
+        self.autosave_deadline = Some(deadline);
+    }
+
+    /// Returns `true`, and clears the deadline, if an autosave was scheduled
+    /// for `now` or earlier.
+    pub(crate) fn take_due_autosave(&mut self, now: Instant) -> bool {
+        match self.autosave_deadline {
+            Some(deadline) if deadline <= now => {
+                self.autosave_deadline = None;
+                true
             }
-            self.add_delta(builder.build());
+            _ => false,
         }
     }
 
+    pub(crate) fn edit_latency(&self) -> &EditLatency {
+        &self.edit_latency
+    }
+
+    pub(crate) fn edit_latency_mut(&mut self) -> &mut EditLatency {
+        &mut self.edit_latency
+    }
+
+    #[cfg(test)]
+    pub(crate) fn set_edit_stage_delay(&mut self, delay: Option<(EditStage, Duration)>) {
+        self.edit_stage_delay = delay;
+    }
+
+    #[cfg(test)]
+    pub(crate) fn edit_stage_delay(&self) -> Option<(EditStage, Duration)> {
+        self.edit_stage_delay
+    }
+
+    fn add_paste_delta(&mut self, delta: RopeDelta) {
+        let force_undo_group = mem::replace(&mut self.force_undo_group, true);
+        self.add_delta(delta);
+        self.force_undo_group = force_undo_group;
+    }
+
     pub(crate) fn do_cut(&mut self, view: &mut View) -> Value {
         let result = self.do_copy(view);
+        if self.read_only {
+            return result;
+        }
         let delta = edit_ops::delete_sel_regions(&self.text, view.sel_regions());
         if !delta.is_identity() {
             self.this_edit_type = EditType::Delete;
@@ -409,33 +653,45 @@ impl Editor {
         self.text = self.engine.get_head().clone();
     }
 
-    fn do_replace(&mut self, view: &mut View, replace_all: bool) {
-        if let Some(Replace { chars, .. }) = view.get_replace() {
-            // todo: implement preserve case
-            // store old selection because in case nothing is found the selection will be preserved
-            let mut old_selection = Selection::new();
-            for &region in view.sel_regions() {
-                old_selection.add_region(region);
-            }
+    fn do_replace_next(&mut self, view: &mut View) {
+        if let Some(Replace { chars, preserve_case }) = view.get_replace() {
             view.collapse_selections(&self.text);
-
-            if replace_all {
-                view.do_find_all(&self.text);
-            } else {
-                view.do_find_next(&self.text, false, true, true, &SelectionModifier::Set);
-            }
+            view.do_find_next(&self.text, false, true, true, &SelectionModifier::Set);
 
             if last_selection_region(view.sel_regions()).is_some() {
-                self.add_delta(edit_ops::insert(&self.text, view.sel_regions(), chars));
+                self.this_edit_type = EditType::Other;
+                let regions = view.sel_regions();
+                self.add_delta(edit_ops::replace_all(&self.text, regions, &chars, preserve_case));
             }
         }
     }
 
+    /// Replaces every find match at once, so that a single undo restores
+    /// them. The matches all refer to the text before the edit, and the
+    /// selections are left in place, moved along by the edit.
+    fn do_replace_all(&mut self, view: &mut View, chars: Option<String>, preserve_case: bool) {
+        let Replace { chars, preserve_case } = match chars {
+            Some(chars) => Replace { chars, preserve_case },
+            None => match view.get_replace() {
+                Some(replace) => replace,
+                None => return,
+            },
+        };
+        view.finish_find(&self.text);
+        let matches = view.find_occurrences();
+        let delta = edit_ops::replace_all(&self.text, &matches, &chars, preserve_case);
+        if !delta.is_identity() {
+            self.this_edit_type = EditType::Other;
+            self.add_delta(delta);
+        }
+    }
+
     fn do_delete_by_movement(
         &mut self,
         view: &View,
         movement: Movement,
         save: bool,
+        count: usize,
         kill_ring: &mut Rope,
     ) {
         let (delta, rope) = edit_ops::delete_by_movement(
@@ -445,6 +701,7 @@ impl Editor {
             movement,
             view.scroll_height(),
             save,
+            count,
         );
         if let Some(rope) = rope {
             *kill_ring = rope;
@@ -471,6 +728,34 @@ impl Editor {
         }
     }
 
+    fn do_sort_selection_contents(&mut self, view: &View) {
+        let delta = edit_ops::sort_selection_contents(&self.text, view.sel_regions());
+        if !delta.is_identity() {
+            // Like transposing selections, this permutes their contents.
+            self.this_edit_type = EditType::Transpose;
+            self.add_delta(delta);
+        }
+    }
+
+    fn do_flatten_selection(&mut self, view: &View, separator: &str) {
+        let delta = edit_ops::flatten_selection(&self.text, view.sel_regions(), separator);
+        if !delta.is_identity() {
+            // Like normalizing, this keeps selections around the replaced text.
+            self.this_edit_type = EditType::Transpose;
+            self.add_delta(delta);
+        }
+    }
+
+    fn do_expand_on_separator(&mut self, view: &View, config: &BufferItems, separator: &str) {
+        let regions = view.sel_regions();
+        let delta =
+            edit_ops::expand_on_separator(&self.text, regions, separator, &config.line_ending);
+        if !delta.is_identity() {
+            self.this_edit_type = EditType::Transpose;
+            self.add_delta(delta);
+        }
+    }
+
     fn do_transform_text<F: Fn(&str) -> String>(&mut self, view: &View, transform_function: F) {
         let delta = edit_ops::transform_text(&self.text, view.sel_regions(), transform_function);
         if !delta.is_identity() {
@@ -479,6 +764,23 @@ impl Editor {
         }
     }
 
+    fn do_normalize_unicode(&mut self, view: &View, form: NormalizationForm) {
+        let delta = edit_ops::normalize_unicode(&self.text, view.sel_regions(), form);
+        if !delta.is_identity() {
+            self.this_edit_type = EditType::Normalize;
+            self.add_delta(delta);
+        }
+    }
+
+    /// Converts the line endings of the buffer to `line_ending`, as one edit.
+    pub(crate) fn convert_line_endings(&mut self, line_ending: &str) {
+        let delta = edit_ops::convert_line_endings(&self.text, line_ending);
+        if !delta.is_identity() {
+            self.this_edit_type = EditType::LineEnding;
+            self.add_delta(delta);
+        }
+    }
+
     fn do_capitalize_text(&mut self, view: &mut View) {
         let (delta, final_selection) = edit_ops::capitalize_text(&self.text, view.sel_regions());
         if !delta.is_identity() {
@@ -538,6 +840,17 @@ impl Editor {
         self.this_edit_type = EditType::Other;
     }
 
+    fn do_reflow_list_item(&mut self, view: &View, config: &BufferItems, column: Option<usize>) {
+        let column = column
+            .or_else(|| Some(config.wrap_width).filter(|&width| width > 0))
+            .unwrap_or(DEFAULT_REFLOW_COLUMN);
+        let delta = edit_ops::reflow_list_item(&self.text, view.sel_regions(), column, config);
+        if !delta.is_identity() {
+            self.this_edit_type = EditType::Other;
+            self.add_delta(delta);
+        }
+    }
+
     fn do_change_number<F: Fn(i128) -> Option<i128>>(
         &mut self,
         view: &View,
@@ -550,6 +863,14 @@ impl Editor {
         }
     }
 
+    fn do_increment_column(&mut self, view: &View, base: i64, step: i64) {
+        let delta = edit_ops::increment_column(&self.text, view.sel_regions(), base, step);
+        if !delta.is_identity() {
+            self.this_edit_type = EditType::Other;
+            self.add_delta(delta);
+        }
+    }
+
     pub(crate) fn do_edit(
         &mut self,
         view: &mut View,
@@ -558,9 +879,12 @@ impl Editor {
         cmd: BufferEvent,
     ) {
         use self::BufferEvent::*;
+        if self.read_only {
+            return;
+        }
         match cmd {
-            Delete { movement, kill } => {
-                self.do_delete_by_movement(view, movement, kill, kill_ring)
+            Delete { movement, kill, count } => {
+                self.do_delete_by_movement(view, movement, kill, count, kill_ring)
             }
             Backspace => self.do_delete_backward(view, config),
             Transpose => self.do_transpose(view),
@@ -569,18 +893,25 @@ impl Editor {
             Uppercase => self.do_transform_text(view, |s| s.to_uppercase()),
             Lowercase => self.do_transform_text(view, |s| s.to_lowercase()),
             Capitalize => self.do_capitalize_text(view),
+            SortSelectionContents => self.do_sort_selection_contents(view),
+            FlattenSelection { separator } => self.do_flatten_selection(view, &separator),
+            ExpandOnSeparator { sep } => self.do_expand_on_separator(view, config, &sep),
             Indent => self.do_modify_indent(view, config, IndentDirection::In),
             Outdent => self.do_modify_indent(view, config, IndentDirection::Out),
             InsertNewline => self.do_insert_newline(view, config),
             InsertTab => self.do_insert_tab(view, config),
             Insert(chars) => self.do_insert(view, config, &chars),
-            Paste(chars) => self.do_paste(view, &chars),
+            Paste(chars) => self.do_paste(view, config, &chars),
+            PasteWithReindent(chars) => self.do_paste_with_reindent(view, config, &chars),
             Yank => self.do_yank(view, kill_ring),
-            ReplaceNext => self.do_replace(view, false),
-            ReplaceAll => self.do_replace(view, true),
+            ReplaceNext => self.do_replace_next(view),
+            ReplaceAll { chars, preserve_case } => self.do_replace_all(view, chars, preserve_case),
             DuplicateLine => self.do_duplicate_line(view, config),
+            ReflowListItem { column } => self.do_reflow_list_item(view, config, column),
             IncreaseNumber => self.do_change_number(view, |s| s.checked_add(1)),
             DecreaseNumber => self.do_change_number(view, |s| s.checked_sub(1)),
+            IncrementColumn { base, step } => self.do_increment_column(view, base, step),
+            NormalizeUnicode { form } => self.do_normalize_unicode(view, form),
         }
     }
 
@@ -725,12 +1056,22 @@ pub enum EditType {
     Redo,
     Transpose,
     Surround,
+    /// A conversion of line endings, which leaves carets and selections on
+    /// the same side of each line break.
+    LineEnding,
+    /// A Unicode normalization, which replaces text in place: selections
+    /// keep covering it, and carets stay in front of it.
+    Normalize,
 }
 
 impl EditType {
     /// Checks whether a new undo group should be created between two edits.
     fn breaks_undo_group(self, previous: EditType) -> bool {
-        self == EditType::Other || self == EditType::Transpose || self != previous
+        self == EditType::Other
+            || self == EditType::Transpose
+            || self == EditType::LineEnding
+            || self == EditType::Normalize
+            || self != previous
     }
 }
 
@@ -743,18 +1084,10 @@ fn last_selection_region(regions: &[SelRegion]) -> Option<&SelRegion> {
     None
 }
 
-/// Counts the number of lines in the string, not including any trailing newline.
-fn count_lines(s: &str) -> usize {
-    let mut newlines = count_newlines(s);
-    if s.as_bytes().last() == Some(&0xa) {
-        newlines -= 1;
-    }
-    1 + newlines
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
+    use xi_rope::DeltaBuilder;
 
     #[test]
     fn plugin_edit() {
@@ -772,11 +1105,60 @@ mod tests {
             after_cursor: false,
             undo_group: None,
             author: "plugin_one".into(),
+            checked: false,
         };
 
-        editor.apply_plugin_edit(edit_one.clone());
-        editor.apply_plugin_edit(edit_one);
+        assert_eq!(editor.apply_plugin_edit(edit_one.clone()), Ok(()));
+        assert_eq!(editor.apply_plugin_edit(edit_one), Ok(()));
 
         assert_eq!(editor.get_buffer().to_string(), "sshello");
     }
+
+    /// Inserts "x" at the start of the buffer, in a new undo group.
+    fn insert_x(editor: &mut Editor) {
+        let mut builder = DeltaBuilder::new(editor.get_buffer().len());
+        builder.replace(0..0, "x".into());
+        let edit = PluginEdit {
+            rev: editor.get_head_rev_token(),
+            delta: builder.build(),
+            priority: 0x10000,
+            after_cursor: false,
+            undo_group: None,
+            author: "core".into(),
+            checked: false,
+        };
+        editor.apply_plugin_edit(edit).unwrap();
+        editor.commit_delta();
+    }
+
+    #[test]
+    fn gc_history_keeps_revs_in_flight() {
+        let mut editor = Editor::with_text("hello");
+        insert_x(&mut editor);
+        let early_rev = editor.get_head_rev_token();
+        (0..4).for_each(|_| insert_x(&mut editor));
+        // a plugin is working on this revision
+        editor.increment_revs_in_flight();
+        let held_rev = editor.get_head_rev_token();
+        let held_text = editor.get_buffer().clone();
+        (0..MAX_UNDOS + 10).for_each(|_| insert_x(&mut editor));
+        let old_rev = editor.get_head_rev_token();
+
+        editor.gc_history();
+        // the edits before the held revision, which can't be undone, are gone
+        assert!(editor.get_rev(early_rev).is_none());
+        assert!(editor.get_rev(old_rev).is_some());
+        let delta = editor.delta_rev_head(held_rev).unwrap();
+        assert_eq!(String::from(delta.apply(&held_text)), String::from(editor.get_buffer()));
+
+        (0..MAX_UNDOS).for_each(|_| insert_x(&mut editor));
+        editor.gc_history();
+        assert!(editor.delta_rev_head(held_rev).is_some());
+
+        // once the plugin is done, the rest of the history can be collected
+        editor.dec_revs_in_flight(held_rev);
+        assert!(editor.delta_rev_head(held_rev).is_none());
+        assert!(editor.get_rev(old_rev).is_none());
+        assert_eq!(editor.get_buffer().len(), "hello".len() + 5 + 2 * MAX_UNDOS + 10);
+    }
 }
@@ -17,35 +17,41 @@
 use std::cell::RefCell;
 use std::iter;
 use std::ops::Range;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::time::{Duration, Instant};
 
 use serde_json::{self, Value};
 
-use xi_rope::{Cursor, Interval, LinesMetric, Rope, RopeDelta};
+use xi_rope::engine::{RevId, RevToken};
+use xi_rope::{Interval, LinesMetric, Rope, RopeDelta};
 use xi_rpc::{Error as RpcError, RemoteError};
-use xi_trace::trace_block;
+use xi_trace::{trace_block, trace_block_payload};
 
 use crate::plugins::rpc::{
-    ClientPluginInfo, Hover, PluginBufferInfo, PluginNotification, PluginRequest, PluginUpdate,
+    CompletionItem, CompletionList, GetConfigResponse, GetSelectionsResponse, Hover,
+    PluginBufferInfo, PluginEdit, PluginNotification, PluginRequest, PluginSelRegion, PluginUpdate,
 };
 use crate::rpc::{EditNotification, EditRequest, LineRange, Position as ClientPosition};
 
+use crate::bookmarks::Bookmarks;
 use crate::client::Client;
 use crate::config::{BufferItems, Table};
+use crate::edit_latency::{EditStage, EditTimings};
 use crate::edit_types::{EventDomain, SpecialEvent};
 use crate::editor::Editor;
-use crate::file::FileInfo;
+use crate::file::{CharacterEncoding, FileInfo};
+use crate::find::Find;
 use crate::line_offset::LineOffset;
 use crate::plugins::Plugin;
 use crate::recorder::Recorder;
-use crate::selection::InsertDrift;
+use crate::selection::{InsertDrift, SelRegion, Selection};
 use crate::styles::ThemeStyleMap;
 use crate::syntax::LanguageId;
 use crate::tabs::{
-    BufferId, PluginId, ViewId, FIND_VIEW_IDLE_MASK, RENDER_VIEW_IDLE_MASK, REWRAP_VIEW_IDLE_MASK,
+    BufferId, PluginId, ViewId, AUTOSAVE_VIEW_IDLE_MASK, FIND_STATUS_VIEW_IDLE_MASK,
+    FIND_VIEW_IDLE_MASK, PASTE_VIEW_IDLE_MASK, RENDER_VIEW_IDLE_MASK, REWRAP_VIEW_IDLE_MASK,
 };
-use crate::view::View;
+use crate::view::{LinePosition, View};
 use crate::width_cache::WidthCache;
 use crate::WeakXiCore;
 
@@ -58,6 +64,49 @@ pub const MAX_SIZE_LIMIT: usize = 1024 * 1024;
 /// window will be sent to the view along with the edit.
 const RENDER_DELAY: Duration = Duration::from_millis(2);
 
+/// The minimum interval between find status updates while incremental find is running.
+const FIND_STATUS_INTERVAL: Duration = Duration::from_millis(100);
+
+/// State derived from the config, which has to be recomputed when some
+/// keys change. Variants are listed in the order they are recomputed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ConfigDependent {
+    WidthCache,
+    WrapSettings,
+    BookmarkSettings,
+    PrefetchSettings,
+}
+
+impl ConfigDependent {
+    const ALL: [ConfigDependent; 4] = [
+        ConfigDependent::WidthCache,
+        ConfigDependent::WrapSettings,
+        ConfigDependent::BookmarkSettings,
+        ConfigDependent::PrefetchSettings,
+    ];
+
+    fn keys(self) -> &'static [&'static str] {
+        match self {
+            ConfigDependent::WidthCache => &["word_wrap", "tab_size", "tab_display_width"],
+            ConfigDependent::WrapSettings => {
+                &["word_wrap", "wrap_width", "tab_size", "tab_display_width"]
+            }
+            ConfigDependent::BookmarkSettings => &["keep_bookmarks_on_deleted_lines"],
+            ConfigDependent::PrefetchSettings => &["prefetch_min_lines", "prefetch_max_lines"],
+        }
+    }
+
+    /// Returns the state to recompute after `changes`, in order. Each is
+    /// recomputed once, however many of its keys changed.
+    fn for_changes(changes: &Table) -> Vec<ConfigDependent> {
+        ConfigDependent::ALL
+            .iter()
+            .cloned()
+            .filter(|dep| dep.keys().iter().any(|key| changes.contains_key(*key)))
+            .collect()
+    }
+}
+
 /// A collection of all the state relevant for handling a particular event.
 ///
 /// This is created dynamically for each event that arrives to the core,
@@ -68,6 +117,8 @@ pub struct EventContext<'a> {
     pub(crate) editor: &'a RefCell<Editor>,
     pub(crate) info: Option<&'a FileInfo>,
     pub(crate) config: &'a BufferItems,
+    /// The generation of `config`, sent to plugins with each update.
+    pub(crate) config_generation: u64,
     pub(crate) recorder: &'a RefCell<Recorder>,
     pub(crate) language: LanguageId,
     pub(crate) view: &'a RefCell<View>,
@@ -112,6 +163,15 @@ impl<'a> EventContext<'a> {
     pub(crate) fn do_edit(&mut self, cmd: EditNotification) {
         let event: EventDomain = cmd.into();
 
+        if event.interrupts_composition() && self.view.borrow().is_composing() {
+            if !self.config.commit_interrupted_composition {
+                warn!("ignoring {:?} during input method composition", event);
+                return;
+            }
+            self.commit_composition(None);
+            self.after_edit("core");
+        }
+
         {
             // Handle recording-- clone every non-toggle and play event into the recording buffer
             let mut recorder = self.recorder.borrow_mut();
@@ -128,8 +188,12 @@ impl<'a> EventContext<'a> {
             }
         }
 
+        self.finish_pending_paste();
         self.dispatch_event(event);
         self.after_edit("core");
+        if self.editor.borrow().has_pending_paste() {
+            self.schedule_paste();
+        }
         self.render_if_needed();
     }
 
@@ -175,8 +239,18 @@ impl<'a> EventContext<'a> {
             SpecialEvent::RequestHover { request_id, position } => {
                 self.do_request_hover(request_id, position)
             }
+            SpecialEvent::RequestCompletions { request_id, position } => {
+                self.do_request_completions(request_id, position)
+            }
+            SpecialEvent::ResolveCompletion { request_id, index } => {
+                self.with_each_plugin(|p| p.resolve_completion(self.view_id, request_id, index))
+            }
+            SpecialEvent::SelectAllOccurrencesAllViews => {
+                self.do_select_all_occurrences_all_views()
+            }
             SpecialEvent::DebugToggleComment => self.do_debug_toggle_comment(),
             SpecialEvent::Reindent => self.do_reindent(),
+            SpecialEvent::ForceRehighlight => self.force_rehighlight(),
             SpecialEvent::ToggleRecording(_) => {}
             SpecialEvent::PlayRecording(recording_name) => {
                 let recorder = self.recorder.borrow();
@@ -193,6 +267,8 @@ impl<'a> EventContext<'a> {
                     self.dispatch_event(event.clone());
 
                     let mut editor = self.editor.borrow_mut();
+                    // the events after a paste expect it to be complete
+                    editor.finish_paste(&self.view.borrow());
                     let (delta, last_text, drift) = match editor.commit_delta() {
                         Some(edit_info) => edit_info,
                         None => return,
@@ -204,21 +280,77 @@ impl<'a> EventContext<'a> {
                 // The action that follows the block must belong to a separate undo group
                 self.editor.borrow_mut().update_edit_type();
 
-                let delta = self.editor.borrow_mut().delta_rev_head(starting_revision).unwrap();
-                self.update_plugins(&mut self.editor.borrow_mut(), delta, "core");
+                let delta = self.editor.borrow().delta_rev_head(starting_revision);
+                match delta {
+                    Some(delta) => {
+                        self.update_plugins(&mut self.editor.borrow_mut(), delta, "core")
+                    }
+                    None => error!("revision before playing {} was lost", recording_name),
+                }
             }
             SpecialEvent::ClearRecording(recording_name) => {
                 let mut recorder = self.recorder.borrow_mut();
                 recorder.clear(&recording_name);
             }
+            SpecialEvent::ToggleBookmark { line } => self.with_editor(|ed, view, _, _| {
+                let offset = view.line_col_to_offset(ed.get_buffer(), line as usize, 0);
+                ed.toggle_bookmark(offset);
+            }),
+            SpecialEvent::NextBookmark => self.goto_bookmark(true),
+            SpecialEvent::PreviousBookmark => self.goto_bookmark(false),
+            SpecialEvent::ClearBookmarks => {
+                self.editor.borrow_mut().get_bookmarks_mut().clear();
+            }
+            // `CoreState` updates the config once the buffer is converted
+            SpecialEvent::SetLineEnding(ending) => {
+                self.editor.borrow_mut().convert_line_endings(ending.as_str())
+            }
+            SpecialEvent::NormalizeLineEndings => {
+                self.editor.borrow_mut().convert_line_endings(&self.config.line_ending)
+            }
+            SpecialEvent::CompositionStart => self.with_view(|view, _| view.start_composition()),
+            SpecialEvent::CompositionUpdate { text, caret } => {
+                match self.view.borrow_mut().composition_mut() {
+                    Some(composition) => composition.update(text, caret),
+                    None => warn!("composition_update without composition_start"),
+                }
+            }
+            SpecialEvent::CompositionCommit(text) => self.commit_composition(Some(text)),
+            SpecialEvent::CompositionCancel => {
+                self.view.borrow_mut().take_composition();
+            }
+        }
+    }
+
+    /// Ends the input method composition in progress, inserting `text`, or
+    /// the composition's own text if `None`, at the selections. Text
+    /// committed with no composition in progress is inserted all the same.
+    fn commit_composition(&mut self, text: Option<String>) {
+        let composition = self.view.borrow_mut().take_composition();
+        let text = match (text, composition) {
+            (Some(text), _) => text,
+            (None, Some(composition)) => composition.text().to_owned(),
+            (None, None) => return,
+        };
+        if !text.is_empty() {
+            self.with_editor(|ed, view, _, _| ed.commit_composition(view, &text));
         }
     }
 
     pub(crate) fn do_edit_sync(&mut self, cmd: EditRequest) -> Result<Value, RemoteError> {
         use self::EditRequest::*;
+        self.finish_pending_paste();
         let result = match cmd {
             Cut => Ok(self.with_editor(|ed, view, _, _| ed.do_cut(view))),
             Copy => Ok(self.with_editor(|ed, view, _, _| ed.do_copy(view))),
+            GetBookmarks => Ok(self.bookmarks_json()),
+            FindAll { query, case_sensitive, regex, whole_words } => {
+                self.find_all_json(&query, case_sensitive, regex, whole_words)
+            }
+            GetFindMatchLines { max_lines } => {
+                let ed = self.editor.borrow();
+                Ok(json!(self.view.borrow().get_find_match_lines(ed.get_buffer(), max_lines)))
+            }
         };
         self.after_edit("core");
         self.render_if_needed();
@@ -236,7 +368,16 @@ impl<'a> EventContext<'a> {
             UpdateSpans { start, len, spans, rev } => self.with_editor(|ed, view, _, _| {
                 ed.update_spans(view, plugin, start, len, spans, rev)
             }),
-            Edit { edit } => self.with_editor(|ed, _, _, _| ed.apply_plugin_edit(edit)),
+            Edit { edit } => {
+                let (rev, checked) = (edit.rev, edit.checked);
+                let result = self.with_editor(|ed, _, _, _| ed.apply_plugin_edit(edit));
+                if let (Err(reason), true) = (result, checked) {
+                    let head_rev = self.editor.borrow().get_head_rev_token();
+                    if let Some(plugin) = self.plugins.iter().find(|p| p.id == plugin) {
+                        plugin.edit_rejected(self.view_id, rev, head_rev, reason);
+                    }
+                }
+            }
             Alert { msg } => self.client.alert(&msg),
             AddStatusItem { key, value, alignment } => {
                 let plugin_name = &self.plugins.iter().find(|p| p.id == plugin).unwrap().name;
@@ -246,25 +387,73 @@ impl<'a> EventContext<'a> {
                 self.client.update_status_item(self.view_id, &key, &value)
             }
             UpdateAnnotations { start, len, spans, annotation_type, rev } => {
+                if let Some(kind) = annotation_type.highlight_kind() {
+                    if !self.view.borrow().highlight_kinds().is_owned_by(kind, plugin) {
+                        warn!("{} sent annotations for unregistered kind {:?}", plugin, kind);
+                        return;
+                    }
+                }
                 self.with_editor(|ed, view, _, _| {
                     ed.update_annotations(view, plugin, start, len, spans, annotation_type, rev)
                 })
             }
             RemoveStatusItem { key } => self.client.remove_status_item(self.view_id, &key),
             ShowHover { request_id, result } => self.do_show_hover(request_id, result),
+            ShowCompletions { request_id, result } => self.do_show_completions(request_id, result),
+            ShowResolvedCompletion { request_id, index, result } => {
+                self.do_show_resolved_completion(request_id, index, result)
+            }
         };
         self.after_edit(&plugin.to_string());
         self.render_if_needed();
     }
 
-    pub(crate) fn do_plugin_cmd_sync(&mut self, _plugin: PluginId, cmd: PluginRequest) -> Value {
+    pub(crate) fn do_plugin_cmd_sync(&mut self, plugin: PluginId, cmd: PluginRequest) -> Value {
         use self::PluginRequest::*;
         match cmd {
             LineCount => json!(self.editor.borrow().plugin_n_lines()),
             GetData { start, unit, max_size, rev } => {
                 json!(self.editor.borrow().plugin_get_data(start, unit, max_size, rev))
             }
-            GetSelections => json!("not implemented"),
+            GetSelections {} => json!(self.plugin_get_selections()),
+            GetConfig {} => json!(self.plugin_get_config()),
+            RegisterHighlightKind { name, default_style_hints } => json!(self
+                .view
+                .borrow_mut()
+                .register_highlight_kind(plugin, &name, default_style_hints)),
+            CreateScratchView { .. } | UpdateScratchView { .. } => {
+                unreachable!("scratch views are handled by CoreState")
+            }
+            WatchPath { .. } | UnwatchPath { .. } => {
+                unreachable!("path watches are handled by CoreState")
+            }
+        }
+    }
+
+    /// Returns the selection of this view in the form sent to plugins.
+    fn plugin_get_selections(&self) -> GetSelectionsResponse {
+        let rev = self.editor.borrow().get_head_rev_token();
+        let selections = self
+            .view
+            .borrow()
+            .sel_regions()
+            .iter()
+            .map(|region| PluginSelRegion {
+                start: region.start,
+                end: region.end,
+                is_caret: region.is_caret(),
+                horiz: region.horiz,
+            })
+            .collect();
+        GetSelectionsResponse { rev, selections }
+    }
+
+    /// Returns the buffer's config in the form sent to plugins.
+    fn plugin_get_config(&self) -> GetConfigResponse {
+        let config = serde_json::to_value(self.config).unwrap();
+        GetConfigResponse {
+            generation: self.config_generation,
+            config: config.as_object().unwrap().to_owned(),
         }
     }
 
@@ -272,15 +461,71 @@ impl<'a> EventContext<'a> {
     /// This only updates internal state; it does not update the client.
     fn after_edit(&mut self, author: &str) {
         let _t = trace_block("EventContext::after_edit", &["core"]);
-
-        let edit_info = self.editor.borrow_mut().commit_delta();
+        // the payload of each stage's trace, only formatted when tracing
+        let view_payload =
+            if xi_trace::is_enabled() { self.view_id.to_string() } else { String::new() };
+        let mut timings = EditTimings::default();
+
+        let edit_info =
+            self.run_edit_stage(EditStage::CommitDelta, &view_payload, &mut timings, |ctx| {
+                ctx.editor.borrow_mut().commit_delta()
+            });
         let (delta, last_text, drift) = match edit_info {
             Some(edit_info) => edit_info,
             None => return,
         };
 
-        self.update_views(&self.editor.borrow(), &delta, &last_text, drift);
-        self.update_plugins(&mut self.editor.borrow_mut(), delta, author);
+        self.run_edit_stage(EditStage::UpdateViews, &view_payload, &mut timings, |ctx| {
+            ctx.update_views(&ctx.editor.borrow(), &delta, &last_text, drift)
+        });
+        self.run_edit_stage(EditStage::UpdatePlugins, &view_payload, &mut timings, |ctx| {
+            ctx.update_plugins(&mut ctx.editor.borrow_mut(), delta, author);
+        });
+        self.run_edit_stage(EditStage::ScheduleRender, &view_payload, &mut timings, |ctx| {
+            ctx.schedule_render()
+        });
+
+        let threshold = Duration::from_millis(self.config.slow_edit_warn_ms);
+        let slowest = self.editor.borrow_mut().edit_latency_mut().record(&timings, threshold);
+        if let Some(stage) = slowest {
+            warn!(
+                "handling an edit in {} took {:?}; the slowest stage was {} ({:?})",
+                self.view_id,
+                timings.total(),
+                stage.name(),
+                timings.get(stage)
+            );
+        }
+    }
+
+    /// Runs one stage of `after_edit`, tracing it and recording how long it took.
+    fn run_edit_stage<T>(
+        &mut self,
+        stage: EditStage,
+        view_payload: &str,
+        timings: &mut EditTimings,
+        f: impl FnOnce(&mut Self) -> T,
+    ) -> T {
+        let _t = trace_block_payload(stage.name(), &["core"], view_payload.to_owned());
+        let start = Instant::now();
+        let result = f(self);
+        #[cfg(test)]
+        {
+            if let Some((delayed, delay)) = self.editor.borrow().edit_stage_delay() {
+                if delayed == stage {
+                    std::thread::sleep(delay);
+                }
+            }
+        }
+        timings.set(stage, start.elapsed());
+        result
+    }
+
+    /// Schedules the autosave of the buffer and the render of the view, after an edit.
+    fn schedule_render(&self) {
+        if self.config.autosave_delay_ms > 0 && self.info.is_some() {
+            self.schedule_autosave();
+        }
 
         //if we have no plugins we always render immediately.
         if !self.plugins.is_empty() {
@@ -323,15 +568,17 @@ impl<'a> EventContext<'a> {
         let v: Value = serde_json::to_value(&ed.get_edit_type()).unwrap();
         let edit_type_str = v.as_str().unwrap().to_string();
 
+        let rev = ed.get_head_rev_token();
         let update = PluginUpdate::new(
             self.view_id,
-            ed.get_head_rev_token(),
+            rev,
             delta,
             new_len,
             nb_lines,
             Some(undo_group),
             edit_type_str,
             author.into(),
+            self.config_generation,
         );
 
         // we always increment and decrement regardless of whether we're
@@ -344,10 +591,10 @@ impl<'a> EventContext<'a> {
             let id = plugin.id;
             let view_id = self.view_id;
             plugin.update(&update, move |resp| {
-                weak_core.handle_plugin_update(id, view_id, resp);
+                weak_core.handle_plugin_update(id, view_id, rev, resp);
             });
         });
-        ed.dec_revs_in_flight();
+        ed.dec_revs_in_flight(rev);
         ed.update_edit_type();
     }
 
@@ -368,14 +615,17 @@ impl<'a> EventContext<'a> {
     fn render(&mut self) {
         let _t = trace_block("EventContext::render", &["core"]);
         let ed = self.editor.borrow();
-        //TODO: render other views
-        self.view.borrow_mut().render_if_dirty(
-            ed.get_buffer(),
-            self.client,
-            self.style_map,
-            ed.get_layers().get_merged(),
-            ed.is_pristine(),
-        )
+        iter::once(&self.view).chain(self.siblings.iter()).for_each(|view| {
+            view.borrow_mut().render_if_dirty(
+                ed.get_buffer(),
+                self.client,
+                self.style_map,
+                ed.get_layers().get_merged(),
+                ed.get_bookmarks(),
+                ed.get_head_rev_token(),
+                ed.is_pristine(),
+            )
+        })
     }
 }
 
@@ -388,29 +638,43 @@ impl<'a> EventContext<'a> {
     pub(crate) fn view_init(&mut self) {
         let wrap_width = self.config.wrap_width;
         let word_wrap = self.config.word_wrap;
+        let tab_width = self.config.tab_display_width();
+
+        self.with_view(|view, text| {
+            view.update_wrap_settings(text, wrap_width, word_wrap, tab_width)
+        });
+        self.update_bookmark_settings();
+    }
 
-        self.with_view(|view, text| view.update_wrap_settings(text, wrap_width, word_wrap));
+    /// Like `view_init`, for a view split from the sibling `source`. The new
+    /// view starts with a copy of the selection of `source`.
+    pub(crate) fn split_init(&mut self, source: ViewId) {
+        self.view_init();
+        let source = self.siblings.iter().find(|v| v.borrow().get_view_id() == source);
+        let selection = source.map(|view| {
+            let mut selection = Selection::new();
+            view.borrow().sel_regions().iter().for_each(|region| selection.add_region(*region));
+            selection
+        });
+        if let Some(selection) = selection {
+            self.with_view(|view, text| view.set_selection(text, selection));
+        }
     }
 
     pub(crate) fn finish_init(&mut self, config: &Table) {
-        if !self.plugins.is_empty() {
+        // plugins already know about the buffer of a split view
+        if !self.plugins.is_empty() && self.siblings.is_empty() {
             let info = self.plugin_info();
-
-            self.plugins.iter().for_each(|plugin| {
-                plugin.new_buffer(&info);
-                self.plugin_started(plugin);
-            });
+            self.plugins.iter().for_each(|plugin| plugin.new_buffer(&info));
         }
-
-        let available_plugins = self
-            .plugins
-            .iter()
-            .map(|plugin| ClientPluginInfo { name: plugin.name.clone(), running: true })
-            .collect::<Vec<_>>();
-        self.client.available_plugins(self.view_id, &available_plugins);
+        self.plugins.iter().for_each(|plugin| self.plugin_started(plugin));
 
         self.client.config_changed(self.view_id, config);
         self.client.language_changed(self.view_id, &self.language);
+        self.client.bom_changed(self.view_id, self.has_bom());
+        self.encoding_changed();
+
+        self.update_prefetch_settings();
 
         // Rewrap and request a render.
         // This is largely similar to update_wrap_settings(), the only difference
@@ -431,6 +695,18 @@ impl<'a> EventContext<'a> {
 
         self.editor.borrow_mut().set_pristine();
         self.with_view(|view, text| view.set_dirty(text));
+        // a lossily loaded file that was saved elsewhere no longer is
+        self.encoding_changed();
+        self.render()
+    }
+
+    /// Like `after_save`, for a save completed by the frontend, of the
+    /// revision `rev_id`.
+    pub(crate) fn after_external_save(&mut self, path: &Path, rev_id: RevId) {
+        self.plugins.iter().for_each(|plugin| plugin.did_save(self.view_id, path));
+
+        self.editor.borrow_mut().set_pristine_rev(rev_id);
+        self.with_view(|view, text| view.set_dirty(text));
         self.render()
     }
 
@@ -442,23 +718,32 @@ impl<'a> EventContext<'a> {
         self.siblings.is_empty()
     }
 
+    /// Updates state derived from the config. By the time this is called
+    /// `self.config` holds all of `changes`, so state that depends on
+    /// several keys is recomputed once, from their new values.
     pub(crate) fn config_changed(&mut self, changes: &Table) {
-        if changes.contains_key("wrap_width") || changes.contains_key("word_wrap") {
-            // FIXME: if switching from measurement-based widths to columnar widths,
-            // we need to reset the cache, since we're using different coordinate spaces
-            // for the same IDs. The long-term solution would be to include font
-            // information in the width cache, and then use real width even in the column
-            // case, getting the unit width for a typeface and multiplying that by
-            // a string's unicode width.
-            if changes.contains_key("word_wrap") {
-                debug!("clearing {} items from width cache", self.width_cache.borrow().len());
-                self.width_cache.replace(WidthCache::new());
+        for dependent in ConfigDependent::for_changes(changes) {
+            match dependent {
+                // FIXME: if switching from measurement-based widths to columnar widths,
+                // we need to reset the cache, since we're using different coordinate spaces
+                // for the same IDs. The long-term solution would be to include font
+                // information in the width cache, and then use real width even in the column
+                // case, getting the unit width for a typeface and multiplying that by
+                // a string's unicode width.
+                ConfigDependent::WidthCache => {
+                    debug!("clearing {} items from width cache", self.width_cache.borrow().len());
+                    self.width_cache.replace(WidthCache::new());
+                }
+                ConfigDependent::WrapSettings => self.update_wrap_settings(true),
+                ConfigDependent::BookmarkSettings => self.update_bookmark_settings(),
+                ConfigDependent::PrefetchSettings => self.update_prefetch_settings(),
             }
-            self.update_wrap_settings(true);
         }
 
         self.client.config_changed(self.view_id, changes);
-        self.plugins.iter().for_each(|plug| plug.config_changed(self.view_id, changes));
+        self.plugins
+            .iter()
+            .for_each(|plug| plug.config_changed(self.view_id, changes, self.config_generation));
         self.render()
     }
 
@@ -468,8 +753,46 @@ impl<'a> EventContext<'a> {
         self.plugins.iter().for_each(|plug| plug.language_changed(self.view_id, new_language_id));
     }
 
+    /// Makes plugins recompute their state from scratch, by telling them
+    /// the language changed to the one already in use.
+    fn force_rehighlight(&mut self) {
+        self.plugins.iter().for_each(|plug| plug.language_changed(self.view_id, &self.language));
+    }
+
+    /// Returns `true` if the buffer's file starts with a byte order mark.
+    fn has_bom(&self) -> bool {
+        self.info.map_or(false, FileInfo::has_bom)
+    }
+
+    /// Notifies the client, for every view of the buffer, that the byte
+    /// order mark was added or removed. The buffer is now modified, since
+    /// saving it changes the file.
+    pub(crate) fn bom_changed(&mut self) {
+        self.editor.borrow_mut().set_file_format_changed();
+        let (encoding, lossy) = self.encoding();
+        for view in iter::once(&self.view).chain(self.siblings.iter()) {
+            let view_id = view.borrow().get_view_id();
+            self.client.bom_changed(view_id, self.has_bom());
+            self.client.encoding_changed(view_id, encoding, lossy);
+        }
+        self.render()
+    }
+
+    /// Notifies the client of the encoding of the buffer's file.
+    fn encoding_changed(&self) {
+        let (encoding, lossy) = self.encoding();
+        self.client.encoding_changed(self.view_id, encoding, lossy);
+    }
+
+    /// The encoding of the buffer's file, and whether it was loaded lossily.
+    /// Buffers without a file are saved as UTF-8.
+    fn encoding(&self) -> (CharacterEncoding, bool) {
+        self.info.map_or((CharacterEncoding::Utf8, false), |info| (info.encoding, info.lossy))
+    }
+
     pub(crate) fn reload(&mut self, text: Rope) {
         self.with_editor(|ed, _, _, _| ed.reload(text));
+        self.encoding_changed();
         self.after_edit("core");
         self.render();
     }
@@ -493,6 +816,7 @@ impl<'a> EventContext<'a> {
             path,
             self.language.clone(),
             changes.as_object().unwrap().to_owned(),
+            self.config_generation,
         )
     }
 
@@ -502,7 +826,10 @@ impl<'a> EventContext<'a> {
 
     pub(crate) fn plugin_stopped(&mut self, plugin: &Plugin) {
         self.client.plugin_stopped(self.view_id, &plugin.name, 0);
-        let needs_render = self.with_editor(|ed, view, _, _| {
+        for view in &self.siblings {
+            self.client.plugin_stopped(view.borrow().get_view_id(), &plugin.name, 0);
+        }
+        let mut needs_render = self.with_editor(|ed, view, _, _| {
             if ed.get_layers_mut().remove_layer(plugin.id).is_some() {
                 view.set_dirty(ed.get_buffer());
                 true
@@ -510,18 +837,60 @@ impl<'a> EventContext<'a> {
                 false
             }
         });
+        for view in iter::once(&self.view).chain(self.siblings.iter()) {
+            needs_render |= view.borrow_mut().clear_annotations(plugin.id);
+        }
         if needs_render {
             self.render();
         }
     }
 
-    pub(crate) fn do_plugin_update(&mut self, update: Result<Value, RpcError>) {
+    pub(crate) fn do_plugin_update(&mut self, rev: RevToken, update: Result<Value, RpcError>) {
         match update.map(serde_json::from_value::<u64>) {
             Ok(Ok(_)) => (),
             Ok(Err(err)) => error!("plugin response json err: {:?}", err),
             Err(err) => error!("plugin shutdown, do something {:?}", err),
         }
-        self.editor.borrow_mut().dec_revs_in_flight();
+        self.editor.borrow_mut().dec_revs_in_flight(rev);
+    }
+
+    /// Applies a plugin's formatting of the buffer at `rev`, if it sent one,
+    /// before the buffer is saved. The formatting is its own undo group.
+    pub(crate) fn finish_format(
+        &mut self,
+        rev: RevToken,
+        response: Option<Result<Value, RpcError>>,
+    ) {
+        let delta = match response.map(|r| r.map(serde_json::from_value::<Option<RopeDelta>>)) {
+            Some(Ok(Ok(delta))) => delta,
+            Some(Ok(Err(err))) => {
+                error!("invalid formatting response: {:?}", err);
+                None
+            }
+            Some(Err(err)) => {
+                warn!("formatting failed, saving as is: {:?}", err);
+                None
+            }
+            None => None,
+        };
+        if let Some(delta) = delta {
+            let edit = PluginEdit {
+                rev,
+                delta,
+                priority: 0,
+                after_cursor: false,
+                undo_group: None,
+                author: "core".into(),
+                checked: true,
+            };
+            let result = self.with_editor(|ed, _, _, _| ed.apply_plugin_edit(edit));
+            if let Err(reason) = result {
+                warn!("formatting not applied, saving as is: {:?}", reason);
+            }
+            self.after_edit("core");
+            self.render_if_needed();
+        }
+        self.editor.borrow_mut().dec_revs_in_flight(rev);
     }
 
     /// Returns the text to be saved, appending a newline if necessary.
@@ -534,12 +903,9 @@ impl<'a> EventContext<'a> {
             return rope;
         }
 
-        let cursor = Cursor::new(&rope, rope.len());
-        let has_newline_at_eof = match cursor.get_leaf() {
-            Some((last_chunk, _)) => last_chunk.ends_with(&self.config.line_ending),
-            // The rope can't be empty, since we would have returned earlier if it was
-            None => unreachable!(),
-        };
+        // Any line ending will do; checking for `line_ending` itself would miss
+        // a "\r\n" split between leaves, and double a stray "\n".
+        let has_newline_at_eof = rope.byte_at(rope_len - 1) == b'\n';
 
         if !has_newline_at_eof {
             let line_ending = &self.config.line_ending;
@@ -555,7 +921,10 @@ impl<'a> EventContext<'a> {
     fn update_wrap_settings(&mut self, rewrap_immediately: bool) {
         let wrap_width = self.config.wrap_width;
         let word_wrap = self.config.word_wrap;
-        self.with_view(|view, text| view.update_wrap_settings(text, wrap_width, word_wrap));
+        let tab_width = self.config.tab_display_width();
+        self.with_view(|view, text| {
+            view.update_wrap_settings(text, wrap_width, word_wrap, tab_width)
+        });
         if rewrap_immediately {
             self.rewrap();
             self.with_view(|view, text| view.set_dirty(text));
@@ -581,14 +950,43 @@ impl<'a> EventContext<'a> {
 
         self.find();
         if self.view.borrow().find_in_progress() {
-            let ed = self.editor.borrow();
-            self.client.find_status(
-                self.view_id,
-                &json!(self.view.borrow().find_status(ed.get_buffer(), true)),
-            );
             self.schedule_find();
         }
-        self.render_if_needed();
+        self.send_find_status_throttled();
+    }
+
+    /// Sends a find status that was held back while incremental find is running.
+    pub(crate) fn do_find_status_timer(&mut self) {
+        self.view.borrow_mut().set_find_status_scheduled(false);
+        let pending = {
+            let view = self.view.borrow();
+            view.find_in_progress() && view.find_status_pending()
+        };
+        if pending {
+            self.send_find_status_throttled();
+        }
+    }
+
+    /// Sends the find status and the updated highlights. While incremental find
+    /// is running these are sent at most once per `FIND_STATUS_INTERVAL`; a
+    /// timer sends the latest ones once the interval has passed.
+    fn send_find_status_throttled(&mut self) {
+        let due = {
+            let view = self.view.borrow();
+            view.find_status_sent()
+                .map(|sent| sent + FIND_STATUS_INTERVAL)
+                .filter(|_| view.find_in_progress())
+        };
+        match due {
+            Some(due) if Instant::now() < due => self.schedule_find_status(due),
+            _ => {
+                {
+                    let ed = self.editor.borrow();
+                    self.view.borrow_mut().send_find_status(ed.get_buffer(), self.client);
+                }
+                self.render_if_needed();
+            }
+        }
     }
 
     fn schedule_find(&self) {
@@ -597,6 +995,16 @@ impl<'a> EventContext<'a> {
         self.client.schedule_idle(token);
     }
 
+    fn schedule_find_status(&self, timeout: Instant) {
+        let mut view = self.view.borrow_mut();
+        if !view.find_status_scheduled() {
+            let view_id: usize = self.view_id.into();
+            let token = FIND_STATUS_VIEW_IDLE_MASK | view_id;
+            self.client.schedule_timer(timeout, token);
+            view.set_find_status_scheduled(true);
+        }
+    }
+
     /// Tells the view to execute find on a batch of lines, if needed.
     fn find(&mut self) {
         let mut view = self.view.borrow_mut();
@@ -613,6 +1021,54 @@ impl<'a> EventContext<'a> {
         self.render_if_needed();
     }
 
+    /// Inserts the next chunk of a large paste, scheduling another batch if
+    /// more remain.
+    pub(crate) fn do_paste_batch(&mut self) {
+        let more = self.editor.borrow_mut().do_paste_chunk(&self.view.borrow());
+        self.after_edit("core");
+        if more {
+            self.schedule_paste();
+        }
+        self.render_if_needed();
+    }
+
+    /// Inserts the rest of a large paste at once, so that a new event sees
+    /// the whole paste.
+    fn finish_pending_paste(&mut self) {
+        if self.editor.borrow().has_pending_paste() {
+            self.editor.borrow_mut().finish_paste(&self.view.borrow());
+            self.after_edit("core");
+        }
+    }
+
+    fn schedule_paste(&self) {
+        let view_id: usize = self.view_id.into();
+        let token = PASTE_VIEW_IDLE_MASK | view_id;
+        self.client.schedule_idle(token);
+    }
+
+    /// Schedules an autosave of the buffer, postponing any that is pending.
+    fn schedule_autosave(&self) {
+        let deadline = Instant::now() + Duration::from_millis(self.config.autosave_delay_ms);
+        self.editor.borrow_mut().set_autosave_deadline(deadline);
+        let view_id: usize = self.view_id.into();
+        let token = AUTOSAVE_VIEW_IDLE_MASK | view_id;
+        self.client.schedule_timer(deadline, token);
+    }
+
+    /// Returns the path to save the buffer to, if its autosave is due and it
+    /// has unsaved changes.
+    pub(crate) fn due_autosave_path(&self) -> Option<PathBuf> {
+        if !self.editor.borrow_mut().take_due_autosave(Instant::now()) {
+            return None;
+        }
+        let info = self.info?;
+        if self.editor.borrow().is_pristine() || info.has_changed {
+            return None;
+        }
+        Some(info.path.clone())
+    }
+
     fn schedule_rewrap(&self) {
         let view_id: usize = self.view_id.into();
         let token = REWRAP_VIEW_IDLE_MASK | view_id;
@@ -627,8 +1083,10 @@ impl<'a> EventContext<'a> {
             self.client,
             self.style_map,
             ed.get_layers().get_merged(),
+            ed.get_bookmarks(),
             first,
             last,
+            ed.get_head_rev_token(),
             ed.is_pristine(),
         )
     }
@@ -665,6 +1123,25 @@ impl<'a> EventContext<'a> {
         line_ranges
     }
 
+    /// Selects every occurrence of this view's last selection, or of the
+    /// word at its caret, in each view of the buffer.
+    ///
+    /// Views don't share selections: each of them is given its own copy of
+    /// the occurrences, which it then changes independently, and which
+    /// follows edits made from any view as usual. Only this view scrolls.
+    fn do_select_all_occurrences_all_views(&mut self) {
+        let ed = self.editor.borrow();
+        let text = ed.get_buffer();
+        let occurrences = self.view.borrow().selection_occurrences(text);
+        if occurrences.is_empty() {
+            return;
+        }
+        for view in &self.siblings {
+            view.borrow_mut().set_selection_in_place(text, occurrences.clone());
+        }
+        self.view.borrow_mut().set_selection(text, occurrences);
+    }
+
     fn do_reindent(&mut self) {
         let line_ranges = self.selected_line_ranges();
         // this is handled by syntect only; this is definitely not the long-term solution.
@@ -691,13 +1168,143 @@ impl<'a> EventContext<'a> {
     fn do_show_hover(&mut self, request_id: usize, hover: Result<Hover, RemoteError>) {
         match hover {
             Ok(hover) => {
-                // TODO: Get Range from hover here and use it to highlight text
-                self.client.show_hover(self.view_id, request_id, hover.content)
+                // TODO: use the range to highlight text
+                let range = hover.range.map(|range| {
+                    let positions = self.offsets_to_positions(&[range.start, range.end]);
+                    (positions[0], positions[1])
+                });
+                self.client.show_hover(self.view_id, request_id, hover.content, range)
             }
             Err(err) => warn!("Hover Response from Client Error {:?}", err),
         }
     }
 
+    fn do_request_completions(&mut self, request_id: usize, position: Option<ClientPosition>) {
+        if let Some(position) = self.get_resolved_position(position) {
+            self.with_each_plugin(|p| p.get_completions(self.view_id, request_id, position))
+        }
+    }
+
+    fn do_show_completions(
+        &mut self,
+        request_id: usize,
+        list: Result<CompletionList, RemoteError>,
+    ) {
+        match list {
+            Ok(list) => self.client.show_completions(self.view_id, request_id, &list),
+            Err(err) => warn!("completion request {} failed: {:?}", request_id, err),
+        }
+    }
+
+    fn do_show_resolved_completion(
+        &mut self,
+        request_id: usize,
+        index: usize,
+        item: Result<CompletionItem, RemoteError>,
+    ) {
+        match item {
+            Ok(item) => {
+                self.client.show_resolved_completion(self.view_id, request_id, index, &item)
+            }
+            Err(err) => warn!("resolving completion {} of {} failed: {:?}", index, request_id, err),
+        }
+    }
+
+    /// Returns the line and column positions of `offsets` in this view.
+    pub(crate) fn offsets_to_positions(&self, offsets: &[usize]) -> Vec<LinePosition> {
+        let ed = self.editor.borrow();
+        let view = self.view.borrow();
+        offsets.iter().map(|&offset| view.offset_to_position(ed.get_buffer(), offset)).collect()
+    }
+
+    fn update_bookmark_settings(&mut self) {
+        let keep_deleted = self.config.keep_bookmarks_on_deleted_lines;
+        self.editor.borrow_mut().get_bookmarks_mut().set_keep_deleted(keep_deleted);
+    }
+
+    fn update_prefetch_settings(&mut self) {
+        let (min_lines, max_lines) =
+            (self.config.prefetch_min_lines, self.config.prefetch_max_lines);
+        self.view.borrow_mut().set_prefetch_bounds(min_lines, max_lines);
+    }
+
+    /// Moves the caret to the start of the next (or previous) bookmarked line,
+    /// wrapping around at the ends of the buffer.
+    fn goto_bookmark(&mut self, forward: bool) {
+        self.with_editor(|ed, view, _, _| {
+            let text = ed.get_buffer();
+            let caret = view.sel_regions().last().unwrap().end;
+            let bookmarks = ed.get_bookmarks();
+            let target =
+                if forward { bookmarks.next(text, caret) } else { bookmarks.previous(text, caret) };
+            if let Some(offset) = target {
+                view.set_selection(text, SelRegion::caret(offset));
+            }
+        })
+    }
+
+    /// Returns the bookmarked lines of this buffer, in view coordinates, along
+    /// with a snippet of the text of each line.
+    fn bookmarks_json(&self) -> Value {
+        let ed = self.editor.borrow();
+        let view = self.view.borrow();
+        let text = ed.get_buffer();
+        let bookmarks = ed
+            .get_bookmarks()
+            .offsets()
+            .iter()
+            .map(|&offset| {
+                json!({
+                    "line": view.line_of_offset(text, offset),
+                    "snippet": Bookmarks::snippet(text, offset),
+                })
+            })
+            .collect::<Vec<_>>();
+        json!(bookmarks)
+    }
+
+    /// Searches the whole buffer for `query`, without touching the view's
+    /// find state. Matches are returned as UTF-8 offsets, with the line of
+    /// their start in view coordinates; at most `find_all_max_results` are
+    /// returned.
+    fn find_all_json(
+        &self,
+        query: &str,
+        case_sensitive: bool,
+        regex: bool,
+        whole_words: bool,
+    ) -> Result<Value, RemoteError> {
+        let mut find = Find::new(0);
+        find.set_find(query, case_sensitive, regex, whole_words);
+        if regex && !query.is_empty() && !find.is_regex() {
+            let msg = format!("invalid regex: {}", query);
+            return Err(RemoteError::custom(400, msg, None));
+        }
+
+        let ed = self.editor.borrow();
+        let view = self.view.borrow();
+        let text = ed.get_buffer();
+        find.update_find(text, 0, text.len(), false);
+
+        let max_results = self.config.find_all_max_results;
+        let occurrences = find.occurrences();
+        let matches = occurrences
+            .iter()
+            .take(max_results)
+            .map(|region| {
+                json!({
+                    "start": region.min(),
+                    "end": region.max(),
+                    "line": view.line_of_offset(text, region.min()),
+                })
+            })
+            .collect::<Vec<_>>();
+        Ok(json!({
+            "matches": matches,
+            "truncated": occurrences.len() > max_results,
+        }))
+    }
+
     /// Gives the requested position in UTF-8 offset format to be sent to plugin
     /// If position is `None`, it tries to get the current Caret Position and use
     /// that instead
@@ -712,10 +1319,62 @@ impl<'a> EventContext<'a> {
 #[rustfmt::skip]
 mod tests {
     use super::*;
-    use crate::config::ConfigManager;
+    use crate::config::{ConfigDomain, ConfigManager};
     use crate::core::dummy_weak_core;
+    use crate::line_offset::LogicalLines;
+    use crate::rpc::{ClientCapability, SelectionModifier};
+    use crate::styles::N_RESERVED_STYLES;
     use crate::tabs::BufferId;
+    use std::sync::{Arc, Mutex};
     use xi_rpc::test_utils::DummyPeer;
+    use xi_rpc::{Callback, Peer, PeerMetrics, RpcPeer};
+
+    /// A peer that records the notifications and async requests sent to it, with the
+    /// time they were sent, and the timers scheduled on it.
+    #[derive(Debug, Clone, Default)]
+    struct CapturePeer(
+        Arc<Mutex<Vec<(Instant, String, Value)>>>,
+        Arc<Mutex<Vec<(Instant, usize)>>>,
+    );
+
+    impl CapturePeer {
+        fn clear(&self) {
+            self.0.lock().unwrap().clear();
+        }
+
+        fn find_statuses(&self) -> Vec<(Instant, Value)> {
+            self.0.lock().unwrap().iter()
+                .filter(|(_, method, _)| method == "find_status")
+                .map(|(time, _, params)| (*time, params["queries"][0].clone()))
+                .collect()
+        }
+    }
+
+    impl Peer for CapturePeer {
+        fn box_clone(&self) -> RpcPeer {
+            Box::new(self.clone())
+        }
+        fn send_rpc_notification(&self, method: &str, params: &Value) {
+            self.0.lock().unwrap().push((Instant::now(), method.to_owned(), params.clone()));
+        }
+        fn send_rpc_request_async(&self, method: &str, params: &Value, f: Box<dyn Callback>) {
+            self.0.lock().unwrap().push((Instant::now(), method.to_owned(), params.clone()));
+            f.call(Ok("capture peer".into()))
+        }
+        fn send_rpc_request(&self, _method: &str, _params: &Value) -> Result<Value, RpcError> {
+            Ok("capture peer".into())
+        }
+        fn request_is_pending(&self) -> bool {
+            false
+        }
+        fn metrics(&self) -> PeerMetrics {
+            PeerMetrics::default()
+        }
+        fn schedule_idle(&self, _token: usize) {}
+        fn schedule_timer(&self, time: Instant, token: usize) {
+            self.1.lock().unwrap().push((time, token));
+        }
+    }
 
     struct ContextHarness {
         view: RefCell<View>,
@@ -727,26 +1386,37 @@ mod tests {
         width_cache: RefCell<WidthCache>,
         config_manager: ConfigManager,
         recorder: RefCell<Recorder>,
+        file_info: Option<FileInfo>,
     }
 
     impl ContextHarness {
         fn new<S: AsRef<str>>(s: S) -> Self {
-            // we could make this take a config, which would let us test
-            // behaviour with different config settings?
+            ContextHarness::with_config(s, Table::new())
+        }
+
+        /// Creates a harness whose buffer uses `user_config` on top of the defaults.
+        fn with_config<S: AsRef<str>>(s: S, user_config: Table) -> Self {
+            ContextHarness::with_peer(s, user_config, Box::new(DummyPeer))
+        }
+
+        /// Creates a harness whose client sends its messages to `peer`.
+        fn with_peer<S: AsRef<str>>(s: S, user_config: Table, peer: RpcPeer) -> Self {
             let view_id = ViewId(1);
             let buffer_id = BufferId(2);
             let mut config_manager = ConfigManager::new(None, None);
+            config_manager.set_user_config(ConfigDomain::General, user_config).unwrap();
             let config = config_manager.add_buffer(buffer_id, None);
             let view = RefCell::new(View::new(view_id, buffer_id));
             let editor = RefCell::new(Editor::with_text(s));
-            let client = Client::new(Box::new(DummyPeer));
+            let client = Client::new(peer);
             let core_ref = dummy_weak_core();
             let kill_ring = RefCell::new(Rope::from(""));
             let style_map = RefCell::new(ThemeStyleMap::new(None));
             let width_cache = RefCell::new(WidthCache::new());
             let recorder = RefCell::new(Recorder::new());
             let harness = ContextHarness { view, editor, client, core_ref, kill_ring,
-                             style_map, width_cache, config_manager, recorder };
+                             style_map, width_cache, config_manager, recorder,
+                             file_info: None };
             harness.make_context().view_init();
             harness.make_context().finish_init(&config);
             harness
@@ -784,8 +1454,9 @@ mod tests {
                 view: &self.view,
                 editor: &self.editor,
                 config: &config.items,
+                config_generation: config.generation(),
                 language,
-                info: None,
+                info: self.file_info.as_ref(),
                 siblings: Vec::new(),
                 plugins: Vec::new(),
                 recorder: &self.recorder,
@@ -807,10 +1478,10 @@ mod tests {
         ctx.do_edit(EditNotification::Insert { chars: "world".into() });
         ctx.do_edit(EditNotification::Insert { chars: "!".into() });
         assert_eq!(harness.debug_render(),"hello world!|");
-        ctx.do_edit(EditNotification::MoveWordLeft);
+        ctx.do_edit(EditNotification::MoveWordLeft { count: 1 });
         ctx.do_edit(EditNotification::InsertNewline);
         assert_eq!(harness.debug_render(),"hello \n|world!");
-        ctx.do_edit(EditNotification::MoveWordRightAndModifySelection);
+        ctx.do_edit(EditNotification::MoveWordRightAndModifySelection { count: 1 });
         assert_eq!(harness.debug_render(), "hello \n[world|]!");
         ctx.do_edit(EditNotification::Insert { chars: "friends".into() });
         assert_eq!(harness.debug_render(), "hello \nfriends|!");
@@ -826,23 +1497,23 @@ mod tests {
         let harness = ContextHarness::new(initial_text);
         let mut ctx = harness.make_context();
 
-        ctx.do_edit(EditNotification::MoveDown);
-        ctx.do_edit(EditNotification::MoveDown);
-        ctx.do_edit(EditNotification::MoveToEndOfParagraph);
+        ctx.do_edit(EditNotification::MoveDown { count: 1 });
+        ctx.do_edit(EditNotification::MoveDown { count: 1 });
+        ctx.do_edit(EditNotification::MoveToEndOfParagraph { count: 1 });
         assert_eq!(harness.debug_render(),"\
         this is a string\n\
         that has three\n\
         lines.|" );
 
         ctx.do_edit(EditNotification::Gesture { line: 0, col: 0, ty: PointSelect });
-        ctx.do_edit(EditNotification::MoveToEndOfParagraphAndModifySelection);
+        ctx.do_edit(EditNotification::MoveToEndOfParagraphAndModifySelection { count: 1 });
         assert_eq!(harness.debug_render(),"\
         [this is a string|]\n\
         that has three\n\
         lines." );
 
-        ctx.do_edit(EditNotification::MoveToEndOfParagraph);
-        ctx.do_edit(EditNotification::MoveToBeginningOfParagraphAndModifySelection);
+        ctx.do_edit(EditNotification::MoveToEndOfParagraph { count: 1 });
+        ctx.do_edit(EditNotification::MoveToBeginningOfParagraphAndModifySelection { count: 1 });
         assert_eq!(harness.debug_render(),"\
         [|this is a string]\n\
         that has three\n\
@@ -896,7 +1567,7 @@ mod tests {
         |that has three\n\
         |lines." );
 
-        ctx.do_edit(EditNotification::MoveWordRight);
+        ctx.do_edit(EditNotification::MoveWordRight { count: 1 });
         assert_eq!(harness.debug_render(),"\
         this| is a string\n\
         that| has three\n\
@@ -934,19 +1605,99 @@ mod tests {
         that h|as three\n\
         lines.|" );
 
-        ctx.do_edit(EditNotification::MoveRight);
+        ctx.do_edit(EditNotification::MoveRight { count: 1 });
         assert_eq!(harness.debug_render(),"\
         this is a string\n\
         that ha|s three\n\
         lines.|" );
 
-        ctx.do_edit(EditNotification::MoveLeft);
+        ctx.do_edit(EditNotification::MoveLeft { count: 1 });
         assert_eq!(harness.debug_render(),"\
         this is a string\n\
         that h|as three\n\
         lines|." );
     }
 
+    #[test]
+    fn move_caret_column() {
+        use crate::rpc::GestureType::*;
+        let initial_text = "\
+        ab  12\n\
+        c\n\
+        éé  34\n\
+        \n\
+        fg  5678";
+        let harness = ContextHarness::new(initial_text);
+        let mut ctx = harness.make_context();
+        ctx.do_edit(EditNotification::Gesture { line: 0, col: 4, ty: PointSelect });
+
+        ctx.do_edit(EditNotification::MoveCaretColumnDown);
+        assert_eq!(harness.debug_render(),"\
+        ab  12\n\
+        c|\n\
+        éé  34\n\
+        \n\
+        fg  5678");
+
+        // the column is kept past short lines, and counts codepoints
+        ctx.do_edit(EditNotification::MoveCaretColumnDown);
+        assert_eq!(harness.debug_render(),"\
+        ab  12\n\
+        c\n\
+        éé  |34\n\
+        \n\
+        fg  5678");
+
+        ctx.do_edit(EditNotification::MoveCaretColumnDown);
+        ctx.do_edit(EditNotification::MoveCaretColumnDown);
+        assert_eq!(harness.debug_render(),"\
+        ab  12\n\
+        c\n\
+        éé  34\n\
+        \n\
+        fg  |5678");
+
+        // the last line is as far as it goes
+        ctx.do_edit(EditNotification::MoveCaretColumnDown);
+        assert_eq!(harness.debug_render(),"\
+        ab  12\n\
+        c\n\
+        éé  34\n\
+        \n\
+        fg  |5678");
+
+        ctx.do_edit(EditNotification::MoveCaretColumnUp);
+        ctx.do_edit(EditNotification::MoveCaretColumnUp);
+        assert_eq!(harness.debug_render(),"\
+        ab  12\n\
+        c\n\
+        éé  |34\n\
+        \n\
+        fg  5678");
+
+        // moving within the line picks up the new column
+        ctx.do_edit(EditNotification::MoveRight { count: 1 });
+        ctx.do_edit(EditNotification::MoveCaretColumnUp);
+        ctx.do_edit(EditNotification::MoveCaretColumnUp);
+        assert_eq!(harness.debug_render(),"\
+        ab  1|2\n\
+        c\n\
+        éé  34\n\
+        \n\
+        fg  5678");
+
+        // the column is kept in bytes, so vertical moves line up by bytes
+        ctx.do_edit(EditNotification::MoveCaretColumnDown);
+        ctx.do_edit(EditNotification::MoveCaretColumnDown);
+        ctx.do_edit(EditNotification::MoveUp { count: 2 });
+        assert_eq!(harness.debug_render(),"\
+        ab  12|\n\
+        c\n\
+        éé  34\n\
+        \n\
+        fg  5678");
+    }
+
     #[test]
     fn delete_combining_enclosing_keycaps_tests() {
         use crate::rpc::GestureType::*;
@@ -985,22 +1736,81 @@ mod tests {
     }
 
     #[test]
-    fn delete_variation_selector_tests() {
+    fn delete_empty_pair() {
         use crate::rpc::GestureType::*;
 
-        let initial_text = "\u{FE0F}";
-        let harness = ContextHarness::new(initial_text);
+        let harness = ContextHarness::new("f() + \"\" + [x]");
         let mut ctx = harness.make_context();
-        ctx.do_edit(EditNotification::Gesture { line: 0, col: 3, ty: PointSelect });
-
-        assert_eq!(harness.debug_render(), "\u{FE0F}|");
+        ctx.do_edit(EditNotification::Gesture { line: 0, col: 2, ty: PointSelect });
+        ctx.do_edit(EditNotification::DeleteBackward);
+        assert_eq!(harness.debug_render(), "f| + \"\" + [x]");
 
-        // Isolated variation selector
+        ctx.do_edit(EditNotification::Gesture { line: 0, col: 5, ty: PointSelect });
         ctx.do_edit(EditNotification::DeleteBackward);
-        assert_eq!(harness.debug_render(), "|");
+        assert_eq!(harness.debug_render(), "f + | + [x]");
 
-        ctx.do_edit(EditNotification::Insert { chars: "\u{E0100}".into() });
-        assert_eq!(harness.debug_render(), "\u{E0100}|");
+        // a pair that isn't empty loses one character
+        ctx.do_edit(EditNotification::Gesture { line: 0, col: 9, ty: PointSelect });
+        ctx.do_edit(EditNotification::DeleteBackward);
+        assert_eq!(harness.debug_render(), "f +  + [|]");
+        // and then deletes both
+        ctx.do_edit(EditNotification::DeleteBackward);
+        assert_eq!(harness.debug_render(), "f +  + |");
+    }
+
+    #[test]
+    fn delete_empty_pair_disabled() {
+        use crate::rpc::GestureType::*;
+
+        let mut config = Table::new();
+        config.insert("delete_empty_pairs".into(), json!(false));
+        let harness = ContextHarness::with_config("f()", config);
+        let mut ctx = harness.make_context();
+        ctx.do_edit(EditNotification::Gesture { line: 0, col: 2, ty: PointSelect });
+        ctx.do_edit(EditNotification::DeleteBackward);
+        assert_eq!(harness.debug_render(), "f|)");
+    }
+
+    #[test]
+    fn empty_pairs_do_not_surround() {
+        use crate::rpc::GestureType::*;
+
+        // parens aren't a surrounding pair, so typing one replaces the selection
+        let harness = ContextHarness::new("f x");
+        let mut ctx = harness.make_context();
+        ctx.do_edit(EditNotification::Gesture { line: 0, col: 2, ty: WordSelect });
+        ctx.do_edit(EditNotification::Insert { chars: "(".into() });
+        assert_eq!(harness.debug_render(), "f (|");
+
+        let mut config = Table::new();
+        config.insert("empty_pairs".into(), json!([["<", ">"]]));
+        let harness = ContextHarness::with_config("f() <>", config);
+        let mut ctx = harness.make_context();
+        ctx.do_edit(EditNotification::Gesture { line: 0, col: 2, ty: PointSelect });
+        ctx.do_edit(EditNotification::DeleteBackward);
+        assert_eq!(harness.debug_render(), "f|) <>");
+        ctx.do_edit(EditNotification::Gesture { line: 0, col: 4, ty: PointSelect });
+        ctx.do_edit(EditNotification::DeleteBackward);
+        assert_eq!(harness.debug_render(), "f) |");
+    }
+
+    #[test]
+    fn delete_variation_selector_tests() {
+        use crate::rpc::GestureType::*;
+
+        let initial_text = "\u{FE0F}";
+        let harness = ContextHarness::new(initial_text);
+        let mut ctx = harness.make_context();
+        ctx.do_edit(EditNotification::Gesture { line: 0, col: 3, ty: PointSelect });
+
+        assert_eq!(harness.debug_render(), "\u{FE0F}|");
+
+        // Isolated variation selector
+        ctx.do_edit(EditNotification::DeleteBackward);
+        assert_eq!(harness.debug_render(), "|");
+
+        ctx.do_edit(EditNotification::Insert { chars: "\u{E0100}".into() });
+        assert_eq!(harness.debug_render(), "\u{E0100}|");
         ctx.do_edit(EditNotification::DeleteBackward);
         assert_eq!(harness.debug_render(), "|");
 
@@ -1482,7 +2292,7 @@ mod tests {
         let mut ctx = harness.make_context();
         ctx.do_edit(EditNotification::Gesture { line: 0, col: 0, ty: PointSelect });
 
-        ctx.do_edit(EditNotification::MoveRight);
+        ctx.do_edit(EditNotification::MoveRight { count: 1 });
         assert_eq!(harness.debug_render(),"\
         t|his is a string\n\
         that has three\n\
@@ -1500,14 +2310,14 @@ mod tests {
         that has three\n\
         lines." );
 
-        ctx.do_edit(EditNotification::MoveWordRight);
-        ctx.do_edit(EditNotification::DeleteWordForward);
+        ctx.do_edit(EditNotification::MoveWordRight { count: 1 });
+        ctx.do_edit(EditNotification::DeleteWordForward { count: 1 });
         assert_eq!(harness.debug_render(),"\
         is| a string\n\
         that has three\n\
         lines." );
 
-        ctx.do_edit(EditNotification::DeleteWordBackward);
+        ctx.do_edit(EditNotification::DeleteWordBackward { count: 1 });
         assert_eq!(harness.debug_render(),"| \
         a string\n\
         that has three\n\
@@ -1551,7 +2361,7 @@ mod tests {
         ctx.do_edit(EditNotification::Insert { chars: "world".into() });
         assert_eq!(harness.debug_render(),"    hello\nworld|");
 
-        ctx.do_edit(EditNotification::MoveWordLeft);
+        ctx.do_edit(EditNotification::MoveWordLeft { count: 1 });
         ctx.do_edit(EditNotification::MoveToBeginningOfDocumentAndModifySelection);
         ctx.do_edit(EditNotification::Indent);
         assert_eq!(harness.debug_render(),"    [|    hello\n]world");
@@ -1567,6 +2377,75 @@ mod tests {
         assert_eq!(harness.debug_render(),"    |");
     }
 
+    #[test]
+    fn indent_size_differs_from_tab_display_width() {
+        use crate::rpc::GestureType::*;
+        let config = json!({"tab_size": 8, "indent_size": 2, "tab_display_width": 8});
+        let harness = ContextHarness::with_config("hello", config.as_object().unwrap().to_owned());
+        let mut ctx = harness.make_context();
+        assert_eq!(ctx.config.tab_display_width(), 8);
+        assert_eq!(ctx.config.indent_size(), 2);
+
+        ctx.do_edit(EditNotification::Indent);
+        assert_eq!(harness.debug_render(),"  |hello");
+        ctx.do_edit(EditNotification::Indent);
+        assert_eq!(harness.debug_render(),"    |hello");
+        ctx.do_edit(EditNotification::Outdent);
+        assert_eq!(harness.debug_render(),"  |hello");
+
+        ctx.do_edit(EditNotification::Gesture { line: 0, col: 3, ty: PointSelect });
+        ctx.do_edit(EditNotification::InsertTab);
+        assert_eq!(harness.debug_render(),"  h |ello");
+        ctx.do_edit(EditNotification::DeleteBackward);
+        assert_eq!(harness.debug_render(),"  h|ello");
+
+        ctx.do_edit(EditNotification::SelectAll);
+        ctx.do_edit(EditNotification::Insert { chars: "\tx".into() });
+        let text = harness.editor.borrow().get_buffer().clone();
+        let display_col = |offset| {
+            LogicalLines.offset_to_line_display_col(&text, offset, ctx.config.tab_display_width())
+        };
+        assert_eq!(display_col(1), (0, 8));
+        assert_eq!(display_col(2), (0, 9));
+    }
+
+    #[test]
+    fn indent_size_defaults_to_tab_size() {
+        let config = json!({"tab_size": 3});
+        let harness = ContextHarness::with_config("hello", config.as_object().unwrap().to_owned());
+        let mut ctx = harness.make_context();
+        assert_eq!(ctx.config.tab_display_width(), 3);
+        assert_eq!(ctx.config.indent_size(), 3);
+        ctx.do_edit(EditNotification::Indent);
+        assert_eq!(harness.debug_render(),"   |hello");
+    }
+
+    #[test]
+    fn tab_display_width_wraps_columns() {
+        let config = json!({"wrap_width": 10, "tab_display_width": 8});
+        let mut harness =
+            ContextHarness::with_config("\tab cd ef", config.as_object().unwrap().to_owned());
+        let text = harness.editor.borrow().get_buffer().clone();
+        let last_visual_line = |harness: &ContextHarness| {
+            harness.view.borrow().get_lines().visual_line_of_offset(&text, text.len())
+        };
+        assert_eq!(last_visual_line(&harness), 1);
+
+        // narrower tabs leave room for the first word
+        let changes = json!({"tab_display_width": 2}).as_object().unwrap().to_owned();
+        let table = harness.config_manager.table_for_update(ConfigDomain::General, changes);
+        let mut changes =
+            harness.config_manager.set_user_config(ConfigDomain::General, table).unwrap();
+        let (_, changes) = changes.pop().unwrap();
+        harness.make_context().config_changed(&changes);
+        assert_eq!(last_visual_line(&harness), 0);
+
+        // unset widths are left out of the config sent to plugins
+        let config = harness.make_context().plugin_get_config().config;
+        assert!(config.values().all(|value| !value.is_null()), "{:?}", config);
+        assert!(!config.contains_key("indent_size"));
+    }
+
     #[test]
     fn multiline_indentation_test() {
         use crate::rpc::GestureType::*;
@@ -1769,7 +2648,7 @@ mod tests {
         ctx.do_edit(EditNotification::DeleteToBeginningOfLine);
         ctx.do_edit(EditNotification::Insert { chars: "this is a 1000 text example".into() });
         ctx.do_edit(EditNotification::Gesture { line: 0, col: 11, ty: PointSelect });
-        ctx.do_edit(EditNotification::MoveRightAndModifySelection);
+        ctx.do_edit(EditNotification::MoveRightAndModifySelection { count: 1 });
         ctx.do_edit(EditNotification::DecreaseNumber);
         assert_eq!(harness.debug_render(), "this is a 999| text example");
 
@@ -1814,6 +2693,69 @@ mod tests {
         Done.");
     }
 
+    #[test]
+    fn increment_column() {
+        use crate::rpc::GestureType::*;
+        let initial_text = "\
+        item 7: a\n\
+        item 3: b\n\
+        item 12: c\n";
+        let harness = ContextHarness::new(initial_text);
+        let mut ctx = harness.make_context();
+        ctx.do_edit(EditNotification::Gesture { line: 0, col: 5, ty: PointSelect });
+        ctx.do_edit(EditNotification::AddSelectionBelow);
+        ctx.do_edit(EditNotification::AddSelectionBelow);
+        ctx.do_edit(EditNotification::IncrementColumn { base: 1, step: 1 });
+        assert_eq!(harness.debug_render(), "\
+        item 1|: a\n\
+        item 2|: b\n\
+        item 3|: c\n");
+
+        ctx.do_edit(EditNotification::IncrementColumn { base: 10, step: -5 });
+        assert_eq!(harness.debug_render(), "\
+        item 10|: a\n\
+        item 5|: b\n\
+        item 0|: c\n");
+
+        // the renumbering is undone at once
+        ctx.do_edit(EditNotification::Undo);
+        let text: String = harness.editor.borrow().get_buffer().into();
+        assert_eq!(text, "item 1: a\nitem 2: b\nitem 3: c\n");
+    }
+
+    #[test]
+    fn increment_column_padding() {
+        use crate::rpc::GestureType::*;
+        let initial_text = "\
+        08 -02\n\
+        no number\n\
+        09 -1\n\
+        00 1\n";
+        let harness = ContextHarness::new(initial_text);
+        let mut ctx = harness.make_context();
+        ctx.do_edit(EditNotification::Gesture { line: 0, col: 0, ty: PointSelect });
+        ctx.do_edit(EditNotification::Gesture { line: 1, col: 0, ty: ToggleSel });
+        ctx.do_edit(EditNotification::Gesture { line: 2, col: 0, ty: ToggleSel });
+        ctx.do_edit(EditNotification::Gesture { line: 3, col: 0, ty: ToggleSel });
+        ctx.do_edit(EditNotification::IncrementColumn { base: 9, step: 1 });
+        // the line without a number is skipped, and leading zeros are kept
+        assert_eq!(harness.debug_render(), "\
+        09| -02\n\
+        |no number\n\
+        10| -1\n\
+        11| 1\n");
+
+        ctx.do_edit(EditNotification::Gesture { line: 0, col: 4, ty: PointSelect });
+        ctx.do_edit(EditNotification::Gesture { line: 2, col: 4, ty: ToggleSel });
+        ctx.do_edit(EditNotification::Gesture { line: 3, col: 3, ty: ToggleSel });
+        ctx.do_edit(EditNotification::IncrementColumn { base: -3, step: 3 });
+        assert_eq!(harness.debug_render(), "\
+        09 -03|\n\
+        no number\n\
+        10 0|\n\
+        11 3|\n");
+    }
+
     #[test]
     fn text_recording() {
         use crate::rpc::GestureType::*;
@@ -1866,7 +2808,7 @@ mod tests {
         // Swap last word of the current line and the line below
         ctx.do_edit(EditNotification::AddSelectionBelow);
         ctx.do_edit(EditNotification::MoveToRightEndOfLine);
-        ctx.do_edit(EditNotification::MoveWordLeftAndModifySelection);
+        ctx.do_edit(EditNotification::MoveWordLeftAndModifySelection { count: 1 });
         ctx.do_edit(EditNotification::Transpose);
         ctx.do_edit(EditNotification::CollapseSelections);
         ctx.do_edit(EditNotification::MoveToRightEndOfLine);
@@ -1914,6 +2856,51 @@ mod tests {
         lines to see." );
     }
 
+    #[test]
+    fn loaded_recording_playback() {
+        use crate::rpc::GestureType::*;
+        let initial_text = "\
+        this is a string\n\
+        that has about\n\
+        four really nice\n\
+        lines to see.";
+        let tmp = tempdir::TempDir::new("xi-test-loaded-recording").unwrap();
+        let path = tmp.path().join("swap.json");
+        let recording_name = "swap".to_owned();
+
+        let harness = ContextHarness::new(initial_text);
+        let mut ctx = harness.make_context();
+        ctx.do_edit(EditNotification::Gesture { line: 0, col: 5, ty: PointSelect });
+        ctx.do_edit(EditNotification::ToggleRecording { recording_name: Some(recording_name.clone()) });
+        ctx.do_edit(EditNotification::AddSelectionBelow);
+        ctx.do_edit(EditNotification::MoveToRightEndOfLine);
+        ctx.do_edit(EditNotification::MoveWordLeftAndModifySelection { count: 1 });
+        ctx.do_edit(EditNotification::Transpose);
+        ctx.do_edit(EditNotification::CollapseSelections);
+        ctx.do_edit(EditNotification::MoveToRightEndOfLine);
+        ctx.do_edit(EditNotification::ToggleRecording { recording_name: Some(recording_name.clone()) });
+        harness.recorder.borrow().save(&recording_name, &path).unwrap();
+
+        // a fresh session plays the loaded recording as a single undo group
+        let harness = ContextHarness::new(initial_text);
+        assert_eq!(harness.recorder.borrow_mut().load(&path).unwrap(), recording_name);
+        let mut ctx = harness.make_context();
+        ctx.do_edit(EditNotification::Gesture { line: 2, col: 5, ty: PointSelect });
+        ctx.do_edit(EditNotification::PlayRecording { recording_name });
+        assert_eq!(harness.debug_render(),"\
+        this is a string\n\
+        that has about\n\
+        four really see.|\n\
+        lines to nice" );
+
+        ctx.do_edit(EditNotification::Undo);
+        assert_eq!(harness.debug_render(),"\
+        this is a string\n\
+        that has about\n\
+        four really nice|\n\
+        lines to see." );
+    }
+
     #[test]
     fn test_exact_position() {
         use crate::rpc::GestureType::*;
@@ -1977,6 +2964,7 @@ mod tests {
             after_cursor: false,
             undo_group: None,
             author: "plugin_one".into(),
+            checked: false,
         };
 
         ctx.do_plugin_cmd(PluginPid(1), PluginNotification::Edit { edit: edit_one });
@@ -1986,6 +2974,141 @@ mod tests {
     }
 
 
+    #[test]
+    fn checked_plugin_edit_rejected_after_user_edit() {
+        use xi_rope::DeltaBuilder;
+        use crate::plugins::rpc::PluginEdit;
+        use crate::plugins::PluginPid;
+        use crate::rpc::GestureType::*;
+
+        let harness = ContextHarness::new("one\ntwo\n");
+        let plugin_peer = CapturePeer::default();
+        let plugin = Plugin::with_peer(PluginPid(1), "syntax", Box::new(plugin_peer.clone()));
+        let mut ctx = harness.make_context();
+        ctx.plugins = vec![&plugin];
+        let uppercase = |ctx: &EventContext, start: usize, end: usize, rev: u64| {
+            let text = String::from(ctx.editor.borrow().get_buffer());
+            let mut builder = DeltaBuilder::new(ctx.editor.borrow().get_buffer().len());
+            builder.replace(Interval::new(start, end), text[start..end].to_uppercase().into());
+            PluginEdit {
+                rev, delta: builder.build(), priority: 55, after_cursor: false,
+                undo_group: None, author: "plugin_one".into(), checked: true,
+            }
+        };
+        let rejections = || plugin_peer.0.lock().unwrap().iter()
+            .filter(|(_, method, _)| method == "edit_rejected")
+            .map(|(_, _, params)| params.clone())
+            .collect::<Vec<_>>();
+
+        // the plugin reads "two" at `rev`, and the user edits it before the
+        // plugin's edit arrives
+        let rev = ctx.editor.borrow().get_head_rev_token();
+        let edit = uppercase(&ctx, 4, 7, rev);
+        ctx.do_edit(EditNotification::Gesture { line: 1, col: 1, ty: PointSelect });
+        ctx.do_edit(EditNotification::Insert { chars: "w".into() });
+        let head_rev = ctx.editor.borrow().get_head_rev_token();
+        ctx.do_plugin_cmd(PluginPid(1), PluginNotification::Edit { edit });
+        assert_eq!(String::from(ctx.editor.borrow().get_buffer()), "one\ntwwo\n");
+        assert_eq!(rejections(), vec![json!({
+            "view_id": ViewId(1), "rev": rev, "head_rev": head_rev, "reason": "conflict",
+        })]);
+
+        // an edit elsewhere does not conflict, and the plugin's edit is
+        // transformed to the new revision
+        let rev = ctx.editor.borrow().get_head_rev_token();
+        let edit = uppercase(&ctx, 4, 8, rev);
+        ctx.do_edit(EditNotification::Gesture { line: 0, col: 0, ty: PointSelect });
+        ctx.do_edit(EditNotification::Insert { chars: "x".into() });
+        ctx.do_plugin_cmd(PluginPid(1), PluginNotification::Edit { edit });
+        assert_eq!(String::from(ctx.editor.borrow().get_buffer()), "xone\nTWWO\n");
+        assert_eq!(rejections().len(), 1);
+    }
+
+    #[test]
+    fn completions_routed_through_plugins() {
+        use crate::plugins::PluginPid;
+
+        let peer = CapturePeer::default();
+        let harness = ContextHarness::with_peer(
+            "let foo = f\n", Table::new(), Box::new(peer.clone()));
+        let plugin_peer = CapturePeer::default();
+        let plugin = Plugin::with_peer(PluginPid(1), "lsp", Box::new(plugin_peer.clone()));
+        let mut ctx = harness.make_context();
+        ctx.plugins = vec![&plugin];
+        let sent = |peer: &CapturePeer, method: &str| peer.0.lock().unwrap().iter()
+            .filter(|(_, m, _)| m == method)
+            .map(|(_, _, params)| params.clone())
+            .collect::<Vec<_>>();
+
+        let position = Some(ClientPosition { line: 0, column: 11 });
+        ctx.do_edit(EditNotification::RequestCompletions { request_id: 4, position });
+        assert_eq!(sent(&plugin_peer, "get_completions"), vec![json!({
+            "view_id": ViewId(1), "request_id": 4, "position": 11,
+        })]);
+        // without a position, the cursor is used
+        ctx.do_edit(EditNotification::RequestCompletions { request_id: 5, position: None });
+        assert_eq!(sent(&plugin_peer, "get_completions")[1]["position"], 0);
+
+        let item = CompletionItem {
+            label: "foo".into(), kind: Some("variable".into()), ..CompletionItem::default()
+        };
+        let result = Ok(CompletionList { is_incomplete: true, items: vec![item.clone()] });
+        ctx.do_plugin_cmd(PluginPid(1), PluginNotification::ShowCompletions {
+            request_id: 5, result,
+        });
+        assert_eq!(sent(&peer, "show_completions"), vec![json!({
+            "view_id": ViewId(1), "request_id": 5, "is_incomplete": true,
+            "items": [{
+                "label": "foo", "detail": null, "insert_text": null, "kind": "variable",
+                "documentation": null,
+            }],
+        })]);
+
+        ctx.do_edit(EditNotification::ResolveCompletion { request_id: 5, index: 0 });
+        assert_eq!(sent(&plugin_peer, "resolve_completion"), vec![json!({
+            "view_id": ViewId(1), "request_id": 5, "index": 0,
+        })]);
+        let result = Ok(CompletionItem { documentation: Some("A foo.".into()), ..item });
+        ctx.do_plugin_cmd(PluginPid(1), PluginNotification::ShowResolvedCompletion {
+            request_id: 5, index: 0, result,
+        });
+        let resolved = sent(&peer, "show_resolved_completion");
+        assert_eq!(resolved.len(), 1);
+        assert_eq!(resolved[0]["index"], 0);
+        assert_eq!(resolved[0]["item"]["documentation"], "A foo.");
+
+        // a failed request is not sent on to the client
+        let result = Err(RemoteError::custom(2, "language server error occured", None));
+        ctx.do_plugin_cmd(PluginPid(1), PluginNotification::ShowCompletions {
+            request_id: 6, result,
+        });
+        assert_eq!(sent(&peer, "show_completions").len(), 1);
+    }
+
+    #[test]
+    fn plugin_get_selections() {
+        use crate::plugins::PluginPid;
+        use crate::rpc::GestureType::*;
+
+        let harness = ContextHarness::new("one two\nthree");
+        let mut ctx = harness.make_context();
+        ctx.do_edit(EditNotification::Gesture { line: 0, col: 0, ty: PointSelect });
+        ctx.do_edit(EditNotification::Gesture { line: 1, col: 1, ty: MultiWordSelect });
+        assert_eq!(harness.debug_render(),"\
+        |one two\n\
+        [three|]" );
+
+        let rev = ctx.editor.borrow().get_head_rev_token();
+        let response = ctx.do_plugin_cmd_sync(PluginPid(1), PluginRequest::GetSelections {});
+        assert_eq!(response, json!({
+            "rev": rev,
+            "selections": [
+                { "start": 0, "end": 0, "is_caret": true, "horiz": null },
+                { "start": 8, "end": 13, "is_caret": false, "horiz": null },
+            ],
+        }));
+    }
+
     #[test]
     fn empty_transpose() {
         let harness = ContextHarness::new("");
@@ -2010,4 +3133,1524 @@ mod tests {
 
         assert_eq!(harness.debug_render(), "wor\nd|");
     }
+
+    #[test]
+    fn bookmarks_follow_edits() {
+        use crate::rpc::GestureType::*;
+        let harness = ContextHarness::new("one\ntwo\nthree\nfour\n");
+        let mut ctx = harness.make_context();
+
+        ctx.do_edit(EditNotification::ToggleBookmark { line: 1 });
+        ctx.do_edit(EditNotification::ToggleBookmark { line: 3 });
+        assert_eq!(ctx.do_edit_sync(EditRequest::GetBookmarks).unwrap(), json!([
+            {"line": 1, "snippet": "two"},
+            {"line": 3, "snippet": "four"},
+        ]));
+
+        // insert a line above both bookmarks
+        ctx.do_edit(EditNotification::Insert { chars: "zero\n".into() });
+        assert_eq!(ctx.do_edit_sync(EditRequest::GetBookmarks).unwrap(), json!([
+            {"line": 2, "snippet": "two"},
+            {"line": 4, "snippet": "four"},
+        ]));
+
+        // edit on the bookmarked line
+        ctx.do_edit(EditNotification::Gesture { line: 2, col: 0, ty: PointSelect });
+        ctx.do_edit(EditNotification::Insert { chars: "- ".into() });
+        assert_eq!(ctx.do_edit_sync(EditRequest::GetBookmarks).unwrap(), json!([
+            {"line": 2, "snippet": "- two"},
+            {"line": 4, "snippet": "four"},
+        ]));
+
+        // delete the bookmarked line; the bookmark moves to the next line
+        ctx.do_edit(EditNotification::MoveToLeftEndOfLine);
+        ctx.do_edit(EditNotification::MoveDownAndModifySelection { count: 1 });
+        ctx.do_edit(EditNotification::DeleteBackward);
+        assert_eq!(harness.debug_render(), "zero\none\n|three\nfour\n");
+        assert_eq!(ctx.do_edit_sync(EditRequest::GetBookmarks).unwrap(), json!([
+            {"line": 2, "snippet": "three"},
+            {"line": 3, "snippet": "four"},
+        ]));
+
+        // undo restores the line, but the bookmark stays where it moved
+        ctx.do_edit(EditNotification::Undo);
+        assert_eq!(ctx.do_edit_sync(EditRequest::GetBookmarks).unwrap(), json!([
+            {"line": 3, "snippet": "three"},
+            {"line": 4, "snippet": "four"},
+        ]));
+
+        ctx.do_edit(EditNotification::ClearBookmarks);
+        assert_eq!(ctx.do_edit_sync(EditRequest::GetBookmarks).unwrap(), json!([]));
+    }
+
+    #[test]
+    fn bookmarks_removed_with_deleted_line() {
+        let mut config = Table::new();
+        config.insert("keep_bookmarks_on_deleted_lines".into(), json!(false));
+        let harness = ContextHarness::with_config("one\ntwo\nthree\n", config);
+        let mut ctx = harness.make_context();
+
+        ctx.do_edit(EditNotification::ToggleBookmark { line: 0 });
+        ctx.do_edit(EditNotification::ToggleBookmark { line: 1 });
+        ctx.do_edit(EditNotification::MoveDown { count: 1 });
+        ctx.do_edit(EditNotification::MoveDownAndModifySelection { count: 1 });
+        ctx.do_edit(EditNotification::DeleteBackward);
+        assert_eq!(harness.debug_render(), "one\n|three\n");
+        assert_eq!(ctx.do_edit_sync(EditRequest::GetBookmarks).unwrap(), json!([
+            {"line": 0, "snippet": "one"},
+        ]));
+    }
+
+    #[test]
+    fn find_all_case_insensitive_unicode() {
+        let harness = ContextHarness::new("Ärger ärger ÄRGER\nstraße STRASSE");
+        let mut ctx = harness.make_context();
+        let find_all = |query: &str, case_sensitive| EditRequest::FindAll {
+            query: query.into(), case_sensitive, regex: false, whole_words: false,
+        };
+
+        assert_eq!(ctx.do_edit_sync(find_all("ärger", false)).unwrap(), json!({
+            "matches": [
+                {"start": 0, "end": 6, "line": 0},
+                {"start": 7, "end": 13, "line": 0},
+                {"start": 14, "end": 20, "line": 0},
+            ],
+            "truncated": false,
+        }));
+        assert_eq!(ctx.do_edit_sync(find_all("ärger", true)).unwrap(), json!({
+            "matches": [{"start": 7, "end": 13, "line": 0}],
+            "truncated": false,
+        }));
+        // full case folding matches ß against SS
+        assert_eq!(ctx.do_edit_sync(find_all("straße", false)).unwrap(), json!({
+            "matches": [
+                {"start": 21, "end": 28, "line": 1},
+                {"start": 29, "end": 36, "line": 1},
+            ],
+            "truncated": false,
+        }));
+        // the view's own find state is untouched
+        assert_eq!(harness.debug_render(), "|Ärger ärger ÄRGER\nstraße STRASSE");
+        assert!(harness.view.borrow().find_status(harness.editor.borrow().get_buffer(), true)
+            .is_empty());
+    }
+
+    #[test]
+    fn find_all_multiline_regex() {
+        // long enough to span many rope leaves
+        let harness = ContextHarness::new("abcdefg\n".repeat(500));
+        let mut ctx = harness.make_context();
+        let result = ctx.do_edit_sync(EditRequest::FindAll {
+            query: "fg\\nab".into(), case_sensitive: true, regex: true, whole_words: false,
+        }).unwrap();
+        let matches = result["matches"].as_array().unwrap();
+        assert_eq!(matches.len(), 499);
+        assert_eq!(matches[0], json!({"start": 5, "end": 10, "line": 0}));
+        assert_eq!(matches[498], json!({"start": 3989, "end": 3994, "line": 498}));
+        assert_eq!(result["truncated"], json!(false));
+
+        let result = ctx.do_edit_sync(EditRequest::FindAll {
+            query: "fg(".into(), case_sensitive: true, regex: true, whole_words: false,
+        });
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn find_all_max_results() {
+        let mut config = Table::new();
+        config.insert("find_all_max_results".into(), json!(2));
+        let harness = ContextHarness::with_config("an a\na and a", config);
+        let mut ctx = harness.make_context();
+
+        assert_eq!(ctx.do_edit_sync(EditRequest::FindAll {
+            query: "a".into(), case_sensitive: false, regex: false, whole_words: true,
+        }).unwrap(), json!({
+            "matches": [
+                {"start": 3, "end": 4, "line": 0},
+                {"start": 5, "end": 6, "line": 1},
+            ],
+            "truncated": true,
+        }));
+        assert_eq!(ctx.do_edit_sync(EditRequest::FindAll {
+            query: "and".into(), case_sensitive: false, regex: false, whole_words: true,
+        }).unwrap(), json!({
+            "matches": [{"start": 7, "end": 10, "line": 1}],
+            "truncated": false,
+        }));
+    }
+
+    #[test]
+    fn bookmark_navigation_wraps() {
+        let harness = ContextHarness::new("a\nb\nc\nd\ne");
+        let mut ctx = harness.make_context();
+
+        // with no bookmarks, navigation is a no-op
+        ctx.do_edit(EditNotification::NextBookmark);
+        assert_eq!(harness.debug_render(), "|a\nb\nc\nd\ne");
+
+        ctx.do_edit(EditNotification::ToggleBookmark { line: 1 });
+        ctx.do_edit(EditNotification::ToggleBookmark { line: 3 });
+
+        ctx.do_edit(EditNotification::NextBookmark);
+        assert_eq!(harness.debug_render(), "a\n|b\nc\nd\ne");
+        ctx.do_edit(EditNotification::NextBookmark);
+        assert_eq!(harness.debug_render(), "a\nb\nc\n|d\ne");
+        ctx.do_edit(EditNotification::NextBookmark);
+        assert_eq!(harness.debug_render(), "a\n|b\nc\nd\ne");
+        ctx.do_edit(EditNotification::PreviousBookmark);
+        assert_eq!(harness.debug_render(), "a\nb\nc\n|d\ne");
+        ctx.do_edit(EditNotification::MoveToEndOfDocument);
+        ctx.do_edit(EditNotification::PreviousBookmark);
+        assert_eq!(harness.debug_render(), "a\nb\nc\n|d\ne");
+        ctx.do_edit(EditNotification::PreviousBookmark);
+        assert_eq!(harness.debug_render(), "a\n|b\nc\nd\ne");
+
+        // toggling an existing bookmark removes it
+        ctx.do_edit(EditNotification::ToggleBookmark { line: 1 });
+        ctx.do_edit(EditNotification::NextBookmark);
+        assert_eq!(harness.debug_render(), "a\nb\nc\n|d\ne");
+        ctx.do_edit(EditNotification::NextBookmark);
+        assert_eq!(harness.debug_render(), "a\nb\nc\n|d\ne");
+    }
+
+    /// Returns the text of the lines inserted by the last update, and whether
+    /// each was marked as folded.
+    fn last_inserted_lines(peer: &CapturePeer) -> Vec<(String, bool)> {
+        let sent = peer.0.lock().unwrap();
+        let (_, _, params) = sent.iter().rev().find(|(_, method, _)| method == "update").unwrap();
+        params["update"]["ops"].as_array().unwrap().iter()
+            .filter(|op| op["op"] == json!("ins"))
+            .flat_map(|op| op["lines"].as_array().unwrap().iter())
+            .map(|line| (line["text"].as_str().unwrap().to_owned(), line["folded"] == json!(true)))
+            .collect()
+    }
+
+    fn fold(first: i64, last: i64) -> EditNotification {
+        EditNotification::Fold { line_range: LineRange { first, last } }
+    }
+
+    #[test]
+    fn fold_lines() {
+        let peer = CapturePeer::default();
+        let harness = ContextHarness::with_peer(
+            "zero\none\ntwo\nthree\n", Table::new(), Box::new(peer.clone()));
+        let mut ctx = harness.make_context();
+        ctx.do_edit(fold(0, 2));
+        assert_eq!(last_inserted_lines(&peer), vec![
+            ("zero\n".to_owned(), true),
+            ("three\n".to_owned(), false),
+            ("".to_owned(), false),
+        ]);
+
+        // vertical movement skips the folded lines
+        ctx.do_edit(EditNotification::MoveDown { count: 1 });
+        assert_eq!(harness.debug_render(), "zero\none\ntwo\n|three\n");
+        ctx.do_edit(EditNotification::MoveUp { count: 1 });
+        assert_eq!(harness.debug_render(), "|zero\none\ntwo\nthree\n");
+        ctx.do_edit(EditNotification::MoveToRightEndOfLine);
+        assert_eq!(harness.debug_render(), "zero|\none\ntwo\nthree\n");
+        // as does horizontal movement
+        ctx.do_edit(EditNotification::MoveRight { count: 1 });
+        assert_eq!(harness.debug_render(), "zero\none\ntwo\n|three\n");
+        ctx.do_edit(EditNotification::MoveLeft { count: 1 });
+        assert_eq!(harness.debug_render(), "zero|\none\ntwo\nthree\n");
+
+        ctx.do_edit(EditNotification::Unfold { line_range: LineRange { first: 0, last: 0 } });
+        assert_eq!(last_inserted_lines(&peer).len(), 5);
+    }
+
+    #[test]
+    fn fold_last_line_and_merge() {
+        let peer = CapturePeer::default();
+        let harness =
+            ContextHarness::with_peer("a\nb\nc\nd", Table::new(), Box::new(peer.clone()));
+        let mut ctx = harness.make_context();
+        // the last line has no line ending; the end of the buffer is folded with it
+        ctx.do_edit(fold(2, 3));
+        assert_eq!(last_inserted_lines(&peer), vec![
+            ("a\n".to_owned(), false),
+            ("b\n".to_owned(), false),
+            ("c\n".to_owned(), true),
+        ]);
+        ctx.do_edit(EditNotification::MoveToEndOfDocument);
+        assert_eq!(harness.debug_render(), "a\nb\nc|\nd");
+        ctx.do_edit(EditNotification::MoveRight { count: 1 });
+        assert_eq!(harness.debug_render(), "a\nb\nc|\nd");
+
+        ctx.do_edit(fold(0, 1));
+        assert_eq!(last_inserted_lines(&peer), vec![
+            ("a\n".to_owned(), true),
+            ("c\n".to_owned(), true),
+        ]);
+        // folding across both folds merges them, moving the caret out of them
+        ctx.do_edit(fold(0, 1));
+        assert_eq!(last_inserted_lines(&peer), vec![("a\n".to_owned(), true)]);
+        assert_eq!(harness.debug_render(), "a|\nb\nc\nd");
+
+        ctx.do_edit(EditNotification::UnfoldAll);
+        assert_eq!(last_inserted_lines(&peer).len(), 4);
+    }
+
+    #[test]
+    fn folded_wrapped_lines() {
+        let peer = CapturePeer::default();
+        let mut config = Table::new();
+        config.insert("wrap_width".into(), json!(5));
+        let harness = ContextHarness::with_peer(
+            "top\naaaa bbbb cccc\nend", config, Box::new(peer.clone()));
+        let mut ctx = harness.make_context();
+        // the second line wraps to three visual lines, all of which are folded
+        ctx.do_edit(fold(0, 1));
+        assert_eq!(last_inserted_lines(&peer), vec![
+            ("top\n".to_owned(), true),
+            ("end".to_owned(), false),
+        ]);
+        ctx.do_edit(EditNotification::MoveDown { count: 1 });
+        assert_eq!(harness.debug_render(), "top\naaaa bbbb cccc\n|end");
+        // the line after a fold is not part of it
+        ctx.do_edit(EditNotification::Unfold { line_range: LineRange { first: 1, last: 1 } });
+        assert!(last_inserted_lines(&peer).is_empty());
+        ctx.do_edit(EditNotification::Unfold { line_range: LineRange { first: 0, last: 0 } });
+        assert_eq!(last_inserted_lines(&peer).len(), 5);
+    }
+
+    #[test]
+    fn edits_in_folds_unfold() {
+        let peer = CapturePeer::default();
+        let harness = ContextHarness::with_peer(
+            "zero\none\ntwo\nthree\n", Table::new(), Box::new(peer.clone()));
+        let mut ctx = harness.make_context();
+        ctx.do_edit(fold(0, 2));
+        ctx.do_edit(EditNotification::MoveDown { count: 1 });
+        // deleting the line ending of a folded line unfolds it
+        ctx.do_edit(EditNotification::DeleteBackward);
+        assert_eq!(harness.debug_render(), "zero\none\ntwo|three\n");
+        assert_eq!(last_inserted_lines(&peer).len(), 4);
+
+        // while editing the end of the anchor leaves the fold alone
+        ctx.do_edit(fold(0, 2));
+        assert_eq!(harness.debug_render(), "zero|\none\ntwothree\n");
+        ctx.do_edit(EditNotification::Insert { chars: "!".into() });
+        assert_eq!(last_inserted_lines(&peer), vec![
+            ("zero!\n".to_owned(), true),
+            ("".to_owned(), false),
+        ]);
+    }
+
+    #[test]
+    fn sort_selection_contents() {
+        use crate::rpc::GestureType::*;
+        let harness = ContextHarness::new("pear, fig, apple");
+        let mut ctx = harness.make_context();
+
+        ctx.do_edit(EditNotification::Gesture { line: 0, col: 0, ty: WordSelect });
+        ctx.do_edit(EditNotification::Gesture { line: 0, col: 6, ty: MultiWordSelect });
+        ctx.do_edit(EditNotification::Gesture { line: 0, col: 11, ty: MultiWordSelect });
+        assert_eq!(harness.debug_render(), "[pear|], [fig|], [apple|]");
+
+        ctx.do_edit(EditNotification::SortSelectionContents);
+        assert_eq!(harness.debug_render(), "[apple|], [fig|], [pear|]");
+
+        // sorting again is a no-op
+        ctx.do_edit(EditNotification::SortSelectionContents);
+        assert_eq!(harness.debug_render(), "[apple|], [fig|], [pear|]");
+
+        // the whole sort is a single undo group
+        ctx.do_edit(EditNotification::Undo);
+        let text: String = harness.editor.borrow().get_buffer().into();
+        assert_eq!(text, "pear, fig, apple");
+    }
+
+    #[test]
+    fn sort_selection_contents_ignores_carets() {
+        use crate::rpc::GestureType::*;
+        let harness = ContextHarness::new("c b\na");
+        let mut ctx = harness.make_context();
+
+        ctx.do_edit(EditNotification::Gesture { line: 0, col: 0, ty: WordSelect });
+        ctx.do_edit(EditNotification::Gesture { line: 0, col: 2, ty: ToggleSel });
+        ctx.do_edit(EditNotification::Gesture { line: 1, col: 0, ty: MultiWordSelect });
+        assert_eq!(harness.debug_render(), "[c|] |b\n[a|]");
+
+        ctx.do_edit(EditNotification::SortSelectionContents);
+        assert_eq!(harness.debug_render(), "[a|] |b\n[c|]");
+    }
+
+    #[test]
+    fn flatten_and_expand_selection() {
+        use crate::rpc::GestureType::*;
+        let harness = ContextHarness::new("one\ntwo\nthree\nfour");
+        let mut ctx = harness.make_context();
+        let text = || -> String { harness.editor.borrow().get_buffer().into() };
+
+        ctx.do_edit(EditNotification::MoveDownAndModifySelection { count: 3 });
+        assert_eq!(harness.debug_render(), "[one\ntwo\nthree\n|]four");
+        ctx.do_edit(EditNotification::FlattenSelection { separator: Some(", ".into()) });
+        // the line ending at the end of the selection is kept
+        assert_eq!(harness.debug_render(), "[one, two, three\n|]four");
+
+        ctx.do_edit(EditNotification::ExpandOnSeparator { sep: ", ".into() });
+        assert_eq!(harness.debug_render(), "[one\ntwo\nthree\n|]four");
+
+        // each is a single undo group
+        ctx.do_edit(EditNotification::Undo);
+        assert_eq!(text(), "one, two, three\nfour");
+        ctx.do_edit(EditNotification::Undo);
+        assert_eq!(text(), "one\ntwo\nthree\nfour");
+
+        // the separator defaults to a space, and carets are ignored
+        ctx.do_edit(EditNotification::Gesture { line: 0, col: 0, ty: PointSelect });
+        ctx.do_edit(EditNotification::MoveDownAndModifySelection { count: 2 });
+        ctx.do_edit(EditNotification::Gesture { line: 3, col: 0, ty: ToggleSel });
+        assert_eq!(harness.debug_render(), "[one\ntwo\n|]three\n|four");
+        ctx.do_edit(EditNotification::FlattenSelection { separator: None });
+        assert_eq!(harness.debug_render(), "[one two\n|]three\n|four");
+    }
+
+    #[test]
+    fn normalize_unicode() {
+        use crate::rpc::GestureType::*;
+        use crate::rpc::NormalizationForm::*;
+        let harness = ContextHarness::new("caf\u{e9} x\ncafe\u{301} y");
+        let mut ctx = harness.make_context();
+        let text = || -> String { harness.editor.borrow().get_buffer().into() };
+
+        // with only carets, the whole buffer is normalized
+        ctx.do_edit(EditNotification::NormalizeUnicode { form: Nfc });
+        assert_eq!(text(), "caf\u{e9} x\ncaf\u{e9} y");
+        ctx.do_edit(EditNotification::Undo);
+        assert_eq!(text(), "caf\u{e9} x\ncafe\u{301} y");
+
+        // a selection keeps covering its text as it changes length
+        ctx.do_edit(EditNotification::Gesture { line: 0, col: 0, ty: PointSelect });
+        ctx.do_edit(EditNotification::MoveToRightEndOfLineAndModifySelection);
+        ctx.do_edit(EditNotification::NormalizeUnicode { form: Nfd });
+        assert_eq!(harness.debug_render(), "[cafe\u{301} x|]\ncafe\u{301} y");
+        ctx.do_edit(EditNotification::NormalizeUnicode { form: Nfd });
+        assert_eq!(harness.debug_render(), "[cafe\u{301} x|]\ncafe\u{301} y");
+
+        // and converting back restores the original
+        ctx.do_edit(EditNotification::NormalizeUnicode { form: Nfc });
+        assert_eq!(harness.debug_render(), "[caf\u{e9} x|]\ncafe\u{301} y");
+        ctx.do_edit(EditNotification::Undo);
+        assert_eq!(text(), "cafe\u{301} x\ncafe\u{301} y");
+
+        // Hangul syllables are composed and decomposed algorithmically
+        let harness = ContextHarness::new("\u{d55c}\u{1100}\u{1161}");
+        let mut ctx = harness.make_context();
+        ctx.do_edit(EditNotification::NormalizeUnicode { form: Nfd });
+        assert_eq!(harness.debug_render(), "|\u{1112}\u{1161}\u{11ab}\u{1100}\u{1161}");
+        ctx.do_edit(EditNotification::NormalizeUnicode { form: Nfc });
+        assert_eq!(harness.debug_render(), "|\u{d55c}\u{ac00}");
+    }
+
+    #[test]
+    fn set_line_ending() {
+        use crate::line_ending::LineEnding;
+        use crate::rpc::GestureType::*;
+        let harness = ContextHarness::new("one\ntwo\nthree");
+        let mut ctx = harness.make_context();
+        let text = || -> String { harness.editor.borrow().get_buffer().into() };
+
+        ctx.do_edit(EditNotification::Gesture { line: 1, col: 1, ty: PointSelect });
+        ctx.do_edit(EditNotification::Gesture { line: 0, col: 3, ty: ToggleSel });
+        ctx.do_edit(EditNotification::SetLineEnding { ending: LineEnding::CrLf });
+        assert_eq!(harness.debug_render(), "one|\r\nt|wo\r\nthree");
+        ctx.do_edit(EditNotification::SetLineEnding { ending: LineEnding::CrLf });
+        assert_eq!(text(), "one\r\ntwo\r\nthree");
+        ctx.do_edit(EditNotification::SetLineEnding { ending: LineEnding::Lf });
+        assert_eq!(harness.debug_render(), "one|\nt|wo\nthree");
+
+        // each conversion is undone on its own
+        ctx.do_edit(EditNotification::Undo);
+        assert_eq!(text(), "one\r\ntwo\r\nthree");
+        ctx.do_edit(EditNotification::Undo);
+        assert_eq!(text(), "one\ntwo\nthree");
+        ctx.do_edit(EditNotification::Redo);
+        assert_eq!(text(), "one\r\ntwo\r\nthree");
+    }
+
+    #[test]
+    fn normalize_line_endings() {
+        let mut config = Table::new();
+        config.insert("line_ending".into(), json!("\r\n"));
+        let harness = ContextHarness::with_config("a\nb\r\nc\rd\n", config);
+        let mut ctx = harness.make_context();
+        let text = || -> String { harness.editor.borrow().get_buffer().into() };
+
+        // a lone CR is not a line ending
+        ctx.do_edit(EditNotification::NormalizeLineEndings);
+        assert_eq!(text(), "a\r\nb\r\nc\rd\r\n");
+        ctx.do_edit(EditNotification::Undo);
+        assert_eq!(text(), "a\nb\r\nc\rd\n");
+
+        // a CRLF split between leaves is one line ending
+        let (x, y) = ("x".repeat(700), "y".repeat(700));
+        let rope = Rope::concat(Rope::from(format!("{}\r", x)), Rope::from(format!("\n{}", y)));
+        assert!(rope.iter_chunks(..).next().unwrap().ends_with('\r'));
+        assert!(crate::edit_ops::convert_line_endings(&rope, "\r\n").is_identity());
+        let delta = crate::edit_ops::convert_line_endings(&rope, "\n");
+        assert_eq!(String::from(delta.apply(&rope)), format!("{}\n{}", x, y));
+    }
+
+    #[test]
+    fn save_with_newline_any_line_ending() {
+        let mut config = Table::new();
+        config.insert("line_ending".into(), json!("\r\n"));
+        config.insert("save_with_newline".into(), json!(true));
+        let harness = ContextHarness::with_config("a\r\nb", config.clone());
+        assert_eq!(String::from(harness.make_context().text_for_save()), "a\r\nb\r\n");
+        // a file ending with a different line ending does not get another
+        let harness = ContextHarness::with_config("a\r\nb\n", config);
+        assert_eq!(String::from(harness.make_context().text_for_save()), "a\r\nb\n");
+    }
+
+    #[test]
+    fn repeated_word_delete() {
+        let text = "one two three four five six";
+        let harness = ContextHarness::new(text);
+        let mut ctx = harness.make_context();
+        ctx.do_edit(EditNotification::MoveToEndOfDocument);
+        ctx.do_edit(EditNotification::DeleteWordBackward { count: 5 });
+        assert_eq!(harness.debug_render(), "one |");
+
+        let sequential = ContextHarness::new(text);
+        let mut ctx2 = sequential.make_context();
+        ctx2.do_edit(EditNotification::MoveToEndOfDocument);
+        (0..5).for_each(|_| ctx2.do_edit(EditNotification::DeleteWordBackward { count: 1 }));
+        assert_eq!(sequential.debug_render(), harness.debug_render());
+
+        // the whole deletion is a single undo group
+        ctx.do_edit(EditNotification::Insert { chars: "1".into() });
+        ctx.do_edit(EditNotification::Undo);
+        ctx.do_edit(EditNotification::Undo);
+        let buffer: String = harness.editor.borrow().get_buffer().into();
+        assert_eq!(buffer, text);
+    }
+
+    #[test]
+    fn repeated_movement_clamps_at_edges() {
+        let harness = ContextHarness::new("one\ntwo\nthree");
+        let mut ctx = harness.make_context();
+        ctx.do_edit(EditNotification::MoveDown { count: 1 });
+        ctx.do_edit(EditNotification::MoveRight { count: 2 });
+        assert_eq!(harness.debug_render(), "one\ntw|o\nthree");
+
+        ctx.do_edit(EditNotification::MoveUp { count: 100 });
+        assert_eq!(harness.debug_render(), "|one\ntwo\nthree");
+        ctx.do_edit(EditNotification::MoveDown { count: 2 });
+        assert_eq!(harness.debug_render(), "one\ntwo\nth|ree");
+        ctx.do_edit(EditNotification::MoveDown { count: 100 });
+        assert_eq!(harness.debug_render(), "one\ntwo\nthree|");
+
+        // word movement stops at the end of each line
+        ctx.do_edit(EditNotification::MoveWordLeftAndModifySelection { count: 2 });
+        assert_eq!(harness.debug_render(), "one\ntwo[|\nthree]");
+        ctx.do_edit(EditNotification::MoveLeft { count: 100 });
+        assert_eq!(harness.debug_render(), "|one\ntwo\nthree");
+        ctx.do_edit(EditNotification::DeleteWordForward { count: 100 });
+        assert_eq!(harness.debug_render(), "|");
+    }
+
+    #[test]
+    fn paste_normalizes_line_endings() {
+        let harness = ContextHarness::new("");
+        let mut ctx = harness.make_context();
+        ctx.do_edit(EditNotification::Paste { chars: "one\r\ntwo\nthree\r\n".into() });
+        assert_eq!(harness.debug_render(), "one\ntwo\nthree\n|");
+
+        let mut config = Table::new();
+        config.insert("line_ending".into(), json!("\r\n"));
+        let harness = ContextHarness::with_config("", config);
+        let mut ctx = harness.make_context();
+        ctx.do_edit(EditNotification::Paste { chars: "one\ntwo\r\nthree".into() });
+        assert_eq!(harness.debug_render(), "one\r\ntwo\r\nthree|");
+    }
+
+    #[test]
+    fn paste_keeps_line_endings() {
+        let mut config = Table::new();
+        config.insert("normalize_pasted_line_endings".into(), json!(false));
+        let harness = ContextHarness::with_config("", config);
+        let mut ctx = harness.make_context();
+        ctx.do_edit(EditNotification::Paste { chars: "one\r\ntwo\n".into() });
+        assert_eq!(harness.debug_render(), "one\r\ntwo\n|");
+    }
+
+    #[test]
+    fn paste_with_reindent() {
+        use crate::rpc::GestureType::*;
+        let harness = ContextHarness::new("fn main() {\n    \n}");
+        let mut ctx = harness.make_context();
+        ctx.do_edit(EditNotification::Gesture { line: 1, col: 4, ty: PointSelect });
+        let block = "            if x {\n                y();\n\n            }\n";
+        ctx.do_edit(EditNotification::PasteWithReindent { chars: block.into() });
+        assert_eq!(
+            harness.debug_render(),
+            "fn main() {\n    if x {\n        y();\n\n    }\n|\n}"
+        );
+        // the whole paste is undone at once
+        ctx.do_edit(EditNotification::Undo);
+        assert_eq!(harness.debug_render(), "fn main() {\n    |\n}");
+
+        // lines less indented than the first are shifted too, as far as they go
+        let harness = ContextHarness::new("");
+        let mut ctx = harness.make_context();
+        ctx.do_edit(EditNotification::PasteWithReindent { chars: "    a\n  b\n      c".into() });
+        assert_eq!(harness.debug_render(), "a\nb\n  c|");
+    }
+
+    #[test]
+    fn paste_with_reindent_tabs() {
+        use crate::rpc::GestureType::*;
+        let mut config = Table::new();
+        config.insert("translate_tabs_to_spaces".into(), json!(false));
+        config.insert("tab_size".into(), json!(4));
+        let harness = ContextHarness::with_config("\t\tx = []", config);
+        let mut ctx = harness.make_context();
+        ctx.do_edit(EditNotification::Gesture { line: 0, col: 7, ty: PointSelect });
+        ctx.do_edit(EditNotification::PasteWithReindent { chars: "\n  1,\n  2,\n".into() });
+        assert_eq!(harness.debug_render(), "\t\tx = [\n\t\t1,\n\t\t2,\n|]");
+    }
+
+    #[test]
+    fn large_paste_is_chunked() {
+        let mut config = Table::new();
+        config.insert("paste_chunk_size".into(), json!(4));
+        let harness = ContextHarness::with_config("<>", config);
+        let mut ctx = harness.make_context();
+        ctx.do_edit(EditNotification::MoveRight { count: 1 });
+        ctx.do_edit(EditNotification::Paste { chars: "0123456789".into() });
+        assert_eq!(harness.debug_render(), "<0123|>");
+
+        let mut batches = 1;
+        while ctx.editor.borrow().has_pending_paste() {
+            ctx.do_paste_batch();
+            batches += 1;
+        }
+        assert_eq!(batches, 3);
+        assert_eq!(harness.debug_render(), "<0123456789|>");
+
+        // the whole paste is undone at once
+        ctx.do_edit(EditNotification::Undo);
+        assert_eq!(harness.debug_render(), "<|>");
+
+        // a new event first completes the pending paste
+        ctx.do_edit(EditNotification::Paste { chars: "abcdefghij".into() });
+        ctx.do_edit(EditNotification::Insert { chars: "!".into() });
+        assert!(!ctx.editor.borrow().has_pending_paste());
+        assert_eq!(harness.debug_render(), "<abcdefghij!|>");
+    }
+
+    fn find(chars: &str) -> EditNotification {
+        EditNotification::Find {
+            chars: chars.into(), case_sensitive: false, regex: false, whole_words: false,
+            find_in_block: false,
+        }
+    }
+
+    #[test]
+    fn find_status_throttled() {
+        let peer = CapturePeer::default();
+        // large enough for several incremental find batches
+        let text = "needle and hay\n".repeat(300_000);
+        let harness = ContextHarness::with_peer(&text, Table::new(), Box::new(peer.clone()));
+        let mut ctx = harness.make_context();
+        peer.clear();
+
+        let start = Instant::now();
+        ctx.do_edit(find("needle"));
+        while harness.view.borrow().find_in_progress() {
+            ctx.do_incremental_find();
+        }
+        let elapsed = start.elapsed();
+
+        let statuses = peer.find_statuses();
+        let max_statuses = 2 + (elapsed.as_millis() / FIND_STATUS_INTERVAL.as_millis()) as usize;
+        assert!(statuses.len() <= max_statuses, "{} statuses in {:?}", statuses.len(), elapsed);
+
+        let (last, in_progress) = statuses.split_last().unwrap();
+        assert_eq!(last.1["incomplete"], json!(false));
+        assert_eq!(last.1["matches"], json!(300_000));
+        assert_eq!(last.1["chars"], json!("needle"));
+
+        assert!(!in_progress.is_empty());
+        assert_eq!(in_progress[0].1["chars"], json!("needle"));
+        for (i, (time, status)) in in_progress.iter().enumerate() {
+            assert_eq!(status["incomplete"], json!(true));
+            if i > 0 {
+                assert!(*time - in_progress[i - 1].0 >= FIND_STATUS_INTERVAL);
+            }
+        }
+    }
+
+    #[test]
+    fn find_status_new_query_not_throttled() {
+        let peer = CapturePeer::default();
+        let text = "needle and hay\n".repeat(300_000);
+        let harness = ContextHarness::with_peer(&text, Table::new(), Box::new(peer.clone()));
+        let mut ctx = harness.make_context();
+
+        ctx.do_edit(find("needle"));
+        ctx.do_incremental_find();
+        assert!(harness.view.borrow().find_in_progress());
+        peer.clear();
+
+        ctx.do_edit(find("hay"));
+        let statuses = peer.find_statuses();
+        assert_eq!(statuses.len(), 1);
+        assert_eq!(statuses[0].1["chars"], json!("hay"));
+        assert_eq!(statuses[0].1["incomplete"], json!(true));
+    }
+
+    #[test]
+    fn find_in_block() {
+        let harness = ContextHarness::new(
+            "fn a() {\n    foo\n}\nfn b() {\n    foo\n    foo\n}\nfoo\n");
+        let mut ctx = harness.make_context();
+        let find_in_block = |find_in_block| EditNotification::Find {
+            chars: "foo".into(), case_sensitive: false, regex: false, whole_words: false,
+            find_in_block,
+        };
+        let find_next = || EditNotification::FindNext {
+            wrap_around: true, allow_same: false, modify_selection: SelectionModifier::Set,
+        };
+
+        ctx.do_edit(EditNotification::GotoLine { line: 4 });
+        ctx.do_edit(find_in_block(true));
+        while harness.view.borrow().find_in_progress() {
+            ctx.do_incremental_find();
+        }
+        ctx.do_edit(EditNotification::FindAll);
+        assert_eq!(harness.debug_render(),
+            "fn a() {\n    foo\n}\nfn b() {\n    [foo|]\n    [foo|]\n}\nfoo\n");
+
+        // navigation wraps around within the block
+        ctx.do_edit(EditNotification::GotoLine { line: 5 });
+        ctx.do_edit(find_next());
+        ctx.do_edit(find_next());
+        assert_eq!(harness.debug_render(),
+            "fn a() {\n    foo\n}\nfn b() {\n    [foo|]\n    foo\n}\nfoo\n");
+
+        let replace_all = || EditNotification::ReplaceAll { chars: None, preserve_case: false };
+        ctx.do_edit(EditNotification::Replace { chars: "bar".into(), preserve_case: false });
+        ctx.do_edit(replace_all());
+        assert_eq!(harness.debug_render(),
+            "fn a() {\n    foo\n}\nfn b() {\n    bar|\n    bar\n}\nfoo\n");
+
+        // clearing the flag searches the whole buffer again
+        ctx.do_edit(find_in_block(false));
+        while harness.view.borrow().find_in_progress() {
+            ctx.do_incremental_find();
+        }
+        ctx.do_edit(replace_all());
+        assert_eq!(harness.debug_render(),
+            "fn a() {\n    bar\n}\nfn b() {\n    bar|\n    bar\n}\nbar\n");
+    }
+
+    #[test]
+    fn replace_all_single_undo() {
+        let initial = "ab".repeat(1000);
+        let harness = ContextHarness::new(&initial);
+        let mut ctx = harness.make_context();
+        ctx.do_edit(EditNotification::Find {
+            chars: "a".into(), case_sensitive: false, regex: false, whole_words: false,
+            find_in_block: false,
+        });
+        while harness.view.borrow().find_in_progress() {
+            ctx.do_incremental_find();
+        }
+        assert_eq!(harness.view.borrow().find_occurrences().len(), 1000);
+        ctx.do_edit(EditNotification::ReplaceAll {
+            chars: Some("bb".into()), preserve_case: false,
+        });
+        assert_eq!(String::from(harness.editor.borrow().get_buffer()), "bbb".repeat(1000));
+
+        ctx.do_edit(EditNotification::Undo);
+        assert_eq!(String::from(harness.editor.borrow().get_buffer()), initial);
+    }
+
+    #[test]
+    fn replace_all_edge_cases() {
+        let harness = ContextHarness::new("aaaa Foo FOO foo\n");
+        let mut ctx = harness.make_context();
+        let text = || String::from(harness.editor.borrow().get_buffer());
+        let find = |chars: &str, regex| EditNotification::Find {
+            chars: chars.into(), case_sensitive: false, regex, whole_words: false,
+            find_in_block: false,
+        };
+        let replace_all = |chars: &str, preserve_case| EditNotification::ReplaceAll {
+            chars: Some(chars.into()), preserve_case,
+        };
+
+        // matches are taken from the original text, so new ones aren't formed
+        ctx.do_edit(find("aa", false));
+        ctx.do_edit(replace_all("a", false));
+        assert_eq!(text(), "aa Foo FOO foo\n");
+
+        ctx.do_edit(find("foo", false));
+        ctx.do_edit(replace_all("bar", true));
+        assert_eq!(text(), "aa Bar BAR bar\n");
+
+        // an empty match doesn't stop the search from advancing
+        ctx.do_edit(find("x*", true));
+        ctx.do_edit(replace_all("-", false));
+        assert_eq!(text(), "-a-a- -B-a-r- -B-A-R- -b-a-r-\n");
+        ctx.do_edit(EditNotification::Undo);
+        assert_eq!(text(), "aa Bar BAR bar\n");
+    }
+
+    #[test]
+    fn reflow_list_item() {
+        use crate::rpc::GestureType::*;
+        let initial = "\
+            Intro\n\
+            - This is a long bullet item that should wrap nicely\n  across lines.\n\
+            - short\n";
+        let harness = ContextHarness::new(initial);
+        let mut ctx = harness.make_context();
+        let text = || String::from(harness.editor.borrow().get_buffer());
+
+        // the caret can be on any line of the item
+        ctx.do_edit(EditNotification::Gesture { line: 2, col: 4, ty: PointSelect });
+        ctx.do_edit(EditNotification::ReflowListItem { column: Some(20) });
+        assert_eq!(text(), "\
+            Intro\n\
+            - This is a long\n  bullet item that\n  should wrap nicely\n  across lines.\n\
+            - short\n");
+
+        // the whole reflow is a single undo group
+        ctx.do_edit(EditNotification::Undo);
+        assert_eq!(text(), initial);
+
+        // outside of a list item, nothing changes
+        ctx.do_edit(EditNotification::Gesture { line: 0, col: 0, ty: PointSelect });
+        ctx.do_edit(EditNotification::ReflowListItem { column: Some(20) });
+        assert_eq!(text(), initial);
+    }
+
+    #[test]
+    fn reflow_numbered_list_item() {
+        use crate::rpc::GestureType::*;
+        let harness = ContextHarness::new("1. top\n  12. alpha  beta gamma delta   \n\nafter\n");
+        let mut ctx = harness.make_context();
+        ctx.do_edit(EditNotification::Gesture { line: 1, col: 8, ty: PointSelect });
+        ctx.do_edit(EditNotification::ReflowListItem { column: Some(16) });
+        assert_eq!(String::from(harness.editor.borrow().get_buffer()),
+                   "1. top\n  12. alpha beta\n      gamma\n      delta\n\nafter\n");
+        // the caret stays on the word it was in
+        assert_eq!(harness.debug_render(),
+                   "1. top\n  12. al|pha beta\n      gamma\n      delta\n\nafter\n");
+    }
+
+    /// Returns the sources of the annotations in the last update, and whether
+    /// each was marked unchanged.
+    fn last_annotation_sources(peer: &CapturePeer) -> Vec<(String, bool)> {
+        let sent = peer.0.lock().unwrap();
+        let (_, _, params) = sent.iter().rev().find(|(_, method, _)| method == "update").unwrap();
+        params["update"]["annotations"].as_array().unwrap().iter()
+            .map(|a| (a["source"].as_str().unwrap().to_owned(), a["unchanged"] == json!(true)))
+            .collect()
+    }
+
+    #[test]
+    fn retained_annotations_only_sent_when_changed() {
+        let peer = CapturePeer::default();
+        let mut harness =
+            ContextHarness::with_peer("foo bar\nfoo baz\n", Table::new(), Box::new(peer.clone()));
+        harness.client.set_capabilities(vec![ClientCapability::RetainAnnotations]);
+        let mut ctx = harness.make_context();
+        ctx.do_edit(find("foo"));
+        while harness.view.borrow().find_in_progress() {
+            ctx.do_incremental_find();
+        }
+        let find_source = last_annotation_sources(&peer)[1].0.clone();
+        assert!(find_source.starts_with("find-"));
+
+        // a caret move only resends the selection
+        ctx.do_edit(EditNotification::MoveRight { count: 1 });
+        assert_eq!(last_annotation_sources(&peer), vec![
+            ("selection".to_owned(), false),
+            (find_source.clone(), true),
+            ("bookmarks".to_owned(), true),
+        ]);
+
+        // a change to one source resends only that source
+        ctx.do_edit(EditNotification::ToggleBookmark { line: 1 });
+        assert_eq!(last_annotation_sources(&peer), vec![
+            ("selection".to_owned(), false),
+            (find_source.clone(), true),
+            ("bookmarks".to_owned(), false),
+        ]);
+
+        // invalidating the view resends everything
+        ctx.after_save(Path::new("foo.txt"));
+        assert_eq!(last_annotation_sources(&peer), vec![
+            ("selection".to_owned(), false),
+            (find_source.clone(), false),
+            ("bookmarks".to_owned(), false),
+        ]);
+    }
+
+    #[test]
+    fn annotations_always_sent_without_capability() {
+        let peer = CapturePeer::default();
+        let harness =
+            ContextHarness::with_peer("foo bar\nfoo baz\n", Table::new(), Box::new(peer.clone()));
+        let mut ctx = harness.make_context();
+        ctx.do_edit(find("foo"));
+        ctx.do_edit(EditNotification::MoveRight { count: 1 });
+        let sent = peer.0.lock().unwrap();
+        let (_, _, params) = sent.iter().rev().find(|(_, method, _)| method == "update").unwrap();
+        let annotations = params["update"]["annotations"].as_array().unwrap();
+        assert_eq!(annotations.len(), 3);
+        assert!(annotations.iter().all(|a| a.get("source").is_none() && a["ranges"].is_array()));
+    }
+
+    /// Styles all of a three line document with a plugin's scope, and returns
+    /// the ops of the update that follows.
+    fn style_flush_ops(capabilities: Vec<ClientCapability>) -> Value {
+        use crate::plugins::rpc::ScopeSpan;
+        use crate::plugins::PluginPid;
+
+        let peer = CapturePeer::default();
+        let mut harness =
+            ContextHarness::with_peer("one\ntwo\nsix", Table::new(), Box::new(peer.clone()));
+        harness.client.set_capabilities(capabilities);
+        let mut ctx = harness.make_context();
+        let scopes = vec![vec!["comment".to_owned()]];
+        ctx.do_plugin_cmd(PluginPid(1), PluginNotification::AddScopes { scopes });
+        let rev = ctx.editor.borrow().get_head_rev_token();
+        let spans = vec![ScopeSpan { start: 0, end: 11, scope_id: 0 }];
+        let update = PluginNotification::UpdateSpans { start: 0, len: 11, spans, rev };
+        ctx.do_plugin_cmd(PluginPid(1), update);
+
+        let sent = peer.0.lock().unwrap();
+        let (_, _, params) = sent.iter().rev().find(|(_, method, _)| method == "update").unwrap();
+        params["update"]["ops"].clone()
+    }
+
+    #[test]
+    fn style_change_resends_lines() {
+        let style = N_RESERVED_STYLES;
+        assert_eq!(style_flush_ops(vec![]), json!([{
+            "op": "ins",
+            "n": 3,
+            "lines": [
+                {"text": "one\n", "styles": [0, 4, style], "cursor": [0], "ln": 1},
+                {"text": "two\n", "styles": [0, 4, style], "ln": 2},
+                {"text": "six", "styles": [0, 3, style], "ln": 3},
+            ],
+        }]));
+    }
+
+    #[test]
+    fn style_change_with_partial_line_updates() {
+        let style = N_RESERVED_STYLES;
+        let ops = style_flush_ops(vec![ClientCapability::PartialLineUpdates]);
+        assert_eq!(ops, json!([{
+            "op": "update",
+            "n": 3,
+            "lines": [
+                {"styles": [0, 4, style], "cursor": [0], "ln": 1},
+                {"styles": [0, 4, style], "ln": 2},
+                {"styles": [0, 3, style], "ln": 3},
+            ],
+        }]));
+    }
+
+    #[test]
+    fn plugin_highlight_kinds() {
+        use crate::annotations::{AnnotationType, HighlightKindId};
+        use crate::plugins::rpc::DataSpan;
+        use crate::plugins::PluginPid;
+        use crate::rpc::GestureType::*;
+
+        let peer = CapturePeer::default();
+        let mut harness =
+            ContextHarness::with_peer("let foo = foo;\n", Table::new(), Box::new(peer.clone()));
+        harness.client.set_capabilities(vec![ClientCapability::HighlightKinds]);
+        let mut ctx = harness.make_context();
+        let register = PluginRequest::RegisterHighlightKind {
+            name: "occurrences".into(),
+            default_style_hints: json!({"underline": true}),
+        };
+        let id = ctx.do_plugin_cmd_sync(PluginPid(1), register);
+        assert_eq!(id, json!(2));
+        let kind = serde_json::from_value(id).unwrap();
+
+        let submit = |ctx: &mut EventContext, plugin, annotation_type| {
+            let spans = vec![
+                DataSpan { start: 4, end: 7, data: json!(null) },
+                DataSpan { start: 10, end: 13, data: json!(null) },
+            ];
+            let rev = ctx.editor.borrow().get_head_rev_token();
+            let update = PluginNotification::UpdateAnnotations {
+                start: 0, len: 15, spans, annotation_type, rev,
+            };
+            ctx.do_plugin_cmd(plugin, update);
+        };
+        // only the plugin that registered a kind can submit ranges for it
+        submit(&mut ctx, PluginPid(2), AnnotationType::Highlight(kind));
+        submit(&mut ctx, PluginPid(1), AnnotationType::Highlight(HighlightKindId::FIND));
+        submit(&mut ctx, PluginPid(1), AnnotationType::Highlight(kind));
+
+        let highlights = || {
+            let sent = peer.0.lock().unwrap();
+            let (_, _, params) =
+                sent.iter().rev().find(|(_, method, _)| method == "update").unwrap();
+            params["update"]["annotations"].as_array().unwrap().iter()
+                .filter(|a| a["type"] == json!("highlight"))
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+        ctx.do_edit(EditNotification::Gesture { line: 0, col: 0, ty: PointSelect });
+        assert_eq!(highlights(), vec![json!({
+            "type": "highlight",
+            "ranges": [[0, 4, 0, 7], [0, 10, 0, 13]],
+            "payloads": [null, null],
+            "n": 2,
+            "kind": { "id": 2, "name": "occurrences", "style_hints": {"underline": true} },
+        })]);
+
+        // highlights move with the text around them
+        ctx.do_edit(EditNotification::Insert { chars: "ab".into() });
+        assert_eq!(highlights()[0]["ranges"], json!([[0, 6, 0, 9], [0, 12, 0, 15]]));
+
+        // selections are highlights too
+        let sent = peer.0.lock().unwrap();
+        let (_, _, params) = sent.iter().rev().find(|(_, method, _)| method == "update").unwrap();
+        let selection = &params["update"]["annotations"][0];
+        assert_eq!(selection["type"], json!("selection"));
+        assert_eq!(selection["kind"]["name"], json!("selection"));
+    }
+
+    #[test]
+    fn prefetch_hints_follow_scrolling() {
+        let peer = CapturePeer::default();
+        let text = (0..300).map(|i| format!("line {}\n", i)).collect::<String>();
+        let mut config = Table::new();
+        config.insert("prefetch_min_lines".into(), json!(10));
+        config.insert("prefetch_max_lines".into(), json!(100));
+        let mut harness = ContextHarness::with_peer(text, config, Box::new(peer.clone()));
+        let last_update = || peer.0.lock().unwrap().iter().rev()
+            .find(|(_, method, _)| method == "update")
+            .map(|(_, _, params)| params["update"].clone())
+            .unwrap();
+        // the line after the last one sent or kept in the client's cache
+        let rendered_end = |update: &Value| {
+            let mut line = 0;
+            let mut end = 0;
+            for op in update["ops"].as_array().unwrap() {
+                let n = op["n"].as_u64().unwrap() as usize;
+                match op["op"].as_str().unwrap() {
+                    "skip" => continue,
+                    "invalidate" => line += n,
+                    _ => { line += n; end = line; }
+                }
+            }
+            end
+        };
+
+        // clients without the capability get no hints
+        harness.make_context().do_edit(EditNotification::Scroll(LineRange { first: 0, last: 30 }));
+        assert!(last_update().get("prefetch_hint").is_none());
+
+        harness.client.set_capabilities(vec![ClientCapability::PrefetchHints]);
+        let mut ctx = harness.make_context();
+        ctx.do_edit(EditNotification::Scroll(LineRange { first: 10, last: 40 }));
+        ctx.do_edit(EditNotification::Scroll(LineRange { first: 20, last: 50 }));
+        let update = last_update();
+        assert_eq!(update["prefetch_hint"], json!({"lines_ahead": 100, "lines_behind": 10}));
+        // lines ahead of the viewport are sent without being requested
+        assert_eq!(rendered_end(&update), 150);
+    }
+
+    #[test]
+    fn edit_latency_recorded() {
+        let harness = ContextHarness::new("");
+        harness.editor.borrow_mut().edit_latency_mut().reset();
+        let mut ctx = harness.make_context();
+        ctx.do_edit(EditNotification::Insert { chars: "a".into() });
+        ctx.do_edit(EditNotification::Insert { chars: "b".into() });
+        ctx.do_edit(EditNotification::MoveLeft { count: 1 });
+        ctx.do_edit(EditNotification::DeleteBackward);
+
+        // only edits that changed the buffer are recorded
+        let report = harness.editor.borrow().edit_latency().report();
+        assert_eq!(report.edits, 3);
+        assert_eq!(report.slow_edits, 0);
+        assert!(report.stages.iter().all(|stage| stage.count == 3));
+        assert!(report.stages.iter().all(|stage| stage.p50_us <= stage.p95_us));
+        assert!(report.stages.iter().all(|stage| stage.p95_us <= stage.max_us));
+    }
+
+    #[test]
+    fn slow_edit_stage_reported() {
+        let harness = ContextHarness::new("");
+        let delay = Some((EditStage::UpdatePlugins, Duration::from_millis(20)));
+        harness.editor.borrow_mut().set_edit_stage_delay(delay);
+        let mut ctx = harness.make_context();
+        ctx.do_edit(EditNotification::Insert { chars: "a".into() });
+
+        let report = harness.editor.borrow().edit_latency().report();
+        assert_eq!(report.slow_edits, 1);
+        let slow_stages = report.stages.iter()
+            .filter(|stage| stage.slow_edits > 0)
+            .map(|stage| stage.stage)
+            .collect::<Vec<_>>();
+        assert_eq!(slow_stages, vec![EditStage::UpdatePlugins]);
+        assert!(report.stages[EditStage::UpdatePlugins as usize].max_us >= 20_000);
+
+        // a zero threshold disables the warning
+        let mut config = Table::new();
+        config.insert("slow_edit_warn_ms".into(), json!(0));
+        let harness = ContextHarness::with_config("", config);
+        harness.editor.borrow_mut().set_edit_stage_delay(delay);
+        harness.make_context().do_edit(EditNotification::Insert { chars: "a".into() });
+        assert_eq!(harness.editor.borrow().edit_latency().report().slow_edits, 0);
+    }
+
+    #[test]
+    fn edit_stages_traced() {
+        let harness = ContextHarness::new("");
+        xi_trace::enable_tracing();
+        harness.make_context().do_edit(EditNotification::Insert { chars: "a".into() });
+        let samples = xi_trace::samples_cloned_unsorted();
+        xi_trace::disable_tracing();
+
+        for stage in &EditStage::ALL {
+            let traced = samples.iter().any(|sample| {
+                sample.name == stage.name()
+                    && sample.args.as_ref().and_then(|args| args.payload.as_ref())
+                        .map_or(false, |payload| payload == "view-id-1")
+            });
+            assert!(traced, "no trace of {}", stage.name());
+        }
+    }
+
+    #[test]
+    fn render_journal_matches_updates() {
+        let peer = CapturePeer::default();
+        let text = (0..300).map(|i| format!("line {}\n", i)).collect::<String>();
+        let harness = ContextHarness::with_peer(text, Table::new(), Box::new(peer.clone()));
+        // start from an empty journal, whatever the build profile
+        harness.view.borrow_mut().set_render_journal(false);
+        harness.view.borrow_mut().set_render_journal(true);
+        peer.clear();
+
+        let mut ctx = harness.make_context();
+        ctx.do_edit(EditNotification::Insert { chars: "a".into() });
+        ctx.do_edit(EditNotification::Scroll(LineRange { first: 100, last: 150 }));
+        ctx.do_edit(EditNotification::InsertNewline);
+        ctx.do_edit(EditNotification::RequestLines(LineRange { first: 0, last: 20 }));
+        ctx.do_edit(EditNotification::Scroll(LineRange { first: 0, last: 50 }));
+
+        let sent = peer.0.lock().unwrap().iter()
+            .filter(|(_, method, _)| method == "update")
+            .map(|(_, _, params)| params["update"]["ops"].as_array().unwrap().iter()
+                .map(|op| (op["op"].clone(), op["n"].as_u64().unwrap() as usize))
+                .collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        let view = harness.view.borrow();
+        let recorded = view.render_journal().unwrap().records()
+            .map(|r| r.ops.iter().map(|op| (json!(op.op), op.n)).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        assert!(sent.len() >= 5);
+        assert_eq!(recorded, sent);
+        let last = view.render_journal().unwrap().records().last().unwrap();
+        assert_eq!(last.rev, harness.editor.borrow().get_head_rev_token());
+        assert_eq!(last.after.lines, 302);
+    }
+
+    #[test]
+    fn force_rehighlight() {
+        use crate::plugins::PluginPid;
+
+        let harness = ContextHarness::new("fn main() {}");
+        let plugin_peer = CapturePeer::default();
+        let plugin = Plugin::with_peer(PluginPid(1), "syntax", Box::new(plugin_peer.clone()));
+        let mut ctx = harness.make_context();
+        ctx.plugins = vec![&plugin];
+
+        ctx.do_edit(EditNotification::ForceRehighlight);
+        let sent = plugin_peer.0.lock().unwrap();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].1, "language_changed");
+        assert_eq!(sent[0].2["view_id"], json!(ViewId(1)));
+        assert_eq!(sent[0].2["new_lang"], json!(ctx.language));
+    }
+
+    #[test]
+    fn autosave_scheduled_after_edits() {
+        let peer = CapturePeer::default();
+        let mut config = Table::new();
+        config.insert("autosave_delay_ms".into(), json!(500));
+        let mut harness = ContextHarness::with_peer("hello", config, Box::new(peer.clone()));
+        let autosave_timers = || peer.1.lock().unwrap().iter()
+            .filter(|(_, token)| token & AUTOSAVE_VIEW_IDLE_MASK != 0)
+            .cloned()
+            .collect::<Vec<_>>();
+
+        // a buffer without a file is never autosaved
+        harness.make_context().do_edit(EditNotification::Insert { chars: "a".into() });
+        assert!(autosave_timers().is_empty());
+
+        harness.file_info = Some(FileInfo {
+            encoding: crate::file::CharacterEncoding::Utf8,
+            lossy: false,
+            path: "hello.txt".into(),
+            mod_time: None,
+            has_changed: false,
+            #[cfg(target_family = "unix")]
+            permissions: None,
+        });
+        let mut ctx = harness.make_context();
+        let before = Instant::now();
+        ctx.do_edit(EditNotification::Insert { chars: "b".into() });
+        ctx.do_edit(EditNotification::Insert { chars: "c".into() });
+        let timers = autosave_timers();
+        assert_eq!(timers.len(), 2);
+        assert_eq!(ViewId::from(timers[0].1 ^ AUTOSAVE_VIEW_IDLE_MASK), ViewId(1));
+        assert!(timers[0].0 >= before + Duration::from_millis(500));
+
+        // only the last timer saves: earlier ones find the deadline postponed
+        assert_eq!(ctx.due_autosave_path(), None);
+        assert!(harness.editor.borrow_mut().take_due_autosave(timers[1].0));
+        harness.editor.borrow_mut().set_autosave_deadline(before);
+        assert_eq!(ctx.due_autosave_path(), Some(PathBuf::from("hello.txt")));
+        assert_eq!(ctx.due_autosave_path(), None);
+
+        // nor is a buffer without changes
+        ctx.after_save(Path::new("hello.txt"));
+        harness.editor.borrow_mut().set_autosave_deadline(before);
+        assert_eq!(ctx.due_autosave_path(), None);
+    }
+
+    #[test]
+    fn config_dependents() {
+        let changes = json!({"wrap_width": 20, "font_size": 12, "word_wrap": false});
+        assert_eq!(
+            ConfigDependent::for_changes(changes.as_object().unwrap()),
+            vec![ConfigDependent::WidthCache, ConfigDependent::WrapSettings]
+        );
+        let changes = json!({"prefetch_max_lines": 20, "prefetch_min_lines": 10});
+        assert_eq!(
+            ConfigDependent::for_changes(changes.as_object().unwrap()),
+            vec![ConfigDependent::PrefetchSettings]
+        );
+        assert!(ConfigDependent::for_changes(&Table::new()).is_empty());
+    }
+
+    #[test]
+    fn batched_wrap_changes() {
+        // the buffer starts empty, so that wrapping to the view's width
+        // doesn't need the client to measure anything
+        let mut config = Table::new();
+        config.insert("word_wrap".into(), json!(true));
+        let mut harness = ContextHarness::with_config("", config);
+        assert_eq!(harness.make_context().config_generation, 0);
+
+        // wrapping at 5 columns needs both new values: with either one
+        // alone, lines would wrap to the view's width, or not at all.
+        let changes = json!({"word_wrap": false, "wrap_width": 5});
+        let table = harness
+            .config_manager
+            .table_for_update(ConfigDomain::General, changes.as_object().unwrap().to_owned());
+        let mut changes =
+            harness.config_manager.set_user_config(ConfigDomain::General, table).unwrap();
+        assert_eq!(changes.len(), 1);
+        let (_, changes) = changes.pop().unwrap();
+        let mut ctx = harness.make_context();
+        assert_eq!(ctx.config_generation, 1);
+        ctx.config_changed(&changes);
+        ctx.do_edit(EditNotification::Insert { chars: "aaaa bbbb cccc".into() });
+
+        let text = harness.editor.borrow().get_buffer().clone();
+        let view = harness.view.borrow();
+        assert!(view.get_lines().is_wrapping());
+        assert_eq!(view.get_lines().visual_line_of_offset(&text, text.len()), 2);
+    }
+
+    #[test]
+    fn set_bom() {
+        use crate::file::CharacterEncoding;
+
+        let peer = CapturePeer::default();
+        let mut harness = ContextHarness::with_peer("hello", Table::new(), Box::new(peer.clone()));
+        harness.file_info = Some(FileInfo {
+            encoding: CharacterEncoding::Utf8WithBom,
+            lossy: false,
+            path: "hello.txt".into(),
+            mod_time: None,
+            has_changed: false,
+            #[cfg(target_family = "unix")]
+            permissions: None,
+        });
+        let bom_reports = || peer.0.lock().unwrap().iter()
+            .filter(|(_, method, _)| method == "bom_changed")
+            .map(|(_, _, params)| params["present"].clone())
+            .collect::<Vec<_>>();
+
+        assert!(harness.editor.borrow().is_pristine());
+        assert!(!harness.file_info.as_mut().unwrap().set_bom(true).unwrap());
+
+        assert!(harness.file_info.as_mut().unwrap().set_bom(false).unwrap());
+        harness.make_context().bom_changed();
+        assert!(!harness.editor.borrow().is_pristine());
+        assert_eq!(bom_reports().last(), Some(&json!(false)));
+
+        // every view of the buffer is told
+        assert!(harness.file_info.as_mut().unwrap().set_bom(true).unwrap());
+        let sibling = RefCell::new(View::new(ViewId(3), BufferId(2)));
+        let mut ctx = harness.make_context();
+        ctx.siblings.push(&sibling);
+        ctx.bom_changed();
+        assert!(!harness.editor.borrow().is_pristine());
+        assert_eq!(bom_reports()[2..], [json!(true), json!(true)]);
+        let reported_views = peer.0.lock().unwrap().iter()
+            .filter(|(_, method, _)| method == "bom_changed")
+            .map(|(_, _, params)| params["view_id"].clone())
+            .collect::<Vec<_>>();
+        assert_eq!(reported_views[2..], [json!("view-id-1"), json!("view-id-3")]);
+
+        harness.make_context().after_save(Path::new("hello.txt"));
+        assert!(harness.editor.borrow().is_pristine());
+    }
+
+    #[test]
+    fn cjk_path_status_item() {
+        use crate::plugins::PluginPid;
+        use crate::truncate::str_width;
+
+        let peer = CapturePeer::default();
+        let harness = ContextHarness::with_peer("", Table::new(), Box::new(peer.clone()));
+        let mut ctx = harness.make_context();
+        let path = format!("/home/{}\u{62A5}\u{544A}.txt", "\u{6587}\u{6863}/".repeat(30));
+        let update = PluginNotification::UpdateStatusItem { key: "path".into(), value: path };
+        ctx.do_plugin_cmd(PluginPid(1), update);
+
+        let sent = peer.0.lock().unwrap();
+        let (_, _, params) =
+            sent.iter().find(|(_, method, _)| method == "update_status_item").unwrap();
+        // the path is cut between characters, not in the middle of one
+        let value = params["value"].as_str().unwrap();
+        let expected = format!("/home/{}\u{6587}\u{2026}", "\u{6587}\u{6863}/".repeat(14));
+        assert_eq!(value, expected);
+        assert_eq!(str_width(value), 79);
+    }
+
+    #[test]
+    fn custom_annotation_types() {
+        use crate::annotations::AnnotationType;
+        use crate::plugins::rpc::DataSpan;
+        use crate::plugins::PluginPid;
+        use crate::rpc::GestureType::*;
+
+        let peer = CapturePeer::default();
+        let harness = ContextHarness::with_peer("let x = y;\n", Table::new(), Box::new(peer.clone()));
+        let mut ctx = harness.make_context();
+        let diagnostic = json!({"severity": 1, "message": "unknown name `y`"});
+        let spans = vec![DataSpan { start: 8, end: 9, data: diagnostic.clone() }];
+        let rev = ctx.editor.borrow().get_head_rev_token();
+        let annotation_type = AnnotationType::Custom("lsp.diagnostics".into());
+        let update = PluginNotification::UpdateAnnotations { start: 0, len: 11, spans, annotation_type, rev };
+        ctx.do_plugin_cmd(PluginPid(1), update);
+
+        let diagnostics = || {
+            let sent = peer.0.lock().unwrap();
+            let (_, _, params) =
+                sent.iter().rev().find(|(_, method, _)| method == "update").unwrap();
+            params["update"]["annotations"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .filter(|a| a["type"] == json!("lsp.diagnostics"))
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+        ctx.do_edit(EditNotification::Gesture { line: 0, col: 0, ty: PointSelect });
+        assert_eq!(
+            diagnostics(),
+            vec![json!({
+                "type": "lsp.diagnostics",
+                "ranges": [[0, 8, 0, 9]],
+                "payloads": [diagnostic],
+                "n": 1,
+            })]
+        );
+
+        // a plugin's annotations go away when it stops
+        let plugin = Plugin::with_peer(PluginPid(1), "lsp", Box::new(CapturePeer::default()));
+        ctx.plugin_stopped(&plugin);
+        assert!(diagnostics().is_empty());
+    }
+
+    #[test]
+    fn composition_is_one_edit() {
+        use crate::plugins::PluginPid;
+
+        let harness = ContextHarness::new("hello ");
+        let plugin_peer = CapturePeer::default();
+        let plugin = Plugin::with_peer(PluginPid(1), "syntax", Box::new(plugin_peer.clone()));
+        let mut ctx = harness.make_context();
+        ctx.plugins = vec![&plugin];
+        let plugin_updates = || plugin_peer.0.lock().unwrap().iter()
+            .filter(|(_, method, _)| method == "update")
+            .map(|(_, _, params)| params.clone())
+            .collect::<Vec<_>>();
+
+        ctx.do_edit(EditNotification::MoveToEndOfDocument);
+        ctx.do_edit(EditNotification::CompositionStart);
+        let preedits = [("n", 1), ("\u{306B}", 3), ("\u{306B}h", 4), ("\u{306B}\u{307B}", 6)];
+        for &(text, caret_offset_within) in preedits.iter() {
+            let text = text.to_owned();
+            ctx.do_edit(EditNotification::CompositionUpdate { text, caret_offset_within });
+        }
+        // nothing is inserted until the composition is committed
+        assert_eq!(harness.debug_render(), "hello |");
+        assert!(plugin_updates().is_empty());
+
+        let text = "\u{65E5}\u{672C}".to_owned();
+        ctx.do_edit(EditNotification::CompositionCommit { text });
+        assert_eq!(harness.debug_render(), "hello \u{65E5}\u{672C}|");
+        let updates = plugin_updates();
+        assert_eq!(updates.len(), 1);
+        assert_eq!(updates[0]["edit_type"], json!("insert"));
+
+        ctx.do_edit(EditNotification::Undo);
+        assert_eq!(harness.debug_render(), "hello |");
+    }
+
+    #[test]
+    fn composition_cancel() {
+        use crate::plugins::PluginPid;
+
+        let harness = ContextHarness::new("hello");
+        let plugin_peer = CapturePeer::default();
+        let plugin = Plugin::with_peer(PluginPid(1), "syntax", Box::new(plugin_peer.clone()));
+        let mut ctx = harness.make_context();
+        ctx.plugins = vec![&plugin];
+        let rev = ctx.editor.borrow().get_head_rev_token();
+
+        ctx.do_edit(EditNotification::CompositionStart);
+        let text = "\u{304B}".to_owned();
+        ctx.do_edit(EditNotification::CompositionUpdate { text, caret_offset_within: 3 });
+        ctx.do_edit(EditNotification::CompositionCancel);
+        assert!(!harness.view.borrow().is_composing());
+        assert_eq!(harness.debug_render(), "|hello");
+        assert_eq!(ctx.editor.borrow().get_head_rev_token(), rev);
+        assert!(plugin_peer.0.lock().unwrap().is_empty());
+
+        // so there is nothing to undo
+        ctx.do_edit(EditNotification::Insert { chars: "a".into() });
+        ctx.do_edit(EditNotification::Undo);
+        ctx.do_edit(EditNotification::Undo);
+        assert_eq!(harness.debug_render(), "|hello");
+    }
+
+    #[test]
+    fn composition_follows_sibling_edits() {
+        use crate::rpc::GestureType::*;
+
+        let peer = CapturePeer::default();
+        let harness = ContextHarness::with_peer("hello world", Table::new(), Box::new(peer.clone()));
+        let composition = || {
+            let sent = peer.0.lock().unwrap();
+            let (_, _, params) = sent.iter().rev()
+                .filter(|(_, method, _)| method == "update")
+                .find(|(_, _, params)| params["view_id"] == json!(ViewId(1)))
+                .unwrap();
+            params["update"]["annotations"].as_array().unwrap().iter()
+                .find(|a| a["type"] == json!("composition"))
+                .cloned()
+        };
+        let mut ctx = harness.make_context();
+        ctx.do_edit(EditNotification::Gesture { line: 0, col: 6, ty: PointSelect });
+        ctx.do_edit(EditNotification::CompositionStart);
+        let text = "\u{304B}".to_owned();
+        ctx.do_edit(EditNotification::CompositionUpdate { text, caret_offset_within: 3 });
+        assert_eq!(
+            composition(),
+            Some(json!({
+                "type": "composition",
+                "ranges": [[0, 6, 0, 6]],
+                "payloads": [{"text": "\u{304B}", "caret": 3}],
+                "n": 1,
+            }))
+        );
+
+        // another view of the buffer inserts before the composition
+        let sibling = RefCell::new(View::new(ViewId(3), BufferId(2)));
+        let mut sibling_ctx = harness.make_context();
+        sibling_ctx.view_id = ViewId(3);
+        sibling_ctx.view = &sibling;
+        sibling_ctx.siblings = vec![&harness.view];
+        sibling_ctx.view_init();
+        sibling_ctx.finish_init(&Table::new());
+        sibling_ctx.do_edit(EditNotification::Insert { chars: "ab".into() });
+        assert_eq!(composition().unwrap()["ranges"], json!([[0, 8, 0, 8]]));
+
+        let text = "\u{304B}".to_owned();
+        ctx.do_edit(EditNotification::CompositionCommit { text });
+        assert_eq!(harness.debug_render(), "abhello \u{304B}|world");
+        assert_eq!(composition(), None);
+    }
+
+    #[test]
+    fn composition_interrupted() {
+        let harness = ContextHarness::new("ab");
+        let mut ctx = harness.make_context();
+        ctx.do_edit(EditNotification::CompositionStart);
+        let text = "\u{304B}".to_owned();
+        ctx.do_edit(EditNotification::CompositionUpdate { text, caret_offset_within: 3 });
+        // by default, a movement commits the composition first
+        ctx.do_edit(EditNotification::MoveRight { count: 1 });
+        assert!(!harness.view.borrow().is_composing());
+        assert_eq!(harness.debug_render(), "\u{304B}a|b");
+
+        let mut config = Table::new();
+        config.insert("commit_interrupted_composition".into(), json!(false));
+        let harness = ContextHarness::with_config("ab", config);
+        let mut ctx = harness.make_context();
+        ctx.do_edit(EditNotification::CompositionStart);
+        let text = "\u{304B}".to_owned();
+        ctx.do_edit(EditNotification::CompositionUpdate { text, caret_offset_within: 3 });
+        // otherwise edits and movements are ignored until it ends
+        ctx.do_edit(EditNotification::MoveRight { count: 1 });
+        ctx.do_edit(EditNotification::Insert { chars: "x".into() });
+        assert!(harness.view.borrow().is_composing());
+        assert_eq!(harness.debug_render(), "|ab");
+        ctx.do_edit(EditNotification::Scroll(LineRange { first: 0, last: 10 }));
+        assert!(harness.view.borrow().is_composing());
+        ctx.do_edit(EditNotification::CompositionCancel);
+        ctx.do_edit(EditNotification::MoveRight { count: 1 });
+        assert_eq!(harness.debug_render(), "a|b");
+    }
+
+    #[test]
+    fn select_all_occurrences_in_sibling_views() {
+        let harness = ContextHarness::new("foo Foo foo\nfoo");
+        let sibling = RefCell::new(View::new(ViewId(3), BufferId(2)));
+        let mut sibling_ctx = harness.make_context();
+        sibling_ctx.view_id = ViewId(3);
+        sibling_ctx.view = &sibling;
+        sibling_ctx.siblings = vec![&harness.view];
+        sibling_ctx.view_init();
+        sibling_ctx.finish_init(&Table::new());
+
+        // the word at the caret is matched exactly
+        let mut ctx = harness.make_context();
+        ctx.siblings = vec![&sibling];
+        ctx.do_edit(EditNotification::SelectAllOccurrencesAllViews);
+        assert_eq!(harness.debug_render(), "[foo|] Foo [foo|]\n[foo|]");
+        assert_eq!(sibling.borrow().sel_regions(), harness.view.borrow().sel_regions());
+
+        // each view then has its own selection
+        sibling_ctx.do_edit(EditNotification::Insert { chars: "x".into() });
+        assert_eq!(harness.debug_render(), "x| Foo x|\nx|");
+        ctx.do_edit(EditNotification::MoveToBeginningOfDocument);
+        assert_eq!(harness.debug_render(), "|x Foo x\nx");
+        assert_eq!(sibling.borrow().sel_regions().len(), 3);
+    }
 }
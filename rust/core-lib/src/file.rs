@@ -14,15 +14,22 @@
 
 //! Interactions with the file system.
 
+use std::borrow::Cow;
+use std::char;
+use std::collections::hash_map::RandomState;
 use std::collections::HashMap;
 use std::ffi::OsString;
 use std::fmt;
 use std::fs::{self, File};
+use std::hash::{BuildHasher, Hasher};
 use std::io::{self, Read, Write};
 use std::path::{Path, PathBuf};
+use std::process;
 use std::str;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::time::SystemTime;
 
+use xi_rope::engine::RevId;
 use xi_rope::Rope;
 use xi_rpc::RemoteError;
 
@@ -33,14 +40,27 @@ use crate::tabs::OPEN_FILE_EVENT_TOKEN;
 #[cfg(feature = "notify")]
 use crate::watcher::FileWatcher;
 #[cfg(target_family = "unix")]
-use std::{fs::Permissions, os::unix::fs::PermissionsExt};
+use std::{
+    fs::Permissions,
+    os::unix::fs::{DirBuilderExt, OpenOptionsExt, PermissionsExt},
+};
 
 const UTF8_BOM: &str = "\u{feff}";
+const UTF16LE_BOM: &[u8] = b"\xff\xfe";
+const UTF16BE_BOM: &[u8] = b"\xfe\xff";
+
+/// How many names `save_to_temp` tries for its directory before giving up.
+const MAX_TEMP_DIR_ATTEMPTS: usize = 16;
 
 /// Tracks all state related to open files.
 pub struct FileManager {
     open_files: HashMap<PathBuf, BufferId>,
     file_info: HashMap<BufferId, FileInfo>,
+    /// The destination of the last save of each buffer, if it failed. The
+    /// frontend may retry these with rights core does not have.
+    failed_saves: HashMap<BufferId, PathBuf>,
+    /// Saves handed off to the frontend, waiting for it to confirm them.
+    external_saves: HashMap<BufferId, ExternalSave>,
     /// A monitor of filesystem events, for things like reloading changed files.
     #[cfg(feature = "notify")]
     watcher: FileWatcher,
@@ -49,6 +69,9 @@ pub struct FileManager {
 #[derive(Debug)]
 pub struct FileInfo {
     pub encoding: CharacterEncoding,
+    /// The file had bytes that are invalid in its encoding, which were
+    /// replaced when it was loaded. Saving over it would lose them.
+    pub lossy: bool,
     pub path: PathBuf,
     pub mod_time: Option<SystemTime>,
     pub has_changed: bool,
@@ -56,27 +79,104 @@ pub struct FileInfo {
     pub permissions: Option<u32>,
 }
 
+impl FileInfo {
+    /// Returns `true` if the file starts with a byte order mark.
+    pub fn has_bom(&self) -> bool {
+        self.encoding.has_bom()
+    }
+
+    /// Sets whether a UTF-8 file is saved with a byte order mark. Returns
+    /// `true` if this changed. The byte order mark of files in other
+    /// encodings is fixed, so changing it is an error.
+    pub fn set_bom(&mut self, present: bool) -> Result<bool, FileError> {
+        match self.encoding {
+            CharacterEncoding::Utf8 | CharacterEncoding::Utf8WithBom => {
+                let changed = self.has_bom() != present;
+                self.encoding =
+                    if present { CharacterEncoding::Utf8WithBom } else { CharacterEncoding::Utf8 };
+                Ok(changed)
+            }
+            _ => Err(FileError::BomUnsupported(self.path.clone())),
+        }
+    }
+}
+
+/// A save handed off to the frontend: the text was written to `temp_path`,
+/// and the frontend moves it to `path`, using rights core does not have.
+#[derive(Debug)]
+pub struct ExternalSave {
+    pub path: PathBuf,
+    pub temp_path: PathBuf,
+    /// The revision of the buffer that was written.
+    pub rev_id: RevId,
+}
+
+#[derive(Debug)]
 pub enum FileError {
     Io(io::Error, PathBuf),
     UnknownEncoding(PathBuf),
     HasChanged(PathBuf),
+    /// Saving would lose text: either the buffer has characters its file's
+    /// encoding cannot represent, or the file was loaded lossily.
+    LossyEncoding(PathBuf),
+    /// The byte order mark of the file's encoding cannot be added or removed.
+    BomUnsupported(PathBuf),
 }
 
-#[derive(Debug, Clone, Copy)]
+/// The reason a file could not be saved, for frontends to offer a way to recover.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SaveErrorKind {
+    PermissionDenied,
+    ReadOnlyFilesystem,
+    /// The file's directory does not exist.
+    NotFound,
+    /// The file is locked by another program.
+    Locked,
+    /// The disk, or the user's quota, is full.
+    OutOfSpace,
+    /// The file has changed on disk since it was opened.
+    HasChanged,
+    /// The text cannot be saved in the file's encoding without loss.
+    LossyEncoding,
+    Other,
+}
+
+/// The encoding of a file on disk. Buffers are always UTF-8; files are
+/// decoded when they are loaded, and encoded again when they are saved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum CharacterEncoding {
     Utf8,
     Utf8WithBom,
+    /// Little-endian UTF-16, with a byte order mark.
+    Utf16Le,
+    /// Big-endian UTF-16, with a byte order mark.
+    Utf16Be,
+    /// ISO 8859-1, used for files that are not valid UTF-8.
+    Latin1,
 }
 
 impl FileManager {
     #[cfg(feature = "notify")]
     pub fn new(watcher: FileWatcher) -> Self {
-        FileManager { open_files: HashMap::new(), file_info: HashMap::new(), watcher }
+        FileManager {
+            open_files: HashMap::new(),
+            file_info: HashMap::new(),
+            failed_saves: HashMap::new(),
+            external_saves: HashMap::new(),
+            watcher,
+        }
     }
 
     #[cfg(not(feature = "notify"))]
     pub fn new() -> Self {
-        FileManager { open_files: HashMap::new(), file_info: HashMap::new() }
+        FileManager {
+            open_files: HashMap::new(),
+            file_info: HashMap::new(),
+            failed_saves: HashMap::new(),
+            external_saves: HashMap::new(),
+        }
     }
 
     #[cfg(feature = "notify")]
@@ -105,6 +205,13 @@ impl FileManager {
         false
     }
 
+    /// Returns `true` if `path` is the file of this buffer, and the file has
+    /// not changed on disk since it was last read or written by core.
+    pub fn is_current_file(&mut self, path: &Path, id: BufferId) -> bool {
+        let is_file = self.get_info(id).map_or(false, |info| info.path == path);
+        is_file && !self.failed_saves.contains_key(&id) && !self.check_file(path, id)
+    }
+
     pub fn open(&mut self, path: &Path, id: BufferId) -> Result<Rope, FileError> {
         if !path.exists() {
             return Ok(Rope::from(""));
@@ -126,22 +233,136 @@ impl FileManager {
             #[cfg(feature = "notify")]
             self.watcher.unwatch(&info.path, OPEN_FILE_EVENT_TOKEN);
         }
+        self.failed_saves.remove(&id);
+        self.discard_external_save(id);
     }
 
     pub fn save(&mut self, path: &Path, text: &Rope, id: BufferId) -> Result<(), FileError> {
         let is_existing = self.file_info.contains_key(&id);
-        if is_existing {
-            self.save_existing(path, text, id)
+        let result = self.check_lossless(path, text, id).and_then(|()| {
+            if is_existing {
+                self.save_existing(path, text, id)
+            } else {
+                self.save_new(path, text, id)
+            }
+        });
+        match result {
+            Ok(()) => {
+                self.failed_saves.remove(&id);
+                self.discard_external_save(id);
+            }
+            // only failures to write the file can be helped by elevated rights
+            Err(FileError::Io(..)) => {
+                self.failed_saves.insert(id, path.to_owned());
+            }
+            Err(_) => (),
+        }
+        result
+    }
+
+    /// Returns the destination of the last save of this buffer, if it failed.
+    pub fn get_failed_save(&self, id: BufferId) -> Option<&Path> {
+        self.failed_saves.get(&id).map(PathBuf::as_path)
+    }
+
+    /// Hands off a save to `path` to the frontend, by writing `text` (the
+    /// revision `rev_id` of the buffer) to a temporary file. The save is
+    /// completed by [`confirm_external_save`](#method.confirm_external_save),
+    /// once the frontend has moved this file to `path`.
+    pub fn save_to_temp(
+        &mut self,
+        path: &Path,
+        text: &Rope,
+        id: BufferId,
+        rev_id: RevId,
+    ) -> Result<&ExternalSave, FileError> {
+        self.discard_external_save(id);
+        self.check_lossless(path, text, id)?;
+        let file_name = path.file_name().map_or_else(|| "untitled".into(), |n| n.to_os_string());
+        let temp_dir =
+            create_private_dir(id).map_err(|e| FileError::Io(e, std::env::temp_dir()))?;
+        let temp_path = temp_dir.join(file_name);
+        let result = create_private_file(&temp_path)
+            .and_then(|mut f| write_text(&mut f, text, self.encoding(id)))
+            .and_then(|()| self.set_temp_permissions(&temp_path, path, id));
+        if let Err(e) = result {
+            remove_temp_file(&temp_path);
+            return Err(FileError::Io(e, temp_path));
+        }
+
+        let save = ExternalSave { path: path.to_owned(), temp_path, rev_id };
+        Ok(self.external_saves.entry(id).or_insert(save))
+    }
+
+    /// Completes a save handed off to the frontend, which has moved the
+    /// temporary file into place. Returns the save, if there was one.
+    pub fn confirm_external_save(&mut self, id: BufferId) -> Option<ExternalSave> {
+        let save = self.external_saves.remove(&id)?;
+        // the frontend may have copied the file, rather than moving it
+        remove_temp_file(&save.temp_path);
+        self.failed_saves.remove(&id);
+
+        let prev_path = self.file_info.get(&id).map(|info| info.path.clone());
+        if prev_path.as_ref() == Some(&save.path) {
+            let info = self.file_info.get_mut(&id).unwrap();
+            info.mod_time = get_mod_time(&save.path);
+            info.has_changed = false;
         } else {
-            self.save_new(path, text, id)
+            self.add_file_info(&save.path, id, self.encoding(id));
+            if let Some(prev_path) = prev_path {
+                self.open_files.remove(&prev_path);
+                #[cfg(feature = "notify")]
+                self.watcher.unwatch(&prev_path, OPEN_FILE_EVENT_TOKEN);
+            }
         }
+        Some(save)
     }
 
-    fn save_new(&mut self, path: &Path, text: &Rope, id: BufferId) -> Result<(), FileError> {
-        try_save(path, text, CharacterEncoding::Utf8, self.get_info(id))
-            .map_err(|e| FileError::Io(e, path.to_owned()))?;
+    /// Gives the temporary file of a save to `path` the permissions of the
+    /// file it replaces, so they survive the frontend moving it into place.
+    /// The temporary file stays private until its text is written.
+    #[cfg(target_family = "unix")]
+    fn set_temp_permissions(&self, temp_path: &Path, path: &Path, id: BufferId) -> io::Result<()> {
+        let recorded =
+            self.get_info(id).filter(|info| info.path == path).map(|info| info.permissions);
+        let mode = recorded.unwrap_or_else(|| get_permissions(path)).unwrap_or(0o644);
+        fs::set_permissions(temp_path, Permissions::from_mode(mode))
+    }
+
+    #[cfg(not(target_family = "unix"))]
+    fn set_temp_permissions(&self, _: &Path, _: &Path, _: BufferId) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Removes a save handed off to the frontend, which will not be confirmed.
+    fn discard_external_save(&mut self, id: BufferId) {
+        if let Some(save) = self.external_saves.remove(&id) {
+            remove_temp_file(&save.temp_path);
+        }
+    }
+
+    /// The encoding to save this buffer with.
+    fn encoding(&self, id: BufferId) -> CharacterEncoding {
+        self.get_info(id).map_or(CharacterEncoding::Utf8, |info| info.encoding)
+    }
+
+    /// Checks that saving this buffer to `path` loses no text. A file that
+    /// was loaded lossily can still be saved under another path, leaving the
+    /// original bytes untouched.
+    fn check_lossless(&self, path: &Path, text: &Rope, id: BufferId) -> Result<(), FileError> {
+        let overwrites_lossy =
+            self.get_info(id).map_or(false, |info| info.lossy && info.path == path);
+        if overwrites_lossy || !self.encoding(id).can_encode(text) {
+            return Err(FileError::LossyEncoding(path.to_owned()));
+        }
+        Ok(())
+    }
+
+    /// Tracks `path`, where this buffer was just saved, as the buffer's file.
+    fn add_file_info(&mut self, path: &Path, id: BufferId, encoding: CharacterEncoding) {
         let info = FileInfo {
-            encoding: CharacterEncoding::Utf8,
+            encoding,
+            lossy: false,
             path: path.to_owned(),
             mod_time: get_mod_time(path),
             has_changed: false,
@@ -152,6 +373,23 @@ impl FileManager {
         self.file_info.insert(id, info);
         #[cfg(feature = "notify")]
         self.watcher.watch(path, false, OPEN_FILE_EVENT_TOKEN);
+    }
+
+    /// Sets whether the file of this buffer is saved with a byte order mark.
+    /// Returns `true` if this changed; buffers without a file are unaffected.
+    pub fn set_bom(&mut self, id: BufferId, present: bool) -> Result<bool, FileError> {
+        match self.file_info.get_mut(&id) {
+            Some(info) => info.set_bom(present),
+            None => Ok(false),
+        }
+    }
+
+    fn save_new(&mut self, path: &Path, text: &Rope, id: BufferId) -> Result<(), FileError> {
+        // keep the encoding when saving an existing buffer under a new path
+        let encoding = self.encoding(id);
+        try_save(path, text, encoding, self.get_info(id))
+            .map_err(|e| FileError::Io(e, path.to_owned()))?;
+        self.add_file_info(path, id, encoding);
         Ok(())
     }
 
@@ -178,7 +416,6 @@ fn try_load_file<P>(path: P) -> Result<(Rope, FileInfo), FileError>
 where
     P: AsRef<Path>,
 {
-    // TODO: support for non-utf8
     // it's arguable that the rope crate should have file loading functionality
     let mut f =
         File::open(path.as_ref()).map_err(|e| FileError::Io(e, path.as_ref().to_owned()))?;
@@ -186,9 +423,10 @@ where
     f.read_to_end(&mut bytes).map_err(|e| FileError::Io(e, path.as_ref().to_owned()))?;
 
     let encoding = CharacterEncoding::guess(&bytes);
-    let rope = try_decode(bytes, encoding, path.as_ref())?;
+    let (rope, lossy) = encoding.decode(bytes);
     let info = FileInfo {
         encoding,
+        lossy,
         mod_time: get_mod_time(&path),
         #[cfg(target_family = "unix")]
         permissions: get_permissions(&path),
@@ -216,14 +454,7 @@ fn try_save(
     let tmp_path = &path.with_extension(tmp_extension);
 
     let mut f = File::create(tmp_path)?;
-    match encoding {
-        CharacterEncoding::Utf8WithBom => f.write_all(UTF8_BOM.as_bytes())?,
-        CharacterEncoding::Utf8 => (),
-    }
-
-    for chunk in text.iter_chunks(..text.len()) {
-        f.write_all(chunk.as_bytes())?;
-    }
+    write_text(&mut f, text, encoding)?;
 
     fs::rename(tmp_path, path)?;
 
@@ -240,31 +471,252 @@ fn try_save(
     Ok(())
 }
 
-fn try_decode(bytes: Vec<u8>, encoding: CharacterEncoding, path: &Path) -> Result<Rope, FileError> {
-    match encoding {
-        CharacterEncoding::Utf8 => Ok(Rope::from(
-            str::from_utf8(&bytes).map_err(|_e| FileError::UnknownEncoding(path.to_owned()))?,
-        )),
-        CharacterEncoding::Utf8WithBom => {
-            let s = String::from_utf8(bytes)
-                .map_err(|_e| FileError::UnknownEncoding(path.to_owned()))?;
-            Ok(Rope::from(&s[UTF8_BOM.len()..]))
+fn write_text(f: &mut File, text: &Rope, encoding: CharacterEncoding) -> io::Result<()> {
+    f.write_all(encoding.bom())?;
+    for chunk in text.iter_chunks(..text.len()) {
+        f.write_all(&encoding.encode(chunk))?;
+    }
+    Ok(())
+}
+
+/// Creates a directory in the system's temporary directory that only the
+/// current user can access. Its name is not predictable, and an existing
+/// directory is never reused, so other users can't plant or read the files
+/// written there.
+fn create_private_dir(id: BufferId) -> io::Result<PathBuf> {
+    static NEXT_DIR: AtomicUsize = AtomicUsize::new(0);
+    let mut builder = fs::DirBuilder::new();
+    #[cfg(target_family = "unix")]
+    builder.mode(0o700);
+
+    let mut attempts = 0;
+    loop {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_usize(NEXT_DIR.fetch_add(1, Ordering::Relaxed));
+        if let Ok(elapsed) = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+            hasher.write_u128(elapsed.as_nanos());
+        }
+        let name = format!("xi-save-{}-{}-{:016x}", process::id(), id.0, hasher.finish());
+        let dir = std::env::temp_dir().join(name);
+        match builder.create(&dir) {
+            Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => (),
+            result => return result.map(|_| dir),
         }
+        attempts += 1;
+        if attempts == MAX_TEMP_DIR_ATTEMPTS {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, "no unused temp dir name"));
+        }
+    }
+}
+
+/// Creates a file which only the current user can read or write, failing if
+/// `path` exists.
+fn create_private_file(path: &Path) -> io::Result<File> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(target_family = "unix")]
+    options.mode(0o600);
+    options.open(path)
+}
+
+/// Removes a file written by `save_to_temp`, and its private directory.
+fn remove_temp_file(path: &Path) {
+    let _ = fs::remove_file(path);
+    if let Some(dir) = path.parent() {
+        let _ = fs::remove_dir(dir);
     }
 }
 
 impl CharacterEncoding {
+    pub fn has_bom(self) -> bool {
+        !self.bom().is_empty()
+    }
+
+    /// The byte order mark files in this encoding start with, if any.
+    fn bom(self) -> &'static [u8] {
+        match self {
+            CharacterEncoding::Utf8WithBom => UTF8_BOM.as_bytes(),
+            CharacterEncoding::Utf16Le => UTF16LE_BOM,
+            CharacterEncoding::Utf16Be => UTF16BE_BOM,
+            CharacterEncoding::Utf8 | CharacterEncoding::Latin1 => &[],
+        }
+    }
+
+    /// Guesses the encoding of a file from its byte order mark, falling back
+    /// to Latin-1 for files that are not valid UTF-8.
     fn guess(s: &[u8]) -> Self {
         if s.starts_with(UTF8_BOM.as_bytes()) {
             CharacterEncoding::Utf8WithBom
-        } else {
+        } else if s.starts_with(UTF16LE_BOM) {
+            CharacterEncoding::Utf16Le
+        } else if s.starts_with(UTF16BE_BOM) {
+            CharacterEncoding::Utf16Be
+        } else if str::from_utf8(s).is_ok() {
             CharacterEncoding::Utf8
+        } else {
+            CharacterEncoding::Latin1
+        }
+    }
+
+    /// Decodes the contents of a file, including any byte order mark. Invalid
+    /// sequences are replaced by U+FFFD, in which case this also returns `true`.
+    fn decode(self, bytes: Vec<u8>) -> (Rope, bool) {
+        let body = &bytes[self.bom().len()..];
+        match self {
+            CharacterEncoding::Utf8 | CharacterEncoding::Utf8WithBom => {
+                let s = String::from_utf8_lossy(body);
+                let lossy = match s {
+                    Cow::Owned(_) => true,
+                    Cow::Borrowed(_) => false,
+                };
+                (Rope::from(s), lossy)
+            }
+            CharacterEncoding::Utf16Le | CharacterEncoding::Utf16Be => {
+                let (pairs, odd_byte) = body.split_at(body.len() - body.len() % 2);
+                let units = pairs.chunks(2).map(|pair| match self {
+                    CharacterEncoding::Utf16Le => u16::from_le_bytes([pair[0], pair[1]]),
+                    _ => u16::from_be_bytes([pair[0], pair[1]]),
+                });
+                let mut lossy = !odd_byte.is_empty();
+                let mut s = char::decode_utf16(units)
+                    .map(|c| {
+                        c.unwrap_or_else(|_| {
+                            lossy = true;
+                            char::REPLACEMENT_CHARACTER
+                        })
+                    })
+                    .collect::<String>();
+                if !odd_byte.is_empty() {
+                    s.push(char::REPLACEMENT_CHARACTER);
+                }
+                (Rope::from(s), lossy)
+            }
+            CharacterEncoding::Latin1 => {
+                (Rope::from(body.iter().map(|&b| char::from(b)).collect::<String>()), false)
+            }
+        }
+    }
+
+    /// Returns `true` if every character of `text` can be saved in this encoding.
+    fn can_encode(self, text: &Rope) -> bool {
+        match self {
+            CharacterEncoding::Latin1 => {
+                text.iter_chunks(..).all(|chunk| chunk.chars().all(|c| (c as u32) <= 0xff))
+            }
+            _ => true,
+        }
+    }
+
+    /// Encodes a piece of text, without a byte order mark. Characters that
+    /// this encoding cannot represent (see `can_encode`) are replaced by `?`.
+    fn encode(self, s: &str) -> Cow<[u8]> {
+        match self {
+            CharacterEncoding::Utf8 | CharacterEncoding::Utf8WithBom => Cow::from(s.as_bytes()),
+            CharacterEncoding::Utf16Le | CharacterEncoding::Utf16Be => {
+                let to_bytes = if self == CharacterEncoding::Utf16Le {
+                    u16::to_le_bytes
+                } else {
+                    u16::to_be_bytes
+                };
+                let mut bytes = Vec::with_capacity(s.len() * 2);
+                for unit in s.encode_utf16() {
+                    bytes.extend_from_slice(&to_bytes(unit));
+                }
+                Cow::from(bytes)
+            }
+            CharacterEncoding::Latin1 => Cow::from(
+                s.chars()
+                    .map(|c| if (c as u32) <= 0xff { c as u8 } else { b'?' })
+                    .collect::<Vec<_>>(),
+            ),
+        }
+    }
+}
+
+/// Raw OS error codes for the kinds of save errors `io::ErrorKind` does not
+/// distinguish.
+#[cfg(target_family = "unix")]
+mod os_error {
+    /// EROFS
+    pub const READ_ONLY_FILESYSTEM: &[i32] = &[30];
+    /// EBUSY, ETXTBSY
+    pub const LOCKED: &[i32] = &[16, 26];
+    /// ENOSPC
+    pub const OUT_OF_SPACE: &[i32] = &[28];
+}
+
+#[cfg(target_family = "windows")]
+mod os_error {
+    /// ERROR_WRITE_PROTECT
+    pub const READ_ONLY_FILESYSTEM: &[i32] = &[19];
+    /// ERROR_SHARING_VIOLATION, ERROR_LOCK_VIOLATION
+    pub const LOCKED: &[i32] = &[32, 33];
+    /// ERROR_HANDLE_DISK_FULL, ERROR_DISK_FULL
+    pub const OUT_OF_SPACE: &[i32] = &[39, 112];
+}
+
+#[cfg(not(any(target_family = "unix", target_family = "windows")))]
+mod os_error {
+    pub const READ_ONLY_FILESYSTEM: &[i32] = &[];
+    pub const LOCKED: &[i32] = &[];
+    pub const OUT_OF_SPACE: &[i32] = &[];
+}
+
+impl SaveErrorKind {
+    fn from_io_error(err: &io::Error) -> SaveErrorKind {
+        match err.raw_os_error() {
+            Some(code) if os_error::READ_ONLY_FILESYSTEM.contains(&code) => {
+                return SaveErrorKind::ReadOnlyFilesystem
+            }
+            Some(code) if os_error::LOCKED.contains(&code) => return SaveErrorKind::Locked,
+            Some(code) if os_error::OUT_OF_SPACE.contains(&code) => {
+                return SaveErrorKind::OutOfSpace
+            }
+            _ => (),
+        }
+        match err.kind() {
+            io::ErrorKind::PermissionDenied => SaveErrorKind::PermissionDenied,
+            io::ErrorKind::NotFound => SaveErrorKind::NotFound,
+            _ => SaveErrorKind::Other,
         }
     }
 }
 
 /// Returns the modification timestamp for the file at a given path,
 /// if present.
+/// Returns the paths related to `path` under `rules`, such as the header of
+/// a C++ source file, in order of preference. Each rule is a pair of file name
+/// patterns like `("*.cpp", "*.h")`: if the file name matches the first, the
+/// `*` in the second is replaced by the text the first `*` matched. The second
+/// pattern may also name a directory relative to that of `path`.
+pub fn related_files(path: &Path, rules: &[(String, String)]) -> Vec<PathBuf> {
+    let name = match path.file_name().and_then(|name| name.to_str()) {
+        Some(name) => name,
+        None => return Vec::new(),
+    };
+    rules
+        .iter()
+        .filter_map(|(pattern, related)| {
+            let stem = match_file_pattern(pattern, name)?;
+            Some(path.with_file_name(related.replacen('*', stem, 1)))
+        })
+        .collect()
+}
+
+/// If `name` matches `pattern`, returns the part of `name` matched by the
+/// pattern's `*`. Patterns without a `*` match nothing.
+fn match_file_pattern<'a>(pattern: &str, name: &'a str) -> Option<&'a str> {
+    let star = pattern.find('*')?;
+    let (prefix, suffix) = (&pattern[..star], &pattern[star + 1..]);
+    if name.len() < prefix.len() + suffix.len()
+        || !name.starts_with(prefix)
+        || !name.ends_with(suffix)
+    {
+        return None;
+    }
+    Some(&name[prefix.len()..name.len() - suffix.len()])
+}
+
 fn get_mod_time<P: AsRef<Path>>(path: P) -> Option<SystemTime> {
     File::open(path).and_then(|f| f.metadata()).and_then(|meta| meta.modified()).ok()
 }
@@ -287,11 +739,35 @@ impl From<FileError> for RemoteError {
 }
 
 impl FileError {
+    /// Returns the reason a save failed with this error.
+    pub fn save_error_kind(&self) -> SaveErrorKind {
+        match self {
+            FileError::Io(e, _) => SaveErrorKind::from_io_error(e),
+            FileError::UnknownEncoding(_) => SaveErrorKind::Other,
+            FileError::HasChanged(_) => SaveErrorKind::HasChanged,
+            FileError::LossyEncoding(_) => SaveErrorKind::LossyEncoding,
+            FileError::BomUnsupported(_) => SaveErrorKind::Other,
+        }
+    }
+
+    /// The path of the file this error concerns.
+    pub fn path(&self) -> &Path {
+        match self {
+            FileError::Io(_, p)
+            | FileError::UnknownEncoding(p)
+            | FileError::HasChanged(p)
+            | FileError::LossyEncoding(p)
+            | FileError::BomUnsupported(p) => p,
+        }
+    }
+
     fn error_code(&self) -> i64 {
         match self {
             FileError::Io(_, _) => 5,
             FileError::UnknownEncoding(_) => 6,
             FileError::HasChanged(_) => 7,
+            FileError::LossyEncoding(_) => 8,
+            FileError::BomUnsupported(_) => 9,
         }
     }
 }
@@ -307,6 +783,182 @@ impl fmt::Display for FileError {
                  Please save elsewhere and reload the file. File path: {:?}",
                 p
             ),
+            FileError::LossyEncoding(ref p) => write!(
+                f,
+                "Saving in the file's encoding would lose some of its text. \
+                 Please save elsewhere. File path: {:?}",
+                p
+            ),
+            FileError::BomUnsupported(ref p) => write!(
+                f,
+                "The byte order mark can only be changed for UTF-8 files. File path: {:?}",
+                p
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file_info(bytes: &[u8]) -> FileInfo {
+        FileInfo {
+            encoding: CharacterEncoding::guess(bytes),
+            lossy: false,
+            path: PathBuf::from("test.txt"),
+            mod_time: None,
+            has_changed: false,
+            #[cfg(target_family = "unix")]
+            permissions: None,
         }
     }
+
+    #[test]
+    fn related_file_patterns() {
+        let rules = |pairs: &[(&str, &str)]| -> Vec<(String, String)> {
+            pairs.iter().map(|(a, b)| (a.to_string(), b.to_string())).collect()
+        };
+        let rules = rules(&[("*.cpp", "*.h"), ("*.cpp", "../include/*.hpp"), ("*.h", "*.cpp")]);
+        let path = Path::new("src/foo.cpp");
+        assert_eq!(
+            related_files(path, &rules),
+            vec![PathBuf::from("src/foo.h"), PathBuf::from("src/../include/foo.hpp")]
+        );
+        assert_eq!(related_files(Path::new("foo.h"), &rules), vec![PathBuf::from("foo.cpp")]);
+        assert!(related_files(Path::new("foo.rs"), &rules).is_empty());
+        assert_eq!(match_file_pattern("test_*.rs", "test_.rs"), Some(""));
+        assert_eq!(match_file_pattern("a*a", "a"), None);
+        assert_eq!(match_file_pattern("foo.h", "foo.h"), None);
+    }
+
+    #[test]
+    fn toggle_bom() {
+        let mut info = file_info(b"\xef\xbb\xbfhello");
+        assert!(info.has_bom());
+        assert!(!info.set_bom(true).unwrap());
+        assert!(info.set_bom(false).unwrap());
+        assert!(!info.has_bom());
+
+        let mut info = file_info(b"hello");
+        assert!(!info.has_bom());
+        assert!(info.set_bom(true).unwrap());
+        assert!(info.has_bom());
+
+        let mut info = file_info(b"\xff\xfeh\x00");
+        assert_eq!(info.encoding, CharacterEncoding::Utf16Le);
+        assert!(matches!(info.set_bom(false), Err(FileError::BomUnsupported(_))));
+        assert!(info.has_bom());
+    }
+    /// Files in each encoding, with their text.
+    const FIXTURES: &[(&[u8], CharacterEncoding, &str)] = &[
+        (b"h\xc3\xa9\n", CharacterEncoding::Utf8, "h\u{e9}\n"),
+        (b"\xef\xbb\xbfh\xc3\xa9\n", CharacterEncoding::Utf8WithBom, "h\u{e9}\n"),
+        (b"\xff\xfeh\x00\xe9\x00=\xd8\x00\xde", CharacterEncoding::Utf16Le, "h\u{e9}\u{1f600}"),
+        (b"\xfe\xff\x00h\x00\xe9\xd8=\xde\x00", CharacterEncoding::Utf16Be, "h\u{e9}\u{1f600}"),
+        (b"h\xe9\n", CharacterEncoding::Latin1, "h\u{e9}\n"),
+    ];
+
+    #[test]
+    fn encoding_round_trips() {
+        for &(bytes, encoding, text) in FIXTURES {
+            assert_eq!(CharacterEncoding::guess(bytes), encoding);
+            let (rope, lossy) = encoding.decode(bytes.to_vec());
+            assert_eq!(String::from(&rope), text, "{:?}", encoding);
+            assert!(!lossy);
+            assert!(encoding.can_encode(&rope));
+            let mut saved = encoding.bom().to_vec();
+            saved.extend_from_slice(&encoding.encode(text));
+            assert_eq!(saved, bytes, "{:?}", encoding);
+        }
+    }
+
+    #[test]
+    fn lossy_decoding() {
+        // an unpaired surrogate, and a trailing odd byte
+        let bytes = b"\xff\xfe\x00\xd8a\x00b".to_vec();
+        let (rope, lossy) = CharacterEncoding::Utf16Le.decode(bytes);
+        assert_eq!(String::from(&rope), "\u{fffd}a\u{fffd}");
+        assert!(lossy);
+
+        let bytes = b"\xef\xbb\xbfa\xff".to_vec();
+        assert_eq!(CharacterEncoding::guess(&bytes), CharacterEncoding::Utf8WithBom);
+        let (rope, lossy) = CharacterEncoding::Utf8WithBom.decode(bytes);
+        assert_eq!(String::from(&rope), "a\u{fffd}");
+        assert!(lossy);
+
+        // Latin-1 is only lossy when saving characters it doesn't have
+        assert!(CharacterEncoding::Latin1.can_encode(&Rope::from("caf\u{e9}")));
+        assert!(!CharacterEncoding::Latin1.can_encode(&Rope::from("\u{20ac}")));
+    }
+
+    #[test]
+    fn save_error_kinds() {
+        let kind = |e: io::Error| FileError::Io(e, PathBuf::new()).save_error_kind();
+        assert_eq!(
+            kind(io::Error::new(io::ErrorKind::PermissionDenied, "")),
+            SaveErrorKind::PermissionDenied
+        );
+        assert_eq!(kind(io::Error::new(io::ErrorKind::NotFound, "")), SaveErrorKind::NotFound);
+        assert_eq!(kind(io::Error::new(io::ErrorKind::Other, "")), SaveErrorKind::Other);
+        #[cfg(target_family = "unix")]
+        {
+            assert_eq!(kind(io::Error::from_raw_os_error(30)), SaveErrorKind::ReadOnlyFilesystem);
+            assert_eq!(kind(io::Error::from_raw_os_error(26)), SaveErrorKind::Locked);
+            assert_eq!(kind(io::Error::from_raw_os_error(28)), SaveErrorKind::OutOfSpace);
+            // EACCES
+            assert_eq!(kind(io::Error::from_raw_os_error(13)), SaveErrorKind::PermissionDenied);
+        }
+        let changed = FileError::HasChanged(PathBuf::new());
+        assert_eq!(changed.save_error_kind(), SaveErrorKind::HasChanged);
+    }
+
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn temp_files_are_private() {
+        let dir = create_private_dir(BufferId(1)).unwrap();
+        let other = create_private_dir(BufferId(1)).unwrap();
+        assert_ne!(dir, other);
+        let _ = fs::remove_dir(&other);
+
+        let path = dir.join("file.txt");
+        create_private_file(&path).unwrap();
+        assert!(create_private_file(&path).is_err());
+        let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(&dir), 0o700);
+        assert_eq!(mode(&path), 0o600);
+
+        remove_temp_file(&path);
+        assert!(!dir.exists());
+    }
+
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn temp_files_keep_permissions() {
+        use xi_rope::engine::Engine;
+
+        let tmp = tempdir::TempDir::new("xi-test-temp-permissions").unwrap();
+        let path = tmp.path().join("file.txt");
+        fs::write(&path, "hello\n").unwrap();
+        fs::set_permissions(&path, Permissions::from_mode(0o640)).unwrap();
+        #[cfg(feature = "notify")]
+        let mut manager = FileManager::new(FileWatcher::new(crossbeam_channel::unbounded().0));
+        #[cfg(not(feature = "notify"))]
+        let mut manager = FileManager::new();
+        let id = BufferId(1);
+        let text = manager.open(&path, id).unwrap();
+        let rev_id = Engine::new(text.clone()).get_head_rev_id();
+        let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+
+        let temp_path = manager.save_to_temp(&path, &text, id, rev_id).unwrap().temp_path.clone();
+        assert_eq!(mode(&temp_path), 0o640);
+
+        // the frontend's copy doesn't change the permissions core restores
+        fs::copy(&temp_path, &path).unwrap();
+        fs::set_permissions(&path, Permissions::from_mode(0o600)).unwrap();
+        manager.confirm_external_save(id).unwrap();
+        let permissions = manager.get_info(id).unwrap().permissions.unwrap();
+        assert_eq!(permissions & 0o777, 0o640);
+        assert!(!temp_path.exists());
+    }
 }
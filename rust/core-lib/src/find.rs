@@ -25,7 +25,7 @@ use crate::word_boundaries::WordCursor;
 use regex::{Regex, RegexBuilder};
 use xi_rope::delta::DeltaRegion;
 use xi_rope::find::{find, is_multiline_regex, CaseMatching};
-use xi_rope::{Cursor, Interval, LinesMetric, Metric, Rope, RopeDelta};
+use xi_rope::{Cursor, Interval, LinesMetric, Metric, Rope, RopeDelta, Transformer};
 
 const REGEX_SIZE_LIMIT: usize = 1000000;
 
@@ -47,11 +47,17 @@ pub struct FindStatus {
     /// Query only matches whole words.
     whole_words: Option<bool>,
 
+    /// Query only matches inside the block that contained the caret.
+    find_in_block: Option<bool>,
+
     /// Total number of matches.
     matches: usize,
 
     /// Line numbers which have find results.
     lines: Vec<usize>,
+
+    /// Incremental find is still running, so `matches` and `lines` are not final.
+    incomplete: bool,
 }
 
 /// Contains logic to search text
@@ -62,6 +68,9 @@ pub struct Find {
     /// The occurrences, which determine the highlights, have been updated.
     hls_dirty: bool,
 
+    /// Bumped whenever the occurrences change.
+    generation: u64,
+
     /// The currently active search string.
     search_string: Option<String>,
 
@@ -74,6 +83,9 @@ pub struct Find {
     /// Query matches only whole words.
     whole_words: bool,
 
+    /// If set, only occurrences inside this range are found.
+    scope: Option<Interval>,
+
     /// The set of all known find occurrences (highlights).
     occurrences: Selection,
 }
@@ -83,10 +95,12 @@ impl Find {
         Find {
             id,
             hls_dirty: true,
+            generation: 0,
             search_string: None,
             case_matching: CaseMatching::CaseInsensitive,
             regex: None,
             whole_words: false,
+            scope: None,
             occurrences: Selection::new(),
         }
     }
@@ -99,11 +113,27 @@ impl Find {
         &self.occurrences
     }
 
+    /// Whether the current search is a regular expression. This is `false`
+    /// if the query was meant as a regex but failed to compile.
+    pub(crate) fn is_regex(&self) -> bool {
+        self.regex.is_some()
+    }
+
     pub fn hls_dirty(&self) -> bool {
         self.hls_dirty
     }
 
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    fn occurrences_changed(&mut self) {
+        self.hls_dirty = true;
+        self.generation += 1;
+    }
+
     pub fn find_status(&self, view: &View, text: &Rope, matches_only: bool) -> FindStatus {
+        let incomplete = view.find_in_progress();
         if matches_only {
             FindStatus {
                 id: self.id,
@@ -111,8 +141,10 @@ impl Find {
                 case_sensitive: None,
                 is_regex: None,
                 whole_words: None,
+                find_in_block: None,
                 matches: self.occurrences.len(),
                 lines: Vec::new(),
+                incomplete,
             }
         } else {
             FindStatus {
@@ -121,16 +153,39 @@ impl Find {
                 case_sensitive: Some(self.case_matching == CaseMatching::Exact),
                 is_regex: Some(self.regex.is_some()),
                 whole_words: Some(self.whole_words),
+                find_in_block: Some(self.scope.is_some()),
                 matches: self.occurrences.len(),
                 lines: self
                     .occurrences
                     .iter()
                     .map(|o| view.offset_to_line_col(text, o.min()).0 + 1)
                     .collect(),
+                incomplete,
             }
         }
     }
 
+    /// Returns the lines of `view` that have matches, in order. If more than
+    /// `max_lines` lines have matches, the document is split into
+    /// `max_lines` equal parts and only the first line with matches in each
+    /// part is kept, so that a scrollbar overview of a huge file still shows
+    /// where the matches are.
+    pub fn match_lines(&self, view: &View, text: &Rope, max_lines: usize) -> Vec<usize> {
+        let mut lines =
+            self.occurrences.iter().map(|o| view.line_of_offset(text, o.min())).collect::<Vec<_>>();
+        lines.dedup();
+        if lines.len() <= max_lines {
+            return lines;
+        }
+        if max_lines == 0 {
+            return Vec::new();
+        }
+        let n_lines = view.line_of_offset(text, text.len()) + 1;
+        let part_len = (n_lines + max_lines - 1) / max_lines;
+        lines.dedup_by_key(|line| *line / part_len);
+        lines
+    }
+
     pub fn set_hls_dirty(&mut self, is_dirty: bool) {
         self.hls_dirty = is_dirty
     }
@@ -138,6 +193,8 @@ impl Find {
     pub fn update_highlights(&mut self, text: &Rope, delta: &RopeDelta) {
         // update search highlights for changed regions
         if self.search_string.is_some() {
+            self.occurrences_changed();
+
             // invalidate occurrences around deletion positions
             for DeltaRegion { old_offset, len, .. } in delta.iter_deletions() {
                 self.occurrences.delete_range(old_offset, old_offset + len, false);
@@ -145,6 +202,14 @@ impl Find {
 
             self.occurrences = self.occurrences.apply_delta(delta, false, InsertDrift::Default);
 
+            // edits at the edges of the scope are inside it
+            if let Some(scope) = self.scope {
+                let mut transformer = Transformer::new(delta);
+                let start = transformer.transform(scope.start(), false);
+                let end = transformer.transform(scope.end(), true);
+                self.scope = Some(Interval::new(start, end));
+            }
+
             // invalidate occurrences around insert positions
             for DeltaRegion { new_offset, len, .. } in delta.iter_inserts() {
                 // also invalidate previous occurrence since it might expand after insertion
@@ -197,7 +262,7 @@ impl Find {
     pub fn unset(&mut self) {
         self.search_string = None;
         self.occurrences = Selection::new();
-        self.hls_dirty = true;
+        self.occurrences_changed();
     }
 
     /// Sets find parameters and search query. Returns `true` if parameters have been updated.
@@ -246,12 +311,26 @@ impl Find {
         true
     }
 
+    /// Limits the search to `scope`, or the whole text if `None`. Returns `true`
+    /// if the scope changed, in which case the search has to be re-run.
+    pub(crate) fn set_scope(&mut self, scope: Option<Interval>) -> bool {
+        if self.scope == scope {
+            return false;
+        }
+        self.scope = scope;
+        self.occurrences = Selection::new();
+        self.occurrences_changed();
+        true
+    }
+
     /// Execute the search on the provided text in the range provided by `start` and `end`.
     pub fn update_find(&mut self, text: &Rope, start: usize, end: usize, include_slop: bool) {
         if self.search_string.is_none() {
             return;
         }
 
+        let scope = self.scope.unwrap_or_else(|| Interval::new(0, text.len()));
+
         // extend the search by twice the string length (twice, because case matching may increase
         // the length of an occurrence)
         let slop = if include_slop { self.search_string.as_ref().unwrap().len() * 2 } else { 0 };
@@ -259,8 +338,11 @@ impl Find {
         let search_string = self.search_string.as_ref().unwrap();
 
         // expand region to be able to find occurrences around the region's edges
-        let expanded_start = max(start, slop) - slop;
-        let expanded_end = min(end + slop, text.len());
+        let expanded_start = max(max(start, slop) - slop, scope.start());
+        let expanded_end = min(min(end + slop, text.len()), scope.end());
+        if expanded_start > expanded_end {
+            return;
+        }
         let from = text.at_or_prev_codepoint_boundary(expanded_start).unwrap_or(0);
         let to = text.at_or_next_codepoint_boundary(expanded_end).unwrap_or(text.len());
         let mut to_cursor = Cursor::new(text, to);
@@ -280,6 +362,11 @@ impl Find {
         ) {
             let end = find_cursor.pos();
 
+            // a multi-line match may extend past the end of the scope
+            if end > scope.end() {
+                break;
+            }
+
             if self.whole_words && !self.is_matching_whole_words(text, start, end) {
                 raw_lines = text.lines_raw(find_cursor.pos()..to);
                 continue;
@@ -316,7 +403,7 @@ impl Find {
             raw_lines = text.lines_raw(find_cursor.pos()..to);
         }
 
-        self.hls_dirty = true;
+        self.occurrences_changed();
     }
 
     /// Return the occurrence closest to the provided selection `sel`. If searched is reversed then
@@ -428,6 +515,84 @@ impl ToAnnotation for Find {
     }
 }
 
+/// Returns the block containing `offset`: the text inside the innermost pair
+/// of braces around it or, if there is none, the run of lines indented at
+/// least as deeply as the line containing `offset`. Returns `None` if `offset`
+/// is at the top level.
+///
+/// This is a heuristic; braces in strings and comments are counted as well.
+pub(crate) fn enclosing_block(text: &Rope, offset: usize) -> Option<Interval> {
+    brace_block(text, offset).or_else(|| indent_block(text, offset))
+}
+
+fn brace_block(text: &Rope, offset: usize) -> Option<Interval> {
+    let mut cursor = Cursor::new(text, offset);
+    let mut depth = 0;
+    let start = loop {
+        match cursor.prev_codepoint()? {
+            '}' => depth += 1,
+            '{' if depth == 0 => break cursor.pos() + 1,
+            '{' => depth -= 1,
+            _ => (),
+        }
+    };
+
+    let mut cursor = Cursor::new(text, offset);
+    let mut depth = 0;
+    let end = loop {
+        match cursor.next_codepoint()? {
+            '{' => depth += 1,
+            '}' if depth == 0 => break cursor.pos() - 1,
+            '}' => depth -= 1,
+            _ => (),
+        }
+    };
+    Some(Interval::new(start, end))
+}
+
+fn indent_block(text: &Rope, offset: usize) -> Option<Interval> {
+    let line = text.line_of_offset(offset);
+    let last_line = text.line_of_offset(text.len());
+    let indent = match line_indent(text, line) {
+        Some(0) | None => return None,
+        Some(indent) => indent,
+    };
+    // blank lines don't end a block, but aren't included at its edges
+    let in_block = |line| line_indent(text, line).map(|i| i >= indent);
+
+    let mut first = line;
+    let mut l = line;
+    while l > 0 && in_block(l - 1) != Some(false) {
+        l -= 1;
+        if in_block(l).is_some() {
+            first = l;
+        }
+    }
+
+    let mut last = line;
+    let mut l = line;
+    while l < last_line && in_block(l + 1) != Some(false) {
+        l += 1;
+        if in_block(l).is_some() {
+            last = l;
+        }
+    }
+
+    Some(Interval::new(text.offset_of_line(first), text.offset_of_line(last + 1)))
+}
+
+/// Returns the width of the leading whitespace of `line`, or `None` if the
+/// line is blank.
+fn line_indent(text: &Rope, line: usize) -> Option<usize> {
+    let content = text.slice_to_cow(text.offset_of_line(line)..text.offset_of_line(line + 1));
+    let trimmed = content.trim_start();
+    if trimmed.is_empty() {
+        None
+    } else {
+        Some(content.len() - trimmed.len())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -463,6 +628,21 @@ mod tests {
         assert_eq!(find.occurrences().first(), Some(&SelRegion::new(6, 11)));
     }
 
+    #[test]
+    fn find_case_folding() {
+        let base_text = Rope::from("Straße, STRASSE\n \u{1C4}EMAL \u{1C5}emal");
+        let mut find = Find::new(1);
+        find.set_find("strasse", false, false, false);
+        find.update_find(&base_text, 0, base_text.len(), false);
+        assert_eq!(find.occurrences().len(), 2);
+        assert_eq!(find.occurrences().first(), Some(&SelRegion::new(0, 7)));
+
+        // title case and upper case digraphs both fold to lower case
+        find.set_find("\u{1C6}emal", false, false, false);
+        find.update_find(&base_text, 0, base_text.len(), false);
+        assert_eq!(find.occurrences().len(), 2);
+    }
+
     #[test]
     fn find_multiline() {
         let base_text = Rope::from("hello world\n HELLO WORLD");
@@ -503,6 +683,19 @@ mod tests {
         assert_eq!(find.occurrences().first(), Some(&SelRegion::new(0, 12)));
     }
 
+    #[test]
+    fn find_empty_matches_again() {
+        let base_text = Rope::from("ab b");
+        let mut find = Find::new(1);
+        find.set_find("a*", false, true, false);
+        find.update_find(&base_text, 0, base_text.len(), false);
+        let count = find.occurrences().len();
+        // searching the same text again keeps the empty matches as they are
+        find.update_find(&base_text, 0, base_text.len(), false);
+        assert_eq!(find.occurrences().len(), count);
+        assert_eq!(find.occurrences().first(), Some(&SelRegion::new(0, 1)));
+    }
+
     #[test]
     fn find_multiline_regex() {
         let mut find = Find::new(1);
@@ -528,6 +721,63 @@ mod tests {
         assert_eq!(find.occurrences().first(), Some(&SelRegion::new(0, 3)));
     }
 
+    #[test]
+    fn find_scoped() {
+        let base_text = Rope::from("aaa bbb aaa bbb aaa x");
+        let mut find = Find::new(1);
+        find.set_find("aaa", true, false, false);
+        assert!(find.set_scope(Some(Interval::new(4, 16))));
+        find.update_find(&base_text, 0, base_text.len(), true);
+        assert_eq!(find.occurrences().len(), 1);
+        assert_eq!(find.occurrences().first(), Some(&SelRegion::new(8, 11)));
+
+        assert!(!find.set_scope(Some(Interval::new(4, 16))));
+        assert!(find.set_scope(None));
+        find.update_find(&base_text, 0, base_text.len(), true);
+        assert_eq!(find.occurrences().len(), 3);
+    }
+
+    #[test]
+    fn find_scoped_edit() {
+        let base_text = Rope::from("a { a } a");
+        let mut find = Find::new(1);
+        find.set_find("a", false, false, false);
+        find.set_scope(Some(Interval::new(3, 6)));
+        find.update_find(&base_text, 0, base_text.len(), false);
+        assert_eq!(find.occurrences().len(), 1);
+
+        // an insertion at the start of the scope is inside it
+        let mut builder = DeltaBuilder::new(base_text.len());
+        builder.replace(3..3, "a".into());
+        let delta = builder.build();
+        let text = delta.apply(&base_text);
+        find.update_highlights(&text, &delta);
+        assert_eq!(find.occurrences().len(), 2);
+        assert_eq!(find.occurrences().first(), Some(&SelRegion::new(3, 4)));
+        assert_eq!(find.occurrences().last(), Some(&SelRegion::new(5, 6)));
+    }
+
+    #[test]
+    fn enclosing_brace_block() {
+        let text = Rope::from("fn a() {\n    if b { c }\n    d\n}\ne");
+        let block = |offset| enclosing_block(&text, offset).map(|iv| iv.start()..iv.end());
+        assert_eq!(block(28), Some(8..30));
+        // nested blocks are skipped over
+        assert_eq!(block(14), Some(8..30));
+        assert_eq!(block(20), Some(19..22));
+        assert_eq!(block(32), None);
+    }
+
+    #[test]
+    fn enclosing_indent_block() {
+        let text = Rope::from("def a():\n    b\n\n    c\n        d\n\ne\n");
+        let block = |offset| enclosing_block(&text, offset).map(|iv| iv.start()..iv.end());
+        // blank lines inside the block are included, but not those after it
+        assert_eq!(block(13), Some(9..32));
+        assert_eq!(block(30), Some(22..32));
+        assert_eq!(block(33), None);
+    }
+
     #[test]
     fn find_next_occurrence() {
         let base_text = Rope::from("aaa bbb aaa bbb aaa x");
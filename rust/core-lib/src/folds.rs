@@ -0,0 +1,263 @@
+// Copyright 2020 The xi-editor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Folded regions of a view, whose lines are hidden from the client.
+
+use std::cmp::{max, min};
+
+use xi_rope::{DeltaElement, Interval, LinesMetric, Rope, RopeDelta, Transformer};
+
+/// The folded regions of a view.
+///
+/// A fold hides one or more whole logical lines, beneath the line that
+/// anchors it, which stays visible. Each fold is stored as the offsets of the
+/// text it hides: from the start of its first hidden line to the end of its
+/// last hidden line, not counting its line ending. Unlike most intervals, the
+/// end offset is hidden as well; this way, the line ending of a folded last
+/// line is hidden with it, and a folded last line without a line ending
+/// hides the end of the buffer.
+#[derive(Debug, Default, Clone)]
+pub struct Folds {
+    /// Invariant: sorted, and no fold hides the anchor of another.
+    folds: Vec<Interval>,
+}
+
+impl Folds {
+    pub fn is_empty(&self) -> bool {
+        self.folds.is_empty()
+    }
+
+    /// Returns the folds, in order.
+    pub fn iter(&self) -> impl Iterator<Item = Interval> + '_ {
+        self.folds.iter().cloned()
+    }
+
+    /// Returns the fold hiding `offset`, if there is one.
+    pub fn fold_at(&self, offset: usize) -> Option<Interval> {
+        let ix = match self.folds.binary_search_by_key(&offset, |fold| fold.start()) {
+            Ok(ix) => ix,
+            Err(0) => return None,
+            Err(ix) => ix - 1,
+        };
+        let fold = self.folds[ix];
+        if offset <= fold.end() {
+            Some(fold)
+        } else {
+            None
+        }
+    }
+
+    /// Returns `true` if the line starting at `line_start` is the first line
+    /// hidden by a fold, that is, if the line before it anchors a fold.
+    pub fn is_hidden_line_start(&self, line_start: usize) -> bool {
+        self.folds.binary_search_by_key(&line_start, |fold| fold.start()).is_ok()
+    }
+
+    /// Folds the lines touched by `iv`, other than the first, which anchors
+    /// the fold. Any folds this overlaps, including their anchors, are merged
+    /// into it. Returns `false` if nothing was folded, because `iv` touches a
+    /// single line.
+    pub fn fold(&mut self, text: &Rope, iv: Interval) -> bool {
+        let anchor = text.line_of_offset(iv.start());
+        let last = text.line_of_offset(max(iv.start(), iv.end().saturating_sub(1)));
+        if last <= anchor {
+            return false;
+        }
+        let anchor_start = text.offset_of_line(anchor);
+        let mut start = text.offset_of_line(anchor + 1);
+        let mut end = line_end(text, last);
+
+        let mut ix = 0;
+        while ix < self.folds.len() {
+            let fold = self.folds[ix];
+            if fold_anchor_start(text, fold) > end {
+                break;
+            }
+            if fold.end() >= anchor_start {
+                start = min(start, fold.start());
+                end = max(end, fold.end());
+                self.folds.remove(ix);
+            } else {
+                ix += 1;
+            }
+        }
+        self.folds.insert(ix, Interval::new(start, end));
+        true
+    }
+
+    /// Removes the folds whose lines, including their anchors, intersect
+    /// `iv`. Returns `true` if any fold was removed.
+    pub fn unfold(&mut self, text: &Rope, iv: Interval) -> bool {
+        let n_folds = self.folds.len();
+        self.folds
+            .retain(|&fold| fold_anchor_start(text, fold) >= iv.end() || fold.end() < iv.start());
+        self.folds.len() != n_folds
+    }
+
+    /// Removes every fold. Returns `true` if there were any.
+    pub fn unfold_all(&mut self) -> bool {
+        let had_folds = !self.folds.is_empty();
+        self.folds.clear();
+        had_folds
+    }
+
+    /// Updates the folds for an edit. Folds whose hidden text is touched by
+    /// the edit, or whose anchor loses its line ending, are unfolded, and the
+    /// others move with the text. Returns `true` if any fold was unfolded.
+    pub(crate) fn apply_delta(&mut self, delta: &RopeDelta) -> bool {
+        if self.folds.is_empty() {
+            return false;
+        }
+        let mut touched = vec![false; self.folds.len()];
+        let mut touch = |start: usize, end: usize, folds: &[Interval]| {
+            for (ix, fold) in folds.iter().enumerate() {
+                if start <= fold.end() && end >= fold.start() {
+                    touched[ix] = true;
+                }
+            }
+        };
+        let mut old_pos = 0;
+        for el in &delta.els {
+            match *el {
+                DeltaElement::Copy(beg, end) => {
+                    if beg > old_pos {
+                        // this includes deleting the line ending of an anchor
+                        touch(old_pos, beg, &self.folds);
+                    }
+                    old_pos = end;
+                }
+                // an insert at the end of the anchor, or at the start of the
+                // line after the fold, leaves it alone
+                DeltaElement::Insert(_) => touch(old_pos, old_pos, &self.folds),
+            }
+        }
+        if old_pos < delta.base_len {
+            touch(old_pos, delta.base_len, &self.folds);
+        }
+
+        let mut transformer = Transformer::new(delta);
+        let mut touched = touched.into_iter();
+        let n_folds = self.folds.len();
+        self.folds.retain(|_| !touched.next().unwrap());
+        for fold in &mut self.folds {
+            let start = transformer.transform(fold.start(), false);
+            let end = transformer.transform(fold.end(), false);
+            *fold = Interval::new(start, end);
+        }
+        self.folds.len() != n_folds
+    }
+}
+
+/// Returns the offset of the start of the line anchoring `fold`.
+fn fold_anchor_start(text: &Rope, fold: Interval) -> usize {
+    text.offset_of_line(text.line_of_offset(fold.start() - 1))
+}
+
+/// Returns the offset of the end of `line`, before its line ending.
+fn line_end(text: &Rope, line: usize) -> usize {
+    if line < text.measure::<LinesMetric>() {
+        let next_start = text.offset_of_line(line + 1);
+        text.prev_grapheme_offset(next_start).unwrap_or(next_start)
+    } else {
+        text.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use xi_rope::DeltaBuilder;
+
+    fn edit(folds: &mut Folds, text: &mut Rope, iv: Interval, new: &str) -> bool {
+        let mut builder = DeltaBuilder::new(text.len());
+        builder.replace(iv, new.into());
+        let delta = builder.build();
+        *text = delta.apply(text);
+        folds.apply_delta(&delta)
+    }
+
+    fn folds(folds: &Folds) -> Vec<(usize, usize)> {
+        folds.iter().map(|fold| (fold.start(), fold.end())).collect()
+    }
+
+    #[test]
+    fn fold_lines() {
+        let text = Rope::from("zero\none\ntwo\nthree\nfour");
+        let mut f = Folds::default();
+        // a single line can't be folded
+        assert!(!f.fold(&text, Interval::new(5, 8)));
+        // an interval ending at a line start doesn't include that line
+        assert!(f.fold(&text, Interval::new(0, 9)));
+        assert_eq!(folds(&f), vec![(5, 8)]);
+        assert_eq!(f.fold_at(4), None);
+        assert_eq!(f.fold_at(5), Some(Interval::new(5, 8)));
+        assert_eq!(f.fold_at(8), Some(Interval::new(5, 8)));
+        assert_eq!(f.fold_at(9), None);
+        assert!(f.is_hidden_line_start(5));
+        assert!(!f.is_hidden_line_start(0));
+
+        // the last line, without a line ending
+        assert!(f.fold(&text, Interval::new(13, 20)));
+        assert_eq!(folds(&f), vec![(5, 8), (19, 23)]);
+        assert_eq!(f.fold_at(23), Some(Interval::new(19, 23)));
+    }
+
+    #[test]
+    fn overlapping_folds_merge() {
+        let text = Rope::from("0\n1\n2\n3\n4\n5\n6\n");
+        let mut f = Folds::default();
+        f.fold(&text, Interval::new(2, 6));
+        f.fold(&text, Interval::new(10, 14));
+        assert_eq!(folds(&f), vec![(4, 5), (12, 13)]);
+        // folding the anchor of a fold merges it in
+        f.fold(&text, Interval::new(0, 4));
+        assert_eq!(folds(&f), vec![(2, 5), (12, 13)]);
+        // as does folding some of its hidden lines
+        f.fold(&text, Interval::new(4, 11));
+        assert_eq!(folds(&f), vec![(2, 13)]);
+
+        assert!(f.unfold(&text, Interval::new(0, 1)));
+        assert!(f.is_empty());
+        f.fold(&text, Interval::new(2, 6));
+        assert!(!f.unfold(&text, Interval::new(6, 8)));
+        assert!(f.unfold_all());
+        assert!(!f.unfold_all());
+    }
+
+    #[test]
+    fn edits_move_or_unfold() {
+        let mut text = Rope::from("zero\none\ntwo\nthree\n");
+        let mut f = Folds::default();
+        f.fold(&text, Interval::new(0, 9));
+        assert_eq!(folds(&f), vec![(5, 8)]);
+
+        // edits before the fold, and at the end of its anchor, move it
+        assert!(!edit(&mut f, &mut text, Interval::new(0, 0), "0"));
+        assert!(!edit(&mut f, &mut text, Interval::new(5, 5), "!"));
+        assert_eq!(folds(&f), vec![(7, 10)]);
+        // as do edits after it
+        assert!(!edit(&mut f, &mut text, Interval::new(11, 14), ""));
+        assert_eq!(folds(&f), vec![(7, 10)]);
+
+        // deleting the line ending of the anchor unfolds it
+        assert!(edit(&mut f, &mut text, Interval::new(6, 7), ""));
+        assert!(f.is_empty());
+
+        // as does inserting into the hidden lines, even an empty one
+        f.fold(&text, Interval::new(0, 11));
+        assert_eq!(folds(&f), vec![(10, 10)]);
+        assert!(edit(&mut f, &mut text, Interval::new(10, 10), "\n"));
+        assert!(f.is_empty());
+    }
+}
@@ -45,6 +45,7 @@ extern crate notify;
 extern crate syntect;
 extern crate time;
 extern crate toml;
+extern crate unicode_segmentation;
 
 extern crate xi_rope;
 extern crate xi_rpc;
@@ -66,15 +67,19 @@ use ledger_includes::*;
 
 pub mod annotations;
 pub mod backspace;
+pub mod bookmarks;
 pub mod client;
+pub mod composition;
 pub mod config;
 pub mod core;
+pub mod edit_latency;
 pub mod edit_ops;
 pub mod edit_types;
 pub mod editor;
 pub mod event_context;
 pub mod file;
 pub mod find;
+pub mod folds;
 #[cfg(feature = "ledger")]
 pub mod fuchsia;
 pub mod index_set;
@@ -83,13 +88,16 @@ pub mod line_cache_shadow;
 pub mod line_ending;
 pub mod line_offset;
 pub mod linewrap;
+pub mod maintenance;
 pub mod movement;
 pub mod plugins;
 pub mod recorder;
+pub mod render_journal;
 pub mod selection;
 pub mod styles;
 pub mod syntax;
 pub mod tabs;
+pub mod truncate;
 pub mod view;
 #[cfg(feature = "notify")]
 pub mod watcher;
@@ -110,4 +118,4 @@ pub use crate::plugins::rpc as plugin_rpc;
 pub use crate::plugins::PluginPid;
 pub use crate::syntax::{LanguageDefinition, LanguageId};
 pub use crate::tabs::test_helpers;
-pub use crate::tabs::{BufferId, BufferIdentifier, ViewId};
+pub use crate::tabs::{BufferId, BufferIdentifier, ParseIdError, ViewId};
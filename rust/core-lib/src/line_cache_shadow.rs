@@ -199,6 +199,10 @@ impl LineCacheShadow {
         &self.spans
     }
 
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
     pub fn iter_with_plan<'a>(&'a self, plan: &'a RenderPlan) -> PlanIterator<'a> {
         PlanIterator {
             lc_shadow: self,
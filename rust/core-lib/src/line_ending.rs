@@ -20,7 +20,8 @@ use memchr::memchr2;
 use xi_rope::Rope;
 
 /// An enumeration of valid line endings
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum LineEnding {
     CrLf, // DOS style, \r\n
     Lf,   // *nix style, \n
@@ -31,6 +32,14 @@ pub enum LineEnding {
 pub struct MixedLineEndingError;
 
 impl LineEnding {
+    /// The line ending itself, as used for the `line_ending` config item.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LineEnding::CrLf => "\r\n",
+            LineEnding::Lf => "\n",
+        }
+    }
+
     /// Breaks a rope down into chunks, and checks each chunk for line endings
     pub fn parse(rope: &Rope) -> Result<Option<Self>, MixedLineEndingError> {
         let mut crlf = false;
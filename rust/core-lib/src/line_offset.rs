@@ -16,7 +16,7 @@
 
 use std::ops::Range;
 
-use xi_rope::Rope;
+use xi_rope::{Interval, Rope};
 
 use crate::linewrap::Lines;
 use crate::selection::SelRegion;
@@ -73,6 +73,32 @@ pub trait LineOffset {
         offset
     }
 
+    /// Returns the line and the display column of `offset`, where tab characters
+    /// advance the column to the next multiple of `tab_width` and every other
+    /// codepoint occupies a single column.
+    fn offset_to_line_display_col(
+        &self,
+        text: &Rope,
+        offset: usize,
+        tab_width: usize,
+    ) -> (usize, usize) {
+        let line = self.line_of_offset(text, offset);
+        let line_start = self.offset_of_line(text, line);
+        let col = text.slice_to_cow(line_start..offset).chars().fold(0, |col, c| match c {
+            '\t' => col + tab_width - (col % tab_width),
+            _ => col + 1,
+        });
+        (line, col)
+    }
+
+    /// Returns the folded region containing `offset`, if there is one. The
+    /// end of the region is folded as well; see [`Folds`].
+    ///
+    /// [`Folds`]: ../folds/struct.Folds.html
+    fn folded_range(&self, _offset: usize) -> Option<Interval> {
+        None
+    }
+
     /// Get the line range of a selected region.
     fn get_line_range(&self, text: &Rope, region: &SelRegion) -> Range<usize> {
         let (first_line, _) = self.offset_to_line_col(text, region.min());
@@ -111,3 +137,19 @@ impl LineOffset for Lines {
         self.visual_line_of_offset(text, offset)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display_col_expands_tabs() {
+        let text = Rope::from("a\tb\n\t\tc\n  \td");
+        assert_eq!(LogicalLines.offset_to_line_display_col(&text, 2, 4), (0, 4));
+        assert_eq!(LogicalLines.offset_to_line_display_col(&text, 2, 8), (0, 8));
+        assert_eq!(LogicalLines.offset_to_line_display_col(&text, 6, 4), (1, 8));
+        assert_eq!(LogicalLines.offset_to_line_display_col(&text, 6, 2), (1, 4));
+        assert_eq!(LogicalLines.offset_to_line_display_col(&text, 11, 4), (2, 4));
+        assert_eq!(LogicalLines.offset_to_line_display_col(&text, 11, 3), (2, 3));
+    }
+}
@@ -17,7 +17,7 @@
 use std::cmp::Ordering;
 use std::ops::Range;
 
-use xi_rope::breaks::{BreakBuilder, Breaks, BreaksInfo, BreaksMetric};
+use xi_rope::breaks::{Breaks, BreaksInfo, BreaksMetric};
 use xi_rope::spans::Spans;
 use xi_rope::{Cursor, Interval, LinesMetric, Rope, RopeDelta, RopeInfo};
 use xi_trace::trace_block;
@@ -25,7 +25,7 @@ use xi_unicode::LineBreakLeafIter;
 
 use crate::client::Client;
 use crate::styles::{Style, N_RESERVED_STYLES};
-use crate::width_cache::{CodepointMono, Token, WidthCache, WidthMeasure};
+use crate::width_cache::{ColumnMono, Token, WidthCache, WidthMeasure};
 
 /// The visual width of the buffer for the purpose of word wrapping.
 #[derive(Clone, Copy, Debug, PartialEq)]
@@ -33,10 +33,11 @@ pub(crate) enum WrapWidth {
     /// No wrapping in effect.
     None,
 
-    /// Width in bytes (utf-8 code units).
+    /// Width in bytes (utf-8 code units), with tab characters `tab_width`
+    /// columns wide.
     ///
     /// Only works well for ASCII, will probably not be maintained long-term.
-    Bytes(usize),
+    Bytes { cols: usize, tab_width: usize },
 
     /// Width in px units, requiring measurement by the front-end.
     Width(f64),
@@ -52,7 +53,7 @@ impl WrapWidth {
     fn differs_in_kind(self, other: WrapWidth) -> bool {
         use self::WrapWidth::*;
         match (self, other) {
-            (None, None) | (Bytes(_), Bytes(_)) | (Width(_), Width(_)) => false,
+            (None, None) | (Bytes { .. }, Bytes { .. }) | (Width(_), Width(_)) => false,
             _else => true,
         }
     }
@@ -68,6 +69,10 @@ pub(crate) struct Lines {
     wrap: WrapWidth,
     /// Aka the 'frontier'; ranges of lines that still need to be wrapped.
     work: Vec<Task>,
+    /// The task scheduled by the most recent edit, and the offset at which
+    /// that edit ended. Wrapping within this task can stop as soon as it
+    /// produces a break past the edit that matches a previously computed one.
+    converge_after: Option<(Task, usize)>,
 }
 
 pub(crate) struct VisualLine {
@@ -102,13 +107,14 @@ struct WrapSummary {
     inval_count: usize,
     /// The total number of new (hard + soft) breaks in the wrapped region.
     new_count: usize,
-    /// The number of new soft breaks.
-    new_soft: usize,
+    /// The offset at which wrapping stopped.
+    end: usize,
 }
 
 impl Lines {
     pub(crate) fn set_wrap_width(&mut self, text: &Rope, wrap: WrapWidth) {
         self.work.clear();
+        self.converge_after = None;
         self.add_task(0..text.len());
         if self.breaks.is_empty() || self.wrap.differs_in_kind(wrap) {
             // we keep breaks while resizing, for more efficient invalidation
@@ -154,6 +160,12 @@ impl Lines {
         self.wrap == WrapWidth::None || self.work.is_empty()
     }
 
+    /// Returns `true` if lines are wrapped, in which case visual lines can
+    /// differ from logical lines.
+    pub(crate) fn is_wrapping(&self) -> bool {
+        self.wrap != WrapWidth::None
+    }
+
     /// Returns `true` if this interval is part of an incomplete task.
     pub(crate) fn interval_needs_wrap(&self, iv: Interval) -> bool {
         self.work.iter().any(|t| !t.intersect(iv).is_empty())
@@ -281,6 +293,45 @@ impl Lines {
         }
     }
 
+    /// Updates breaks after the interval `iv` of the previous text has been
+    /// replaced by `new_len` bytes, and schedules the affected region to be
+    /// rewrapped.
+    ///
+    /// Rather than rewrapping the whole paragraph containing the edit, the
+    /// scheduled task starts at the earliest visual line whose breaks can be
+    /// affected by it, and stops as soon as the new breaks converge with the
+    /// previously computed ones. Returns the offset at which rewrapping will
+    /// start.
+    pub(crate) fn interval_changed(&mut self, text: &Rope, iv: Interval, new_len: usize) -> usize {
+        // update soft breaks, adding empty spans in the edited region
+        self.breaks.edit(iv, Breaks::new_no_break(new_len));
+        self.patchup_tasks(iv, new_len);
+        self.converge_after = None;
+
+        let para_start = text.offset_of_line(text.line_of_offset(iv.start));
+        if self.wrap == WrapWidth::None {
+            return para_start;
+        }
+
+        // Where a line breaks depends on the first word of the following line,
+        // including whether that word ends at the start of the line after it;
+        // the edit can only affect lines whose following word ends at or
+        // after the start of the edit.
+        let edit_line = self.visual_line_of_offset(text, iv.start);
+        let lines_back =
+            if self.offset_of_visual_line(text, edit_line) == iv.start { 3 } else { 2 };
+        let start_line = edit_line.saturating_sub(lines_back);
+        let start = self.offset_of_visual_line(text, start_line).max(para_start);
+        let next_hard_break = text.offset_of_line(text.line_of_offset(iv.start + new_len) + 1);
+
+        let task = Interval::new(start, next_hard_break);
+        if !self.interval_needs_wrap(task) {
+            self.converge_after = Some((task, iv.start + new_len));
+        }
+        self.add_task(task);
+        start
+    }
+
     /// Updates breaks after an edit. Returns `InvalLines`, for minimal invalidation,
     /// when possible.
     pub(crate) fn after_edit(
@@ -294,55 +345,44 @@ impl Lines {
     ) -> Option<InvalLines> {
         let (iv, newlen) = delta.summary();
 
-        let logical_start_line = text.line_of_offset(iv.start);
-        let old_logical_end_line = old_text.line_of_offset(iv.end) + 1;
-        let new_logical_end_line = text.line_of_offset(iv.start + newlen) + 1;
-        let old_logical_end_offset = old_text.offset_of_line(old_logical_end_line);
-        let old_hard_count = old_logical_end_line - logical_start_line;
-        let new_hard_count = new_logical_end_line - logical_start_line;
-
-        //TODO: we should be able to avoid wrapping the whole para in most cases,
-        // but the logic is trickier.
-        let prev_break = text.offset_of_line(logical_start_line);
-        let next_hard_break = text.offset_of_line(new_logical_end_line);
-
-        // count the soft breaks in the region we will rewrap, before we update them.
-        let inval_soft = self.breaks.count::<BreaksMetric>(old_logical_end_offset)
-            - self.breaks.count::<BreaksMetric>(prev_break);
-
-        // update soft breaks, adding empty spans in the edited region
-        let mut builder = BreakBuilder::new();
-        builder.add_no_break(newlen);
-        self.breaks.edit(iv, builder.build());
-        self.patchup_tasks(iv, newlen);
-
         if self.wrap == WrapWidth::None {
+            let logical_start_line = text.line_of_offset(iv.start);
+            let old_logical_end_line = old_text.line_of_offset(iv.end) + 1;
+            let new_logical_end_line = text.line_of_offset(iv.start + newlen) + 1;
+            self.interval_changed(text, iv, newlen);
             return Some(InvalLines {
                 start_line: logical_start_line,
-                inval_count: old_hard_count,
-                new_count: new_hard_count,
+                inval_count: old_logical_end_line - logical_start_line,
+                new_count: new_logical_end_line - logical_start_line,
             });
         }
 
-        let new_task = prev_break..next_hard_break;
-        self.add_task(new_task);
+        let old_breaks = self.breaks.clone();
+        self.interval_changed(text, iv, newlen);
 
         // possible if the whole buffer is deleted, e.g
-        if !self.work.is_empty() {
-            let summary = self.do_wrap_task(text, width_cache, client, visible_lines, None);
-            let WrapSummary { start_line, new_soft, .. } = summary;
-            // if we haven't converged after this update we can't do minimal invalidation
-            // because we don't have complete knowledge of the new breaks state.
-            if self.is_converged() {
-                let inval_count = old_hard_count + inval_soft;
-                let new_count = new_hard_count + new_soft;
-                Some(InvalLines { start_line, inval_count, new_count })
-            } else {
-                None
-            }
-        } else {
-            None
+        if self.work.is_empty() {
+            return None;
         }
+
+        let summary = self.do_wrap_task(text, width_cache, client, visible_lines, None);
+        // if we haven't converged after this update we can't do minimal invalidation
+        // because we don't have complete knowledge of the new breaks state.
+        if !self.is_converged() {
+            return None;
+        }
+
+        // The wrapped region always extends past the edit, and is the same
+        // before the edit in both the old and new coordinate spaces.
+        let WrapSummary { start_line, end, .. } = summary;
+        let start = self.offset_of_visual_line(text, start_line);
+        let old_end = end + iv.size() - newlen;
+        let old_start_line = merged_line_of_offset(old_text, &old_breaks, start);
+        let old_end_line = merged_line_of_offset(old_text, &old_breaks, old_end);
+        let new_end_line = merged_line_of_offset(text, &self.breaks, end);
+        let inval_count = old_end_line - old_start_line + (old_end == old_text.len()) as usize;
+        let new_count = new_end_line - start_line + (end == text.len()) as usize;
+        Some(InvalLines { start_line, inval_count, new_count })
     }
 
     fn do_wrap_task(
@@ -362,13 +402,21 @@ impl Lines {
         let visible_off = cursor.offset_of_line(visible_lines.start);
         let logical_off = text.offset_of_line(text.line_of_offset(visible_off));
 
-        // task.start is a hard break; task.end is a boundary or EOF.
+        // task.start is a hard or soft break; task.end is a boundary or EOF.
         let task = self.get_next_task(logical_off).unwrap();
         cursor.set_offset(task.start);
         debug_assert_eq!(cursor.offset, task.start, "task_start must be valid offset");
 
+        // columnar widths depend on the buffer's tab width, so they are kept
+        // out of the cache shared by all buffers.
+        let column_measure;
+        let mut column_cache;
         let mut ctx = match self.wrap {
-            Bytes(b) => RewrapCtx::new(text, &CodepointMono, b as f64, width_cache, task.start),
+            Bytes { cols, tab_width } => {
+                column_measure = ColumnMono { tab_width };
+                column_cache = WidthCache::new();
+                RewrapCtx::new(text, &column_measure, cols as f64, &mut column_cache, task.start)
+            }
             Width(w) => RewrapCtx::new(text, client, w, width_cache, task.start),
             None => unreachable!(),
         };
@@ -378,7 +426,15 @@ impl Lines {
         // always wrap at least a screen worth of lines (unless we converge earlier)
         let batch_size = max_lines.max(visible_lines.end - visible_lines.start);
 
-        let mut builder = BreakBuilder::new();
+        // breaks past this point that match the existing breaks end the task
+        let converge_after = self
+            .converge_after
+            .filter(|(edit_task, _)| edit_task.start <= task.start && task.end <= edit_task.end)
+            .map(|(_, off)| off);
+        let mut converged = false;
+
+        // soft breaks found, relative to the start of the task
+        let mut soft_breaks = Vec::new();
         let mut lines_wrapped = 0;
         let mut pos = task.start;
         let mut old_next_maybe = cursor.next();
@@ -393,25 +449,26 @@ impl Lines {
                 }
 
                 let is_hard = cursor.offset == new_next && cursor.is_hard_break();
-                if is_hard {
-                    builder.add_no_break(new_next - pos);
-                } else {
-                    builder.add_break(new_next - pos);
+                if !is_hard {
+                    soft_breaks.push(new_next - task.start);
                 }
                 lines_wrapped += 1;
                 pos = new_next;
-                if pos == task.end || (lines_wrapped > batch_size && is_hard) {
+                converged = converge_after.map(|off| pos >= off).unwrap_or(false)
+                    && cursor.offset == new_next
+                    && !is_hard;
+                if pos == task.end || converged || (lines_wrapped > batch_size && is_hard) {
                     break;
                 }
             } else {
                 // EOF
-                builder.add_no_break(text.len() - pos);
+                pos = text.len();
                 break;
             }
         }
 
-        let breaks = builder.build();
-        let end = task.start + breaks.len();
+        let end = pos;
+        let breaks = Breaks::from_iter(end - task.start, soft_breaks);
 
         // this is correct *only* when an edit has not occured.
         let inval_soft =
@@ -424,10 +481,20 @@ impl Lines {
         let new_count = new_soft + hard_count;
 
         let iv = Interval::new(task.start, end);
-        self.breaks.edit(iv, breaks);
-        self.update_tasks_after_wrap(iv);
+        self.breaks.splice(iv, breaks);
+        if converged {
+            // the remainder of the task already has the correct breaks.
+            self.update_tasks_after_wrap(task);
+        } else {
+            self.update_tasks_after_wrap(iv);
+        }
+        if let Some((edit_task, _)) = self.converge_after {
+            if !self.interval_needs_wrap(edit_task) {
+                self.converge_after = Option::None;
+            }
+        }
 
-        WrapSummary { start_line, inval_count, new_count, new_soft }
+        WrapSummary { start_line, inval_count, new_count, end }
     }
 
     pub fn logical_line_range(&self, text: &Rope, line: usize) -> (usize, usize) {
@@ -448,7 +515,7 @@ impl Lines {
 
     #[cfg(test)]
     fn rewrap_all(&mut self, text: &Rope, client: &Client, width_cache: &mut WidthCache) {
-        if !self.is_converged() {
+        while !self.is_converged() {
             self.do_wrap_task(text, width_cache, client, 0..10, Some(usize::max_value()));
         }
     }
@@ -777,12 +844,15 @@ fn merged_line_of_offset(text: &Rope, soft: &Breaks, offset: usize) -> usize {
 mod tests {
     use super::*;
     use std::borrow::Cow;
+    use xi_rope::breaks::BreakBuilder;
+    use xi_rope::test_helpers::Lcg;
+    use xi_rope::DeltaBuilder;
     use xi_rpc::test_utils::DummyPeer;
 
     fn make_lines(text: &Rope, width: f64) -> Lines {
         let client = Client::new(Box::new(DummyPeer));
         let mut width_cache = WidthCache::new();
-        let wrap = WrapWidth::Bytes(width as usize);
+        let wrap = WrapWidth::Bytes { cols: width as usize, tab_width: 1 };
         let mut lines = Lines::for_testing(text, wrap);
         lines.rewrap_all(text, &client, &mut width_cache);
         lines
@@ -812,6 +882,21 @@ mod tests {
         assert_eq!(result, vec!["every ", "wordthing ", "should ", "getits ", "ow\n", "",]);
     }
 
+    #[test]
+    fn column_breaks_tab_width() {
+        let text: Rope = "\tab cd ef".into();
+        let client = Client::new(Box::new(DummyPeer));
+        let mut width_cache = WidthCache::new();
+        let wrap = WrapWidth::Bytes { cols: 10, tab_width: 8 };
+        let mut lines = Lines::for_testing(&text, wrap);
+        lines.rewrap_all(&text, &client, &mut width_cache);
+        assert_eq!(render_breaks(&text, &lines), vec!["\t", "ab cd ef"]);
+
+        // with narrower tabs, the line fits
+        assert_eq!(debug_breaks(&text, 10.0), vec!["\tab cd ef"]);
+        assert_eq!(width_cache.len(), 0);
+    }
+
     #[test]
     fn soft_before_hard() {
         let text: Rope = "create abreak between THESE TWO\nwords andbreakcorrectlyhere\nplz".into();
@@ -880,6 +965,63 @@ mod tests {
         assert_eq!(result, vec!["hello"]);
     }
 
+    fn random_text(rng: &mut Lcg, words: usize) -> String {
+        const PIECES: &[&str] = &["a", "bb", "ccc", "dddd ", " ", "  ", "eeeeeeeeeee", "\n", "f-g"];
+        (0..words).map(|_| PIECES[rng.next(PIECES.len())]).collect()
+    }
+
+    #[test]
+    fn incremental_rewrap_matches_full_rewrap() {
+        let client = Client::new(Box::new(DummyPeer));
+        let mut width_cache = WidthCache::new();
+        let mut rng = Lcg(42);
+        for &width in &[1., 4., 10.] {
+            let mut text: Rope = random_text(&mut rng, 200).into();
+            let mut lines = make_lines(&text, width);
+            for _ in 0..200 {
+                let start = rng.next(text.len() + 1);
+                let end = (start + rng.next(12)).min(text.len());
+                let words = rng.next(4);
+                let insert = random_text(&mut rng, words);
+                let mut builder = DeltaBuilder::new(text.len());
+                builder.replace(start..end, insert.into());
+                let delta = builder.build();
+                let new_text = delta.apply(&text);
+
+                let old_count = lines.iter_lines(&text, 0).count();
+                let inval =
+                    lines.after_edit(&new_text, &text, &delta, &mut width_cache, &client, 0..10);
+                if let Some(InvalLines { inval_count, new_count, .. }) = inval {
+                    let count = lines.iter_lines(&new_text, 0).count();
+                    assert_eq!(count + inval_count, old_count + new_count);
+                }
+                lines.rewrap_all(&new_text, &client, &mut width_cache);
+                assert_eq!(render_breaks(&new_text, &lines), debug_breaks(&new_text, width));
+                text = new_text;
+            }
+        }
+    }
+
+    #[test]
+    fn incremental_rewrap_is_local() {
+        let client = Client::new(Box::new(DummyPeer));
+        let mut width_cache = WidthCache::new();
+        // lines hold four words with room to spare, so a one byte insertion
+        // doesn't push a word onto the next line.
+        let text: Rope = "word ".repeat(10_000).into();
+        let mut lines = make_lines(&text, 22.);
+
+        let mut builder = DeltaBuilder::new(text.len());
+        builder.replace(25_000..25_000, "x".into());
+        let delta = builder.build();
+        let new_text = delta.apply(&text);
+        let inval = lines.after_edit(&new_text, &text, &delta, &mut width_cache, &client, 0..10);
+        let InvalLines { start_line, inval_count, new_count } = inval.unwrap();
+        assert!(start_line > 1000);
+        assert!(inval_count < 5 && new_count < 5);
+        assert_eq!(render_breaks(&new_text, &lines), debug_breaks(&new_text, 22.));
+    }
+
     #[test]
     fn merged_offset() {
         let text: Rope = "a quite\nshort text".into();
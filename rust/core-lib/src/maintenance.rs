@@ -0,0 +1,393 @@
+// Copyright 2020 The xi-editor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Maintenance work that core does while the user is idle.
+//!
+//! Tasks are registered by name, and run one slice at a time, when no
+//! user-facing work is pending and the user hasn't typed for a while. Each
+//! slice should stop once `MaintenanceCtx::out_of_time` returns `true`.
+
+use std::cell::RefCell;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+
+use crate::editor::Editor;
+use crate::tabs::BufferId;
+use crate::width_cache::WidthCache;
+
+/// How long a slice of maintenance work may take.
+pub(crate) const MAINTENANCE_BUDGET: Duration = Duration::from_millis(4);
+
+/// How long after the last input maintenance waits before running.
+pub(crate) const TYPING_PAUSE: Duration = Duration::from_millis(500);
+
+/// Edit history is collected this often, if it has changed.
+const HISTORY_GC_INTERVAL: Duration = Duration::from_secs(2);
+
+/// The width cache is checked against its limit this often.
+const WIDTH_CACHE_TRIM_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The number of measured strings the width cache may hold before it is
+/// cleared.
+const MAX_WIDTH_CACHE_ITEMS: usize = 50_000;
+
+/// Which of the due tasks runs first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TaskPriority {
+    Low,
+    Normal,
+    High,
+}
+
+/// What a task reports after a slice.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaskStatus {
+    /// The task is finished; it runs again once its interval has passed.
+    Done,
+    /// The task has more to do, and runs again at the next opportunity.
+    MoreWork,
+    /// The task had nothing to do. It runs again once its interval has
+    /// passed, and the next due task runs in its place.
+    Skip,
+}
+
+/// The state a maintenance task can work on.
+pub struct MaintenanceCtx<'a> {
+    pub(crate) editors: &'a BTreeMap<BufferId, RefCell<Editor>>,
+    pub(crate) width_cache: &'a RefCell<WidthCache>,
+    deadline: Instant,
+}
+
+impl<'a> MaintenanceCtx<'a> {
+    /// Returns `true` if the slice has used up its budget, and the task
+    /// should return.
+    pub fn out_of_time(&self) -> bool {
+        Instant::now() >= self.deadline
+    }
+}
+
+pub type MaintenanceTask = Box<dyn FnMut(&mut MaintenanceCtx) -> TaskStatus + Send>;
+
+struct Task {
+    name: String,
+    priority: TaskPriority,
+    interval: Duration,
+    next_run: Instant,
+    run: MaintenanceTask,
+}
+
+/// The registered maintenance tasks, and when each is next due.
+pub(crate) struct Maintenance {
+    /// In order of registration, which breaks ties in priority.
+    tasks: Vec<Task>,
+    budget: Duration,
+    typing_pause: Duration,
+    last_input: Option<Instant>,
+    /// When the runner was last scheduled for, if it hasn't run since.
+    scheduled: Option<Instant>,
+}
+
+impl Maintenance {
+    pub(crate) fn new(budget: Duration, typing_pause: Duration) -> Maintenance {
+        Maintenance { tasks: Vec::new(), budget, typing_pause, last_input: None, scheduled: None }
+    }
+
+    /// Registers a task, replacing any task of the same name. It first runs
+    /// at the next opportunity.
+    pub(crate) fn register(
+        &mut self,
+        name: &str,
+        priority: TaskPriority,
+        interval_hint: Duration,
+        run: MaintenanceTask,
+    ) {
+        self.cancel(name);
+        let next_run = Instant::now();
+        self.tasks.push(Task {
+            name: name.to_owned(),
+            priority,
+            interval: interval_hint,
+            next_run,
+            run,
+        });
+    }
+
+    /// Removes the task called `name`. Returns `true` if there was one.
+    pub(crate) fn cancel(&mut self, name: &str) -> bool {
+        let n_tasks = self.tasks.len();
+        self.tasks.retain(|task| task.name != name);
+        self.tasks.len() != n_tasks
+    }
+
+    /// Records user input, which pauses maintenance for a while.
+    pub(crate) fn note_input(&mut self, now: Instant) {
+        self.last_input = Some(now);
+    }
+
+    /// Returns when the next task can run, if there are any tasks.
+    pub(crate) fn next_due(&self) -> Option<Instant> {
+        let next_run = self.tasks.iter().map(|task| task.next_run).min()?;
+        match self.last_input {
+            Some(input) => Some(next_run.max(input + self.typing_pause)),
+            None => Some(next_run),
+        }
+    }
+
+    /// Returns when the runner should next be scheduled, if it isn't already
+    /// scheduled for that time or earlier.
+    pub(crate) fn schedule(&mut self) -> Option<Instant> {
+        let due = self.next_due()?;
+        if self.scheduled.map_or(false, |scheduled| scheduled <= due) {
+            return None;
+        }
+        self.scheduled = Some(due);
+        Some(due)
+    }
+
+    /// Notes that the scheduled run has happened, or was passed up.
+    pub(crate) fn unschedule(&mut self) {
+        self.scheduled = None;
+    }
+
+    /// Runs one slice of the most urgent due task, and of any task it is
+    /// skipped for. Tasks only check their budget between units of work, so
+    /// they may go over it a little; a task that takes more than twice its
+    /// budget is put off for its interval, whatever it reports.
+    pub(crate) fn run_slice(
+        &mut self,
+        now: Instant,
+        editors: &BTreeMap<BufferId, RefCell<Editor>>,
+        width_cache: &RefCell<WidthCache>,
+    ) {
+        if self.last_input.map_or(false, |input| now < input + self.typing_pause) {
+            return;
+        }
+        let mut due =
+            (0..self.tasks.len()).filter(|&ix| self.tasks[ix].next_run <= now).collect::<Vec<_>>();
+        // stable, so that tasks of the same priority run in order
+        due.sort_by_key(|&ix| Reverse(self.tasks[ix].priority));
+
+        for ix in due {
+            let start = Instant::now();
+            let mut ctx = MaintenanceCtx { editors, width_cache, deadline: start + self.budget };
+            let task = &mut self.tasks[ix];
+            let status = (task.run)(&mut ctx);
+            let finish = Instant::now();
+            let elapsed = finish.duration_since(start);
+            if elapsed > self.budget * 2 {
+                warn!("maintenance task {} took {:?}, over its budget", task.name, elapsed);
+                task.next_run = finish + task.interval;
+                return;
+            }
+            match status {
+                TaskStatus::MoreWork => task.next_run = finish,
+                TaskStatus::Done | TaskStatus::Skip => task.next_run = finish + task.interval,
+            }
+            if status != TaskStatus::Skip {
+                return;
+            }
+        }
+    }
+
+    /// Registers the maintenance core does on its own behalf.
+    pub(crate) fn register_core_tasks(&mut self) {
+        self.register("history-gc", TaskPriority::Normal, HISTORY_GC_INTERVAL, history_gc_task());
+        self.register(
+            "width-cache-trim",
+            TaskPriority::Low,
+            WIDTH_CACHE_TRIM_INTERVAL,
+            Box::new(trim_width_cache),
+        );
+    }
+}
+
+/// Collects the edit history of each buffer that was edited since its
+/// history was last collected.
+fn history_gc_task() -> MaintenanceTask {
+    let mut collected_revs: HashMap<BufferId, u64> = HashMap::new();
+    Box::new(move |ctx: &mut MaintenanceCtx| {
+        collected_revs.retain(|id, _| ctx.editors.contains_key(id));
+        let mut status = TaskStatus::Skip;
+        for (id, editor) in ctx.editors {
+            let rev = editor.borrow().get_head_rev_token();
+            if collected_revs.get(id) == Some(&rev) {
+                continue;
+            }
+            if ctx.out_of_time() {
+                return TaskStatus::MoreWork;
+            }
+            editor.borrow_mut().gc_history();
+            collected_revs.insert(*id, rev);
+            status = TaskStatus::Done;
+        }
+        status
+    })
+}
+
+/// Clears the width cache if it has grown too large.
+fn trim_width_cache(ctx: &mut MaintenanceCtx) -> TaskStatus {
+    let len = ctx.width_cache.borrow().len();
+    if len <= MAX_WIDTH_CACHE_ITEMS {
+        return TaskStatus::Skip;
+    }
+    debug!("clearing {} items from width cache", len);
+    ctx.width_cache.replace(WidthCache::new());
+    TaskStatus::Done
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    const BUDGET: Duration = Duration::from_millis(20);
+    const PAUSE: Duration = Duration::from_millis(500);
+    const INTERVAL: Duration = Duration::from_secs(60);
+
+    struct Runner {
+        maintenance: Maintenance,
+        editors: BTreeMap<BufferId, RefCell<Editor>>,
+        width_cache: RefCell<WidthCache>,
+        log: Arc<Mutex<Vec<&'static str>>>,
+    }
+
+    impl Runner {
+        fn new() -> Runner {
+            Runner {
+                maintenance: Maintenance::new(BUDGET, PAUSE),
+                editors: BTreeMap::new(),
+                width_cache: RefCell::new(WidthCache::new()),
+                log: Arc::new(Mutex::new(Vec::new())),
+            }
+        }
+
+        /// Registers a task that logs its name and reports `status`.
+        fn register(&mut self, name: &'static str, priority: TaskPriority, status: TaskStatus) {
+            let log = self.log.clone();
+            let task = Box::new(move |_: &mut MaintenanceCtx| {
+                log.lock().unwrap().push(name);
+                status
+            });
+            self.maintenance.register(name, priority, INTERVAL, task);
+        }
+
+        fn run(&mut self, now: Instant) -> Vec<&'static str> {
+            self.maintenance.run_slice(now, &self.editors, &self.width_cache);
+            self.log.lock().unwrap().drain(..).collect()
+        }
+    }
+
+    #[test]
+    fn priority_ordering() {
+        let mut runner = Runner::new();
+        runner.register("low", TaskPriority::Low, TaskStatus::Done);
+        runner.register("high", TaskPriority::High, TaskStatus::Done);
+        runner.register("normal", TaskPriority::Normal, TaskStatus::MoreWork);
+        runner.register("skipped", TaskPriority::High, TaskStatus::Skip);
+
+        // tasks of the same priority run in the order they were registered
+        assert_eq!(runner.run(Instant::now()), vec!["high"]);
+        // a skipping task gives up its slice to the next one
+        assert_eq!(runner.run(Instant::now()), vec!["skipped", "normal"]);
+        // a task with more work keeps its place
+        assert_eq!(runner.run(Instant::now()), vec!["normal"]);
+        runner.maintenance.cancel("normal");
+        assert_eq!(runner.run(Instant::now()), vec!["low"]);
+        assert!(runner.run(Instant::now()).is_empty());
+        // done and skipped tasks wait for their interval
+        assert_eq!(runner.run(Instant::now() + INTERVAL), vec!["high"]);
+    }
+
+    #[test]
+    fn budget_enforcement() {
+        let mut runner = Runner::new();
+        let units = Arc::new(Mutex::new(0));
+        let units_done = units.clone();
+        let task = Box::new(move |ctx: &mut MaintenanceCtx| {
+            let mut units = units_done.lock().unwrap();
+            while *units < 1000 {
+                if ctx.out_of_time() {
+                    return TaskStatus::MoreWork;
+                }
+                thread::sleep(Duration::from_millis(1));
+                *units += 1;
+            }
+            TaskStatus::Done
+        });
+        runner.maintenance.register("chunked", TaskPriority::Normal, INTERVAL, task);
+        runner.run(Instant::now());
+        // the slice was cut off, and the task runs again at the next chance
+        let after_one_slice = *units.lock().unwrap();
+        assert!(after_one_slice > 0 && after_one_slice < 1000);
+        assert!(runner.maintenance.next_due().unwrap() <= Instant::now());
+
+        // a task that ignores its budget is put off
+        runner.maintenance.cancel("chunked");
+        let slow = Box::new(|_: &mut MaintenanceCtx| {
+            thread::sleep(BUDGET * 3);
+            TaskStatus::MoreWork
+        });
+        runner.maintenance.register("slow", TaskPriority::High, INTERVAL, slow);
+        runner.register("next", TaskPriority::Low, TaskStatus::Done);
+        let start = Instant::now();
+        assert!(runner.run(start).is_empty());
+        assert_eq!(runner.run(Instant::now()), vec!["next"]);
+        assert!(runner.maintenance.next_due().unwrap() >= start + INTERVAL);
+    }
+
+    #[test]
+    fn pause_during_typing() {
+        let mut runner = Runner::new();
+        runner.register("task", TaskPriority::Normal, TaskStatus::MoreWork);
+        let typed = Instant::now();
+        runner.maintenance.note_input(typed);
+        assert!(runner.run(typed).is_empty());
+        assert!(runner.run(typed + PAUSE / 2).is_empty());
+        assert_eq!(runner.maintenance.next_due(), Some(typed + PAUSE));
+        assert_eq!(runner.run(typed + PAUSE), vec!["task"]);
+    }
+
+    #[test]
+    fn cancellation() {
+        let mut runner = Runner::new();
+        runner.register("a", TaskPriority::Normal, TaskStatus::MoreWork);
+        runner.register("b", TaskPriority::Normal, TaskStatus::MoreWork);
+        assert!(runner.maintenance.cancel("a"));
+        assert!(!runner.maintenance.cancel("a"));
+        assert_eq!(runner.run(Instant::now()), vec!["b"]);
+        assert!(runner.maintenance.cancel("b"));
+        assert_eq!(runner.maintenance.next_due(), None);
+
+        // registering under a name in use replaces that task
+        runner.register("b", TaskPriority::Normal, TaskStatus::Done);
+        runner.register("b", TaskPriority::Normal, TaskStatus::MoreWork);
+        assert_eq!(runner.run(Instant::now()), vec!["b"]);
+        assert_eq!(runner.run(Instant::now()), vec!["b"]);
+    }
+
+    #[test]
+    fn scheduling() {
+        let mut runner = Runner::new();
+        assert_eq!(runner.maintenance.schedule(), None);
+        runner.register("task", TaskPriority::Normal, TaskStatus::Done);
+        let due = runner.maintenance.schedule().unwrap();
+        // already scheduled
+        assert_eq!(runner.maintenance.schedule(), None);
+        runner.maintenance.unschedule();
+        runner.run(Instant::now());
+        assert!(runner.maintenance.schedule().unwrap() >= due + INTERVAL);
+    }
+}
@@ -14,6 +14,7 @@
 
 //! Representation and calculation of movement within a lineoffset.
 
+use std::borrow::Cow;
 use std::cmp::max;
 
 use crate::line_offset::LineOffset;
@@ -22,7 +23,8 @@ use crate::word_boundaries::WordCursor;
 use xi_rope::{Cursor, LinesMetric, Rope};
 
 /// The specification of a movement.
-#[derive(Debug, PartialEq, Clone, Copy)]
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum Movement {
     /// Move to the left by one grapheme cluster.
     Left,
@@ -48,6 +50,10 @@ pub enum Movement {
     UpExactPosition,
     /// Move down to the next line that can preserve the cursor position.
     DownExactPosition,
+    /// Move up one text line, keeping the column of the cursor.
+    ColumnUp,
+    /// Move down one text line, keeping the column of the cursor.
+    ColumnDown,
     /// Move to the start of the text line.
     StartOfParagraph,
     /// Move to the end of the text line.
@@ -134,6 +140,64 @@ fn vertical_motion_exact_pos(
     (lo.line_col_to_offset(text, line, col), Some(col))
 }
 
+/// Compute movement to the same column of the previous or next text line.
+///
+/// Unlike `vertical_motion`, this ignores line wrapping, and counts columns
+/// in codepoints rather than bytes, so that carets in aligned columns of
+/// text stay aligned. On lines shorter than the column the cursor stops at
+/// the end of the line, but the column is kept in `horiz` for later moves.
+/// At the first or last line, the cursor does not move.
+///
+/// Like the other vertical motions, `horiz` is kept in bytes: columns past
+/// the end of a line count one byte per missing codepoint.
+fn column_motion(
+    r: SelRegion,
+    text: &Rope,
+    move_up: bool,
+    modify: bool,
+) -> (usize, Option<HorizPos>) {
+    let active = if modify {
+        r.end
+    } else if move_up {
+        r.min()
+    } else {
+        r.max()
+    };
+    let line = text.line_of_offset(active);
+    let horiz = r.horiz.unwrap_or(active - text.offset_of_line(line));
+
+    let n_lines = text.line_of_offset(text.len());
+    if (move_up && line == 0) || (!move_up && line == n_lines) {
+        return (active, Some(horiz));
+    }
+
+    let line_text = line_content(text, line, n_lines);
+    let n_chars = line_text.char_indices().take_while(|&(i, _)| i < horiz).count();
+    let col = n_chars + horiz.saturating_sub(line_text.len());
+
+    let line = if move_up { line - 1 } else { line + 1 };
+    let line_start = text.offset_of_line(line);
+    let line_text = line_content(text, line, n_lines);
+    match line_text.char_indices().nth(col) {
+        Some((i, _)) => (line_start + i, Some(i)),
+        None => {
+            let horiz = line_text.len() + col - line_text.chars().count();
+            (line_start + line_text.len(), Some(horiz))
+        }
+    }
+}
+
+/// Returns the text of `line`, without its line ending.
+fn line_content(text: &Rope, line: usize, n_lines: usize) -> Cow<str> {
+    let line_start = text.offset_of_line(line);
+    let line_end = if line == n_lines {
+        text.len()
+    } else {
+        text.prev_grapheme_offset(text.offset_of_line(line + 1)).unwrap()
+    };
+    text.slice_to_cow(line_start..line_end)
+}
+
 /// Based on the current selection position this will return the cursor position, the current line, and the
 /// total number of lines of the file.
 fn selection_position(
@@ -235,6 +299,8 @@ pub fn region_movement(
         Movement::Down => vertical_motion(r, lo, text, 1, modify),
         Movement::UpExactPosition => vertical_motion_exact_pos(r, lo, text, true, modify),
         Movement::DownExactPosition => vertical_motion_exact_pos(r, lo, text, false, modify),
+        Movement::ColumnUp => column_motion(r, text, true, modify),
+        Movement::ColumnDown => column_motion(r, text, false, modify),
         Movement::StartOfParagraph => {
             // Note: TextEdit would start at modify ? r.end : r.min()
             let mut cursor = Cursor::new(text, r.end);
@@ -280,6 +346,56 @@ pub fn region_movement(
         Movement::StartOfDocument => (0, None),
         Movement::EndOfDocument => (text.len(), None),
     };
+    let offset = skip_folded(m, offset, lo, text);
+    SelRegion::new(if modify { r.start } else { offset }, offset).with_horiz(horiz)
+}
+
+/// Moves an offset the movement `m` landed on out of any folded region:
+/// past the region when moving right, and otherwise to the end of the line
+/// anchoring it.
+fn skip_folded(m: Movement, offset: usize, lo: &dyn LineOffset, text: &Rope) -> usize {
+    let fold = match lo.folded_range(offset) {
+        Some(fold) => fold,
+        None => return offset,
+    };
+    let after = match m {
+        Movement::Right | Movement::RightWord => text.next_grapheme_offset(fold.end()),
+        _ => None,
+    };
+    after.or_else(|| text.prev_grapheme_offset(fold.start())).unwrap_or(0)
+}
+
+/// Compute the result of applying a movement `count` times to one selection
+/// region.
+///
+/// Vertical movement is computed directly; other movements are applied one at
+/// a time, stopping early if the region stops moving.
+pub fn region_movement_repeated(
+    m: Movement,
+    r: SelRegion,
+    lo: &dyn LineOffset,
+    height: usize,
+    text: &Rope,
+    modify: bool,
+    count: usize,
+) -> SelRegion {
+    let line_delta = match m {
+        Movement::Up => -(count as isize),
+        Movement::Down => count as isize,
+        _ => {
+            let mut r = r;
+            for _ in 0..count {
+                let next = region_movement(m, r, lo, height, text, modify);
+                if next == r {
+                    break;
+                }
+                r = next;
+            }
+            return r;
+        }
+    };
+    let (offset, horiz) = vertical_motion(r, lo, text, line_delta, modify);
+    let offset = skip_folded(m, offset, lo, text);
     SelRegion::new(if modify { r.start } else { offset }, offset).with_horiz(horiz)
 }
 
@@ -301,10 +417,24 @@ pub fn selection_movement(
     height: usize,
     text: &Rope,
     modify: bool,
+) -> Selection {
+    selection_movement_repeated(m, s, lo, height, text, modify, 1)
+}
+
+/// Like `selection_movement`, but applies the movement to each region `count`
+/// times.
+pub fn selection_movement_repeated(
+    m: Movement,
+    s: &Selection,
+    lo: &dyn LineOffset,
+    height: usize,
+    text: &Rope,
+    modify: bool,
+    count: usize,
 ) -> Selection {
     let mut result = Selection::new();
     for &r in s.iter() {
-        let new_region = region_movement(m, r, lo, height, text, modify);
+        let new_region = region_movement_repeated(m, r, lo, height, text, modify, count);
         result.add_region(new_region);
     }
     result
@@ -14,21 +14,34 @@
 
 //! Keeping track of available plugins.
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fs;
 use std::io::{self, Read};
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
+use super::manifest::{validate_manifest, ManifestDiagnostic};
 use super::{PluginDescription, PluginName};
 use crate::config::table_from_toml_str;
-use crate::syntax::Languages;
+use crate::syntax::{LanguageId, Languages};
 
 /// A catalog of all available plugins.
 #[derive(Debug, Clone, Default)]
 pub struct PluginCatalog {
     items: HashMap<PluginName, Arc<PluginDescription>>,
     locations: HashMap<PathBuf, Arc<PluginDescription>>,
+    /// The outcome of loading each manifest found, including those that
+    /// failed to load.
+    reports: BTreeMap<PathBuf, ManifestReport>,
+}
+
+/// The outcome of loading a plugin manifest.
+#[derive(Debug, Clone, Serialize)]
+pub struct ManifestReport {
+    pub path: PathBuf,
+    /// The name of the plugin, if the manifest was loaded.
+    pub name: Option<PluginName>,
+    pub diagnostics: Vec<ManifestDiagnostic>,
 }
 
 /// Errors that can occur while trying to load a plugin.
@@ -37,6 +50,8 @@ pub enum PluginLoadError {
     Io(io::Error),
     /// Malformed manifest
     Parse(toml::de::Error),
+    /// A well-formed manifest that failed validation; see `validate_manifest`.
+    Invalid(Vec<ManifestDiagnostic>),
 }
 
 #[allow(dead_code)]
@@ -46,6 +61,7 @@ impl<'a> PluginCatalog {
     pub fn reload_from_paths(&mut self, paths: &[PathBuf]) {
         self.items.clear();
         self.locations.clear();
+        self.reports.clear();
         self.load_from_paths(paths);
     }
 
@@ -53,18 +69,29 @@ impl<'a> PluginCatalog {
     pub fn load_from_paths(&mut self, paths: &[PathBuf]) {
         let all_manifests = find_all_manifests(paths);
         for manifest_path in &all_manifests {
-            match load_manifest(manifest_path) {
-                Err(e) => warn!("error loading plugin {:?}", e),
-                Ok(manifest) => {
-                    info!("loaded {}", manifest.name);
-                    let manifest = Arc::new(manifest);
-                    self.items.insert(manifest.name.clone(), manifest.clone());
-                    self.locations.insert(manifest_path.clone(), manifest);
+            let (manifest, report) = load_with_report(manifest_path);
+            for diagnostic in &report.diagnostics {
+                if diagnostic.is_error() {
+                    error!("plugin manifest {:?}: {}", manifest_path, diagnostic);
+                } else {
+                    warn!("plugin manifest {:?}: {}", manifest_path, diagnostic);
                 }
             }
+            if let Some(manifest) = manifest {
+                info!("loaded {}", manifest.name);
+                let manifest = Arc::new(manifest);
+                self.items.insert(manifest.name.clone(), manifest.clone());
+                self.locations.insert(manifest_path.clone(), manifest);
+            }
+            self.reports.insert(manifest_path.clone(), report);
         }
     }
 
+    /// Returns the outcome of loading each manifest found, ordered by path.
+    pub fn reports(&'a self) -> impl Iterator<Item = &'a ManifestReport> {
+        self.reports.values()
+    }
+
     pub fn make_languages_map(&self) -> Languages {
         let all_langs =
             self.items.values().flat_map(|plug| plug.languages.iter().cloned()).collect::<Vec<_>>();
@@ -90,6 +117,34 @@ impl<'a> PluginCatalog {
             .cloned()
     }
 
+    /// Compares the plugins started by their activations, rather than with
+    /// the first buffer, with those `running`. Returns the plugins to start,
+    /// which are activated by one of `buffers`, the language and path of
+    /// each open buffer, and the names of those to stop, which no longer
+    /// are.
+    pub fn activation_changes(
+        &self,
+        buffers: &[(LanguageId, Option<PathBuf>)],
+        running: &BTreeSet<PluginName>,
+    ) -> (Vec<Arc<PluginDescription>>, Vec<PluginName>) {
+        let mut start = Vec::new();
+        let mut stop = Vec::new();
+        for manifest in self.items.values().filter(|m| !m.autoruns()) {
+            let activated = buffers
+                .iter()
+                .any(|(language, path)| manifest.activated_by_buffer(language, path.as_deref()));
+            let is_running = running.contains(&manifest.name);
+            if activated && !is_running {
+                start.push(manifest.clone());
+            } else if !activated && is_running {
+                stop.push(manifest.name.clone());
+            }
+        }
+        start.sort_by(|a, b| a.name.cmp(&b.name));
+        stop.sort();
+        (start, stop)
+    }
+
     /// Returns a reference to the named plugin if it exists in the catalog.
     pub fn get_named(&self, plugin_name: &str) -> Option<Arc<PluginDescription>> {
         self.items.get(plugin_name).map(Arc::clone)
@@ -98,9 +153,39 @@ impl<'a> PluginCatalog {
     /// Removes the named plugin.
     pub fn remove_named(&mut self, plugin_name: &str) {
         self.items.remove(plugin_name);
+        self.reports.retain(|_, report| report.name.as_deref() != Some(plugin_name));
+    }
+}
+
+impl ManifestReport {
+    /// Returns `true` if the manifest could not be loaded.
+    pub fn has_errors(&self) -> bool {
+        self.diagnostics.iter().any(ManifestDiagnostic::is_error)
     }
 }
 
+/// Validates the plugin manifest at `path` or, if `path` is a directory, the
+/// manifests found there as when loading plugins, without loading them into
+/// a catalog.
+pub fn validate_plugin_path(path: &Path) -> Vec<ManifestReport> {
+    let manifests =
+        if path.is_dir() { find_all_manifests(&[path.to_owned()]) } else { vec![path.to_owned()] };
+    manifests.iter().map(|path| load_with_report(path).1).collect()
+}
+
+fn load_with_report(path: &Path) -> (Option<PluginDescription>, ManifestReport) {
+    let (manifest, diagnostics) = match load_manifest(path) {
+        Ok((manifest, warnings)) => (Some(manifest), warnings),
+        Err(PluginLoadError::Invalid(diagnostics)) => (None, diagnostics),
+        Err(PluginLoadError::Io(e)) => (None, vec![ManifestDiagnostic::error("", e.to_string())]),
+        Err(PluginLoadError::Parse(e)) => {
+            (None, vec![ManifestDiagnostic::error("", e.to_string())])
+        }
+    };
+    let name = manifest.as_ref().map(|m| m.name.clone());
+    (manifest, ManifestReport { path: path.to_owned(), name, diagnostics })
+}
+
 fn find_all_manifests(paths: &[PathBuf]) -> Vec<PathBuf> {
     let mut manifest_paths = Vec::new();
     for path in paths.iter() {
@@ -123,14 +208,31 @@ fn find_all_manifests(paths: &[PathBuf]) -> Vec<PathBuf> {
     manifest_paths
 }
 
-fn load_manifest(path: &Path) -> Result<PluginDescription, PluginLoadError> {
+/// Loads the manifest at `path`, returning it along with any warnings.
+fn load_manifest(
+    path: &Path,
+) -> Result<(PluginDescription, Vec<ManifestDiagnostic>), PluginLoadError> {
     let mut file = fs::File::open(&path)?;
     let mut contents = String::new();
     file.read_to_string(&mut contents)?;
-    let mut manifest: PluginDescription = toml::from_str(&contents)?;
-    // normalize relative paths
-    if manifest.exec_path.starts_with("./") {
-        manifest.exec_path = path.parent().unwrap().join(manifest.exec_path).canonicalize()?;
+    let raw: toml::Value = toml::from_str(&contents)?;
+    let mut diagnostics = validate_manifest(&raw);
+    if diagnostics.iter().any(ManifestDiagnostic::is_error) {
+        return Err(PluginLoadError::Invalid(diagnostics));
+    }
+    let mut manifest: PluginDescription = raw.try_into()?;
+    // normalize relative paths; other paths without a directory are looked up
+    // in PATH when the plugin is launched.
+    let is_local = manifest.exec_path.starts_with("./");
+    if is_local {
+        manifest.exec_path = path.parent().unwrap().join(manifest.exec_path);
+    }
+    if is_local || manifest.exec_path.is_absolute() {
+        if let Err(message) = check_executable(&manifest.exec_path) {
+            diagnostics.push(ManifestDiagnostic::error("exec_path", message));
+            return Err(PluginLoadError::Invalid(diagnostics));
+        }
+        manifest.exec_path = manifest.exec_path.canonicalize()?;
     }
 
     for lang in &mut manifest.languages {
@@ -143,7 +245,29 @@ fn load_manifest(path: &Path) -> Result<PluginDescription, PluginLoadError> {
         let lang_defaults = table_from_toml_str(&lang_defaults)?;
         lang.default_config = Some(lang_defaults);
     }
-    Ok(manifest)
+    Ok((manifest, diagnostics))
+}
+
+/// Checks that `path` is a file that can be run as the plugin.
+fn check_executable(path: &Path) -> Result<(), String> {
+    let metadata = match fs::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            return Err(format!("{:?} does not exist; has the plugin been built?", path));
+        }
+        Err(e) => return Err(format!("{:?} cannot be read: {}", path, e)),
+    };
+    if !metadata.is_file() {
+        return Err(format!("{:?} is not a file", path));
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if metadata.permissions().mode() & 0o111 == 0 {
+            return Err(format!("{:?} is not executable", path));
+        }
+    }
+    Ok(())
 }
 
 impl From<io::Error> for PluginLoadError {
@@ -157,3 +281,119 @@ impl From<toml::de::Error> for PluginLoadError {
         PluginLoadError::Parse(err)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    fn write_manifest(dir: &Path, contents: &str) -> PathBuf {
+        fs::create_dir_all(dir).unwrap();
+        let path = dir.join("manifest.toml");
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn exec_path_is_checked() {
+        let tmp = TempDir::new("xi-test-plugin-exec-path").unwrap();
+        let manifest =
+            write_manifest(tmp.path(), "name = \"p\"\nversion = \"0\"\nexec_path = \"./plugin\"\n");
+
+        let report = validate_plugin_path(tmp.path()).remove(0);
+        assert_eq!(report.path, manifest);
+        assert_eq!(report.name, None);
+        assert_eq!(report.diagnostics[0].location, "exec_path");
+        assert!(report.diagnostics[0]
+            .message
+            .ends_with("does not exist; has the plugin been built?"));
+
+        let exec_path = tmp.path().join(if cfg!(windows) { "plugin.exe" } else { "plugin" });
+        fs::write(&exec_path, "").unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let report = validate_plugin_path(&manifest).remove(0);
+            assert!(report.diagnostics[0].message.ends_with("is not executable"));
+            fs::set_permissions(&exec_path, fs::Permissions::from_mode(0o755)).unwrap();
+        }
+
+        let report = validate_plugin_path(&manifest).remove(0);
+        assert!(!report.has_errors(), "{:?}", report.diagnostics);
+        assert_eq!(report.name.as_deref(), Some("p"));
+    }
+
+    #[test]
+    fn catalog_keeps_reports() {
+        let tmp = TempDir::new("xi-test-plugin-reports").unwrap();
+        write_manifest(
+            &tmp.path().join("a"),
+            "name = \"a\"\nversion = \"0\"\nexec_path = \"a\"\nhomepage = \"\"\n",
+        );
+        write_manifest(&tmp.path().join("b"), "name = \"b\"\nexec_path = \"b\"\n");
+
+        let mut catalog = PluginCatalog::default();
+        catalog.reload_from_paths(&[tmp.path().to_owned()]);
+        assert_eq!(catalog.iter_names().collect::<Vec<_>>(), vec!["a"]);
+        let reports = catalog.reports().collect::<Vec<_>>();
+        assert_eq!(reports.len(), 2);
+        assert_eq!(reports[0].name.as_deref(), Some("a"));
+        assert_eq!(reports[0].diagnostics[0].location, "homepage");
+        assert!(!reports[0].has_errors());
+        assert_eq!(reports[1].name, None);
+        assert!(reports[1].has_errors());
+    }
+
+    #[test]
+    fn activations_start_and_stop_plugins() {
+        let tmp = TempDir::new("xi-test-plugin-activations").unwrap();
+        let manifest = |name: &str, activations: &str| {
+            let contents = format!(
+                "name = \"{}\"\nversion = \"0\"\nexec_path = \"{}\"\nactivations = [{}]\n",
+                name, name, activations
+            );
+            write_manifest(&tmp.path().join(name), &contents);
+        };
+        manifest("syntect", "\"autorun\"");
+        manifest("rust_lsp", "\"on_language:Rust\"");
+        manifest("markdown", "\"on_language:Markdown\", \"on_file_pattern:*.md\"");
+        manifest("formatter", "\"on_command:format\"");
+        let mut catalog = PluginCatalog::default();
+        catalog.reload_from_paths(&[tmp.path().to_owned()]);
+        assert_eq!(catalog.iter_names().count(), 4);
+
+        let mut running = BTreeSet::new();
+        let mut buffers = Vec::new();
+        // applies the changes for the open buffers, returning them by name
+        let mut update = |buffers: &[(LanguageId, Option<PathBuf>)]| {
+            let (start, stop) = catalog.activation_changes(buffers, &running);
+            let start = start.iter().map(|m| m.name.clone()).collect::<Vec<_>>();
+            running.extend(start.iter().cloned());
+            stop.iter().for_each(|name| {
+                running.remove(name);
+            });
+            (start, stop)
+        };
+        let plain = LanguageId::from("Plain Text");
+        let none = (Vec::<String>::new(), Vec::<String>::new());
+
+        // a plain text scratch buffer starts nothing
+        buffers.push((plain.clone(), None));
+        assert_eq!(update(&buffers), none);
+        // opening a Rust file starts the Rust plugin, once
+        buffers.push((LanguageId::from("Rust"), Some(PathBuf::from("/xi/main.rs"))));
+        assert_eq!(update(&buffers), (vec!["rust_lsp".to_owned()], vec![]));
+        assert_eq!(update(&buffers), none);
+        // a file pattern matches without the language
+        buffers.push((plain.clone(), Some(PathBuf::from("/xi/README.md"))));
+        assert_eq!(update(&buffers), (vec!["markdown".to_owned()], vec![]));
+        // the Rust file is closed, and the plain buffer becomes Markdown
+        buffers.remove(1);
+        buffers[0].0 = LanguageId::from("Markdown");
+        assert_eq!(update(&buffers), (vec![], vec!["rust_lsp".to_owned()]));
+        // the last Markdown buffers close
+        buffers.clear();
+        assert_eq!(update(&buffers), (vec![], vec!["markdown".to_owned()]));
+        assert!(running.is_empty());
+    }
+}
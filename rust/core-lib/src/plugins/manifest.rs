@@ -14,10 +14,14 @@
 
 //! Structured representation of a plugin's features and capabilities.
 
-use std::path::PathBuf;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
-use serde::{Deserialize, Deserializer, Serialize};
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use serde_json::{self, Value};
+use toml::value::{Table as TomlTable, Value as TomlValue};
 
 use crate::syntax::{LanguageDefinition, LanguageId};
 
@@ -35,13 +39,22 @@ pub struct PluginDescription {
     /// path to plugin executable
     #[serde(deserialize_with = "platform_exec_path")]
     pub exec_path: PathBuf,
-    /// Events that cause this plugin to run
+    /// Events that cause this plugin to run. A plugin with none, or with
+    /// `autorun`, is started with the first buffer; others are started
+    /// when one of their activations matches.
     #[serde(default)]
     pub activations: Vec<PluginActivation>,
     #[serde(default)]
     pub commands: Vec<Command>,
     #[serde(default)]
     pub languages: Vec<LanguageDefinition>,
+    /// Privileged requests this plugin is allowed to make.
+    #[serde(default)]
+    pub capabilities: Vec<PluginCapability>,
+    /// Whether to start this plugin when core starts, instead of when the
+    /// first buffer is opened. Ignored for plugins started by activations.
+    #[serde(default)]
+    pub preload: bool,
 }
 
 fn platform_exec_path<'de, D: Deserializer<'de>>(deserializer: D) -> Result<PathBuf, D::Error> {
@@ -54,17 +67,113 @@ fn platform_exec_path<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Path
 }
 
 /// `PluginActivation`s represent events that trigger running a plugin.
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(rename_all = "snake_case")]
+///
+/// In a manifest each is a string: `autorun`, or a kind of activation and
+/// its argument, such as `on_language:Rust` or `on_file_pattern:*.md`.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum PluginActivation {
     /// Always run this plugin, when available.
     Autorun,
-    /// Run this plugin if the provided SyntaxDefinition is active.
-    #[allow(dead_code)]
-    OnSyntax(LanguageId),
-    /// Run this plugin in response to a given command.
-    #[allow(dead_code)]
-    OnCommand,
+    /// Run this plugin while a buffer of the given language is open.
+    OnLanguage(LanguageId),
+    /// Run this plugin while a file matching the given glob is open. A
+    /// pattern with no `/` is matched against the file name, and any other
+    /// against the end of the path. `*` matches any run of characters, and
+    /// `?` any one character.
+    OnFilePattern(String),
+    /// Run this plugin when the client sends it the given command.
+    OnCommand(String),
+}
+
+impl PluginActivation {
+    /// The kinds of activation, as they are written in a manifest.
+    pub const KINDS: &'static [&'static str] =
+        &["autorun", "on_language:", "on_file_pattern:", "on_command:"];
+
+    /// Returns `true` if a buffer with this language and path activates
+    /// the plugin.
+    pub fn matches_buffer(&self, language: &LanguageId, path: Option<&Path>) -> bool {
+        match self {
+            PluginActivation::OnLanguage(id) => id == language,
+            PluginActivation::OnFilePattern(pattern) => {
+                path.map(|path| path_matches(pattern, path)).unwrap_or(false)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for PluginActivation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        if s == "autorun" {
+            return Ok(PluginActivation::Autorun);
+        }
+        let (kind, arg) = match s.find(':') {
+            Some(ix) if ix + 1 < s.len() => (&s[..ix], &s[ix + 1..]),
+            _ => return Err(format!("unknown activation `{}`", s)),
+        };
+        match kind {
+            "on_language" => Ok(PluginActivation::OnLanguage(arg.into())),
+            "on_file_pattern" => Ok(PluginActivation::OnFilePattern(arg.to_owned())),
+            "on_command" => Ok(PluginActivation::OnCommand(arg.to_owned())),
+            _ => Err(format!("unknown activation `{}`", s)),
+        }
+    }
+}
+
+impl fmt::Display for PluginActivation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PluginActivation::Autorun => write!(f, "autorun"),
+            PluginActivation::OnLanguage(id) => write!(f, "on_language:{}", id.as_ref()),
+            PluginActivation::OnFilePattern(pattern) => write!(f, "on_file_pattern:{}", pattern),
+            PluginActivation::OnCommand(command) => write!(f, "on_command:{}", command),
+        }
+    }
+}
+
+impl Serialize for PluginActivation {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for PluginActivation {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(D::Error::custom)
+    }
+}
+
+/// Returns `true` if `path` matches the glob `pattern`, as described for
+/// `PluginActivation::OnFilePattern`.
+fn path_matches(pattern: &str, path: &Path) -> bool {
+    let path = path.to_string_lossy().replace('\\', "/");
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let text = path.chars().collect::<Vec<_>>();
+    if pattern.contains(&'/') {
+        // match any suffix which starts a path component
+        (0..text.len())
+            .filter(|&i| i == 0 || text[i - 1] == '/')
+            .any(|i| glob_matches(&pattern, &text[i..]))
+    } else {
+        let name = match text.iter().rposition(|&c| c == '/') {
+            Some(ix) => &text[ix + 1..],
+            None => &text[..],
+        };
+        glob_matches(&pattern, name)
+    }
+}
+
+fn glob_matches(pattern: &[char], text: &[char]) -> bool {
+    match pattern.split_first() {
+        None => text.is_empty(),
+        Some(('*', rest)) => (0..=text.len()).any(|i| glob_matches(rest, &text[i..])),
+        Some(('?', rest)) => !text.is_empty() && glob_matches(rest, &text[1..]),
+        Some((c, rest)) => text.first() == Some(c) && glob_matches(rest, &text[1..]),
+    }
 }
 
 /// Describes the scope of events a plugin receives.
@@ -80,6 +189,17 @@ pub enum PluginScope {
     SingleInvocation,
 }
 
+/// Privileged requests a plugin has to declare in its manifest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PluginCapability {
+    /// The plugin may create and update scratch views.
+    ScratchViews,
+    /// The plugin formats buffers before they are saved, when
+    /// `format_on_save` is set.
+    Formatting,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 /// Represents a custom command provided by a plugin.
 pub struct Command {
@@ -221,6 +341,28 @@ impl PluginDescription {
     pub fn is_global(&self) -> bool {
         matches!(self.scope, PluginScope::Global)
     }
+
+    /// Returns `true` if this plugin declares the given capability.
+    pub fn has_capability(&self, capability: PluginCapability) -> bool {
+        self.capabilities.contains(&capability)
+    }
+
+    /// Returns `true` if this plugin is started with the first buffer,
+    /// rather than when one of its activations matches.
+    pub fn autoruns(&self) -> bool {
+        self.activations.is_empty() || self.activations.contains(&PluginActivation::Autorun)
+    }
+
+    /// Returns `true` if a buffer with this language and path activates
+    /// this plugin.
+    pub fn activated_by_buffer(&self, language: &LanguageId, path: Option<&Path>) -> bool {
+        self.activations.iter().any(|a| a.matches_buffer(language, path))
+    }
+
+    /// Returns `true` if sending this plugin `command` activates it.
+    pub fn activated_by_command(&self, command: &str) -> bool {
+        self.activations.contains(&PluginActivation::OnCommand(command.to_owned()))
+    }
 }
 
 impl Default for PluginScope {
@@ -229,6 +371,271 @@ impl Default for PluginScope {
     }
 }
 
+/// How serious a problem found in a manifest is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    /// The manifest loads, but probably not as its author intended.
+    Warning,
+    /// The manifest cannot be loaded.
+    Error,
+}
+
+/// A problem found while validating a plugin manifest.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ManifestDiagnostic {
+    pub severity: Severity,
+    /// The key path of the offending item, such as `commands[0].args[1].arg_type`;
+    /// empty if the problem concerns the manifest as a whole.
+    pub location: String,
+    pub message: String,
+}
+
+impl ManifestDiagnostic {
+    pub fn warning<S: Into<String>>(location: &str, message: S) -> Self {
+        ManifestDiagnostic {
+            severity: Severity::Warning,
+            location: location.to_owned(),
+            message: message.into(),
+        }
+    }
+
+    pub fn error<S: Into<String>>(location: &str, message: S) -> Self {
+        ManifestDiagnostic {
+            severity: Severity::Error,
+            location: location.to_owned(),
+            message: message.into(),
+        }
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
+
+impl fmt::Display for ManifestDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        if self.location.is_empty() {
+            write!(f, "{}: {}", severity, self.message)
+        } else {
+            write!(f, "{}: {}: {}", severity, self.location, self.message)
+        }
+    }
+}
+
+/// Checks a parsed manifest against the fields `PluginDescription` accepts,
+/// which serde alone is too lenient for: unknown fields are reported as
+/// warnings, and missing fields, values of the wrong type, and unknown
+/// variants of enum-like fields as errors.
+///
+/// A manifest without errors deserializes as a `PluginDescription`.
+pub fn validate_manifest(manifest: &TomlValue) -> Vec<ManifestDiagnostic> {
+    let mut validator = Validator::default();
+    validator.plugin(manifest);
+    if !validator.diagnostics.iter().any(ManifestDiagnostic::is_error) {
+        // catches anything the checks above don't know about
+        if let Err(e) = manifest.clone().try_into::<PluginDescription>() {
+            validator.error("", e.to_string());
+        }
+    }
+    validator.diagnostics
+}
+
+#[derive(Default)]
+struct Validator {
+    diagnostics: Vec<ManifestDiagnostic>,
+}
+
+fn join_key(loc: &str, key: &str) -> String {
+    if loc.is_empty() {
+        key.to_owned()
+    } else {
+        format!("{}.{}", loc, key)
+    }
+}
+
+impl Validator {
+    fn error<S: Into<String>>(&mut self, loc: &str, message: S) {
+        self.diagnostics.push(ManifestDiagnostic::error(loc, message));
+    }
+
+    /// Checks that `value` is a table with all of the `required` keys, and
+    /// warns about any keys not in `known`.
+    fn table<'a>(
+        &mut self,
+        value: &'a TomlValue,
+        loc: &str,
+        known: &[&str],
+        required: &[&str],
+    ) -> Option<&'a TomlTable> {
+        let table = match value.as_table() {
+            Some(table) => table,
+            None => {
+                self.error(loc, format!("expected a table, found {}", value.type_str()));
+                return None;
+            }
+        };
+        for key in table.keys().filter(|k| !known.contains(&k.as_str())) {
+            let message = format!("unknown field `{}`, expected one of {}", key, known.join(", "));
+            self.diagnostics.push(ManifestDiagnostic::warning(&join_key(loc, key), message));
+        }
+        for key in required.iter().filter(|k| !table.contains_key(**k)) {
+            self.error(&join_key(loc, key), "missing required field");
+        }
+        Some(table)
+    }
+
+    fn string<'a>(&mut self, table: &'a TomlTable, loc: &str, key: &str) -> Option<&'a str> {
+        let value = table.get(key)?;
+        let s = value.as_str();
+        if s.is_none() {
+            let message = format!("expected a string, found {}", value.type_str());
+            self.error(&join_key(loc, key), message);
+        }
+        s
+    }
+
+    fn one_of(&mut self, table: &TomlTable, loc: &str, key: &str, allowed: &[&str]) {
+        if let Some(s) = self.string(table, loc, key) {
+            self.check_variant(s, &join_key(loc, key), allowed);
+        }
+    }
+
+    fn check_variant(&mut self, s: &str, loc: &str, allowed: &[&str]) {
+        if !allowed.contains(&s) {
+            let message = format!("unknown value `{}`, expected one of {}", s, allowed.join(", "));
+            self.error(loc, message);
+        }
+    }
+
+    /// Returns the items of the array at `key`, with the location of each.
+    fn array<'a>(
+        &mut self,
+        table: &'a TomlTable,
+        loc: &str,
+        key: &str,
+    ) -> Vec<(String, &'a TomlValue)> {
+        let loc = join_key(loc, key);
+        match table.get(key) {
+            Some(TomlValue::Array(items)) => {
+                items.iter().enumerate().map(|(i, v)| (format!("{}[{}]", loc, i), v)).collect()
+            }
+            Some(other) => {
+                self.error(&loc, format!("expected an array, found {}", other.type_str()));
+                Vec::new()
+            }
+            None => Vec::new(),
+        }
+    }
+
+    fn plugin(&mut self, value: &TomlValue) {
+        const FIELDS: &[&str] = &[
+            "name",
+            "version",
+            "scope",
+            "exec_path",
+            "activations",
+            "commands",
+            "languages",
+            "capabilities",
+            "preload",
+        ];
+        let table = match self.table(value, "", FIELDS, &["name", "version", "exec_path"]) {
+            Some(table) => table,
+            None => return,
+        };
+        self.string(table, "", "name");
+        self.string(table, "", "version");
+        self.string(table, "", "exec_path");
+        self.one_of(table, "", "scope", &["global", "buffer_local", "single_invocation"]);
+        for (loc, item) in self.array(table, "", "activations") {
+            self.activation(item, &loc);
+        }
+        for (loc, item) in self.array(table, "", "capabilities") {
+            match item.as_str() {
+                Some(s) => self.check_variant(s, &loc, &["scratch_views", "formatting"]),
+                None => self.error(&loc, format!("expected a string, found {}", item.type_str())),
+            }
+        }
+        for (loc, item) in self.array(table, "", "commands") {
+            self.command(item, &loc);
+        }
+        for (loc, item) in self.array(table, "", "languages") {
+            const LANGUAGE_FIELDS: &[&str] = &["name", "extensions", "first_line_match", "scope"];
+            let required = &["name", "extensions", "scope"];
+            if let Some(lang) = self.table(item, &loc, LANGUAGE_FIELDS, required) {
+                self.string(lang, &loc, "name");
+                self.string(lang, &loc, "first_line_match");
+                self.string(lang, &loc, "scope");
+            }
+        }
+    }
+
+    fn activation(&mut self, value: &TomlValue, loc: &str) {
+        match value.as_str() {
+            Some(s) => {
+                if s.parse::<PluginActivation>().is_err() {
+                    let kinds = PluginActivation::KINDS;
+                    let message =
+                        format!("unknown value `{}`, expected one of {}", s, kinds.join(", "));
+                    self.error(loc, message);
+                }
+            }
+            None => self.error(loc, format!("expected a string, found {}", value.type_str())),
+        }
+    }
+
+    fn command(&mut self, value: &TomlValue, loc: &str) {
+        const FIELDS: &[&str] = &["title", "description", "rpc_cmd", "args"];
+        let table = match self.table(value, loc, FIELDS, FIELDS) {
+            Some(table) => table,
+            None => return,
+        };
+        self.string(table, loc, "title");
+        self.string(table, loc, "description");
+        if let Some(rpc_cmd) = table.get("rpc_cmd") {
+            const RPC_FIELDS: &[&str] = &["method", "params", "rpc_type"];
+            let loc = join_key(loc, "rpc_cmd");
+            if let Some(rpc_cmd) = self.table(rpc_cmd, &loc, RPC_FIELDS, RPC_FIELDS) {
+                self.string(rpc_cmd, &loc, "method");
+                self.one_of(rpc_cmd, &loc, "rpc_type", &["notification", "request"]);
+            }
+        }
+        for (loc, arg) in self.array(table, loc, "args") {
+            self.command_arg(arg, &loc);
+        }
+    }
+
+    fn command_arg(&mut self, value: &TomlValue, loc: &str) {
+        const FIELDS: &[&str] = &["title", "description", "key", "arg_type", "options"];
+        const REQUIRED: &[&str] = &["title", "description", "key", "arg_type"];
+        const ARG_TYPES: &[&str] = &["Number", "Int", "PosInt", "Bool", "String", "Choice"];
+        let table = match self.table(value, loc, FIELDS, REQUIRED) {
+            Some(table) => table,
+            None => return,
+        };
+        self.string(table, loc, "title");
+        self.string(table, loc, "description");
+        self.string(table, loc, "key");
+        self.one_of(table, loc, "arg_type", ARG_TYPES);
+        if table.get("arg_type").and_then(TomlValue::as_str) == Some("Choice")
+            && !table.contains_key("options")
+        {
+            self.error(&join_key(loc, "options"), "required when `arg_type` is `Choice`");
+        }
+        for (loc, option) in self.array(table, loc, "options") {
+            if let Some(option) = self.table(option, &loc, &["title", "value"], &["title", "value"])
+            {
+                self.string(option, &loc, "title");
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -299,4 +706,151 @@ mod tests {
         assert_eq!(command.rpc_cmd.params_ref()["non_arg"], "plugin supplied value");
         assert_eq!(command.args[1].options.clone().unwrap()[1].value, json!(10));
     }
+
+    #[test]
+    fn parse_activations() {
+        let manifest = r#"
+            name = "rust_lsp"
+            version = "0.0.0"
+            exec_path = "path/to/binary"
+            activations = [
+                "on_language:Rust",
+                "on_file_pattern:Cargo.toml",
+                "on_command:lsp.restart",
+            ]
+        "#;
+        assert_eq!(diagnostics(manifest), Vec::<String>::new());
+        let plugin_desc: PluginDescription = toml::from_str(manifest).unwrap();
+        assert!(!plugin_desc.autoruns());
+        assert_eq!(plugin_desc.activations[0], PluginActivation::OnLanguage("Rust".into()));
+        assert_eq!(
+            serde_json::to_value(&plugin_desc.activations).unwrap(),
+            json!(["on_language:Rust", "on_file_pattern:Cargo.toml", "on_command:lsp.restart"])
+        );
+
+        let rust = LanguageId::from("Rust");
+        let markdown = LanguageId::from("Markdown");
+        assert!(plugin_desc.activated_by_buffer(&rust, None));
+        assert!(!plugin_desc.activated_by_buffer(&markdown, Some(Path::new("/xi/README.md"))));
+        assert!(plugin_desc.activated_by_buffer(&markdown, Some(Path::new("/xi/Cargo.toml"))));
+        assert!(plugin_desc.activated_by_command("lsp.restart"));
+        assert!(!plugin_desc.activated_by_command("lsp.stop"));
+
+        assert!("on_language:".parse::<PluginActivation>().is_err());
+        assert!("on_syntax:Rust".parse::<PluginActivation>().is_err());
+        let plugin_desc: PluginDescription =
+            toml::from_str("name = \"p\"\nversion = \"0\"\nexec_path = \"p\"\n").unwrap();
+        assert!(plugin_desc.autoruns());
+    }
+
+    #[test]
+    fn activation_file_patterns() {
+        let matches = |pattern: &str, path: &str| path_matches(pattern, Path::new(path));
+        assert!(matches("*.md", "/home/xi/README.md"));
+        assert!(matches("*.md", "README.md"));
+        assert!(!matches("*.md", "/home/xi/README.mdx"));
+        assert!(matches("Cargo.?oml", "/home/xi/Cargo.toml"));
+        assert!(matches("docs/*.md", "/home/xi/docs/intro.md"));
+        assert!(!matches("docs/*.md", "/home/xi/mydocs/intro.md"));
+    }
+
+    fn diagnostics(manifest: &str) -> Vec<String> {
+        let manifest = toml::from_str(manifest).unwrap();
+        validate_manifest(&manifest).iter().map(ManifestDiagnostic::to_string).collect()
+    }
+
+    #[test]
+    fn validate_valid_manifest() {
+        let manifest = include_str!("../../../syntect-plugin/manifest.toml");
+        assert_eq!(diagnostics(manifest), Vec::<String>::new());
+    }
+
+    #[test]
+    fn validate_unknown_fields() {
+        let manifest = r#"
+            name = "test_plugin"
+            version = "0.0.0"
+            exec_path = "path/to/binary"
+            actvations = ["autorun"]
+
+            [[languages]]
+            name = "Foo"
+            extensions = ["foo"]
+            extension = "f"
+            scope = "source.foo"
+        "#;
+        assert_eq!(
+            diagnostics(manifest),
+            vec![
+                "warning: actvations: unknown field `actvations`, expected one of name, version, \
+                 scope, exec_path, activations, commands, languages, capabilities, preload",
+                "warning: languages[0].extension: unknown field `extension`, expected one of \
+                 name, extensions, first_line_match, scope",
+            ]
+        );
+    }
+
+    #[test]
+    fn validate_missing_fields() {
+        let manifest = r#"
+            name = 5
+
+            [[commands]]
+            title = "Test Command"
+
+            [commands.rpc_cmd]
+            method = "test.cmd"
+            params = {}
+        "#;
+        assert_eq!(
+            diagnostics(manifest),
+            vec![
+                "error: version: missing required field",
+                "error: exec_path: missing required field",
+                "error: name: expected a string, found integer",
+                "error: commands[0].description: missing required field",
+                "error: commands[0].args: missing required field",
+                "error: commands[0].rpc_cmd.rpc_type: missing required field",
+            ]
+        );
+    }
+
+    #[test]
+    fn validate_enum_values() {
+        let manifest = r#"
+            name = "test_plugin"
+            version = "0.0.0"
+            exec_path = "path/to/binary"
+            scope = "everywhere"
+            activations = ["autorun", "on_save"]
+            capabilities = ["scratch_views", "network"]
+
+            [[commands]]
+            title = "Test Command"
+            description = "Passes the current test"
+            args = [
+                { title = "A", description = "First", key = "a", arg_type = "Choice" },
+                { title = "B", description = "Second", key = "b", arg_type = "Float" },
+            ]
+
+            [commands.rpc_cmd]
+            method = "test.cmd"
+            params = {}
+            rpc_type = "request"
+        "#;
+        assert_eq!(
+            diagnostics(manifest),
+            vec![
+                "error: scope: unknown value `everywhere`, expected one of global, buffer_local, \
+                 single_invocation",
+                "error: activations[1]: unknown value `on_save`, expected one of autorun, \
+                 on_language:, on_file_pattern:, on_command:",
+                "error: capabilities[1]: unknown value `network`, expected one of scratch_views, \
+                 formatting",
+                "error: commands[0].args[0].options: required when `arg_type` is `Choice`",
+                "error: commands[0].args[1].arg_type: unknown value `Float`, expected one of \
+                 Number, Int, PosInt, Bool, String, Choice",
+            ]
+        );
+    }
 }
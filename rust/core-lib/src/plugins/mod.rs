@@ -32,12 +32,15 @@ use xi_rpc::{self, RpcLoop, RpcPeer};
 use crate::config::Table;
 use crate::syntax::LanguageId;
 use crate::tabs::ViewId;
+#[cfg(feature = "notify")]
+use crate::watcher::FsEventKind;
 use crate::WeakXiCore;
 
-use self::rpc::{PluginBufferInfo, PluginUpdate};
+use self::rpc::{EditRejection, PluginBufferInfo, PluginUpdate};
 
 pub(crate) use self::catalog::PluginCatalog;
-pub use self::manifest::{Command, PlaceholderRpc, PluginDescription};
+pub use self::catalog::{validate_plugin_path, ManifestReport};
+pub use self::manifest::{Command, PlaceholderRpc, PluginCapability, PluginDescription};
 
 pub type PluginName = String;
 
@@ -58,15 +61,34 @@ impl fmt::Display for PluginPid {
     }
 }
 
+/// Where a running plugin is in its lifecycle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum PluginState {
+    /// Started before there was a buffer to attach to, and waiting for one.
+    Warmed,
+    /// Sent at least one buffer.
+    Attached,
+    /// Asked to shut down, and sent nothing further.
+    Stopping,
+}
+
 pub struct Plugin {
     peer: RpcPeer,
     pub(crate) id: PluginId,
     pub(crate) name: String,
+    pub(crate) capabilities: Vec<PluginCapability>,
+    pub(crate) state: PluginState,
+    /// The plugin's process, or `None` for a plugin that runs in-process.
     #[allow(dead_code)]
-    process: Child,
+    process: Option<Child>,
 }
 
 impl Plugin {
+    /// Returns `true` if this plugin's manifest declares `capability`.
+    pub fn has_capability(&self, capability: PluginCapability) -> bool {
+        self.capabilities.contains(&capability)
+    }
+
     //TODO: initialize should be sent automatically during launch,
     //and should only send the plugin_id. We can just use the existing 'new_buffer'
     // RPC for adding views
@@ -110,6 +132,16 @@ impl Plugin {
         self.peer.send_rpc_request_async("update", &json!(update), Box::new(callback))
     }
 
+    /// Asks the plugin for the delta that formats the view's buffer, based
+    /// on `rev`, or `null` if it needs no changes.
+    pub fn format<F>(&self, view_id: ViewId, rev: u64, callback: F)
+    where
+        F: FnOnce(Result<Value, xi_rpc::Error>) + Send + 'static,
+    {
+        let params = json!({ "view_id": view_id, "rev": rev });
+        self.peer.send_rpc_request_async("format", &params, Box::new(callback))
+    }
+
     pub fn toggle_tracing(&self, enabled: bool) {
         self.peer.send_rpc_notification("tracing_config", &json!({ "enabled": enabled }))
     }
@@ -118,12 +150,13 @@ impl Plugin {
         self.peer.send_rpc_request("collect_trace", &json!({}))
     }
 
-    pub fn config_changed(&self, view_id: ViewId, changes: &Table) {
+    pub fn config_changed(&self, view_id: ViewId, changes: &Table, generation: u64) {
         self.peer.send_rpc_notification(
             "config_changed",
             &json!({
                 "view_id": view_id,
                 "changes": changes,
+                "generation": generation,
             }),
         )
     }
@@ -149,6 +182,55 @@ impl Plugin {
         )
     }
 
+    pub fn get_completions(&self, view_id: ViewId, request_id: usize, position: usize) {
+        self.peer.send_rpc_notification(
+            "get_completions",
+            &json!({
+                "view_id": view_id,
+                "request_id": request_id,
+                "position": position,
+            }),
+        )
+    }
+
+    pub fn resolve_completion(&self, view_id: ViewId, request_id: usize, index: usize) {
+        self.peer.send_rpc_notification(
+            "resolve_completion",
+            &json!({
+                "view_id": view_id,
+                "request_id": request_id,
+                "index": index,
+            }),
+        )
+    }
+
+    /// Tells the plugin of a change to a path it is watching with `token`.
+    #[cfg(feature = "notify")]
+    pub fn fs_event(&self, token: u64, path: &Path, kind: FsEventKind) {
+        self.peer.send_rpc_notification(
+            "fs_event",
+            &json!({
+                "token": token,
+                "path": path,
+                "kind": kind,
+            }),
+        )
+    }
+
+    /// Tells the plugin that a checked edit it sent, based on `rev`, was not
+    /// applied.
+    pub fn edit_rejected(&self, view_id: ViewId, rev: u64, head_rev: u64, reason: EditRejection) {
+        self.peer.send_rpc_notification(
+            "edit_rejected",
+            &json!({
+                "view_id": view_id,
+                "rev": rev,
+                "head_rev": head_rev,
+                "reason": reason,
+            }),
+        )
+    }
+
     pub fn dispatch_command(&self, view_id: ViewId, method: &str, params: &Value) {
         self.peer.send_rpc_notification(
             "custom_command",
@@ -161,6 +243,16 @@ impl Plugin {
     }
 }
 
+#[cfg(test)]
+impl Plugin {
+    /// Creates an in-process plugin whose messages are sent to `peer`.
+    pub(crate) fn with_peer(id: PluginId, name: &str, peer: RpcPeer) -> Plugin {
+        let name = name.to_owned();
+        let state = PluginState::Attached;
+        Plugin { peer, id, name, capabilities: Vec::new(), state, process: None }
+    }
+}
+
 pub(crate) fn start_plugin_process(
     plugin_desc: Arc<PluginDescription>,
     id: PluginId,
@@ -182,8 +274,11 @@ pub(crate) fn start_plugin_process(
                     let mut looper = RpcLoop::new(child_stdin);
                     let peer: RpcPeer = Box::new(looper.get_raw_peer());
                     let name = plugin_desc.name.clone();
+                    let capabilities = plugin_desc.capabilities.clone();
                     peer.send_rpc_notification("ping", &Value::Array(Vec::new()));
-                    let plugin = Plugin { peer, process: child, name, id };
+                    let state = PluginState::Warmed;
+                    let process = Some(child);
+                    let plugin = Plugin { peer, process, name, capabilities, state, id };
 
                     // set tracing immediately
                     if xi_trace::is_enabled() {
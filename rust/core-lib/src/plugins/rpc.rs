@@ -51,6 +51,9 @@ pub struct PluginBufferInfo {
     pub path: Option<String>,
     pub syntax: LanguageId,
     pub config: Table,
+    /// The generation of `config`; see `PluginUpdate::config_generation`.
+    #[serde(default)]
+    pub config_generation: u64,
 }
 
 //TODO: very likely this should be merged with PluginDescription
@@ -85,6 +88,12 @@ pub struct PluginUpdate {
     pub undo_group: Option<usize>,
     pub edit_type: String,
     pub author: String,
+    /// The generation of the buffer's config when this update was sent.
+    /// It increases each time the config changes, so a plugin holding a
+    /// config with a different generation can fetch the current one before
+    /// acting on the update.
+    #[serde(default)]
+    pub config_generation: u64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -107,14 +116,17 @@ pub enum HostNotification {
     Ping(EmptyStruct),
     Initialize { plugin_id: PluginPid, buffer_info: Vec<PluginBufferInfo> },
     DidSave { view_id: ViewId, path: PathBuf },
-    ConfigChanged { view_id: ViewId, changes: Table },
+    ConfigChanged { view_id: ViewId, changes: Table, generation: u64 },
     NewBuffer { buffer_info: Vec<PluginBufferInfo> },
     DidClose { view_id: ViewId },
     GetHover { view_id: ViewId, request_id: usize, position: usize },
+    GetCompletions { view_id: ViewId, request_id: usize, position: usize },
+    ResolveCompletion { view_id: ViewId, request_id: usize, index: usize },
     Shutdown(EmptyStruct),
     TracingConfig { enabled: bool },
     LanguageChanged { view_id: ViewId, new_lang: LanguageId },
     CustomCommand { view_id: ViewId, method: String, params: Value },
+    EditRejected { view_id: ViewId, rev: u64, head_rev: u64, reason: EditRejection },
 }
 
 // ====================================================================
@@ -135,6 +147,22 @@ pub struct PluginEdit {
     /// undo_group associated with this edit
     pub undo_group: Option<usize>,
     pub author: String,
+    /// If `true`, the edit is only applied if the text it changes has not
+    /// been edited since `rev`; otherwise the plugin is sent `edit_rejected`.
+    #[serde(default)]
+    pub checked: bool,
+}
+
+/// Why a plugin edit was not applied.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum EditRejection {
+    /// The text changed by the edit was edited after the edit's revision.
+    Conflict,
+    /// The edit's revision is unknown, or no longer kept by core.
+    MissingRevision,
+    /// The edit's delta does not match the length of its revision.
+    MalformedDelta,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Copy)]
@@ -160,6 +188,35 @@ pub struct GetDataResponse {
     pub first_line_offset: usize,
 }
 
+/// A region of a view's selection, as returned by the `get_selections` RPC.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PluginSelRegion {
+    /// The inactive edge of the region, as a byte offset.
+    pub start: usize,
+    /// The active edge of the region, as a byte offset.
+    pub end: usize,
+    /// Whether the region is a caret, with an empty interior.
+    pub is_caret: bool,
+    /// The saved horizontal position used for line up/down movement.
+    pub horiz: Option<usize>,
+}
+
+/// The object returned by the `get_selections` RPC.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GetSelectionsResponse {
+    /// The revision of the buffer the selections refer to.
+    pub rev: u64,
+    pub selections: Vec<PluginSelRegion>,
+}
+
+/// The object returned by the `get_config` RPC.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GetConfigResponse {
+    /// The generation of the config; see `PluginUpdate::config_generation`.
+    pub generation: u64,
+    pub config: Table,
+}
+
 /// The unit of measure when requesting data.
 #[derive(Serialize, Deserialize, Debug, Clone, Copy)]
 #[serde(rename_all = "snake_case")]
@@ -177,9 +234,53 @@ pub enum TextUnit {
 #[serde(tag = "method", content = "params")]
 /// RPC requests sent from plugins.
 pub enum PluginRequest {
-    GetData { start: usize, unit: TextUnit, max_size: usize, rev: u64 },
+    GetData {
+        start: usize,
+        unit: TextUnit,
+        max_size: usize,
+        rev: u64,
+    },
     LineCount,
-    GetSelections,
+    /// Returns the selection regions of the view, as a `GetSelectionsResponse`.
+    // A struct variant, so that the ids in `params` are ignored.
+    GetSelections {},
+    /// Returns the buffer's current config, as a `GetConfigResponse`.
+    GetConfig {},
+    /// Creates a new untitled view holding `content`, and asks the client
+    /// to show it. Requires the `scratch_views` capability.
+    CreateScratchView {
+        title: String,
+        content: String,
+        #[serde(default)]
+        language_id: Option<LanguageId>,
+        #[serde(default)]
+        read_only: bool,
+    },
+    /// Replaces the content of a scratch view created by this plugin. The
+    /// view is the one identified by the request's `view_id`.
+    UpdateScratchView {
+        content: String,
+    },
+    /// Registers a named highlight kind with the view, returning its id.
+    /// Ranges are then submitted with `update_annotations`, with an
+    /// `annotation_type` of `{"Highlight": id}`.
+    RegisterHighlightKind {
+        name: String,
+        #[serde(default)]
+        default_style_hints: Value,
+    },
+    /// Watches `path` for changes, sending the plugin `fs_event`
+    /// notifications with `token` until it is unwatched or the plugin exits.
+    WatchPath {
+        path: PathBuf,
+        #[serde(default)]
+        recursive: bool,
+        token: u64,
+    },
+    /// Stops the watch started with `token`.
+    UnwatchPath {
+        token: u64,
+    },
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -218,6 +319,15 @@ pub enum PluginNotification {
         request_id: usize,
         result: Result<Hover, RemoteError>,
     },
+    ShowCompletions {
+        request_id: usize,
+        result: Result<CompletionList, RemoteError>,
+    },
+    ShowResolvedCompletion {
+        request_id: usize,
+        index: usize,
+        result: Result<CompletionItem, RemoteError>,
+    },
     UpdateAnnotations {
         start: usize,
         len: usize,
@@ -244,6 +354,35 @@ pub struct Hover {
     pub range: Option<Range>,
 }
 
+/// Completions sent from Plugin to Core. If `is_incomplete` is set, the
+/// list should be requested again as the user keeps typing, rather than
+/// filtered by the client.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub struct CompletionList {
+    #[serde(default)]
+    pub is_incomplete: bool,
+    pub items: Vec<CompletionItem>,
+}
+
+/// A single completion. `insert_text` is the text to insert, if it differs
+/// from the label; `kind` is a lowercase name such as `"function"`.
+/// `documentation` is markdown, and is often only sent once the item is
+/// resolved.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub struct CompletionItem {
+    pub label: String,
+    #[serde(default)]
+    pub detail: Option<String>,
+    #[serde(default)]
+    pub insert_text: Option<String>,
+    #[serde(default)]
+    pub kind: Option<String>,
+    #[serde(default)]
+    pub documentation: Option<String>,
+}
+
 /// Common wrapper for plugin-originating RPCs.
 pub struct PluginCommand<T> {
     pub view_id: ViewId,
@@ -296,11 +435,22 @@ impl PluginBufferInfo {
         path: Option<PathBuf>,
         syntax: LanguageId,
         config: Table,
+        config_generation: u64,
     ) -> Self {
         //TODO: do make any current assertions about paths being valid utf-8? do we want to?
         let path = path.map(|p| p.to_str().unwrap().to_owned());
         let views = views.to_owned();
-        PluginBufferInfo { buffer_id, views, rev, buf_size, nb_lines, path, syntax, config }
+        PluginBufferInfo {
+            buffer_id,
+            views,
+            rev,
+            buf_size,
+            nb_lines,
+            path,
+            syntax,
+            config,
+            config_generation,
+        }
     }
 }
 
@@ -314,12 +464,23 @@ impl PluginUpdate {
         undo_group: Option<usize>,
         edit_type: String,
         author: String,
+        config_generation: u64,
     ) -> Self
     where
         D: Into<Option<RopeDelta>>,
     {
         let delta = delta.into();
-        PluginUpdate { view_id, delta, new_len, new_line_count, rev, undo_group, edit_type, author }
+        PluginUpdate {
+            view_id,
+            delta,
+            new_len,
+            new_line_count,
+            rev,
+            undo_group,
+            edit_type,
+            author,
+            config_generation,
+        }
     }
 }
 
@@ -408,4 +569,16 @@ mod tests {
             _ => panic!("{:?}", de.cmd),
         }
     }
+
+    #[test]
+    fn test_de_get_selections() {
+        let json =
+            r#"{"method": "get_selections", "params": {"view_id": "view-id-1", "plugin_id": 42}}"#;
+        let de: PluginCommand<PluginRequest> = serde_json::from_str(json).unwrap();
+        assert_eq!(de.view_id, ViewId(1));
+        match de.cmd {
+            PluginRequest::GetSelections {} => (),
+            _ => panic!("{:?}", de.cmd),
+        }
+    }
 }
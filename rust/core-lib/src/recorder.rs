@@ -14,14 +14,36 @@
 
 //! Manages recording and enables playback for client sent events.
 //!
-//! Clients can store multiple, named recordings.
+//! Clients can store multiple, named recordings, and save them to disk
+//! so that they outlive the editing session.
 
 use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
 
+use serde_json::Value;
+
+use xi_rpc::RemoteError;
 use xi_trace::trace_block;
 
 use crate::edit_types::{BufferEvent, EventDomain};
 
+/// The name and length of a saved recording, as returned by `list_recordings`.
+#[derive(Debug, PartialEq, Serialize)]
+pub(crate) struct RecordingInfo {
+    pub(crate) name: String,
+    pub(crate) len: usize,
+}
+
+/// The contents of a recording file. Events are kept as raw json so that
+/// each one can be checked individually when loading.
+#[derive(Serialize, Deserialize)]
+struct RecordingFile {
+    name: String,
+    events: Vec<Value>,
+}
+
 /// A container that manages and holds all recordings for the current editing session
 pub(crate) struct Recorder {
     active_recording: Option<String>,
@@ -131,6 +153,63 @@ impl Recorder {
         self.recordings.remove(recording_name);
     }
 
+    /// Returns the name and number of events of every saved recording,
+    /// sorted by name.
+    pub(crate) fn list(&self) -> Vec<RecordingInfo> {
+        let mut recordings: Vec<RecordingInfo> = self
+            .recordings
+            .iter()
+            .map(|(name, recording)| RecordingInfo {
+                name: name.clone(),
+                len: recording.events.len(),
+            })
+            .collect();
+        recordings.sort_by(|a, b| a.name.cmp(&b.name));
+        recordings
+    }
+
+    /// Writes the specified recording to `path` as json.
+    pub(crate) fn save(&self, recording_name: &str, path: &Path) -> Result<(), RemoteError> {
+        let recording = self.recordings.get(recording_name).ok_or_else(|| {
+            RemoteError::custom(404, format!("no recording named {:?}", recording_name), None)
+        })?;
+        let events = recording.events.iter().map(|event| json!(event)).collect();
+        let contents = RecordingFile { name: recording_name.to_owned(), events };
+
+        let file = File::create(path).map_err(|e| io_error(path, &e))?;
+        serde_json::to_writer_pretty(BufWriter::new(file), &contents)
+            .map_err(|e| io_error(path, &e))
+    }
+
+    /// Reads a recording written by `save`, replacing any existing recording
+    /// of the same name. Returns the name of the loaded recording.
+    ///
+    /// The file is rejected unless every event is an edit that could have
+    /// been recorded; special events, undo and redo never are.
+    pub(crate) fn load(&mut self, path: &Path) -> Result<String, RemoteError> {
+        let file = File::open(path).map_err(|e| io_error(path, &e))?;
+        let contents: RecordingFile = serde_json::from_reader(BufReader::new(file))
+            .map_err(|e| invalid_recording(path, &e))?;
+
+        let events = contents
+            .events
+            .into_iter()
+            .enumerate()
+            .map(|(i, event)| match serde_json::from_value(event) {
+                Ok(EventDomain::Special(_))
+                | Ok(EventDomain::Buffer(BufferEvent::Undo))
+                | Ok(EventDomain::Buffer(BufferEvent::Redo)) => {
+                    Err(invalid_recording(path, &format!("event {} cannot be recorded", i)))
+                }
+                Ok(event) => Ok(event),
+                Err(e) => Err(invalid_recording(path, &format!("event {}: {}", i, e))),
+            })
+            .collect::<Result<Vec<EventDomain>, RemoteError>>()?;
+
+        self.recordings.insert(contents.name.clone(), Recording::new(events));
+        Ok(contents.name)
+    }
+
     /// Cleans the recording buffer by filtering out any undo or redo events and then saving it
     /// with the specified name.
     ///
@@ -181,6 +260,14 @@ impl Recorder {
     }
 }
 
+fn io_error(path: &Path, err: &dyn std::fmt::Display) -> RemoteError {
+    RemoteError::custom(500, format!("recording file {:?}: {}", path, err), None)
+}
+
+fn invalid_recording(path: &Path, err: &dyn std::fmt::Display) -> RemoteError {
+    RemoteError::custom(400, format!("invalid recording file {:?}: {}", path, err), None)
+}
+
 struct Recording {
     events: Vec<EventDomain>,
 }
@@ -208,8 +295,14 @@ impl Recording {
 // R = Redo
 #[cfg(test)]
 mod tests {
-    use crate::edit_types::{BufferEvent, EventDomain};
-    use crate::recorder::Recorder;
+    use std::fs;
+
+    use tempdir::TempDir;
+    use xi_rpc::RemoteError;
+
+    use crate::edit_types::{BufferEvent, EventDomain, ViewEvent};
+    use crate::movement::Movement;
+    use crate::recorder::{Recorder, RecordingInfo};
 
     #[test]
     fn play_recording() {
@@ -477,4 +570,57 @@ mod tests {
             vec![BufferEvent::Transpose.into()]
         );
     }
+
+    #[test]
+    fn save_and_load() {
+        let tmp = TempDir::new("xi-test-recordings").unwrap();
+        let path = tmp.path().join("macro.json");
+        let events: Vec<EventDomain> = vec![
+            ViewEvent::Move(Movement::RightWord, 2).into(),
+            BufferEvent::Insert("foo".to_owned()).into(),
+            BufferEvent::Delete { movement: Movement::Left, kill: false, count: 1 }.into(),
+            BufferEvent::ReplaceAll { chars: None, preserve_case: true }.into(),
+        ];
+
+        let mut recorder = Recorder::new();
+        recorder.toggle_recording(Some("a".to_owned()));
+        events.iter().for_each(|event| recorder.record(event.clone()));
+        recorder.toggle_recording(None);
+        recorder.save("a", &path).unwrap();
+
+        let mut loaded = Recorder::new();
+        assert_eq!(loaded.load(&path).unwrap(), "a");
+        assert_eq!(loaded.recordings.get("a").unwrap().events, events);
+        assert_eq!(loaded.list(), vec![RecordingInfo { name: "a".to_owned(), len: 4 }]);
+
+        let err = recorder.save("missing", &path).unwrap_err();
+        assert!(matches!(err, RemoteError::Custom { code: 404, .. }), "{:?}", err);
+    }
+
+    #[test]
+    fn load_rejects_unknown_events() {
+        let tmp = TempDir::new("xi-test-bad-recordings").unwrap();
+        let mut recorder = Recorder::new();
+        let mut check = |name: &str, contents: &str, expected: i64| {
+            let path = tmp.path().join(name);
+            if !contents.is_empty() {
+                fs::write(&path, contents).unwrap();
+            }
+            let err = recorder.load(&path).unwrap_err();
+            assert!(
+                matches!(err, RemoteError::Custom { code, .. } if code == expected),
+                "{:?}",
+                err
+            );
+        };
+
+        check("unknown", r#"{"name": "a", "events": [{"buffer": "frobnicate"}]}"#, 400);
+        check("special", r#"{"name": "a", "events": [{"special": "reindent"}]}"#, 400);
+        check("undo", r#"{"name": "a", "events": [{"buffer": "undo"}]}"#, 400);
+        check("truncated", r#"{"name": "a", "events": ["#, 400);
+        check("missing", "", 500);
+
+        // a file that fails to load leaves no recording behind
+        assert!(recorder.list().is_empty());
+    }
 }
@@ -0,0 +1,221 @@
+// Copyright 2020 The xi-editor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A bounded history of the renders sent to the front-end for a view,
+//! for debugging the front-end's line cache.
+//!
+//! Records hold no text, only the shape of each update, so the journal is
+//! cheap enough to keep on in debug builds.
+
+use std::collections::VecDeque;
+
+use serde_json::Value;
+
+use crate::client::{OpType, UpdateOp};
+use crate::line_cache_shadow::{LineCacheShadow, ALL_VALID};
+
+/// The number of renders kept per view.
+pub const JOURNAL_CAPACITY: usize = 200;
+
+/// The most recent renders of a view, oldest first.
+#[derive(Debug)]
+pub struct RenderJournal {
+    records: VecDeque<RenderRecord>,
+    capacity: usize,
+}
+
+/// One render: the update ops sent, and the line cache shadow around them.
+#[derive(Debug, Clone, Serialize)]
+pub struct RenderRecord {
+    /// The buffer revision that was rendered.
+    pub rev: u64,
+    /// Line ranges that were not fully valid in the front-end before the render.
+    pub dirty: Vec<(usize, usize)>,
+    pub ops: Vec<OpSummary>,
+    pub before: ShadowSummary,
+    pub after: ShadowSummary,
+}
+
+/// An update op, without its content.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct OpSummary {
+    pub op: OpType,
+    pub n: usize,
+    /// The first line in the front-end's old cache that the op consumes, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub old_line: Option<usize>,
+    /// The first line in the front-end's new cache that the op produces, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub new_line: Option<usize>,
+}
+
+/// The shape of a `LineCacheShadow`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct ShadowSummary {
+    pub lines: usize,
+    pub spans: usize,
+    pub valid_lines: usize,
+    pub dirty: bool,
+}
+
+/// A render in progress; see `RenderRecord`.
+pub(crate) struct PendingRecord {
+    rev: u64,
+    dirty: Vec<(usize, usize)>,
+    before: ShadowSummary,
+}
+
+impl RenderJournal {
+    pub fn new(capacity: usize) -> RenderJournal {
+        RenderJournal { records: VecDeque::with_capacity(capacity), capacity }
+    }
+
+    pub fn push(&mut self, record: RenderRecord) {
+        if self.records.len() == self.capacity {
+            self.records.pop_front();
+        }
+        self.records.push_back(record);
+    }
+
+    pub fn records(&self) -> impl Iterator<Item = &RenderRecord> {
+        self.records.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!(self.records)
+    }
+}
+
+impl Default for RenderJournal {
+    fn default() -> RenderJournal {
+        RenderJournal::new(JOURNAL_CAPACITY)
+    }
+}
+
+impl PendingRecord {
+    pub(crate) fn new(rev: u64, shadow: &LineCacheShadow) -> PendingRecord {
+        let mut dirty: Vec<(usize, usize)> = Vec::new();
+        let mut line_num = 0;
+        for span in shadow.spans() {
+            if span.validity != ALL_VALID {
+                match dirty.last_mut() {
+                    Some(last) if last.1 == line_num => last.1 += span.n,
+                    _ => dirty.push((line_num, line_num + span.n)),
+                }
+            }
+            line_num += span.n;
+        }
+        PendingRecord { rev, dirty, before: ShadowSummary::new(shadow) }
+    }
+
+    pub(crate) fn finish(self, ops: &[UpdateOp], shadow: &LineCacheShadow) -> RenderRecord {
+        let PendingRecord { rev, dirty, before } = self;
+        let ops = OpSummary::from_ops(ops);
+        RenderRecord { rev, dirty, ops, before, after: ShadowSummary::new(shadow) }
+    }
+}
+
+impl OpSummary {
+    /// Summarizes a sequence of ops, tracking their positions in the
+    /// front-end's old and new line caches.
+    pub(crate) fn from_ops(ops: &[UpdateOp]) -> Vec<OpSummary> {
+        let (mut old_line, mut new_line) = (0, 0);
+        ops.iter()
+            .map(|op| {
+                let (op, n) = (op.op_type(), op.n());
+                let consumes = matches!(op, OpType::Skip | OpType::Copy | OpType::Update);
+                let produces = op != OpType::Skip;
+                let summary = OpSummary {
+                    op,
+                    n,
+                    old_line: if consumes { Some(old_line) } else { None },
+                    new_line: if produces { Some(new_line) } else { None },
+                };
+                if consumes {
+                    old_line += n;
+                }
+                if produces {
+                    new_line += n;
+                }
+                summary
+            })
+            .collect()
+    }
+}
+
+impl ShadowSummary {
+    pub fn new(shadow: &LineCacheShadow) -> ShadowSummary {
+        let spans = shadow.spans();
+        ShadowSummary {
+            lines: spans.iter().map(|s| s.n).sum(),
+            spans: spans.len(),
+            valid_lines: spans.iter().filter(|s| s.validity == ALL_VALID).map(|s| s.n).sum(),
+            dirty: shadow.is_dirty(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::line_cache_shadow::{Builder, INVALID, TEXT_VALID};
+
+    fn record(rev: u64) -> RenderRecord {
+        PendingRecord::new(rev, &LineCacheShadow::default()).finish(&[], &Default::default())
+    }
+
+    #[test]
+    fn journal_is_bounded() {
+        let mut journal = RenderJournal::new(3);
+        (0..5).for_each(|rev| journal.push(record(rev)));
+        assert_eq!(journal.len(), 3);
+        assert_eq!(journal.records().map(|r| r.rev).collect::<Vec<_>>(), vec![2, 3, 4]);
+    }
+
+    #[test]
+    fn summarize_shadow_and_ops() {
+        let mut b = Builder::new();
+        b.add_span(2, 0, ALL_VALID);
+        b.add_span(3, 0, INVALID);
+        b.add_span(1, 5, TEXT_VALID);
+        b.add_span(4, 6, ALL_VALID);
+        let shadow = b.build();
+        let pending = PendingRecord::new(7, &shadow);
+        assert_eq!(pending.dirty, vec![(2, 6)]);
+
+        let ops = [UpdateOp::copy(2, 1), UpdateOp::invalidate(3), UpdateOp::skip(3)];
+        let record = pending.finish(&ops, &shadow);
+        assert_eq!(
+            record.before,
+            ShadowSummary { lines: 10, spans: 4, valid_lines: 6, dirty: false }
+        );
+        let summary = |op, n, old_line, new_line| OpSummary { op, n, old_line, new_line };
+        assert_eq!(
+            record.ops,
+            vec![
+                summary(OpType::Copy, 2, Some(0), Some(0)),
+                summary(OpType::Invalidate, 3, None, Some(2)),
+                summary(OpType::Skip, 3, Some(2), None),
+            ]
+        );
+    }
+}
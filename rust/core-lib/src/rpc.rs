@@ -26,6 +26,7 @@ use serde::ser::{self, Serialize, Serializer};
 use serde_json::{self, Value};
 
 use crate::config::{ConfigDomainExternal, Table};
+use crate::line_ending::LineEnding;
 use crate::plugins::PlaceholderRpc;
 use crate::syntax::LanguageId;
 use crate::tabs::ViewId;
@@ -184,6 +185,9 @@ pub enum CoreNotification {
         /// Path to additional plugins, included by the client.
         #[serde(default)]
         client_extras_dir: Option<PathBuf>,
+        /// Optional protocol features the client supports.
+        #[serde(default)]
+        capabilities: Vec<ClientCapability>,
     },
     /// Updates the user's config for the given domain. Where keys in
     /// `changes` are `null`, those keys are cleared in the user config
@@ -204,6 +208,24 @@ pub enum CoreNotification {
     SaveTrace { destination: PathBuf, frontend_samples: Value },
     /// Tells `xi-core` to set the language id for the view.
     SetLanguage { view_id: ViewId, language_id: LanguageId },
+    /// Tells `xi-core` whether the file of the view's buffer should be saved
+    /// with a byte order mark.
+    SetBom { view_id: ViewId, present: bool },
+    /// Tells `xi-core` that the frontend has moved the file written by
+    /// `retry_save_elevated` into place, completing the save.
+    ConfirmExternalSave { view_id: ViewId },
+    /// Tells `xi-core` to show the file related to the view's file, such as
+    /// its header or source file, opening it if needed.
+    ToggleRelatedFile { view_id: ViewId },
+    /// Turns the view's render journal on or off. The journal is on by
+    /// default in debug builds; see `CoreRequest::DebugRenderJournal`.
+    DebugSetRenderJournal { view_id: ViewId, enabled: bool },
+    /// Writes the named macro recording to `path`, so that it can be
+    /// restored with `load_recording` in a later session.
+    SaveRecording { name: String, path: PathBuf },
+    /// Reads a recording written by `save_recording`, replacing any
+    /// recording with the same name.
+    LoadRecording { path: PathBuf },
 }
 
 /// The requests which make up the base of the protocol.
@@ -249,6 +271,63 @@ pub enum CoreRequest {
     /// In the future this might also be used to return structured data (such
     /// as for printing).
     DebugGetContents { view_id: ViewId },
+    /// Tells `xi-core` to create a new view on the buffer of the given view,
+    /// starting with a copy of its selection.
+    ///
+    /// Returns the view identifier of the new view.
+    SplitView { view_id: ViewId },
+    /// Retries the last, failed save of the view's buffer by writing its
+    /// contents to a temporary file, which the frontend can move into
+    /// place with rights `xi-core` does not have.
+    ///
+    /// Returns the path of the destination and of the temporary file.
+    RetrySaveElevated { view_id: ViewId },
+    /// Converts offsets in the view's buffer to logical and visual line and
+    /// column positions, as computed by `xi-core`'s wrapping.
+    ///
+    /// Returns one position per offset, in order.
+    OffsetToLineCol { view_id: ViewId, offsets: Vec<usize> },
+    /// Returns the view's recent renders, oldest first, and also writes them
+    /// to `path` if it is present. Records hold the ops sent to the frontend
+    /// and the state of core's model of its line cache, without any text.
+    DebugRenderJournal {
+        view_id: ViewId,
+        #[serde(default)]
+        path: Option<PathBuf>,
+    },
+    /// Returns how long the stages of handling recent edits to the view's
+    /// buffer took, then clears these timings if `reset` is set.
+    DebugEditLatency {
+        view_id: ViewId,
+        #[serde(default)]
+        reset: bool,
+    },
+    /// Returns each plugin manifest found, with the name of the plugin if
+    /// it was loaded, and any problems found in the manifest.
+    DebugPluginCatalog {},
+    /// Returns the name and number of events of each saved recording.
+    ListRecordings {},
+}
+
+/// Optional protocol features a client can declare in `client_started`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ClientCapability {
+    /// The client keeps the annotations of a view between updates, so
+    /// annotation sets that have not changed need not be sent again.
+    RetainAnnotations,
+    /// The client themes highlight kinds, so highlight annotations carry a
+    /// description of their kind. Without it, highlights of kinds the
+    /// client doesn't know should get a generic highlight rendering.
+    HighlightKinds,
+    /// The client sizes the window of lines it requests by the
+    /// `prefetch_hint` in updates, and core may send lines ahead of the
+    /// viewport while the view scrolls quickly.
+    PrefetchHints,
+    /// The client applies the styles of an "update" op to lines it already
+    /// has, so lines whose text is unchanged need not be sent again when
+    /// only their styles change.
+    PartialLineUpdates,
 }
 
 /// A helper type, which extracts the `view_id` field from edit
@@ -296,6 +375,17 @@ pub enum SelectionGranularity {
     Line,
 }
 
+/// A Unicode normalization form, for the `normalize_unicode` edit.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Copy, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum NormalizationForm {
+    /// Canonical composition, e.g. "e" followed by a combining acute accent
+    /// becomes "é".
+    Nfc,
+    /// Canonical decomposition, the reverse of `Nfc`.
+    Nfd,
+}
+
 /// An enum representing touch and mouse gestures applied to the text.
 #[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Copy, Clone)]
 #[serde(rename_all = "snake_case")]
@@ -371,12 +461,50 @@ pub struct FindQuery {
     pub regex: bool,
     #[serde(default)]
     pub whole_words: bool,
+    #[serde(default)]
+    pub find_in_block: bool,
 }
 
+/// The largest `count` accepted by the repeatable edit notifications.
+pub const MAX_REPEAT_COUNT: u64 = 10_000;
+
+/// The edit notifications whose params are all optional, and which may be
+/// sent without them: the repeatable ones, from frontends that don't know
+/// about counts, and `replace_all`.
+const OPTIONAL_PARAMS_METHODS: &[&str] = &[
+    "delete_word_forward",
+    "delete_word_backward",
+    "move_up",
+    "move_up_and_modify_selection",
+    "move_down",
+    "move_down_and_modify_selection",
+    "move_left",
+    "move_backward",
+    "move_left_and_modify_selection",
+    "move_right",
+    "move_forward",
+    "move_right_and_modify_selection",
+    "move_word_left",
+    "move_word_left_and_modify_selection",
+    "move_word_right",
+    "move_word_right_and_modify_selection",
+    "move_to_beginning_of_paragraph",
+    "move_to_beginning_of_paragraph_and_modify_selection",
+    "move_to_end_of_paragraph",
+    "move_to_end_of_paragraph_and_modify_selection",
+    "replace_all",
+];
+
 /// The edit-related notifications.
 ///
 /// Alongside the [`EditRequest`] members, these commands constitute
 /// the API for interacting with a particular window and document.
+///
+/// The movement and word deletion commands take an optional `count`,
+/// which repeats the command as a single edit. It defaults to 1, and must
+/// be no greater than [`MAX_REPEAT_COUNT`].
+///
+/// [`MAX_REPEAT_COUNT`]: constant.MAX_REPEAT_COUNT.html
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
 #[serde(rename_all = "snake_case")]
 #[serde(tag = "method", content = "params")]
@@ -387,34 +515,99 @@ pub enum EditNotification {
     Paste {
         chars: String,
     },
+    PasteWithReindent {
+        chars: String,
+    },
     DeleteForward,
     DeleteBackward,
-    DeleteWordForward,
-    DeleteWordBackward,
+    DeleteWordForward {
+        #[serde(default = "default_count", deserialize_with = "deserialize_count")]
+        count: u64,
+    },
+    DeleteWordBackward {
+        #[serde(default = "default_count", deserialize_with = "deserialize_count")]
+        count: u64,
+    },
     DeleteToEndOfParagraph,
     DeleteToBeginningOfLine,
     InsertNewline,
     InsertTab,
-    MoveUp,
-    MoveUpAndModifySelection,
-    MoveDown,
-    MoveDownAndModifySelection,
-    MoveLeft,
+    MoveUp {
+        #[serde(default = "default_count", deserialize_with = "deserialize_count")]
+        count: u64,
+    },
+    MoveUpAndModifySelection {
+        #[serde(default = "default_count", deserialize_with = "deserialize_count")]
+        count: u64,
+    },
+    MoveDown {
+        #[serde(default = "default_count", deserialize_with = "deserialize_count")]
+        count: u64,
+    },
+    MoveDownAndModifySelection {
+        #[serde(default = "default_count", deserialize_with = "deserialize_count")]
+        count: u64,
+    },
+    MoveCaretColumnUp,
+    MoveCaretColumnDown,
+    MoveLeft {
+        #[serde(default = "default_count", deserialize_with = "deserialize_count")]
+        count: u64,
+    },
     // synoynm for `MoveLeft`
-    MoveBackward,
-    MoveLeftAndModifySelection,
-    MoveRight,
+    MoveBackward {
+        #[serde(default = "default_count", deserialize_with = "deserialize_count")]
+        count: u64,
+    },
+    MoveLeftAndModifySelection {
+        #[serde(default = "default_count", deserialize_with = "deserialize_count")]
+        count: u64,
+    },
+    MoveRight {
+        #[serde(default = "default_count", deserialize_with = "deserialize_count")]
+        count: u64,
+    },
     // synoynm for `MoveRight`
-    MoveForward,
-    MoveRightAndModifySelection,
-    MoveWordLeft,
-    MoveWordLeftAndModifySelection,
-    MoveWordRight,
-    MoveWordRightAndModifySelection,
-    MoveToBeginningOfParagraph,
-    MoveToBeginningOfParagraphAndModifySelection,
-    MoveToEndOfParagraph,
-    MoveToEndOfParagraphAndModifySelection,
+    MoveForward {
+        #[serde(default = "default_count", deserialize_with = "deserialize_count")]
+        count: u64,
+    },
+    MoveRightAndModifySelection {
+        #[serde(default = "default_count", deserialize_with = "deserialize_count")]
+        count: u64,
+    },
+    MoveWordLeft {
+        #[serde(default = "default_count", deserialize_with = "deserialize_count")]
+        count: u64,
+    },
+    MoveWordLeftAndModifySelection {
+        #[serde(default = "default_count", deserialize_with = "deserialize_count")]
+        count: u64,
+    },
+    MoveWordRight {
+        #[serde(default = "default_count", deserialize_with = "deserialize_count")]
+        count: u64,
+    },
+    MoveWordRightAndModifySelection {
+        #[serde(default = "default_count", deserialize_with = "deserialize_count")]
+        count: u64,
+    },
+    MoveToBeginningOfParagraph {
+        #[serde(default = "default_count", deserialize_with = "deserialize_count")]
+        count: u64,
+    },
+    MoveToBeginningOfParagraphAndModifySelection {
+        #[serde(default = "default_count", deserialize_with = "deserialize_count")]
+        count: u64,
+    },
+    MoveToEndOfParagraph {
+        #[serde(default = "default_count", deserialize_with = "deserialize_count")]
+        count: u64,
+    },
+    MoveToEndOfParagraphAndModifySelection {
+        #[serde(default = "default_count", deserialize_with = "deserialize_count")]
+        count: u64,
+    },
     MoveToLeftEndOfLine,
     MoveToLeftEndOfLineAndModifySelection,
     MoveToRightEndOfLine,
@@ -454,6 +647,8 @@ pub enum EditNotification {
         regex: bool,
         #[serde(default)]
         whole_words: bool,
+        #[serde(default)]
+        find_in_block: bool,
     },
     MultiFind {
         queries: Vec<FindQuery>,
@@ -483,7 +678,23 @@ pub enum EditNotification {
     Uppercase,
     Lowercase,
     Capitalize,
+    /// Sorts the contents of the selections, reassigning them to the
+    /// selections in position order.
+    SortSelectionContents,
+    /// Joins the lines of each selection into one, replacing the line
+    /// endings between them with `separator`, or a space if it is not given.
+    FlattenSelection {
+        #[serde(default)]
+        separator: Option<String>,
+    },
+    /// Splits each selection into lines at each occurrence of `sep`.
+    ExpandOnSeparator {
+        sep: String,
+    },
     Reindent,
+    /// Asks plugins to discard and recompute their state for the buffer,
+    /// such as syntax highlighting.
+    ForceRehighlight,
     Indent,
     Outdent,
     /// Indicates whether find highlights should be rendered
@@ -500,16 +711,61 @@ pub enum EditNotification {
         preserve_case: bool,
     },
     ReplaceNext,
-    ReplaceAll,
+    /// Replaces every find match in the buffer, as a single edit. The
+    /// replacement set by `Replace` is used if `chars` is not given.
+    ReplaceAll {
+        #[serde(default)]
+        chars: Option<String>,
+        #[serde(default)]
+        preserve_case: bool,
+    },
     SelectionForReplace,
     RequestHover {
         request_id: usize,
         position: Option<Position>,
     },
+    /// Asks plugins for completions at `position`, or at the cursor if it
+    /// is not given. Results are sent back with `show_completions`.
+    RequestCompletions {
+        request_id: usize,
+        position: Option<Position>,
+    },
+    /// Asks for the details of the item at `index` in the completions sent
+    /// for `request_id`, such as its documentation.
+    ResolveCompletion {
+        request_id: usize,
+        index: usize,
+    },
     SelectionIntoLines,
+    /// Selects every occurrence of the text of the last selection, or of
+    /// the word at its caret, in this view and in every other view of the
+    /// same buffer.
+    SelectAllOccurrencesAllViews,
     DuplicateLine,
+    ReflowListItem {
+        #[serde(default)]
+        column: Option<usize>,
+    },
     IncreaseNumber,
     DecreaseNumber,
+    /// Sets the number at each caret to `base` plus `step` times the
+    /// number of numbers before it.
+    IncrementColumn {
+        base: i64,
+        step: i64,
+    },
+    /// Normalizes the selected text to `form`, or the whole buffer if
+    /// nothing is selected.
+    NormalizeUnicode {
+        form: NormalizationForm,
+    },
+    /// Converts every line ending to `ending`, which is also used from then
+    /// on when inserting and saving.
+    SetLineEnding {
+        ending: LineEnding,
+    },
+    /// Converts any line endings that differ from the `line_ending` setting.
+    NormalizeLineEndings,
     ToggleRecording {
         recording_name: Option<String>,
     },
@@ -520,6 +776,42 @@ pub enum EditNotification {
         recording_name: String,
     },
     CollapseSelections,
+    /// Adds a bookmark to the given line, or removes it if one is present.
+    ToggleBookmark {
+        line: u64,
+    },
+    /// Moves the caret to the next bookmarked line, wrapping around.
+    NextBookmark,
+    /// Moves the caret to the previous bookmarked line, wrapping around.
+    PreviousBookmark,
+    ClearBookmarks,
+    /// Folds the lines in `line_range`: all but the first, which anchors the
+    /// fold, are hidden. Folds it overlaps are merged into it.
+    Fold {
+        line_range: LineRange,
+    },
+    /// Unfolds the folds anchored on or hiding any of the lines in `line_range`.
+    Unfold {
+        line_range: LineRange,
+    },
+    UnfoldAll,
+    /// Starts an input method composition at the selections. Until it is
+    /// committed or cancelled, its text is shown as an annotation rather
+    /// than inserted into the buffer.
+    CompositionStart,
+    /// Replaces the text of the composition in progress. `caret_offset_within`
+    /// is the byte offset of the input method's caret in `text`.
+    CompositionUpdate {
+        text: String,
+        caret_offset_within: usize,
+    },
+    /// Ends the composition in progress, inserting `text` at the selections
+    /// as a single edit.
+    CompositionCommit {
+        text: String,
+    },
+    /// Ends the composition in progress without changing the buffer.
+    CompositionCancel,
 }
 
 /// The edit related requests.
@@ -533,6 +825,25 @@ pub enum EditRequest {
     /// Copies the active selection, returning their contents or
     /// or `Null` if the selection was empty.
     Copy,
+    /// Returns the bookmarked lines, as a list of objects with `line`
+    /// and `snippet` fields, in buffer order.
+    GetBookmarks,
+    /// Searches the whole buffer, returning an object with a `matches` list
+    /// of `start`, `end` and `line` objects and a `truncated` flag. Unlike
+    /// `find`, this does not change the view's find state.
+    FindAll {
+        query: String,
+        #[serde(default)]
+        case_sensitive: bool,
+        #[serde(default)]
+        regex: bool,
+        #[serde(default)]
+        whole_words: bool,
+    },
+    /// Returns the lines with matches for the current search query, for
+    /// marking them on a scrollbar. If more than `max_lines` lines have
+    /// matches, they are downsampled to at most that many.
+    GetFindMatchLines { max_lines: usize },
 }
 
 /// The plugin related notifications.
@@ -588,11 +899,37 @@ impl<'de, T: Deserialize<'de>> Deserialize<'de> for EditCommand<T> {
             v.as_object_mut().map(|v| v.remove("params"));
         }
 
+        let has_optional_params = v
+            .get("method")
+            .and_then(Value::as_str)
+            .map_or(false, |m| OPTIONAL_PARAMS_METHODS.contains(&m));
+        if has_optional_params && v.get("params").is_none() {
+            v["params"] = json!({});
+        }
+
         let cmd = T::deserialize(v).map_err(de::Error::custom)?;
         Ok(EditCommand { view_id, cmd })
     }
 }
 
+fn default_count() -> u64 {
+    1
+}
+
+fn deserialize_count<'de, D>(deserializer: D) -> Result<u64, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let count = u64::deserialize(deserializer)?;
+    if count == 0 || count > MAX_REPEAT_COUNT {
+        return Err(de::Error::invalid_value(
+            de::Unexpected::Unsigned(count),
+            &format!("a count between 1 and {}", MAX_REPEAT_COUNT).as_str(),
+        ));
+    }
+    Ok(count)
+}
+
 impl Serialize for MouseAction {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -660,4 +997,33 @@ mod tests {
             assert_eq!(chars, message);
         }
     }
+
+    #[test]
+    fn test_repeat_count() {
+        let parse = |params: Value| {
+            let mut json = json!({"method": "move_down", "view_id": "view-id-1"});
+            if !params.is_null() {
+                json["params"] = params;
+            }
+            serde_json::from_value::<EditCommand<EditNotification>>(json).map(|c| c.cmd)
+        };
+        let move_down = |count| EditNotification::MoveDown { count };
+
+        // frontends that don't know about counts are unaffected
+        assert_eq!(parse(Value::Null).unwrap(), move_down(1));
+        assert_eq!(parse(json!({})).unwrap(), move_down(1));
+        assert_eq!(parse(json!([])).unwrap(), move_down(1));
+        assert_eq!(parse(json!({"count": 12})).unwrap(), move_down(12));
+        assert_eq!(parse(json!({ "count": MAX_REPEAT_COUNT })).unwrap(), move_down(10_000));
+
+        assert!(parse(json!({"count": 0})).is_err());
+        assert!(parse(json!({ "count": MAX_REPEAT_COUNT + 1 })).is_err());
+        assert!(parse(json!({"count": 1_000_000_000})).is_err());
+
+        for method in OPTIONAL_PARAMS_METHODS {
+            let json = json!({ "method": method, "view_id": "view-id-1" });
+            let cmd = serde_json::from_value::<EditCommand<EditNotification>>(json);
+            assert!(cmd.is_ok(), "{}: {:?}", method, cmd);
+        }
+    }
 }
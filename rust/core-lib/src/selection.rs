@@ -31,13 +31,19 @@ pub type HorizPos = usize;
 
 /// Indicates if an edit should try to drift inside or outside nearby selections. If the selection
 /// is zero width, that is, it is a caret, this value will be ignored, the equivalent of the
-/// `Default` value.
+/// `Default` value, except for `Trailing` and `InPlace`.
 #[derive(Copy, Clone)]
 pub enum InsertDrift {
     /// Indicates this edit should happen within any (non-caret) selections if possible.
     Inside,
     /// Indicates this edit should happen outside any selections if possible.
     Outside,
+    /// Indicates this edit should happen after the selections and carets
+    /// at its position, so that they stay in front of it.
+    Trailing,
+    /// Indicates this edit replaces text where it is, so that it happens
+    /// within any (non-caret) selections, and after carets at its position.
+    InPlace,
     /// Indicates to do whatever the `after` bool says to do
     Default,
 }
@@ -167,6 +173,10 @@ impl Selection {
         let mut ix = self.search(region.min());
 
         if ix < self.regions.len() && self.regions[ix].max() == region.min() {
+            // an empty region is only ambiguous with an equal one
+            if region.is_caret() && self.regions[ix].is_caret() {
+                return (region.min(), region.max());
+            }
             ix += 1;
         }
 
@@ -181,7 +191,7 @@ impl Selection {
         }
 
         // delete ambiguous regions to the right
-        let mut last = self.search(region.max());
+        let mut last = max(self.search(region.max()), ix);
         if last < self.regions.len() && self.regions[last].min() < region.max() {
             last += 1;
         }
@@ -211,8 +221,12 @@ impl Selection {
             let is_region_forward = region.start < region.end;
 
             let (start_after, end_after) = match (drift, is_caret) {
-                (InsertDrift::Inside, false) => (!is_region_forward, is_region_forward),
+                (InsertDrift::Inside, false) | (InsertDrift::InPlace, false) => {
+                    (!is_region_forward, is_region_forward)
+                }
+                (InsertDrift::InPlace, true) => (false, false),
                 (InsertDrift::Outside, false) => (is_region_forward, !is_region_forward),
+                (InsertDrift::Trailing, _) => (false, false),
                 _ => (after, after),
             };
 
@@ -607,6 +621,22 @@ mod tests {
         assert_eq!(s3.deref(), &[r(1, 1)]);
     }
 
+    #[test]
+    fn apply_delta_trailing_drift() {
+        let mut s = Selection::new();
+        s.add_region(r(1, 1));
+        s.add_region(r(2, 3));
+        assert_eq!(s.deref(), &[r(1, 1), r(2, 3)]);
+
+        // like converting "a\nb\nc" to "a\r\nb\r\nc", with carets and
+        // selections staying before the inserted "\r"s
+        let mut builder = DeltaBuilder::new("a\nb\nc".len());
+        builder.replace(Interval::new(1, 1), "\r".into());
+        builder.replace(Interval::new(3, 3), "\r".into());
+        let s2 = s.apply_delta(&builder.build(), true, InsertDrift::Trailing);
+        assert_eq!(s2.deref(), &[r(1, 1), r(3, 4)]);
+    }
+
     #[test]
     fn display() {
         let mut s = Selection::new();
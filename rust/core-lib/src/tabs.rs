@@ -20,17 +20,21 @@
 //! be renamed.
 
 use std::cell::{Cell, RefCell};
-use std::collections::{BTreeMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
 use std::fmt;
 use std::fs::File;
 use std::io;
 use std::mem;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use serde::de::{self, Deserialize, Deserializer, Unexpected};
 use serde::ser::{Serialize, Serializer};
 use serde_json::Value;
 
+use xi_rope::engine::RevToken;
 use xi_rope::Rope;
 use xi_rpc::{self, ReadError, RemoteError, RpcCtx, RpcPeer};
 use xi_trace::{self, trace_block};
@@ -39,11 +43,17 @@ use crate::client::Client;
 use crate::config::{self, ConfigDomain, ConfigDomainExternal, ConfigManager, Table};
 use crate::editor::Editor;
 use crate::event_context::EventContext;
-use crate::file::FileManager;
+use crate::file::{related_files, FileManager};
 use crate::line_ending::LineEnding;
+use crate::maintenance::{
+    Maintenance, MaintenanceTask, TaskPriority, MAINTENANCE_BUDGET, TYPING_PAUSE,
+};
 use crate::plugin_rpc::{PluginNotification, PluginRequest};
 use crate::plugins::rpc::ClientPluginInfo;
-use crate::plugins::{start_plugin_process, Plugin, PluginCatalog, PluginPid};
+use crate::plugins::{
+    start_plugin_process, Plugin, PluginCapability, PluginCatalog, PluginDescription, PluginPid,
+    PluginState,
+};
 use crate::recorder::Recorder;
 use crate::rpc::{
     CoreNotification, CoreRequest, EditNotification, EditRequest,
@@ -51,42 +61,91 @@ use crate::rpc::{
 };
 use crate::styles::{ThemeStyleMap, DEFAULT_THEME};
 use crate::syntax::LanguageId;
-use crate::view::View;
+use crate::truncate::middle_ellipsis;
+use crate::view::{LinePosition, View};
 use crate::whitespace::Indentation;
 use crate::width_cache::WidthCache;
 use crate::WeakXiCore;
 
 #[cfg(feature = "notify")]
-use crate::watcher::{FileWatcher, WatchToken};
+use crate::config::PROJECT_CONFIG_FILE_NAME;
+#[cfg(feature = "notify")]
+use crate::watcher::{FileWatcher, FsEventKind, WatchToken};
 #[cfg(feature = "notify")]
 use notify::Event;
 #[cfg(feature = "notify")]
 use std::ffi::OsStr;
 
+// Identifiers on the wire
+//
+// `ViewId`s and `BufferId`s are sent as strings, `view-id-N` and
+// `buffer-id-N`, where `N` is a decimal number between 0 and `MAX_ID`, with
+// no sign or leading zeros. The prefix doubles as the version of the format:
+// peers should treat ids as opaque and only compare them for equality. Ids
+// that don't match this format exactly are rejected with a `ParseIdError`,
+// which surfaces as an invalid request at the RPC boundary.
+//
+// For compatibility, a `BufferId` may also be deserialized from its bare
+// number, which is how it was sent before.
+
 /// ViewIds are the primary means of routing messages between
 /// xi-core and a client view.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct ViewId(pub(crate) usize);
 
 /// BufferIds uniquely identify open buffers.
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct BufferId(pub(crate) usize);
 
+/// The largest numeric part of a `ViewId` or `BufferId`. Idle tokens for a
+/// view combine its id with one of the `*_VIEW_IDLE_MASK` bits, so ids have
+/// to stay below all of them.
+pub const MAX_ID: usize = FORMAT_VIEW_IDLE_MASK - 1;
+
+const VIEW_ID_PREFIX: &str = "view-id-";
+const BUFFER_ID_PREFIX: &str = "buffer-id-";
+
+/// An error returned when a string is not a valid `ViewId` or `BufferId`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseIdError {
+    expected: &'static str,
+    input: String,
+}
+
 pub type PluginId = crate::plugins::PluginPid;
 
 // old-style names; will be deprecated
 pub type BufferIdentifier = BufferId;
 
 /// Totally arbitrary; we reserve this space for `ViewId`s
+pub(crate) const FORMAT_VIEW_IDLE_MASK: usize = 1 << 24;
 pub(crate) const RENDER_VIEW_IDLE_MASK: usize = 1 << 25;
 pub(crate) const REWRAP_VIEW_IDLE_MASK: usize = 1 << 26;
 pub(crate) const FIND_VIEW_IDLE_MASK: usize = 1 << 27;
+pub(crate) const FIND_STATUS_VIEW_IDLE_MASK: usize = 1 << 28;
+pub(crate) const PASTE_VIEW_IDLE_MASK: usize = 1 << 29;
+pub(crate) const AUTOSAVE_VIEW_IDLE_MASK: usize = 1 << 30;
+/// Reserved for the `PluginId`s of warmed plugins.
+pub(crate) const WARM_PLUGIN_IDLE_MASK: usize = 1 << 31;
 
 const NEW_VIEW_IDLE_TOKEN: usize = 1001;
 
+/// How long a save waits for a plugin to format the buffer before the
+/// buffer is saved as it is.
+const FORMAT_ON_SAVE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The largest content, in bytes, a plugin may put in a scratch view.
+const MAX_SCRATCH_VIEW_SIZE: usize = 1 << 24;
+
+/// The maximum width, in columns, of a path shown in an alert. Longer paths
+/// lose their middle, so the file name stays visible.
+const MAX_ALERT_PATH_COLS: usize = 60;
+
 /// xi_rpc idle Token for watcher related idle scheduling.
 pub(crate) const WATCH_IDLE_TOKEN: usize = 1002;
 
+const MAINTENANCE_IDLE_TOKEN: usize = 1003;
+
 #[cfg(feature = "notify")]
 const CONFIG_EVENT_TOKEN: WatchToken = WatchToken(1);
 
@@ -100,6 +159,14 @@ const THEME_FILE_EVENT_TOKEN: WatchToken = WatchToken(3);
 #[cfg(feature = "notify")]
 const PLUGIN_EVENT_TOKEN: WatchToken = WatchToken(4);
 
+#[cfg(feature = "notify")]
+const PROJECT_CONFIG_EVENT_TOKEN: WatchToken = WatchToken(5);
+
+/// Paths watched for plugins use tokens from here up, so that they never
+/// share one with core's own watches.
+#[cfg(feature = "notify")]
+const FIRST_PLUGIN_WATCH_TOKEN: usize = 1 << 16;
+
 #[allow(dead_code)]
 pub struct CoreState {
     editors: BTreeMap<BufferId, RefCell<Editor>>,
@@ -119,11 +186,46 @@ pub struct CoreState {
     self_ref: Option<WeakXiCore>,
     /// Views which need to have setup finished.
     pending_views: Vec<(ViewId, Table)>,
+    /// Saves waiting for a plugin to format the buffer: the path to save
+    /// to, the revision sent to be formatted, and when to stop waiting.
+    pending_format_saves: BTreeMap<ViewId, (PathBuf, RevToken, Instant)>,
     peer: Client,
     id_counter: Counter,
     plugins: PluginCatalog,
-    // for the time being we auto-start all plugins we find on launch.
     running_plugins: Vec<Plugin>,
+    /// Plugins which are started when the next buffer is opened.
+    deferred_plugins: Vec<Arc<PluginDescription>>,
+    /// Plugins which have been restarted after exiting while warmed; each
+    /// is restarted at most once.
+    respawned_plugins: BTreeSet<String>,
+    /// Plugins started by their activations which have not yet connected.
+    /// One that fails to start stays here, so that it is not started again
+    /// for every buffer.
+    activating_plugins: BTreeSet<String>,
+    /// Plugins started by the client or by one of their commands, which are
+    /// not stopped when no buffer activates them.
+    pinned_plugins: BTreeSet<String>,
+    /// Commands sent to plugins that are still starting: the plugin, the
+    /// view, the method and its params.
+    pending_plugin_rpcs: Vec<(String, ViewId, String, Value)>,
+    /// Views created by plugins, and the plugin that created each of them.
+    scratch_views: BTreeMap<ViewId, PluginId>,
+    /// Work done while the user is idle.
+    maintenance: Maintenance,
+    /// The project config files whose directories are being watched.
+    #[cfg(feature = "notify")]
+    watched_project_configs: BTreeSet<PathBuf>,
+    /// Paths watched for plugins, by the token of their `WatchToken`.
+    #[cfg(feature = "notify")]
+    plugin_watches: BTreeMap<usize, PluginWatch>,
+}
+
+/// A path a plugin is watching, and the token the plugin knows it by.
+#[cfg(feature = "notify")]
+struct PluginWatch {
+    plugin: PluginId,
+    token: u64,
+    path: PathBuf,
 }
 
 /// Initial setup and bookkeeping
@@ -166,6 +268,9 @@ impl CoreState {
             watcher.watch_filtered(p, true, PLUGIN_EVENT_TOKEN, |p| p.is_dir() || !p.exists());
         }
 
+        let mut maintenance = Maintenance::new(MAINTENANCE_BUDGET, TYPING_PAUSE);
+        maintenance.register_core_tasks();
+
         CoreState {
             views: BTreeMap::new(),
             editors: BTreeMap::new(),
@@ -180,19 +285,31 @@ impl CoreState {
             recorder: RefCell::new(Recorder::new()),
             self_ref: None,
             pending_views: Vec::new(),
+            pending_format_saves: BTreeMap::new(),
             peer: Client::new(peer.clone()),
             id_counter: Counter::default(),
             plugins: PluginCatalog::default(),
             running_plugins: Vec::new(),
+            deferred_plugins: Vec::new(),
+            respawned_plugins: BTreeSet::new(),
+            activating_plugins: BTreeSet::new(),
+            pinned_plugins: BTreeSet::new(),
+            pending_plugin_rpcs: Vec::new(),
+            scratch_views: BTreeMap::new(),
+            maintenance,
+            #[cfg(feature = "notify")]
+            watched_project_configs: BTreeSet::new(),
+            #[cfg(feature = "notify")]
+            plugin_watches: BTreeMap::new(),
         }
     }
 
     fn next_view_id(&self) -> ViewId {
-        ViewId(self.id_counter.next())
+        ViewId::new(self.id_counter.next())
     }
 
     fn next_buffer_id(&self) -> BufferId {
-        BufferId(self.id_counter.next())
+        BufferId::new(self.id_counter.next())
     }
 
     fn next_plugin_id(&self) -> PluginId {
@@ -220,16 +337,29 @@ impl CoreState {
         let theme_names = self.style_map.borrow().get_theme_names();
         self.peer.available_themes(theme_names);
 
-        // FIXME: temporary: we just launch every plugin we find at startup
-        for manifest in self.plugins.iter() {
-            start_plugin_process(
-                manifest.clone(),
-                self.next_plugin_id(),
-                self.self_ref.as_ref().unwrap().clone(),
-            );
+        // preloaded plugins are warmed now, so that they are ready when the
+        // first buffer opens; the others are started then. Plugins with
+        // activations wait for a buffer, or a command, that matches one.
+        let preload_all = self.config_manager.default_buffer_config().items.preload_plugins;
+        let (preloaded, deferred) = self
+            .plugins
+            .iter()
+            .filter(|m| m.autoruns())
+            .partition::<Vec<_>, _>(|m| preload_all || m.preload);
+        self.deferred_plugins = deferred;
+        for manifest in preloaded {
+            self.start_plugin(manifest);
         }
     }
 
+    fn start_plugin(&self, manifest: Arc<PluginDescription>) {
+        start_plugin_process(
+            manifest,
+            self.next_plugin_id(),
+            self.self_ref.as_ref().unwrap().clone(),
+        );
+    }
+
     /// Attempt to load a config file.
     fn load_file_based_config(&mut self, path: &Path) {
         let _t = trace_block("CoreState::load_config_file", &["core"]);
@@ -251,6 +381,30 @@ impl CoreState {
         }
     }
 
+    /// Watches the directories of the project config files used by open
+    /// buffers, so that edits to them are applied, and stops watching those
+    /// no longer used.
+    #[cfg(feature = "notify")]
+    fn update_project_config_watches(&mut self) {
+        let projects = self.config_manager.project_config_paths();
+        let watcher = self.file_manager.watcher();
+        for path in self.watched_project_configs.difference(&projects) {
+            watcher.unwatch(path.parent().unwrap(), PROJECT_CONFIG_EVENT_TOKEN);
+        }
+        for path in projects.difference(&self.watched_project_configs) {
+            watcher.watch_filtered(
+                path.parent().unwrap(),
+                false,
+                PROJECT_CONFIG_EVENT_TOKEN,
+                |p| p.file_name() == Some(OsStr::new(PROJECT_CONFIG_FILE_NAME)),
+            );
+        }
+        self.watched_project_configs = projects;
+    }
+
+    #[cfg(not(feature = "notify"))]
+    fn update_project_config_watches(&mut self) {}
+
     /// Notify editors/views/plugins of config changes.
     fn handle_config_changes(&self, changes: Vec<(BufferId, Table)>) {
         for (id, table) in changes {
@@ -277,8 +431,18 @@ impl CoreState {
             let buffer_id = view.borrow().get_buffer_id();
 
             let editor = &self.editors[&buffer_id];
+            let siblings = self
+                .views
+                .iter()
+                .filter(|(id, v)| **id != view_id && v.borrow().get_buffer_id() == buffer_id)
+                .map(|(_, v)| v)
+                .collect();
             let info = self.file_manager.get_info(buffer_id);
-            let plugins = self.running_plugins.iter().collect::<Vec<_>>();
+            let plugins = self
+                .running_plugins
+                .iter()
+                .filter(|p| p.state != PluginState::Stopping)
+                .collect::<Vec<_>>();
             let config = self.config_manager.get_buffer_config(buffer_id);
             let language = self.config_manager.get_buffer_language(buffer_id);
 
@@ -288,10 +452,11 @@ impl CoreState {
                 view,
                 editor,
                 config: &config.items,
+                config_generation: config.generation(),
                 recorder: &self.recorder,
                 language,
                 info,
-                siblings: Vec::new(),
+                siblings,
                 plugins,
                 client: &self.peer,
                 style_map: &self.style_map,
@@ -312,7 +477,10 @@ impl CoreState {
         use self::CoreNotification::*;
         use self::CorePluginNotification as PN;
         match cmd {
-            Edit(crate::rpc::EditCommand { view_id, cmd }) => self.do_edit(view_id, cmd),
+            Edit(crate::rpc::EditCommand { view_id, cmd }) => {
+                self.maintenance.note_input(Instant::now());
+                self.do_edit(view_id, cmd)
+            }
             Save { view_id, file_path } => self.do_save(view_id, file_path),
             CloseView { view_id } => self.do_close_view(view_id),
             ModifyUserConfig { domain, changes } => self.do_modify_user_config(domain, changes),
@@ -321,16 +489,38 @@ impl CoreState {
                 self.save_trace(&destination, frontend_samples)
             }
             Plugin(cmd) => match cmd {
-                PN::Start { view_id, plugin_name } => self.do_start_plugin(view_id, &plugin_name),
-                PN::Stop { view_id, plugin_name } => self.do_stop_plugin(view_id, &plugin_name),
+                PN::Start { view_id, plugin_name } => {
+                    self.pinned_plugins.insert(plugin_name.clone());
+                    self.do_start_plugin(view_id, &plugin_name)
+                }
+                PN::Stop { view_id, plugin_name } => {
+                    self.pinned_plugins.remove(&plugin_name);
+                    self.do_stop_plugin(view_id, &plugin_name)
+                }
                 PN::PluginRpc { view_id, receiver, rpc } => {
                     self.do_plugin_rpc(view_id, &receiver, &rpc.method, &rpc.params)
                 }
             },
             TracingConfig { enabled } => self.toggle_tracing(enabled),
-            // handled at the top level
-            ClientStarted { .. } => (),
+            // the rest is handled at the top level
+            ClientStarted { capabilities, .. } => self.peer.set_capabilities(capabilities),
             SetLanguage { view_id, language_id } => self.do_set_language(view_id, language_id),
+            SetBom { view_id, present } => self.do_set_bom(view_id, present),
+            ConfirmExternalSave { view_id } => self.do_confirm_external_save(view_id),
+            ToggleRelatedFile { view_id } => self.do_toggle_related_file(view_id),
+            DebugSetRenderJournal { view_id, enabled } => {
+                if let Some(view) = self.views.get(&view_id) {
+                    view.borrow_mut().set_render_journal(enabled);
+                }
+            }
+            SaveRecording { name, path } => {
+                let result = self.recorder.borrow().save(&name, &path);
+                self.report_recording_error(result);
+            }
+            LoadRecording { path } => {
+                let result = self.recorder.borrow_mut().load(&path).map(|_| ());
+                self.report_recording_error(result);
+            }
         }
     }
 
@@ -344,13 +534,54 @@ impl CoreState {
             //TODO: why is this a request?? make a notification?
             GetConfig { view_id } => self.do_get_config(view_id).map(|c| json!(c)),
             DebugGetContents { view_id } => self.do_get_contents(view_id).map(|c| json!(c)),
+            SplitView { view_id } => self.do_split_view(view_id),
+            RetrySaveElevated { view_id } => self.do_retry_save_elevated(view_id),
+            OffsetToLineCol { view_id, offsets } => {
+                self.do_offset_to_line_col(view_id, &offsets).map(|p| json!(p))
+            }
+            DebugRenderJournal { view_id, path } => {
+                self.do_debug_render_journal(view_id, path.as_deref())
+            }
+            DebugEditLatency { view_id, reset } => self.do_debug_edit_latency(view_id, reset),
+            DebugPluginCatalog {} => Ok(json!(self.plugins.reports().collect::<Vec<_>>())),
+            ListRecordings {} => Ok(json!(self.recorder.borrow().list())),
+        }
+    }
+
+    /// Save and load are notifications, so failures are shown to the user.
+    fn report_recording_error(&self, result: Result<(), RemoteError>) {
+        if let Err(err) = result {
+            error!("recording error: {:?}", err);
+            if let RemoteError::Custom { message, .. } = err {
+                self.peer.alert(message);
+            }
         }
     }
 
     fn do_edit(&mut self, view_id: ViewId, cmd: EditNotification) {
+        let new_line_ending = match cmd {
+            EditNotification::SetLineEnding { ending } => Some(ending),
+            _ => None,
+        };
         if let Some(mut edit_ctx) = self.make_context(view_id) {
             edit_ctx.do_edit(cmd);
         }
+        if let Some(ending) = new_line_ending {
+            self.do_set_line_ending(view_id, ending);
+        }
+    }
+
+    /// Overrides the buffer's `line_ending`, after its text was converted.
+    fn do_set_line_ending(&mut self, view_id: ViewId, ending: LineEnding) {
+        let buffer_id = match self.views.get(&view_id) {
+            Some(view) => view.borrow().get_buffer_id(),
+            None => return,
+        };
+        let mut changes = Table::new();
+        changes.insert("line_ending".into(), ending.as_str().into());
+        let domain = ConfigDomain::UserOverride(buffer_id);
+        let new_config = self.config_manager.table_for_update(domain.clone(), changes);
+        self.set_config(domain, new_config);
     }
 
     fn do_edit_sync(&mut self, view_id: ViewId, cmd: EditRequest) -> Result<Value, RemoteError> {
@@ -378,6 +609,7 @@ impl CoreState {
         self.views.insert(view_id, view);
 
         let config = self.config_manager.add_buffer(buffer_id, path.as_deref());
+        self.update_project_config_watches();
 
         // NOTE: because this is a synchronous call, we have to initialize the
         // view and return the view_id before we can send any events to this
@@ -394,6 +626,29 @@ impl CoreState {
         Ok(json!(view_id))
     }
 
+    fn do_split_view(&mut self, view_id: ViewId) -> Result<Value, RemoteError> {
+        let buffer_id = match self.views.get(&view_id) {
+            Some(view) => view.borrow().get_buffer_id(),
+            None => {
+                return Err(RemoteError::custom(404, format!("missing view {:?}", view_id), None))
+            }
+        };
+        let new_view_id = self.next_view_id();
+        let view = RefCell::new(View::new(new_view_id, buffer_id));
+        self.views.insert(new_view_id, view);
+
+        let config = self.config_manager.get_buffer_config(buffer_id).to_table();
+
+        // the new view is finished on the next runloop pass, as in do_new_view.
+        let mut edit_ctx = self.make_context(new_view_id).unwrap();
+        edit_ctx.split_init(view_id);
+
+        self.pending_views.push((new_view_id, config));
+        self.peer.schedule_idle(NEW_VIEW_IDLE_TOKEN);
+
+        Ok(json!(new_view_id))
+    }
+
     fn do_save<P>(&mut self, view_id: ViewId, path: P)
     where
         P: AsRef<Path>,
@@ -406,18 +661,95 @@ impl CoreState {
             None => return,
         };
 
+        // a buffer that matches its file needs no write; this makes repeated
+        // saves cheap. A file changed on disk still goes through `save`, which
+        // reports the conflict.
+        let is_pristine = self.editors[&buffer_id].borrow().is_pristine();
+        if is_pristine && self.file_manager.is_current_file(path, buffer_id) {
+            info!("skipping save of unmodified {:?}", path);
+            return;
+        }
+
+        if !self.format_before_save(view_id, buffer_id, path) {
+            self.write_save(view_id, buffer_id, path);
+        }
+    }
+
+    /// Asks a plugin with the `formatting` capability to format the buffer,
+    /// if `format_on_save` is set, deferring the save until it responds or
+    /// `FORMAT_ON_SAVE_TIMEOUT` passes. Returns `false` if the buffer should
+    /// be saved now.
+    fn format_before_save(&mut self, view_id: ViewId, buffer_id: BufferId, path: &Path) -> bool {
+        if !self.config_manager.get_buffer_config(buffer_id).items.format_on_save {
+            return false;
+        }
+        // a save while one is waiting just changes where it is saved
+        if let Some(pending) = self.pending_format_saves.get_mut(&view_id) {
+            pending.0 = path.to_owned();
+            return true;
+        }
+        let plugin = self.running_plugins.iter().find(|p| {
+            p.state != PluginState::Stopping && p.has_capability(PluginCapability::Formatting)
+        });
+        let plugin = match plugin {
+            Some(plugin) => plugin,
+            None => {
+                info!("no plugin can format {:?}, saving it as is", path);
+                return false;
+            }
+        };
+
+        // the revision is kept until the plugin's delta has been applied
+        let mut editor = self.editors[&buffer_id].borrow_mut();
+        editor.increment_revs_in_flight();
+        let rev = editor.get_head_rev_token();
+        let weak_core = self.self_ref.clone().unwrap();
+        plugin.format(view_id, rev, move |response| {
+            weak_core.handle_format_response(view_id, rev, response)
+        });
+
+        let deadline = Instant::now() + FORMAT_ON_SAVE_TIMEOUT;
+        let view_token: usize = view_id.into();
+        self.peer.schedule_timer(deadline, FORMAT_VIEW_IDLE_MASK | view_token);
+        self.pending_format_saves.insert(view_id, (path.to_owned(), rev, deadline));
+        true
+    }
+
+    /// Saves a buffer waiting to be formatted, applying the plugin's
+    /// `response` first if it arrived.
+    fn finish_format_save(
+        &mut self,
+        view_id: ViewId,
+        response: Option<Result<Value, xi_rpc::Error>>,
+    ) {
+        let (path, rev, _) = match self.pending_format_saves.remove(&view_id) {
+            Some(pending) => pending,
+            None => return,
+        };
+        let buffer_id = match self.views.get(&view_id) {
+            Some(view) => view.borrow().get_buffer_id(),
+            None => return,
+        };
+        self.make_context(view_id).unwrap().finish_format(rev, response);
+        self.write_save(view_id, buffer_id, &path);
+    }
+
+    /// Writes the buffer to `path`, and tells the client and plugins.
+    fn write_save(&mut self, view_id: ViewId, buffer_id: BufferId, path: &Path) {
         let mut save_ctx = self.make_context(view_id).unwrap();
         let fin_text = save_ctx.text_for_save();
 
         if let Err(e) = self.file_manager.save(path, &fin_text, buffer_id) {
             let error_message = e.to_string();
             error!("File error: {:?}", error_message);
+            self.peer.save_failed(view_id, path, e.save_error_kind(), &error_message);
             self.peer.alert(error_message);
             return;
         }
 
         let changes = self.config_manager.update_buffer_path(buffer_id, path);
         let language = self.config_manager.get_buffer_language(buffer_id);
+        self.update_project_config_watches();
 
         self.make_context(view_id).unwrap().after_save(path);
         self.make_context(view_id).unwrap().language_changed(&language);
@@ -426,9 +758,106 @@ impl CoreState {
         if let Some(changes) = changes {
             self.make_context(view_id).unwrap().config_changed(&changes);
         }
+        self.update_activated_plugins();
+    }
+
+    fn do_retry_save_elevated(&mut self, view_id: ViewId) -> Result<Value, RemoteError> {
+        let buffer_id = match self.views.get(&view_id) {
+            Some(view) => view.borrow().get_buffer_id(),
+            None => {
+                return Err(RemoteError::custom(404, format!("missing view {:?}", view_id), None))
+            }
+        };
+        let path = match self.file_manager.get_failed_save(buffer_id) {
+            Some(path) => path.to_owned(),
+            None => {
+                let msg = format!("no failed save for {:?}", view_id);
+                return Err(RemoteError::custom(404, msg, None));
+            }
+        };
+
+        let text = self.make_context(view_id).unwrap().text_for_save();
+        let rev_id = self.editors[&buffer_id].borrow().get_head_rev_id();
+        let save = self.file_manager.save_to_temp(&path, &text, buffer_id, rev_id)?;
+        Ok(json!({
+            "path": save.path,
+            "temp_path": save.temp_path,
+        }))
+    }
+
+    fn do_confirm_external_save(&mut self, view_id: ViewId) {
+        let buffer_id = match self.views.get(&view_id) {
+            Some(view) => view.borrow().get_buffer_id(),
+            None => return,
+        };
+        let save = match self.file_manager.confirm_external_save(buffer_id) {
+            Some(save) => save,
+            None => {
+                warn!("no external save of {:?} to confirm", view_id);
+                return;
+            }
+        };
+
+        let changes = self.config_manager.update_buffer_path(buffer_id, &save.path);
+        let language = self.config_manager.get_buffer_language(buffer_id);
+        self.update_project_config_watches();
+
+        self.make_context(view_id).unwrap().after_external_save(&save.path, save.rev_id);
+        self.make_context(view_id).unwrap().language_changed(&language);
+
+        if let Some(changes) = changes {
+            self.make_context(view_id).unwrap().config_changed(&changes);
+        }
+        self.update_activated_plugins();
+    }
+
+    /// Shows the first existing file related to the file of this view, as
+    /// given by the buffer's `related_files` rules, opening it if it is not
+    /// already open.
+    fn do_toggle_related_file(&mut self, view_id: ViewId) {
+        let buffer_id = match self.views.get(&view_id) {
+            Some(view) => view.borrow().get_buffer_id(),
+            None => return,
+        };
+        let path = match self.file_manager.get_info(buffer_id) {
+            Some(info) => info.path.clone(),
+            None => return,
+        };
+        let rules = &self.config_manager.get_buffer_config(buffer_id).items.related_files;
+        let related = match related_files(&path, rules).into_iter().find(|p| p.is_file()) {
+            Some(related) => related,
+            None => {
+                let msg = format!("No related file found for {}", alert_path(&path));
+                self.peer.alert(msg);
+                return;
+            }
+        };
+        let title = related.file_name().map(|n| n.to_string_lossy().into_owned());
+        let title = title.unwrap_or_default();
+
+        let open_view = self.file_manager.get_editor(&related).and_then(|buffer_id| {
+            let mut views = self.views.iter();
+            views.find(|(_, v)| v.borrow().get_buffer_id() == buffer_id).map(|(id, _)| *id)
+        });
+        let related_view = match open_view {
+            Some(related_view) => related_view,
+            None => match self.do_new_view(Some(related.clone())) {
+                Ok(related_view) => serde_json::from_value(related_view).unwrap(),
+                Err(err) => {
+                    warn!("failed to open related file {:?}: {:?}", related, err);
+                    self.peer.alert(format!("Couldn't open {}", alert_path(&related)));
+                    return;
+                }
+            },
+        };
+        self.peer.show_view(related_view, &title);
     }
 
     fn do_close_view(&mut self, view_id: ViewId) {
+        // a save waiting for formatting is finished first
+        self.finish_format_save(view_id, None);
+        self.scratch_views.remove(&view_id);
+        self.peer.view_closed(view_id);
         let close_buffer = self.make_context(view_id).map(|ctx| ctx.close_view()).unwrap_or(true);
 
         let buffer_id = self.views.remove(&view_id).map(|v| v.borrow().get_buffer_id());
@@ -438,6 +867,8 @@ impl CoreState {
                 self.editors.remove(&buffer_id);
                 self.file_manager.close(buffer_id);
                 self.config_manager.remove_buffer(buffer_id);
+                self.update_project_config_watches();
+                self.update_activated_plugins();
             }
         }
     }
@@ -500,6 +931,54 @@ impl CoreState {
             .ok_or_else(|| RemoteError::custom(404, format!("No view for id {}", view_id), None))
     }
 
+    fn do_offset_to_line_col(
+        &self,
+        view_id: ViewId,
+        offsets: &[usize],
+    ) -> Result<Vec<LinePosition>, RemoteError> {
+        self.make_context(view_id)
+            .map(|ctx| ctx.offsets_to_positions(offsets))
+            .ok_or_else(|| RemoteError::custom(404, format!("No view for id {}", view_id), None))
+    }
+
+    fn do_debug_render_journal(
+        &self,
+        view_id: ViewId,
+        path: Option<&Path>,
+    ) -> Result<Value, RemoteError> {
+        let view = self
+            .views
+            .get(&view_id)
+            .ok_or_else(|| RemoteError::custom(404, format!("No view for id {}", view_id), None))?;
+        let journal = match view.borrow().render_journal() {
+            Some(journal) => journal.to_json(),
+            None => return Err(RemoteError::custom(400, "render journal is disabled", None)),
+        };
+        if let Some(path) = path {
+            File::create(path)
+                .map_err(serde_json::Error::io)
+                .and_then(|f| serde_json::to_writer_pretty(f, &journal))
+                .map_err(|e| {
+                    let msg = format!("could not write render journal to {:?}: {}", path, e);
+                    RemoteError::custom(500, msg, None)
+                })?;
+        }
+        Ok(journal)
+    }
+
+    fn do_debug_edit_latency(&self, view_id: ViewId, reset: bool) -> Result<Value, RemoteError> {
+        let view = self
+            .views
+            .get(&view_id)
+            .ok_or_else(|| RemoteError::custom(404, format!("No view for id {}", view_id), None))?;
+        let mut editor = self.editors[&view.borrow().get_buffer_id()].borrow_mut();
+        let report = json!(editor.edit_latency().report());
+        if reset {
+            editor.edit_latency_mut().reset();
+        }
+        Ok(report)
+    }
+
     fn do_set_language(&mut self, view_id: ViewId, language_id: LanguageId) {
         if let Some(view) = self.views.get(&view_id) {
             let buffer_id = view.borrow().get_buffer_id();
@@ -510,11 +989,30 @@ impl CoreState {
             if let Some(changes) = changes {
                 context.config_changed(&changes);
             }
+            self.update_activated_plugins();
+        }
+    }
+
+    fn do_set_bom(&mut self, view_id: ViewId, present: bool) {
+        let buffer_id = match self.views.get(&view_id) {
+            Some(view) => view.borrow().get_buffer_id(),
+            None => return,
+        };
+        if self.file_manager.get_info(buffer_id).is_none() {
+            warn!("cannot set the BOM of {:?}, which has no file", view_id);
+            return;
+        }
+        match self.file_manager.set_bom(buffer_id, present) {
+            Ok(true) => self.make_context(view_id).unwrap().bom_changed(),
+            Ok(false) => (),
+            Err(e) => self.peer.alert(e.to_string()),
         }
     }
 
     fn do_start_plugin(&mut self, _view_id: ViewId, plugin: &str) {
-        if self.running_plugins.iter().any(|p| p.name == plugin) {
+        if self.running_plugins.iter().any(|p| p.name == plugin && p.state != PluginState::Stopping)
+            || self.activating_plugins.contains(plugin)
+        {
             info!("plugin {} already running", plugin);
             return;
         }
@@ -522,21 +1020,22 @@ impl CoreState {
         if let Some(manifest) = self.plugins.get_named(plugin) {
             //TODO: lots of races possible here, we need to keep track of
             //pending launches.
-            start_plugin_process(
-                manifest,
-                self.next_plugin_id(),
-                self.self_ref.as_ref().unwrap().clone(),
-            );
+            self.deferred_plugins.retain(|m| m.name != plugin);
+            if !manifest.autoruns() {
+                self.activating_plugins.insert(manifest.name.clone());
+            }
+            self.start_plugin(manifest);
         } else {
             warn!("no plugin found with name '{}'", plugin);
         }
     }
 
     fn do_stop_plugin(&mut self, _view_id: ViewId, plugin: &str) {
+        self.deferred_plugins.retain(|m| m.name != plugin);
         if let Some(p) = self
             .running_plugins
             .iter()
-            .position(|p| p.name == plugin)
+            .position(|p| p.name == plugin && p.state != PluginState::Stopping)
             .map(|ix| self.running_plugins.remove(ix))
         {
             //TODO: verify shutdown; kill if necessary
@@ -545,16 +1044,74 @@ impl CoreState {
         }
     }
 
-    fn do_plugin_rpc(&self, view_id: ViewId, receiver: &str, method: &str, params: &Value) {
-        self.running_plugins
+    fn do_plugin_rpc(&mut self, view_id: ViewId, receiver: &str, method: &str, params: &Value) {
+        let is_running = self.running_plugins.iter().any(|p| p.name == receiver);
+        match self.plugins.get_named(receiver).filter(|m| m.activated_by_command(method)) {
+            Some(manifest) if !is_running => {
+                // sent once the plugin connects
+                let rpc = (receiver.to_owned(), view_id, method.to_owned(), params.clone());
+                self.pending_plugin_rpcs.push(rpc);
+                self.pinned_plugins.insert(manifest.name.clone());
+                if self.activating_plugins.insert(manifest.name.clone()) {
+                    self.start_plugin(manifest);
+                }
+            }
+            _ => self
+                .running_plugins
+                .iter()
+                .filter(|p| p.name == receiver)
+                .for_each(|p| p.dispatch_command(view_id, method, params)),
+        }
+    }
+
+    /// Starts the plugins activated by the open buffers, and stops those
+    /// which were but no longer are, unless they were started some other way.
+    fn update_activated_plugins(&mut self) {
+        let buffers = self
+            .editors
+            .keys()
+            .map(|id| {
+                let language = self.config_manager.get_buffer_language(*id);
+                (language, self.file_manager.get_info(*id).map(|info| info.path.clone()))
+            })
+            .collect::<Vec<_>>();
+        let running = self
+            .running_plugins
             .iter()
-            .filter(|p| p.name == receiver)
-            .for_each(|p| p.dispatch_command(view_id, method, params))
+            .filter(|p| p.state != PluginState::Stopping)
+            .map(|p| p.name.clone())
+            .chain(self.activating_plugins.iter().cloned())
+            .collect::<BTreeSet<_>>();
+        let (start, stop) = self.plugins.activation_changes(&buffers, &running);
+        for manifest in start {
+            info!("activating plugin {}", manifest.name);
+            self.activating_plugins.insert(manifest.name.clone());
+            self.start_plugin(manifest);
+        }
+        for name in stop {
+            // a plugin that is still starting is stopped once it connects
+            if !self.pinned_plugins.contains(&name) && !self.activating_plugins.contains(&name) {
+                info!("deactivating plugin {}", name);
+                self.do_stop_plugin(ViewId(0), &name);
+            }
+        }
     }
 
     fn after_stop_plugin(&mut self, plugin: &Plugin) {
         self.iter_groups().for_each(|mut cx| cx.plugin_stopped(plugin));
     }
+
+    /// Returns the plugins in the catalog, and any others that are running,
+    /// in order of name. Each is listed once, as running if any plugin of
+    /// that name is.
+    fn available_plugins(&self) -> Vec<ClientPluginInfo> {
+        let mut plugins =
+            self.plugins.iter().map(|m| (m.name.clone(), false)).collect::<BTreeMap<_, _>>();
+        for plugin in self.running_plugins.iter().filter(|p| p.state != PluginState::Stopping) {
+            plugins.insert(plugin.name.clone(), true);
+        }
+        plugins.into_iter().map(|(name, running)| ClientPluginInfo { name, running }).collect()
+    }
 }
 
 /// Idle, tracing, and file event handling
@@ -563,6 +1120,9 @@ impl CoreState {
         match token {
             NEW_VIEW_IDLE_TOKEN => self.finalize_new_views(),
             WATCH_IDLE_TOKEN => self.handle_fs_events(),
+            other if (other & FORMAT_VIEW_IDLE_MASK) != 0 => {
+                self.handle_format_timer(other ^ FORMAT_VIEW_IDLE_MASK)
+            }
             other if (other & RENDER_VIEW_IDLE_MASK) != 0 => {
                 self.handle_render_timer(other ^ RENDER_VIEW_IDLE_MASK)
             }
@@ -572,8 +1132,76 @@ impl CoreState {
             other if (other & FIND_VIEW_IDLE_MASK) != 0 => {
                 self.handle_find_callback(other ^ FIND_VIEW_IDLE_MASK)
             }
+            other if (other & FIND_STATUS_VIEW_IDLE_MASK) != 0 => {
+                self.handle_find_status_timer(other ^ FIND_STATUS_VIEW_IDLE_MASK)
+            }
+            other if (other & PASTE_VIEW_IDLE_MASK) != 0 => {
+                self.handle_paste_callback(other ^ PASTE_VIEW_IDLE_MASK)
+            }
+            other if (other & AUTOSAVE_VIEW_IDLE_MASK) != 0 => {
+                self.handle_autosave_timer(other ^ AUTOSAVE_VIEW_IDLE_MASK)
+            }
+            other if (other & WARM_PLUGIN_IDLE_MASK) != 0 => {
+                self.handle_warm_plugin_timer(PluginPid(other ^ WARM_PLUGIN_IDLE_MASK))
+            }
+            MAINTENANCE_IDLE_TOKEN => self.handle_maintenance(),
             other => panic!("unexpected idle token {}", other),
         };
+        // maintenance that was held back by this work can run after it
+        if token != MAINTENANCE_IDLE_TOKEN {
+            self.schedule_maintenance();
+        }
+    }
+
+    /// Registers a maintenance task, replacing any task of the same name. See
+    /// the `maintenance` module.
+    pub(crate) fn register_maintenance(
+        &mut self,
+        name: &str,
+        priority: TaskPriority,
+        interval_hint: Duration,
+        task: MaintenanceTask,
+    ) {
+        self.maintenance.register(name, priority, interval_hint, task);
+        self.schedule_maintenance();
+    }
+
+    /// Cancels the maintenance task called `name`. Returns `true` if there
+    /// was one.
+    pub(crate) fn cancel_maintenance(&mut self, name: &str) -> bool {
+        self.maintenance.cancel(name)
+    }
+
+    fn schedule_maintenance(&mut self) {
+        if let Some(due) = self.maintenance.schedule() {
+            if due <= Instant::now() {
+                self.peer.schedule_idle(MAINTENANCE_IDLE_TOKEN);
+            } else {
+                self.peer.schedule_timer(due, MAINTENANCE_IDLE_TOKEN);
+            }
+        }
+    }
+
+    /// Runs a slice of maintenance, unless there is user-facing work to do
+    /// first. In that case it is scheduled again once that work is handled.
+    fn handle_maintenance(&mut self) {
+        self.maintenance.unschedule();
+        if self.has_pending_view_work() {
+            return;
+        }
+        self.maintenance.run_slice(Instant::now(), &self.editors, &self.width_cache);
+        self.schedule_maintenance();
+    }
+
+    /// Returns `true` if any view has rendering, finding, wrapping or pasting
+    /// left to do.
+    fn has_pending_view_work(&self) -> bool {
+        !self.pending_views.is_empty()
+            || self.views.values().any(|view| {
+                let view = view.borrow();
+                view.has_pending_render() || view.find_in_progress() || view.needs_more_wrap()
+            })
+            || self.editors.values().any(|ed| ed.borrow().has_pending_paste())
     }
 
     fn finalize_new_views(&mut self) {
@@ -585,6 +1213,21 @@ impl CoreState {
             let mut edit_ctx = self.make_context(*id).unwrap();
             edit_ctx.finish_init(config);
         });
+        let available_plugins = self.available_plugins();
+        for (id, _) in &to_start {
+            self.peer.available_plugins(*id, &available_plugins);
+        }
+
+        // warmed plugins have now been sent the new buffers
+        for plugin in &mut self.running_plugins {
+            if plugin.state == PluginState::Warmed {
+                plugin.state = PluginState::Attached;
+            }
+        }
+        for manifest in mem::take(&mut self.deferred_plugins) {
+            self.start_plugin(manifest);
+        }
+        self.update_activated_plugins();
     }
 
     // Detects whitespace settings from the file and merges them with the config
@@ -682,20 +1325,98 @@ impl CoreState {
         }
     }
 
+    /// Callback for inserting the next chunk of a large paste in a view
+    fn handle_paste_callback(&mut self, token: usize) {
+        let id: ViewId = token.into();
+        if let Some(mut ctx) = self.make_context(id) {
+            ctx.do_paste_batch();
+        }
+    }
+
+    /// Callback for autosaving the buffer of a view, once edits have stopped
+    fn handle_autosave_timer(&mut self, token: usize) {
+        let id: ViewId = token.into();
+        let path = match self.make_context(id).and_then(|ctx| ctx.due_autosave_path()) {
+            Some(path) => path,
+            None => return,
+        };
+        // don't overwrite changes made on disk; an explicit save reports them
+        let buffer_id = self.views[&id].borrow().get_buffer_id();
+        if self.file_manager.check_file(&path, buffer_id) {
+            info!("not autosaving {:?}, which changed on disk", path);
+            return;
+        }
+        self.do_save(id, path);
+    }
+
+    /// Callback for saving a buffer that a plugin has not yet formatted.
+    fn handle_format_timer(&mut self, token: usize) {
+        let id: ViewId = token.into();
+        match self.pending_format_saves.get(&id) {
+            // the timer of an earlier save
+            Some((_, _, deadline)) if Instant::now() < *deadline => return,
+            Some((path, _, _)) => warn!("formatting {:?} timed out, saving it as is", path),
+            None => return,
+        }
+        self.finish_format_save(id, None);
+    }
+
+    /// Callback for stopping a warmed plugin if it is still unused.
+    fn handle_warm_plugin_timer(&mut self, id: PluginId) {
+        let plugin = match self.running_plugins.iter_mut().find(|p| p.id == id) {
+            Some(plugin) if plugin.state == PluginState::Warmed => plugin,
+            _ => return,
+        };
+        info!("stopping unused plugin {}", plugin.name);
+        plugin.state = PluginState::Stopping;
+        plugin.shutdown();
+        // it is started again with the next buffer, or by its activations
+        if let Some(manifest) = self.plugins.get_named(&plugin.name).filter(|m| m.autoruns()) {
+            self.deferred_plugins.push(manifest);
+        }
+    }
+
+    /// Callback for sending a throttled find status in a view
+    fn handle_find_status_timer(&mut self, token: usize) {
+        let id: ViewId = token.into();
+        if let Some(mut ctx) = self.make_context(id) {
+            ctx.do_find_status_timer();
+        }
+    }
+
     #[cfg(feature = "notify")]
     fn handle_fs_events(&mut self) {
         let _t = trace_block("CoreState::handle_fs_events", &["core"]);
-        let mut events = self.file_manager.watcher().take_events();
+        let events = self.file_manager.watcher().take_events();
+        self.handle_fs_event_batch(events);
+    }
 
-        for (token, event) in events.drain(..) {
+    #[cfg(feature = "notify")]
+    fn handle_fs_event_batch(&mut self, events: impl IntoIterator<Item = (WatchToken, Event)>) {
+        let mut plugins_changed = false;
+        for (token, event) in events {
             match token {
                 OPEN_FILE_EVENT_TOKEN => self.handle_open_file_fs_event(event),
                 CONFIG_EVENT_TOKEN => self.handle_config_fs_event(event),
                 THEME_FILE_EVENT_TOKEN => self.handle_themes_fs_event(event),
-                PLUGIN_EVENT_TOKEN => self.handle_plugin_fs_event(event),
+                PLUGIN_EVENT_TOKEN => {
+                    self.handle_plugin_fs_event(event);
+                    plugins_changed = true;
+                }
+                PROJECT_CONFIG_EVENT_TOKEN => self.handle_project_config_fs_event(event),
+                WatchToken(watch_token) if self.plugin_watches.contains_key(&watch_token) => {
+                    self.handle_plugin_watch_fs_event(watch_token, event)
+                }
                 _ => warn!("unexpected fs event token {:?}", token),
             }
         }
+        // clients are sent the catalog once all of the changes are made
+        if plugins_changed {
+            let available_plugins = self.available_plugins();
+            for view_id in self.views.keys() {
+                self.peer.available_plugins(*view_id, &available_plugins);
+            }
+        }
     }
 
     #[cfg(not(feature = "notify"))]
@@ -705,10 +1426,11 @@ impl CoreState {
     #[cfg(feature = "notify")]
     fn handle_open_file_fs_event(&mut self, event: Event) {
         use notify::event::*;
+        // a change to only the metadata, such as a `touch`, is not a reload
         let path = match event.kind {
-            EventKind::Create(CreateKind::Any)
-            | EventKind::Modify(ModifyKind::Metadata(MetadataKind::Any))
-            | EventKind::Modify(ModifyKind::Any) => &event.paths[0],
+            EventKind::Create(CreateKind::Any) | EventKind::Modify(ModifyKind::Any) => {
+                &event.paths[0]
+            }
             other => {
                 debug!("Ignoring event in open file {:?}", other);
                 return;
@@ -762,6 +1484,26 @@ impl CoreState {
         }
     }
 
+    /// Handles a change to a project config file, reloading it for the
+    /// buffers that use it.
+    #[cfg(feature = "notify")]
+    fn handle_project_config_fs_event(&mut self, event: Event) {
+        use notify::event::*;
+        match event.kind {
+            EventKind::Create(CreateKind::Any)
+            | EventKind::Modify(ModifyKind::Any)
+            | EventKind::Modify(ModifyKind::Metadata(MetadataKind::Any))
+            | EventKind::Modify(ModifyKind::Name(RenameMode::Both))
+            | EventKind::Remove(RemoveKind::Any) => {
+                for path in &event.paths {
+                    let changes = self.config_manager.reload_project_config(path);
+                    self.handle_config_changes(changes);
+                }
+            }
+            _ => (),
+        }
+    }
+
     fn remove_config_at_path(&mut self, path: &Path) {
         if let Some(domain) = self.config_manager.domain_for_path(path) {
             self.set_config(domain, Table::default());
@@ -809,15 +1551,6 @@ impl CoreState {
             }
             _ => (),
         }
-
-        self.views.keys().for_each(|view_id| {
-            let available_plugins = self
-                .plugins
-                .iter()
-                .map(|plugin| ClientPluginInfo { name: plugin.name.clone(), running: true })
-                .collect::<Vec<_>>();
-            self.peer.available_plugins(*view_id, &available_plugins);
-        });
     }
 
     /// Handles changes in theme files.
@@ -923,22 +1656,92 @@ impl CoreState {
     /// Called from a plugin's thread after trying to start the plugin.
     pub(crate) fn plugin_connect(&mut self, plugin: Result<Plugin, io::Error>) {
         match plugin {
-            Ok(plugin) => {
+            Ok(mut plugin) => {
                 let init_info =
                     self.iter_groups().map(|mut ctx| ctx.plugin_info()).collect::<Vec<_>>();
+                if init_info.is_empty() {
+                    self.schedule_warm_plugin_timer(plugin.id);
+                } else {
+                    plugin.state = PluginState::Attached;
+                }
                 plugin.initialize(init_info);
+                // views still being set up are told when that is done
+                let pending = self.pending_views.iter().map(|(id, _)| id).collect::<Vec<_>>();
+                for view_id in self.views.keys().filter(|id| !pending.contains(id)) {
+                    self.peer.plugin_started(*view_id, &plugin.name);
+                }
+                // an activated plugin learns of the open buffers from
+                // `initialize`, as other plugins do, and is then sent the
+                // commands that started it.
+                self.activating_plugins.remove(&plugin.name);
+                let (pending_rpcs, rest) = mem::take(&mut self.pending_plugin_rpcs)
+                    .into_iter()
+                    .partition::<Vec<_>, _>(|(name, ..)| name == &plugin.name);
+                self.pending_plugin_rpcs = rest;
+                for (_, view_id, method, params) in pending_rpcs {
+                    plugin.dispatch_command(view_id, &method, &params);
+                }
                 self.running_plugins.push(plugin);
+                // buffers may have closed while it was starting
+                self.update_activated_plugins();
             }
             Err(e) => error!("failed to start plugin {:?}", e),
         }
     }
 
+    fn schedule_warm_plugin_timer(&self, id: PluginId) {
+        let timeout = self.config_manager.default_buffer_config().items.warm_plugin_timeout_ms;
+        if timeout > 0 {
+            let deadline = Instant::now() + Duration::from_millis(timeout);
+            self.peer.schedule_timer(deadline, WARM_PLUGIN_IDLE_MASK | id.0);
+        }
+    }
+
     pub(crate) fn plugin_exit(&mut self, id: PluginId, error: Result<(), ReadError>) {
-        warn!("plugin {:?} exited with result {:?}", id, error);
+        self.remove_plugin_watches(id);
         let running_idx = self.running_plugins.iter().position(|p| p.id == id);
-        if let Some(idx) = running_idx {
-            let plugin = self.running_plugins.remove(idx);
-            self.after_stop_plugin(&plugin);
+        let plugin = match running_idx {
+            Some(idx) => self.running_plugins.remove(idx),
+            None => {
+                warn!("plugin {:?} exited with result {:?}", id, error);
+                return;
+            }
+        };
+        match plugin.state {
+            PluginState::Attached => {
+                warn!("plugin {:?} exited with result {:?}", id, error);
+                self.after_stop_plugin(&plugin);
+            }
+            PluginState::Stopping => info!("plugin {} stopped", plugin.name),
+            // no buffer depends on it yet, so the user needn't know
+            PluginState::Warmed => {
+                info!("warmed plugin {} exited with result {:?}", plugin.name, error);
+                if self.respawned_plugins.insert(plugin.name.clone()) {
+                    if let Some(manifest) = self.plugins.get_named(&plugin.name) {
+                        if !manifest.autoruns() {
+                            self.activating_plugins.insert(manifest.name.clone());
+                        }
+                        self.start_plugin(manifest);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Handles a plugin's response to a request to format a buffer before
+    /// it is saved.
+    pub(crate) fn format_response(
+        &mut self,
+        view_id: ViewId,
+        rev: RevToken,
+        response: Result<Value, xi_rpc::Error>,
+    ) {
+        match self.pending_format_saves.get(&view_id) {
+            Some((_, pending_rev, _)) if *pending_rev == rev => {
+                self.finish_format_save(view_id, Some(response))
+            }
+            // it was saved as is after waiting too long
+            _ => info!("ignoring late formatting of {:?}", view_id),
         }
     }
 
@@ -947,10 +1750,11 @@ impl CoreState {
         &mut self,
         _plugin_id: PluginId,
         view_id: ViewId,
+        rev: RevToken,
         response: Result<Value, xi_rpc::Error>,
     ) {
         if let Some(mut edit_ctx) = self.make_context(view_id) {
-            edit_ctx.do_plugin_update(response);
+            edit_ctx.do_plugin_update(rev, response);
         }
     }
 
@@ -973,6 +1777,27 @@ impl CoreState {
         plugin_id: PluginId,
         cmd: PluginRequest,
     ) -> Result<Value, RemoteError> {
+        match cmd {
+            PluginRequest::CreateScratchView { title, content, language_id, read_only } => {
+                return self.do_create_scratch_view(
+                    plugin_id,
+                    &title,
+                    content,
+                    language_id,
+                    read_only,
+                );
+            }
+            PluginRequest::UpdateScratchView { content } => {
+                return self.do_update_scratch_view(view_id, plugin_id, content);
+            }
+            PluginRequest::WatchPath { path, recursive, token } => {
+                return self.do_watch_path(plugin_id, &path, recursive, token);
+            }
+            PluginRequest::UnwatchPath { token } => {
+                return self.do_unwatch_path(plugin_id, token);
+            }
+            _ => (),
+        }
         if let Some(mut edit_ctx) = self.make_context(view_id) {
             Ok(edit_ctx.do_plugin_cmd_sync(plugin_id, cmd))
         } else {
@@ -981,6 +1806,199 @@ impl CoreState {
     }
 }
 
+/// paths watched for plugins
+impl CoreState {
+    /// Starts watching `path` for `plugin_id`, replacing any watch it
+    /// started with the same `token`.
+    #[cfg(feature = "notify")]
+    fn do_watch_path(
+        &mut self,
+        plugin_id: PluginId,
+        path: &Path,
+        recursive: bool,
+        token: u64,
+    ) -> Result<Value, RemoteError> {
+        let path = path.canonicalize().map_err(|e| {
+            RemoteError::custom(404, format!("can't watch {:?}: {}", path, e), None)
+        })?;
+        self.do_unwatch_path(plugin_id, token)?;
+        let watch_token = FIRST_PLUGIN_WATCH_TOKEN + self.id_counter.next();
+        self.file_manager.watcher().watch(&path, recursive, WatchToken(watch_token));
+        self.plugin_watches.insert(watch_token, PluginWatch { plugin: plugin_id, token, path });
+        Ok(Value::Null)
+    }
+
+    #[cfg(feature = "notify")]
+    fn do_unwatch_path(&mut self, plugin_id: PluginId, token: u64) -> Result<Value, RemoteError> {
+        let watch_token = self
+            .plugin_watches
+            .iter()
+            .find(|(_, w)| w.plugin == plugin_id && w.token == token)
+            .map(|(watch_token, _)| *watch_token);
+        if let Some(watch_token) = watch_token {
+            self.remove_plugin_watch(watch_token);
+        }
+        Ok(Value::Null)
+    }
+
+    #[cfg(feature = "notify")]
+    fn remove_plugin_watch(&mut self, watch_token: usize) {
+        if let Some(watch) = self.plugin_watches.remove(&watch_token) {
+            self.file_manager.watcher().unwatch(&watch.path, WatchToken(watch_token));
+        }
+    }
+
+    /// Stops the watches of a plugin that has exited.
+    #[cfg(feature = "notify")]
+    fn remove_plugin_watches(&mut self, plugin_id: PluginId) {
+        let watch_tokens = self
+            .plugin_watches
+            .iter()
+            .filter(|(_, w)| w.plugin == plugin_id)
+            .map(|(watch_token, _)| *watch_token)
+            .collect::<Vec<_>>();
+        for watch_token in watch_tokens {
+            self.remove_plugin_watch(watch_token);
+        }
+    }
+
+    /// Sends a plugin an event for a path it is watching.
+    #[cfg(feature = "notify")]
+    fn handle_plugin_watch_fs_event(&self, watch_token: usize, event: Event) {
+        let watch = &self.plugin_watches[&watch_token];
+        let plugin = match self.running_plugins.iter().find(|p| p.id == watch.plugin) {
+            Some(plugin) => plugin,
+            None => return,
+        };
+        match FsEventKind::classify(&event) {
+            Some(kind) => plugin.fs_event(watch.token, &event.paths[0], kind),
+            // a rename removes the old path and creates the new one
+            None if event.paths.len() == 2 => {
+                plugin.fs_event(watch.token, &event.paths[0], FsEventKind::Removed);
+                plugin.fs_event(watch.token, &event.paths[1], FsEventKind::Created);
+            }
+            None => debug!("ignoring watched path event {:?}", event),
+        }
+    }
+
+    #[cfg(not(feature = "notify"))]
+    fn do_watch_path(
+        &mut self,
+        _plugin_id: PluginId,
+        _path: &Path,
+        _recursive: bool,
+        _token: u64,
+    ) -> Result<Value, RemoteError> {
+        Err(RemoteError::custom(501, "xi-core was built without file watching", None))
+    }
+
+    #[cfg(not(feature = "notify"))]
+    fn do_unwatch_path(&mut self, _plugin_id: PluginId, _token: u64) -> Result<Value, RemoteError> {
+        Ok(Value::Null)
+    }
+
+    #[cfg(not(feature = "notify"))]
+    fn remove_plugin_watches(&mut self, _plugin_id: PluginId) {}
+}
+
+/// scratch views
+impl CoreState {
+    fn check_scratch_view_capability(&self, plugin_id: PluginId) -> Result<(), RemoteError> {
+        let allowed = self
+            .running_plugins
+            .iter()
+            .find(|p| p.id == plugin_id)
+            .map(|p| p.has_capability(PluginCapability::ScratchViews))
+            .unwrap_or(false);
+        if allowed {
+            Ok(())
+        } else {
+            let msg = format!("{} lacks the scratch_views capability", plugin_id);
+            Err(RemoteError::custom(403, msg, None))
+        }
+    }
+
+    fn check_scratch_view_size(content: &str) -> Result<(), RemoteError> {
+        if content.len() > MAX_SCRATCH_VIEW_SIZE {
+            let msg = format!(
+                "scratch view content is {} bytes, the limit is {}",
+                content.len(),
+                MAX_SCRATCH_VIEW_SIZE
+            );
+            Err(RemoteError::custom(413, msg, None))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Creates an untitled buffer holding `content`, owned by `plugin_id`,
+    /// and asks the client to show it. The view is finished, like any other,
+    /// in `finalize_new_views`.
+    fn do_create_scratch_view(
+        &mut self,
+        plugin_id: PluginId,
+        title: &str,
+        content: String,
+        language_id: Option<LanguageId>,
+        read_only: bool,
+    ) -> Result<Value, RemoteError> {
+        self.check_scratch_view_capability(plugin_id)?;
+        CoreState::check_scratch_view_size(&content)?;
+
+        let view_id = self.next_view_id();
+        let buffer_id = self.next_buffer_id();
+
+        let mut editor = Editor::with_text(content);
+        editor.set_read_only(read_only);
+        self.editors.insert(buffer_id, RefCell::new(editor));
+        self.views.insert(view_id, RefCell::new(View::new(view_id, buffer_id)));
+        self.scratch_views.insert(view_id, plugin_id);
+
+        let mut config = self.config_manager.add_buffer(buffer_id, None);
+        if let Some(language_id) = language_id {
+            if let Some(changes) = self.config_manager.override_language(buffer_id, language_id) {
+                config.extend(changes);
+            }
+        }
+
+        self.make_context(view_id).unwrap().view_init();
+        self.pending_views.push((view_id, config));
+        self.peer.schedule_idle(NEW_VIEW_IDLE_TOKEN);
+        self.peer.show_view(view_id, title);
+
+        Ok(json!(view_id))
+    }
+
+    /// Replaces the content of a scratch view. Only the plugin that created
+    /// the view may update it.
+    fn do_update_scratch_view(
+        &mut self,
+        view_id: ViewId,
+        plugin_id: PluginId,
+        content: String,
+    ) -> Result<Value, RemoteError> {
+        self.check_scratch_view_capability(plugin_id)?;
+        CoreState::check_scratch_view_size(&content)?;
+
+        match self.scratch_views.get(&view_id) {
+            Some(owner) if *owner == plugin_id => (),
+            Some(_) => {
+                let msg = format!("{} does not own scratch view {}", plugin_id, view_id);
+                return Err(RemoteError::custom(403, msg, None));
+            }
+            None => {
+                let msg = format!("no scratch view {}", view_id);
+                return Err(RemoteError::custom(404, msg, None));
+            }
+        }
+
+        // the new text is diffed against the old, so that appending to a
+        // large view only sends the appended text to views and plugins.
+        self.make_context(view_id).unwrap().reload(Rope::from(content));
+        Ok(Value::Null)
+    }
+}
+
 /// test helpers
 impl CoreState {
     pub fn _test_open_editors(&self) -> Vec<BufferId> {
@@ -994,6 +2012,7 @@ impl CoreState {
 
 pub mod test_helpers {
     use super::{BufferId, ViewId};
+    use crate::config::{ConfigManager, Table};
 
     pub fn new_view_id(id: usize) -> ViewId {
         ViewId(id)
@@ -1002,6 +2021,11 @@ pub mod test_helpers {
     pub fn new_buffer_id(id: usize) -> BufferId {
         BufferId(id)
     }
+
+    /// Returns the config of a buffer with no user settings, as sent to plugins.
+    pub fn default_buffer_config() -> Table {
+        ConfigManager::new(None, None).default_buffer_config().to_table()
+    }
 }
 
 /// A multi-view aware iterator over `EventContext`s. A view which appears
@@ -1046,10 +2070,46 @@ impl Counter {
     }
 }
 
-// these two only exist so that we can use ViewIds as idle tokens
+impl ViewId {
+    /// Creates a `ViewId` from its numeric part.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `id` is greater than `MAX_ID`.
+    pub fn new(id: usize) -> Self {
+        assert!(id <= MAX_ID, "view id {} out of range", id);
+        ViewId(id)
+    }
+
+    /// Returns the numeric part of this id.
+    pub fn get(self) -> usize {
+        self.0
+    }
+}
+
+impl BufferId {
+    /// Creates a `BufferId` from its numeric part.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `val` is greater than `MAX_ID`.
+    pub fn new(val: usize) -> Self {
+        assert!(val <= MAX_ID, "buffer id {} out of range", val);
+        BufferId(val)
+    }
+
+    /// Returns the numeric part of this id.
+    pub fn get(self) -> usize {
+        self.0
+    }
+}
+
+// these two only exist so that we can use ViewIds as idle tokens. The
+// conversion from a token drops any idle mask bits, so it can't fail and
+// always yields an id in range.
 impl From<usize> for ViewId {
     fn from(src: usize) -> ViewId {
-        ViewId(src)
+        ViewId(src & MAX_ID)
     }
 }
 
@@ -1059,9 +2119,63 @@ impl From<ViewId> for usize {
     }
 }
 
+/// Formats `path` for an alert, shortening it if it is long.
+fn alert_path(path: &Path) -> String {
+    middle_ellipsis(&path.to_string_lossy(), MAX_ALERT_PATH_COLS).into_owned()
+}
+
+/// Parses the numeric part of an id, in the format described next to `ViewId`.
+fn parse_id(s: &str, prefix: &str) -> Option<usize> {
+    if !s.starts_with(prefix) {
+        return None;
+    }
+    let digits = &s[prefix.len()..];
+    let well_formed = !digits.is_empty()
+        && digits.bytes().all(|b| b.is_ascii_digit())
+        && (digits == "0" || !digits.starts_with('0'));
+    if !well_formed {
+        return None;
+    }
+    digits.parse::<usize>().ok().filter(|&id| id <= MAX_ID)
+}
+
+impl fmt::Display for ParseIdError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid {} {:?}", self.expected, self.input)
+    }
+}
+
+impl std::error::Error for ParseIdError {}
+
+impl FromStr for ViewId {
+    type Err = ParseIdError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_id(s, VIEW_ID_PREFIX)
+            .map(ViewId)
+            .ok_or_else(|| ParseIdError { expected: "view id", input: s.to_owned() })
+    }
+}
+
+impl FromStr for BufferId {
+    type Err = ParseIdError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_id(s, BUFFER_ID_PREFIX)
+            .map(BufferId)
+            .ok_or_else(|| ParseIdError { expected: "buffer id", input: s.to_owned() })
+    }
+}
+
 impl fmt::Display for ViewId {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "view-id-{}", self.0)
+        write!(f, "{}{}", VIEW_ID_PREFIX, self.0)
+    }
+}
+
+impl fmt::Display for BufferId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}", BUFFER_ID_PREFIX, self.0)
     }
 }
 
@@ -1080,30 +2194,581 @@ impl<'de> Deserialize<'de> for ViewId {
         D: Deserializer<'de>,
     {
         let s = String::deserialize(deserializer)?;
-        s.trim_start_matches("view-id-")
-            .parse::<usize>()
-            .map(ViewId)
-            .map_err(|_| de::Error::invalid_value(Unexpected::Str(&s), &"view id"))
+        s.parse().map_err(|_| de::Error::invalid_value(Unexpected::Str(&s), &"a view id"))
     }
 }
 
-impl fmt::Display for BufferId {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "buffer-id-{}", self.0)
+impl Serialize for BufferId {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
     }
 }
 
-impl BufferId {
-    pub fn new(val: usize) -> Self {
-        BufferId(val)
+impl<'de> Deserialize<'de> for BufferId {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum RawBufferId {
+            Str(String),
+            Legacy(u64),
+        }
+
+        match RawBufferId::deserialize(deserializer)? {
+            RawBufferId::Str(s) => {
+                s.parse().map_err(|_| de::Error::invalid_value(Unexpected::Str(&s), &"a buffer id"))
+            }
+            RawBufferId::Legacy(n) if n <= MAX_ID as u64 => Ok(BufferId(n as usize)),
+            RawBufferId::Legacy(n) => {
+                Err(de::Error::invalid_value(Unexpected::Unsigned(n), &"a buffer id"))
+            }
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::fs;
+    use std::path::Path;
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant, SystemTime};
+
     use serde::Deserialize;
+    use serde_json::Value;
+    use tempdir::TempDir;
+    use xi_rpc::test_utils::{make_reader, test_channel, DummyReader};
+    use xi_rpc::{RemoteError, RpcLoop, RpcPeer};
+
+    use super::{
+        BufferId, CoreState, ViewId, AUTOSAVE_VIEW_IDLE_MASK, FIND_VIEW_IDLE_MASK,
+        FORMAT_VIEW_IDLE_MASK, MAX_ID, NEW_VIEW_IDLE_TOKEN, RENDER_VIEW_IDLE_MASK,
+    };
+    use crate::config::{ConfigDomainExternal, Table};
+    use crate::core::weak_core;
+    use crate::plugins::{Plugin, PluginCapability, PluginPid};
+    use crate::rpc::{CoreNotification, CoreRequest, EditNotification};
+
+    /// Reads messages until one with the given method arrives.
+    fn skip_to(rx: &mut DummyReader, method: &str) -> Value {
+        loop {
+            let obj = rx.expect_object();
+            if obj.get_method() == Some(method) {
+                return obj.0;
+            }
+        }
+    }
+
+    /// A core with a single plugin, whose messages can be read from the
+    /// returned reader, as can those sent to the client.
+    struct CoreHarness {
+        core: Arc<Mutex<CoreState>>,
+        client_rx: DummyReader,
+        plugin_rx: DummyReader,
+    }
+
+    impl CoreHarness {
+        fn new(capabilities: Vec<PluginCapability>) -> CoreHarness {
+            let (client_tx, client_rx) = test_channel();
+            let client: RpcPeer = Box::new(RpcLoop::new(client_tx).get_raw_peer());
+            let core = Arc::new(Mutex::new(CoreState::new(&client, None, None)));
+            core.lock().unwrap().finish_setup(weak_core(&core));
+
+            let (plugin_tx, plugin_rx) = test_channel();
+            let peer: RpcPeer = Box::new(RpcLoop::new(plugin_tx).get_raw_peer());
+            let mut plugin = Plugin::with_peer(PluginPid(100), "scratch", peer);
+            plugin.capabilities = capabilities;
+            core.lock().unwrap().running_plugins.push(plugin);
+            CoreHarness { core, client_rx, plugin_rx }
+        }
+
+        /// Sends a request from the plugin through core's plugin handler,
+        /// returning the response.
+        fn request(
+            &self,
+            view_id: &str,
+            method: &str,
+            mut params: Value,
+        ) -> Result<Value, RemoteError> {
+            params["view_id"] = json!(view_id);
+            params["plugin_id"] = json!(PluginPid(100));
+            let msg = json!({ "id": 0, "method": method, "params": params });
+            let (tx, mut rx) = test_channel();
+            let mut looper = RpcLoop::new(tx);
+            let reader = make_reader(msg.to_string());
+            looper.mainloop(|| reader, &mut weak_core(&self.core)).unwrap();
+            rx.expect_response()
+        }
+    }
+
+    #[test]
+    fn scratch_view_round_trip() {
+        let mut harness = CoreHarness::new(vec![PluginCapability::ScratchViews]);
+        let content = "fn one() at src/lib.rs:1\n";
+        let params = json!({ "title": "Usages", "content": content, "read_only": true });
+        let view_id = harness.request("view-id-1", "create_scratch_view", params).unwrap();
+
+        let show_view = skip_to(&mut harness.client_rx, "show_view");
+        assert_eq!(show_view["params"], json!({ "view_id": view_id, "title": "Usages" }));
+
+        harness.core.lock().unwrap().handle_idle(NEW_VIEW_IDLE_TOKEN);
+        let new_buffer = skip_to(&mut harness.plugin_rx, "new_buffer");
+        assert_eq!(new_buffer["params"]["buffer_info"][0]["views"], json!([view_id]));
+
+        // appending is sent to plugins as a single insert
+        let appended = "fn two() at src/lib.rs:9\n";
+        let params = json!({ "content": format!("{}{}", content, appended) });
+        let view = view_id.as_str().unwrap();
+        assert_eq!(harness.request(view, "update_scratch_view", params), Ok(Value::Null));
+        let update = skip_to(&mut harness.plugin_rx, "update");
+        let els = &update["params"]["delta"]["els"];
+        assert_eq!(els, &json!([{ "copy": [0, content.len()] }, { "insert": appended }]));
+
+        // the buffer ignores edits from the user
+        let view_id: ViewId = serde_json::from_value(view_id).unwrap();
+        let core = harness.core.lock().unwrap();
+        let mut ctx = core.make_context(view_id).unwrap();
+        ctx.do_edit(EditNotification::Insert { chars: "x".into() });
+        let text = String::from(ctx.editor.borrow().get_buffer());
+        assert_eq!(text, format!("{}{}", content, appended));
+    }
+
+    #[test]
+    fn scratch_views_require_capability() {
+        let mut harness = CoreHarness::new(vec![]);
+        let params = json!({ "title": "Usages", "content": "nope" });
+        let err = harness.request("view-id-1", "create_scratch_view", params).unwrap_err();
+        assert!(matches!(err, RemoteError::Custom { code: 403, .. }), "{:?}", err);
+        assert!(harness.core.lock().unwrap().views.is_empty());
+
+        let params = json!({ "content": "nope" });
+        let err = harness.request("view-id-1", "update_scratch_view", params).unwrap_err();
+        assert!(matches!(err, RemoteError::Custom { code: 403, .. }), "{:?}", err);
+        while let Some(msg) = harness.client_rx.next_timeout(Duration::from_millis(100)) {
+            assert_ne!(msg.unwrap().get_method(), Some("show_view"));
+        }
+    }
+
+    #[test]
+    fn split_views_are_siblings() {
+        let harness = CoreHarness::new(vec![]);
+        let mut core = harness.core.lock().unwrap();
+        let view_id = |id: Value| -> ViewId { serde_json::from_value(id).unwrap() };
+        let first = view_id(core.do_new_view(None).unwrap());
+        let other = view_id(core.do_new_view(None).unwrap());
+        core.handle_idle(NEW_VIEW_IDLE_TOKEN);
+        let split = view_id(core.do_split_view(first).unwrap());
+        core.handle_idle(NEW_VIEW_IDLE_TOKEN);
+
+        let siblings = |view_id| -> Vec<ViewId> {
+            let ctx = core.make_context(view_id).unwrap();
+            ctx.siblings.iter().map(|view| view.borrow().get_view_id()).collect()
+        };
+        assert_eq!(siblings(first), vec![split]);
+        assert_eq!(siblings(split), vec![first]);
+        assert!(siblings(other).is_empty());
+    }
+
+    /// Returns the messages received so far.
+    fn drain(rx: &mut DummyReader) -> Vec<Value> {
+        let mut msgs = Vec::new();
+        while let Some(msg) = rx.next_timeout(Duration::from_millis(50)) {
+            msgs.push(msg.unwrap().0);
+        }
+        msgs
+    }
+
+    fn count_rpcs(msgs: &[Value], method: &str) -> usize {
+        msgs.iter().filter(|msg| msg["method"] == method).count()
+    }
+
+    /// Opens `path` in a new view, returning its id.
+    fn open_file(harness: &CoreHarness, path: &Path) -> ViewId {
+        let mut core = harness.core.lock().unwrap();
+        let view_id = core.do_new_view(Some(path.to_owned())).unwrap();
+        core.handle_idle(NEW_VIEW_IDLE_TOKEN);
+        serde_json::from_value(view_id).unwrap()
+    }
+
+    fn insert(harness: &CoreHarness, view_id: ViewId, chars: &str) {
+        let core = harness.core.lock().unwrap();
+        let mut ctx = core.make_context(view_id).unwrap();
+        ctx.do_edit(EditNotification::Insert { chars: chars.into() });
+    }
+
+    fn buffer_text(harness: &CoreHarness, view_id: ViewId) -> String {
+        let core = harness.core.lock().unwrap();
+        let buffer_id = core.views[&view_id].borrow().get_buffer_id();
+        let text = core.editors[&buffer_id].borrow().get_buffer().to_string();
+        text
+    }
+
+    #[test]
+    fn repeated_saves_write_once() {
+        let tmp = TempDir::new("xi-test-repeated-saves").unwrap();
+        let path = tmp.path().join("file.txt");
+        fs::write(&path, "hello\n").unwrap();
+        let mut harness = CoreHarness::new(vec![]);
+        let view_id = open_file(&harness, &path);
+        insert(&harness, view_id, "x");
+        drain(&mut harness.plugin_rx);
+
+        harness.core.lock().unwrap().do_save(view_id, &path);
+        harness.core.lock().unwrap().do_save(view_id, &path);
+        assert_eq!(count_rpcs(&drain(&mut harness.plugin_rx), "did_save"), 1);
+        assert_eq!(count_rpcs(&drain(&mut harness.client_rx), "save_failed"), 0);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "xhello\n");
+    }
+
+    /// Enables autosave with a delay of `delay_ms`.
+    fn enable_autosave(harness: &CoreHarness, delay_ms: u64) {
+        let mut changes = Table::new();
+        changes.insert("autosave_delay_ms".into(), json!(delay_ms));
+        harness.core.lock().unwrap().do_modify_user_config(ConfigDomainExternal::General, changes);
+    }
+
+    #[test]
+    fn autosave_timer_saves() {
+        let tmp = TempDir::new("xi-test-autosave").unwrap();
+        let path = tmp.path().join("file.txt");
+        fs::write(&path, "hello\n").unwrap();
+        let mut harness = CoreHarness::new(vec![]);
+        enable_autosave(&harness, 1);
+        let view_id = open_file(&harness, &path);
+        insert(&harness, view_id, "x");
+        drain(&mut harness.plugin_rx);
+
+        std::thread::sleep(Duration::from_millis(5));
+        let token = usize::from(view_id) | AUTOSAVE_VIEW_IDLE_MASK;
+        harness.core.lock().unwrap().handle_idle(token);
+        assert_eq!(count_rpcs(&drain(&mut harness.plugin_rx), "did_save"), 1);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "xhello\n");
+        {
+            let core = harness.core.lock().unwrap();
+            let buffer_id = core.views[&view_id].borrow().get_buffer_id();
+            assert!(core.editors[&buffer_id].borrow().is_pristine());
+        }
+
+        // a timer that fires again has nothing to save
+        harness.core.lock().unwrap().handle_idle(token);
+        assert_eq!(count_rpcs(&drain(&mut harness.plugin_rx), "did_save"), 0);
+    }
+
+    #[test]
+    fn autosave_skips_files_changed_on_disk() {
+        let tmp = TempDir::new("xi-test-autosave-conflict").unwrap();
+        let path = tmp.path().join("file.txt");
+        fs::write(&path, "hello\n").unwrap();
+        let mut harness = CoreHarness::new(vec![]);
+        enable_autosave(&harness, 1);
+        let view_id = open_file(&harness, &path);
+        insert(&harness, view_id, "x");
+        drain(&mut harness.plugin_rx);
+        drain(&mut harness.client_rx);
+
+        let file = fs::OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000)).unwrap();
+
+        std::thread::sleep(Duration::from_millis(5));
+        let token = usize::from(view_id) | AUTOSAVE_VIEW_IDLE_MASK;
+        harness.core.lock().unwrap().handle_idle(token);
+        assert_eq!(count_rpcs(&drain(&mut harness.plugin_rx), "did_save"), 0);
+        assert_eq!(count_rpcs(&drain(&mut harness.client_rx), "save_failed"), 0);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "hello\n");
+    }
+
+    #[test]
+    fn format_on_save() {
+        let tmp = TempDir::new("xi-test-format-on-save").unwrap();
+        let path = tmp.path().join("file.txt");
+        fs::write(&path, "hello\n").unwrap();
+        let mut harness = CoreHarness::new(vec![PluginCapability::Formatting]);
+        let mut changes = Table::new();
+        changes.insert("format_on_save".into(), json!(true));
+        harness.core.lock().unwrap().do_modify_user_config(ConfigDomainExternal::General, changes);
+        let view_id = open_file(&harness, &path);
+        insert(&harness, view_id, "x");
+
+        // the save waits for the plugin's formatting
+        harness.core.lock().unwrap().do_save(view_id, &path);
+        let format = skip_to(&mut harness.plugin_rx, "format");
+        let rev = format["params"]["rev"].as_u64().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "hello\n");
+        drain(&mut harness.plugin_rx);
+
+        let delta = json!({ "base_len": 7, "els": [{ "insert": "  " }, { "copy": [0, 7] }] });
+        harness.core.lock().unwrap().format_response(view_id, rev, Ok(delta));
+        assert_eq!(count_rpcs(&drain(&mut harness.plugin_rx), "did_save"), 1);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "  xhello\n");
+
+        // undoing the formatting keeps the edit before it
+        {
+            let core = harness.core.lock().unwrap();
+            core.make_context(view_id).unwrap().do_edit(EditNotification::Undo);
+        }
+        assert_eq!(buffer_text(&harness, view_id), "xhello\n");
+
+        // a failed formatting saves the buffer as it is
+        insert(&harness, view_id, "y");
+        harness.core.lock().unwrap().do_save(view_id, &path);
+        let format = skip_to(&mut harness.plugin_rx, "format");
+        let rev = format["params"]["rev"].as_u64().unwrap();
+        let err = xi_rpc::Error::RemoteError(RemoteError::custom(1, "no formatter", None));
+        harness.core.lock().unwrap().format_response(view_id, rev, Err(err));
+        assert_eq!(count_rpcs(&drain(&mut harness.plugin_rx), "did_save"), 1);
+        assert_eq!(fs::read_to_string(&path).unwrap(), buffer_text(&harness, view_id));
+
+        // as does a plugin that never responds
+        insert(&harness, view_id, "z");
+        harness.core.lock().unwrap().do_save(view_id, &path);
+        skip_to(&mut harness.plugin_rx, "format");
+        harness.core.lock().unwrap().pending_format_saves.get_mut(&view_id).unwrap().2 =
+            Instant::now();
+        let token = usize::from(view_id) | FORMAT_VIEW_IDLE_MASK;
+        harness.core.lock().unwrap().handle_idle(token);
+        assert_eq!(count_rpcs(&drain(&mut harness.plugin_rx), "did_save"), 1);
+        assert_eq!(fs::read_to_string(&path).unwrap(), buffer_text(&harness, view_id));
+    }
+
+    #[test]
+    #[cfg(feature = "notify")]
+    fn plugin_watches_paths() {
+        use notify::event::{EventKind, ModifyKind, RenameMode};
+        use notify::Event;
+
+        use crate::watcher::WatchToken;
+
+        let tmp = TempDir::new("xi-test-plugin-watch").unwrap();
+        let path = tmp.path().join("Cargo.toml");
+        fs::write(&path, "[package]\n").unwrap();
+        let path = path.canonicalize().unwrap();
+        let mut harness = CoreHarness::new(vec![]);
+        let params = json!({ "path": path, "token": 7 });
+        assert_eq!(harness.request("view-id-1", "watch_path", params.clone()), Ok(Value::Null));
+        let watch_token = *harness.core.lock().unwrap().plugin_watches.keys().next().unwrap();
+
+        let event = Event::new(EventKind::Modify(ModifyKind::Any)).add_path(path.clone());
+        harness.core.lock().unwrap().handle_fs_event_batch(vec![(WatchToken(watch_token), event)]);
+        let fs_event = skip_to(&mut harness.plugin_rx, "fs_event");
+        let expected = json!({ "token": 7, "path": path, "kind": "data_modified" });
+        assert_eq!(fs_event["params"], expected);
+
+        // a rename is sent as a removal and a creation
+        let new_path = path.with_file_name("Cargo.toml.bak");
+        let event = Event::new(EventKind::Modify(ModifyKind::Name(RenameMode::Both)))
+            .add_path(path.clone())
+            .add_path(new_path.clone());
+        harness.core.lock().unwrap().handle_fs_event_batch(vec![(WatchToken(watch_token), event)]);
+        let removed = skip_to(&mut harness.plugin_rx, "fs_event");
+        assert_eq!(removed["params"]["kind"], json!("removed"));
+        let created = skip_to(&mut harness.plugin_rx, "fs_event");
+        assert_eq!(created["params"], json!({ "token": 7, "path": new_path, "kind": "created" }));
+
+        let unwatch = json!({ "token": 7 });
+        assert_eq!(harness.request("view-id-1", "unwatch_path", unwatch), Ok(Value::Null));
+        assert!(harness.core.lock().unwrap().plugin_watches.is_empty());
+
+        // watches end with the plugin
+        harness.request("view-id-1", "watch_path", params).unwrap();
+        harness.core.lock().unwrap().plugin_exit(PluginPid(100), Ok(()));
+        assert!(harness.core.lock().unwrap().plugin_watches.is_empty());
+
+        let missing = json!({ "path": tmp.path().join("missing"), "token": 8 });
+        let err = harness.request("view-id-1", "watch_path", missing).unwrap_err();
+        assert!(matches!(err, RemoteError::Custom { code: 404, .. }), "{:?}", err);
+    }
+
+    #[test]
+    fn saving_pristine_buffer_is_noop() {
+        let tmp = TempDir::new("xi-test-pristine-save").unwrap();
+        let path = tmp.path().join("file.txt");
+        fs::write(&path, "hello\n").unwrap();
+        let mut harness = CoreHarness::new(vec![]);
+        let view_id = open_file(&harness, &path);
+        drain(&mut harness.plugin_rx);
+        drain(&mut harness.client_rx);
+
+        harness.core.lock().unwrap().do_save(view_id, &path);
+        assert_eq!(count_rpcs(&drain(&mut harness.plugin_rx), "did_save"), 0);
+        assert_eq!(count_rpcs(&drain(&mut harness.client_rx), "save_failed"), 0);
+
+        // saving somewhere else is a real save
+        let other_path = tmp.path().join("other.txt");
+        harness.core.lock().unwrap().do_save(view_id, &other_path);
+        assert_eq!(count_rpcs(&drain(&mut harness.plugin_rx), "did_save"), 1);
+        assert_eq!(fs::read_to_string(&other_path).unwrap(), "hello\n");
+    }
+
+    #[test]
+    fn saving_pristine_buffer_after_external_change() {
+        let tmp = TempDir::new("xi-test-external-change-save").unwrap();
+        let path = tmp.path().join("file.txt");
+        fs::write(&path, "hello\n").unwrap();
+        let mut harness = CoreHarness::new(vec![]);
+        let view_id = open_file(&harness, &path);
+        drain(&mut harness.client_rx);
+
+        let file = fs::OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_len(0).unwrap();
+        file.set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000)).unwrap();
+
+        // the save is attempted, and the conflict reported
+        harness.core.lock().unwrap().do_save(view_id, &path);
+        let msgs = drain(&mut harness.client_rx);
+        let failed = msgs.iter().find(|msg| msg["method"] == "save_failed").unwrap();
+        assert_eq!(failed["params"]["error"]["kind"], json!("has_changed"));
+    }
+
+    #[test]
+    fn save_preserves_encoding() {
+        let tmp = TempDir::new("xi-test-encoding").unwrap();
+        let path = tmp.path().join("file.txt");
+        fs::write(&path, b"\xff\xfeh\x00i\x00").unwrap();
+        let mut harness = CoreHarness::new(vec![]);
+        let view_id = open_file(&harness, &path);
+        let msgs = drain(&mut harness.client_rx);
+        let reported = msgs.iter().find(|msg| msg["method"] == "encoding_changed");
+        assert_eq!(reported.unwrap()["params"]["encoding"], json!("utf16_le"));
+
+        insert(&harness, view_id, "\u{e9}");
+        harness.core.lock().unwrap().do_save(view_id, &path);
+        assert_eq!(fs::read(&path).unwrap(), b"\xff\xfe\xe9\x00h\x00i\x00\n\x00");
+    }
+
+    #[test]
+    fn set_line_ending_updates_config() {
+        use crate::line_ending::LineEnding;
+        let tmp = TempDir::new("xi-test-line-ending").unwrap();
+        let path = tmp.path().join("file.txt");
+        fs::write(&path, "one\ntwo").unwrap();
+        let mut harness = CoreHarness::new(vec![]);
+        let view_id = open_file(&harness, &path);
+        drain(&mut harness.client_rx);
+
+        let cmd = EditNotification::SetLineEnding { ending: LineEnding::CrLf };
+        harness.core.lock().unwrap().do_edit(view_id, cmd);
+        let msgs = drain(&mut harness.client_rx);
+        let changed = msgs.iter().find(|msg| msg["method"] == "config_changed");
+        assert_eq!(changed.unwrap()["params"]["changes"]["line_ending"], json!("\r\n"));
+
+        // the newline added on save, and inserted ones, use the new ending
+        harness.core.lock().unwrap().do_save(view_id, &path);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "one\r\ntwo\r\n");
+        harness.core.lock().unwrap().do_edit(view_id, EditNotification::MoveToEndOfDocument);
+        harness.core.lock().unwrap().do_edit(view_id, EditNotification::InsertNewline);
+        insert(&harness, view_id, "three");
+        harness.core.lock().unwrap().do_save(view_id, &path);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "one\r\ntwo\r\nthree\r\n");
+    }
+
+    #[test]
+    fn lossy_file_saved_elsewhere() {
+        let tmp = TempDir::new("xi-test-lossy-encoding").unwrap();
+        let path = tmp.path().join("file.txt");
+        let bytes = b"\xff\xfe\x00\xd8a\x00";
+        fs::write(&path, bytes).unwrap();
+        let mut harness = CoreHarness::new(vec![]);
+        let view_id = open_file(&harness, &path);
+        let msgs = drain(&mut harness.client_rx);
+        let reported = msgs.iter().find(|msg| msg["method"] == "encoding_changed");
+        assert_eq!(reported.unwrap()["params"]["lossy"], json!(true));
+
+        // saving over the file would replace its invalid bytes
+        insert(&harness, view_id, "x");
+        harness.core.lock().unwrap().do_save(view_id, &path);
+        let msgs = drain(&mut harness.client_rx);
+        let failed = msgs.iter().find(|msg| msg["method"] == "save_failed").unwrap();
+        assert_eq!(failed["params"]["error"]["kind"], json!("lossy_encoding"));
+        assert_eq!(fs::read(&path).unwrap(), bytes);
+
+        let other_path = tmp.path().join("other.txt");
+        harness.core.lock().unwrap().do_save(view_id, &other_path);
+        assert_eq!(fs::read(&other_path).unwrap(), b"\xff\xfex\x00\xfd\xffa\x00\n\x00");
+    }
+
+    #[test]
+    fn toggle_related_file() {
+        let tmp = TempDir::new("xi-test-related-file").unwrap();
+        let source = tmp.path().join("foo.cpp");
+        fs::write(&source, "#include \"foo.h\"\n").unwrap();
+        fs::write(tmp.path().join("foo.h"), "void foo();\n").unwrap();
+        let mut harness = CoreHarness::new(vec![]);
+        let source_view = open_file(&harness, &source);
+        drain(&mut harness.client_rx);
+
+        let toggle = |view_id| CoreNotification::ToggleRelatedFile { view_id };
+        harness.core.lock().unwrap().client_notification(toggle(source_view));
+        let show_view = skip_to(&mut harness.client_rx, "show_view");
+        assert_eq!(show_view["params"]["title"], json!("foo.h"));
+        let header_view: ViewId =
+            serde_json::from_value(show_view["params"]["view_id"].clone()).unwrap();
+        assert_ne!(header_view, source_view);
+
+        // toggling back shows the view that is already open
+        harness.core.lock().unwrap().client_notification(toggle(header_view));
+        let show_view = skip_to(&mut harness.client_rx, "show_view");
+        assert_eq!(show_view["params"], json!({ "view_id": source_view, "title": "foo.cpp" }));
+        assert_eq!(harness.core.lock().unwrap().views.len(), 2);
+    }
 
-    use super::ViewId;
+    #[test]
+    fn toggle_related_file_missing() {
+        let tmp = TempDir::new("xi-test-related-file-missing").unwrap();
+        let source = tmp.path().join("foo.cpp");
+        fs::write(&source, "int main() {}\n").unwrap();
+        let mut harness = CoreHarness::new(vec![]);
+        let view_id = open_file(&harness, &source);
+        drain(&mut harness.client_rx);
+
+        let toggle = CoreNotification::ToggleRelatedFile { view_id };
+        harness.core.lock().unwrap().client_notification(toggle);
+        let msgs = drain(&mut harness.client_rx);
+        assert_eq!(count_rpcs(&msgs, "alert"), 1);
+        assert_eq!(count_rpcs(&msgs, "show_view"), 0);
+        assert_eq!(harness.core.lock().unwrap().views.len(), 1);
+    }
+
+    #[test]
+    fn recording_rpcs() {
+        let tmp = TempDir::new("xi-test-recording-rpcs").unwrap();
+        let path = tmp.path().join("file.txt");
+        fs::write(&path, "hello\n").unwrap();
+        let mut harness = CoreHarness::new(vec![]);
+        let view_id = open_file(&harness, &path);
+        {
+            let core = harness.core.lock().unwrap();
+            let mut ctx = core.make_context(view_id).unwrap();
+            let recording_name = Some("greet".to_owned());
+            ctx.do_edit(EditNotification::ToggleRecording {
+                recording_name: recording_name.clone(),
+            });
+            ctx.do_edit(EditNotification::Insert { chars: "hi ".into() });
+            ctx.do_edit(EditNotification::ToggleRecording { recording_name });
+        }
+        drain(&mut harness.client_rx);
+
+        let recording_path = tmp.path().join("greet.json");
+        let mut core = harness.core.lock().unwrap();
+        core.client_notification(CoreNotification::SaveRecording {
+            name: "greet".into(),
+            path: recording_path.clone(),
+        });
+        core.recorder.borrow_mut().clear("greet");
+        assert_eq!(core.client_request(CoreRequest::ListRecordings {}).unwrap(), json!([]));
+
+        core.client_notification(CoreNotification::LoadRecording { path: recording_path });
+        let recordings = core.client_request(CoreRequest::ListRecordings {}).unwrap();
+        assert_eq!(recordings, json!([{ "name": "greet", "len": 1 }]));
+
+        // a file that isn't a recording is reported to the user
+        core.client_notification(CoreNotification::LoadRecording { path });
+        drop(core);
+        assert_eq!(count_rpcs(&drain(&mut harness.client_rx), "alert"), 1);
+    }
 
     #[test]
     fn test_deserialize_view_id() {
@@ -1113,4 +2778,338 @@ mod tests {
         let de = json!("not-a-view-id");
         assert!(ViewId::deserialize(&de).unwrap_err().is_data());
     }
+
+    #[test]
+    fn id_round_trips() {
+        for &id in &[0, 1, 42, MAX_ID] {
+            let view_id = ViewId::new(id);
+            assert_eq!(view_id.get(), id);
+            assert_eq!(view_id.to_string().parse::<ViewId>(), Ok(view_id));
+            let json = serde_json::to_value(view_id).unwrap();
+            assert_eq!(json, json!(format!("view-id-{}", id)));
+            assert_eq!(ViewId::deserialize(&json).unwrap(), view_id);
+
+            let buffer_id = BufferId::new(id);
+            assert_eq!(buffer_id.get(), id);
+            assert_eq!(buffer_id.to_string().parse::<BufferId>(), Ok(buffer_id));
+            let json = serde_json::to_value(buffer_id).unwrap();
+            assert_eq!(json, json!(format!("buffer-id-{}", id)));
+            assert_eq!(BufferId::deserialize(&json).unwrap(), buffer_id);
+        }
+
+        // buffer ids used to be sent as bare numbers
+        assert_eq!(BufferId::deserialize(&json!(42)).unwrap(), BufferId(42));
+    }
+
+    #[test]
+    fn malformed_ids_are_rejected() {
+        let too_big = format!("view-id-{}", MAX_ID + 1);
+        let overflow = format!("view-id-{}0", usize::max_value());
+        let bad_view_ids = [
+            "",
+            "view-id-",
+            "view-1-id",
+            "view-id-view-id-1",
+            "1",
+            "view-id--1",
+            "view-id-+1",
+            "view-id-01",
+            "view-id-1 ",
+            " view-id-1",
+            "view-id-1.0",
+            "view-id-0x1",
+            "VIEW-ID-1",
+            "buffer-id-1",
+            "view-id-١",
+            too_big.as_str(),
+            overflow.as_str(),
+        ];
+        for &s in bad_view_ids.iter() {
+            let err = s.parse::<ViewId>().unwrap_err();
+            assert_eq!(err.to_string(), format!("invalid view id {:?}", s));
+            assert!(ViewId::deserialize(&json!(s)).unwrap_err().is_data(), "{:?}", s);
+        }
+        assert!(ViewId::deserialize(&json!(1)).is_err());
+        assert!(ViewId::deserialize(&json!(null)).is_err());
+
+        let bad_buffer_ids = ["", "buffer-id-", "buffer-id-01", "view-id-1", "buffer-id-1a"];
+        for &s in bad_buffer_ids.iter() {
+            assert!(s.parse::<BufferId>().is_err(), "{:?}", s);
+            assert!(BufferId::deserialize(&json!(s)).is_err(), "{:?}", s);
+        }
+        assert!(BufferId::deserialize(&json!(-1)).is_err());
+        assert!(BufferId::deserialize(&json!(1.5)).is_err());
+        assert!(BufferId::deserialize(&json!(MAX_ID as u64 + 1)).is_err());
+    }
+
+    #[test]
+    fn idle_token_conversion_is_bounded() {
+        let id = ViewId::new(7);
+        let token = usize::from(id) | FIND_VIEW_IDLE_MASK;
+        assert_eq!(ViewId::from(token ^ FIND_VIEW_IDLE_MASK), id);
+        // stray mask bits are dropped rather than producing an out-of-range id
+        assert_eq!(ViewId::from(token | RENDER_VIEW_IDLE_MASK), id);
+        assert_eq!(ViewId::from(usize::max_value()).get(), MAX_ID);
+    }
+
+    /// Warm starts of plugins, and the notifications clients are sent about
+    /// plugins, using a shell script as the plugin.
+    #[cfg(unix)]
+    mod warm_plugins {
+        use std::os::unix::fs::PermissionsExt;
+        use std::path::PathBuf;
+        use std::thread;
+        use std::time::Instant;
+
+        use super::*;
+        use crate::plugins::PluginState;
+        use crate::tabs::WARM_PLUGIN_IDLE_MASK;
+
+        /// A plugin which appends to `log`, in its directory, when it starts and
+        /// when it is sent `new_buffer` or `shutdown`. If there is a file named
+        /// `crash` beside it, it removes it and exits at once.
+        const FAKE_PLUGIN: &str = r#"#!/bin/sh
+dir=$(dirname "$0")
+echo started >> "$dir/log"
+if [ -e "$dir/crash" ]; then
+    rm "$dir/crash"
+    exit 1
+fi
+while read -r line; do
+    case "$line" in
+        *'"method":"new_buffer"'*) echo new_buffer >> "$dir/log" ;;
+        *'"method":"shutdown"'*) echo shutdown >> "$dir/log"; exit 0 ;;
+    esac
+done
+"#;
+
+        /// Writes a `FAKE_PLUGIN` named `name` to a directory in `dir`, and
+        /// returns the directory.
+        fn write_fake_plugin(dir: &Path, name: &str, preload: bool) -> PathBuf {
+            let plugin_dir = dir.join(name);
+            fs::create_dir_all(&plugin_dir).unwrap();
+            let manifest = format!(
+                "name = \"{}\"\nversion = \"0\"\nexec_path = \"./fake\"\npreload = {}\n",
+                name, preload
+            );
+            fs::write(plugin_dir.join("manifest.toml"), manifest).unwrap();
+            let exec_path = plugin_dir.join("fake");
+            fs::write(&exec_path, FAKE_PLUGIN).unwrap();
+            fs::set_permissions(&exec_path, fs::Permissions::from_mode(0o755)).unwrap();
+            plugin_dir
+        }
+
+        fn read_log(plugin_dir: &Path) -> Vec<String> {
+            let log = fs::read_to_string(plugin_dir.join("log")).unwrap_or_default();
+            log.lines().map(String::from).collect()
+        }
+
+        /// Starts a core which loads the plugins in `plugins_dir`.
+        fn core_with_plugins(plugins_dir: &Path) -> (Arc<Mutex<CoreState>>, DummyReader) {
+            let (client_tx, client_rx) = test_channel();
+            let client: RpcPeer = Box::new(RpcLoop::new(client_tx).get_raw_peer());
+            let core = CoreState::new(&client, None, Some(plugins_dir.to_owned()));
+            let core = Arc::new(Mutex::new(core));
+            core.lock().unwrap().finish_setup(weak_core(&core));
+            (core, client_rx)
+        }
+
+        fn plugin_states(core: &Mutex<CoreState>) -> Vec<(String, PluginState)> {
+            let core = core.lock().unwrap();
+            core.running_plugins.iter().map(|p| (p.name.clone(), p.state)).collect()
+        }
+
+        /// Waits for plugin processes, which run on their own threads.
+        fn wait_for<F: FnMut() -> bool>(mut done: F) {
+            let deadline = Instant::now() + Duration::from_secs(10);
+            while !done() {
+                assert!(Instant::now() < deadline, "timed out waiting for plugins");
+                thread::sleep(Duration::from_millis(10));
+            }
+        }
+
+        fn open_in(core: &Mutex<CoreState>, path: &Path) {
+            let mut core = core.lock().unwrap();
+            core.do_new_view(Some(path.to_owned())).unwrap();
+            core.handle_idle(NEW_VIEW_IDLE_TOKEN);
+        }
+
+        #[test]
+        fn preloaded_plugin_is_warmed() {
+            let tmp = TempDir::new("xi-test-plugin-warm").unwrap();
+            let warm = write_fake_plugin(tmp.path(), "warm", true);
+            let cold = write_fake_plugin(tmp.path(), "cold", false);
+            let path = tmp.path().join("file.txt");
+            fs::write(&path, "hello\n").unwrap();
+            let (core, _client_rx) = core_with_plugins(tmp.path());
+
+            wait_for(|| plugin_states(&core) == vec![("warm".to_owned(), PluginState::Warmed)]);
+            wait_for(|| read_log(&warm) == vec!["started"]);
+            assert!(read_log(&cold).is_empty());
+
+            // the warmed plugin is sent the buffer, without being started again,
+            // and the other plugin is started
+            open_in(&core, &path);
+            wait_for(|| read_log(&warm) == vec!["started", "new_buffer"]);
+            wait_for(|| plugin_states(&core).len() == 2);
+            assert!(plugin_states(&core).iter().all(|(_, state)| *state == PluginState::Attached));
+            wait_for(|| read_log(&cold) == vec!["started"]);
+        }
+
+        #[test]
+        fn unused_warmed_plugin_is_stopped() {
+            let tmp = TempDir::new("xi-test-plugin-warm-timeout").unwrap();
+            let warm = write_fake_plugin(tmp.path(), "warm", true);
+            let path = tmp.path().join("file.txt");
+            fs::write(&path, "hello\n").unwrap();
+            let (core, _client_rx) = core_with_plugins(tmp.path());
+            wait_for(|| plugin_states(&core).len() == 1);
+
+            {
+                // checked before the plugin gets to exit
+                let mut core = core.lock().unwrap();
+                let id = core.running_plugins[0].id;
+                core.handle_idle(WARM_PLUGIN_IDLE_MASK | id.0);
+                assert_eq!(core.running_plugins[0].state, PluginState::Stopping);
+            }
+            wait_for(|| plugin_states(&core).is_empty());
+            assert_eq!(read_log(&warm), vec!["started", "shutdown"]);
+
+            // it is started again when a buffer is opened
+            open_in(&core, &path);
+            wait_for(|| plugin_states(&core) == vec![("warm".to_owned(), PluginState::Attached)]);
+            wait_for(|| read_log(&warm).len() == 3);
+            assert_eq!(read_log(&warm), vec!["started", "shutdown", "started"]);
+
+            // and the timer of an attached plugin does nothing
+            let id = core.lock().unwrap().running_plugins[0].id;
+            core.lock().unwrap().handle_idle(WARM_PLUGIN_IDLE_MASK | id.0);
+            assert_eq!(plugin_states(&core), vec![("warm".to_owned(), PluginState::Attached)]);
+        }
+
+        #[test]
+        fn crashed_warmed_plugin_is_respawned() {
+            let tmp = TempDir::new("xi-test-plugin-warm-crash").unwrap();
+            let warm = write_fake_plugin(tmp.path(), "warm", true);
+            fs::write(warm.join("crash"), "").unwrap();
+            let (core, mut client_rx) = core_with_plugins(tmp.path());
+
+            wait_for(|| read_log(&warm).len() == 2 && plugin_states(&core).len() == 1);
+            assert_eq!(read_log(&warm), vec!["started", "started"]);
+            assert_eq!(plugin_states(&core), vec![("warm".to_owned(), PluginState::Warmed)]);
+            let msgs = drain(&mut client_rx);
+            assert_eq!(count_rpcs(&msgs, "alert"), 0);
+            assert_eq!(count_rpcs(&msgs, "plugin_stopped"), 0);
+        }
+
+        /// Returns the method and params of each plugin notification.
+        fn plugin_msgs(msgs: &[Value]) -> Vec<(String, Value)> {
+            msgs.iter()
+                .filter(|msg| msg["method"].as_str().map_or(false, |m| m.contains("plugin")))
+                .map(|msg| (msg["method"].as_str().unwrap().to_owned(), msg["params"].clone()))
+                .collect()
+        }
+
+        #[test]
+        fn available_plugins_are_sorted() {
+            let tmp = TempDir::new("xi-test-plugin-order").unwrap();
+            for name in &["zeta", "alpha", "mid"] {
+                write_fake_plugin(tmp.path(), name, *name == "mid");
+            }
+            let path = tmp.path().join("file.txt");
+            fs::write(&path, "hello\n").unwrap();
+            let (core, mut client_rx) = core_with_plugins(tmp.path());
+            wait_for(|| plugin_states(&core).len() == 1);
+
+            let snapshot = |client_rx: &mut DummyReader| {
+                let msgs = plugin_msgs(&drain(client_rx));
+                let (_, params) =
+                    msgs.into_iter().find(|(method, _)| method == "available_plugins").unwrap();
+                params["plugins"].clone()
+            };
+            open_in(&core, &path);
+            let expected = json!([
+                {"name": "alpha", "running": false},
+                {"name": "mid", "running": true},
+                {"name": "zeta", "running": false},
+            ]);
+            assert_eq!(snapshot(&mut client_rx), expected);
+
+            // the other plugins are started once there is a view
+            wait_for(|| plugin_states(&core).len() == 3);
+            open_in(&core, &path);
+            let expected = json!([
+                {"name": "alpha", "running": true},
+                {"name": "mid", "running": true},
+                {"name": "zeta", "running": true},
+            ]);
+            assert_eq!(snapshot(&mut client_rx), expected);
+        }
+
+        #[test]
+        #[cfg(feature = "notify")]
+        fn plugin_changes_send_one_snapshot() {
+            use notify::event::{CreateKind, EventKind};
+            use notify::Event;
+
+            use crate::tabs::PLUGIN_EVENT_TOKEN;
+
+            let tmp = TempDir::new("xi-test-plugin-reload").unwrap();
+            let path = tmp.path().join("file.txt");
+            fs::write(&path, "hello\n").unwrap();
+            let (core, mut client_rx) = core_with_plugins(tmp.path());
+            open_in(&core, &path);
+            drain(&mut client_rx);
+
+            let events = ["one", "two"]
+                .iter()
+                .map(|name| {
+                    let dir = write_fake_plugin(tmp.path(), name, false);
+                    let event = Event::new(EventKind::Create(CreateKind::Any)).add_path(dir);
+                    (PLUGIN_EVENT_TOKEN, event)
+                })
+                .collect::<Vec<_>>();
+            core.lock().unwrap().handle_fs_event_batch(events);
+            let msgs = drain(&mut client_rx);
+            assert_eq!(count_rpcs(&msgs, "available_plugins"), 1);
+            let snapshot = msgs.iter().find(|m| m["method"] == "available_plugins");
+            let names = snapshot.unwrap()["params"]["plugins"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|p| p["name"].clone())
+                .collect::<Vec<_>>();
+            assert_eq!(names, vec![json!("one"), json!("two")]);
+            wait_for(|| plugin_states(&core).len() == 2);
+        }
+
+        #[test]
+        fn plugin_restart_is_sequenced() {
+            let tmp = TempDir::new("xi-test-plugin-restart").unwrap();
+            write_fake_plugin(tmp.path(), "warm", true);
+            let path = tmp.path().join("file.txt");
+            fs::write(&path, "hello\n").unwrap();
+            let (core, mut client_rx) = core_with_plugins(tmp.path());
+            wait_for(|| plugin_states(&core).len() == 1);
+            open_in(&core, &path);
+            let attached = vec![("warm".to_owned(), PluginState::Attached)];
+            wait_for(|| plugin_states(&core) == attached);
+
+            {
+                let mut core = core.lock().unwrap();
+                let view_id = *core.views.keys().next().unwrap();
+                core.do_stop_plugin(view_id, "warm");
+                core.do_start_plugin(view_id, "warm");
+            }
+            wait_for(|| plugin_states(&core) == attached);
+
+            let msgs = plugin_msgs(&drain(&mut client_rx));
+            let methods = msgs.iter().map(|(method, _)| method.as_str()).collect::<Vec<_>>();
+            let expected =
+                vec!["plugin_started", "available_plugins", "plugin_stopped", "plugin_started"];
+            assert_eq!(methods, expected);
+            let seqs = msgs.iter().map(|(_, params)| params["seq"].as_u64().unwrap());
+            assert_eq!(seqs.collect::<Vec<_>>(), vec![1, 2, 3, 4]);
+        }
+    }
 }
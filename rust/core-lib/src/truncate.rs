@@ -0,0 +1,209 @@
+// Copyright 2020 The xi-editor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Truncation of strings shown to the user, such as status items and alerts.
+//!
+//! Strings are only ever cut between grapheme clusters, so that neither a
+//! multi-byte character nor a sequence like an emoji ZWJ sequence is split.
+
+use std::borrow::Cow;
+use std::cmp::Ordering;
+
+use unicode_segmentation::UnicodeSegmentation;
+use xi_unicode::has_emoji_presentation;
+
+const ELLIPSIS: char = '\u{2026}';
+
+/// Ranges of characters with an East Asian Width of Wide or Fullwidth,
+/// other than emoji, which are handled separately.
+const WIDE_RANGES: &[(char, char)] = &[
+    ('\u{1100}', '\u{115F}'),
+    ('\u{2E80}', '\u{303E}'),
+    ('\u{3041}', '\u{33FF}'),
+    ('\u{3400}', '\u{4DBF}'),
+    ('\u{4E00}', '\u{9FFF}'),
+    ('\u{A000}', '\u{A4CF}'),
+    ('\u{A960}', '\u{A97F}'),
+    ('\u{AC00}', '\u{D7A3}'),
+    ('\u{F900}', '\u{FAFF}'),
+    ('\u{FE10}', '\u{FE19}'),
+    ('\u{FE30}', '\u{FE6F}'),
+    ('\u{FF00}', '\u{FF60}'),
+    ('\u{FFE0}', '\u{FFE6}'),
+    ('\u{20000}', '\u{2FFFD}'),
+    ('\u{30000}', '\u{3FFFD}'),
+];
+
+fn is_wide(c: char) -> bool {
+    WIDE_RANGES
+        .binary_search_by(|&(start, end)| {
+            if end < c {
+                Ordering::Less
+            } else if start > c {
+                Ordering::Greater
+            } else {
+                Ordering::Equal
+            }
+        })
+        .is_ok()
+}
+
+/// The number of columns taken up by a grapheme cluster: two if it starts
+/// with a wide character or is displayed as an emoji, and one otherwise.
+fn grapheme_width(grapheme: &str) -> usize {
+    let mut chars = grapheme.chars();
+    match chars.next() {
+        Some(c) if is_wide(c) || has_emoji_presentation(c, chars.next()) => 2,
+        Some(_) => 1,
+        None => 0,
+    }
+}
+
+/// Returns the number of columns `s` takes up in a monospace font, counting
+/// wide characters, such as CJK ideographs, and emoji as two.
+pub fn str_width(s: &str) -> usize {
+    s.graphemes(true).map(grapheme_width).sum()
+}
+
+/// Returns the longest prefix of `s` of at most `max` grapheme clusters.
+pub fn truncate_graphemes(s: &str, max: usize) -> &str {
+    match s.grapheme_indices(true).nth(max) {
+        Some((ix, _)) => &s[..ix],
+        None => s,
+    }
+}
+
+/// Returns the longest prefix of `s` that fits in `max_cols` columns.
+fn prefix_in_cols(s: &str, max_cols: usize) -> &str {
+    let mut cols = 0;
+    for (ix, grapheme) in s.grapheme_indices(true) {
+        cols += grapheme_width(grapheme);
+        if cols > max_cols {
+            return &s[..ix];
+        }
+    }
+    s
+}
+
+/// Returns the longest suffix of `s` that fits in `max_cols` columns.
+fn suffix_in_cols(s: &str, max_cols: usize) -> &str {
+    let mut cols = 0;
+    for (ix, grapheme) in s.grapheme_indices(true).rev() {
+        cols += grapheme_width(grapheme);
+        if cols > max_cols {
+            return &s[ix + grapheme.len()..];
+        }
+    }
+    s
+}
+
+/// Returns `s` if it fits in `max_cols` columns. Otherwise, returns as much
+/// of its start as fits, followed by an ellipsis.
+pub fn truncate_with_ellipsis(s: &str, max_cols: usize) -> Cow<str> {
+    if str_width(s) <= max_cols {
+        return Cow::from(s);
+    }
+    match max_cols.checked_sub(1) {
+        Some(cols) => Cow::from(format!("{}{}", prefix_in_cols(s, cols), ELLIPSIS)),
+        None => Cow::from(""),
+    }
+}
+
+/// Returns `s` if it fits in `max_cols` columns. Otherwise, replaces its
+/// middle with an ellipsis, keeping as much of its start and end as fits.
+/// The end gets the larger share, as for paths it holds the file name.
+pub fn middle_ellipsis(s: &str, max_cols: usize) -> Cow<str> {
+    if str_width(s) <= max_cols {
+        return Cow::from(s);
+    }
+    let cols = match max_cols.checked_sub(1) {
+        Some(cols) => cols,
+        None => return Cow::from(""),
+    };
+    let head = prefix_in_cols(s, cols / 2);
+    let tail = suffix_in_cols(&s[head.len()..], cols - str_width(head));
+    Cow::from(format!("{}{}{}", head, ELLIPSIS, tail))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FAMILY: &str = "\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}";
+
+    #[test]
+    fn truncate_at_graphemes() {
+        assert_eq!(truncate_graphemes("hello", 3), "hel");
+        assert_eq!(truncate_graphemes("hello", 5), "hello");
+        assert_eq!(truncate_graphemes("hello", 10), "hello");
+        assert_eq!(truncate_graphemes("hello", 0), "");
+        // multi-byte characters are kept whole
+        assert_eq!(truncate_graphemes("\u{65E5}\u{672C}\u{8A9E}", 2), "\u{65E5}\u{672C}");
+        // as are combining sequences
+        assert_eq!(truncate_graphemes("ce\u{301}u", 2), "ce\u{301}");
+        // and emoji ZWJ sequences
+        let s = format!("a{}b", FAMILY);
+        assert_eq!(truncate_graphemes(&s, 2), format!("a{}", FAMILY));
+        assert_eq!(truncate_graphemes(&s, 1), "a");
+    }
+
+    #[test]
+    fn widths() {
+        assert_eq!(str_width(""), 0);
+        assert_eq!(str_width("abc"), 3);
+        assert_eq!(str_width("e\u{301}"), 1);
+        assert_eq!(str_width("\u{65E5}\u{672C}"), 4);
+        assert_eq!(str_width("\u{D55C}\u{AE00}"), 4);
+        assert_eq!(str_width("\u{FF21}"), 2);
+        assert_eq!(str_width("\u{1F44D}"), 2);
+        assert_eq!(str_width(FAMILY), 2);
+        // a flag is a single grapheme
+        assert_eq!(str_width("\u{1F1EF}\u{1F1F5}"), 2);
+        // variation selectors choose between text and emoji presentation
+        assert_eq!(str_width("\u{2764}"), 1);
+        assert_eq!(str_width("\u{2764}\u{FE0F}"), 2);
+        assert_eq!(str_width("\u{231A}\u{FE0E}"), 1);
+    }
+
+    #[test]
+    fn ellipsis_by_columns() {
+        assert_eq!(truncate_with_ellipsis("hello", 5), Cow::from("hello"));
+        assert_eq!(truncate_with_ellipsis("hello world", 8), "hello w\u{2026}");
+        assert_eq!(truncate_with_ellipsis("hello", 1), "\u{2026}");
+        assert_eq!(truncate_with_ellipsis("hello", 0), "");
+        // a wide character that doesn't fit is left out
+        let cjk = "\u{65E5}\u{672C}\u{8A9E}\u{30C6}\u{30AD}\u{30B9}\u{30C8}";
+        assert_eq!(truncate_with_ellipsis(cjk, 6), "\u{65E5}\u{672C}\u{2026}");
+        assert_eq!(truncate_with_ellipsis(cjk, 7), "\u{65E5}\u{672C}\u{8A9E}\u{2026}");
+        let s = format!("ab{}cd", FAMILY);
+        assert_eq!(truncate_with_ellipsis(&s, 4), "ab\u{2026}");
+        assert_eq!(truncate_with_ellipsis(&s, 5), format!("ab{}\u{2026}", FAMILY));
+    }
+
+    #[test]
+    fn ellipsis_in_middle() {
+        let path = "/home/user/projects/file.rs";
+        assert_eq!(middle_ellipsis(path, 27), path);
+        assert_eq!(middle_ellipsis(path, 16), "/home/u\u{2026}/file.rs");
+        assert_eq!(middle_ellipsis(path, 1), "\u{2026}");
+        assert_eq!(middle_ellipsis(path, 0), "");
+
+        // "/\u{6587}\u{6863}/\u{62A5}\u{544A}.txt" is 14 columns wide
+        let path = "/\u{6587}\u{6863}/\u{62A5}\u{544A}.txt";
+        assert_eq!(middle_ellipsis(path, 10), "/\u{6587}\u{2026}\u{544A}.txt");
+        for max_cols in 0..14 {
+            assert!(str_width(&middle_ellipsis(path, max_cols)) <= max_cols);
+        }
+    }
+}
@@ -12,28 +12,44 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::borrow::Cow;
 use std::cell::RefCell;
 use std::cmp::{max, min};
+use std::collections::{HashMap, VecDeque};
 use std::iter;
+use std::mem;
 use std::ops::Range;
+use std::panic::{self, AssertUnwindSafe};
+use std::time::{Duration, Instant};
 
 use serde_json::Value;
 
-use crate::annotations::{AnnotationStore, Annotations, ToAnnotation};
-use crate::client::{Client, Update, UpdateOp};
+use crate::annotations::{
+    AnnotationSlice, AnnotationSource, AnnotationStore, Annotations, HighlightKindId,
+    HighlightKinds, ToAnnotation,
+};
+use crate::bookmarks::Bookmarks;
+use crate::client::{Client, PrefetchHint, Update, UpdateOp};
+use crate::composition::Composition;
 use crate::edit_types::ViewEvent;
-use crate::find::{Find, FindStatus};
+use crate::find::{enclosing_block, Find, FindStatus};
+use crate::folds::Folds;
 use crate::line_cache_shadow::{self, LineCacheShadow, RenderPlan, RenderTactic};
-use crate::line_offset::LineOffset;
+use crate::line_offset::{LineOffset, LogicalLines};
 use crate::linewrap::{InvalLines, Lines, VisualLine, WrapWidth};
-use crate::movement::{region_movement, selection_movement, Movement};
+use crate::movement::{region_movement, selection_movement_repeated, Movement};
 use crate::plugins::PluginId;
-use crate::rpc::{FindQuery, GestureType, MouseAction, SelectionGranularity, SelectionModifier};
+use crate::render_journal::{PendingRecord, RenderJournal};
+use crate::rpc::{
+    ClientCapability, FindQuery, GestureType, LineRange, MouseAction, SelectionGranularity,
+    SelectionModifier,
+};
 use crate::selection::{Affinity, InsertDrift, SelRegion, Selection};
 use crate::styles::{Style, ThemeStyleMap};
 use crate::tabs::{BufferId, Counter, ViewId};
 use crate::width_cache::WidthCache;
 use crate::word_boundaries::WordCursor;
+use xi_rope::rope::Utf16CodeUnitsMetric;
 use xi_rope::spans::Spans;
 use xi_rope::{Cursor, Interval, LinesMetric, Rope, RopeDelta};
 use xi_trace::trace_block;
@@ -46,6 +62,23 @@ const FLAG_SELECT: u64 = 2;
 /// Size of batches as number of bytes used during incremental find.
 const FIND_BATCH_SIZE: usize = 500000;
 
+/// How long viewport positions are kept for estimating the scroll velocity.
+const SCROLL_SAMPLE_WINDOW: Duration = Duration::from_millis(300);
+
+/// A view that hasn't scrolled for this long is considered idle.
+const SCROLL_IDLE_TIME: Duration = Duration::from_millis(300);
+
+/// Prefetch hints grow to cover the lines scrolled past in this many seconds
+/// at the current velocity.
+const PREFETCH_HORIZON_SECS: f64 = 0.5;
+
+/// At this many lines per second or more, lines ahead of the viewport are
+/// rendered without waiting for the client to request them.
+const PROACTIVE_RENDER_VELOCITY: f64 = 100.0;
+
+/// The most lines rendered ahead of the viewport in a single update.
+const MAX_PROACTIVE_LINES: usize = 200;
+
 /// A view to a buffer. It is the buffer plus additional information
 /// like line breaks and selection state.
 pub struct View {
@@ -68,6 +101,9 @@ pub struct View {
     height: usize,
     lines: Lines,
 
+    /// The folded regions, whose lines are hidden from the front end.
+    folds: Folds,
+
     /// Front end's line cache state for this view. See the `LineCacheShadow`
     /// description for the invariant.
     lc_shadow: LineCacheShadow,
@@ -89,6 +125,13 @@ pub struct View {
     /// Tracks the progress of incremental find.
     find_progress: FindProgress,
 
+    /// When the last find status was sent during the current incremental find.
+    /// `None` if the next status should be sent right away.
+    find_status_sent: Option<Instant>,
+
+    /// Tracks whether a timer is scheduled to send a throttled find status.
+    find_status_scheduled: bool,
+
     /// Tracks whether find highlights should be rendered.
     /// Highlights are only rendered when search dialog is open.
     highlight_find: bool,
@@ -101,6 +144,63 @@ pub struct View {
 
     /// Annotations provided by plugins.
     annotations: AnnotationStore,
+
+    /// The highlight kinds registered with this view.
+    highlight_kinds: HighlightKinds,
+
+    /// The input method composition in progress, if any. Its text is shown
+    /// at the selections, which it replaces when it is committed.
+    composition: Option<Composition>,
+
+    /// For a client that retains annotations, the generation of each set of
+    /// annotations in the last update. Cleared to send everything again.
+    sent_annotations: HashMap<AnnotationSource, u64>,
+
+    /// The range of text the last sent annotations were computed for.
+    sent_annotations_range: Option<Interval>,
+
+    /// The recent renders of this view, if enabled. See `RenderJournal`.
+    render_journal: Option<RenderJournal>,
+
+    /// Recent scroll positions, for advising the client how many lines to
+    /// keep around the viewport.
+    scroll_tracker: ScrollTracker,
+
+    /// The smallest and largest number of lines in a `PrefetchHint`.
+    prefetch_bounds: (usize, usize),
+}
+
+/// Recent viewport positions of a view, for estimating how fast it scrolls.
+#[derive(Debug, Default)]
+struct ScrollTracker {
+    /// The first visible line, with the time it was set; oldest first.
+    samples: VecDeque<(Instant, usize)>,
+}
+
+impl ScrollTracker {
+    fn record(&mut self, now: Instant, first_line: usize) {
+        self.samples.push_back((now, first_line));
+        while self.samples.len() > 1
+            && now.saturating_duration_since(self.samples[0].0) > SCROLL_SAMPLE_WINDOW
+        {
+            self.samples.pop_front();
+        }
+    }
+
+    /// Returns the scroll velocity at `now`, in lines per second. This is
+    /// positive when scrolling down, and zero when the view is idle.
+    fn velocity(&self, now: Instant) -> f64 {
+        let (first, last) = match (self.samples.front(), self.samples.back()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return 0.0,
+        };
+        if now.saturating_duration_since(last.0) > SCROLL_IDLE_TIME || first.1 == last.1 {
+            return 0.0;
+        }
+        // events arriving together still count as (very) fast scrolling
+        let secs = last.0.saturating_duration_since(first.0).as_secs_f64().max(0.001);
+        (last.1 as f64 - first.1 as f64) / secs
+    }
 }
 
 /// Indicates what changed in the find state.
@@ -137,6 +237,26 @@ pub struct Replace {
     pub preserve_case: bool,
 }
 
+/// The position of an offset in the buffer, in coordinates that frontends
+/// can use to anchor popups without reproducing core's wrapping.
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Clone, Copy)]
+pub struct LinePosition {
+    /// The logical line.
+    pub line: usize,
+    /// The column in the logical line, in UTF-8 code units.
+    pub col: usize,
+    /// The column in the logical line, in UTF-16 code units.
+    pub col_utf16: usize,
+    /// The visual line, when lines are wrapped.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub visual_line: Option<usize>,
+    /// The column in the visual line, in UTF-8 code units, when lines are wrapped.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub visual_col: Option<usize>,
+}
+
 /// A size, in pixel units (not display pixels).
 #[derive(Debug, Default, PartialEq, Serialize, Deserialize, Clone)]
 pub struct Size {
@@ -172,15 +292,29 @@ impl View {
             first_line: 0,
             height: 10,
             lines: Lines::default(),
+            folds: Folds::default(),
             lc_shadow: LineCacheShadow::default(),
             find: Vec::new(),
             find_id_counter: Counter::default(),
             find_changed: FindStatusChange::None,
             find_progress: FindProgress::Ready,
+            find_status_sent: None,
+            find_status_scheduled: false,
             highlight_find: false,
             replace: None,
             replace_changed: false,
             annotations: AnnotationStore::new(),
+            highlight_kinds: HighlightKinds::new(),
+            composition: None,
+            sent_annotations: HashMap::new(),
+            sent_annotations_range: None,
+            render_journal: if cfg!(debug_assertions) {
+                Some(RenderJournal::default())
+            } else {
+                None
+            },
+            scroll_tracker: ScrollTracker::default(),
+            prefetch_bounds: (0, 0),
         }
     }
 
@@ -208,11 +342,17 @@ impl View {
         self.pending_render
     }
 
-    pub(crate) fn update_wrap_settings(&mut self, text: &Rope, wrap_cols: usize, word_wrap: bool) {
+    pub(crate) fn update_wrap_settings(
+        &mut self,
+        text: &Rope,
+        wrap_cols: usize,
+        word_wrap: bool,
+        tab_width: usize,
+    ) {
         let wrap_width = match (word_wrap, wrap_cols) {
             (true, _) => WrapWidth::Width(self.size.width),
             (false, 0) => WrapWidth::None,
-            (false, cols) => WrapWidth::Bytes(cols),
+            (false, cols) => WrapWidth::Bytes { cols, tab_width },
         };
         self.lines.set_wrap_width(text, wrap_width);
     }
@@ -234,20 +374,38 @@ impl View {
         matches!(self.find_progress, FindProgress::InProgress(_) | FindProgress::Started)
     }
 
+    /// Returns `true` if there are find changes the frontend hasn't been told about.
+    pub(crate) fn find_status_pending(&self) -> bool {
+        self.find_changed != FindStatusChange::None
+    }
+
+    pub(crate) fn find_status_sent(&self) -> Option<Instant> {
+        self.find_status_sent
+    }
+
+    pub(crate) fn find_status_scheduled(&self) -> bool {
+        self.find_status_scheduled
+    }
+
+    pub(crate) fn set_find_status_scheduled(&mut self, scheduled: bool) {
+        self.find_status_scheduled = scheduled;
+    }
+
     pub(crate) fn do_edit(&mut self, text: &Rope, cmd: ViewEvent) {
         use self::ViewEvent::*;
         match cmd {
-            Move(movement) => self.do_move(text, movement, false),
-            ModifySelection(movement) => self.do_move(text, movement, true),
+            Move(movement, count) => self.do_move_repeated(text, movement, false, count),
+            ModifySelection(movement, count) => self.do_move_repeated(text, movement, true, count),
             SelectAll => self.select_all(text),
             Scroll(range) => self.set_scroll(range.first, range.last),
             AddSelectionAbove => self.add_selection_by_movement(text, Movement::UpExactPosition),
             AddSelectionBelow => self.add_selection_by_movement(text, Movement::DownExactPosition),
             Gesture { line, col, ty } => self.do_gesture(text, line, col, ty),
             GotoLine { line } => self.goto_line(text, line),
-            Find { chars, case_sensitive, regex, whole_words } => {
+            Find { chars, case_sensitive, regex, whole_words, find_in_block } => {
                 let id = self.find.first().map(|q| q.id());
-                let query_changes = FindQuery { id, chars, case_sensitive, regex, whole_words };
+                let query_changes =
+                    FindQuery { id, chars, case_sensitive, regex, whole_words, find_in_block };
                 self.set_find(text, [query_changes].to_vec())
             }
             MultiFind { queries } => self.set_find(text, queries),
@@ -291,13 +449,16 @@ impl View {
             Replace { chars, preserve_case } => self.do_set_replace(chars, preserve_case),
             SelectionForReplace => self.do_selection_for_replace(text),
             SelectionIntoLines => self.do_split_selection_into_lines(text),
+            Fold { line_range } => self.fold(text, self.interval_of_lines(text, &line_range)),
+            Unfold { line_range } => self.unfold(text, self.interval_of_lines(text, &line_range)),
+            UnfoldAll => self.unfold_all(text),
         }
     }
 
     fn do_gesture(&mut self, text: &Rope, line: u64, col: u64, ty: GestureType) {
         let line = line as usize;
         let col = col as usize;
-        let offset = self.line_col_to_offset(text, line, col);
+        let offset = self.visible_offset(text, self.line_col_to_offset(text, line, col));
         match ty {
             GestureType::Select { granularity, multi } => {
                 self.select(text, offset, granularity, multi)
@@ -315,7 +476,73 @@ impl View {
 
     fn goto_line(&mut self, text: &Rope, line: u64) {
         let offset = self.line_col_to_offset(text, line as usize, 0);
-        self.set_selection(text, SelRegion::caret(offset));
+        self.set_selection(text, SelRegion::caret(self.visible_offset(text, offset)));
+    }
+
+    /// Folds the lines touched by `iv`, hiding all but the first of them.
+    /// Carets in the hidden lines move to the end of that first line.
+    pub fn fold(&mut self, text: &Rope, iv: Interval) {
+        if !self.folds.fold(text, iv) {
+            return;
+        }
+        self.set_dirty(text);
+        let mut sel = Selection::new();
+        for region in self.selection.iter() {
+            let start = self.visible_offset(text, region.start);
+            let end = self.visible_offset(text, region.end);
+            sel.add_region(SelRegion::new(start, end));
+        }
+        self.set_selection(text, sel);
+    }
+
+    /// Unfolds the folds whose lines, including the line anchoring them,
+    /// intersect `iv`.
+    pub fn unfold(&mut self, text: &Rope, iv: Interval) {
+        if self.folds.unfold(text, iv) {
+            self.set_dirty(text);
+        }
+    }
+
+    pub fn unfold_all(&mut self, text: &Rope) {
+        if self.folds.unfold_all() {
+            self.set_dirty(text);
+        }
+    }
+
+    /// Returns `offset`, or if it is folded, the end of the line anchoring
+    /// its fold.
+    fn visible_offset(&self, text: &Rope, offset: usize) -> usize {
+        match self.folds.fold_at(offset) {
+            Some(fold) => text.prev_grapheme_offset(fold.start()).unwrap_or(0),
+            None => offset,
+        }
+    }
+
+    /// Returns the number of visual lines hidden by `fold`.
+    fn hidden_lines(&self, text: &Rope, fold: Interval) -> usize {
+        self.lines.visual_line_of_offset(text, fold.end()) + 1
+            - self.lines.visual_line_of_offset(text, fold.start())
+    }
+
+    /// Returns the byte range of the lines in `range`, both included.
+    fn interval_of_lines(&self, text: &Rope, range: &LineRange) -> Interval {
+        let first = max(range.first, 0) as usize;
+        let last = max(range.last, 0) as usize;
+        let last = max(last, first);
+        Interval::new(self.offset_of_line(text, first), self.offset_of_line(text, last + 1))
+    }
+
+    /// Returns an iterator over the visual lines that aren't folded, starting
+    /// at (and including) `start_line`.
+    fn iter_lines<'a>(
+        &'a self,
+        text: &'a Rope,
+        start_line: usize,
+    ) -> impl Iterator<Item = VisualLine> + 'a {
+        let start = self.offset_of_line(text, start_line);
+        self.lines
+            .iter_lines(text, self.lines.visual_line_of_offset(text, start))
+            .filter(move |line| self.folds.fold_at(line.interval.start).is_none())
     }
 
     pub fn set_size(&mut self, size: Size) {
@@ -327,6 +554,52 @@ impl View {
         let last = max(last, 0) as usize;
         self.first_line = first;
         self.height = last - first;
+        self.scroll_tracker.record(Instant::now(), first);
+    }
+
+    /// Sets the smallest and largest number of lines advised in prefetch hints.
+    pub(crate) fn set_prefetch_bounds(&mut self, min_lines: usize, max_lines: usize) {
+        self.prefetch_bounds = (min_lines, max(min_lines, max_lines));
+    }
+
+    /// Returns how many lines the client should keep beyond the viewport
+    /// at `now`. The lines in the direction of scrolling grow with the scroll
+    /// velocity; both return to the minimum when the view is idle.
+    pub(crate) fn prefetch_hint(&self, now: Instant) -> PrefetchHint {
+        let (min_lines, max_lines) = self.prefetch_bounds;
+        let velocity = self.scroll_tracker.velocity(now);
+        let extra = (velocity.abs() * PREFETCH_HORIZON_SECS) as usize;
+        let grown = min_lines.saturating_add(extra).min(max_lines);
+        if velocity > 0.0 {
+            PrefetchHint { lines_ahead: grown, lines_behind: min_lines }
+        } else if velocity < 0.0 {
+            PrefetchHint { lines_ahead: min_lines, lines_behind: grown }
+        } else {
+            PrefetchHint { lines_ahead: min_lines, lines_behind: min_lines }
+        }
+    }
+
+    /// When the view is scrolling fast, extends `plan` to render the lines
+    /// the client will soon scroll to, saving it a round trip to request
+    /// them. Lines still valid in the client's cache are sent as cheap copies.
+    fn plan_proactive_lines(&self, plan: &mut RenderPlan, total_height: usize, now: Instant) {
+        let velocity = self.scroll_tracker.velocity(now);
+        if velocity.abs() < PROACTIVE_RENDER_VELOCITY {
+            return;
+        }
+        let hint = self.prefetch_hint(now);
+        let first_line = min(self.first_line, total_height);
+        let (start, end) = if velocity > 0.0 {
+            let visible_end = min(first_line + self.height, total_height);
+            let n = min(hint.lines_ahead, MAX_PROACTIVE_LINES);
+            (visible_end, min(visible_end + n, total_height))
+        } else {
+            let n = min(hint.lines_behind, MAX_PROACTIVE_LINES);
+            (first_line.saturating_sub(n), first_line)
+        };
+        if start < end {
+            plan.request_lines(start, end);
+        }
     }
 
     pub fn scroll_height(&self) -> usize {
@@ -368,9 +641,27 @@ impl View {
     /// If `modify` is `true`, the selections are modified, otherwise the results
     /// of individual region movements become carets.
     pub fn do_move(&mut self, text: &Rope, movement: Movement, modify: bool) {
+        self.do_move_repeated(text, movement, modify, 1)
+    }
+
+    /// Applies a movement `count` times, as a single selection change.
+    pub fn do_move_repeated(
+        &mut self,
+        text: &Rope,
+        movement: Movement,
+        modify: bool,
+        count: usize,
+    ) {
         self.drag_state = None;
-        let new_sel =
-            selection_movement(movement, &self.selection, self, self.scroll_height(), text, modify);
+        let new_sel = selection_movement_repeated(
+            movement,
+            &self.selection,
+            self,
+            self.scroll_height(),
+            text,
+            modify,
+            count,
+        );
         self.set_selection(text, new_sel);
     }
 
@@ -440,6 +731,47 @@ impl View {
         self.annotations.update(plugin, interval, annotations)
     }
 
+    /// Removes the annotations of every type sent by `plugin`. Returns `true`
+    /// if there were any.
+    pub fn clear_annotations(&mut self, plugin: PluginId) -> bool {
+        self.annotations.clear(plugin)
+    }
+
+    pub fn is_composing(&self) -> bool {
+        self.composition.is_some()
+    }
+
+    /// Starts an input method composition, discarding any in progress.
+    pub fn start_composition(&mut self) {
+        self.composition = Some(Composition::new());
+    }
+
+    /// Returns the composition in progress, if any.
+    pub fn composition_mut(&mut self) -> Option<&mut Composition> {
+        self.composition.as_mut()
+    }
+
+    /// Ends the composition in progress, returning it.
+    pub fn take_composition(&mut self) -> Option<Composition> {
+        self.composition.take()
+    }
+
+    /// Registers a highlight kind on behalf of `plugin`, returning the id
+    /// under which it can submit ranges as annotations.
+    pub fn register_highlight_kind(
+        &mut self,
+        plugin: PluginId,
+        name: &str,
+        style_hints: Value,
+    ) -> HighlightKindId {
+        self.sent_annotations.clear();
+        self.highlight_kinds.register(plugin, name, style_hints)
+    }
+
+    pub fn highlight_kinds(&self) -> &HighlightKinds {
+        &self.highlight_kinds
+    }
+
     /// Select entire buffer.
     ///
     /// Note: unlike movement based selection, this does not scroll.
@@ -664,6 +996,10 @@ impl View {
 
         if let Some(text) = text {
             result["text"] = json!(text.slice_to_cow(start_pos..pos));
+            // the last visual line of a fold's anchor is followed by the fold
+            if self.folds.is_hidden_line_start(pos) {
+                result["folded"] = json!(true);
+            }
         }
         if let Some(style_spans) = style_spans {
             result["styles"] = json!(self.encode_styles(
@@ -744,39 +1080,58 @@ impl View {
         client: &Client,
         styles: &StyleMap,
         style_spans: &Spans<Style>,
+        bookmarks: &Bookmarks,
         plan: &RenderPlan,
+        rev: u64,
         pristine: bool,
     ) {
+        let record = self.render_journal.as_ref().map(|_| PendingRecord::new(rev, &self.lc_shadow));
+
         // every time current visible range changes, annotations are sent to frontend
         let start_off = self.offset_of_line(text, self.first_line);
         let end_off = self.offset_of_line(text, self.first_line + self.height + 2);
         let visible_range = Interval::new(start_off, end_off);
-        let selection_annotations =
-            self.selection.get_annotations(visible_range, self, text).to_json();
-        let find_annotations =
-            self.find.iter().map(|f| f.get_annotations(visible_range, self, text).to_json());
-        let plugin_annotations =
-            self.annotations.iter_range(self, text, visible_range).map(|a| a.to_json());
-
-        let annotations = iter::once(selection_annotations)
-            .chain(find_annotations)
-            .chain(plugin_annotations)
-            .collect::<Vec<_>>();
+        let describe_kinds = client.has_capability(ClientCapability::HighlightKinds);
+        let prefetch_hint = if client.has_capability(ClientCapability::PrefetchHints) {
+            Some(self.prefetch_hint(Instant::now()))
+        } else {
+            None
+        };
+        let annotations = if client.has_capability(ClientCapability::RetainAnnotations) {
+            self.changed_annotations(text, bookmarks, visible_range, describe_kinds)
+        } else {
+            let slice_json = |slice: AnnotationSlice| self.annotation_json(slice, describe_kinds);
+            let selection_annotations =
+                slice_json(self.selection.get_annotations(visible_range, self, text));
+            let find_annotations =
+                self.find.iter().map(|f| slice_json(f.get_annotations(visible_range, self, text)));
+            let bookmark_annotations =
+                slice_json(bookmarks.get_annotations(visible_range, self, text));
+            let composition_annotations = self
+                .composition
+                .iter()
+                .map(|c| slice_json(c.get_annotations(visible_range, self, text)));
+            let plugin_annotations =
+                self.annotations.iter_range(self, text, visible_range).map(|(_, a)| slice_json(a));
+
+            iter::once(selection_annotations)
+                .chain(find_annotations)
+                .chain(iter::once(bookmark_annotations))
+                .chain(composition_annotations)
+                .chain(plugin_annotations)
+                .collect::<Vec<_>>()
+        };
 
         if !self.lc_shadow.needs_render(plan) {
             let total_lines = self.line_of_offset(text, text.len()) + 1;
-            let update =
-                Update { ops: vec![UpdateOp::copy(total_lines, 1)], pristine, annotations };
+            let ops = vec![UpdateOp::copy(total_lines, 1)];
+            let update = Update { ops, pristine, annotations, prefetch_hint };
+            self.record_render(record, &update.ops);
             client.update_view(self.view_id, &update);
             return;
         }
 
-        // send updated find status only if there have been changes
-        if self.find_changed != FindStatusChange::None {
-            let matches_only = self.find_changed == FindStatusChange::Matches;
-            client.find_status(self.view_id, &json!(self.find_status(text, matches_only)));
-            self.find_changed = FindStatusChange::None;
-        }
+        self.send_find_status(text, client);
 
         // send updated replace status if changed
         if self.replace_changed {
@@ -785,6 +1140,7 @@ impl View {
             }
         }
 
+        let partial_updates = client.has_capability(ClientCapability::PartialLineUpdates);
         let mut b = line_cache_shadow::Builder::new();
         let mut ops = Vec::new();
         let mut line_num = 0; // tracks old line cache
@@ -802,32 +1158,36 @@ impl View {
                     //   - All the three are valid => send the "copy" op
                     //     (+leading "skip" to catch up with "ln" to update);
                     //
-                    //   - Text and styles are valid, cursors are not => same,
+                    //   - Text is valid, cursors or styles are not => same,
                     //     but send an "update" op instead of "copy" to move
-                    //     the cursors;
+                    //     the cursors, and to restyle the lines if the client
+                    //     supports partial line updates and they are rendered;
                     //
                     //   - Text or styles are invalid:
                     //     => send "invalidate" if RenderTactic is "Preserve";
                     //     => send "skip"+"insert" (recreate the lines) if
                     //        RenderTactic is "Render".
-                    if (seg.validity & line_cache_shadow::TEXT_VALID) != 0
-                        && (seg.validity & line_cache_shadow::STYLES_VALID) != 0
-                    {
+                    let text_valid = (seg.validity & line_cache_shadow::TEXT_VALID) != 0;
+                    let styles_valid = (seg.validity & line_cache_shadow::STYLES_VALID) != 0;
+                    let restyle = partial_updates && seg.tactic == RenderTactic::Render;
+                    if text_valid && (styles_valid || restyle) {
                         let n_skip = seg.their_line_num - line_num;
                         if n_skip > 0 {
                             ops.push(UpdateOp::skip(n_skip));
                         }
                         let line_offset = self.offset_of_line(text, seg.our_line_num);
                         let logical_line = text.line_of_offset(line_offset);
-                        if (seg.validity & line_cache_shadow::CURSOR_VALID) != 0 {
+                        if seg.validity == line_cache_shadow::ALL_VALID {
                             // ALL_VALID; copy lines as-is
                             ops.push(UpdateOp::copy(seg.n, logical_line + 1));
+                            b.add_span(seg.n, seg.our_line_num, seg.validity);
                         } else {
-                            // !CURSOR_VALID; update cursors
+                            // !CURSOR_VALID or !STYLES_VALID; update the
+                            // cursors, and the styles if they are invalid
                             let start_line = seg.our_line_num;
+                            let line_styles = if styles_valid { None } else { Some(style_spans) };
 
                             let encoded_lines = self
-                                .lines
                                 .iter_lines(text, start_line)
                                 .take(seg.n)
                                 .map(|l| {
@@ -836,7 +1196,7 @@ impl View {
                                         styles,
                                         l,
                                         /* text = */ None,
-                                        /* style_spans = */ None,
+                                        line_styles,
                                         text.len(),
                                     )
                                 })
@@ -845,8 +1205,9 @@ impl View {
                             let logical_line_opt =
                                 if logical_line == 0 { None } else { Some(logical_line + 1) };
                             ops.push(UpdateOp::update(encoded_lines, logical_line_opt));
+                            let validity = seg.validity | line_cache_shadow::STYLES_VALID;
+                            b.add_span(seg.n, seg.our_line_num, validity);
                         }
-                        b.add_span(seg.n, seg.our_line_num, seg.validity);
                         line_num = seg.their_line_num + seg.n;
                     } else if seg.tactic == RenderTactic::Preserve {
                         ops.push(UpdateOp::invalidate(seg.n));
@@ -854,7 +1215,6 @@ impl View {
                     } else if seg.tactic == RenderTactic::Render {
                         let start_line = seg.our_line_num;
                         let encoded_lines = self
-                            .lines
                             .iter_lines(text, start_line)
                             .take(seg.n)
                             .map(|l| {
@@ -881,10 +1241,126 @@ impl View {
             find.set_hls_dirty(false)
         }
 
-        let update = Update { ops, pristine, annotations };
+        let update = Update { ops, pristine, annotations, prefetch_hint };
+        self.record_render(record, &update.ops);
         client.update_view(self.view_id, &update);
     }
 
+    fn record_render(&mut self, record: Option<PendingRecord>, ops: &[UpdateOp]) {
+        if let (Some(journal), Some(record)) = (self.render_journal.as_mut(), record) {
+            journal.push(record.finish(ops, &self.lc_shadow));
+        }
+    }
+
+    /// Runs `f`, which renders this view. If it panics, the render journal
+    /// is logged, so that the crash report includes the recent renders.
+    fn log_journal_on_panic<F: FnOnce(&mut View)>(&mut self, f: F) {
+        if self.render_journal.is_none() {
+            return f(self);
+        }
+        if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(|| f(self))) {
+            if let Some(journal) = self.render_journal.as_ref() {
+                error!(
+                    "render of {:?} panicked; recent renders: {}",
+                    self.view_id,
+                    journal.to_json()
+                );
+            }
+            panic::resume_unwind(payload);
+        }
+    }
+
+    pub(crate) fn render_journal(&self) -> Option<&RenderJournal> {
+        self.render_journal.as_ref()
+    }
+
+    /// Turns the render journal on or off. Turning it on again keeps the
+    /// renders recorded so far.
+    pub(crate) fn set_render_journal(&mut self, enabled: bool) {
+        match (enabled, self.render_journal.is_some()) {
+            (true, false) => self.render_journal = Some(RenderJournal::default()),
+            (false, true) => self.render_journal = None,
+            _ => (),
+        }
+    }
+
+    /// Returns the annotations in `visible_range` for a client that retains
+    /// annotations between updates. Sets that haven't changed since the last
+    /// update are sent as `{"source": .., "unchanged": true}`; sources missing
+    /// from the update no longer have annotations.
+    fn changed_annotations(
+        &mut self,
+        text: &Rope,
+        bookmarks: &Bookmarks,
+        visible_range: Interval,
+        describe_kinds: bool,
+    ) -> Vec<Value> {
+        if self.sent_annotations_range != Some(visible_range) {
+            self.sent_annotations.clear();
+            self.sent_annotations_range = Some(visible_range);
+        }
+        let last_sent = mem::take(&mut self.sent_annotations);
+        let mut sent = HashMap::new();
+
+        // selections and compositions change with nearly every update, so
+        // they are always sent
+        let describe = |slice: AnnotationSlice| {
+            if describe_kinds {
+                slice.with_kind(&self.highlight_kinds)
+            } else {
+                slice
+            }
+        };
+        let selection = describe(self.selection.get_annotations(visible_range, self, text));
+        let mut annotations = vec![AnnotationSource::Selection.to_json(Some(selection))];
+        if let Some(composition) = self.composition.as_ref() {
+            let slice = describe(composition.get_annotations(visible_range, self, text));
+            annotations.push(AnnotationSource::Composition.to_json(Some(slice)));
+        }
+
+        let finds = self
+            .find
+            .iter()
+            .map(|f| (AnnotationSource::Find(f.id()), f.generation(), f as &dyn ToAnnotation));
+        let bookmarks = iter::once((
+            AnnotationSource::Bookmarks,
+            bookmarks.generation(),
+            bookmarks as &dyn ToAnnotation,
+        ));
+        for (source, generation, item) in finds.chain(bookmarks) {
+            let slice = if last_sent.get(&source) == Some(&generation) {
+                None
+            } else {
+                Some(describe(item.get_annotations(visible_range, self, text)))
+            };
+            annotations.push(source.to_json(slice));
+            sent.insert(source, generation);
+        }
+        for (source, slice) in self.annotations.iter_range(self, text, visible_range) {
+            let generation = self.annotations.generation(&source);
+            let slice = if last_sent.get(&source) == Some(&generation) {
+                None
+            } else {
+                Some(describe(slice))
+            };
+            annotations.push(source.to_json(slice));
+            sent.insert(source, generation);
+        }
+
+        self.sent_annotations = sent;
+        annotations
+    }
+
+    /// Returns the json sent to the client for `slice`, describing its
+    /// highlight kind if the client themes highlight kinds.
+    fn annotation_json(&self, slice: AnnotationSlice, describe_kinds: bool) -> Value {
+        if describe_kinds {
+            slice.with_kind(&self.highlight_kinds).to_json()
+        } else {
+            slice.to_json()
+        }
+    }
+
     /// Determines the current number of find results and search parameters to send them to
     /// the frontend.
     pub fn find_status(&self, text: &Rope, matches_only: bool) -> Vec<FindStatus> {
@@ -894,6 +1370,23 @@ impl View {
             .collect::<Vec<FindStatus>>()
     }
 
+    /// Returns the lines with matches for the current search query, for
+    /// marking them on a scrollbar. See `Find::match_lines`.
+    pub fn get_find_match_lines(&self, text: &Rope, max_lines: usize) -> Vec<usize> {
+        self.find.first().map(|find| find.match_lines(self, text, max_lines)).unwrap_or_default()
+    }
+
+    /// Sends the find status to the frontend, if there have been changes.
+    pub(crate) fn send_find_status(&mut self, text: &Rope, client: &Client) {
+        if self.find_changed != FindStatusChange::None {
+            let matches_only = self.find_changed == FindStatusChange::Matches;
+            client.find_status(self.view_id, &json!(self.find_status(text, matches_only)));
+            self.find_changed = FindStatusChange::None;
+            self.find_status_sent =
+                if self.find_in_progress() { Some(Instant::now()) } else { None };
+        }
+    }
+
     /// Update front-end with any changes to view since the last time sent.
     /// The `pristine` argument indicates whether or not the buffer has
     /// unsaved changes.
@@ -903,11 +1396,27 @@ impl View {
         client: &Client,
         styles: &StyleMap,
         style_spans: &Spans<Style>,
+        bookmarks: &Bookmarks,
+        rev: u64,
         pristine: bool,
     ) {
         let height = self.line_of_offset(text, text.len()) + 1;
-        let plan = RenderPlan::create(height, self.first_line, self.height);
-        self.send_update_for_plan(text, client, styles, style_spans, &plan, pristine);
+        let mut plan = RenderPlan::create(height, self.first_line, self.height);
+        if client.has_capability(ClientCapability::PrefetchHints) {
+            self.plan_proactive_lines(&mut plan, height, Instant::now());
+        }
+        self.log_journal_on_panic(|view| {
+            view.send_update_for_plan(
+                text,
+                client,
+                styles,
+                style_spans,
+                bookmarks,
+                &plan,
+                rev,
+                pristine,
+            )
+        });
         if let Some(new_scroll_pos) = self.scroll_to.take() {
             let (line, col) = self.offset_to_line_col(text, new_scroll_pos);
             client.scroll_to(self.view_id, line, col);
@@ -921,14 +1430,27 @@ impl View {
         client: &Client,
         styles: &StyleMap,
         style_spans: &Spans<Style>,
+        bookmarks: &Bookmarks,
         first_line: usize,
         last_line: usize,
+        rev: u64,
         pristine: bool,
     ) {
         let height = self.line_of_offset(text, text.len()) + 1;
         let mut plan = RenderPlan::create(height, self.first_line, self.height);
         plan.request_lines(first_line, last_line);
-        self.send_update_for_plan(text, client, styles, style_spans, &plan, pristine);
+        self.log_journal_on_panic(|view| {
+            view.send_update_for_plan(
+                text,
+                client,
+                styles,
+                style_spans,
+                bookmarks,
+                &plan,
+                rev,
+                pristine,
+            )
+        });
     }
 
     /// Invalidates front-end's entire line cache, forcing a full render at the next
@@ -940,6 +1462,7 @@ impl View {
         b.add_span(height, 0, 0);
         b.set_dirty(true);
         self.lc_shadow = b.build();
+        self.sent_annotations.clear();
     }
 
     /// Returns the byte range of the currently visible lines.
@@ -962,7 +1485,14 @@ impl View {
         let visible = self.first_line..self.first_line + self.height;
         let inval = self.lines.rewrap_chunk(text, width_cache, client, spans, visible);
         if let Some(InvalLines { start_line, inval_count, new_count }) = inval {
-            self.lc_shadow.edit(start_line, start_line + inval_count, new_count);
+            // the line numbers of `InvalLines` don't account for folds
+            if self.folds.is_empty() {
+                self.lc_shadow.edit(start_line, start_line + inval_count, new_count);
+            } else {
+                self.set_dirty(text);
+            }
+            // annotation positions are in visual lines
+            self.sent_annotations.clear();
         }
     }
 
@@ -978,21 +1508,28 @@ impl View {
         width_cache: &mut WidthCache,
         drift: InsertDrift,
     ) {
+        // edits that touch a fold unfold it
+        let unfolded = self.folds.apply_delta(delta);
         let visible = self.first_line..self.first_line + self.height;
         match self.lines.after_edit(text, last_text, delta, width_cache, client, visible) {
-            Some(InvalLines { start_line, inval_count, new_count }) => {
+            Some(InvalLines { start_line, inval_count, new_count })
+                if self.folds.is_empty() && !unfolded =>
+            {
                 self.lc_shadow.edit(start_line, start_line + inval_count, new_count);
             }
-            None => self.set_dirty(text),
+            _ => self.set_dirty(text),
         }
 
         // Any edit cancels a drag. This is good behavior for edits initiated through
         // the front-end, but perhaps not for async edits.
         self.drag_state = None;
 
-        // all annotations that come after the edit need to be invalidated
-        let (iv, _) = delta.summary();
-        self.annotations.invalidate(iv);
+        // the edit may have moved any annotation to a different line
+        self.sent_annotations.clear();
+
+        // highlights move with the text; other annotations that come after
+        // the edit need to be invalidated
+        self.annotations.apply_edit(delta);
 
         // update only find highlights affected by change
         for find in &mut self.find {
@@ -1006,21 +1543,45 @@ impl View {
         self.set_selection_for_edit(text, new_sel);
     }
 
+    /// The text of the last selection or, if it is a caret, of the word
+    /// under it.
+    fn selection_query<'t>(&self, text: &'t Rope) -> Option<Cow<'t, str>> {
+        let region = self.selection.last()?;
+        if !region.is_caret() {
+            Some(text.slice_to_cow(region))
+        } else {
+            let (start, end) = {
+                let mut word_cursor = WordCursor::new(text, region.max());
+                word_cursor.select_word()
+            };
+            Some(text.slice_to_cow(start..end))
+        }
+    }
+
+    /// Every occurrence in `text` of the text of the last selection, or of
+    /// the word under it, matched exactly.
+    pub(crate) fn selection_occurrences(&self, text: &Rope) -> Selection {
+        let query = match self.selection_query(text) {
+            Some(query) if !query.is_empty() => query,
+            _ => return Selection::new(),
+        };
+        let mut find = Find::new(0);
+        find.set_find(&query, true, false, false);
+        find.update_find(text, 0, text.len(), false);
+        find.occurrences().clone()
+    }
+
+    /// Sets the selection without scrolling to it, as when it is changed
+    /// from another view of the buffer.
+    pub(crate) fn set_selection_in_place(&mut self, text: &Rope, sel: Selection) {
+        self.set_selection_raw(text, sel);
+    }
+
     fn do_selection_for_find(&mut self, text: &Rope, case_sensitive: bool) {
         // set last selection or word under current cursor as search query
-        let search_query = match self.selection.last() {
-            Some(region) => {
-                if !region.is_caret() {
-                    text.slice_to_cow(region)
-                } else {
-                    let (start, end) = {
-                        let mut word_cursor = WordCursor::new(text, region.max());
-                        word_cursor.select_word()
-                    };
-                    text.slice_to_cow(start..end)
-                }
-            }
-            _ => return,
+        let search_query = match self.selection_query(text) {
+            Some(query) => query,
+            None => return,
         };
 
         self.set_dirty(text);
@@ -1032,7 +1593,14 @@ impl View {
         }
 
         self.find.last_mut().unwrap().set_find(&search_query, case_sensitive, false, true);
+        self.start_find();
+    }
+
+    /// Restarts incremental find. The status for the new search is sent right
+    /// away, rather than waiting for the throttle interval of a previous one.
+    fn start_find(&mut self) {
         self.find_progress = FindProgress::Started;
+        self.find_status_sent = None;
     }
 
     fn add_find(&mut self) {
@@ -1047,6 +1615,13 @@ impl View {
         // remove deleted queries
         self.find.retain(|f| queries.iter().any(|q| q.id == Some(f.id())));
 
+        let block = match self.selection.last() {
+            Some(region) if queries.iter().any(|q| q.find_in_block) => {
+                enclosing_block(text, region.max())
+            }
+            _ => None,
+        };
+
         for query in &queries {
             let pos = match query.id {
                 Some(id) => {
@@ -1063,6 +1638,10 @@ impl View {
                 }
             };
 
+            let scope = if query.find_in_block { block } else { None };
+            if self.find[pos].set_scope(scope) {
+                find_changed = true;
+            }
             if self.find[pos].set_find(
                 &query.chars.clone(),
                 query.case_sensitive,
@@ -1075,7 +1654,7 @@ impl View {
 
         if find_changed {
             self.set_dirty(text);
-            self.find_progress = FindProgress::Started;
+            self.start_find();
         }
     }
 
@@ -1085,7 +1664,8 @@ impl View {
                 // start incremental find on visible region
                 let start = self.offset_of_line(text, self.first_line);
                 let end = min(text.len(), start + FIND_BATCH_SIZE);
-                self.find_changed = FindStatusChange::Matches;
+                // the first status of a new search includes the query
+                self.find_changed = FindStatusChange::All;
                 self.find_progress = FindProgress::InProgress(Range { start, end });
                 Some((start, end))
             }
@@ -1158,15 +1738,33 @@ impl View {
         }
     }
 
-    /// Selects all find matches.
-    pub fn do_find_all(&mut self, text: &Rope) {
+    /// Completes any incremental find in progress, by searching the rest of
+    /// the text at once.
+    pub(crate) fn finish_find(&mut self, text: &Rope) {
+        if !self.find_in_progress() {
+            return;
+        }
+        for query in &mut self.find {
+            query.update_find(text, 0, text.len(), false);
+        }
+        self.find_progress = FindProgress::Ready;
+        self.find_changed = FindStatusChange::All;
+    }
+
+    /// The matches of all find queries, merged into one selection.
+    pub(crate) fn find_occurrences(&self) -> Selection {
         let mut selection = Selection::new();
         for find in &self.find {
             for &occurrence in find.occurrences().iter() {
                 selection.add_region(occurrence);
             }
         }
+        selection
+    }
 
+    /// Selects all find matches.
+    pub fn do_find_all(&mut self, text: &Rope) {
+        let selection = self.find_occurrences();
         if !selection.is_empty() {
             // todo: invalidate so that nothing selected accidentally replaced
             self.set_selection(text, selection);
@@ -1265,6 +1863,23 @@ impl View {
             _ => None,
         }
     }
+
+    /// Returns the position of `offset`. Offsets past the end of the buffer
+    /// are clamped to its end, and offsets inside a codepoint are moved back
+    /// to its start.
+    pub fn offset_to_position(&self, text: &Rope, offset: usize) -> LinePosition {
+        let offset = text.at_or_prev_codepoint_boundary(offset.min(text.len())).unwrap_or(0);
+        let (line, col) = LogicalLines.offset_to_line_col(text, offset);
+        let col_utf16 = text.count::<Utf16CodeUnitsMetric>(offset)
+            - text.count::<Utf16CodeUnitsMetric>(offset - col);
+        let (visual_line, visual_col) = if self.lines.is_wrapping() {
+            let (visual_line, visual_col) = self.offset_to_line_col(text, offset);
+            (Some(visual_line), Some(visual_col))
+        } else {
+            (None, None)
+        };
+        LinePosition { line, col, col_utf16, visual_line, visual_col }
+    }
 }
 
 impl View {
@@ -1276,18 +1891,45 @@ impl View {
         let spans: Spans<Style> = Spans::default();
         let mut width_cache = WidthCache::new();
         let client = Client::new(Box::new(DummyPeer));
-        self.update_wrap_settings(text, cols, false);
-        self.rewrap(text, &mut width_cache, &client, &spans);
+        self.update_wrap_settings(text, cols, false, 1);
+        while self.needs_more_wrap() {
+            self.rewrap(text, &mut width_cache, &client, &spans);
+        }
     }
 }
 
 impl LineOffset for View {
     fn offset_of_line(&self, text: &Rope, line: usize) -> usize {
+        // skip over the lines hidden by folds before `line`
+        let mut line = line;
+        for fold in self.folds.iter() {
+            if self.lines.visual_line_of_offset(text, fold.start()) > line {
+                break;
+            }
+            line += self.hidden_lines(text, fold);
+        }
         self.lines.offset_of_visual_line(text, line)
     }
 
     fn line_of_offset(&self, text: &Rope, offset: usize) -> usize {
-        self.lines.visual_line_of_offset(text, offset)
+        let mut offset = offset;
+        let mut hidden = 0;
+        for fold in self.folds.iter() {
+            if offset < fold.start() {
+                break;
+            }
+            if offset <= fold.end() {
+                // folded text belongs to the line anchoring it
+                offset = fold.start() - 1;
+                break;
+            }
+            hidden += self.hidden_lines(text, fold);
+        }
+        self.lines.visual_line_of_offset(text, offset) - hidden
+    }
+
+    fn folded_range(&self, offset: usize) -> Option<Interval> {
+        self.folds.fold_at(offset)
     }
 }
 
@@ -1329,6 +1971,7 @@ mod tests {
                 case_sensitive: false,
                 regex: false,
                 whole_words: false,
+                find_in_block: false,
             },
         );
         view.do_find(&text);
@@ -1363,6 +2006,7 @@ mod tests {
                 case_sensitive: false,
                 regex: false,
                 whole_words: false,
+                find_in_block: false,
             },
         );
         view.do_find(&text);
@@ -1393,6 +2037,7 @@ mod tests {
                 case_sensitive: false,
                 regex: false,
                 whole_words: false,
+                find_in_block: false,
             },
         );
         view.do_find(&text);
@@ -1407,6 +2052,7 @@ mod tests {
                 case_sensitive: false,
                 regex: false,
                 whole_words: false,
+                find_in_block: false,
             },
         );
         view.do_find(&text);
@@ -1439,6 +2085,7 @@ mod tests {
                 case_sensitive: false,
                 regex: false,
                 whole_words: false,
+                find_in_block: false,
             },
         );
         view.do_find(&text);
@@ -1452,6 +2099,7 @@ mod tests {
                 case_sensitive: false,
                 regex: false,
                 whole_words: false,
+                find_in_block: false,
             },
         );
         view.do_find(&text);
@@ -1465,6 +2113,7 @@ mod tests {
                 case_sensitive: false,
                 regex: false,
                 whole_words: false,
+                find_in_block: false,
             },
         );
         view.do_find(&text);
@@ -1472,6 +2121,33 @@ mod tests {
         assert_eq!(view.sel_regions().len(), 3);
     }
 
+    #[test]
+    fn find_match_lines() {
+        let mut view = View::new(1.into(), BufferId::new(2));
+        let text = Rope::from("a foo\nb\nfoo foo\nc\nd\nfoo\ne\nf\ng\nfoo");
+        assert!(view.get_find_match_lines(&text, 10).is_empty());
+
+        view.do_edit(
+            &text,
+            ViewEvent::Find {
+                chars: "foo".to_string(),
+                case_sensitive: false,
+                regex: false,
+                whole_words: false,
+                find_in_block: false,
+            },
+        );
+        view.do_find(&text);
+        // a line with several matches is reported once
+        assert_eq!(view.get_find_match_lines(&text, 10), vec![0, 2, 5, 9]);
+        assert_eq!(view.get_find_match_lines(&text, 4), vec![0, 2, 5, 9]);
+        // downsampled to the first line with matches in each part of the buffer
+        assert_eq!(view.get_find_match_lines(&text, 3), vec![0, 5, 9]);
+        assert_eq!(view.get_find_match_lines(&text, 2), vec![0, 5]);
+        assert_eq!(view.get_find_match_lines(&text, 1), vec![0]);
+        assert!(view.get_find_match_lines(&text, 0).is_empty());
+    }
+
     #[test]
     fn multi_queries_find_next() {
         let mut view = View::new(1.into(), BufferId::new(2));
@@ -1482,6 +2158,7 @@ mod tests {
             case_sensitive: false,
             regex: false,
             whole_words: false,
+            find_in_block: false,
         };
         let query2 = FindQuery {
             id: None,
@@ -1489,6 +2166,7 @@ mod tests {
             case_sensitive: false,
             regex: false,
             whole_words: false,
+            find_in_block: false,
         };
         view.do_edit(&text, ViewEvent::MultiFind { queries: vec![query1, query2] });
         view.do_find(&text);
@@ -1510,6 +2188,7 @@ mod tests {
             case_sensitive: false,
             regex: false,
             whole_words: false,
+            find_in_block: false,
         };
         let query2 = FindQuery {
             id: None,
@@ -1517,10 +2196,72 @@ mod tests {
             case_sensitive: false,
             regex: false,
             whole_words: false,
+            find_in_block: false,
         };
         view.do_edit(&text, ViewEvent::MultiFind { queries: vec![query1, query2] });
         view.do_find(&text);
         view.do_find_all(&text);
         assert_eq!(view.sel_regions().len(), 4);
     }
+
+    fn position(line: usize, col: usize, col_utf16: usize, visual: (usize, usize)) -> LinePosition {
+        let (visual_line, visual_col) = (Some(visual.0), Some(visual.1));
+        LinePosition { line, col, col_utf16, visual_line, visual_col }
+    }
+
+    #[test]
+    fn offset_to_position() {
+        let mut view = View::new(1.into(), BufferId::new(2));
+        // "é" is two bytes and one UTF-16 unit, "😀" four bytes and two units.
+        let text = Rope::from("aaaa bbbb cccc\né😀x");
+
+        let unwrapped = view.offset_to_position(&text, 17);
+        assert_eq!(
+            unwrapped,
+            LinePosition { line: 1, col: 2, col_utf16: 1, visual_line: None, visual_col: None }
+        );
+
+        // visual lines are "aaaa ", "bbbb ", "cccc\n" and "é😀x"
+        view.debug_force_rewrap_cols(&text, 6);
+        assert_eq!(view.offset_to_position(&text, 4), position(0, 4, 4, (0, 4)));
+        assert_eq!(view.offset_to_position(&text, 5), position(0, 5, 5, (1, 0)));
+        assert_eq!(view.offset_to_position(&text, 10), position(0, 10, 10, (2, 0)));
+        assert_eq!(view.offset_to_position(&text, 15), position(1, 0, 0, (3, 0)));
+        assert_eq!(view.offset_to_position(&text, 17), position(1, 2, 1, (3, 2)));
+        assert_eq!(view.offset_to_position(&text, 21), position(1, 6, 3, (3, 6)));
+
+        // inside a codepoint, and out of range
+        assert_eq!(view.offset_to_position(&text, 19), position(1, 2, 1, (3, 2)));
+        assert_eq!(view.offset_to_position(&text, 100), position(1, 7, 4, (3, 7)));
+    }
+
+    #[test]
+    fn prefetch_hint_follows_scroll_velocity() {
+        let mut view = View::new(1.into(), BufferId::new(2));
+        view.set_prefetch_bounds(10, 100);
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let idle = PrefetchHint { lines_ahead: 10, lines_behind: 10 };
+        view.scroll_tracker.record(at(0), 0);
+        assert_eq!(view.prefetch_hint(at(0)), idle);
+
+        // 100 lines/s down adds the 50 lines passed in PREFETCH_HORIZON_SECS
+        view.scroll_tracker.record(at(100), 10);
+        assert_eq!(view.prefetch_hint(at(100)), PrefetchHint { lines_ahead: 60, lines_behind: 10 });
+
+        // faster scrolling is clamped to the maximum
+        view.scroll_tracker.record(at(200), 110);
+        assert_eq!(
+            view.prefetch_hint(at(200)),
+            PrefetchHint { lines_ahead: 100, lines_behind: 10 }
+        );
+
+        // old samples expire, so reversing direction takes effect quickly
+        view.scroll_tracker.record(at(600), 100);
+        view.scroll_tracker.record(at(700), 90);
+        assert_eq!(view.prefetch_hint(at(700)), PrefetchHint { lines_ahead: 10, lines_behind: 60 });
+
+        // and an idle view goes back to the minimum
+        assert_eq!(view.prefetch_hint(at(1100)), idle);
+    }
 }
@@ -31,8 +31,12 @@
 //! - There is the option (via `FileWatcher::watch_filtered`) to include
 //! a predicate along with a path, to filter paths before delivery.
 //!
+//! - Events are debounced: those for the same token and path that arrive
+//! within the debounce window of each other are coalesced into one, and
+//! classified as an `FsEventKind`.
+//!
 //! - We are integrated with the xi_rpc runloop; events are queued as
-//! they arrive, and an idle task is scheduled.
+//! they arrive, and a timer is scheduled for when they will be ready.
 
 use crossbeam_channel::unbounded;
 use notify::{event::*, watcher, RecommendedWatcher, RecursiveMode, Watcher};
@@ -42,7 +46,7 @@ use std::mem;
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use xi_rpc::RpcPeer;
 
@@ -59,8 +63,31 @@ pub struct FileWatcher {
 
 #[derive(Debug, Default)]
 struct WatcherState {
-    events: EventQueue,
+    events: Vec<PendingEvent>,
     watchees: Vec<Watchee>,
+    debounce: Duration,
+}
+
+/// An event waiting for its path to be quiet for the debounce window.
+#[derive(Debug)]
+struct PendingEvent {
+    token: WatchToken,
+    event: Event,
+    /// `None` for renames, which are not coalesced.
+    kind: Option<FsEventKind>,
+    last_seen: Instant,
+}
+
+/// What happened to a path, once the events for it have been coalesced.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FsEventKind {
+    Created,
+    Removed,
+    /// The contents of the file may have changed.
+    DataModified,
+    /// Only the file's metadata, such as its permissions, changed.
+    MetadataOnly,
 }
 
 /// Tracks a registered 'that-which-is-watched'.
@@ -82,9 +109,10 @@ struct Watchee {
 pub struct WatchToken(pub usize);
 
 /// A trait for types which can be notified of new events.
-/// New events are accessible through the `FileWatcher` instance.
+/// New events are accessible through the `FileWatcher` instance,
+/// once they have been quiet until `deadline`.
 pub trait Notify: Send {
-    fn notify(&self);
+    fn notify(&self, deadline: Instant);
 }
 
 pub type EventQueue = VecDeque<(WatchToken, Event)>;
@@ -95,7 +123,8 @@ impl FileWatcher {
     pub fn new<T: Notify + 'static>(peer: T) -> Self {
         let (tx_event, rx_event) = unbounded();
 
-        let state = Arc::new(Mutex::new(WatcherState::default()));
+        let debounce = Duration::from_millis(DEBOUNCE_WAIT_MILLIS);
+        let state = Arc::new(Mutex::new(WatcherState { debounce, ..Default::default() }));
         let state_clone = state.clone();
 
         let inner = watcher(tx_event, Duration::from_millis(100)).expect("watcher should spawn");
@@ -103,21 +132,31 @@ impl FileWatcher {
         thread::spawn(move || {
             while let Ok(Ok(event)) = rx_event.recv() {
                 let mut state = state_clone.lock().unwrap();
-                let WatcherState { ref mut events, ref mut watchees } = *state;
-
-                watchees
+                let now = Instant::now();
+                let tokens = state
+                    .watchees
                     .iter()
                     .filter(|w| w.wants_event(&event))
                     .map(|w| w.token)
-                    .for_each(|t| events.push_back((t, event.clone())));
+                    .collect::<Vec<_>>();
+
+                for token in tokens {
+                    state.queue_event(token, &event, now);
+                }
 
-                peer.notify();
+                peer.notify(now + state.debounce);
             }
         });
 
         FileWatcher { inner, state }
     }
 
+    /// Sets how long a path must be quiet before its events are delivered.
+    /// Defaults to `DEBOUNCE_WAIT_MILLIS`.
+    pub fn set_debounce(&mut self, debounce: Duration) {
+        self.state.lock().unwrap().debounce = debounce;
+    }
+
     /// Begin watching `path`. As `Event`s (documented in the
     /// [notify](https://docs.rs/notify) crate) arrive, they are stored
     /// with the associated `token` and a task is added to the runloop's
@@ -213,11 +252,90 @@ impl FileWatcher {
         }
     }
 
-    /// Takes ownership of this `Watcher`'s current event queue.
-    pub fn take_events(&mut self) -> VecDeque<(WatchToken, Event)> {
+    /// Takes the queued events whose paths have been quiet for the
+    /// debounce window. Later events stay queued, to be delivered when
+    /// the timer scheduled for them fires.
+    pub fn take_events(&mut self) -> EventQueue {
         let mut state = self.state.lock().unwrap();
-        let WatcherState { ref mut events, .. } = *state;
-        mem::take(events)
+        let now = Instant::now();
+        let debounce = state.debounce;
+        let (ready, pending): (Vec<_>, Vec<_>) =
+            mem::take(&mut state.events).into_iter().partition(|e| e.last_seen + debounce <= now);
+        state.events = pending;
+        ready.into_iter().map(|e| (e.token, e.event)).collect()
+    }
+}
+
+impl WatcherState {
+    /// Queues `event` for `token`, coalescing it with a queued event for
+    /// the same path.
+    fn queue_event(&mut self, token: WatchToken, event: &Event, now: Instant) {
+        let kind = match FsEventKind::classify(event) {
+            Some(kind) => kind,
+            None => {
+                let event = event.clone();
+                self.events.push(PendingEvent { token, event, kind: None, last_seen: now });
+                return;
+            }
+        };
+        let path = &event.paths[0];
+        let queued = self
+            .events
+            .iter_mut()
+            .find(|e| e.token == token && e.kind.is_some() && &e.event.paths[0] == path);
+        match queued {
+            Some(queued) => {
+                let kind = queued.kind.unwrap().then(kind);
+                queued.event = kind.to_event(path);
+                queued.kind = Some(kind);
+                queued.last_seen = now;
+            }
+            None => {
+                let event = kind.to_event(path);
+                self.events.push(PendingEvent { token, event, kind: Some(kind), last_seen: now });
+            }
+        }
+    }
+}
+
+impl FsEventKind {
+    /// Returns the kind of a single event, or `None` for a rename or an
+    /// event we do not handle.
+    pub fn classify(event: &Event) -> Option<FsEventKind> {
+        match event.kind {
+            EventKind::Modify(ModifyKind::Name(_)) => None,
+            _ if event.paths.len() != 1 => None,
+            EventKind::Create(_) => Some(FsEventKind::Created),
+            EventKind::Remove(_) => Some(FsEventKind::Removed),
+            EventKind::Modify(ModifyKind::Metadata(_)) => Some(FsEventKind::MetadataOnly),
+            EventKind::Modify(_) => Some(FsEventKind::DataModified),
+            _ => None,
+        }
+    }
+
+    /// Returns the kind of `self` followed by `later`, for the same path.
+    fn then(self, later: FsEventKind) -> FsEventKind {
+        use self::FsEventKind::*;
+        match (self, later) {
+            // a file replaced by a new one, as many editors save
+            (Removed, Created) => DataModified,
+            (_, Created) | (_, Removed) => later,
+            (Created, _) | (Removed, _) => self,
+            (MetadataOnly, DataModified) => DataModified,
+            (_, MetadataOnly) | (DataModified, DataModified) => self,
+        }
+    }
+
+    /// Returns an event of this kind for `path`, in the form the watcher's
+    /// users expect.
+    fn to_event(self, path: &Path) -> Event {
+        let kind = match self {
+            FsEventKind::Created => EventKind::Create(CreateKind::Any),
+            FsEventKind::Removed => EventKind::Remove(RemoveKind::Any),
+            FsEventKind::DataModified => EventKind::Modify(ModifyKind::Any),
+            FsEventKind::MetadataOnly => EventKind::Modify(ModifyKind::Metadata(MetadataKind::Any)),
+        };
+        Event::new(kind).add_path(path.to_owned())
     }
 }
 
@@ -272,8 +390,8 @@ impl Watchee {
 }
 
 impl Notify for RpcPeer {
-    fn notify(&self) {
-        self.schedule_idle(crate::tabs::WATCH_IDLE_TOKEN);
+    fn notify(&self, deadline: Instant) {
+        self.schedule_timer(deadline, crate::tabs::WATCH_IDLE_TOKEN);
     }
 }
 
@@ -325,7 +443,7 @@ mod tests {
     }
 
     impl Notify for crossbeam_channel::Sender<bool> {
-        fn notify(&self) {
+        fn notify(&self, _deadline: Instant) {
             self.send(true).expect("send shouldn't fail")
         }
     }
@@ -492,19 +610,11 @@ mod tests {
         let events = w.take_events();
         assert_eq!(
             events,
-            vec![
-                (
-                    2.into(),
-                    Event::new(EventKind::Modify(ModifyKind::Any))
-                        .add_path(tmp.mkpath("adir/dir2/file"))
-                        .set_flag(Flag::Notice)
-                ),
-                (
-                    2.into(),
-                    Event::new(EventKind::Modify(ModifyKind::Any))
-                        .add_path(tmp.mkpath("adir/dir2/file"))
-                ),
-            ]
+            vec![(
+                2.into(),
+                Event::new(EventKind::Modify(ModifyKind::Any))
+                    .add_path(tmp.mkpath("adir/dir2/file"))
+            ),]
         );
     }
 
@@ -526,18 +636,6 @@ mod tests {
         assert_eq!(
             events,
             vec![
-                (
-                    1.into(),
-                    Event::new(EventKind::Modify(ModifyKind::Any))
-                        .add_path(tmp.mkpath("my_file"))
-                        .set_flag(Flag::Notice)
-                ),
-                (
-                    2.into(),
-                    Event::new(EventKind::Modify(ModifyKind::Any))
-                        .add_path(tmp.mkpath("my_file"))
-                        .set_flag(Flag::Notice)
-                ),
                 (
                     1.into(),
                     Event::new(EventKind::Modify(ModifyKind::Any)).add_path(tmp.mkpath("my_file"))
@@ -560,13 +658,80 @@ mod tests {
         let events = w.take_events();
         assert!(events.contains(&(
             2.into(),
-            Event::new(EventKind::Remove(RemoveKind::Any))
-                .add_path(path.clone())
-                .set_flag(Flag::Notice)
-        )));
-        assert!(!events.contains(&(
-            1.into(),
-            Event::new(EventKind::Remove(RemoveKind::Any)).add_path(path).set_flag(Flag::Notice)
+            Event::new(EventKind::Remove(RemoveKind::Any)).add_path(path.clone())
         )));
+        assert!(!events
+            .contains(&(1.into(), Event::new(EventKind::Remove(RemoveKind::Any)).add_path(path))));
+    }
+
+    #[test]
+    fn rapid_writes_coalesce() {
+        let (tx, rx) = unbounded();
+        let tmp = tempdir::TempDir::new("xi-test-rapid-writes").unwrap();
+        tmp.create("my_file");
+        sleep_if_macos(30_100);
+        let mut w = FileWatcher::new(tx);
+        w.watch(&tmp.mkpath("my_file"), false, 1.into());
+        sleep_if_macos(10);
+        for _ in 0..5 {
+            tmp.write("my_file");
+        }
+
+        let _ = recv_all(&rx, Duration::from_millis(1000));
+        let events = w.take_events();
+        assert_eq!(
+            events,
+            vec![(
+                1.into(),
+                Event::new(EventKind::Modify(ModifyKind::Any)).add_path(tmp.mkpath("my_file"))
+            )]
+        );
+    }
+
+    #[test]
+    fn events_wait_for_quiet_path() {
+        let (tx, _rx) = unbounded();
+        let mut w = FileWatcher::new(tx);
+        w.set_debounce(Duration::from_millis(100));
+        let path = PathBuf::from("/hi/there/friend.txt");
+        let metadata = Event::new(EventKind::Modify(ModifyKind::Metadata(MetadataKind::Any)))
+            .add_path(path.clone());
+        let data = Event::new(EventKind::Modify(ModifyKind::Data(DataChange::Content)))
+            .add_path(path.clone());
+        let now = Instant::now();
+        w.state.lock().unwrap().queue_event(1.into(), &metadata, now);
+        w.state.lock().unwrap().queue_event(1.into(), &data, now);
+        w.state.lock().unwrap().queue_event(2.into(), &metadata, now);
+        assert!(w.take_events().is_empty());
+
+        sleep(150);
+        let events = w.take_events();
+        assert_eq!(
+            events,
+            vec![
+                (1.into(), Event::new(EventKind::Modify(ModifyKind::Any)).add_path(path.clone())),
+                (2.into(), metadata),
+            ]
+        );
+        assert!(w.take_events().is_empty());
+    }
+
+    #[test]
+    fn coalesced_kinds() {
+        use super::FsEventKind::*;
+        assert_eq!(Created.then(DataModified), Created);
+        assert_eq!(Created.then(Removed), Removed);
+        assert_eq!(Removed.then(Created), DataModified);
+        assert_eq!(MetadataOnly.then(DataModified), DataModified);
+        assert_eq!(DataModified.then(MetadataOnly), DataModified);
+        assert_eq!(MetadataOnly.then(MetadataOnly), MetadataOnly);
+
+        let rename = Event::new(EventKind::Modify(ModifyKind::Name(RenameMode::Both)))
+            .add_path(PathBuf::from("/a"))
+            .add_path(PathBuf::from("/b"));
+        assert_eq!(FsEventKind::classify(&rename), None);
+        let touch = Event::new(EventKind::Modify(ModifyKind::Metadata(MetadataKind::Any)))
+            .add_path(PathBuf::from("/a"));
+        assert_eq!(FsEventKind::classify(&touch), Some(MetadataOnly));
     }
 }
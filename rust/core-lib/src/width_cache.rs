@@ -90,6 +90,21 @@ impl WidthMeasure for CodepointMono {
     }
 }
 
+/// A measure in which each codepoint has width of 1, except for tabs,
+/// which are `tab_width` wide.
+pub struct ColumnMono {
+    pub tab_width: usize,
+}
+
+impl WidthMeasure for ColumnMono {
+    fn measure_width(&self, request: &[WidthReq]) -> Result<WidthResponse, xi_rpc::Error> {
+        let width = |s: &String| {
+            s.chars().map(|c| if c == '\t' { self.tab_width } else { 1 }).sum::<usize>() as f64
+        };
+        Ok(request.iter().map(|r| r.strings.iter().map(width).collect()).collect())
+    }
+}
+
 impl WidthCache {
     pub fn new() -> WidthCache {
         WidthCache { m: HashMap::new(), widths: Vec::new() }
@@ -15,15 +15,22 @@
 #[macro_use]
 extern crate serde_json;
 
+extern crate tempdir;
 extern crate xi_core_lib;
 extern crate xi_rpc;
 
+use std::fs;
 use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use serde_json::Value;
+use tempdir::TempDir;
 
 use xi_core_lib::test_helpers;
 use xi_core_lib::XiCore;
-use xi_rpc::test_utils::{make_reader, test_channel};
-use xi_rpc::{ReadError, RpcLoop};
+use xi_rpc::test_utils::{make_reader, test_channel, DummyReader, DummyWriter};
+use xi_rpc::{ReadError, RemoteError, RpcLoop};
 
 #[test]
 /// Tests that the handler responds to a standard startup sequence as expected.
@@ -43,11 +50,39 @@ fn test_startup() {
     let json = make_reader(r#"{"id":0,"method":"new_view","params":{}}"#);
     assert!(rpc_looper.mainloop(|| json, &mut state).is_ok());
     assert_eq!(rx.expect_response(), Ok(json!("view-id-1")));
-    rx.expect_rpc("available_plugins");
     rx.expect_rpc("config_changed");
     rx.expect_rpc("language_changed");
+    rx.expect_rpc("bom_changed");
+    rx.expect_rpc("encoding_changed");
     rx.expect_rpc("update");
     rx.expect_rpc("scroll_to");
+    rx.expect_rpc("available_plugins");
+    rx.expect_nothing();
+}
+
+#[test]
+/// Tests that malformed view ids are answered with an error, not a panic.
+fn test_malformed_view_ids() {
+    let mut state = XiCore::new();
+    let (tx, mut rx) = test_channel();
+    let mut rpc_looper = RpcLoop::new(tx);
+    let bad_ids = [
+        json!(""),
+        json!("view-id-"),
+        json!("view-1-id"),
+        json!("view-id-99999999999999999999999"),
+        json!(1),
+    ];
+    for (i, view_id) in bad_ids.iter().enumerate() {
+        let msg =
+            json!({"id": i, "method": "edit", "params": {"view_id": view_id, "method": "copy"}});
+        let json = make_reader(msg.to_string());
+        assert!(rpc_looper.mainloop(|| json, &mut state).is_ok());
+        match rx.expect_response() {
+            Err(RemoteError::InvalidRequest(_)) => (),
+            other => panic!("unexpected response for {}: {:?}", view_id, other),
+        }
+    }
     rx.expect_nothing();
 }
 
@@ -279,11 +314,13 @@ fn test_settings_commands() {
     rx.expect_rpc("available_themes");
     rx.expect_rpc("theme_changed");
     rx.expect_response().unwrap();
-    rx.expect_rpc("available_plugins");
     rx.expect_rpc("config_changed");
     rx.expect_rpc("language_changed");
+    rx.expect_rpc("bom_changed");
+    rx.expect_rpc("encoding_changed");
     rx.expect_rpc("update");
     rx.expect_rpc("scroll_to");
+    rx.expect_rpc("available_plugins");
 
     let json = make_reader(r#"{"method":"get_config","id":1,"params":{"view_id":"view-id-1"}}"#);
     rpc_looper.mainloop(|| json, &mut state).unwrap();
@@ -317,7 +354,256 @@ fn test_settings_commands() {
     assert_eq!(resp.0["params"]["changes"]["font_face"], json!("Papyrus"));
 }
 
-//TODO: test saving rpc
+/// Starts a core with a single, empty view, discarding the startup messages.
+fn start_with_view() -> (XiCore, RpcLoop<DummyWriter>, DummyReader) {
+    let mut state = XiCore::new();
+    let (tx, mut rx) = test_channel();
+    let mut rpc_looper = RpcLoop::new(tx);
+    let json = make_reader(
+        r#"{"method":"client_started","params":{}}
+{"id":0,"method":"new_view","params":{}}"#,
+    );
+    assert!(rpc_looper.mainloop(|| json, &mut state).is_ok());
+    drain(&mut rx);
+    (state, rpc_looper, rx)
+}
+
+fn send(rpc_looper: &mut RpcLoop<DummyWriter>, state: &mut XiCore, msg: Value) {
+    let json = make_reader(msg.to_string());
+    assert!(rpc_looper.mainloop(|| json, state).is_ok());
+}
+
+fn insert(chars: &str) -> Value {
+    json!({"method": "edit", "params":
+        {"view_id": "view-id-1", "method": "insert", "params": {"chars": chars}}})
+}
+
+/// Returns all the messages sent by core so far.
+fn drain(rx: &mut DummyReader) -> Vec<Value> {
+    let mut msgs = Vec::new();
+    while let Some(Ok(msg)) = rx.next_timeout(Duration::from_millis(100)) {
+        msgs.push(msg.0);
+    }
+    msgs
+}
+
+fn find_rpc<'a>(msgs: &'a [Value], method: &str) -> Option<&'a Value> {
+    msgs.iter().find(|msg| msg["method"] == json!(method))
+}
+
+/// Returns the `pristine` state of the last update in `msgs`.
+fn last_pristine(msgs: &[Value]) -> Option<bool> {
+    msgs.iter()
+        .rev()
+        .find(|msg| msg["method"] == json!("update"))
+        .map(|msg| msg["params"]["update"]["pristine"].as_bool().unwrap())
+}
+
+#[test]
+/// Tests that a failed save can be completed by the frontend, and that the
+/// buffer is only pristine once the frontend confirms it.
+fn test_retry_save_elevated() {
+    let (mut state, mut rpc_looper, mut rx) = start_with_view();
+    let tmp = TempDir::new("xi-test-retry-save").unwrap();
+    let dir = tmp.path().join("missing");
+    let path = dir.join("file.txt");
+
+    send(&mut rpc_looper, &mut state, insert("hello"));
+    send(
+        &mut rpc_looper,
+        &mut state,
+        json!({"method": "save", "params": {"view_id": "view-id-1", "file_path": path}}),
+    );
+    let msgs = drain(&mut rx);
+    let failed = find_rpc(&msgs, "save_failed").expect("save_failed not sent");
+    assert_eq!(failed["params"]["path"], json!(path));
+    assert_eq!(failed["params"]["error"]["kind"], json!("not_found"));
+    assert_eq!(last_pristine(&msgs), Some(false));
+
+    send(
+        &mut rpc_looper,
+        &mut state,
+        json!({"id": 1, "method": "retry_save_elevated", "params": {"view_id": "view-id-1"}}),
+    );
+    let resp = rx.expect_response().unwrap();
+    assert_eq!(resp["path"], json!(path));
+    let temp_path = PathBuf::from(resp["temp_path"].as_str().unwrap());
+    assert_eq!(fs::read_to_string(&temp_path).unwrap(), "hello\n");
+    // nothing is saved until the frontend confirms
+    assert_eq!(last_pristine(&drain(&mut rx)), None);
+
+    // the frontend moves the file into place, with rights of its own
+    fs::create_dir(&dir).unwrap();
+    fs::copy(&temp_path, &path).unwrap();
+    send(
+        &mut rpc_looper,
+        &mut state,
+        json!({"method": "confirm_external_save", "params": {"view_id": "view-id-1"}}),
+    );
+    assert_eq!(last_pristine(&drain(&mut rx)), Some(true));
+    assert!(!temp_path.exists());
+
+    // later saves go to the new path
+    send(&mut rpc_looper, &mut state, insert("!"));
+    assert_eq!(last_pristine(&drain(&mut rx)), Some(false));
+    send(
+        &mut rpc_looper,
+        &mut state,
+        json!({"method": "save", "params": {"view_id": "view-id-1", "file_path": path}}),
+    );
+    assert_eq!(last_pristine(&drain(&mut rx)), Some(true));
+    assert_eq!(fs::read_to_string(&path).unwrap(), "hello!\n");
+}
+
+#[test]
+/// Tests that edits made while the frontend completes a save keep the buffer
+/// modified, and that a save which is never confirmed changes nothing.
+fn test_retry_save_elevated_unconfirmed() {
+    let (mut state, mut rpc_looper, mut rx) = start_with_view();
+    let tmp = TempDir::new("xi-test-retry-save-unconfirmed").unwrap();
+    let dir = tmp.path().join("missing");
+    let path = dir.join("file.txt");
+
+    // there is no failed save to retry
+    send(
+        &mut rpc_looper,
+        &mut state,
+        json!({"id": 1, "method": "retry_save_elevated", "params": {"view_id": "view-id-1"}}),
+    );
+    assert!(rx.expect_response().is_err());
+
+    send(&mut rpc_looper, &mut state, insert("hello"));
+    send(
+        &mut rpc_looper,
+        &mut state,
+        json!({"method": "save", "params": {"view_id": "view-id-1", "file_path": path}}),
+    );
+    send(
+        &mut rpc_looper,
+        &mut state,
+        json!({"id": 2, "method": "retry_save_elevated", "params": {"view_id": "view-id-1"}}),
+    );
+    let msgs = drain(&mut rx);
+    let resp = msgs.iter().find(|msg| msg["id"] == json!(2)).unwrap();
+    let temp_path = PathBuf::from(resp["result"]["temp_path"].as_str().unwrap());
+    assert!(temp_path.exists());
+
+    // edited while the frontend is busy
+    send(&mut rpc_looper, &mut state, insert(" world"));
+    assert_eq!(last_pristine(&drain(&mut rx)), Some(false));
+
+    // the frontend gives up, and the user saves somewhere core can write
+    fs::create_dir(&dir).unwrap();
+    send(
+        &mut rpc_looper,
+        &mut state,
+        json!({"method": "save", "params": {"view_id": "view-id-1", "file_path": path}}),
+    );
+    let msgs = drain(&mut rx);
+    assert!(find_rpc(&msgs, "save_failed").is_none());
+    assert_eq!(last_pristine(&msgs), Some(true));
+    assert_eq!(fs::read_to_string(&path).unwrap(), "hello world\n");
+    // the handed off save was discarded
+    assert!(!temp_path.exists());
+
+    // so a late confirmation changes nothing
+    send(
+        &mut rpc_looper,
+        &mut state,
+        json!({"method": "confirm_external_save", "params": {"view_id": "view-id-1"}}),
+    );
+    assert!(drain(&mut rx).is_empty());
+    assert_eq!(fs::read_to_string(&path).unwrap(), "hello world\n");
+}
+
+#[test]
+/// Tests that split views share their buffer, but not their selections.
+fn test_split_view() {
+    let (mut state, mut rpc_looper, mut rx) = start_with_view();
+    send(&mut rpc_looper, &mut state, insert("hello"));
+    drain(&mut rx);
+    send(
+        &mut rpc_looper,
+        &mut state,
+        json!({"id": 1, "method": "split_view", "params": {"view_id": "view-id-1"}}),
+    );
+    let split = rx.expect_response().unwrap();
+    assert_ne!(split, json!("view-id-1"));
+    let msgs = drain(&mut rx);
+    let config = find_rpc(&msgs, "config_changed").unwrap();
+    assert_eq!(config["params"]["view_id"], split);
+    {
+        let state = state.inner();
+        assert_eq!(state._test_open_editors().len(), 1);
+        assert_eq!(state._test_open_views().len(), 2);
+    }
+
+    // the split starts with a copy of the selection, but moves on its own
+    let edit = |view_id: &Value, method: &str, params: Value| {
+        json!({"method": "edit",
+            "params": {"view_id": view_id, "method": method, "params": params}})
+    };
+    send(&mut rpc_looper, &mut state, edit(&split, "move_to_beginning_of_document", json!([])));
+    send(&mut rpc_looper, &mut state, edit(&split, "insert", json!({"chars": "> "})));
+    send(&mut rpc_looper, &mut state, insert("!"));
+    let msgs = drain(&mut rx);
+    let updated = |view_id: &Value| {
+        msgs.iter()
+            .any(|msg| msg["method"] == json!("update") && &msg["params"]["view_id"] == view_id)
+    };
+    assert!(updated(&json!("view-id-1")));
+    assert!(updated(&split));
+
+    let contents = json!({"id": 2, "method": "debug_get_contents", "params": {"view_id": split}});
+    send(&mut rpc_looper, &mut state, contents);
+    assert_eq!(rx.expect_response(), Ok(json!("> hello!")));
+
+    // the buffer lives on while any of its views are open
+    send(
+        &mut rpc_looper,
+        &mut state,
+        json!({"method": "close_view", "params": {"view_id": "view-id-1"}}),
+    );
+    assert_eq!(state.inner()._test_open_editors().len(), 1);
+    send(
+        &mut rpc_looper,
+        &mut state,
+        json!({"method": "close_view", "params": {"view_id": split}}),
+    );
+    assert!(state.inner()._test_open_editors().is_empty());
+}
+
+#[test]
+fn test_offset_to_line_col() {
+    let (mut state, mut rpc_looper, mut rx) = start_with_view();
+    send(&mut rpc_looper, &mut state, insert("ab\né"));
+    drain(&mut rx);
+    send(
+        &mut rpc_looper,
+        &mut state,
+        json!({"id": 1, "method": "offset_to_line_col",
+            "params": {"view_id": "view-id-1", "offsets": [1, 3, 4, 5, 1000]}}),
+    );
+    // wrapping is off, so there are no visual positions; the offset inside
+    // "é" and the one past the end are clamped.
+    let expected = json!([
+        {"line": 0, "col": 1, "col_utf16": 1},
+        {"line": 1, "col": 0, "col_utf16": 0},
+        {"line": 1, "col": 0, "col_utf16": 0},
+        {"line": 1, "col": 2, "col_utf16": 1},
+        {"line": 1, "col": 2, "col_utf16": 1},
+    ]);
+    assert_eq!(rx.expect_response(), Ok(expected));
+
+    send(
+        &mut rpc_looper,
+        &mut state,
+        json!({"id": 2, "method": "offset_to_line_col",
+            "params": {"view_id": "view-id-7", "offsets": [0]}}),
+    );
+    assert!(rx.expect_response().is_err());
+}
+
 //TODO: test plugin rpc
 
 const MOVEMENT_RPCS: &str = r#"{"method":"edit","params":{"view_id":"view-id-1","method":"move_up","params":[]}}
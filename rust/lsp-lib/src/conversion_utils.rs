@@ -15,9 +15,16 @@
 //! Utility functions meant for converting types from LSP to Core format
 //! and vice-versa
 
+use std::iter;
+
 use crate::lsp_types::*;
 use crate::types::LanguageResponseError;
-use xi_plugin_lib::{Cache, Error as PluginLibError, Hover as CoreHover, Range as CoreRange, View};
+use crate::xi_core::plugin_rpc::DataSpan;
+use serde_json::Value;
+use xi_plugin_lib::{
+    Cache, CompletionItem as CoreCompletionItem, CompletionList as CoreCompletionList,
+    Error as PluginLibError, Hover as CoreHover, Range as CoreRange, View,
+};
 
 pub(crate) fn marked_string_to_string(marked_string: &MarkedString) -> String {
     match *marked_string {
@@ -71,24 +78,29 @@ pub(crate) fn get_position_of_offset<C: Cache>(
     Ok(Position { line: line_num as u64, character: char_offset as u64 })
 }
 
-pub(crate) fn offset_of_position<C: Cache>(
-    view: &mut View<C>,
-    position: Position,
-) -> Result<usize, PluginLibError> {
-    let line_offset = view.offset_of_line(position.line as usize);
-
+/// Returns the utf-8 offset in `line` of the LSP style utf-16 based
+/// `character`. As LSP specifies, a `character` past the end of the line
+/// means the end of the line, before its line ending. One that falls inside
+/// a surrogate pair is moved to the end of that character.
+pub(crate) fn utf8_offset_of_utf16(line: &str, character: usize) -> usize {
+    let line = line.trim_end_matches(|c| c == '\n' || c == '\r');
     let mut cur_len_utf16 = 0;
-    let mut cur_len_utf8 = 0;
-
-    for u in view.get_line(position.line as usize)?.chars() {
-        if cur_len_utf16 >= (position.character as usize) {
-            break;
+    for (offset, c) in line.char_indices() {
+        if cur_len_utf16 >= character {
+            return offset;
         }
-        cur_len_utf16 += u.len_utf16();
-        cur_len_utf8 += u.len_utf8();
+        cur_len_utf16 += c.len_utf16();
     }
+    line.len()
+}
 
-    Ok(cur_len_utf8 + line_offset?)
+pub(crate) fn offset_of_position<C: Cache>(
+    view: &mut View<C>,
+    position: Position,
+) -> Result<usize, PluginLibError> {
+    let line_offset = view.offset_of_line(position.line as usize)?;
+    let line = view.get_line(position.line as usize)?;
+    Ok(line_offset + utf8_offset_of_utf16(line, position.character as usize))
 }
 
 pub(crate) fn core_range_from_range<C: Cache>(
@@ -113,3 +125,200 @@ pub(crate) fn core_hover_from_hover<C: Cache>(
         },
     })
 }
+
+/// Parses the result of a completion request. Servers may send a bare array
+/// of items rather than a list, which is then complete; a null result has no
+/// items.
+pub(crate) fn completion_list_from_result(
+    result: Value,
+) -> Result<CompletionList, LanguageResponseError> {
+    let response: Option<CompletionResponse> = serde_json::from_value(result)
+        .map_err(|e| LanguageResponseError::LanguageServerError(e.to_string()))?;
+    Ok(match response {
+        Some(CompletionResponse::List(list)) => list,
+        Some(CompletionResponse::Array(items)) => CompletionList { is_incomplete: false, items },
+        None => CompletionList { is_incomplete: false, items: Vec::new() },
+    })
+}
+
+/// Returns the name of a completion kind in snake case, such as
+/// `"enum_member"`.
+fn completion_kind_name(kind: &CompletionItemKind) -> String {
+    let mut name = String::new();
+    for c in format!("{:?}", kind).chars() {
+        if c.is_uppercase() && !name.is_empty() {
+            name.push('_');
+        }
+        name.extend(c.to_lowercase());
+    }
+    name
+}
+
+/// Converts a completion item to the core format. An item which only
+/// has a text edit is inserted as the edit's text; the range it replaces
+/// is not sent.
+pub(crate) fn core_completion_from_completion(item: &CompletionItem) -> CoreCompletionItem {
+    let insert_text = item
+        .insert_text
+        .clone()
+        .or_else(|| item.text_edit.as_ref().map(|edit| edit.new_text.clone()));
+    let documentation = item.documentation.as_ref().map(|documentation| match documentation {
+        Documentation::String(text) => text.clone(),
+        Documentation::MarkupContent(content) => content.value.clone(),
+    });
+    CoreCompletionItem {
+        label: item.label.clone(),
+        detail: item.detail.clone(),
+        insert_text,
+        kind: item.kind.as_ref().map(completion_kind_name),
+        documentation,
+    }
+}
+
+pub(crate) fn core_completions_from_completions(list: &CompletionList) -> CoreCompletionList {
+    CoreCompletionList {
+        is_incomplete: list.is_incomplete,
+        items: list.items.iter().map(core_completion_from_completion).collect(),
+    }
+}
+
+/// Converts diagnostics to annotation spans over `text`, the document they
+/// were computed against, in order of their start. The data of each span is
+/// the severity and message of its diagnostic.
+///
+/// Diagnostics on lines past the end of `text` are dropped. An empty range
+/// is widened to cover the character after it, so that it can be shown.
+pub(crate) fn diagnostic_spans(text: &str, diagnostics: &[Diagnostic]) -> Vec<DataSpan> {
+    let line_starts = iter::once(0)
+        .chain(text.match_indices('\n').map(|(offset, _)| offset + 1))
+        .collect::<Vec<_>>();
+    let offset_of_position = |position: Position| {
+        let line = position.line as usize;
+        let start = *line_starts.get(line)?;
+        let end = line_starts.get(line + 1).cloned().unwrap_or_else(|| text.len());
+        Some(start + utf8_offset_of_utf16(&text[start..end], position.character as usize))
+    };
+
+    let mut spans = diagnostics
+        .iter()
+        .filter_map(|diagnostic| {
+            let start = offset_of_position(diagnostic.range.start)?;
+            let mut end = offset_of_position(diagnostic.range.end)?.max(start);
+            if end == start {
+                end += text[start..].chars().next().map_or(0, char::len_utf8);
+            }
+            let data = serde_json::json!({
+                "severity": diagnostic.severity,
+                "message": diagnostic.message,
+            });
+            Some(DataSpan { start, end, data })
+        })
+        .collect::<Vec<_>>();
+    spans.sort_by_key(|span| span.start);
+    spans
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn diagnostic(start: (u64, u64), end: (u64, u64), message: &str) -> Diagnostic {
+        let range = Range {
+            start: Position { line: start.0, character: start.1 },
+            end: Position { line: end.0, character: end.1 },
+        };
+        Diagnostic {
+            severity: Some(DiagnosticSeverity::Error),
+            ..Diagnostic::new_simple(range, message.to_owned())
+        }
+    }
+
+    fn ranges(spans: &[DataSpan]) -> Vec<(usize, usize)> {
+        spans.iter().map(|span| (span.start, span.end)).collect()
+    }
+
+    #[test]
+    fn utf16_positions() {
+        assert_eq!(utf8_offset_of_utf16("abc\n", 0), 0);
+        assert_eq!(utf8_offset_of_utf16("abc\n", 2), 2);
+        // past the end is the end, before the line ending
+        assert_eq!(utf8_offset_of_utf16("abc\n", 10), 3);
+        assert_eq!(utf8_offset_of_utf16("abc\r\n", 4), 3);
+        assert_eq!(utf8_offset_of_utf16("", 1), 0);
+
+        // a two byte, then a three byte character, each one code unit
+        let line = "\u{e9}\u{65E5}x";
+        assert_eq!(utf8_offset_of_utf16(line, 1), 2);
+        assert_eq!(utf8_offset_of_utf16(line, 2), 5);
+        assert_eq!(utf8_offset_of_utf16(line, 3), 6);
+        assert_eq!(count_utf16(line), 3);
+
+        // an astral plane character is four bytes, and two code units
+        let line = "a\u{1F600}b";
+        assert_eq!(utf8_offset_of_utf16(line, 1), 1);
+        assert_eq!(utf8_offset_of_utf16(line, 3), 5);
+        assert_eq!(utf8_offset_of_utf16(line, 4), 6);
+        // inside the surrogate pair
+        assert_eq!(utf8_offset_of_utf16(line, 2), 5);
+        assert_eq!(count_utf16(line), 4);
+        assert_eq!(count_utf16("a\u{1F600}"), 3);
+    }
+
+    #[test]
+    fn completions_to_core() {
+        let item: CompletionItem = serde_json::from_value(serde_json::json!({
+            "label": "push",
+            "kind": 2,
+            "detail": "fn(&mut self, T)",
+            "documentation": {"kind": "markdown", "value": "Appends an element."},
+        }))
+        .unwrap();
+        let expected = CoreCompletionItem {
+            label: "push".into(),
+            detail: Some("fn(&mut self, T)".into()),
+            insert_text: None,
+            kind: Some("method".into()),
+            documentation: Some("Appends an element.".into()),
+        };
+        assert_eq!(core_completion_from_completion(&item), expected);
+
+        // an item with only a text edit inserts the edit's text
+        let item: CompletionItem = serde_json::from_value(serde_json::json!({
+            "label": "None",
+            "kind": 20,
+            "documentation": "No value.",
+            "textEdit": {
+                "range": {"start": {"line": 0, "character": 4}, "end": {"line": 0, "character": 5}},
+                "newText": "None",
+            },
+        }))
+        .unwrap();
+        let core_item = core_completion_from_completion(&item);
+        assert_eq!(core_item.insert_text, Some("None".into()));
+        assert_eq!(core_item.kind, Some("enum_member".into()));
+        assert_eq!(core_item.documentation, Some("No value.".into()));
+    }
+
+    #[test]
+    fn diagnostics_to_spans() {
+        let text = "fn main() {\n    let \u{1F600} = \u{e9};\n}";
+        let diagnostics = vec![
+            // the name after the emoji, in the second line
+            diagnostic((1, 11), (1, 14), "later"),
+            diagnostic((1, 8), (1, 10), "emoji"),
+            // empty, at the end of the text
+            diagnostic((2, 1), (2, 1), "eof"),
+            diagnostic((5, 0), (5, 1), "past the end"),
+        ];
+        let spans = diagnostic_spans(text, &diagnostics);
+        assert_eq!(ranges(&spans), vec![(20, 24), (25, 29), (32, 32)]);
+        assert_eq!(&text[20..24], "\u{1F600}");
+        assert_eq!(&text[25..29], "= \u{e9}");
+        assert_eq!(spans[0].data["message"], "emoji");
+        assert_eq!(spans[0].data["severity"], 1);
+
+        // an empty range covers the next character
+        let spans = diagnostic_spans(text, &[diagnostic((1, 8), (1, 8), "empty")]);
+        assert_eq!(ranges(&spans), vec![(20, 24)]);
+    }
+}
@@ -25,7 +25,7 @@ use xi_plugin_lib::CoreProxy;
 
 use crate::lsp_types::*;
 use crate::result_queue::ResultQueue;
-use crate::types::Callback;
+use crate::types::{Callback, PendingDiagnostics};
 use crate::xi_core::ViewId;
 
 /// A type to abstract communication with the language server
@@ -39,6 +39,11 @@ pub struct LanguageServerClient {
     pub core: CoreProxy,
     pub is_initialized: bool,
     pub opened_documents: HashMap<ViewId, Url>,
+    /// The version of each open document last sent to the server.
+    document_versions: HashMap<ViewId, u64>,
+    /// The latest diagnostics for each open document, until they are sent
+    /// to core.
+    pub pending_diagnostics: HashMap<ViewId, PendingDiagnostics>,
     pub server_capabilities: Option<ServerCapabilities>,
     pub file_extensions: Vec<String>,
 }
@@ -50,6 +55,21 @@ fn prepare_lsp_json(msg: &Value) -> Result<String, serde_json::error::Error> {
     Ok(format!("Content-Length: {}\r\n\r\n{}", request.len(), request))
 }
 
+/// Parses the params of a `textDocument/publishDiagnostics` notification,
+/// along with the version of the document they were computed against, which
+/// servers send from version 3.15 of the protocol.
+fn diagnostics_from_params(
+    params: Params,
+) -> Result<(PublishDiagnosticsParams, Option<u64>), serde_json::Error> {
+    let params = match params {
+        Params::Map(map) => Value::Object(map),
+        Params::Array(array) => Value::Array(array),
+        Params::None(()) => Value::Null,
+    };
+    let version = params.get("version").and_then(Value::as_u64);
+    Ok((serde_json::from_value(params)?, version))
+}
+
 /// Get numeric id from the request id.
 fn number_from_id(id: &Id) -> u64 {
     match *id {
@@ -78,6 +98,8 @@ impl LanguageServerClient {
             language_id,
             server_capabilities: None,
             opened_documents: HashMap::new(),
+            document_versions: HashMap::new(),
+            pending_diagnostics: HashMap::new(),
             file_extensions,
         }
     }
@@ -121,12 +143,40 @@ impl LanguageServerClient {
         match method {
             "window/showMessage" => {}
             "window/logMessage" => {}
-            "textDocument/publishDiagnostics" => {}
+            "textDocument/publishDiagnostics" => self.handle_publish_diagnostics(params),
             "telemetry/event" => {}
             _ => self.handle_misc_notification(method, params),
         }
     }
 
+    /// Keeps the diagnostics for a document until the plugin's next idle
+    /// call for its view, when they are converted against the view's text.
+    /// Any diagnostics not yet sent for the document are replaced.
+    fn handle_publish_diagnostics(&mut self, params: Params) {
+        let (params, version) = match diagnostics_from_params(params) {
+            Ok(result) => result,
+            Err(err) => {
+                warn!("invalid diagnostics: {}", err);
+                return;
+            }
+        };
+        let view_id = match self.opened_documents.iter().find(|(_, uri)| **uri == params.uri) {
+            Some((view_id, _)) => *view_id,
+            None => {
+                trace!("diagnostics for unopened document {}", params.uri);
+                return;
+            }
+        };
+        // servers which don't say which version they saw have the latest
+        let version = version.or_else(|| self.document_versions.get(&view_id).cloned());
+        let pending = PendingDiagnostics {
+            version: version.unwrap_or_default(),
+            diagnostics: params.diagnostics,
+        };
+        self.pending_diagnostics.insert(view_id, pending);
+        self.core.schedule_idle(view_id);
+    }
+
     pub fn handle_misc_notification(&mut self, method: &str, params: Params) {
         match self.language_id.to_lowercase().as_ref() {
             "rust" => self.handle_rust_misc_notification(method, params),
@@ -207,6 +257,7 @@ impl LanguageServerClient {
     /// Send textDocument/didOpen Notification to the Language Server
     pub fn send_did_open(&mut self, view_id: ViewId, document_uri: Url, document_text: String) {
         self.opened_documents.insert(view_id, document_uri.clone());
+        self.document_versions.insert(view_id, 0);
 
         let text_document_did_open_params = DidOpenTextDocumentParams {
             text_document: TextDocumentItem {
@@ -231,6 +282,8 @@ impl LanguageServerClient {
         self.send_notification("textDocument/didClose", params);
 
         self.opened_documents.remove(&view_id);
+        self.document_versions.remove(&view_id);
+        self.pending_diagnostics.remove(&view_id);
     }
 
     /// Send textDocument/didChange Notification to the Language Server
@@ -250,6 +303,7 @@ impl LanguageServerClient {
 
         let params = Params::from(serde_json::to_value(text_document_did_change_params).unwrap());
         self.send_notification("textDocument/didChange", params);
+        self.document_versions.insert(view_id, version);
     }
 
     /// Send textDocument/didSave notification to the Language Server
@@ -275,6 +329,45 @@ impl LanguageServerClient {
         let params = Params::from(serde_json::to_value(text_document_position_params).unwrap());
         self.send_request("textDocument/hover", params, Box::new(on_result))
     }
+
+    /// Send a textDocument/completion request, returning its id, which can
+    /// be passed to `cancel_request`.
+    pub fn request_completion<CB>(
+        &mut self,
+        view_id: ViewId,
+        position: Position,
+        on_result: CB,
+    ) -> u64
+    where
+        CB: 'static + Send + FnOnce(&mut LanguageServerClient, Result<Value, Error>),
+    {
+        let text_document_position_params = TextDocumentPositionParams {
+            text_document: TextDocumentIdentifier { uri: self.opened_documents[&view_id].clone() },
+            position,
+        };
+
+        let id = self.next_id;
+        let params = Params::from(serde_json::to_value(text_document_position_params).unwrap());
+        self.send_request("textDocument/completion", params, Box::new(on_result));
+        id
+    }
+
+    /// Send a completionItem/resolve request, for the details of an item
+    /// returned by a completion request.
+    pub fn request_completion_resolve<CB>(&mut self, item: CompletionItem, on_result: CB)
+    where
+        CB: 'static + Send + FnOnce(&mut LanguageServerClient, Result<Value, Error>),
+    {
+        let params = Params::from(serde_json::to_value(item).unwrap());
+        self.send_request("completionItem/resolve", params, Box::new(on_result))
+    }
+
+    /// Tells the server that the result of a request is no longer needed.
+    /// The server still responds to it, often with an error.
+    pub fn cancel_request(&mut self, id: u64) {
+        let params = Params::from(serde_json::json!({ "id": id }));
+        self.send_notification("$/cancelRequest", params);
+    }
 }
 
 /// Helper methods to query the capabilities of the Language Server before making
@@ -288,6 +381,16 @@ impl LanguageServerClient {
             _ => TextDocumentSyncKind::Full,
         }
     }
+
+    /// Whether the server can fill in the details of completion items
+    /// through completionItem/resolve.
+    pub fn can_resolve_completions(&self) -> bool {
+        self.server_capabilities
+            .as_ref()
+            .and_then(|c| c.completion_provider.as_ref())
+            .and_then(|p| p.resolve_provider)
+            .unwrap_or(false)
+    }
 }
 
 /// Language Specific Notification handling implementations
@@ -335,3 +438,122 @@ impl LanguageServerClient {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::conversion_utils::{
+        completion_list_from_result, core_completions_from_completions, diagnostic_spans,
+    };
+    use crate::parse_helper::read_message;
+
+    fn diagnostics_notification(version: Option<u64>, ranges: &[(u64, u64, u64)]) -> Value {
+        let diagnostics = ranges
+            .iter()
+            .map(|&(line, start, end)| {
+                serde_json::json!({
+                    "range": {
+                        "start": {"line": line, "character": start},
+                        "end": {"line": line, "character": end},
+                    },
+                    "severity": 2,
+                    "message": "unused",
+                })
+            })
+            .collect::<Vec<_>>();
+        let mut params = serde_json::json!({
+            "uri": "file:///tmp/main.rs",
+            "diagnostics": diagnostics,
+        });
+        if let Some(version) = version {
+            params["version"] = version.into();
+        }
+        serde_json::json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/publishDiagnostics",
+            "params": params,
+        })
+    }
+
+    #[test]
+    fn diagnostics_from_server_stream() {
+        let text = "let \u{1F600} = 1;\nlet x = \u{1F600};\n";
+        let stream = [
+            diagnostics_notification(Some(3), &[(1, 8, 10), (0, 4, 6)]),
+            diagnostics_notification(None, &[]),
+        ]
+        .iter()
+        .map(|msg| prepare_lsp_json(msg).unwrap())
+        .collect::<String>();
+        let mut reader = Cursor::new(stream.into_bytes());
+
+        let mut next_diagnostics = || {
+            let message = read_message(&mut reader).unwrap();
+            let notification = JsonRpc::parse(&message).unwrap();
+            assert_eq!(notification.get_method(), Some("textDocument/publishDiagnostics"));
+            diagnostics_from_params(notification.get_params().unwrap()).unwrap()
+        };
+
+        let (params, version) = next_diagnostics();
+        assert_eq!(params.uri.as_str(), "file:///tmp/main.rs");
+        assert_eq!(version, Some(3));
+        let spans = diagnostic_spans(text, &params.diagnostics);
+        let ranges = spans.iter().map(|span| (span.start, span.end)).collect::<Vec<_>>();
+        assert_eq!(ranges, vec![(4, 8), (22, 26)]);
+        assert_eq!(&text[22..26], "\u{1F600}");
+        assert_eq!(spans[0].data["severity"], 2);
+        assert_eq!(spans[0].data["message"], "unused");
+
+        // an empty list clears the document's diagnostics
+        let (params, version) = next_diagnostics();
+        assert_eq!(version, None);
+        assert!(diagnostic_spans(text, &params.diagnostics).is_empty());
+    }
+
+    fn response(id: u64, result: Value) -> Value {
+        serde_json::json!({ "jsonrpc": "2.0", "id": id, "result": result })
+    }
+
+    #[test]
+    fn completions_from_server_stream() {
+        let items = serde_json::json!([
+            {"label": "len", "kind": 2, "detail": "fn(&self) -> usize"},
+            {"label": "last", "kind": 2, "insertText": "last()"},
+        ]);
+        let stream = [
+            response(1, serde_json::json!({"isIncomplete": true, "items": items})),
+            response(2, items),
+            response(3, Value::Null),
+        ]
+        .iter()
+        .map(|msg| prepare_lsp_json(msg).unwrap())
+        .collect::<String>();
+        let mut reader = Cursor::new(stream.into_bytes());
+
+        let mut next_completions = || {
+            let message = read_message(&mut reader).unwrap();
+            let response = JsonRpc::parse(&message).unwrap();
+            completion_list_from_result(response.get_result().unwrap().clone()).unwrap()
+        };
+
+        let list = core_completions_from_completions(&next_completions());
+        assert!(list.is_incomplete);
+        let labels = list.items.iter().map(|item| item.label.as_str()).collect::<Vec<_>>();
+        assert_eq!(labels, vec!["len", "last"]);
+        assert_eq!(list.items[0].detail, Some("fn(&self) -> usize".into()));
+        assert_eq!(list.items[0].kind, Some("method".into()));
+        assert_eq!(list.items[1].insert_text, Some("last()".into()));
+
+        // a bare array is a complete list
+        let list = next_completions();
+        assert!(!list.is_incomplete);
+        assert_eq!(list.items.len(), 2);
+
+        // as is a null result, with no items
+        let list = next_completions();
+        assert!(!list.is_incomplete);
+        assert!(list.items.is_empty());
+    }
+}
@@ -28,6 +28,7 @@ use crate::lsp_types::*;
 use crate::result_queue::ResultQueue;
 use crate::types::{Config, LanguageResponseError, LspResponse};
 use crate::utils::*;
+use crate::xi_core::annotations::AnnotationType;
 use crate::xi_core::{ConfigTable, ViewId};
 
 pub struct ViewInfo {
@@ -35,10 +36,20 @@ pub struct ViewInfo {
     ls_identifier: String,
 }
 
+/// The latest completion request for a view. Responses to earlier requests
+/// are dropped, and the items of this one are kept so they can be resolved.
+struct CompletionRequest {
+    request_id: usize,
+    /// The id of the request sent to the server, until it responds.
+    ls_request: Option<u64>,
+    items: Vec<CompletionItem>,
+}
+
 /// Represents the state of the Language Server Plugin
 pub struct LspPlugin {
     pub config: Config,
     view_info: HashMap<ViewId, ViewInfo>,
+    completions: HashMap<ViewId, CompletionRequest>,
     core: Option<CoreProxy>,
     result_queue: ResultQueue,
     language_server_clients: HashMap<String, Arc<Mutex<LanguageServerClient>>>,
@@ -51,6 +62,7 @@ impl LspPlugin {
             core: None,
             result_queue: ResultQueue::new(),
             view_info: HashMap::new(),
+            completions: HashMap::new(),
             language_server_clients: HashMap::new(),
         }
     }
@@ -100,6 +112,7 @@ impl Plugin for LspPlugin {
         self.with_language_server_for_view(view, |ls_client| {
             ls_client.send_did_close(view.get_id());
         });
+        self.completions.remove(&view.get_id());
     }
 
     fn new_view(&mut self, view: &mut View<Self::Cache>) {
@@ -178,6 +191,94 @@ impl Plugin for LspPlugin {
         });
     }
 
+    fn get_completions(
+        &mut self,
+        view: &mut View<Self::Cache>,
+        request_id: usize,
+        position: usize,
+    ) {
+        let view_id = view.get_id();
+        let position_ls = get_position_of_offset(view, position);
+        let previous = self.completions.remove(&view_id).and_then(|request| request.ls_request);
+
+        let ls_request = self.with_language_server_for_view(view, |ls_client| {
+            // the user kept typing, so the results of the last request won't be shown
+            if let Some(id) = previous {
+                ls_client.cancel_request(id);
+            }
+            match position_ls {
+                Ok(position) => {
+                    let id = ls_client.request_completion(
+                        view_id,
+                        position,
+                        move |ls_client, result| {
+                            let res = result
+                                .map_err(|e| {
+                                    LanguageResponseError::LanguageServerError(format!("{:?}", e))
+                                })
+                                .and_then(completion_list_from_result);
+                            ls_client
+                                .result_queue
+                                .push_result(request_id, LspResponse::Completions(res));
+                            ls_client.core.schedule_idle(view_id);
+                        },
+                    );
+                    Some(id)
+                }
+                Err(err) => {
+                    let res = LspResponse::Completions(Err(err.into()));
+                    ls_client.result_queue.push_result(request_id, res);
+                    ls_client.core.schedule_idle(view_id);
+                    None
+                }
+            }
+        });
+        if let Some(ls_request) = ls_request {
+            let request = CompletionRequest { request_id, ls_request, items: Vec::new() };
+            self.completions.insert(view_id, request);
+        }
+    }
+
+    fn resolve_completion(
+        &mut self,
+        view: &mut View<Self::Cache>,
+        request_id: usize,
+        index: usize,
+    ) {
+        let view_id = view.get_id();
+        let item = match self.completions.get(&view_id) {
+            Some(request) if request.request_id == request_id => request.items.get(index).cloned(),
+            _ => None,
+        };
+        let item = match item {
+            Some(item) => item,
+            None => {
+                debug!("no completion {} for request {} in {}", index, request_id, view_id);
+                return;
+            }
+        };
+
+        self.with_language_server_for_view(view, |ls_client| {
+            if !ls_client.can_resolve_completions() {
+                let res = LspResponse::ResolvedCompletion(index, Ok(item));
+                ls_client.result_queue.push_result(request_id, res);
+                ls_client.core.schedule_idle(view_id);
+                return;
+            }
+            ls_client.request_completion_resolve(item, move |ls_client, result| {
+                let res = result
+                    .map_err(|e| LanguageResponseError::LanguageServerError(format!("{:?}", e)))
+                    .and_then(|item| {
+                        serde_json::from_value(item)
+                            .map_err(|e| LanguageResponseError::LanguageServerError(e.to_string()))
+                    });
+                let res = LspResponse::ResolvedCompletion(index, res);
+                ls_client.result_queue.push_result(request_id, res);
+                ls_client.core.schedule_idle(view_id);
+            });
+        });
+    }
+
     fn idle(&mut self, view: &mut View<Self::Cache>) {
         let result = self.result_queue.pop_result();
         if let Some((request_id, reponse)) = result {
@@ -189,8 +290,13 @@ impl Plugin for LspPlugin {
                         ls_client.core.display_hover(view.get_id(), request_id, &res)
                     });
                 }
+                LspResponse::Completions(res) => self.send_completions(request_id, res),
+                LspResponse::ResolvedCompletion(index, res) => {
+                    self.send_resolved_completion(request_id, index, res)
+                }
             }
         }
+        self.send_pending_diagnostics(view);
     }
 }
 
@@ -275,6 +381,109 @@ impl LspPlugin {
             })
     }
 
+    /// Sends core the completions for `request_id`, unless another request
+    /// was made for its view since.
+    fn send_completions(
+        &mut self,
+        request_id: usize,
+        result: Result<CompletionList, LanguageResponseError>,
+    ) {
+        let (view_id, request) = match self.current_completions(request_id) {
+            Some(current) => current,
+            None => {
+                debug!("dropping completions for stale request {}", request_id);
+                return;
+            }
+        };
+        request.ls_request = None;
+        let result = result
+            .map(|list| {
+                let core_list = core_completions_from_completions(&list);
+                request.items = list.items;
+                core_list
+            })
+            .map_err(|e| e.into());
+        if let Some(core) = self.core.as_mut() {
+            core.display_completions(view_id, request_id, &result);
+        }
+    }
+
+    /// Sends core the details of a completion, unless another request was
+    /// made for its view since. The item is also kept in its resolved form.
+    fn send_resolved_completion(
+        &mut self,
+        request_id: usize,
+        index: usize,
+        result: Result<CompletionItem, LanguageResponseError>,
+    ) {
+        let (view_id, request) = match self.current_completions(request_id) {
+            Some(current) => current,
+            None => {
+                debug!("dropping resolved completion for stale request {}", request_id);
+                return;
+            }
+        };
+        let result = result
+            .map(|item| {
+                let core_item = core_completion_from_completion(&item);
+                if let Some(old) = request.items.get_mut(index) {
+                    *old = item;
+                }
+                core_item
+            })
+            .map_err(|e| e.into());
+        if let Some(core) = self.core.as_mut() {
+            core.display_resolved_completion(view_id, request_id, index, &result);
+        }
+    }
+
+    /// Returns the view whose latest completion request is `request_id`,
+    /// and that request.
+    fn current_completions(
+        &mut self,
+        request_id: usize,
+    ) -> Option<(ViewId, &mut CompletionRequest)> {
+        self.completions
+            .iter_mut()
+            .find(|(_, request)| request.request_id == request_id)
+            .map(|(view_id, request)| (*view_id, request))
+    }
+
+    /// Sends core the diagnostics published for the view, as annotations of
+    /// type "diagnostic". Diagnostics computed against an older version of
+    /// the document are dropped, as their ranges no longer match the text;
+    /// core keeps moving the ones it has with edits until newer ones arrive.
+    fn send_pending_diagnostics(&mut self, view: &mut View<ChunkCache>) {
+        let view_id = view.get_id();
+        let version = match self.view_info.get(&view_id) {
+            Some(view_info) => view_info.version,
+            None => return,
+        };
+        let pending = self
+            .with_language_server_for_view(view, |ls_client| {
+                ls_client.pending_diagnostics.remove(&view_id)
+            })
+            .flatten();
+        let pending = match pending {
+            Some(pending) if pending.version == version => pending,
+            Some(pending) => {
+                debug!("dropping diagnostics for version {} of {}", pending.version, view_id);
+                return;
+            }
+            None => return,
+        };
+        let text = match view.get_document() {
+            Ok(text) => text,
+            Err(err) => {
+                warn!("failed to get document for diagnostics: {:?}", err);
+                return;
+            }
+        };
+        let spans = diagnostic_spans(&text, &pending.diagnostics);
+        let annotation_type = AnnotationType::Custom("diagnostic".into());
+        view.update_annotations(0, text.len(), &spans, &annotation_type);
+    }
+
     fn with_language_server_for_view<F, R>(&mut self, view: &View<ChunkCache>, f: F) -> Option<R>
     where
         F: FnOnce(&mut LanguageServerClient) -> R,
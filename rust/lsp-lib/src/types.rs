@@ -166,4 +166,16 @@ impl From<LanguageResponseError> for RemoteError {
 #[derive(Debug)]
 pub enum LspResponse {
     Hover(Result<Hover, LanguageResponseError>),
+    Completions(Result<CompletionList, LanguageResponseError>),
+    /// The index of a completion in the list it came from, and its details.
+    ResolvedCompletion(usize, Result<CompletionItem, LanguageResponseError>),
+}
+
+/// Diagnostics published by the server for a document, waiting to be sent
+/// to core.
+#[derive(Debug)]
+pub struct PendingDiagnostics {
+    /// The version of the document the diagnostics were computed against.
+    pub version: u64,
+    pub diagnostics: Vec<Diagnostic>,
 }
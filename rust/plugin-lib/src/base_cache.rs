@@ -25,14 +25,21 @@ use xi_trace::trace_block;
 
 use super::{Cache, DataSource, Error};
 
+/// The number of bytes requested from core at a time, unless the plugin
+/// chooses otherwise.
 #[cfg(not(test))]
-const CHUNK_SIZE: usize = 1024 * 1024;
+pub const DEFAULT_CHUNK_SIZE: usize = 1024 * 1024;
 
 #[cfg(test)]
-const CHUNK_SIZE: usize = 16;
+pub const DEFAULT_CHUNK_SIZE: usize = 16;
+
+/// The cache holds at most this many chunks' worth of text, not counting
+/// the current line; when it grows past that, the text furthest from the
+/// most recent fetch is dropped.
+const MAX_CACHED_CHUNKS: usize = 4;
 
 /// A simple cache, holding a single contiguous chunk of the document.
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone)]
 pub struct ChunkCache {
     /// The position of this chunk relative to the tracked document.
     /// All offsets are guaranteed to be valid UTF-8 character boundaries.
@@ -50,11 +57,31 @@ pub struct ChunkCache {
     pub buf_size: usize,
     pub num_lines: usize,
     pub rev: u64,
+    /// The number of bytes requested from the data source at a time.
+    pub chunk_size: usize,
+}
+
+impl Default for ChunkCache {
+    fn default() -> Self {
+        ChunkCache {
+            offset: 0,
+            contents: String::new(),
+            first_line: 0,
+            first_line_offset: 0,
+            line_offsets: Vec::new(),
+            buf_size: 0,
+            num_lines: 0,
+            rev: 0,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+        }
+    }
 }
 
 impl Cache for ChunkCache {
-    fn new(buf_size: usize, rev: u64, num_lines: usize) -> Self {
-        ChunkCache { buf_size, num_lines, rev, ..Default::default() }
+    fn new(buf_size: usize, rev: u64, num_lines: usize, chunk_size: usize) -> Self {
+        // empty chunks would never make progress through the document
+        let chunk_size = chunk_size.max(1);
+        ChunkCache { buf_size, num_lines, rev, chunk_size, ..Default::default() }
     }
 
     /// Returns the line at `line_num` (zero-indexed). Returns an `Err(_)` if
@@ -78,14 +105,14 @@ impl Cache for ChunkCache {
             return Err(Error::BadRequest);
         }
 
-        // if chunk does not include the start of this line, fetch and reset everything
+        // if chunk does not include the start of this line, fetch starting there
         if self.contents.is_empty()
             || line_num < self.first_line
             || (line_num == self.first_line && self.first_line_offset > 0)
             || (line_num > self.first_line + self.line_offsets.len())
         {
-            let resp = source.get_data(line_num, TextUnit::Line, CHUNK_SIZE, self.rev)?;
-            self.reset_chunk(resp);
+            let resp = source.get_data(line_num, TextUnit::Line, self.chunk_size, self.rev)?;
+            self.merge_chunk(resp);
         }
 
         // We now know that the start of this line is contained in self.contents.
@@ -105,7 +132,7 @@ impl Cache for ChunkCache {
             }
 
             let chunk_end = self.offset + self.contents.len();
-            let resp = source.get_data(chunk_end, TextUnit::Utf8, CHUNK_SIZE, self.rev)?;
+            let resp = source.get_data(chunk_end, TextUnit::Utf8, self.chunk_size, self.rev)?;
             self.append_chunk(&resp);
         }
     }
@@ -120,8 +147,8 @@ impl Cache for ChunkCache {
             || start < self.offset
             || start >= self.offset + self.contents.len()
         {
-            let resp = source.get_data(start, TextUnit::Utf8, CHUNK_SIZE, self.rev)?;
-            self.reset_chunk(resp);
+            let resp = source.get_data(start, TextUnit::Utf8, self.chunk_size, self.rev)?;
+            self.merge_chunk(resp);
         }
 
         loop {
@@ -136,7 +163,7 @@ impl Cache for ChunkCache {
             }
 
             let chunk_end = self.offset + self.contents.len();
-            let resp = source.get_data(chunk_end, TextUnit::Utf8, CHUNK_SIZE, self.rev)?;
+            let resp = source.get_data(chunk_end, TextUnit::Utf8, self.chunk_size, self.rev)?;
             self.append_chunk(&resp);
         }
     }
@@ -149,7 +176,7 @@ impl Cache for ChunkCache {
         let mut cur_idx = 0;
         while cur_idx < self.buf_size {
             if self.contents.is_empty() || cur_idx != self.offset {
-                let resp = source.get_data(cur_idx, TextUnit::Utf8, CHUNK_SIZE, self.rev)?;
+                let resp = source.get_data(cur_idx, TextUnit::Utf8, self.chunk_size, self.rev)?;
                 self.reset_chunk(resp);
             }
             result.push_str(&self.contents);
@@ -169,8 +196,8 @@ impl Cache for ChunkCache {
         match self.cached_offset_of_line(line_num) {
             Some(offset) => Ok(offset),
             None => {
-                let resp = source.get_data(line_num, TextUnit::Line, CHUNK_SIZE, self.rev)?;
-                self.reset_chunk(resp);
+                let resp = source.get_data(line_num, TextUnit::Line, self.chunk_size, self.rev)?;
+                self.merge_chunk(resp);
                 self.offset_of_line(source, line_num)
             }
         }
@@ -188,8 +215,8 @@ impl Cache for ChunkCache {
             || offset < self.offset
             || offset > self.offset + self.contents.len()
         {
-            let resp = source.get_data(offset, TextUnit::Utf8, CHUNK_SIZE, self.rev)?;
-            self.reset_chunk(resp);
+            let resp = source.get_data(offset, TextUnit::Utf8, self.chunk_size, self.rev)?;
+            self.merge_chunk(resp);
         }
 
         let rel_offset = offset - self.offset;
@@ -297,11 +324,65 @@ impl ChunkCache {
         self.recalculate_line_offsets();
     }
 
+    /// Adds newly fetched data to the cache. If it overlaps or abuts the
+    /// existing chunk the two are joined, so that moving backwards through
+    /// the document doesn't throw away what was just read; otherwise this
+    /// is the same as `reset_chunk`.
+    fn merge_chunk(&mut self, data: GetDataResponse) {
+        let old_end = self.offset + self.contents.len();
+        let new_end = data.offset + data.chunk.len();
+        if self.contents.is_empty() || new_end < self.offset || data.offset > old_end {
+            self.reset_chunk(data);
+            return;
+        }
+
+        let prepended = data.offset < self.offset;
+        if prepended {
+            let mut contents = data.chunk;
+            if new_end < old_end {
+                contents.push_str(&self.contents[new_end - self.offset..]);
+            }
+            self.contents = contents;
+            self.offset = data.offset;
+            self.first_line = data.first_line;
+            self.first_line_offset = data.first_line_offset;
+        } else if new_end > old_end {
+            self.contents.push_str(&data.chunk[old_end - data.offset..]);
+        } else {
+            return;
+        }
+        self.recalculate_line_offsets();
+        self.evict(prepended);
+    }
+
+    /// Drops text from the end furthest from the most recent fetch until
+    /// the cache is no larger than `MAX_CACHED_CHUNKS` chunks.
+    fn evict(&mut self, keep_start: bool) {
+        let max_len = self.chunk_size * MAX_CACHED_CHUNKS;
+        if self.contents.len() <= max_len {
+            return;
+        }
+        if keep_start {
+            let mut end = max_len;
+            while !self.contents.is_char_boundary(end) {
+                end -= 1;
+            }
+            self.contents.truncate(end);
+            self.line_offsets.retain(|off| *off <= end);
+        } else {
+            let mut start = self.contents.len() - max_len;
+            while !self.contents.is_char_boundary(start) {
+                start += 1;
+            }
+            self.clear_up_to(start);
+        }
+    }
+
     /// Append to the existing cache, leaving existing data in place.
     fn append_chunk(&mut self, data: &GetDataResponse) {
         self.contents.push_str(data.chunk.as_str());
         // this is doing extra work in the case where we're fetching a single
-        // massive (multiple of CHUNK_SIZE) line, but unclear if it's worth optimizing
+        // massive (multiple of chunk_size) line, but unclear if it's worth optimizing
         self.recalculate_line_offsets();
     }
 
@@ -459,18 +540,35 @@ fn newline_offsets(text: &str, storage: &mut Vec<usize>) {
 mod tests {
     use super::*;
     use crate::xi_core::plugin_rpc::GetDataResponse;
+    use std::cell::Cell;
     use xi_rope::delta::Delta;
     use xi_rope::interval::Interval;
     use xi_rope::rope::{LinesMetric, Rope};
 
     struct MockDataSource(Rope);
 
+    /// Counts the requests made of the wrapped source.
+    struct CountingDataSource<'a>(&'a MockDataSource, Cell<usize>);
+
+    impl<'a> DataSource for CountingDataSource<'a> {
+        fn get_data(
+            &self,
+            start: usize,
+            unit: TextUnit,
+            max_size: usize,
+            rev: u64,
+        ) -> Result<GetDataResponse, Error> {
+            self.1.set(self.1.get() + 1);
+            self.0.get_data(start, unit, max_size, rev)
+        }
+    }
+
     impl DataSource for MockDataSource {
         fn get_data(
             &self,
             start: usize,
             unit: TextUnit,
-            _max_size: usize,
+            max_size: usize,
             _rev: u64,
         ) -> Result<GetDataResponse, Error> {
             let offset = unit
@@ -478,7 +576,7 @@ mod tests {
                 .ok_or(Error::Other("unable to resolve offset".into()))?;
             let first_line = self.0.line_of_offset(offset);
             let first_line_offset = offset - self.0.offset_of_line(first_line);
-            let end_off = (offset + CHUNK_SIZE).min(self.0.len());
+            let end_off = (offset + max_size).min(self.0.len());
 
             // not the right error, but okay for this
             if offset > self.0.len() {
@@ -542,6 +640,14 @@ mod tests {
         assert!(c.get_line(&remote_document, 4).is_err());
     }
 
+    #[test]
+    fn zero_chunk_size() {
+        let remote_document = MockDataSource("one\ntwo".into());
+        let mut c = ChunkCache::new(remote_document.0.len(), 0, 2, 0);
+        assert_eq!(c.chunk_size, 1);
+        assert_eq!(c.get_line(&remote_document, 1).ok(), Some("two"));
+    }
+
     #[test]
     fn get_region() {
         let remote_document = MockDataSource("but\nthis big fella\nhas\nFIVE\nlines!".into());
@@ -705,12 +811,12 @@ mod tests {
     #[test]
     fn large_delete() {
         // Issue #1136 on github
-        let large_str = "This string literal is larger than CHUNK_SIZE.";
-        assert!(large_str.len() > CHUNK_SIZE);
+        let large_str = "This string literal is larger than DEFAULT_CHUNK_SIZE.";
+        assert!(large_str.len() > DEFAULT_CHUNK_SIZE);
 
         let data = GetDataResponse {
             // Emulate a cache that has only a part of the buffer.
-            chunk: large_str.split_at(CHUNK_SIZE).0.into(),
+            chunk: large_str.split_at(DEFAULT_CHUNK_SIZE).0.into(),
             offset: 0,
             first_line: 0,
             first_line_offset: 0,
@@ -750,19 +856,20 @@ mod tests {
         assert_eq!(c.offset, 9);
         assert_eq!(c.offset_of_line(&source, 3).unwrap(), 19);
         assert_eq!(c.offset_of_line(&source, 4).unwrap(), 24);
-        // this isn't in the chunk, so should cause a fetch that brings in the line
+        // this isn't in the chunk, so should cause a fetch that brings in the line;
+        // the fetched chunk overlaps what we had, so the two are joined.
         assert_eq!(c.offset_of_line(&source, 0).unwrap(), 0);
         assert_eq!(c.offset, 0);
-        // during tests, we fetch the document in 16 byte chunks
-        assert_eq!(&c.contents, "this\nhas\nfive ni"); // "ce\nsour\nlines!");
+        assert_eq!(&c.contents, "this\nhas\nfive nice\nsour\nlines!");
         assert_eq!(c.offset_of_line(&source, 1).unwrap(), 5);
         assert_eq!(c.offset_of_line(&source, 3).unwrap(), 19);
         assert_eq!(c.offset_of_line(&source, 4).unwrap(), 24);
 
         // reset and fetch the middle, so we have an offset:
-        let _ = c.offset_of_line(&source, 0);
+        c.clear();
+        let _ = c.get_line(&source, 0);
         c.clear_up_to(5);
-        assert_eq!(&c.contents, &"this\nhas\nfive nice\nsour\nlines!"[5..CHUNK_SIZE]);
+        assert_eq!(&c.contents, &"this\nhas\nfive nice\nsour\nlines!"[5..DEFAULT_CHUNK_SIZE]);
         assert_eq!(c.offset, 5);
         assert_eq!(c.first_line, 1);
         //assert_eq!(c.offset_of_line(&source, 2).unwrap(), 9);
@@ -803,11 +910,11 @@ mod tests {
         c.num_lines = source.0.measure::<LinesMetric>() + 1;
         assert_eq!(c.num_lines, 4);
         assert_eq!(c.get_line(&source, 0).unwrap(), "this\n");
-        assert_eq!(c.contents, test_str[..CHUNK_SIZE]);
+        assert_eq!(c.contents, test_str[..DEFAULT_CHUNK_SIZE]);
         assert_eq!(c.get_line(&source, 1).unwrap(), "has one big line in the middle\n");
-        // fetches are always in an interval of CHUNK_SIZE. because getting this line
+        // fetches are always in an interval of DEFAULT_CHUNK_SIZE. because getting this line
         // requres multiple fetches, contents is truncated at the start of the line.
-        assert_eq!(c.contents, test_str[5..CHUNK_SIZE * 3]);
+        assert_eq!(c.contents, test_str[5..DEFAULT_CHUNK_SIZE * 3]);
         assert_eq!(c.get_line(&source, 3).unwrap(), "yay!");
         assert_eq!(c.first_line, 3);
     }
@@ -888,4 +995,50 @@ mod tests {
         assert_eq!(c.get_line(&source, 2).unwrap(), "    let two = \"two\";}");
         assert!(c.get_line(&source, 3).is_err());
     }
+
+    #[test]
+    fn last_line_of_large_document() {
+        let line = "a line of some reasonable length, in a very long document\n";
+        let mut text = line.repeat(10 * 1024 * 1024 / line.len());
+        text.push_str("the end");
+        let source = MockDataSource(text.as_str().into());
+        let num_lines = source.0.measure::<LinesMetric>() + 1;
+        let source = CountingDataSource(&source, Cell::new(0));
+        let mut c = ChunkCache::new(text.len(), 0, num_lines, 64 * 1024);
+
+        assert_eq!(c.get_line(&source, 0).unwrap(), line);
+        assert_eq!(source.1.get(), 1);
+        // jumping to the end is a single fetch, not one per chunk in between
+        assert_eq!(c.get_line(&source, num_lines - 1).unwrap(), "the end");
+        assert_eq!(source.1.get(), 2);
+        // moving backwards fetches once, and keeps what we already had
+        assert_eq!(c.get_line(&source, num_lines - 2).unwrap(), line);
+        assert_eq!(source.1.get(), 3);
+        assert_eq!(c.get_line(&source, num_lines - 1).unwrap(), "the end");
+        assert_eq!(c.get_region(&source, text.len() - 10..).unwrap(), &text[text.len() - 10..]);
+        assert_eq!(source.1.get(), 3);
+    }
+
+    #[test]
+    fn backwards_fetches_are_bounded() {
+        let text: String = (0..50).map(|i| format!("line {:02}\n", i)).collect();
+        let source = MockDataSource(text.as_str().into());
+        let source = CountingDataSource(&source, Cell::new(0));
+        let mut c = ChunkCache::new(text.len(), 0, 51, 16);
+
+        for line_num in (0..50).rev() {
+            let rpcs = source.1.get();
+            assert_eq!(c.get_line(&source, line_num).unwrap(), format!("line {:02}\n", line_num));
+            assert!(c.contents.len() <= 16 * MAX_CACHED_CHUNKS);
+            if line_num < 49 {
+                // the line after this one is still cached
+                assert_eq!(
+                    c.get_line(&source, line_num + 1).unwrap(),
+                    &text[(line_num + 1) * 8..(line_num + 2) * 8]
+                );
+                assert_eq!(source.1.get(), rpcs + 1);
+            }
+        }
+        assert_eq!(c.offset, 0);
+    }
 }
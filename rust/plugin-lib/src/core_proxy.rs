@@ -13,11 +13,15 @@
 // limitations under the License.
 
 //! A proxy for the methods on Core
-use crate::xi_core::plugin_rpc::Hover;
+use crate::xi_core::plugin_rpc::{CompletionItem, CompletionList, Hover};
 use crate::xi_core::plugins::PluginId;
 use crate::xi_core::ViewId;
+use serde_json::Value;
+use std::path::Path;
 use xi_rpc::{RemoteError, RpcCtx, RpcPeer};
 
+use super::Error;
+
 #[derive(Clone)]
 pub struct CoreProxy {
     plugin_id: PluginId,
@@ -78,8 +82,142 @@ impl CoreProxy {
         self.peer.send_rpc_notification("show_hover", &params);
     }
 
+    pub fn display_completions(
+        &mut self,
+        view_id: ViewId,
+        request_id: usize,
+        result: &Result<CompletionList, RemoteError>,
+    ) {
+        let params = json!({
+            "plugin_id": self.plugin_id,
+            "request_id": request_id,
+            "result": result,
+            "view_id": view_id
+        });
+
+        self.peer.send_rpc_notification("show_completions", &params);
+    }
+
+    pub fn display_resolved_completion(
+        &mut self,
+        view_id: ViewId,
+        request_id: usize,
+        index: usize,
+        result: &Result<CompletionItem, RemoteError>,
+    ) {
+        let params = json!({
+            "plugin_id": self.plugin_id,
+            "request_id": request_id,
+            "index": index,
+            "result": result,
+            "view_id": view_id
+        });
+
+        self.peer.send_rpc_notification("show_resolved_completion", &params);
+    }
+
     pub fn schedule_idle(&mut self, view_id: ViewId) {
         let token: usize = view_id.into();
         self.peer.schedule_idle(token);
     }
+
+    /// Asks core to watch `path`, sending `fs_event` notifications with
+    /// `token` when it changes; these arrive at
+    /// `Plugin::custom_core_notification`. `view_id` can be any of the
+    /// plugin's views. Watches end when the plugin exits.
+    pub fn watch_path(
+        &mut self,
+        view_id: ViewId,
+        path: &Path,
+        recursive: bool,
+        token: u64,
+    ) -> Result<(), Error> {
+        let params = json!({
+            "plugin_id": self.plugin_id,
+            "view_id": view_id,
+            "path": path,
+            "recursive": recursive,
+            "token": token,
+        });
+        self.peer.send_rpc_request("watch_path", &params).map(|_| ()).map_err(Error::RpcError)
+    }
+
+    /// Stops the watch started by `watch_path` with `token`.
+    pub fn unwatch_path(&mut self, view_id: ViewId, token: u64) -> Result<(), Error> {
+        let params = json!({
+            "plugin_id": self.plugin_id,
+            "view_id": view_id,
+            "token": token,
+        });
+        self.peer.send_rpc_request("unwatch_path", &params).map(|_| ()).map_err(Error::RpcError)
+    }
+
+    /// Returns the peer used to communicate with core.
+    ///
+    /// This is an escape hatch for plugins that need RPCs this library does
+    /// not model yet. Such RPCs are not part of the stable plugin protocol,
+    /// and may break with any change to core; prefer the typed methods.
+    pub fn raw_peer(&self) -> &RpcPeer {
+        &self.peer
+    }
+
+    /// Sends a notification this library does not model to core. This is
+    /// unstable, as described in `raw_peer`.
+    pub fn send_custom_notification(&self, method: &str, params: &Value) {
+        self.peer.send_rpc_notification(method, params)
+    }
+
+    /// Sends a request this library does not model to core, blocking until
+    /// it is answered. This is unstable, as described in `raw_peer`.
+    pub fn send_custom_request(&self, method: &str, params: &Value) -> Result<Value, Error> {
+        self.peer.send_rpc_request(method, params).map_err(Error::RpcError)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xi_core::PluginPid;
+    use xi_rpc::test_utils::{make_reader, test_channel};
+    use xi_rpc::{RpcCall, RpcLoop};
+
+    #[test]
+    fn custom_request_round_trip() {
+        let (tx, mut rx) = test_channel();
+        let mut rpc_looper = RpcLoop::new(tx);
+        let peer: RpcPeer = Box::new(rpc_looper.get_raw_peer());
+        let plugin_id: PluginPid = serde_json::from_value(json!(1)).unwrap();
+        let proxy = CoreProxy { plugin_id, peer };
+
+        proxy.send_custom_notification("custom_notif", &json!({ "n": 1 }));
+        assert_eq!(rx.expect_rpc("custom_notif").0["params"], json!({ "n": 1 }));
+
+        let request = std::thread::spawn(move || {
+            proxy.send_custom_request("custom_request", &json!({ "words": ["hi"] }))
+        });
+        let sent = rx.expect_rpc("custom_request");
+        assert_eq!(sent.0["params"], json!({ "words": ["hi"] }));
+        let id = sent.get_id().unwrap();
+        let response = json!({ "id": id, "result": { "echo": sent.0["params"] } });
+        let reader = make_reader(response.to_string());
+        assert!(rpc_looper.mainloop(|| reader, &mut NoopHandler).is_ok());
+        let result = request.join().unwrap().unwrap();
+        assert_eq!(result, json!({ "echo": { "words": ["hi"] } }));
+    }
+
+    /// Handler for a loop that only receives responses.
+    struct NoopHandler;
+
+    impl xi_rpc::Handler for NoopHandler {
+        type Notification = RpcCall;
+        type Request = RpcCall;
+        fn handle_notification(&mut self, _ctx: &RpcCtx, _rpc: Self::Notification) {}
+        fn handle_request(
+            &mut self,
+            _ctx: &RpcCtx,
+            rpc: Self::Request,
+        ) -> Result<Value, RemoteError> {
+            Ok(rpc.params)
+        }
+    }
 }
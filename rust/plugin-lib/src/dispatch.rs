@@ -15,10 +15,13 @@
 use std::collections::HashMap;
 use std::path::PathBuf;
 
-use serde_json::{self, Value};
+use serde::de::{self, Deserialize, Deserializer};
+use serde_json::Value;
 
 use crate::core_proxy::CoreProxy;
-use crate::xi_core::plugin_rpc::{HostNotification, HostRequest, PluginBufferInfo, PluginUpdate};
+use crate::xi_core::plugin_rpc::{
+    EditRejection, HostNotification, HostRequest, PluginBufferInfo, PluginUpdate,
+};
 use crate::xi_core::{ConfigTable, LanguageId, PluginPid, ViewId};
 use xi_rpc::{Handler as RpcHandler, RemoteError, RpcCtx};
 use xi_trace::{self, trace, trace_block, trace_block_payload};
@@ -52,6 +55,59 @@ macro_rules! bail_err {
     };
 }
 
+/// A notification from core. Notifications with methods this version of
+/// the library does not know are passed to `Plugin::custom_core_notification`,
+/// so that a newer core can add notifications without breaking older plugins.
+pub enum CoreNotification {
+    Host(HostNotification),
+    Unknown { method: String, params: Value },
+}
+
+/// The methods of `HostNotification`.
+const HOST_NOTIFICATION_METHODS: &[&str] = &[
+    "ping",
+    "initialize",
+    "did_save",
+    "config_changed",
+    "new_buffer",
+    "did_close",
+    "get_hover",
+    "get_completions",
+    "resolve_completion",
+    "shutdown",
+    "tracing_config",
+    "language_changed",
+    "custom_command",
+    "edit_rejected",
+];
+
+/// A notification, before its method is known.
+#[derive(Deserialize)]
+struct RawNotification {
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+impl<'de> Deserialize<'de> for CoreNotification {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let RawNotification { method, params } = RawNotification::deserialize(deserializer)?;
+        if !HOST_NOTIFICATION_METHODS.contains(&method.as_str()) {
+            return Ok(CoreNotification::Unknown { method, params });
+        }
+        let mut notification = json!({ "method": method });
+        if !params.is_null() {
+            notification["params"] = params;
+        }
+        HostNotification::deserialize(notification)
+            .map(CoreNotification::Host)
+            .map_err(de::Error::custom)
+    }
+}
+
 /// Handles raw RPCs from core, updating state and forwarding calls
 /// to the plugin,
 pub struct Dispatcher<'a, P: 'a + Plugin> {
@@ -66,6 +122,12 @@ impl<'a, P: 'a + Plugin> Dispatcher<'a, P> {
         Dispatcher { views: HashMap::new(), pid: None, plugin }
     }
 
+    /// Drops the selections cached by each view, at the start of a call
+    /// into the plugin.
+    fn forget_selections(&mut self) {
+        self.views.values_mut().for_each(View::forget_selections);
+    }
+
     fn do_initialize(
         &mut self,
         ctx: &RpcCtx,
@@ -89,14 +151,10 @@ impl<'a, P: 'a + Plugin> Dispatcher<'a, P> {
         self.plugin.did_save(v, prev_path.as_deref());
     }
 
-    fn do_config_changed(&mut self, view_id: ViewId, changes: &ConfigTable) {
+    fn do_config_changed(&mut self, view_id: ViewId, changes: &ConfigTable, generation: u64) {
         let v = bail!(self.views.get_mut(&view_id), "config_changed", self.pid, view_id);
         self.plugin.config_changed(v, changes);
-        for (key, value) in changes.iter() {
-            v.config_table.insert(key.to_owned(), value.to_owned());
-        }
-        let conf = serde_json::from_value(Value::Object(v.config_table.clone()));
-        v.config = conf.unwrap();
+        v.set_config(changes, generation);
     }
 
     fn do_language_changed(&mut self, view_id: ViewId, new_lang: LanguageId) {
@@ -111,11 +169,17 @@ impl<'a, P: 'a + Plugin> Dispatcher<'a, P> {
         self.plugin.custom_command(v, method, params);
     }
 
+    fn do_edit_rejected(&mut self, view_id: ViewId, rev: u64, reason: EditRejection) {
+        let v = bail!(self.views.get_mut(&view_id), "edit_rejected", self.pid, view_id);
+        self.plugin.edit_rejected(v, rev, reason);
+    }
+
     fn do_new_buffer(&mut self, ctx: &RpcCtx, buffers: Vec<PluginBufferInfo>) {
         let plugin_id = self.pid.unwrap();
+        let chunk_size = self.plugin.chunk_size();
         buffers
             .into_iter()
-            .map(|info| View::new(ctx.get_peer().clone(), plugin_id, info))
+            .map(|info| View::new(ctx.get_peer().clone(), plugin_id, info, chunk_size))
             .for_each(|view| {
                 let mut view = view;
                 self.plugin.new_view(&mut view);
@@ -141,6 +205,16 @@ impl<'a, P: 'a + Plugin> Dispatcher<'a, P> {
         self.plugin.get_hover(v, request_id, position)
     }
 
+    fn do_get_completions(&mut self, view_id: ViewId, request_id: usize, position: usize) {
+        let v = bail!(self.views.get_mut(&view_id), "get_completions", self.pid, view_id);
+        self.plugin.get_completions(v, request_id, position)
+    }
+
+    fn do_resolve_completion(&mut self, view_id: ViewId, request_id: usize, index: usize) {
+        let v = bail!(self.views.get_mut(&view_id), "resolve_completion", self.pid, view_id);
+        self.plugin.resolve_completion(v, request_id, index)
+    }
+
     fn do_tracing_config(&mut self, enabled: bool) {
         if enabled {
             xi_trace::enable_tracing();
@@ -164,9 +238,26 @@ impl<'a, P: 'a + Plugin> Dispatcher<'a, P> {
             undo_group,
             edit_type,
             author,
+            config_generation,
         } = update;
         let v = bail_err!(self.views.get_mut(&view_id), "update", self.pid, view_id);
         v.update(delta.as_ref(), new_len, new_line_count, rev, undo_group);
+        // the config this view holds isn't the one the update was sent
+        // under; fetch the current one, so that the plugin doesn't act on
+        // a mix of old and new settings.
+        if config_generation != v.config_generation {
+            match v.fetch_config_changes() {
+                Ok((changes, generation)) => {
+                    if !changes.is_empty() {
+                        self.plugin.config_changed(v, &changes);
+                    }
+                    v.set_config(&changes, generation);
+                }
+                Err(err) => {
+                    warn!("{:?} failed to fetch config for {:?}: {:?}", self.pid, view_id, err)
+                }
+            }
+        }
         self.plugin.update(v, delta.as_ref(), edit_type, author);
 
         Ok(Value::from(1))
@@ -185,18 +276,27 @@ impl<'a, P: 'a + Plugin> Dispatcher<'a, P> {
 }
 
 impl<'a, P: Plugin> RpcHandler for Dispatcher<'a, P> {
-    type Notification = HostNotification;
+    type Notification = CoreNotification;
     type Request = HostRequest;
 
     fn handle_notification(&mut self, ctx: &RpcCtx, rpc: Self::Notification) {
         use self::HostNotification::*;
         let _t = trace_block("Dispatcher::handle_notif", &["plugin"]);
+        self.forget_selections();
+        let rpc = match rpc {
+            CoreNotification::Host(rpc) => rpc,
+            CoreNotification::Unknown { method, params } => {
+                return self.plugin.custom_core_notification(&method, params);
+            }
+        };
         match rpc {
             Initialize { plugin_id, buffer_info } => {
                 self.do_initialize(ctx, plugin_id, buffer_info)
             }
             DidSave { view_id, path } => self.do_did_save(view_id, path),
-            ConfigChanged { view_id, changes } => self.do_config_changed(view_id, &changes),
+            ConfigChanged { view_id, changes, generation } => {
+                self.do_config_changed(view_id, &changes, generation)
+            }
             NewBuffer { buffer_info } => self.do_new_buffer(ctx, buffer_info),
             DidClose { view_id } => self.do_close(view_id),
             Shutdown(..) => self.do_shutdown(),
@@ -204,10 +304,19 @@ impl<'a, P: Plugin> RpcHandler for Dispatcher<'a, P> {
             GetHover { view_id, request_id, position } => {
                 self.do_get_hover(view_id, request_id, position)
             }
+            GetCompletions { view_id, request_id, position } => {
+                self.do_get_completions(view_id, request_id, position)
+            }
+            ResolveCompletion { view_id, request_id, index } => {
+                self.do_resolve_completion(view_id, request_id, index)
+            }
             LanguageChanged { view_id, new_lang } => self.do_language_changed(view_id, new_lang),
             CustomCommand { view_id, method, params } => {
                 self.do_custom_command(view_id, &method, params)
             }
+            EditRejected { view_id, rev, reason, .. } => {
+                self.do_edit_rejected(view_id, rev, reason)
+            }
             Ping(..) => (),
         }
     }
@@ -215,6 +324,7 @@ impl<'a, P: Plugin> RpcHandler for Dispatcher<'a, P> {
     fn handle_request(&mut self, _ctx: &RpcCtx, rpc: Self::Request) -> Result<Value, RemoteError> {
         use self::HostRequest::*;
         let _t = trace_block("Dispatcher::handle_request", &["plugin"]);
+        self.forget_selections();
         match rpc {
             Update(params) => self.do_update(params),
             CollectTrace(..) => self.do_collect_trace(),
@@ -223,8 +333,243 @@ impl<'a, P: Plugin> RpcHandler for Dispatcher<'a, P> {
 
     fn idle(&mut self, _ctx: &RpcCtx, token: usize) {
         let _t = trace_block_payload("Dispatcher::idle", &["plugin"], format!("token: {}", token));
+        self.forget_selections();
         let view_id: ViewId = token.into();
         let v = bail!(self.views.get_mut(&view_id), "idle", self.pid, view_id);
         self.plugin.idle(v);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::base_cache::ChunkCache;
+    use crate::xi_core::test_helpers;
+    use crate::xi_core::ConfigTable;
+    use std::path::Path;
+    use std::sync::{Arc, Mutex};
+    use std::time::Instant;
+    use xi_rope::RopeDelta;
+    use xi_rpc::test_utils::make_reader;
+    use xi_rpc::{Callback, Error as RpcError, Peer, PeerMetrics, RpcLoop};
+
+    /// Plugin that records the custom notifications it receives.
+    #[derive(Default)]
+    struct RecordingPlugin {
+        notifications: Vec<(String, Value)>,
+        /// The tab size seen by each update, as an auto-indenter would use it.
+        tab_sizes: Vec<usize>,
+        config_changes: Vec<ConfigTable>,
+        /// Whether each update asks for the selections, twice.
+        gets_selections: bool,
+    }
+
+    /// Peer that answers `get_config` requests with a fixed config, and
+    /// `get_selections` with no selections.
+    #[derive(Clone)]
+    struct ConfigPeer {
+        config: ConfigTable,
+        generation: u64,
+        requests: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Peer for ConfigPeer {
+        fn box_clone(&self) -> Box<dyn Peer> {
+            Box::new(self.clone())
+        }
+        fn send_rpc_notification(&self, _method: &str, _params: &Value) {}
+        fn send_rpc_request_async(&self, _method: &str, _params: &Value, _f: Box<dyn Callback>) {}
+        fn send_rpc_request(&self, method: &str, _params: &Value) -> Result<Value, RpcError> {
+            self.requests.lock().unwrap().push(method.to_owned());
+            if method == "get_selections" {
+                return Ok(json!({ "rev": 0, "selections": [] }));
+            }
+            Ok(json!({ "generation": self.generation, "config": self.config }))
+        }
+        fn request_is_pending(&self) -> bool {
+            false
+        }
+        fn metrics(&self) -> PeerMetrics {
+            PeerMetrics::default()
+        }
+        fn schedule_idle(&self, _token: usize) {}
+        fn schedule_timer(&self, _time: Instant, _token: usize) {}
+    }
+
+    impl Plugin for RecordingPlugin {
+        type Cache = ChunkCache;
+
+        fn update(
+            &mut self,
+            view: &mut View<Self::Cache>,
+            _delta: Option<&RopeDelta>,
+            _edit_type: String,
+            _author: String,
+        ) {
+            self.tab_sizes.push(view.get_config().tab_size);
+            if self.gets_selections {
+                view.get_selections().unwrap();
+                view.get_selections().unwrap();
+            }
+        }
+        fn did_save(&mut self, _view: &mut View<Self::Cache>, _old_path: Option<&Path>) {}
+        fn did_close(&mut self, _view: &View<Self::Cache>) {}
+        fn new_view(&mut self, _view: &mut View<Self::Cache>) {}
+        fn config_changed(&mut self, _view: &mut View<Self::Cache>, changes: &ConfigTable) {
+            self.config_changes.push(changes.clone());
+        }
+
+        fn custom_core_notification(&mut self, method: &str, params: Value) {
+            self.notifications.push((method.to_owned(), params));
+        }
+    }
+
+    #[test]
+    fn unknown_notifications_reach_plugin() {
+        let mut plugin = RecordingPlugin::default();
+        let input = concat!(
+            r#"{"method": "ping", "params": {}}"#,
+            "\n",
+            r#"{"method": "from_the_future", "params": {"words": ["one", 2]}}"#,
+            "\n",
+            r#"{"method": "no_params"}"#,
+        );
+        let mut rpc_looper = RpcLoop::new(std::io::sink());
+        let reader = make_reader(input);
+        assert!(rpc_looper.mainloop(|| reader, &mut Dispatcher::new(&mut plugin)).is_ok());
+        assert_eq!(
+            plugin.notifications,
+            vec![
+                ("from_the_future".to_owned(), json!({ "words": ["one", 2] })),
+                ("no_params".to_owned(), Value::Null),
+            ]
+        );
+    }
+
+    #[test]
+    fn malformed_notifications_are_errors() {
+        let mut plugin = RecordingPlugin::default();
+        let input = r#"{"method": "did_save", "params": {"path": 5}}"#;
+        let mut rpc_looper = RpcLoop::new(std::io::sink());
+        let reader = make_reader(input);
+        assert!(rpc_looper.mainloop(|| reader, &mut Dispatcher::new(&mut plugin)).is_err());
+        assert!(plugin.notifications.is_empty());
+    }
+
+    #[test]
+    fn known_methods_are_host_notifications() {
+        let view_id = json!(test_helpers::new_view_id(1));
+        let notifications = [
+            json!({ "method": "ping", "params": {} }),
+            json!({ "method": "did_close", "params": { "view_id": view_id } }),
+            json!({ "method": "shutdown", "params": {} }),
+            json!({ "method": "tracing_config", "params": { "enabled": true } }),
+        ];
+        for notification in notifications.iter() {
+            let parsed = CoreNotification::deserialize(notification).unwrap();
+            assert!(matches!(parsed, CoreNotification::Host(_)), "{}", notification);
+        }
+        let parsed = CoreNotification::deserialize(json!({ "method": "new_method" })).unwrap();
+        assert!(matches!(parsed, CoreNotification::Unknown { .. }));
+    }
+
+    #[test]
+    fn update_under_newer_config_fetches_it() {
+        let view_id = test_helpers::new_view_id(1);
+        let mut config = test_helpers::default_buffer_config();
+        config.insert("tab_size".into(), json!(4));
+        let info = PluginBufferInfo::new(
+            test_helpers::new_buffer_id(1),
+            &[view_id],
+            0,
+            0,
+            1,
+            None,
+            "Plain Text".into(),
+            config.clone(),
+            0,
+        );
+        // core has since changed the tab size, in generation 1
+        config.insert("tab_size".into(), json!(2));
+        let peer = ConfigPeer { config, generation: 1, requests: Arc::default() };
+
+        let mut plugin = RecordingPlugin::default();
+        let mut dispatcher = Dispatcher::new(&mut plugin);
+        let pid: PluginPid = serde_json::from_value(json!(1)).unwrap();
+        let view: View<ChunkCache> = View::new(Box::new(peer.clone()), pid, info, 1024);
+        assert_eq!(view.get_config_generation(), 0);
+        dispatcher.views.insert(view_id, view);
+
+        // an update sent after the change, which this plugin hasn't been told of
+        let update = |rev| {
+            PluginUpdate::new(
+                view_id,
+                rev,
+                None::<RopeDelta>,
+                0,
+                1,
+                None,
+                "insert".into(),
+                "core".into(),
+                1,
+            )
+        };
+        assert!(dispatcher.do_update(update(1)).is_ok());
+        assert_eq!(dispatcher.views[&view_id].get_config_generation(), 1);
+        // the config is only fetched once per generation
+        assert!(dispatcher.do_update(update(2)).is_ok());
+
+        assert_eq!(plugin.tab_sizes, vec![2, 2]);
+        assert_eq!(
+            plugin.config_changes,
+            vec![json!({"tab_size": 2}).as_object().unwrap().clone()]
+        );
+        assert_eq!(*peer.requests.lock().unwrap(), vec!["get_config".to_owned()]);
+    }
+
+    #[test]
+    fn selections_are_cached_within_one_call() {
+        let view_id = test_helpers::new_view_id(1);
+        let config = test_helpers::default_buffer_config();
+        let info = PluginBufferInfo::new(
+            test_helpers::new_buffer_id(1),
+            &[view_id],
+            0,
+            0,
+            1,
+            None,
+            "Plain Text".into(),
+            config.clone(),
+            0,
+        );
+        let peer = ConfigPeer { config, generation: 0, requests: Arc::default() };
+
+        let mut plugin = RecordingPlugin { gets_selections: true, ..Default::default() };
+        let mut dispatcher = Dispatcher::new(&mut plugin);
+        let pid: PluginPid = serde_json::from_value(json!(1)).unwrap();
+        let view: View<ChunkCache> = View::new(Box::new(peer.clone()), pid, info, 1024);
+        dispatcher.views.insert(view_id, view);
+
+        // the selection may move without a new revision, between updates
+        let update = |id| {
+            let update = PluginUpdate::new(
+                view_id,
+                0,
+                None::<RopeDelta>,
+                0,
+                1,
+                None,
+                "insert".into(),
+                "core".into(),
+                0,
+            );
+            json!({ "id": id, "method": "update", "params": update }).to_string()
+        };
+        let input = format!("{}\n{}\n", update(0), update(1));
+        let mut rpc_looper = RpcLoop::new(std::io::sink());
+        let reader = make_reader(input);
+        assert!(rpc_looper.mainloop(|| reader, &mut dispatcher).is_ok());
+        let requests = peer.requests.lock().unwrap();
+        assert_eq!(*requests, vec!["get_selections".to_owned(), "get_selections".to_owned()]);
+    }
+}
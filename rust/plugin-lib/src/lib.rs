@@ -19,10 +19,11 @@ extern crate xi_rpc;
 extern crate xi_trace;
 #[macro_use]
 extern crate serde_json;
-extern crate bytecount;
 extern crate memchr;
 extern crate rand;
 extern crate serde;
+#[macro_use]
+extern crate serde_derive;
 
 #[macro_use]
 extern crate log;
@@ -45,11 +46,13 @@ use xi_rpc::{ReadError, RpcLoop};
 
 use self::dispatch::Dispatcher;
 
-pub use crate::base_cache::ChunkCache;
+pub use crate::base_cache::{ChunkCache, DEFAULT_CHUNK_SIZE};
 pub use crate::core_proxy::CoreProxy;
 pub use crate::state_cache::StateCache;
 pub use crate::view::View;
-pub use crate::xi_core::plugin_rpc::{Hover, Range};
+pub use crate::xi_core::plugin_rpc::{
+    CompletionItem, CompletionList, EditRejection, Hover, PluginSelRegion as SelRegion, Range,
+};
 
 /// Abstracts getting data from the peer. Mainly exists for mocking in tests.
 pub trait DataSource {
@@ -73,8 +76,11 @@ pub trait DataSource {
 /// [`StateCache`]: ../state_cache/struct.StateCache.html
 pub trait Cache {
     /// Create a new instance of this type; instances are created automatically
-    /// as relevant views are added.
-    fn new(buf_size: usize, rev: u64, num_lines: usize) -> Self;
+    /// as relevant views are added. `chunk_size` is the number of bytes to
+    /// request from core at a time; see [`Plugin::chunk_size`].
+    ///
+    /// [`Plugin::chunk_size`]: trait.Plugin.html#method.chunk_size
+    fn new(buf_size: usize, rev: u64, num_lines: usize, chunk_size: usize) -> Self;
     /// Returns the line at `line_num` (zero-indexed). Returns an `Err(_)` if
     /// there is a problem connecting to the peer, or if the requested line
     /// is out of bounds.
@@ -178,6 +184,18 @@ pub trait Plugin {
     #[allow(unused_variables)]
     fn custom_command(&mut self, view: &mut View<Self::Cache>, method: &str, params: Value) {}
 
+    /// Called when core rejects an edit sent with `View::edit_checked`.
+    /// `rev` is the revision the edit was computed against.
+    #[allow(unused_variables)]
+    fn edit_rejected(&mut self, view: &mut View<Self::Cache>, rev: u64, reason: EditRejection) {}
+
+    /// Called with a notification from core that this library does not
+    /// recognize, such as one added in a newer version of core. Together with
+    /// `CoreProxy::send_custom_notification`, this allows extending the
+    /// protocol without updating this library first.
+    #[allow(unused_variables)]
+    fn custom_core_notification(&mut self, method: &str, params: Value) {}
+
     /// Called when the runloop is idle, if the plugin has previously
     /// asked to be scheduled via `View::schedule_idle()`. Plugins that
     /// are doing things like full document analysis can use this mechanism
@@ -185,10 +203,41 @@ pub trait Plugin {
     #[allow(unused_variables)]
     fn idle(&mut self, view: &mut View<Self::Cache>) {}
 
+    /// The number of bytes each view's cache requests from core at a time.
+    /// Plugins that only look at a few lines around the cursor may want a
+    /// smaller value. Values below 1 are treated as 1.
+    fn chunk_size(&self) -> usize {
+        DEFAULT_CHUNK_SIZE
+    }
+
     /// Language Plugins specific methods
 
     #[allow(unused_variables)]
     fn get_hover(&mut self, view: &mut View<Self::Cache>, request_id: usize, position: usize) {}
+
+    /// Called when the client asks for completions at `position`. Results
+    /// are sent with `CoreProxy::display_completions`, tagged with
+    /// `request_id`; a newer request for the same view supersedes it.
+    #[allow(unused_variables)]
+    fn get_completions(
+        &mut self,
+        view: &mut View<Self::Cache>,
+        request_id: usize,
+        position: usize,
+    ) {
+    }
+
+    /// Called when the client asks for the details of the item at `index`
+    /// of the completions sent for `request_id`. Results are sent with
+    /// `CoreProxy::display_resolved_completion`.
+    #[allow(unused_variables)]
+    fn resolve_completion(
+        &mut self,
+        view: &mut View<Self::Cache>,
+        request_id: usize,
+        index: usize,
+    ) {
+    }
 }
 
 #[derive(Debug)]
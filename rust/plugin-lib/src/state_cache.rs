@@ -17,7 +17,7 @@
 use rand::{thread_rng, Rng};
 
 use xi_rope::interval::IntervalBounds;
-use xi_rope::{LinesMetric, RopeDelta};
+use xi_rope::RopeDelta;
 use xi_trace::trace_block;
 
 use super::{Cache, DataSource, Error, View};
@@ -44,9 +44,9 @@ pub struct StateCache<S> {
 }
 
 impl<S: Clone + Default> Cache for StateCache<S> {
-    fn new(buf_size: usize, rev: u64, num_lines: usize) -> Self {
+    fn new(buf_size: usize, rev: u64, num_lines: usize, chunk_size: usize) -> Self {
         StateCache {
-            buf_cache: ChunkCache::new(buf_size, rev, num_lines),
+            buf_cache: ChunkCache::new(buf_size, rev, num_lines, chunk_size),
             state_cache: Vec::new(),
             frontier: Vec::new(),
         }
@@ -89,7 +89,7 @@ impl<S: Clone + Default> Cache for StateCache<S> {
         let _t = trace_block("StateCache::update", &["plugin"]);
 
         if let Some(delta) = delta {
-            self.update_line_cache(delta);
+            self.update_line_cache(delta, num_lines);
         } else {
             // if there's no delta (very large edit) we blow away everything
             self.clear_to_start(0);
@@ -246,81 +246,50 @@ impl<S: Clone + Default> StateCache<S> {
     }
 
     /// Updates the line cache to reflect this delta.
-    fn update_line_cache(&mut self, delta: &RopeDelta) {
+    ///
+    /// States only depend on the text before their line, so entries before
+    /// the edited region are kept as they are, and entries after it are
+    /// shifted by the change in length and line count. Only the entries
+    /// inside the edited region are dropped.
+    fn update_line_cache(&mut self, delta: &RopeDelta, num_lines: usize) {
         let (iv, new_len) = delta.summary();
-        if let Some(n) = delta.as_simple_insert() {
-            assert_eq!(iv.size(), 0);
-            assert_eq!(new_len, n.len());
-
-            let newline_count = n.measure::<LinesMetric>();
-            self.line_cache_simple_insert(iv.start(), new_len, newline_count);
-        } else if delta.is_simple_delete() {
-            assert_eq!(new_len, 0);
-            self.line_cache_simple_delete(iv.start(), iv.end())
-        } else {
-            self.clear_to_start(iv.start());
-        }
-    }
+        let len_delta = new_len as isize - iv.size() as isize;
+        let nl_count_delta = num_lines as isize - self.buf_cache.num_lines as isize;
 
-    fn line_cache_simple_insert(&mut self, start: usize, new_len: usize, newline_num: usize) {
-        let ix = match self.find_offset(start) {
+        let ix = match self.find_offset(iv.start()) {
             Ok(ix) => ix + 1,
             Err(ix) => ix,
         };
-
+        let edited = self.state_cache[ix..].iter().take_while(|e| e.offset <= iv.end()).count();
+        self.state_cache.drain(ix..ix + edited);
         for entry in &mut self.state_cache[ix..] {
-            entry.line_num += newline_num;
-            entry.offset += new_len;
-        }
-        self.patchup_frontier(ix, newline_num as isize);
-    }
-
-    fn line_cache_simple_delete(&mut self, start: usize, end: usize) {
-        let off = self.buf_cache.offset;
-        let chunk_end = off + self.buf_cache.contents.len();
-        if start >= off && end <= chunk_end {
-            let del_newline_num = count_newlines(&self.buf_cache.contents[start - off..end - off]);
-            // delete all entries that overlap the deleted range
-            let ix = match self.find_offset(start) {
-                Ok(ix) => ix + 1,
-                Err(ix) => ix,
-            };
-            while ix < self.state_cache.len() && self.state_cache[ix].offset <= end {
-                self.state_cache.remove(ix);
-            }
-            for entry in &mut self.state_cache[ix..] {
-                entry.line_num -= del_newline_num;
-                entry.offset -= end - start;
-            }
-            self.patchup_frontier(ix, -(del_newline_num as isize));
-        } else {
-            // if this region isn't in our chunk we can't correctly adjust newlines
-            self.clear_to_start(start);
+            entry.line_num = (entry.line_num as isize + nl_count_delta) as usize;
+            entry.offset = (entry.offset as isize + len_delta) as usize;
         }
+        self.patchup_frontier(ix, nl_count_delta);
     }
 
+    /// Moves the frontier back to the last entry before `cache_idx`, which
+    /// is where the edit starts. Pending work from the first shifted entry
+    /// on is shifted by `nl_count_delta`; pending work in between is redone
+    /// anyway, as states are recomputed until they converge.
     fn patchup_frontier(&mut self, cache_idx: usize, nl_count_delta: isize) {
         let line_num = match cache_idx {
             0 => 0,
             ix => self.state_cache[ix - 1].line_num,
         };
-        let mut new_frontier = Vec::new();
-        let mut need_push = true;
-        for old_ln in &self.frontier {
-            if *old_ln < line_num {
-                new_frontier.push(*old_ln);
-            } else if need_push {
-                new_frontier.push(line_num);
-                need_push = false;
-                if let Some(entry) = self.state_cache.get(cache_idx) {
-                    if *old_ln >= entry.line_num {
-                        new_frontier.push(old_ln.wrapping_add(nl_count_delta as usize));
-                    }
-                }
-            }
-        }
-        if need_push {
-            new_frontier.push(line_num);
+        let mut new_frontier: Vec<usize> =
+            self.frontier.iter().cloned().filter(|ln| *ln < line_num).collect();
+        new_frontier.push(line_num);
+        if let Some(entry) = self.state_cache.get(cache_idx) {
+            let old_line_num = (entry.line_num as isize - nl_count_delta) as usize;
+            let shifted = self
+                .frontier
+                .iter()
+                .filter(|ln| **ln >= old_line_num)
+                .map(|ln| (*ln as isize + nl_count_delta) as usize)
+                .filter(|ln| *ln > line_num);
+            new_frontier.extend(shifted);
         }
         self.frontier = new_frontier;
     }
@@ -397,6 +366,86 @@ impl<S: Default + Clone> View<StateCache<S>> {
     }
 }
 
-fn count_newlines(s: &str) -> usize {
-    bytecount::count(s.as_bytes(), b'\n')
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::base_cache::DEFAULT_CHUNK_SIZE;
+    use crate::xi_core::plugin_rpc::{GetDataResponse, TextUnit};
+    use xi_rope::delta::Delta;
+    use xi_rope::interval::Interval;
+    use xi_rope::rope::{LinesMetric, Rope};
+
+    struct MockDataSource(Rope);
+
+    impl DataSource for MockDataSource {
+        fn get_data(
+            &self,
+            start: usize,
+            unit: TextUnit,
+            max_size: usize,
+            _rev: u64,
+        ) -> Result<GetDataResponse, Error> {
+            let offset = unit
+                .resolve_offset(&self.0, start)
+                .ok_or(Error::Other("unable to resolve offset".into()))?;
+            let first_line = self.0.line_of_offset(offset);
+            let first_line_offset = offset - self.0.offset_of_line(first_line);
+            let end_off = (offset + max_size).min(self.0.len());
+            let chunk = self.0.slice_to_cow(offset..end_off).into_owned();
+            Ok(GetDataResponse { chunk, offset, first_line, first_line_offset })
+        }
+    }
+
+    /// Runs the frontier to completion the way a highlighting plugin would,
+    /// with the bracket depth as the state. Returns the number of lines computed.
+    fn run_frontier(cache: &mut StateCache<usize>, source: &MockDataSource) -> usize {
+        let mut computed = 0;
+        while let Some(line_num) = cache.get_frontier() {
+            let (line_num, _, state) = cache.get_prev(line_num);
+            let line = cache.get_line(source, line_num).map(String::from).unwrap_or_default();
+            computed += 1;
+            let new_state = state + line.matches('{').count() - line.matches('}').count();
+            if line.ends_with('\n') && cache.get(line_num + 1) != Some(&new_state) {
+                cache.set(source, line_num + 1, new_state);
+                cache.update_frontier(line_num + 1);
+            } else {
+                cache.close_frontier();
+            }
+        }
+        computed
+    }
+
+    fn edit(cache: &mut StateCache<usize>, source: &mut MockDataSource, iv: Interval, text: &str) {
+        let delta = Delta::simple_edit(iv, text.into(), source.0.len());
+        source.0 = delta.apply(&source.0);
+        let num_lines = source.0.measure::<LinesMetric>() + 1;
+        cache.update(Some(&delta), source.0.len(), num_lines, 1);
+    }
+
+    #[test]
+    fn edit_keeps_states_outside_edited_lines() {
+        let text: String = (0..10_000).map(|i| format!("line {} {{}}\n", i)).collect();
+        let mut source = MockDataSource(text.into());
+        let num_lines = source.0.measure::<LinesMetric>() + 1;
+        let mut cache: StateCache<usize> =
+            StateCache::new(source.0.len(), 0, num_lines, DEFAULT_CHUNK_SIZE);
+        cache.reset();
+        assert_eq!(run_frontier(&mut cache, &source), 10_001);
+
+        // replacing one character is neither a simple insert nor a simple delete
+        let offset = source.0.offset_of_line(5000);
+        edit(&mut cache, &mut source, Interval::new(offset, offset + 1), "L");
+        let computed = run_frontier(&mut cache, &source);
+        assert!(computed < 100, "recomputed {} lines", computed);
+
+        // adding lines shifts the entries after the edit
+        let offset = source.0.offset_of_line(3000);
+        edit(&mut cache, &mut source, Interval::new(offset, offset + 4), "a\nb\nc");
+        let computed = run_frontier(&mut cache, &source);
+        assert!(computed < 100, "recomputed {} lines", computed);
+        for entry in &cache.state_cache {
+            assert_eq!(source.0.offset_of_line(entry.line_num), entry.offset);
+            assert_eq!(entry.user_state, Some(0));
+        }
+    }
 }
@@ -17,7 +17,8 @@ use serde_json::{self, Value};
 use std::path::{Path, PathBuf};
 
 use crate::xi_core::plugin_rpc::{
-    GetDataResponse, PluginBufferInfo, PluginEdit, ScopeSpan, TextUnit,
+    GetConfigResponse, GetDataResponse, GetSelectionsResponse, PluginBufferInfo, PluginEdit,
+    ScopeSpan, TextUnit,
 };
 use crate::xi_core::{BufferConfig, ConfigTable, LanguageId, PluginPid, ViewId};
 use xi_core_lib::annotations::AnnotationType;
@@ -28,7 +29,7 @@ use xi_trace::trace_block;
 
 use xi_rpc::RpcPeer;
 
-use super::{Cache, DataSource, Error};
+use super::{Cache, DataSource, Error, SelRegion};
 
 /// A type that acts as a proxy for a remote view. Provides access to
 /// a document cache, and implements various methods for querying and modifying
@@ -39,6 +40,7 @@ pub struct View<C> {
     pub(crate) path: Option<PathBuf>,
     pub(crate) config: BufferConfig,
     pub(crate) config_table: ConfigTable,
+    pub(crate) config_generation: u64,
     plugin_id: PluginPid,
     // TODO: this is only public to avoid changing the syntect impl
     // this should go away with async edits
@@ -47,20 +49,38 @@ pub struct View<C> {
     buf_size: usize,
     pub(crate) view_id: ViewId,
     pub(crate) language_id: LanguageId,
+    /// The selections last fetched from core, and the revision they refer to.
+    selections: Option<(u64, Vec<SelRegion>)>,
 }
 
 impl<C: Cache> View<C> {
-    pub(crate) fn new(peer: RpcPeer, plugin_id: PluginPid, info: PluginBufferInfo) -> Self {
-        let PluginBufferInfo { views, rev, path, config, buf_size, nb_lines, syntax, .. } = info;
+    pub(crate) fn new(
+        peer: RpcPeer,
+        plugin_id: PluginPid,
+        info: PluginBufferInfo,
+        chunk_size: usize,
+    ) -> Self {
+        let PluginBufferInfo {
+            views,
+            rev,
+            path,
+            config,
+            config_generation,
+            buf_size,
+            nb_lines,
+            syntax,
+            ..
+        } = info;
 
         assert_eq!(views.len(), 1, "assuming single view");
         let view_id = views.first().unwrap().to_owned();
         let path = path.map(PathBuf::from);
         View {
-            cache: C::new(buf_size, rev, nb_lines),
+            cache: C::new(buf_size, rev, nb_lines, chunk_size),
             peer,
             config_table: config.clone(),
             config: serde_json::from_value(Value::Object(config)).unwrap(),
+            config_generation,
             path,
             plugin_id,
             view_id,
@@ -68,6 +88,7 @@ impl<C: Cache> View<C> {
             undo_group: None,
             buf_size,
             language_id: syntax,
+            selections: None,
         }
     }
 
@@ -80,11 +101,48 @@ impl<C: Cache> View<C> {
         undo_group: Option<usize>,
     ) {
         self.cache.update(delta, new_len, new_num_lines, rev);
+        if self.selections.as_ref().map_or(false, |(sel_rev, _)| *sel_rev != rev) {
+            self.selections = None;
+        }
         self.rev = rev;
         self.undo_group = undo_group;
         self.buf_size = new_len;
     }
 
+    /// Merges `changes` into the view's config.
+    pub(crate) fn set_config(&mut self, changes: &ConfigTable, generation: u64) {
+        for (key, value) in changes.iter() {
+            self.config_table.insert(key.to_owned(), value.to_owned());
+        }
+        let conf = serde_json::from_value(Value::Object(self.config_table.clone()));
+        self.config = conf.unwrap();
+        self.config_generation = generation;
+    }
+
+    /// Drops the cached selections. Core doesn't tell plugins when only the
+    /// selection changes, so the cache can't outlive a call into the plugin.
+    pub(crate) fn forget_selections(&mut self) {
+        self.selections = None;
+    }
+
+    /// Fetches the buffer's current config from core, returning the keys
+    /// whose values differ from the config this view holds, and its
+    /// generation.
+    pub(crate) fn fetch_config_changes(&self) -> Result<(ConfigTable, u64), Error> {
+        let params = json!({
+            "plugin_id": self.plugin_id,
+            "view_id": self.view_id,
+        });
+        let result = self.peer.send_rpc_request("get_config", &params).map_err(Error::RpcError)?;
+        let GetConfigResponse { generation, config } =
+            GetConfigResponse::deserialize(result).map_err(|_| Error::WrongReturnType)?;
+        let changes = config
+            .into_iter()
+            .filter(|(key, value)| self.config_table.get(key) != Some(value))
+            .collect();
+        Ok((changes, generation))
+    }
+
     pub(crate) fn set_language(&mut self, new_language_id: LanguageId) {
         self.language_id = new_language_id;
     }
@@ -116,6 +174,17 @@ impl<C: Cache> View<C> {
         &self.config
     }
 
+    /// Returns the generation of the config returned by `get_config`. Core
+    /// increments it each time the buffer's config changes.
+    ///
+    /// Before an update reaches `Plugin::update`, the view's config is
+    /// brought up to the generation the update was sent under. A plugin
+    /// that spreads work over several calls can note the generation when
+    /// it starts, and start over if it has changed.
+    pub fn get_config_generation(&self) -> u64 {
+        self.config_generation
+    }
+
     pub fn get_cache(&mut self) -> &mut C {
         &mut self.cache
     }
@@ -150,6 +219,26 @@ impl<C: Cache> View<C> {
         self.cache.line_of_offset(&ctx, offset)
     }
 
+    /// Returns the regions of the user's selection in this view, in order.
+    ///
+    /// The selections are fetched from core, and cached until the plugin
+    /// returns from the current notification, request or idle callback.
+    pub fn get_selections(&mut self) -> Result<Vec<SelRegion>, Error> {
+        if let Some((_, selections)) = self.selections.as_ref() {
+            return Ok(selections.clone());
+        }
+        let params = json!({
+            "plugin_id": self.plugin_id,
+            "view_id": self.view_id,
+        });
+        let result =
+            self.peer.send_rpc_request("get_selections", &params).map_err(Error::RpcError)?;
+        let GetSelectionsResponse { rev, selections } =
+            GetSelectionsResponse::deserialize(result).map_err(|_| Error::WrongReturnType)?;
+        self.selections = Some((rev, selections.clone()));
+        Ok(selections)
+    }
+
     pub fn add_scopes(&self, scopes: &[Vec<String>]) {
         let params = json!({
             "plugin_id": self.plugin_id,
@@ -168,7 +257,39 @@ impl<C: Cache> View<C> {
         author: String,
     ) {
         let undo_group = if new_undo_group { None } else { self.undo_group };
-        let edit = PluginEdit { rev: self.rev, delta, priority, after_cursor, undo_group, author };
+        let edit = PluginEdit {
+            rev: self.rev,
+            delta,
+            priority,
+            after_cursor,
+            undo_group,
+            author,
+            checked: false,
+        };
+        self.send_edit(edit);
+    }
+
+    /// Like `edit`, but with `delta` computed against `rev`, which may be
+    /// older than the view's current revision.
+    ///
+    /// Core transforms the edit over any concurrent changes, or rejects it if
+    /// those changes touched the region it replaces. Rejections are delivered
+    /// to `Plugin::edit_rejected`.
+    pub fn edit_checked(
+        &self,
+        delta: RopeDelta,
+        priority: u64,
+        after_cursor: bool,
+        undo_group: Option<usize>,
+        author: String,
+        rev: u64,
+    ) {
+        let edit =
+            PluginEdit { rev, delta, priority, after_cursor, undo_group, author, checked: true };
+        self.send_edit(edit);
+    }
+
+    fn send_edit(&self, edit: PluginEdit) {
         let params = json!({
             "plugin_id": self.plugin_id,
             "view_id": self.view_id,
@@ -0,0 +1,56 @@
+// Copyright 2020 The xi-editor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+#![feature(test)]
+
+extern crate test;
+extern crate xi_rope;
+
+use test::Bencher;
+use xi_rope::breaks::{BreakBuilder, Breaks};
+
+/// Offsets of a break every 20 units, like a 500k-line document wrapped
+/// into a few lines per logical line.
+fn break_offsets() -> Vec<usize> {
+    (1..=2_000_000).map(|i| i * 20).collect()
+}
+
+#[bench]
+fn build_breaks_incremental(b: &mut Bencher) {
+    let offsets = break_offsets();
+    let len = *offsets.last().unwrap();
+    b.iter(|| {
+        let mut breaks = Breaks::new_no_break(0);
+        let mut last = 0;
+        for chunk in offsets.chunks(500) {
+            let mut builder = BreakBuilder::new();
+            for &offset in chunk {
+                builder.add_break(offset - last);
+                last = offset;
+            }
+            let end = breaks.len();
+            breaks.edit(end..end, builder.build());
+        }
+        assert_eq!(breaks.len(), len);
+    })
+}
+
+#[bench]
+fn build_breaks_from_iter(b: &mut Bencher) {
+    let offsets = break_offsets();
+    let len = *offsets.last().unwrap();
+    b.iter(|| {
+        let breaks = Breaks::from_iter(len, offsets.iter().cloned());
+        assert_eq!(breaks.len(), len);
+    })
+}
@@ -18,7 +18,7 @@ extern crate xi_rope;
 
 use test::Bencher;
 use xi_rope::compare;
-use xi_rope::diff::{Diff, LineHashDiff};
+use xi_rope::diff::{Diff, DiffStrategy, LineHashDiff, RopeDiffBuilder};
 use xi_rope::rope::{Rope, RopeDelta};
 
 static EDITOR_STR: &str = include_str!("../../core-lib/src/editor.rs");
@@ -198,3 +198,40 @@ fn simple_deletion(b: &mut Bencher) {
     let _result = delta.unwrap().apply(&two);
     assert_eq!(String::from(_result), String::from(&one));
 }
+
+/// A multi-megabyte document, and a copy with a few lines edited throughout,
+/// as when reloading a large file changed on disk.
+fn make_large_reload_data() -> (Rope, Rope) {
+    let one = [EDITOR_STR, VIEW_STR, INTERVAL_STR, BREAKS_STR].concat().repeat(20);
+    let mut lines = one.lines().collect::<Vec<_>>();
+    for i in (0..lines.len()).step_by(997) {
+        lines[i] = "an edited line, replacing the original one";
+    }
+    let two = lines.join("\n");
+    (one.into(), two.into())
+}
+
+#[bench]
+fn hash_diff_large_reload(b: &mut Bencher) {
+    let (one, two) = make_large_reload_data();
+    let mut delta: Option<RopeDelta> = None;
+    b.iter(|| {
+        delta = Some(LineHashDiff::compute_delta(&one, &two));
+    });
+
+    let _result = delta.unwrap().apply(&one);
+    assert_eq!(String::from(_result), String::from(&two));
+}
+
+#[bench]
+fn line_hashed_diff_large_reload(b: &mut Bencher) {
+    let (one, two) = make_large_reload_data();
+    let builder = RopeDiffBuilder::new().strategy(DiffStrategy::LineHashed { min_size: 8 });
+    let mut delta: Option<RopeDelta> = None;
+    b.iter(|| {
+        delta = Some(builder.compute_delta(&one, &two));
+    });
+
+    let _result = delta.unwrap().apply(&one);
+    assert_eq!(String::from(_result), String::from(&two));
+}
@@ -15,7 +15,7 @@
 //! A module for representing a set of breaks, typically used for
 //! storing the result of line breaking.
 
-use crate::interval::Interval;
+use crate::interval::{Interval, IntervalBounds};
 use crate::tree::{DefaultMetric, Leaf, Metric, Node, NodeInfo, TreeBuilder};
 use std::cmp::min;
 use std::mem;
@@ -205,6 +205,47 @@ impl Breaks {
         let leaf = BreaksLeaf { len, data: vec![] };
         Node::from_leaf(leaf)
     }
+
+    /// Builds breaks of length `total_len` from the offsets of the breaks,
+    /// which must be increasing and in `1..=total_len`.
+    ///
+    /// Leaves are filled completely and the tree is built bottom-up in a
+    /// single pass, which is much faster than editing breaks into an existing
+    /// tree one line at a time.
+    pub fn from_iter(total_len: usize, breaks: impl IntoIterator<Item = usize>) -> Breaks {
+        let mut b = TreeBuilder::new();
+        let mut leaf = BreaksLeaf::default();
+        let mut leaf_start = 0;
+        let mut last = 0;
+        for offset in breaks {
+            debug_assert!(last < offset && offset <= total_len, "bad break {}", offset);
+            if leaf.data.len() == MAX_LEAF {
+                leaf.len = last - leaf_start;
+                leaf_start = last;
+                b.push_leaf(mem::take(&mut leaf));
+            }
+            leaf.data.push(offset - leaf_start);
+            last = offset;
+        }
+        leaf.len = total_len - leaf_start;
+        b.push_leaf(leaf);
+        let result = b.build();
+        debug_assert_eq!(result.len(), total_len);
+        result
+    }
+
+    /// Replaces the breaks in `iv` with `replacement`, which must have the
+    /// same length, leaving the breaks outside of `iv` untouched.
+    ///
+    /// This is used when a region is rewrapped without any change to the
+    /// underlying text.
+    pub fn splice<IV: IntervalBounds>(&mut self, iv: IV, replacement: Breaks) {
+        let iv = iv.into_interval(self.len());
+        debug_assert_eq!(iv.size(), replacement.len(), "splice must preserve length");
+        let len = self.len();
+        self.edit(iv, replacement);
+        debug_assert_eq!(self.len(), len);
+    }
 }
 
 pub struct BreakBuilder {
@@ -244,8 +285,9 @@ impl BreakBuilder {
 
 #[cfg(test)]
 mod tests {
-    use crate::breaks::{BreakBuilder, BreaksInfo, BreaksLeaf, BreaksMetric};
+    use crate::breaks::{BreakBuilder, Breaks, BreaksInfo, BreaksLeaf, BreaksMetric};
     use crate::interval::Interval;
+    use crate::test_helpers::Lcg;
     use crate::tree::{Cursor, Node};
 
     fn gen(n: usize) -> Node<BreaksInfo> {
@@ -326,4 +368,70 @@ mod tests {
             breaks.count_base_units::<BreaksMetric>(7)
         );
     }
+
+    /// Returns increasing offsets in `start + 1..=end`.
+    fn random_breaks(rng: &mut Lcg, start: usize, end: usize) -> Vec<usize> {
+        let density = 1 + rng.next(10);
+        (start + 1..=end).filter(|_| rng.next(density) == 0).collect()
+    }
+
+    fn collect_breaks(breaks: &Breaks) -> Vec<usize> {
+        Cursor::new(breaks, 0).iter::<BreaksMetric>().collect()
+    }
+
+    #[test]
+    fn from_iter_empty() {
+        let breaks = Breaks::from_iter(0, None);
+        assert_eq!(breaks.len(), 0);
+        assert_eq!(breaks.measure::<BreaksMetric>(), 0);
+    }
+
+    #[test]
+    fn from_iter_round_trip() {
+        let mut rng = Lcg(17);
+        for &len in &[1, 10, 64, 65, 1_000, 20_000] {
+            let offsets = random_breaks(&mut rng, 0, len);
+            let breaks = Breaks::from_iter(len, offsets.iter().cloned());
+            assert_eq!(breaks.len(), len);
+            assert_eq!(breaks.measure::<BreaksMetric>(), offsets.len());
+            assert_eq!(collect_breaks(&breaks), offsets);
+
+            let mut b = BreakBuilder::new();
+            let mut last = 0;
+            for &offset in &offsets {
+                b.add_break(offset - last);
+                last = offset;
+            }
+            b.add_no_break(len - last);
+            assert_eq!(collect_breaks(&b.build()), offsets);
+        }
+    }
+
+    #[test]
+    fn splice_matches_rebuild() {
+        let mut rng = Lcg(42);
+        for _ in 0..200 {
+            let len = 1 + rng.next(5_000);
+            let offsets = random_breaks(&mut rng, 0, len);
+            let mut breaks = Breaks::from_iter(len, offsets.iter().cloned());
+
+            let start = rng.next(len + 1);
+            let end = start + rng.next(len - start + 1);
+            let new_offsets = random_breaks(&mut rng, start, end);
+            let replacement =
+                Breaks::from_iter(end - start, new_offsets.iter().map(|off| off - start));
+            breaks.splice(start..end, replacement);
+
+            let expected: Vec<usize> = offsets
+                .iter()
+                .filter(|&&off| off <= start)
+                .chain(new_offsets.iter())
+                .chain(offsets.iter().filter(|&&off| off > end))
+                .cloned()
+                .collect();
+            assert_eq!(breaks.len(), len);
+            assert_eq!(collect_breaks(&breaks), expected);
+            assert_eq!(collect_breaks(&Breaks::from_iter(len, expected.iter().cloned())), expected);
+        }
+    }
 }
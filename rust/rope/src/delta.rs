@@ -118,17 +118,12 @@ impl<N: NodeInfo> Delta<N> {
     }
 
     /// Returns `true` if applying the delta will cause no change.
+    ///
+    /// This is the case when the delta copies the whole base, in order, and
+    /// inserts nothing; the copy may be split into several pieces, as
+    /// happens when a `Builder` is given an empty edit.
     pub fn is_identity(&self) -> bool {
-        let len = self.els.len();
-        // Case 1: Everything from beginning to end is getting copied.
-        if len == 1 {
-            if let DeltaElement::Copy(beg, end) = self.els[0] {
-                return beg == 0 && end == self.base_len;
-            }
-        }
-
-        // Case 2: The rope is empty and the entire rope is getting deleted.
-        len == 0 && self.base_len == 0
+        copied_prefix_len(&self.els) == Some(self.base_len)
     }
 
     /// Apply the delta to the given rope. May not work well if the length of the rope
@@ -145,6 +140,50 @@ impl<N: NodeInfo> Delta<N> {
         b.build()
     }
 
+    /// Composes this delta with `other`, which must apply to the document
+    /// this one produces, into a single delta from this one's base:
+    ///
+    /// `a.compose(&b).apply(r) == b.apply(&a.apply(r))`
+    ///
+    /// This is useful for coalescing a burst of edits before sending them on.
+    pub fn compose(&self, other: &Delta<N>) -> Delta<N> {
+        assert_eq!(
+            other.base_len,
+            self.new_document_len(),
+            "composed delta must apply to the result of the first"
+        );
+        let mut els = Vec::new();
+        // the element of `self` that produced the current position in the
+        // intermediate document, and the offset at which its output starts
+        let mut ix = 0;
+        let mut el_start = 0;
+        for el in &other.els {
+            let (mut beg, end) = match *el {
+                DeltaElement::Copy(beg, end) => (beg, end),
+                DeltaElement::Insert(ref n) => {
+                    push_merged(&mut els, DeltaElement::Insert(n.clone()));
+                    continue;
+                }
+            };
+            while beg < end {
+                let el_end = el_start + element_len(&self.els[ix]);
+                if el_end <= beg {
+                    el_start = el_end;
+                    ix += 1;
+                    continue;
+                }
+                let (lo, hi) = (beg - el_start, min(end, el_end) - el_start);
+                let piece = match self.els[ix] {
+                    DeltaElement::Copy(b, _) => DeltaElement::Copy(b + lo, b + hi),
+                    DeltaElement::Insert(ref n) => DeltaElement::Insert(n.subseq(lo..hi)),
+                };
+                push_merged(&mut els, piece);
+                beg = el_start + hi;
+            }
+        }
+        Delta { els, base_len: self.base_len }
+    }
+
     /// Factor the delta into an insert-only delta and a subset representing deletions.
     /// Applying the insert then the delete yields the same result as the original delta:
     ///
@@ -312,12 +351,7 @@ impl<N: NodeInfo> Delta<N> {
     }
 
     fn total_element_len(els: &[DeltaElement<N>]) -> usize {
-        els.iter().fold(0, |sum, el| {
-            sum + match *el {
-                DeltaElement::Copy(beg, end) => end - beg,
-                DeltaElement::Insert(ref n) => n.len(),
-            }
-        })
+        els.iter().map(element_len).sum()
     }
 
     /// Returns the sum length of the inserts of the delta.
@@ -570,6 +604,42 @@ impl<'a, N: NodeInfo + 'a> Transformer<'a, N> {
     }
 }
 
+/// If `els` copy the base from its start, in order and without gaps, and
+/// insert nothing, returns the length of the base that is copied.
+fn copied_prefix_len<N: NodeInfo>(els: &[DeltaElement<N>]) -> Option<usize> {
+    let mut copied = 0;
+    for el in els {
+        match *el {
+            DeltaElement::Copy(beg, end) if beg == copied => copied = end,
+            DeltaElement::Insert(ref n) if n.is_empty() => (),
+            _ => return None,
+        }
+    }
+    Some(copied)
+}
+
+fn element_len<N: NodeInfo>(el: &DeltaElement<N>) -> usize {
+    match *el {
+        DeltaElement::Copy(beg, end) => end - beg,
+        DeltaElement::Insert(ref n) => n.len(),
+    }
+}
+
+/// Appends `el` to `els`, merging adjacent copies and adjacent inserts.
+fn push_merged<N: NodeInfo>(els: &mut Vec<DeltaElement<N>>, el: DeltaElement<N>) {
+    match (els.last_mut(), el) {
+        (Some(&mut DeltaElement::Copy(_, ref mut last_end)), DeltaElement::Copy(beg, end))
+            if *last_end == beg =>
+        {
+            *last_end = end
+        }
+        (Some(&mut DeltaElement::Insert(ref mut last)), DeltaElement::Insert(n)) => {
+            *last = Node::concat(last.clone(), n)
+        }
+        (_, el) => els.push(el),
+    }
+}
+
 /// A builder for creating new `Delta` objects.
 ///
 /// Note that all edit operations must be sorted; the start point of each
@@ -610,6 +680,15 @@ impl<N: NodeInfo> Builder<N> {
         self.last_offset == 0 && self.delta.els.is_empty()
     }
 
+    /// Determines if the delta, if built, would leave the base unchanged.
+    ///
+    /// Unlike `is_empty`, this is also true once edits that change nothing,
+    /// such as replacing an empty interval with an empty rope, have been
+    /// added.
+    pub fn is_identity(&self) -> bool {
+        copied_prefix_len(&self.delta.els) == Some(self.last_offset)
+    }
+
     /// Builds the `Delta`.
     pub fn build(mut self) -> Delta<N> {
         if self.last_offset < self.delta.base_len {
@@ -704,7 +783,7 @@ mod tests {
     use crate::delta::{Builder, Delta, DeltaElement, DeltaRegion};
     use crate::interval::Interval;
     use crate::rope::{Rope, RopeInfo};
-    use crate::test_helpers::find_deletions;
+    use crate::test_helpers::{find_deletions, Lcg};
 
     const TEST_STR: &str = "0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
 
@@ -871,6 +950,34 @@ mod tests {
 
         let d = Delta::simple_edit(0..0, Rope::from(""), 0);
         assert_eq!(true, d.is_identity());
+
+        // a zero-length edit in the middle splits the copy in two
+        let d = Delta::simple_edit(5..5, Rope::from(""), TEST_STR.len());
+        assert_eq!(true, d.is_identity());
+
+        let d = Delta::simple_edit(5..6, Rope::from(""), TEST_STR.len());
+        assert_eq!(false, d.is_identity());
+    }
+
+    #[test]
+    fn builder_is_identity() {
+        let mut builder = Builder::<RopeInfo>::new(TEST_STR.len());
+        assert!(builder.is_identity());
+        builder.replace(3..3, Rope::from(""));
+        builder.delete(7..7);
+        assert!(builder.is_identity());
+        assert!(!builder.is_empty());
+        assert!(builder.build().is_identity());
+
+        let mut builder = Builder::<RopeInfo>::new(TEST_STR.len());
+        builder.replace(3..3, Rope::from(""));
+        builder.replace(10..12, Rope::from("+"));
+        assert!(!builder.is_identity());
+        assert!(!builder.build().is_identity());
+
+        let mut builder = Builder::<RopeInfo>::new(TEST_STR.len());
+        builder.delete(3..5);
+        assert!(!builder.is_identity());
     }
 
     #[test]
@@ -881,6 +988,82 @@ mod tests {
         let d = Delta::simple_edit(Interval::new(10, 10), Rope::from("+"), TEST_STR.len());
         assert_eq!(Some(Rope::from("+")).as_ref(), d.as_simple_insert());
     }
+
+    #[test]
+    fn compose_insert_then_delete() {
+        let a = Delta::simple_edit(Interval::new(5, 5), Rope::from(" there"), 11);
+        assert_eq!("hello there world", a.apply_to_string("hello world"));
+        // deletes part of the insert, and part of the original text
+        let b = Delta::simple_edit(Interval::new(8, 13), Rope::from(""), 17);
+        let composed = a.compose(&b);
+        assert_eq!(composed.base_len, 11);
+        assert_eq!("hello thorld", composed.apply_to_string("hello world"));
+        assert_eq!(composed.new_document_len(), 12);
+
+        // deleting the whole insert leaves the base unchanged
+        let b = Delta::simple_edit(Interval::new(5, 11), Rope::from(""), 17);
+        assert!(a.compose(&b).is_identity());
+    }
+
+    #[test]
+    fn compose_delete_then_insert() {
+        let a = Delta::simple_edit(Interval::new(1, 9), Rope::from(""), 11);
+        assert_eq!("hld", a.apply_to_string("hello world"));
+        let b = Delta::simple_edit(Interval::new(1, 1), Rope::from("era"), 3);
+        let composed = a.compose(&b);
+        assert_eq!(composed.base_len, 11);
+        assert_eq!("herald", composed.apply_to_string("hello world"));
+        let edit = Delta::simple_edit(Interval::new(1, 9), Rope::from("era"), 11);
+        assert_eq!(format!("{:?}", composed), format!("{:?}", edit));
+    }
+
+    #[test]
+    fn compose_coalesces_typing() {
+        let mut composed = Delta::simple_edit(Interval::new(3, 3), Rope::from("a"), 6);
+        for (i, c) in ["b", "c", "d"].iter().enumerate() {
+            let next = Delta::simple_edit(Interval::new(4 + i, 4 + i), Rope::from(*c), 7 + i);
+            composed = composed.compose(&next);
+        }
+        assert_eq!("fooabcdbar", composed.apply_to_string("foobar"));
+        assert_eq!(Some(Rope::from("abcd")).as_ref(), composed.as_simple_insert());
+    }
+
+    /// A delta of a few sorted replacements, some of which may be empty.
+    fn random_delta(rng: &mut Lcg, base_len: usize) -> Delta<RopeInfo> {
+        let mut builder = Builder::new(base_len);
+        let mut offset = 0;
+        while offset <= base_len && rng.next(4) != 0 {
+            let start = offset + rng.next(base_len - offset + 1);
+            let end = start + rng.next((base_len - start).min(5) + 1);
+            let text = &TEST_STR[rng.next(10)..][..rng.next(4)];
+            builder.replace(Interval::new(start, end), Rope::from(text));
+            offset = end + 1;
+        }
+        builder.build()
+    }
+
+    #[test]
+    fn compose_matches_sequential_apply() {
+        let mut rng = Lcg(7);
+        for _ in 0..1_000 {
+            let text = &TEST_STR[..rng.next(TEST_STR.len() + 1)];
+            let a = random_delta(&mut rng, text.len());
+            let intermediate = a.apply_to_string(text);
+            let b = random_delta(&mut rng, intermediate.len());
+            let composed = a.compose(&b);
+            assert_eq!(composed.base_len, text.len());
+            assert_eq!(composed.new_document_len(), b.new_document_len());
+            assert_eq!(composed.apply_to_string(text), b.apply_to_string(&intermediate));
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "composed delta must apply")]
+    fn compose_wrong_length() {
+        let a = Delta::simple_edit(Interval::new(0, 1), Rope::from(""), 11);
+        let b = Delta::simple_edit(Interval::new(0, 1), Rope::from(""), 11);
+        a.compose(&b);
+    }
 }
 
 #[cfg(all(test, feature = "serde"))]
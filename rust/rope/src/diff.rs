@@ -16,6 +16,7 @@
 
 use std::borrow::Cow;
 use std::collections::HashMap;
+use std::iter;
 
 use crate::compare::RopeScanner;
 use crate::delta::{Delta, DeltaElement};
@@ -141,6 +142,276 @@ impl Diff<RopeInfo> for LineHashDiff {
     }
 }
 
+/// The strategy used by a [`RopeDiffBuilder`] to compute a delta.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiffStrategy {
+    /// Diff the documents with [`LineHashDiff`].
+    Fine,
+    /// Match whole lines first, to find the blocks of lines which are
+    /// unchanged, and only diff the regions between these blocks with
+    /// [`LineHashDiff`]. This is much faster on large documents with
+    /// few changes.
+    ///
+    /// Lines with fewer than `min_size` non-whitespace bytes are not used to
+    /// find unchanged blocks, although they can be part of one.
+    LineHashed { min_size: usize },
+}
+
+/// Computes deltas between ropes, with a configurable [`DiffStrategy`].
+///
+/// # Examples
+///
+/// ```
+/// use xi_rope::diff::{DiffStrategy, RopeDiffBuilder};
+/// use xi_rope::Rope;
+///
+/// let base = Rope::from("one\ntwo\nthree\n");
+/// let target = Rope::from("one\n2\nthree\n");
+/// let delta = RopeDiffBuilder::new()
+///     .strategy(DiffStrategy::LineHashed { min_size: 3 })
+///     .compute_delta(&base, &target);
+/// assert_eq!(String::from(delta.apply(&base)), String::from(&target));
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct RopeDiffBuilder {
+    strategy: DiffStrategy,
+}
+
+impl Default for RopeDiffBuilder {
+    fn default() -> RopeDiffBuilder {
+        RopeDiffBuilder::new()
+    }
+}
+
+impl RopeDiffBuilder {
+    /// Creates a builder using the [`DiffStrategy::Fine`] strategy.
+    pub fn new() -> RopeDiffBuilder {
+        RopeDiffBuilder { strategy: DiffStrategy::Fine }
+    }
+
+    pub fn strategy(mut self, strategy: DiffStrategy) -> RopeDiffBuilder {
+        self.strategy = strategy;
+        self
+    }
+
+    /// Computes a delta which, applied to `base`, produces `target`.
+    pub fn compute_delta(&self, base: &Rope, target: &Rope) -> RopeDelta {
+        match self.strategy {
+            DiffStrategy::Fine => LineHashDiff::compute_delta(base, target),
+            DiffStrategy::LineHashed { min_size } => line_block_delta(base, target, min_size),
+        }
+    }
+}
+
+/// Computes a delta which, applied to `base`, produces `target`, using the
+/// default [`RopeDiffBuilder`]. The delta is an identity if the ropes are
+/// equal.
+///
+/// # Examples
+///
+/// ```
+/// use xi_rope::diff::compute_delta;
+/// use xi_rope::Rope;
+///
+/// let base = Rope::from("fn main() {\n    println!(\"hi\")\n}\n");
+/// let target = Rope::from("fn main() {\n    println!(\"hi\");\n}\n");
+/// let delta = compute_delta(&base, &target);
+/// assert_eq!(String::from(delta.apply(&base)), String::from(&target));
+/// assert_eq!(delta.inserts_len(), 1);
+/// ```
+pub fn compute_delta(base: &Rope, target: &Rope) -> RopeDelta {
+    RopeDiffBuilder::new().compute_delta(base, target)
+}
+
+/// A run of `len` unchanged lines, starting at line `base` in the base
+/// document and at line `target` in the target document.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct LineBlock {
+    base: usize,
+    target: usize,
+    len: usize,
+}
+
+/// The raw lines of a rope, with their offsets.
+struct LineTable<'a> {
+    lines: Vec<Cow<'a, str>>,
+    /// The offset of the start of each line, followed by the length of the rope.
+    offsets: Vec<usize>,
+}
+
+impl<'a> LineTable<'a> {
+    fn new(rope: &'a Rope) -> LineTable<'a> {
+        let lines = rope.lines_raw(..).collect::<Vec<_>>();
+        let mut offsets = Vec::with_capacity(lines.len() + 1);
+        let mut offset = 0;
+        offsets.push(offset);
+        for line in &lines {
+            offset += line.len();
+            offsets.push(offset);
+        }
+        LineTable { lines, offsets }
+    }
+}
+
+/// Computes a delta using [`DiffStrategy::LineHashed`].
+fn line_block_delta(base: &Rope, target: &Rope, min_size: usize) -> RopeDelta {
+    let base_lines = LineTable::new(base);
+    let targ_lines = LineTable::new(target);
+    let blocks = unchanged_line_blocks(&base_lines.lines, &targ_lines.lines, min_size);
+
+    let mut els = Vec::new();
+    let mut base_line = 0;
+    let mut targ_line = 0;
+    let end = LineBlock { base: base_lines.lines.len(), target: targ_lines.lines.len(), len: 0 };
+    for block in blocks.into_iter().chain(iter::once(end)) {
+        // diff the changed region before this block
+        let base_iv = Interval::new(base_lines.offsets[base_line], base_lines.offsets[block.base]);
+        let targ_iv =
+            Interval::new(targ_lines.offsets[targ_line], targ_lines.offsets[block.target]);
+        push_changed_region(&mut els, base, target, base_iv, targ_iv);
+
+        base_line = block.base + block.len;
+        targ_line = block.target + block.len;
+        push_copy(&mut els, base_lines.offsets[block.base], base_lines.offsets[base_line]);
+    }
+    Delta { els, base_len: base.len() }
+}
+
+/// Finds the blocks of lines which are unchanged between `base` and `target`.
+///
+/// Apart from a common prefix and suffix, blocks are found by matching lines
+/// which occur the same number of times in each document (ignoring lines
+/// shorter than `min_size`), pairing their occurrences in order, and
+/// extending these matches forwards and backwards.
+fn unchanged_line_blocks(
+    base: &[Cow<str>],
+    target: &[Cow<str>],
+    min_size: usize,
+) -> Vec<LineBlock> {
+    let prefix = base.iter().zip(target).take_while(|(b, t)| b == t).count();
+    let suffix = base[prefix..]
+        .iter()
+        .rev()
+        .zip(target[prefix..].iter().rev())
+        .take_while(|(b, t)| b == t)
+        .count();
+    let base_end = base.len() - suffix;
+    let targ_end = target.len() - suffix;
+
+    // for each candidate line: its lines in base, its count in target, and
+    // the number of its occurrences in target seen so far
+    let mut counts: HashMap<&str, (Vec<usize>, usize, usize)> = HashMap::new();
+    for (i, line) in base.iter().enumerate().take(base_end).skip(prefix) {
+        if line.trim().len() >= min_size {
+            counts.entry(line.as_ref()).or_insert((Vec::new(), 0, 0)).0.push(i);
+        }
+    }
+    for line in &target[prefix..targ_end] {
+        if let Some(entry) = counts.get_mut(line.as_ref()) {
+            entry.1 += 1;
+        }
+    }
+    let mut matches = Vec::new();
+    for (i, line) in target.iter().enumerate().take(targ_end).skip(prefix) {
+        if let Some((base_ixs, targ_count, seen)) = counts.get_mut(line.as_ref()) {
+            if base_ixs.len() == *targ_count {
+                matches.push((i, base_ixs[*seen]));
+                *seen += 1;
+            }
+        }
+    }
+    // copies in the delta must be in order in the base
+    let matches =
+        if matches.is_empty() { matches } else { longest_increasing_region_set(&matches) };
+
+    let mut blocks = Vec::new();
+    push_block(&mut blocks, LineBlock { base: 0, target: 0, len: prefix });
+    let mut prev_base = prefix;
+    let mut prev_targ = prefix;
+    for (targ_ix, base_ix) in matches {
+        if targ_ix < prev_targ || base_ix < prev_base {
+            // already part of the previous block
+            continue;
+        }
+        let mut before = 0;
+        while targ_ix - before > prev_targ
+            && base_ix - before > prev_base
+            && base[base_ix - before - 1] == target[targ_ix - before - 1]
+        {
+            before += 1;
+        }
+        let mut after = 1;
+        while targ_ix + after < targ_end
+            && base_ix + after < base_end
+            && base[base_ix + after] == target[targ_ix + after]
+        {
+            after += 1;
+        }
+        let block =
+            LineBlock { base: base_ix - before, target: targ_ix - before, len: before + after };
+        push_block(&mut blocks, block);
+        prev_base = base_ix + after;
+        prev_targ = targ_ix + after;
+    }
+    push_block(&mut blocks, LineBlock { base: base_end, target: targ_end, len: suffix });
+    blocks
+}
+
+/// Adds a block, merging it with the previous one if they are adjacent.
+fn push_block(blocks: &mut Vec<LineBlock>, block: LineBlock) {
+    if block.len == 0 {
+        return;
+    }
+    if let Some(prev) = blocks.last_mut() {
+        if prev.base + prev.len == block.base && prev.target + prev.len == block.target {
+            prev.len += block.len;
+            return;
+        }
+    }
+    blocks.push(block);
+}
+
+/// Adds the elements turning the `base_iv` region of `base` into the
+/// `targ_iv` region of `target`.
+fn push_changed_region(
+    els: &mut Vec<DeltaElement<RopeInfo>>,
+    base: &Rope,
+    target: &Rope,
+    base_iv: Interval,
+    targ_iv: Interval,
+) {
+    if targ_iv.is_empty() {
+        return;
+    }
+    if base_iv.is_empty() {
+        els.push(DeltaElement::Insert(target.subseq(targ_iv)));
+        return;
+    }
+    let delta = LineHashDiff::compute_delta(&base.subseq(base_iv), &target.subseq(targ_iv));
+    for el in delta.els {
+        match el {
+            DeltaElement::Copy(beg, end) => {
+                push_copy(els, base_iv.start() + beg, base_iv.start() + end)
+            }
+            DeltaElement::Insert(rope) => els.push(DeltaElement::Insert(rope)),
+        }
+    }
+}
+
+/// Adds a copy element, merging it with the previous one if they are adjacent.
+fn push_copy(els: &mut Vec<DeltaElement<RopeInfo>>, beg: usize, end: usize) {
+    if beg == end {
+        return;
+    }
+    if let Some(DeltaElement::Copy(_, prev_end)) = els.last_mut() {
+        if *prev_end == beg {
+            *prev_end = end;
+            return;
+        }
+    }
+    els.push(DeltaElement::Copy(beg, end));
+}
+
 /// Given two ropes and the offsets of two equal bytes, finds the largest
 /// identical substring shared between the two ropes which contains the offset.
 ///
@@ -336,4 +607,101 @@ Currently my sense of smell (and the pain of implementing Write) might be too mu
         let result = delta.apply(&one);
         assert_eq!(String::from(result), String::from(two));
     }
+
+    fn check_line_hashed(base: &str, target: &str) {
+        for &min_size in &[0, 4, usize::MAX] {
+            let builder = RopeDiffBuilder::new().strategy(DiffStrategy::LineHashed { min_size });
+            let (one, two) = (Rope::from(base), Rope::from(target));
+            let delta = builder.compute_delta(&one, &two);
+            assert_eq!(String::from(delta.apply(&one)), target, "min_size {}", min_size);
+            let delta = builder.compute_delta(&two, &one);
+            assert_eq!(String::from(delta.apply(&two)), base, "min_size {}", min_size);
+        }
+    }
+
+    #[test]
+    fn line_hashed_small() {
+        check_line_hashed("", "");
+        check_line_hashed("", "one\ntwo");
+        check_line_hashed("one\ntwo\nthree\n", "one\ntwo\nthree\n");
+        check_line_hashed("one\ntwo\nthree\n", "one\n2\nthree\n");
+        check_line_hashed("one\ntwo\nthree", "one\ntwo\nthree\n");
+        check_line_hashed("one\ntwo", "one\ntwo\nthree");
+        check_line_hashed("one\ntwo\nthree", "three\ntwo\none");
+        check_line_hashed("\n\n\n", "\n\n");
+        check_line_hashed("a\r\nb\r\n", "a\nb\n");
+        check_line_hashed(SMALL_ONE, SMALL_TWO);
+        check_line_hashed(INTERVAL_STR, BREAKS_STR);
+    }
+
+    #[test]
+    fn line_hashed_repeated_lines() {
+        let base = "same\n".repeat(20) + "unique line\n" + &"same\n".repeat(20);
+        check_line_hashed(&base, &("same\n".repeat(41)));
+        check_line_hashed(&base, &("same\n".repeat(5) + &base + "same"));
+        check_line_hashed(&base, &base.replacen("same\n", "", 30));
+        check_line_hashed(&base, &base.replace("unique line", "other line"));
+    }
+
+    fn check_compute_delta(base: &str, target: &str) -> RopeDelta {
+        let (one, two) = (Rope::from(base), Rope::from(target));
+        assert_eq!(String::from(compute_delta(&two, &one).apply(&two)), base);
+        let delta = compute_delta(&one, &two);
+        assert_eq!(String::from(delta.apply(&one)), target);
+        delta
+    }
+
+    #[test]
+    fn compute_delta_pairs() {
+        assert!(check_compute_delta("", "").is_identity());
+        assert!(check_compute_delta(SMALL_ONE, SMALL_ONE).is_identity());
+        assert!(check_compute_delta(INTERVAL_STR, INTERVAL_STR).is_identity());
+        assert!(!check_compute_delta("a", "b").is_identity());
+        check_compute_delta("", INTERVAL_STR);
+        check_compute_delta(SMALL_ONE, SMALL_TWO);
+
+        // nothing in common
+        let delta = check_compute_delta("abcdefghij", "0123456789");
+        assert_eq!(delta.inserts_len(), 10);
+        let delta = check_compute_delta(&"x".repeat(5000), &"y".repeat(3000));
+        assert_eq!(delta.inserts_len(), 3000);
+    }
+
+    #[test]
+    fn compute_delta_common_prefix() {
+        let prefix = INTERVAL_STR.repeat(4);
+        let base = format!("{}{}", prefix, "an old ending");
+        let target = format!("{}{}", prefix, "a new ending\n");
+        let delta = check_compute_delta(&base, &target);
+        assert!(delta.inserts_len() < 20, "{}", delta.inserts_len());
+
+        // a common prefix and suffix around a changed middle
+        let base = format!("{}middle{}", prefix, BREAKS_STR);
+        let target = format!("{}center{}", prefix, BREAKS_STR);
+        let delta = check_compute_delta(&base, &target);
+        assert_eq!(delta.inserts_len(), "center".len());
+
+        let delta = check_compute_delta(&prefix, &format!("{}{}", prefix, SMALL_ONE));
+        assert_eq!(delta.inserts_len(), SMALL_ONE.len());
+    }
+
+    #[test]
+    fn line_hashed_large() {
+        let base = [INTERVAL_STR, BREAKS_STR, SMALL_ONE].concat().repeat(8);
+        let mut lines = base.lines().collect::<Vec<_>>();
+        // edit, insert, delete, and move some lines
+        lines[10] = "an edited line";
+        lines.insert(500, "an inserted line, which is long enough to be hashed");
+        lines.drain(1000..1040);
+        let moved = lines.remove(2000);
+        lines.insert(100, moved);
+        let target = lines.join("\n");
+        check_line_hashed(&base, &target);
+
+        let builder = RopeDiffBuilder::new().strategy(DiffStrategy::LineHashed { min_size: 8 });
+        let (one, two) = (Rope::from(&base), Rope::from(&target));
+        let delta = builder.compute_delta(&one, &two);
+        // most of the document is copied
+        assert!(delta.inserts_len() < 1000, "{}", delta.inserts_len());
+    }
 }
@@ -30,7 +30,7 @@
 
 use std::borrow::Cow;
 use std::collections::hash_map::DefaultHasher;
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
 
 use crate::delta::{Delta, InsertDelta};
 use crate::interval::Interval;
@@ -71,6 +71,19 @@ pub struct Engine {
     undone_groups: BTreeSet<usize>, // set of undo_group id's
     /// The revision history of the document
     revs: Vec<Revision>,
+    /// The highest `num` of a revision collected by GC in each session, so
+    /// that a request for a collected revision can be told apart from a
+    /// request for an unknown one without keeping every collected token.
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing))]
+    collected_revs: BTreeMap<SessionId, u32>,
+    /// The most undo groups kept in the history, if it is limited; see
+    /// `set_max_undo_depth`.
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing))]
+    max_undo_depth: Option<usize>,
+    /// The revisions kept from the undo depth limit by `hold_rev`, and how
+    /// many times each is held.
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing))]
+    held_revs: BTreeMap<RevToken, usize>,
 }
 
 // The advantage of using a session ID over random numbers is that it can be
@@ -109,12 +122,25 @@ pub type RevToken = u64;
 /// the session ID component of a `RevId`
 pub type SessionId = (u64, u32);
 
+/// Which history [`Engine::gc_before`] keeps, besides what it must keep to
+/// resolve newer revisions.
+///
+/// [`Engine::gc_before`]: struct.Engine.html#method.gc_before
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RetentionPolicy {
+    /// Keep the undo groups of the last `n` revisions.
+    KeepLast(usize),
+    /// Keep the `n` most recent undo groups.
+    KeepUndoGroups(usize),
+}
+
 /// Type for errors that occur during CRDT operations.
 #[derive(Clone)]
 pub enum Error {
-    /// An edit specified a revision that did not exist. The revision may
-    /// have been GC'd, or it may have specified incorrectly.
+    /// An edit specified a revision that never existed in this session.
     MissingRevision(RevToken),
+    /// An edit specified a revision that has since been GC'd.
+    CollectedRevision(RevToken),
     /// A delta was applied which had a `base_len` that did not match the length
     /// of the revision it was applied to.
     MalformedDelta { rev_len: usize, delta_len: usize },
@@ -167,6 +193,16 @@ fn initial_revision_counter() -> u32 {
     1
 }
 
+impl Revision {
+    /// Returns the undo group of this revision, if it is an edit.
+    fn undo_group(&self) -> Option<usize> {
+        match self.edit {
+            Edit { undo_group, .. } => Some(undo_group),
+            Undo { .. } => None,
+        }
+    }
+}
+
 impl RevId {
     /// Returns a u64 that will be equal for equivalent revision IDs and
     /// should be as unlikely to collide as two random u64s.
@@ -217,6 +253,9 @@ impl Engine {
             deletes_from_union,
             undone_groups: BTreeSet::new(),
             revs: vec![rev],
+            collected_revs: BTreeMap::new(),
+            max_undo_depth: None,
+            held_revs: BTreeMap::new(),
         }
     }
 
@@ -237,6 +276,33 @@ impl Engine {
             .map(|(i, _)| i)
     }
 
+    /// Like `find_rev_token`, but returns an error saying whether the
+    /// revision was GC'd or never existed.
+    fn rev_index_for_token(&self, rev_token: RevToken) -> Result<usize, Error> {
+        self.find_rev_token(rev_token).ok_or_else(|| {
+            if self.was_collected(rev_token) {
+                Error::CollectedRevision(rev_token)
+            } else {
+                Error::MissingRevision(rev_token)
+            }
+        })
+    }
+
+    /// Whether `rev_token` is that of a revision that could have been
+    /// collected by GC. This has to rebuild the candidate tokens, so it is
+    /// only for the error path.
+    fn was_collected(&self, rev_token: RevToken) -> bool {
+        self.collected_revs.iter().any(|(&(session1, session2), &max_num)| {
+            (0..=max_num).any(|num| RevId { session1, session2, num }.token() == rev_token)
+        })
+    }
+
+    /// Record that GC collected the revision `rev_id`.
+    fn mark_collected(&mut self, rev_id: RevId) {
+        let max_num = self.collected_revs.entry(rev_id.session_id()).or_insert(rev_id.num);
+        *max_num = rev_id.num.max(*max_num);
+    }
+
     // TODO: does Cow really help much here? It certainly won't after making Subsets a rope.
     /// Find what the `deletes_from_union` field in Engine would have been at the time
     /// of a certain `rev_index`. In other words, the deletes from the union string at that time.
@@ -319,9 +385,9 @@ impl Engine {
     }
 
     /// A delta that, when applied to `base_rev`, results in the current head. Returns
-    /// an error if there is not at least one edit.
+    /// an error if `base_rev` was GC'd, or is not a revision of this engine.
     pub fn try_delta_rev_head(&self, base_rev: RevToken) -> Result<Delta<RopeInfo>, Error> {
-        let ix = self.find_rev_token(base_rev).ok_or(Error::MissingRevision(base_rev))?;
+        let ix = self.rev_index_for_token(base_rev)?;
         let prev_from_union = self.deletes_from_cur_union_for_index(ix);
         // TODO: this does 2 calls to Delta::synthesize and 1 to apply, this probably could be better.
         let old_tombstones = shuffle_tombstones(
@@ -346,7 +412,7 @@ impl Engine {
         base_rev: RevToken,
         delta: Delta<RopeInfo>,
     ) -> Result<(Revision, Rope, Rope, Subset), Error> {
-        let ix = self.find_rev_token(base_rev).ok_or(Error::MissingRevision(base_rev))?;
+        let ix = self.rev_index_for_token(base_rev)?;
 
         let (ins_delta, deletes) = delta.factor();
 
@@ -458,6 +524,7 @@ impl Engine {
         self.text = new_text;
         self.tombstones = new_tombstones;
         self.deletes_from_union = new_deletes_from_union;
+        self.collect_past_undo_depth();
         Ok(())
     }
 
@@ -564,15 +631,114 @@ impl Engine {
     // and partly because you need to retain more undo history, to supply input to the
     // reachability calculation.
     //
-    // Thus, it's easiest to defer gc to when all plugins quiesce, but it's certainly
-    // possible to fix it so that's not necessary.
+    // Thus, it's easiest to defer gc to when all plugins quiesce; `gc_before` is the
+    // restricted form which is safe while they still hold older revisions.
     pub fn gc(&mut self, gc_groups: &BTreeSet<usize>) {
-        let mut gc_dels = self.empty_subset_before_first_rev();
-        // TODO: want to let caller retain more rev_id's.
-        let mut retain_revs = BTreeSet::new();
-        if let Some(last) = self.revs.last() {
-            retain_revs.insert(last.rev_id);
+        self.gc_retaining(gc_groups, self.revs.len() - 1);
+    }
+
+    /// Collects the history of undo groups which are no longer needed, while
+    /// retaining the revision `rev_token` and all later ones, so that deltas
+    /// from them can still be computed with `try_delta_rev_head`.
+    ///
+    /// An undo group is collected if all of its edits precede `rev_token`, it
+    /// was not undone or redone since, and it is neither kept by `policy` nor
+    /// in `keep_groups`. Returns the collected groups, which can no longer be
+    /// toggled by `undo`.
+    pub fn gc_before(
+        &mut self,
+        rev_token: RevToken,
+        policy: RetentionPolicy,
+        keep_groups: &BTreeSet<usize>,
+    ) -> Result<BTreeSet<usize>, Error> {
+        let rev_index = self.rev_index_for_token(rev_token)?;
+        let retain_from = match policy {
+            RetentionPolicy::KeepLast(n) => rev_index.min(self.revs.len().saturating_sub(n)),
+            RetentionPolicy::KeepUndoGroups(_) => rev_index,
+        };
+
+        // The content of a retained revision must not change, so neither can
+        // the state of any collected group since then.
+        let mut retained_groups = keep_groups.clone();
+        for rev in &self.revs[retain_from..] {
+            match rev.edit {
+                Edit { undo_group, .. } => {
+                    retained_groups.insert(undo_group);
+                }
+                Undo { ref toggled_groups, .. } => retained_groups.extend(toggled_groups),
+            }
+        }
+        if let RetentionPolicy::KeepUndoGroups(n) = policy {
+            let groups: BTreeSet<usize> =
+                self.revs.iter().filter_map(Revision::undo_group).collect();
+            retained_groups.extend(groups.into_iter().rev().take(n));
+        }
+
+        let gc_groups: BTreeSet<usize> = self.revs[..retain_from]
+            .iter()
+            .filter_map(Revision::undo_group)
+            .filter(|group| !retained_groups.contains(group))
+            .collect();
+        if !gc_groups.is_empty() {
+            self.gc_retaining(&gc_groups, retain_from);
+        }
+        Ok(gc_groups)
+    }
+
+    /// Limits the history to the `n` most recent undo groups. Older groups
+    /// are collected now, and as new edits are made, unless they have edits
+    /// in a revision held with `hold_rev`, or since then, or were undone or
+    /// redone since then.
+    ///
+    /// Toggling a collected group with `undo` has no effect.
+    pub fn set_max_undo_depth(&mut self, n: usize) {
+        self.max_undo_depth = Some(n);
+        self.collect_past_undo_depth();
+    }
+
+    /// Keeps the undo depth limit from collecting `rev_token`, so that deltas
+    /// from it can still be computed, until it is released with
+    /// `release_rev`. A revision may be held several times, and is kept
+    /// until each hold is released.
+    pub fn hold_rev(&mut self, rev_token: RevToken) -> Result<(), Error> {
+        self.rev_index_for_token(rev_token)?;
+        *self.held_revs.entry(rev_token).or_insert(0) += 1;
+        Ok(())
+    }
+
+    /// Releases a hold on `rev_token` taken with `hold_rev`, collecting the
+    /// history it kept past the undo depth limit once it is no longer held.
+    pub fn release_rev(&mut self, rev_token: RevToken) {
+        match self.held_revs.get_mut(&rev_token) {
+            Some(count) if *count > 1 => *count -= 1,
+            Some(_) => {
+                self.held_revs.remove(&rev_token);
+                self.collect_past_undo_depth();
+            }
+            None => (),
         }
+    }
+
+    /// Collects the undo groups past `max_undo_depth`, retaining the oldest
+    /// held revision and all later ones.
+    fn collect_past_undo_depth(&mut self) {
+        let depth = match self.max_undo_depth {
+            Some(depth) => depth,
+            None => return,
+        };
+        let oldest_held = self.held_revs.keys().filter_map(|&rev| self.find_rev_token(rev)).min();
+        let retain_from = oldest_held.unwrap_or(self.revs.len() - 1);
+        let rev_token = self.revs[retain_from].rev_id.token();
+        // the revision exists, so this can't fail
+        let _ = self.gc_before(rev_token, RetentionPolicy::KeepUndoGroups(depth), &BTreeSet::new());
+    }
+
+    /// Collects the history of `gc_groups`, none of which may have edits in
+    /// the revisions from `retain_from` on, which are all retained.
+    fn gc_retaining(&mut self, gc_groups: &BTreeSet<usize>, retain_from: usize) {
+        let mut gc_dels = self.empty_subset_before_first_rev();
+        let retain_revs: BTreeSet<RevId> =
+            self.revs[retain_from..].iter().map(|rev| rev.rev_id).collect();
         {
             for rev in &self.revs {
                 if let Edit { ref undo_group, ref inserts, ref deletes, .. } = rev.edit {
@@ -601,48 +767,59 @@ impl Engine {
             self.tombstones = dels_from_tombstones.delete_from(&self.tombstones);
             self.deletes_from_union = self.deletes_from_union.transform_shrink(&gc_dels);
         }
+        // The collected revisions need not all precede the retained ones, so
+        // the text they inserted becomes part of the text before the first
+        // revision, and the retained revisions are rewritten in terms of the
+        // union string after gc. Walking backwards, `later_inserts` holds the
+        // text inserted after the current revision, in the old union string,
+        // and `retained_later` that inserted by later retained revisions, in
+        // the new one.
+        let mut later_inserts = Subset::new(gc_dels.len());
+        let mut retained_later = Subset::new(gc_dels.len_after_delete());
         let old_revs = std::mem::take(&mut self.revs);
         for rev in old_revs.into_iter().rev() {
+            let to_new = |s: &Subset| {
+                s.transform_expand(&later_inserts)
+                    .transform_shrink(&gc_dels)
+                    .transform_shrink(&retained_later)
+            };
             match rev.edit {
                 Edit { priority, undo_group, inserts, deletes } => {
-                    let new_gc_dels = if inserts.is_empty() {
-                        None
-                    } else {
-                        Some(gc_dels.transform_shrink(&inserts))
-                    };
+                    let all_inserts = inserts.transform_expand(&later_inserts);
                     if retain_revs.contains(&rev.rev_id) || !gc_groups.contains(&undo_group) {
-                        let (inserts, deletes) = if gc_dels.is_empty() {
-                            (inserts, deletes)
-                        } else {
-                            (inserts.transform_shrink(&gc_dels), deletes.transform_shrink(&gc_dels))
-                        };
+                        let new_inserts = to_new(&inserts);
+                        let new_deletes = to_new(&deletes);
+                        let inserted = all_inserts.transform_shrink(&gc_dels);
+                        retained_later = retained_later.union(&inserted);
                         self.revs.push(Revision {
                             rev_id: rev.rev_id,
                             max_undo_so_far: rev.max_undo_so_far,
-                            edit: Edit { priority, undo_group, inserts, deletes },
+                            edit: Edit {
+                                priority,
+                                undo_group,
+                                inserts: new_inserts,
+                                deletes: new_deletes,
+                            },
                         });
+                    } else {
+                        self.mark_collected(rev.rev_id);
                     }
-                    if let Some(new_gc_dels) = new_gc_dels {
-                        gc_dels = new_gc_dels;
-                    }
+                    later_inserts = later_inserts.union(&all_inserts);
                 }
                 Undo { toggled_groups, deletes_bitxor } => {
                     // We're super-aggressive about dropping these; after gc, the history
                     // of which undos were used to compute deletes_from_union in edits may be lost.
                     if retain_revs.contains(&rev.rev_id) {
-                        let new_deletes_bitxor = if gc_dels.is_empty() {
-                            deletes_bitxor
-                        } else {
-                            deletes_bitxor.transform_shrink(&gc_dels)
-                        };
                         self.revs.push(Revision {
                             rev_id: rev.rev_id,
                             max_undo_so_far: rev.max_undo_so_far,
                             edit: Undo {
                                 toggled_groups: &toggled_groups - gc_groups,
-                                deletes_bitxor: new_deletes_bitxor,
+                                deletes_bitxor: to_new(&deletes_bitxor),
                             },
                         })
+                    } else {
+                        self.mark_collected(rev.rev_id);
                     }
                 }
             }
@@ -941,6 +1118,7 @@ impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
             Error::MissingRevision(_) => write!(f, "Revision not found"),
+            Error::CollectedRevision(_) => write!(f, "Revision was garbage collected"),
             Error::MalformedDelta { delta_len, rev_len } => {
                 write!(f, "Delta base_len {} does not match revision length {}", delta_len, rev_len)
             }
@@ -1108,7 +1286,7 @@ mod tests {
         let bad_rev = RevToken::default();
         engine.edit_rev(1, 1, first_rev, build_delta_1());
         let d = engine.try_delta_rev_head(bad_rev);
-        assert!(d.is_err());
+        assert!(matches!(d, Err(Error::MissingRevision(rev)) if rev == bad_rev));
     }
 
     #[test]
@@ -1281,6 +1459,138 @@ mod tests {
         assert_eq!("ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz", String::from(engine.get_head()));
     }
 
+    /// Inserts "a", "b", ... at the start of `TEST_STR`, in undo groups 1, 2, ...
+    /// Returns the engine and the revision after each edit.
+    fn engine_with_groups(n: usize) -> (Engine, Vec<RevToken>) {
+        let mut engine = Engine::new(Rope::from(TEST_STR));
+        let mut revs = Vec::new();
+        for i in 0..n {
+            let c = ((b'a' + i as u8) as char).to_string();
+            let d = Delta::simple_edit(Interval::new(0,0), Rope::from(c), engine.get_head().len());
+            let head = engine.get_head_rev_id().token();
+            engine.edit_rev(1, i+1, head, d);
+            revs.push(engine.get_head_rev_id().token());
+        }
+        (engine, revs)
+    }
+
+    #[test]
+    fn gc_before_retains_rev() {
+        let (mut engine, revs) = engine_with_groups(5);
+        // a plugin still holds the revision after the second edit
+        let held = revs[1];
+        let held_text = String::from(&engine.get_rev(held).unwrap());
+        assert_eq!(format!("ba{}", TEST_STR), held_text);
+        let gc = engine.gc_before(held, RetentionPolicy::KeepUndoGroups(0), &BTreeSet::new()).unwrap();
+        assert_eq!(gc, [0, 1].iter().cloned().collect::<BTreeSet<_>>());
+        assert!(engine.get_rev(revs[0]).is_none());
+        assert_eq!(held_text, String::from(&engine.get_rev(held).unwrap()));
+        let d = engine.try_delta_rev_head(held).unwrap();
+        assert_eq!(String::from(engine.get_head()), d.apply_to_string(&held_text));
+
+        // once the plugin is done with it, it can be collected
+        let gc = engine.gc_before(revs[4], RetentionPolicy::KeepUndoGroups(0), &BTreeSet::new()).unwrap();
+        assert_eq!(gc, [2, 3, 4].iter().cloned().collect::<BTreeSet<_>>());
+        assert!(engine.try_delta_rev_head(held).is_err());
+        assert_eq!(format!("edcba{}", TEST_STR), String::from(engine.get_head()));
+        engine.undo([5].iter().cloned().collect());
+        assert_eq!(format!("dcba{}", TEST_STR), String::from(engine.get_head()));
+    }
+
+    #[test]
+    fn try_delta_rev_head_collected_token() {
+        let (mut engine, revs) = engine_with_groups(3);
+        engine.gc(&[0, 1].iter().cloned().collect());
+        let d = engine.try_delta_rev_head(revs[0]);
+        assert!(matches!(d, Err(Error::CollectedRevision(rev)) if rev == revs[0]));
+        assert!(matches!(engine.try_delta_rev_head(1234), Err(Error::MissingRevision(1234))));
+        assert!(engine.try_delta_rev_head(revs[2]).is_ok());
+    }
+
+    #[test]
+    fn collected_revs_are_bounded() {
+        let (mut engine, revs) = engine_with_groups(5);
+        engine.gc(&[1, 2, 3].iter().cloned().collect());
+        // one entry for the initial revision's session and one for the edits
+        assert_eq!(2, engine.collected_revs.len());
+        engine.gc(&[4].iter().cloned().collect());
+        assert_eq!(2, engine.collected_revs.len());
+        for &rev in &revs[..4] {
+            assert!(matches!(engine.try_delta_rev_head(rev), Err(Error::CollectedRevision(_))));
+        }
+        assert!(engine.try_delta_rev_head(revs[4]).is_ok());
+        assert!(matches!(engine.try_delta_rev_head(1234), Err(Error::MissingRevision(1234))));
+    }
+
+    #[test]
+    fn gc_before_undone_since() {
+        let (mut engine, revs) = engine_with_groups(3);
+        let held = revs[2];
+        engine.undo([1].iter().cloned().collect());
+        // group 1 was undone after the held revision, which still contains it
+        let gc = engine.gc_before(held, RetentionPolicy::KeepUndoGroups(0), &BTreeSet::new()).unwrap();
+        assert_eq!(gc, [0, 2].iter().cloned().collect::<BTreeSet<_>>());
+        let d = engine.try_delta_rev_head(held).unwrap();
+        assert_eq!(format!("cb{}", TEST_STR), d.apply_to_string(&format!("cba{}", TEST_STR)));
+        engine.undo(BTreeSet::new());
+        assert_eq!(format!("cba{}", TEST_STR), String::from(engine.get_head()));
+    }
+
+    #[test]
+    fn gc_before_policies() {
+        let (mut engine, revs) = engine_with_groups(5);
+        let keep: BTreeSet<usize> = [1].iter().cloned().collect();
+        let gc = engine.gc_before(revs[4], RetentionPolicy::KeepUndoGroups(2), &keep).unwrap();
+        assert_eq!(gc, [0, 2, 3].iter().cloned().collect::<BTreeSet<_>>());
+        engine.undo([1, 4].iter().cloned().collect());
+        assert_eq!(format!("ecb{}", TEST_STR), String::from(engine.get_head()));
+
+        let (mut engine, revs) = engine_with_groups(5);
+        let gc = engine.gc_before(revs[4], RetentionPolicy::KeepLast(3), &BTreeSet::new()).unwrap();
+        assert_eq!(gc, [0, 1, 2].iter().cloned().collect::<BTreeSet<_>>());
+        assert!(engine.get_rev(revs[1]).is_none());
+        assert!(engine.get_rev(revs[2]).is_some());
+
+        let bad_rev = RevToken::default();
+        assert!(engine.gc_before(bad_rev, RetentionPolicy::KeepLast(0), &BTreeSet::new()).is_err());
+    }
+
+    #[test]
+    fn max_undo_depth() {
+        let (mut engine, revs) = engine_with_groups(3);
+        engine.set_max_undo_depth(2);
+        // the initial contents and "a" can no longer be undone
+        assert!(engine.get_rev(revs[0]).is_none());
+        assert!(engine.get_rev(revs[1]).is_some());
+
+        // a plugin still holds the head revision
+        let held = revs[2];
+        let held_text = format!("cba{}", TEST_STR);
+        engine.hold_rev(held).unwrap();
+        for (i, c) in ["d", "e", "f"].iter().enumerate() {
+            let d = Delta::simple_edit(Interval::new(0,0), Rope::from(*c), engine.get_head().len());
+            let head = engine.get_head_rev_id().token();
+            engine.edit_rev(1, i+4, head, d);
+        }
+        assert!(engine.get_rev(revs[1]).is_none());
+        let d = engine.try_delta_rev_head(held).unwrap();
+        assert_eq!(String::from(engine.get_head()), d.apply_to_string(&held_text));
+
+        // once it is released, only the last two groups are kept
+        engine.release_rev(held);
+        assert!(matches!(engine.try_delta_rev_head(held), Err(Error::CollectedRevision(_))));
+        assert_eq!(format!("fedcba{}", TEST_STR), String::from(engine.get_head()));
+        engine.undo([6].iter().cloned().collect());
+        assert_eq!(format!("edcba{}", TEST_STR), String::from(engine.get_head()));
+        engine.undo([5, 6].iter().cloned().collect());
+        assert_eq!(format!("dcba{}", TEST_STR), String::from(engine.get_head()));
+        // undoing a collected group does nothing
+        engine.undo([3, 5, 6].iter().cloned().collect());
+        assert_eq!(format!("dcba{}", TEST_STR), String::from(engine.get_head()));
+        engine.undo(BTreeSet::new());
+        assert_eq!(format!("fedcba{}", TEST_STR), String::from(engine.get_head()));
+    }
+
     fn basic_rev(i: usize) -> RevId {
         RevId { session1: 1, session2: 0, num: i as u32 }
     }
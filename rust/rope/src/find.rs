@@ -18,13 +18,15 @@ use std::cmp::min;
 
 use memchr::{memchr, memchr2, memchr3};
 
+use crate::interval::Interval;
 use crate::rope::BaseMetric;
 use crate::rope::LinesRaw;
-use crate::rope::RopeInfo;
+use crate::rope::{Rope, RopeInfo};
 use crate::tree::Cursor;
 use regex::Regex;
 use std::borrow::Cow;
 use std::str;
+use xi_unicode::full_case_fold;
 
 /// The result of a [`find`][find] operation.
 ///
@@ -46,9 +48,8 @@ pub enum FindResult {
 pub enum CaseMatching {
     /// Require an exact codepoint-for-codepoint match (implies case sensitivity).
     Exact,
-    /// Case insensitive match. Guaranteed to work for the ASCII case, and
-    /// reasonably well otherwise (it is currently defined in terms of the
-    /// `to_lowercase` methods in the Rust standard library).
+    /// Case insensitive match, comparing the Unicode full case folding of the
+    /// pattern and the text. For example, `ß` matches `ss` and `SS`.
     CaseInsensitive,
 }
 
@@ -76,6 +77,59 @@ pub fn find(
     }
 }
 
+/// An iterator over the matches of a pattern in a rope, in order.
+///
+/// Matches are found lazily, each call to `next` scanning only as far as the
+/// next match, so that searching a large document doesn't have to run to
+/// completion up front. Matches don't overlap: of two overlapping
+/// candidates, the one that starts first is reported.
+///
+/// As with [`find`][find], the `regex` parameter controls whether the pattern
+/// is a regular expression; `pat` should then be its source.
+///
+/// [find]: fn.find.html
+pub struct RopeFinder<'a> {
+    text: &'a Rope,
+    cursor: Cursor<'a, RopeInfo>,
+    lines: LinesRaw<'a>,
+    cm: CaseMatching,
+    pat: &'a str,
+    regex: Option<&'a Regex>,
+}
+
+impl<'a> RopeFinder<'a> {
+    pub fn new(
+        text: &'a Rope,
+        pat: &'a str,
+        cm: CaseMatching,
+        regex: Option<&'a Regex>,
+    ) -> RopeFinder<'a> {
+        RopeFinder { text, cursor: Cursor::new(text, 0), lines: text.lines_raw(..), cm, pat, regex }
+    }
+
+    /// The offset the search will continue from.
+    pub fn pos(&self) -> usize {
+        self.cursor.pos()
+    }
+}
+
+impl<'a> Iterator for RopeFinder<'a> {
+    type Item = Interval;
+
+    fn next(&mut self) -> Option<Interval> {
+        let start = find(&mut self.cursor, &mut self.lines, self.cm, self.pat, self.regex)?;
+        let end = self.cursor.pos();
+        if start == end {
+            // an empty regex match; move on, so that it isn't found again
+            let next = self.text.next_codepoint_offset(end).unwrap_or_else(|| self.text.len());
+            self.cursor.set(next);
+        }
+        // regex matching consumes whole lines, which have to start at the cursor
+        self.lines = self.text.lines_raw(self.cursor.pos()..);
+        Some(Interval::new(start, end))
+    }
+}
+
 /// A variant of [`find`][find] that makes a bounded amount of progress, then either
 /// returns or suspends (returning `TryAgain`).
 ///
@@ -118,27 +172,32 @@ pub fn find_progress(
                     find_progress_iter(cursor, lines, pat, scanner, matcher, num_steps)
                 }
                 CaseMatching::CaseInsensitive => {
-                    let pat_lower = pat.to_lowercase();
-                    let b = pat_lower.as_bytes()[0];
+                    let pat_folded: String = pat.chars().flat_map(full_case_fold).collect();
+                    let b = pat_folded.as_bytes()[0];
                     let matcher = compare_cursor_str_casei;
                     if b == b'i' {
                         // 0xC4 is first utf-8 byte of 'İ'
                         let scanner = |s: &str| memchr3(b'i', b'I', 0xC4, s.as_bytes());
-                        find_progress_iter(cursor, lines, &pat_lower, scanner, matcher, num_steps)
+                        find_progress_iter(cursor, lines, &pat_folded, scanner, matcher, num_steps)
                     } else if b == b'k' {
                         // 0xE2 is first utf-8 byte of u+212A (kelvin sign)
                         let scanner = |s: &str| memchr3(b'k', b'K', 0xE2, s.as_bytes());
-                        find_progress_iter(cursor, lines, &pat_lower, scanner, matcher, num_steps)
+                        find_progress_iter(cursor, lines, &pat_folded, scanner, matcher, num_steps)
+                    } else if b"afhjstwy".contains(&b) {
+                        // these are also the start of the folding of non-ASCII
+                        // code points, such as 'ß' and 'ﬁ'
+                        let scanner = |s: &str| scan_folded(b as char, s);
+                        find_progress_iter(cursor, lines, &pat_folded, scanner, matcher, num_steps)
                     } else if (b'a'..=b'z').contains(&b) {
                         let scanner = |s: &str| memchr2(b, b - 0x20, s.as_bytes());
-                        find_progress_iter(cursor, lines, &pat_lower, scanner, matcher, num_steps)
+                        find_progress_iter(cursor, lines, &pat_folded, scanner, matcher, num_steps)
                     } else if b < 0x80 {
                         let scanner = |s: &str| memchr(b, s.as_bytes());
-                        find_progress_iter(cursor, lines, &pat_lower, scanner, matcher, num_steps)
+                        find_progress_iter(cursor, lines, &pat_folded, scanner, matcher, num_steps)
                     } else {
-                        let c = pat.chars().next().unwrap();
-                        let scanner = |s: &str| scan_lowercase(c, s);
-                        find_progress_iter(cursor, lines, &pat_lower, scanner, matcher, num_steps)
+                        let c = pat_folded.chars().next().unwrap();
+                        let scanner = |s: &str| scan_folded(c, s);
+                        find_progress_iter(cursor, lines, &pat_folded, scanner, matcher, num_steps)
                     }
                 }
             }
@@ -232,8 +291,8 @@ pub fn compare_cursor_str(
     None
 }
 
-/// Like `compare_cursor_str` but case invariant (using to_lowercase() to
-/// normalize both strings before comparison). Returns the start position
+/// Like `compare_cursor_str` but case invariant. `pat` must already be case
+/// folded; the text is folded as it is compared. Returns the start position
 /// of the match.
 pub fn compare_cursor_str_casei(
     cursor: &mut Cursor<RopeInfo>,
@@ -245,8 +304,8 @@ pub fn compare_cursor_str_casei(
     let mut c = pat_iter.next().unwrap();
     loop {
         if let Some(rope_c) = cursor.next_codepoint() {
-            for lc_c in rope_c.to_lowercase() {
-                if c != lc_c {
+            for folded_c in full_case_fold(rope_c) {
+                if c != folded_c {
                     cursor.set(start_position);
                     cursor.next::<BaseMetric>();
                     return None;
@@ -323,10 +382,10 @@ pub fn is_multiline_regex(regex: &str) -> bool {
     multiline_indicators.iter().any(|&i| regex.contains(i))
 }
 
-/// Scan for a codepoint that, after conversion to lowercase, matches the probe.
-fn scan_lowercase(probe: char, s: &str) -> Option<usize> {
+/// Scan for a codepoint whose case folding starts with the probe.
+fn scan_folded(probe: char, s: &str) -> Option<usize> {
     for (i, c) in s.char_indices() {
-        if c.to_lowercase().next().unwrap() == probe {
+        if full_case_fold(c).next() == Some(probe) {
             return Some(i);
         }
     }
@@ -442,6 +501,34 @@ mod tests {
         assert_eq!(find(&mut c, &mut raw_lines, CaseInsensitive, "\u{212A}", None), Some(0));
     }
 
+    #[test]
+    fn find_casei_expansions() {
+        let a = Rope::from("Straße STRASSE strasse");
+        let mut c = Cursor::new(&a, 0);
+        let mut raw_lines = a.lines_raw(0..a.len());
+        assert_eq!(find(&mut c, &mut raw_lines, CaseInsensitive, "strasse", None), Some(0));
+        assert_eq!(c.pos(), 7);
+        assert_eq!(find(&mut c, &mut raw_lines, CaseInsensitive, "strasse", None), Some(8));
+        assert_eq!(find(&mut c, &mut raw_lines, CaseInsensitive, "strasse", None), Some(16));
+        assert_eq!(find(&mut c, &mut raw_lines, CaseInsensitive, "strasse", None), None);
+        c.set(0);
+        assert_eq!(find(&mut c, &mut raw_lines, CaseInsensitive, "STRAẞE", None), Some(0));
+        assert_eq!(find(&mut c, &mut raw_lines, CaseInsensitive, "STRAẞE", None), Some(8));
+        c.set(0);
+        assert_eq!(find(&mut c, &mut raw_lines, CaseInsensitive, "ß", None), Some(4));
+        assert_eq!(find(&mut c, &mut raw_lines, CaseInsensitive, "ß", None), Some(12));
+
+        let a = Rope::from("an ofﬁce, an OFFICE");
+        let mut c = Cursor::new(&a, 0);
+        assert_eq!(find(&mut c, &mut raw_lines, CaseInsensitive, "Office", None), Some(3));
+        assert_eq!(find(&mut c, &mut raw_lines, CaseInsensitive, "Office", None), Some(15));
+
+        let a = Rope::from("ΣΊΣΥΦΟΣ");
+        let mut c = Cursor::new(&a, 0);
+        assert_eq!(find(&mut c, &mut raw_lines, CaseInsensitive, "σίσυφος", None), Some(0));
+        assert_eq!(c.pos(), a.len());
+    }
+
     #[test]
     fn find_casei_0xc4() {
         let a = Rope::from("\u{0100}I");
@@ -739,6 +826,86 @@ mod tests {
         assert!(compare_cursor_str(&mut c, &mut raw_lines, "Löwe 老虎 Léopardfoo").is_none());
     }
 
+    /// Builds a rope with a leaf for each of the 600 byte chunks of `s`.
+    fn rope_of_chunks(s: &str) -> Rope {
+        let mut rope = Rope::from("");
+        for i in (0..s.len()).step_by(600) {
+            rope = rope + Rope::from(&s[i..min(i + 600, s.len())]);
+        }
+        let mut cursor = Cursor::new(&rope, 0);
+        let mut n_leaves = 1;
+        while cursor.next_leaf().is_some() {
+            n_leaves += 1;
+        }
+        assert_eq!(n_leaves, (s.len() + 599) / 600);
+        rope
+    }
+
+    fn finder_matches(
+        text: &Rope,
+        pat: &str,
+        cm: CaseMatching,
+        regex: Option<&Regex>,
+    ) -> Vec<usize> {
+        RopeFinder::new(text, pat, cm, regex).map(|iv| iv.start()).collect()
+    }
+
+    #[test]
+    fn rope_finder_small() {
+        let a = Rope::from("Löwe 老虎 Léopard");
+        assert_eq!(finder_matches(&a, "L", Exact, None), vec![0, 13]);
+        assert_eq!(finder_matches(&a, "l", CaseInsensitive, None), vec![0, 13]);
+        assert_eq!(finder_matches(&a, "老虎", Exact, None), vec![6]);
+        assert_eq!(finder_matches(&a, "Tiger", Exact, None), Vec::<usize>::new());
+        assert_eq!(finder_matches(&a, "", Exact, None), Vec::<usize>::new());
+        let intervals = RopeFinder::new(&a, "LÉOPARD", CaseInsensitive, None).collect::<Vec<_>>();
+        assert_eq!(intervals, vec![Interval::new(13, a.len())]);
+
+        let regex = RegexBuilder::new("o+").build().unwrap();
+        let b = Rope::from("foo\nbar boo\no");
+        let intervals = RopeFinder::new(&b, "o+", Exact, Some(&regex)).collect::<Vec<_>>();
+        assert_eq!(
+            intervals,
+            vec![Interval::new(1, 3), Interval::new(9, 11), Interval::new(12, 13)]
+        );
+    }
+
+    #[test]
+    fn rope_finder_leaf_boundaries() {
+        // the chunks are 600 bytes, so candidates are split between leaves at
+        // every offset
+        let s = "xxxabaabaab".repeat(1200);
+        let a = rope_of_chunks(&s);
+        for pat in &["abaab", "aab", "ba", "xab", "baabx", "abaabaabxxxab"] {
+            let expected = s.match_indices(pat).map(|(i, _)| i).collect::<Vec<_>>();
+            assert!(!expected.is_empty());
+            assert_eq!(finder_matches(&a, pat, Exact, None), expected, "{}", pat);
+        }
+
+        let upper = rope_of_chunks(&s.to_uppercase());
+        let expected = s.match_indices("abaab").map(|(i, _)| i).collect::<Vec<_>>();
+        assert_eq!(finder_matches(&upper, "abaab", CaseInsensitive, None), expected);
+
+        let regex = RegexBuilder::new("ba+b").build().unwrap();
+        let expected = regex.find_iter(&s).map(|m| m.start()).collect::<Vec<_>>();
+        assert_eq!(finder_matches(&a, "ba+b", Exact, Some(&regex)), expected);
+    }
+
+    #[test]
+    fn rope_finder_casei_expansion_across_leaves() {
+        // 'ß' folds to "ss", which can continue in the next leaf
+        let mut s = "x".repeat(598);
+        s.push('ß');
+        s.push('S');
+        s.push_str(&"y".repeat(598));
+        s.push('s');
+        s.push('S');
+        s.push_str(&"z".repeat(599));
+        let a = rope_of_chunks(&s);
+        assert_eq!(finder_matches(&a, "SS", CaseInsensitive, None), vec![598, 1199]);
+        assert_eq!(finder_matches(&a, "ßs", CaseInsensitive, None), vec![598]);
+    }
+
     #[test]
     fn compare_cursor_str_medium() {
         let mut s = String::new();
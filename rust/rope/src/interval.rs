@@ -115,6 +115,40 @@ impl Interval {
         Interval { start, end }
     }
 
+    /// Returns the intersection of the two intervals, or `None` if it is
+    /// empty. As intervals are half-open, intervals that touch without
+    /// overlapping, such as [1, 3) and [3, 5), have no intersection, and
+    /// neither does a zero-length interval with any interval.
+    pub fn checked_intersect(&self, other: Interval) -> Option<Interval> {
+        let start = max(self.start, other.start);
+        let end = min(self.end, other.end);
+        if start < end {
+            Some(Interval { start, end })
+        } else {
+            None
+        }
+    }
+
+    /// Returns the union of the two intervals, or `None` if there is a gap
+    /// between them, so that it is not an interval. As intervals are
+    /// half-open, intervals that touch, such as [1, 3) and [3, 5), have the
+    /// union [1, 5). A zero-length interval contains nothing, so the union
+    /// with it is the other interval.
+    pub fn checked_union(&self, other: Interval) -> Option<Interval> {
+        if self.is_empty() {
+            return Some(other);
+        }
+        if other.is_empty() {
+            return Some(*self);
+        }
+        if max(self.start, other.start) > min(self.end, other.end) {
+            return None;
+        }
+        let start = min(self.start, other.start);
+        let end = max(self.end, other.end);
+        Some(Interval { start, end })
+    }
+
     // the first half of self - other
     pub fn prefix(&self, other: Interval) -> Interval {
         Interval { start: min(self.start, other.start), end: min(self.end, other.start) }
@@ -266,6 +300,46 @@ mod tests {
         assert!(Interval::new(1, 2).intersect(Interval::new(2, 43)).is_empty());
     }
 
+    #[test]
+    fn checked_intersect() {
+        let iv = |start, end| Interval::new(start, end);
+        // overlapping
+        assert_eq!(iv(1, 3).checked_intersect(iv(2, 4)), Some(iv(2, 3)));
+        assert_eq!(iv(2, 4).checked_intersect(iv(1, 3)), Some(iv(2, 3)));
+        // nested
+        assert_eq!(iv(1, 5).checked_intersect(iv(2, 3)), Some(iv(2, 3)));
+        assert_eq!(iv(1, 5).checked_intersect(iv(1, 5)), Some(iv(1, 5)));
+        // adjacent
+        assert_eq!(iv(1, 3).checked_intersect(iv(3, 5)), None);
+        assert_eq!(iv(3, 5).checked_intersect(iv(1, 3)), None);
+        // disjoint
+        assert_eq!(iv(1, 2).checked_intersect(iv(4, 5)), None);
+        // zero-length
+        assert_eq!(iv(2, 2).checked_intersect(iv(1, 3)), None);
+        assert_eq!(iv(1, 3).checked_intersect(iv(3, 3)), None);
+        assert_eq!(iv(2, 2).checked_intersect(iv(2, 2)), None);
+    }
+
+    #[test]
+    fn checked_union() {
+        let iv = |start, end| Interval::new(start, end);
+        // overlapping
+        assert_eq!(iv(1, 3).checked_union(iv(2, 4)), Some(iv(1, 4)));
+        assert_eq!(iv(2, 4).checked_union(iv(1, 3)), Some(iv(1, 4)));
+        // nested
+        assert_eq!(iv(1, 5).checked_union(iv(2, 3)), Some(iv(1, 5)));
+        // adjacent
+        assert_eq!(iv(1, 3).checked_union(iv(3, 5)), Some(iv(1, 5)));
+        assert_eq!(iv(3, 5).checked_union(iv(1, 3)), Some(iv(1, 5)));
+        // disjoint
+        assert_eq!(iv(1, 2).checked_union(iv(4, 5)), None);
+        assert_eq!(iv(4, 5).checked_union(iv(1, 2)), None);
+        // zero-length, wherever it is
+        assert_eq!(iv(8, 8).checked_union(iv(1, 3)), Some(iv(1, 3)));
+        assert_eq!(iv(1, 3).checked_union(iv(2, 2)), Some(iv(1, 3)));
+        assert_eq!(iv(2, 2).checked_union(iv(5, 5)), Some(iv(5, 5)));
+    }
+
     #[test]
     fn prefix() {
         assert_eq!(Interval::new(1, 2), Interval::new(1, 4).prefix(Interval::new(2, 3)));
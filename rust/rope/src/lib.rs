@@ -28,6 +28,7 @@ extern crate bytecount;
 extern crate memchr;
 extern crate regex;
 extern crate unicode_segmentation;
+extern crate xi_unicode;
 
 #[cfg(feature = "serde")]
 #[macro_use]
@@ -50,11 +51,12 @@ pub mod rope;
 #[cfg(feature = "serde")]
 mod serde_impls;
 pub mod spans;
-#[cfg(test)]
-mod test_helpers;
+/// Helpers for the tests of this crate and of the crates using it.
+#[doc(hidden)]
+pub mod test_helpers;
 pub mod tree;
 
 pub use crate::delta::{Builder as DeltaBuilder, Delta, DeltaElement, Transformer};
 pub use crate::interval::Interval;
-pub use crate::rope::{LinesMetric, Rope, RopeDelta, RopeInfo};
+pub use crate::rope::{LinesMetric, Rope, RopeBuilder, RopeDelta, RopeInfo};
 pub use crate::tree::{Cursor, Metric};
@@ -315,6 +315,20 @@ impl Subset {
         }
     }
 
+    /// Return the ranges of elements with a non-zero count, as `Interval`s.
+    /// Unlike `range_iter`, adjacent ranges with different non-zero counts
+    /// are merged, so no two returned ranges touch.
+    pub fn to_ranges(&self) -> Vec<Interval> {
+        let mut ranges: Vec<Interval> = Vec::new();
+        for (start, end) in self.range_iter(CountMatcher::NonZero) {
+            match ranges.last_mut() {
+                Some(last) if last.end() == start => *last = Interval::new(last.start(), end),
+                _ => ranges.push(Interval::new(start, end)),
+            }
+        }
+        ranges
+    }
+
     /// Find the complement of this Subset. Every 0-count element will have a
     /// count of 1 and every non-zero element will have a count of 0.
     pub fn complement(&self) -> Subset {
@@ -368,6 +382,21 @@ impl fmt::Debug for Subset {
     }
 }
 
+impl fmt::Display for Subset {
+    /// Prints the ranges returned by `to_ranges`, such as `[0,3)[5,8)`,
+    /// or `{}` if no element is included.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let ranges = self.to_ranges();
+        if ranges.is_empty() {
+            return write!(f, "{{}}");
+        }
+        for iv in ranges {
+            write!(f, "[{},{})", iv.start(), iv.end())?;
+        }
+        Ok(())
+    }
+}
+
 pub struct RangeIter<'a> {
     seg_iter: slice::Iter<'a, Segment>,
     pub consumed: usize,
@@ -509,6 +538,7 @@ impl<'a> Mapper<'a> {
 
 #[cfg(test)]
 mod tests {
+    use crate::interval::Interval;
     use crate::multiset::*;
     use crate::test_helpers::find_deletions;
 
@@ -548,6 +578,39 @@ mod tests {
         assert!(s.is_empty());
     }
 
+    #[test]
+    fn to_ranges() {
+        let empty = Subset::new(10);
+        assert_eq!(empty.to_ranges(), vec![]);
+        assert_eq!(empty.to_string(), "{}");
+        assert_eq!(SubsetBuilder::new().build().to_string(), "{}");
+
+        let mut sb = SubsetBuilder::new();
+        sb.add_range(0, 10, 1);
+        let full = sb.build();
+        assert_eq!(full.to_ranges(), vec![Interval::new(0, 10)]);
+        assert_eq!(full.to_string(), "[0,10)");
+
+        let mut sb = SubsetBuilder::new();
+        sb.add_range(0, 3, 1);
+        sb.add_range(5, 8, 1);
+        sb.pad_to_len(10);
+        let gaps = sb.build();
+        assert_eq!(gaps.to_ranges(), vec![Interval::new(0, 3), Interval::new(5, 8)]);
+        assert_eq!(gaps.to_string(), "[0,3)[5,8)");
+        assert_eq!(gaps.complement().to_string(), "[3,5)[8,10)");
+
+        // ranges with different counts are merged
+        let mut sb = SubsetBuilder::new();
+        sb.add_range(2, 4, 1);
+        sb.add_range(4, 6, 2);
+        sb.add_range(6, 7, 1);
+        sb.add_range(8, 9, 3);
+        let counts = sb.build();
+        assert_eq!(counts.to_ranges(), vec![Interval::new(2, 7), Interval::new(8, 9)]);
+        assert_eq!(counts.to_string(), "[2,7)[8,9)");
+    }
+
     #[test]
     fn test_find_deletions() {
         let substr = "015ABDFHJOPQVYdfgloprsuvz";
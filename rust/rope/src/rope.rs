@@ -20,6 +20,7 @@
 use std::borrow::Cow;
 use std::cmp::{max, min, Ordering};
 use std::fmt;
+use std::mem;
 use std::ops::Add;
 use std::str::{self, FromStr};
 use std::string::ParseError;
@@ -391,6 +392,23 @@ impl Rope {
         self.edit(iv, new)
     }
 
+    /// Appends `s` to the end of the rope.
+    ///
+    /// Only the nodes along the right edge of the tree are rebuilt. To build
+    /// a rope from many pieces, a [`RopeBuilder`](struct.RopeBuilder.html)
+    /// is more efficient still.
+    ///
+    /// Time complexity: O(log n + s.len())
+    pub fn push_str(&mut self, s: &str) {
+        if s.is_empty() {
+            return;
+        }
+        let mut b = TreeBuilder::new();
+        b.push(mem::take(self));
+        b.push_str(s);
+        *self = b.build();
+    }
+
     /// Returns a new Rope with the contents of the provided range.
     pub fn slice<T: IntervalBounds>(&self, iv: T) -> Rope {
         self.subseq(iv)
@@ -487,6 +505,48 @@ impl Rope {
         }
     }
 
+    /// Returns the line and column of the byte index `offset`, where the
+    /// column is the offset in bytes from the start of the line.
+    ///
+    /// An offset just after a newline is at column 0 of the next line. An
+    /// `offset` past the end of the rope is clamped to `self.len()`, and one
+    /// inside a codepoint is moved back to the start of that codepoint.
+    ///
+    /// Time complexity: O(log n)
+    pub fn line_col_of_offset(&self, offset: usize) -> (usize, usize) {
+        let offset = offset.min(self.len());
+        let offset = self.at_or_prev_codepoint_boundary(offset).unwrap_or(0);
+        let line = self.line_of_offset(offset);
+        (line, offset - self.offset_of_line(line))
+    }
+
+    /// Returns the byte index of the given line and column, where the column
+    /// is the offset in bytes from the start of the line.
+    ///
+    /// A `col` beyond the end of the line is clamped to the end of the line,
+    /// before its line ending (`\n` or `\r\n`), so that it never refers to the
+    /// next line. A `line` beyond the last line is clamped to the last line,
+    /// and a `col` inside a codepoint is moved back to the start of that
+    /// codepoint.
+    ///
+    /// Time complexity: O(log n)
+    pub fn offset_of_line_col(&self, line: usize, col: usize) -> usize {
+        let last_line = self.measure::<LinesMetric>();
+        let line = line.min(last_line);
+        let line_start = self.offset_of_line(line);
+        let line_end = if line < last_line {
+            let next_start = self.offset_of_line(line + 1);
+            match next_start - line_start {
+                n if n >= 2 && self.byte_at(next_start - 2) == b'\r' => next_start - 2,
+                _ => next_start - 1,
+            }
+        } else {
+            self.len()
+        };
+        let offset = line_start.saturating_add(col).min(line_end);
+        self.at_or_prev_codepoint_boundary(offset).unwrap_or(line_start)
+    }
+
     /// Returns an iterator over chunks of the rope.
     ///
     /// Each chunk is a `&str` slice borrowed from the rope's storage. The size
@@ -527,6 +587,19 @@ impl Rope {
         Lines { inner: self.lines_raw(range) }
     }
 
+    /// An iterator over the raw lines, from last to first. This yields the same
+    /// lines as [`lines_raw`](#method.lines_raw), in reverse order.
+    pub fn lines_raw_rev<T: IntervalBounds>(&self, range: T) -> LinesRawRev {
+        let Interval { start, end } = range.into_interval(self.len());
+        LinesRawRev { cursor: Cursor::new(self, end), start, end }
+    }
+
+    /// An iterator over the lines of a rope, from last to first. This yields the
+    /// same lines as [`lines`](#method.lines), in reverse order.
+    pub fn lines_rev<T: IntervalBounds>(&self, range: T) -> LinesRev {
+        LinesRev { inner: self.lines_raw_rev(range) }
+    }
+
     // callers should be encouraged to use cursor instead
     pub fn byte_at(&self, offset: usize) -> u8 {
         let cursor = Cursor::new(self, offset);
@@ -595,6 +668,61 @@ impl TreeBuilder<RopeInfo> {
     }
 }
 
+/// A builder for creating a rope from many pieces of text, such as the chunks
+/// of a file as it is read from disk.
+///
+/// Text is packed into full leaves as it arrives, and the tree is balanced
+/// once, when the rope is built; so building takes time linear in the total
+/// length of the text, however it is split.
+///
+/// # Examples
+///
+/// ```rust
+/// # use xi_rope::RopeBuilder;
+/// let mut b = RopeBuilder::new();
+/// b.push_str("hello ");
+/// b.push_str("world");
+/// assert_eq!(String::from(b.build()), "hello world");
+/// ```
+pub struct RopeBuilder {
+    tree: TreeBuilder<RopeInfo>,
+    /// Text not yet pushed on the tree: less than a leaf, unless it was just
+    /// appended to.
+    pending: String,
+}
+
+impl RopeBuilder {
+    /// A new, empty builder.
+    pub fn new() -> RopeBuilder {
+        RopeBuilder { tree: TreeBuilder::new(), pending: String::new() }
+    }
+
+    /// Appends `s` to the rope being built.
+    pub fn push_str(&mut self, s: &str) {
+        self.pending.push_str(s);
+        let mut start = 0;
+        while self.pending.len() - start > MAX_LEAF {
+            let rest = &self.pending[start..];
+            let splitpoint = find_leaf_split_for_bulk(rest);
+            self.tree.push_leaf(rest[..splitpoint].to_owned());
+            start += splitpoint;
+        }
+        self.pending.drain(..start);
+    }
+
+    /// Builds the rope from all the text that has been pushed, in order.
+    pub fn build(mut self) -> Rope {
+        self.tree.push_str(&self.pending);
+        self.tree.build()
+    }
+}
+
+impl Default for RopeBuilder {
+    fn default() -> RopeBuilder {
+        RopeBuilder::new()
+    }
+}
+
 impl<T: AsRef<str>> From<T> for Rope {
     fn from(s: T) -> Rope {
         Rope::from_str(s.as_ref()).unwrap()
@@ -672,60 +800,87 @@ impl<'a> Cursor<'a, RopeInfo> {
         self.get_leaf().and_then(|(l, off)| l[off..].chars().next())
     }
 
+    /// Moves the cursor to the end of the extended grapheme cluster after
+    /// it, so that emoji sequences joined with ZWJ and the like are passed
+    /// over whole. Returns the new position, or `None`, leaving the cursor
+    /// where it was, if it is at the end of the text.
     pub fn next_grapheme(&mut self) -> Option<usize> {
-        let (mut l, mut offset) = self.get_leaf()?;
+        let pos = self.pos();
+        let next = self.next_grapheme_boundary();
+        self.set(next.unwrap_or(pos));
+        next
+    }
+
+    /// Moves the cursor to the start of the extended grapheme cluster before
+    /// it. Returns the new position, or `None`, leaving the cursor where it
+    /// was, if it is at the start of the text.
+    pub fn prev_grapheme(&mut self) -> Option<usize> {
+        let pos = self.pos();
+        let prev = self.prev_grapheme_boundary();
+        self.set(prev.unwrap_or(pos));
+        prev
+    }
+
+    /// Returns the grapheme boundary after the cursor. Leaves the cursor
+    /// anywhere.
+    ///
+    /// A `GraphemeCursor` resuming with more context doesn't always reach the
+    /// same decision as one given the whole text at once (a ZWJ at the start
+    /// of a leaf splits an emoji sequence), so when it needs another leaf the
+    /// leaves seen so far are joined and the search is started again.
+    fn next_grapheme_boundary(&mut self) -> Option<usize> {
+        let (l, mut offset) = self.get_leaf()?;
         let mut pos = self.pos();
         while offset < l.len() && !l.is_char_boundary(offset) {
             pos -= 1;
             offset -= 1;
         }
-        let mut leaf_offset = pos - offset;
-        let mut c = GraphemeCursor::new(pos, self.total_len(), true);
-        let mut next_boundary = c.next_boundary(l, leaf_offset);
-        while let Err(incomp) = next_boundary {
-            if let GraphemeIncomplete::PreContext(_) = incomp {
-                let (pl, poffset) = self.prev_leaf()?;
-                c.provide_context(pl, self.pos() - poffset);
-            } else if incomp == GraphemeIncomplete::NextChunk {
-                self.set(pos);
-                let (nl, noffset) = self.next_leaf()?;
-                l = nl;
-                leaf_offset = self.pos() - noffset;
-                pos = leaf_offset + nl.len();
-            } else {
-                return None;
+        let mut chunk_start = pos - offset;
+        let mut chunk = Cow::from(l.as_str());
+        loop {
+            let mut c = GraphemeCursor::new(pos, self.total_len(), true);
+            match c.next_boundary(&chunk, chunk_start) {
+                Ok(next) => return next,
+                Err(GraphemeIncomplete::PreContext(_)) => {
+                    self.set(chunk_start);
+                    let (pl, _) = self.prev_leaf()?;
+                    chunk_start -= pl.len();
+                    chunk = Cow::from([pl.as_str(), &chunk].concat());
+                }
+                Err(GraphemeIncomplete::NextChunk) => {
+                    self.set(chunk_start + chunk.len());
+                    let (nl, _) = self.get_leaf()?;
+                    chunk = Cow::from([&chunk, nl.as_str()].concat());
+                }
+                Err(_) => return None,
             }
-            next_boundary = c.next_boundary(l, leaf_offset);
         }
-        next_boundary.unwrap_or(None)
     }
 
-    pub fn prev_grapheme(&mut self) -> Option<usize> {
-        let (mut l, mut offset) = self.get_leaf()?;
+    /// Returns the grapheme boundary before the cursor. Leaves the cursor
+    /// anywhere.
+    fn prev_grapheme_boundary(&mut self) -> Option<usize> {
+        let (l, mut offset) = self.get_leaf()?;
         let mut pos = self.pos();
         while offset < l.len() && !l.is_char_boundary(offset) {
             pos += 1;
             offset += 1;
         }
-        let mut leaf_offset = pos - offset;
-        let mut c = GraphemeCursor::new(pos, l.len() + leaf_offset, true);
-        let mut prev_boundary = c.prev_boundary(l, leaf_offset);
-        while let Err(incomp) = prev_boundary {
-            if let GraphemeIncomplete::PreContext(_) = incomp {
-                let (pl, poffset) = self.prev_leaf()?;
-                c.provide_context(pl, self.pos() - poffset);
-            } else if incomp == GraphemeIncomplete::PrevChunk {
-                self.set(pos);
-                let (pl, poffset) = self.prev_leaf()?;
-                l = pl;
-                leaf_offset = self.pos() - poffset;
-                pos = leaf_offset + pl.len();
-            } else {
-                return None;
+        let mut chunk_start = pos - offset;
+        let mut chunk = Cow::from(l.as_str());
+        loop {
+            let mut c = GraphemeCursor::new(pos, chunk_start + chunk.len(), true);
+            match c.prev_boundary(&chunk, chunk_start) {
+                Ok(prev) => return prev,
+                Err(GraphemeIncomplete::PreContext(_)) | Err(GraphemeIncomplete::PrevChunk) => {
+                    self.set(chunk_start);
+                    let (pl, _) = self.prev_leaf()?;
+                    chunk_start -= pl.len();
+                    chunk = Cow::from([pl.as_str(), &chunk].concat());
+                }
+                Err(_) => return None,
             }
-            prev_boundary = c.prev_boundary(l, leaf_offset);
         }
-        prev_boundary.unwrap_or(None)
     }
 }
 
@@ -779,31 +934,81 @@ pub struct Lines<'a> {
     inner: LinesRaw<'a>,
 }
 
-impl<'a> Iterator for Lines<'a> {
-    type Item = Cow<'a, str>;
-
-    fn next(&mut self) -> Option<Cow<'a, str>> {
-        match self.inner.next() {
-            Some(Cow::Borrowed(mut s)) => {
-                if s.ends_with('\n') {
+/// Removes the line ending, if any, from a raw line.
+fn strip_line_ending(line: Cow<str>) -> Cow<str> {
+    match line {
+        Cow::Borrowed(mut s) => {
+            if s.ends_with('\n') {
+                s = &s[..s.len() - 1];
+                if s.ends_with('\r') {
                     s = &s[..s.len() - 1];
-                    if s.ends_with('\r') {
-                        s = &s[..s.len() - 1];
-                    }
                 }
-                Some(Cow::from(s))
             }
-            Some(Cow::Owned(mut s)) => {
-                if s.ends_with('\n') {
+            Cow::from(s)
+        }
+        Cow::Owned(mut s) => {
+            if s.ends_with('\n') {
+                let _ = s.pop();
+                if s.ends_with('\r') {
                     let _ = s.pop();
-                    if s.ends_with('\r') {
-                        let _ = s.pop();
-                    }
                 }
-                Some(Cow::from(s))
             }
-            None => None,
+            Cow::from(s)
+        }
+    }
+}
+
+impl<'a> Iterator for Lines<'a> {
+    type Item = Cow<'a, str>;
+
+    fn next(&mut self) -> Option<Cow<'a, str>> {
+        self.inner.next().map(strip_line_ending)
+    }
+}
+
+pub struct LinesRawRev<'a> {
+    /// Positioned at `end`, when valid.
+    cursor: Cursor<'a, RopeInfo>,
+    start: usize,
+    /// The end of the lines not yet yielded.
+    end: usize,
+}
+
+impl<'a> Iterator for LinesRawRev<'a> {
+    type Item = Cow<'a, str>;
+
+    fn next(&mut self) -> Option<Cow<'a, str>> {
+        if self.end <= self.start {
+            return None;
+        }
+        let line_start = match self.cursor.prev::<LinesMetric>() {
+            Some(pos) if pos > self.start => pos,
+            _ => self.start,
+        };
+        let line_end = self.end;
+        self.end = line_start;
+
+        // Borrow from the leaf when the whole line is inside it, which is the
+        // common case, rather than seeking from the root.
+        if let Some((leaf, offset)) = self.cursor.get_leaf() {
+            let len = line_end - line_start;
+            if self.cursor.pos() == line_start && offset + len <= leaf.len() {
+                return Some(Cow::from(&leaf[offset..offset + len]));
+            }
         }
+        Some(self.cursor.root().slice_to_cow(line_start..line_end))
+    }
+}
+
+pub struct LinesRev<'a> {
+    inner: LinesRawRev<'a>,
+}
+
+impl<'a> Iterator for LinesRev<'a> {
+    type Item = Cow<'a, str>;
+
+    fn next(&mut self) -> Option<Cow<'a, str>> {
+        self.inner.next().map(strip_line_ending)
     }
 }
 
@@ -861,6 +1066,50 @@ mod tests {
         assert_eq!(String::from(&a).lines().collect::<Vec<_>>(), a.lines(..).collect::<Vec<_>>());
     }
 
+    #[test]
+    fn lines_rev_small() {
+        for s in &["a\nb\nc", "a\nb\n", "\n", "", "a\r\nb\r\nc", "a\r\n\r\n", "a\rb\rc", "\n\na"] {
+            let a = Rope::from(s);
+            let mut raw = a.lines_raw(..).collect::<Vec<_>>();
+            raw.reverse();
+            assert_eq!(raw, a.lines_raw_rev(..).collect::<Vec<_>>());
+            let mut lines = s.lines().collect::<Vec<_>>();
+            lines.reverse();
+            assert_eq!(lines, a.lines_rev(..).collect::<Vec<_>>());
+        }
+
+        let a = Rope::from("one\ntwo\nthree\nfour");
+        assert_eq!(vec!["thr", "two", "ne"], a.lines_rev(1..11).collect::<Vec<_>>());
+        assert_eq!(vec!["three\n", "wo\n"], a.lines_raw_rev(5..14).collect::<Vec<_>>());
+        assert_eq!(0, a.lines_rev(4..4).count());
+    }
+
+    #[test]
+    fn lines_rev_large() {
+        let mut s = String::new();
+        for i in 0..3_000 {
+            s.push_str(&"x".repeat(i % 37));
+            s.push_str(if i % 3 == 0 { "\r\n" } else { "\n" });
+        }
+        // a line spanning several leaves
+        s.push_str(&"y".repeat(3 * MAX_LEAF));
+        s.push_str("\r\nlast");
+        let r = Rope::from(&s);
+
+        let mut lines = s.lines().collect::<Vec<_>>();
+        lines.reverse();
+        assert_eq!(lines, r.lines_rev(..).collect::<Vec<_>>());
+
+        let mut raw = r.lines_raw(..).collect::<Vec<_>>();
+        raw.reverse();
+        assert_eq!(raw, r.lines_raw_rev(..).collect::<Vec<_>>());
+
+        let range = 1234..s.len() - 10;
+        let mut raw = r.lines_raw(range.clone()).collect::<Vec<_>>();
+        raw.reverse();
+        assert_eq!(raw, r.lines_raw_rev(range).collect::<Vec<_>>());
+    }
+
     #[test]
     fn lines_med() {
         let mut a = String::new();
@@ -996,6 +1245,117 @@ mod tests {
         assert_eq!(None, a.next_grapheme_offset(s1.len() * 3 + 4));
     }
 
+    #[test]
+    fn cursor_steps_over_graphemes() {
+        // a family emoji: man, ZWJ, woman, ZWJ, girl
+        let family = "\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}";
+        let s = format!("a{}e\u{301}b", family);
+        let a = Rope::from(&s);
+        let mut cursor = Cursor::new(&a, 0);
+        let mut forward = vec![];
+        while let Some(offset) = cursor.next_grapheme() {
+            assert_eq!(cursor.pos(), offset);
+            forward.push(offset);
+        }
+        let end = s.len();
+        assert_eq!(forward, vec![1, 1 + family.len(), end - 1, end]);
+        assert_eq!(cursor.pos(), end);
+
+        let mut backward = vec![];
+        while let Some(offset) = cursor.prev_grapheme() {
+            assert_eq!(cursor.pos(), offset);
+            backward.push(offset);
+        }
+        assert_eq!(backward, vec![end - 1, 1 + family.len(), 1, 0]);
+        assert_eq!(cursor.pos(), 0);
+    }
+
+    #[test]
+    fn cursor_steps_over_graphemes_across_leaves() {
+        let family = "\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}";
+        let s = format!("x{}", family).repeat(300);
+        let a = Rope::from(&s);
+        // the emoji sequences are split across leaves
+        assert!(a.iter_chunks(..).count() > 1);
+        let mut cursor = Cursor::new(&a, 0);
+        let mut n_graphemes = 0;
+        while cursor.next_grapheme().is_some() {
+            n_graphemes += 1;
+        }
+        assert_eq!(n_graphemes, 600);
+        while cursor.prev_grapheme().is_some() {
+            n_graphemes -= 1;
+        }
+        assert_eq!(n_graphemes, 0);
+        assert_eq!(cursor.pos(), 0);
+
+        // forward then back over one sequence returns to the same offset
+        let start = 1 + (1 + family.len()) * 150;
+        cursor.set(start);
+        assert_eq!(cursor.next_grapheme(), Some(start + family.len()));
+        assert_eq!(cursor.prev_grapheme(), Some(start));
+    }
+
+    /// Splits `s` into chunks of about `size` bytes, on codepoint boundaries.
+    fn str_chunks(mut s: &str, size: usize) -> Vec<&str> {
+        let mut chunks = Vec::new();
+        while !s.is_empty() {
+            let mut end = min(size, s.len());
+            while !s.is_char_boundary(end) {
+                end += 1;
+            }
+            chunks.push(&s[..end]);
+            s = &s[end..];
+        }
+        chunks
+    }
+
+    fn megabyte_of_text() -> String {
+        let mut s = String::new();
+        let mut i = 0;
+        while s.len() < 1 << 20 {
+            s.push_str(&"x".repeat(i % 97));
+            s.push_str(if i % 5 == 0 { "é😀\n" } else { "\n" });
+            i += 1;
+        }
+        s
+    }
+
+    #[test]
+    fn push_str_chunks() {
+        let s = megabyte_of_text();
+        let mut a = Rope::from("");
+        for chunk in str_chunks(&s, 4096) {
+            a.push_str(chunk);
+        }
+        assert!(a.is_valid());
+        assert_eq!(String::from(&a), s);
+        assert_eq!(a.measure::<LinesMetric>(), Rope::from(&s).measure::<LinesMetric>());
+
+        a.push_str("");
+        assert_eq!(a.len(), s.len());
+        let mut b = Rope::from("a");
+        b.push_str("bc");
+        assert_eq!(String::from(b), "abc");
+    }
+
+    #[test]
+    fn rope_builder_chunks() {
+        let s = megabyte_of_text();
+        for &size in &[1, 100, 4096, 100_000] {
+            let mut b = RopeBuilder::new();
+            for chunk in str_chunks(&s, size) {
+                b.push_str(chunk);
+            }
+            let a = b.build();
+            assert!(a.is_valid());
+            assert_eq!(String::from(a), s);
+        }
+
+        assert_eq!(RopeBuilder::new().build().len(), 0);
+        assert!(Rope::from("").is_valid());
+    }
+
     #[test]
     fn line_of_offset_small() {
         let a = Rope::from("a\nb\nc");
@@ -1023,6 +1383,59 @@ mod tests {
         assert_eq!(2, b.offset_of_line(1));
     }
 
+    #[test]
+    fn line_col_of_offset_small() {
+        // "é" and "√" are two and three bytes long
+        let a = Rope::from("aé\n√b\nlast");
+        assert_eq!((0, 0), a.line_col_of_offset(0));
+        assert_eq!((0, 1), a.line_col_of_offset(1));
+        assert_eq!((0, 3), a.line_col_of_offset(3));
+        // the start of a line, just after the newline
+        assert_eq!((1, 0), a.line_col_of_offset(4));
+        assert_eq!((1, 3), a.line_col_of_offset(7));
+        // inside a codepoint
+        assert_eq!((0, 1), a.line_col_of_offset(2));
+        assert_eq!((1, 0), a.line_col_of_offset(6));
+        // the last line has no trailing newline
+        assert_eq!((2, 4), a.line_col_of_offset(a.len()));
+        assert_eq!((2, 4), a.line_col_of_offset(a.len() + 10));
+
+        let empty = Rope::from("");
+        assert_eq!((0, 0), empty.line_col_of_offset(0));
+        assert_eq!((0, 0), empty.line_col_of_offset(5));
+    }
+
+    #[test]
+    fn offset_of_line_col_small() {
+        let a = Rope::from("aé\n√b\r\nlast");
+        assert_eq!(0, a.offset_of_line_col(0, 0));
+        assert_eq!(3, a.offset_of_line_col(0, 3));
+        assert_eq!(4, a.offset_of_line_col(1, 0));
+        assert_eq!(8, a.offset_of_line_col(1, 4));
+        // inside a codepoint
+        assert_eq!(1, a.offset_of_line_col(0, 2));
+        assert_eq!(4, a.offset_of_line_col(1, 2));
+        // columns past the end stop before the line ending
+        assert_eq!(3, a.offset_of_line_col(0, 100));
+        assert_eq!(8, a.offset_of_line_col(1, 100));
+        assert_eq!(a.len(), a.offset_of_line_col(2, 100));
+        assert_eq!(a.len(), a.offset_of_line_col(2, usize::max_value()));
+        // lines past the end are the last line
+        assert_eq!(10, a.offset_of_line_col(7, 0));
+
+        for offset in (0..a.len()).filter(|&i| a.is_codepoint_boundary(i)) {
+            let (line, col) = a.line_col_of_offset(offset);
+            if a.byte_at(offset) != b'\n' {
+                assert_eq!(offset, a.offset_of_line_col(line, col));
+            }
+        }
+
+        let b = Rope::from("one\n");
+        assert_eq!(3, b.offset_of_line_col(0, 10));
+        assert_eq!(4, b.offset_of_line_col(1, 0));
+        assert_eq!(4, b.offset_of_line_col(3, 3));
+    }
+
     #[test]
     #[allow(clippy::eq_op)]
     fn eq_small() {
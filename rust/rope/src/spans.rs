@@ -286,6 +286,54 @@ impl<T: Clone> Spans<T> {
         sb.build()
     }
 
+    /// Creates a new Spans instance in which no two spans overlap, using
+    /// `combine` to resolve the overlaps in `self`.
+    ///
+    /// Overlapping spans are split at each other's boundaries. Each region
+    /// covered by several spans gets the value of folding `combine` over
+    /// their values, in the order the spans start in. Regions covered by a
+    /// single span keep its value, and adjacent spans are left apart. Empty
+    /// spans overlap nothing, and are kept as they are.
+    pub fn merge_overlapping(&self, combine: impl Fn(&T, &T) -> T) -> Spans<T> {
+        let (empty, spans): (Vec<_>, Vec<_>) = self.iter().partition(|(iv, _)| iv.is_empty());
+        let mut merged = Vec::with_capacity(spans.len() + empty.len());
+        // the spans covering the region starting at `pos`, in order of start
+        let mut active: Vec<(Interval, &T)> = Vec::new();
+        let mut next = 0;
+        let mut pos = 0;
+        while next < spans.len() || !active.is_empty() {
+            if active.is_empty() {
+                pos = spans[next].0.start();
+            }
+            while next < spans.len() && spans[next].0.start() == pos {
+                active.push(spans[next]);
+                next += 1;
+            }
+            // the region ends where the first active span ends, or where
+            // another span starts
+            let mut end = active.iter().map(|(iv, _)| iv.end()).min().unwrap();
+            if let Some((iv, _)) = spans.get(next) {
+                end = end.min(iv.start());
+            }
+            let mut data = active[0].1.clone();
+            for (_, other) in &active[1..] {
+                data = combine(&data, other);
+            }
+            merged.push((Interval::new(pos, end), data));
+            active.retain(|(iv, _)| iv.end() > end);
+            pos = end;
+        }
+        merged.extend(empty.into_iter().map(|(iv, data)| (iv, data.clone())));
+        // stable, so regions stay ahead of empty spans at the same offset
+        merged.sort_by_key(|(iv, _)| iv.start());
+
+        let mut sb = SpansBuilder::new(self.len());
+        for (iv, data) in merged {
+            sb.add_span(iv, data);
+        }
+        sb.build()
+    }
+
     // possible future: an iterator that takes an interval, so results are the same as
     // taking a subseq on the spans object. Would require specialized Cursor.
     pub fn iter(&self) -> SpanIter<T> {
@@ -453,6 +501,47 @@ mod tests {
         assert!(merged_iter.next().is_none());
     }
 
+    #[test]
+    fn merge_overlapping() {
+        // 1 1 1 1 1 1
+        //     2 2 2 2 2 2
+        // == 1 1 3 3 3 3 2 2
+        let mut sb = SpansBuilder::new(10);
+        sb.add_span(Interval::new(0, 6), 1);
+        sb.add_span(Interval::new(2, 8), 2);
+        let spans = sb.build();
+        let merged = spans.merge_overlapping(|a, b| a + b);
+        let merged = merged.iter().map(|(iv, val)| (iv.start(), iv.end(), *val));
+        assert_eq!(merged.collect::<Vec<_>>(), vec![(0, 2, 1), (2, 6, 3), (6, 8, 2)]);
+
+        // a span inside another, and one covering the same region, are
+        // combined in order of start
+        let mut sb = SpansBuilder::new(10);
+        sb.add_span(Interval::new(0, 10), 1);
+        sb.add_span(Interval::new(3, 5), 10);
+        sb.add_span(Interval::new(3, 5), 100);
+        let spans = sb.build();
+        let merged = spans.merge_overlapping(|a, b| a * 1000 + b);
+        let merged = merged.iter().map(|(iv, val)| (iv.start(), iv.end(), *val));
+        let expected = vec![(0, 3, 1), (3, 5, 1_010_100), (5, 10, 1)];
+        assert_eq!(merged.collect::<Vec<_>>(), expected);
+    }
+
+    #[test]
+    fn merge_overlapping_adjacent_and_empty() {
+        // adjacent spans are not combined, nor are empty ones
+        let mut sb = SpansBuilder::new(10);
+        sb.add_span(Interval::new(0, 3), 1);
+        sb.add_span(Interval::new(2, 2), 4);
+        sb.add_span(Interval::new(3, 5), 2);
+        sb.add_span(Interval::new(7, 9), 8);
+        let spans = sb.build();
+        let merged = spans.merge_overlapping(|_, _| panic!("nothing overlaps"));
+        let merged = merged.iter().map(|(iv, val)| (iv.start(), iv.end(), *val));
+        let expected = vec![(0, 3, 1), (2, 2, 4), (3, 5, 2), (7, 9, 8)];
+        assert_eq!(merged.collect::<Vec<_>>(), expected);
+    }
+
     #[test]
     fn test_delete_after() {
         let mut sb = SpansBuilder::new(11);
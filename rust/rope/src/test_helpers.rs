@@ -91,3 +91,14 @@ pub fn parse_delta(s: &str) -> Delta<RopeInfo> {
 
     b.build()
 }
+
+/// A tiny deterministic PRNG, so that randomized test failures are reproducible.
+pub struct Lcg(pub u64);
+
+impl Lcg {
+    /// Returns a value in `0..n`.
+    pub fn next(&mut self, n: usize) -> usize {
+        self.0 = self.0.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        ((self.0 >> 33) as usize) % n
+    }
+}
@@ -377,6 +377,28 @@ impl<N: NodeInfo> Node<N> {
         M::measure(&self.0.info, self.0.len)
     }
 
+    /// Checks the balancing invariants of the tree: every internal node has
+    /// between two and `MAX_CHILDREN` children of the same height, and every
+    /// node except the root satisfies `is_ok_child`.
+    ///
+    /// This walks the whole tree, and is intended for tests.
+    pub fn is_valid(&self) -> bool {
+        match self.0.val {
+            NodeVal::Leaf(ref l) => l.len() == self.len(),
+            NodeVal::Internal(ref nodes) => {
+                nodes.len() > 1 && nodes.len() <= MAX_CHILDREN && self.children_are_valid(nodes)
+            }
+        }
+    }
+
+    fn children_are_valid(&self, nodes: &[Node<N>]) -> bool {
+        let len: usize = nodes.iter().map(Node::len).sum();
+        len == self.len()
+            && nodes.iter().all(|child| {
+                child.height() + 1 == self.height() && child.is_ok_child() && child.is_valid()
+            })
+    }
+
     pub fn subseq<T: IntervalBounds>(&self, iv: T) -> Node<N> {
         let iv = iv.into_interval(self.len());
         let mut b = TreeBuilder::new();
@@ -849,6 +871,25 @@ impl<'a, N: NodeInfo> Cursor<'a, N> {
         CursorIter { cursor: self, _metric: PhantomData }
     }
 
+    /// Returns an iterator with this cursor over the given [`Metric`], which
+    /// moves backwards, toward the beginning of the tree.
+    ///
+    /// # Examples:
+    ///
+    /// ```
+    /// # use xi_rope::{Cursor, LinesMetric, Rope};
+    /// #
+    /// let text: Rope = "one line\ntwo line\nred line\nblue".into();
+    /// let mut cursor = Cursor::new(&text, text.len());
+    /// let line_offsets = cursor.iter_prev::<LinesMetric>().collect::<Vec<_>>();
+    /// assert_eq!(line_offsets, vec![27, 18, 9]);
+    ///
+    /// ```
+    /// [`Metric`]: struct.Metric.html
+    pub fn iter_prev<'c, M: Metric<N>>(&'c mut self) -> CursorRevIter<'c, 'a, N, M> {
+        CursorRevIter { cursor: self, _metric: PhantomData }
+    }
+
     /// Tries to find the last boundary in the leaf the cursor is currently in.
     ///
     /// If the last boundary is at the end of the leaf, it is only counted if
@@ -1066,6 +1107,33 @@ impl<'c, 'a, N: NodeInfo, M: Metric<N>> CursorIter<'c, 'a, N, M> {
     }
 }
 
+/// An iterator generated by a [`Cursor`], for some [`Metric`], which moves
+/// backwards through the tree.
+///
+/// [`Cursor`]: struct.Cursor.html
+/// [`Metric`]: struct.Metric.html
+pub struct CursorRevIter<'c, 'a: 'c, N: 'a + NodeInfo, M: 'a + Metric<N>> {
+    cursor: &'c mut Cursor<'a, N>,
+    _metric: PhantomData<&'a M>,
+}
+
+impl<'c, 'a, N: NodeInfo, M: Metric<N>> Iterator for CursorRevIter<'c, 'a, N, M> {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        self.cursor.prev::<M>()
+    }
+}
+
+impl<'c, 'a, N: NodeInfo, M: Metric<N>> CursorRevIter<'c, 'a, N, M> {
+    /// Returns the current position of the underlying [`Cursor`].
+    ///
+    /// [`Cursor`]: struct.Cursor.html
+    pub fn pos(&self) -> usize {
+        self.cursor.pos()
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -1142,6 +1210,32 @@ mod test {
         assert_eq!(manual, auto);
     }
 
+    #[test]
+    fn cursor_iter_prev() {
+        let mut s = build_triangle(200);
+        s.push_str(&build_triangle(100).replace('\n', "\r\n"));
+        s.push_str("歴史\r\n科学的");
+        let text: Rope = s.into();
+        assert!(text.height() > 1);
+
+        let mut cursor = Cursor::new(&text, 0);
+        let mut forward = cursor.iter::<LinesMetric>().collect::<Vec<_>>();
+        forward.retain(|&pos| pos < text.len());
+        forward.reverse();
+        cursor.set(text.len());
+        let backward = cursor.iter_prev::<LinesMetric>().collect::<Vec<_>>();
+        assert_eq!(forward, backward);
+
+        cursor.set(0);
+        let mut forward = cursor.iter::<BaseMetric>().collect::<Vec<_>>();
+        forward.pop();
+        forward.insert(0, 0);
+        forward.reverse();
+        cursor.set(text.len());
+        let backward = cursor.iter_prev::<BaseMetric>().collect::<Vec<_>>();
+        assert_eq!(forward, backward);
+    }
+
     #[test]
     fn cursor_next_misc() {
         cursor_next_for("toto");
@@ -30,6 +30,13 @@ pub enum Error {
     PeerDisconnect,
     /// The peer sent a response containing the id, but was malformed.
     InvalidResponse,
+    /// The request was cancelled before a response arrived.
+    Cancelled,
+    /// No response arrived before the request timed out.
+    Timeout,
+    /// The request, or its response, was larger than the size limit set
+    /// with `RpcLoop::set_max_message_size`, and was not sent.
+    TooLarge { size: usize, limit: usize },
 }
 
 /// The possible error outcomes when attempting to read a message.
@@ -45,6 +52,13 @@ pub enum ReadError {
     UnknownRequest(JsonError),
     /// The peer closed the connection.
     Disconnect,
+    /// The reader thread panicked; contains the panic message.
+    ReaderPanic(String),
+    /// The message was larger than the size limit set with
+    /// `RpcLoop::set_max_message_size`, and was not parsed beyond its
+    /// `id` and `method`, which are given if present. Unlike other errors,
+    /// this does not end the main loop.
+    TooLarge { id: Option<u64>, method: Option<String>, size: usize, limit: usize },
 }
 
 /// Errors that can be received from the other side of the RPC channel.
@@ -140,6 +154,10 @@ impl fmt::Display for ReadError {
             ReadError::NotObject => write!(f, "JSON message was not an object."),
             ReadError::UnknownRequest(ref err) => write!(f, "Unknown request: {:?}", err),
             ReadError::Disconnect => write!(f, "Peer closed the connection."),
+            ReadError::ReaderPanic(ref msg) => write!(f, "Reader thread panicked: {}", msg),
+            ReadError::TooLarge { size, limit, .. } => {
+                write!(f, "Message of {} bytes exceeds the limit of {} bytes.", size, limit)
+            }
         }
     }
 }
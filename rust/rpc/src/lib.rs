@@ -17,7 +17,10 @@
 //! The RPC protocol is based on [JSON-RPC](http://www.jsonrpc.org/specification),
 //! but with some modifications. Unlike JSON-RPC 2.0, requests and notifications
 //! are allowed in both directions, rather than imposing client and server roles.
-//! Further, the batch form is not supported.
+//! Further, the batch form is only supported for incoming messages, and
+//! only if enabled with [`RpcLoop::set_accept_batches`].
+//!
+//! [`RpcLoop::set_accept_batches`]: struct.RpcLoop.html#method.set_accept_batches
 //!
 //! Because these changes make the protocol not fully compliant with the spec,
 //! the `"jsonrpc"` member is omitted from request and response objects.
@@ -36,14 +39,19 @@ extern crate log;
 
 mod error;
 mod parse;
+mod router;
 
 pub mod test_utils;
 
+use std::any::Any;
+use std::cell::Cell;
 use std::cmp;
 use std::collections::{BTreeMap, BinaryHeap, VecDeque};
-use std::io::{self, BufRead, Write};
+use std::io::{BufRead, BufWriter, Write};
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
-use std::sync::mpsc;
+use std::sync::mpsc::{self, RecvTimeoutError};
 use std::sync::{Arc, Condvar, Mutex};
 use std::thread;
 use std::time::{Duration, Instant};
@@ -55,6 +63,7 @@ use xi_trace::{trace, trace_block, trace_block_payload, trace_payload};
 
 pub use crate::error::{Error, ReadError, RemoteError};
 use crate::parse::{Call, MessageReader, Response, RpcObject};
+pub use crate::router::Router;
 
 /// The maximum duration we will block on a reader before checking for an task.
 const MAX_IDLE_WAIT: Duration = Duration::from_millis(5);
@@ -84,12 +93,51 @@ pub trait Peer: Send + 'static {
     /// `Callback` is an alias for `FnOnce(Result<Value, Error>)`; it must
     /// be boxed because trait objects cannot use generic paramaters.
     fn send_rpc_request_async(&self, method: &str, params: &Value, f: Box<dyn Callback>);
+    /// Like `send_rpc_request_async`, but returns the id of the request,
+    /// which can be passed to `cancel_rpc_request`.
+    ///
+    /// The default implementation sends the request with
+    /// `send_rpc_request_async`, and returns 0.
+    fn send_rpc_request_async_with_id(
+        &self,
+        method: &str,
+        params: &Value,
+        f: Box<dyn Callback>,
+    ) -> usize {
+        self.send_rpc_request_async(method, params, f);
+        0
+    }
+    /// Cancels the pending request with the given id; its callback is
+    /// invoked with `Error::Cancelled`. If the response has already
+    /// arrived this does nothing, and a response arriving later is ignored.
+    ///
+    /// The default implementation does nothing.
+    fn cancel_rpc_request(&self, _id: usize) {}
     /// Sends a request (synchronous RPC) to the peer, and waits for the result.
     fn send_rpc_request(&self, method: &str, params: &Value) -> Result<Value, Error>;
+    /// Like `send_rpc_request`, but gives up after `timeout`, returning
+    /// `Error::Timeout`. A response arriving later is ignored.
+    ///
+    /// The default implementation calls `send_rpc_request`, waiting for
+    /// the result without a limit.
+    fn send_rpc_request_timeout(
+        &self,
+        method: &str,
+        params: &Value,
+        _timeout: Duration,
+    ) -> Result<Value, Error> {
+        self.send_rpc_request(method, params)
+    }
     /// Determines whether an incoming request (or notification) is
     /// pending. This is intended to reduce latency for bulk operations
     /// done in the background.
     fn request_is_pending(&self) -> bool;
+    /// Returns the current depth of the incoming message queue, and related
+    /// counters; see [`PeerMetrics`]. This allows finer decisions about
+    /// yielding than `request_is_pending`.
+    ///
+    /// [`PeerMetrics`]: struct.PeerMetrics.html
+    fn metrics(&self) -> PeerMetrics;
     /// Adds a token to the idle queue. When the runloop is idle and the
     /// queue is not empty, the handler's `idle` fn will be called
     /// with the earliest added token.
@@ -107,8 +155,58 @@ pub trait Peer: Send + 'static {
 /// The `Peer` trait object.
 pub type RpcPeer = Box<dyn Peer>;
 
+/// A snapshot of the backlog of a `Peer`, returned by [`Peer::metrics`].
+///
+/// [`Peer::metrics`]: trait.Peer.html#tymethod.metrics
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PeerMetrics {
+    /// The number of incoming messages waiting to be handled.
+    pub rx_queue_len: usize,
+    /// The largest number of incoming messages that have been waiting at
+    /// once, since the peer was created.
+    pub rx_queue_high_water: usize,
+    /// The number of outgoing requests still awaiting a response.
+    pub pending_requests: usize,
+}
+
 pub struct RpcCtx {
     peer: RpcPeer,
+    outgoing: Arc<dyn Outgoing>,
+    /// The id of the request being handled, if any.
+    current_request: Cell<Option<u64>>,
+    /// Set if the response to the current request has been deferred.
+    deferred: Cell<bool>,
+}
+
+/// The parts of `RawPeer` used by `RpcCtx` that aren't exposed through the
+/// `Peer` trait, so that `RpcCtx` need not be generic over the writer.
+trait Outgoing: Send + Sync {
+    /// Sends the response to the request with the given id.
+    fn respond(&self, result: Response, id: u64);
+    /// Sends the given messages as a single batch.
+    fn send_batch(&self, messages: Vec<Value>);
+}
+
+/// A handle for answering a request whose response was deferred with
+/// [`RpcCtx::defer_response`]. It can be sent to another thread.
+///
+/// If the token is dropped without being used, an error is sent in place
+/// of the response, so that the peer is never left waiting.
+///
+/// [`RpcCtx::defer_response`]: struct.RpcCtx.html#method.defer_response
+#[must_use = "the peer gets an error response if the token is dropped"]
+pub struct ResponseToken {
+    id: u64,
+    outgoing: Option<Arc<dyn Outgoing>>,
+}
+
+/// Notifications collected by [`RpcCtx::begin_batch`], which are sent
+/// together as a single batch message when this is dropped.
+///
+/// [`RpcCtx::begin_batch`]: struct.RpcCtx.html#method.begin_batch
+pub struct NotificationBatch<'a> {
+    ctx: &'a RpcCtx,
+    messages: Vec<Value>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -126,13 +224,27 @@ pub struct RpcCall {
 /// Types which implement `MethodHandler` are also responsible for implementing
 /// `Parser`; `Parser` is provided when Self::Notification and Self::Request
 /// can be used with serde::DeserializeOwned.
+///
+/// A loop has a single handler; to host several, each handling the methods
+/// with a given prefix, use a [`Router`].
+///
+/// [`Router`]: struct.Router.html
 pub trait Handler {
     type Notification: DeserializeOwned;
     type Request: DeserializeOwned;
     fn handle_notification(&mut self, ctx: &RpcCtx, rpc: Self::Notification);
+    /// Handles a request, returning its result. Alternatively, the handler
+    /// can call `ctx.defer_response()` and answer later through the returned
+    /// `ResponseToken`, in which case the value returned here is ignored.
     fn handle_request(&mut self, ctx: &RpcCtx, rpc: Self::Request) -> Result<Value, RemoteError>;
     #[allow(unused_variables)]
     fn idle(&mut self, ctx: &RpcCtx, token: usize) {}
+    /// Called once when the runloop is about to exit, whether because the
+    /// peer disconnected or because of an error. This runs after any final
+    /// idle work, and before pending outgoing requests fail with
+    /// `Error::PeerDisconnect`.
+    #[allow(unused_variables)]
+    fn on_disconnect(&mut self, ctx: &RpcCtx) {}
 }
 
 pub trait Callback: Send {
@@ -184,6 +296,18 @@ impl ResponseHandler {
     }
 }
 
+/// When an `RpcLoop` flushes its output stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlushPolicy {
+    /// Flush after every message.
+    EveryMessage,
+    /// Flush only when the main loop is about to wait for input, so that
+    /// messages sent in between are written together. Responses, and
+    /// synchronous requests, are always flushed immediately, so that the
+    /// peer is never left waiting.
+    OnIdle,
+}
+
 #[derive(Debug, PartialEq, Eq)]
 struct Timer {
     fire_after: Instant,
@@ -192,14 +316,24 @@ struct Timer {
 
 struct RpcState<W: Write> {
     rx_queue: Mutex<VecDeque<Result<RpcObject, ReadError>>>,
+    /// The greatest length `rx_queue` has reached. Only updated while
+    /// holding the `rx_queue` lock.
+    rx_high_water: AtomicUsize,
     rx_cvar: Condvar,
     writer: Mutex<W>,
+    flush_policy: FlushPolicy,
     id: AtomicUsize,
     pending: Mutex<BTreeMap<usize, ResponseHandler>>,
     idle_queue: Mutex<VecDeque<usize>>,
     timers: Mutex<BinaryHeap<Timer>>,
     needs_exit: AtomicBool,
     is_blocked: AtomicBool,
+    /// Set when idle work or a timer is scheduled, so that the main loop
+    /// does not start waiting on `rx_cvar` after missing the notification.
+    wake_pending: AtomicBool,
+    /// The size in bytes of the largest message that is sent, or zero if
+    /// there is no limit.
+    max_message_size: AtomicUsize,
 }
 
 /// A structure holding the state of a main loop for handling RPC's.
@@ -208,20 +342,37 @@ pub struct RpcLoop<W: Write + 'static> {
     peer: RawPeer<W>,
 }
 
+impl<W: Write + Send> RpcLoop<BufWriter<W>> {
+    /// Creates a new `RpcLoop` whose output stream is buffered, and flushed
+    /// according to `flush_policy`. This reduces the number of writes when
+    /// many messages are sent in a row.
+    pub fn new_with_buffering(writer: W, flush_policy: FlushPolicy) -> Self {
+        RpcLoop::with_flush_policy(BufWriter::new(writer), flush_policy)
+    }
+}
+
 impl<W: Write + Send> RpcLoop<W> {
     /// Creates a new `RpcLoop` with the given output stream (which is used for
     /// sending requests and notifications, as well as responses).
     pub fn new(writer: W) -> Self {
+        RpcLoop::with_flush_policy(writer, FlushPolicy::EveryMessage)
+    }
+
+    fn with_flush_policy(writer: W, flush_policy: FlushPolicy) -> Self {
         let rpc_peer = RawPeer(Arc::new(RpcState {
             rx_queue: Mutex::new(VecDeque::new()),
+            rx_high_water: AtomicUsize::new(0),
             rx_cvar: Condvar::new(),
             writer: Mutex::new(writer),
+            flush_policy,
             id: AtomicUsize::new(0),
             pending: Mutex::new(BTreeMap::new()),
             idle_queue: Mutex::new(VecDeque::new()),
             timers: Mutex::new(BinaryHeap::new()),
             needs_exit: AtomicBool::new(false),
             is_blocked: AtomicBool::new(false),
+            wake_pending: AtomicBool::new(false),
+            max_message_size: AtomicUsize::new(0),
         }));
         RpcLoop { reader: MessageReader::default(), peer: rpc_peer }
     }
@@ -231,11 +382,35 @@ impl<W: Write + Send> RpcLoop<W> {
         self.peer.clone()
     }
 
+    /// Sets whether incoming messages may be batches: JSON arrays of
+    /// requests and notifications, which are handled in order. If an
+    /// element of a batch is malformed, the other elements are still
+    /// handled before the read error ends the main loop.
+    pub fn set_accept_batches(&mut self, accept_batches: bool) {
+        self.reader.set_accept_batches(accept_batches);
+    }
+
+    /// Sets the size in bytes of the largest message, not counting the
+    /// trailing newline, that is sent or handled. There is no limit by
+    /// default.
+    ///
+    /// An oversized notification is logged and dropped, in either
+    /// direction. Sending an oversized request fails with
+    /// `Error::TooLarge`, and an oversized incoming request is answered
+    /// with an invalid request error without being parsed. A response that
+    /// is too large to send is replaced by an error response, and one that
+    /// is too large to handle fails the request with `Error::TooLarge`.
+    pub fn set_max_message_size(&mut self, max_message_size: Option<usize>) {
+        self.reader.set_max_message_size(max_message_size);
+        self.peer.0.max_message_size.store(max_message_size.unwrap_or(0), Ordering::Relaxed);
+    }
+
     /// Starts the event loop, reading lines from the reader until EOF,
     /// or an error occurs.
     ///
     /// Returns `Ok()` in the EOF case, otherwise returns the
-    /// underlying `ReadError`.
+    /// underlying `ReadError`. If the I/O thread panics, this returns
+    /// `ReadError::ReaderPanic` with the panic's message.
     ///
     /// # Note:
     /// The reader is supplied via a closure, as basically a workaround
@@ -246,57 +421,67 @@ impl<W: Write + Send> RpcLoop<W> {
     /// Calls to the handler happen on the caller's thread.
     ///
     /// Calls to the handler are guaranteed to preserve the order as
-    /// they appear on on the channel. At the moment, there is no way
-    /// for there to be more than one incoming request to be outstanding.
+    /// they appear on on the channel. Responses are sent in the same order,
+    /// unless the handler defers one with `RpcCtx::defer_response`; only
+    /// then can a later request be answered first.
     pub fn mainloop<R, RF, H>(&mut self, rf: RF, handler: &mut H) -> Result<(), ReadError>
     where
         R: BufRead,
         RF: Send + FnOnce() -> R,
         H: Handler,
     {
+        let peer = self.get_raw_peer();
         let exit = crossbeam_utils::thread::scope(|scope| {
-            let peer = self.get_raw_peer();
             peer.reset_needs_exit();
 
-            let ctx = RpcCtx { peer: Box::new(peer.clone()) };
+            let ctx = RpcCtx {
+                peer: Box::new(peer.clone()),
+                outgoing: Arc::new(peer.clone()),
+                current_request: Cell::new(None),
+                deferred: Cell::new(false),
+            };
             scope.spawn(move |_| {
-                let mut stream = rf();
-                loop {
-                    // The main thread cannot return while this thread is active;
-                    // when the main thread wants to exit it sets this flag.
-                    if self.peer.needs_exit() {
-                        trace("read loop exit", &["rpc"]);
-                        break;
-                    }
-
-                    let json = match self.reader.next(&mut stream) {
-                        Ok(json) => json,
-                        Err(err) => {
-                            if self.peer.0.is_blocked.load(Ordering::Acquire) {
-                                error!("failed to parse response json: {}", err);
-                                self.peer.disconnect();
-                            }
-                            self.peer.put_rx(Err(err));
+                // A panic here would otherwise leave the main thread waiting on
+                // a reader that no longer exists.
+                let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                    let mut stream = rf();
+                    loop {
+                        // The main thread cannot return while this thread is active;
+                        // when the main thread wants to exit it sets this flag.
+                        if self.peer.needs_exit() {
+                            trace("read loop exit", &["rpc"]);
                             break;
                         }
-                    };
-                    if json.is_response() {
-                        let id = json.get_id().unwrap();
-                        let _resp =
-                            trace_block_payload("read loop response", &["rpc"], format!("{}", id));
-                        match json.into_response() {
-                            Ok(resp) => {
-                                let resp = resp.map_err(Error::from);
-                                self.peer.handle_response(id, resp);
+
+                        let batch = match self.reader.next_batch(&mut stream) {
+                            Ok(batch) => batch,
+                            Err(ReadError::TooLarge { id, method, size, limit }) => {
+                                self.peer.handle_too_large(id, method, size, limit);
+                                continue;
                             }
-                            Err(msg) => {
-                                error!("failed to parse response: {}", msg);
-                                self.peer.handle_response(id, Err(Error::InvalidResponse));
+                            Err(err) => {
+                                self.peer.read_failed(err);
+                                break;
+                            }
+                        };
+                        let mut error = None;
+                        for result in batch {
+                            match result {
+                                Ok(json) => self.peer.handle_read(json),
+                                // handle the well-formed elements of a batch before the error
+                                Err(err) => {
+                                    error.get_or_insert(err);
+                                }
                             }
                         }
-                    } else {
-                        self.peer.put_rx(Ok(json));
+                        if let Some(err) = error {
+                            self.peer.read_failed(err);
+                            break;
+                        }
                     }
+                }));
+                if let Err(payload) = result {
+                    self.peer.read_failed(ReadError::ReaderPanic(panic_message(&*payload)));
                 }
             });
 
@@ -307,6 +492,18 @@ impl<W: Write + Send> RpcLoop<W> {
 
                 let json = match read_result {
                     Ok(json) => json,
+                    Err(ReadError::TooLarge { id, method, size, limit }) => {
+                        warn!(
+                            "dropping {:?}: {} bytes is over the limit of {}",
+                            method, size, limit
+                        );
+                        if let Some(id) = id {
+                            let msg =
+                                format!("message of {} bytes is over the limit of {}", size, limit);
+                            peer.respond(Err(RemoteError::InvalidRequest(Some(json!(msg)))), id);
+                        }
+                        continue;
+                    }
                     Err(err) => {
                         trace_payload("main loop err", &["rpc"], err.to_string());
                         // finish idle work before disconnecting;
@@ -314,6 +511,7 @@ impl<W: Write + Send> RpcLoop<W> {
                         if let Some(idle_token) = peer.try_get_idle() {
                             handler.idle(&ctx, idle_token);
                         }
+                        handler.on_disconnect(&ctx);
                         peer.disconnect();
                         return err;
                     }
@@ -323,8 +521,12 @@ impl<W: Write + Send> RpcLoop<W> {
                 match json.into_rpc::<H::Notification, H::Request>() {
                     Ok(Call::Request(id, cmd)) => {
                         let _t = trace_block_payload("handle request", &["rpc"], method.unwrap());
+                        ctx.current_request.set(Some(id));
                         let result = handler.handle_request(&ctx, cmd);
-                        peer.respond(result, id);
+                        ctx.current_request.set(None);
+                        if !ctx.deferred.replace(false) {
+                            peer.respond(result, id);
+                        }
                     }
                     Ok(Call::Notification(cmd)) => {
                         let _t = trace_block_payload("handle notif", &["rpc"], method.unwrap());
@@ -333,6 +535,7 @@ impl<W: Write + Send> RpcLoop<W> {
                     Ok(Call::InvalidRequest(id, err)) => peer.respond(Err(err), id),
                     Err(err) => {
                         trace_payload("read loop exit", &["rpc"], err.to_string());
+                        handler.on_disconnect(&ctx);
                         peer.disconnect();
                         return ReadError::UnknownRequest(err);
                     }
@@ -340,6 +543,7 @@ impl<W: Write + Send> RpcLoop<W> {
             }
         })
         .unwrap();
+        peer.flush();
 
         if exit.is_disconnect() {
             Ok(())
@@ -349,6 +553,17 @@ impl<W: Write + Send> RpcLoop<W> {
     }
 }
 
+/// Extracts the message from a panic payload, if it has one.
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(msg) = payload.downcast_ref::<&str>() {
+        msg.to_string()
+    } else if let Some(msg) = payload.downcast_ref::<String>() {
+        msg.clone()
+    } else {
+        "unknown panic payload".into()
+    }
+}
+
 /// Returns the next read result, checking for idle work when no
 /// result is available.
 fn next_read<W, H>(peer: &RawPeer<W>, handler: &mut H, ctx: &RpcCtx) -> Result<RpcObject, ReadError>
@@ -357,13 +572,19 @@ where
     H: Handler,
 {
     loop {
+        // cleared before checking for work; anything scheduled after this
+        // point sets it again, and cuts short the wait below.
+        peer.0.wake_pending.store(false, Ordering::Release);
         if let Some(result) = peer.try_get_rx() {
             return result;
         }
         // handle timers before general idle work
         let time_to_next_timer = match peer.check_timers() {
-            Some(Ok(token)) => {
-                do_idle(handler, ctx, token);
+            Some(Ok((token, fire_after))) => {
+                let delay = fire_after.elapsed();
+                let payload = format!("token: {}, delay_us: {}", token, delay.as_micros());
+                let _trace = trace_block_payload("timer_fire", &["rpc"], payload);
+                handler.idle(ctx, token);
                 continue;
             }
             Some(Err(duration)) => Some(duration),
@@ -379,6 +600,10 @@ where
         // because idle work could be scheduled from another thread.
         let idle_timeout = time_to_next_timer.unwrap_or(MAX_IDLE_WAIT).min(MAX_IDLE_WAIT);
 
+        if peer.0.flush_policy == FlushPolicy::OnIdle {
+            peer.flush();
+        }
+
         if let Some(result) = peer.get_rx_timeout(idle_timeout) {
             return result;
         }
@@ -395,10 +620,106 @@ impl RpcCtx {
         &self.peer
     }
 
+    /// Sends a notification to the peer.
+    pub fn send_rpc_notification(&self, method: &str, params: &Value) {
+        self.peer.send_rpc_notification(method, params)
+    }
+
+    /// Sends a request to the peer; `f` is called with the response.
+    pub fn send_rpc_request_async<F>(&self, method: &str, params: &Value, f: F)
+    where
+        F: FnOnce(Result<Value, Error>) + Send + 'static,
+    {
+        self.peer.send_rpc_request_async(method, params, Box::new(f))
+    }
+
+    /// Starts collecting notifications, to be sent as a single batch
+    /// message when the returned `NotificationBatch` is dropped. This saves
+    /// the peer from handling each one separately, but the peer must
+    /// accept batches (see `RpcLoop::set_accept_batches`).
+    ///
+    /// Notifications sent through the `RpcCtx` while the batch is open are
+    /// not part of it, and are sent before it.
+    pub fn begin_batch(&self) -> NotificationBatch<'_> {
+        NotificationBatch { ctx: self, messages: Vec::new() }
+    }
+
     /// Schedule the idle handler to be run when there are no requests pending.
     pub fn schedule_idle(&self, token: usize) {
         self.peer.schedule_idle(token)
     }
+
+    /// Defers the response to the request being handled. The main loop
+    /// moves on to the next message without responding, and the response
+    /// is sent when the returned token is used.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of `Handler::handle_request`, or more than
+    /// once for the same request.
+    pub fn defer_response(&self) -> ResponseToken {
+        let id = self.current_request.get().expect("defer_response called outside of a request");
+        assert!(!self.deferred.replace(true), "response to request {} already deferred", id);
+        ResponseToken { id, outgoing: Some(self.outgoing.clone()) }
+    }
+}
+
+impl ResponseToken {
+    /// The id of the request this token answers.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Sends the response to the deferred request.
+    pub fn respond(mut self, result: Result<Value, RemoteError>) {
+        if let Some(outgoing) = self.outgoing.take() {
+            outgoing.respond(result, self.id);
+        }
+    }
+}
+
+impl Drop for ResponseToken {
+    fn drop(&mut self) {
+        if let Some(outgoing) = self.outgoing.take() {
+            warn!("deferred request {} dropped without a response", self.id);
+            let msg = "request was dropped without a response";
+            outgoing.respond(Err(RemoteError::custom(-32603, msg, None)), self.id);
+        }
+    }
+}
+
+impl<'a> NotificationBatch<'a> {
+    /// Adds a notification to the batch.
+    pub fn send_rpc_notification(&mut self, method: &str, params: &Value) {
+        self.messages.push(json!({
+            "method": method,
+            "params": params,
+        }));
+    }
+}
+
+impl<'a> Drop for NotificationBatch<'a> {
+    fn drop(&mut self) {
+        if !self.messages.is_empty() {
+            self.ctx.outgoing.send_batch(mem::take(&mut self.messages));
+        }
+    }
+}
+
+impl<W: Write + Send + 'static> Outgoing for RawPeer<W> {
+    fn respond(&self, result: Response, id: u64) {
+        RawPeer::respond(self, result, id)
+    }
+
+    fn send_batch(&self, mut messages: Vec<Value>) {
+        let _trace = trace_block_payload("send batch", &["rpc"], messages.len().to_string());
+        // a batch of one gains nothing, so it is sent as a plain message
+        let msg =
+            if messages.len() == 1 { messages.pop().unwrap() } else { Value::Array(messages) };
+        if let Err(e) = self.send(&msg) {
+            error!("send error on batch: {:?}", e);
+        }
+    }
 }
 
 impl<W: Write + Send + 'static> Peer for RawPeer<W> {
@@ -412,7 +733,7 @@ impl<W: Write + Send + 'static> Peer for RawPeer<W> {
             "method": method,
             "params": params,
         })) {
-            error!("send error on send_rpc_notification method {}: {}", method, e);
+            error!("send error on send_rpc_notification method {}: {:?}", method, e);
         }
     }
 
@@ -421,6 +742,24 @@ impl<W: Write + Send + 'static> Peer for RawPeer<W> {
         self.send_rpc_request_common(method, params, ResponseHandler::Callback(f));
     }
 
+    fn send_rpc_request_async_with_id(
+        &self,
+        method: &str,
+        params: &Value,
+        f: Box<dyn Callback>,
+    ) -> usize {
+        let _trace = trace_block_payload("send req async", &["rpc"], method.to_owned());
+        self.send_rpc_request_common(method, params, ResponseHandler::Callback(f))
+    }
+
+    fn cancel_rpc_request(&self, id: usize) {
+        // races with `handle_response`; whichever removes the handler first wins.
+        let handler = self.0.pending.lock().unwrap().remove(&id);
+        if let Some(handler) = handler {
+            handler.invoke(Err(Error::Cancelled));
+        }
+    }
+
     fn send_rpc_request(&self, method: &str, params: &Value) -> Result<Value, Error> {
         let _trace = trace_block_payload("send req sync", &["rpc"], method.to_owned());
         self.0.is_blocked.store(true, Ordering::Release);
@@ -429,56 +768,156 @@ impl<W: Write + Send + 'static> Peer for RawPeer<W> {
         rx.recv().unwrap_or(Err(Error::PeerDisconnect))
     }
 
+    fn send_rpc_request_timeout(
+        &self,
+        method: &str,
+        params: &Value,
+        timeout: Duration,
+    ) -> Result<Value, Error> {
+        let _trace = trace_block_payload("send req sync", &["rpc"], method.to_owned());
+        self.0.is_blocked.store(true, Ordering::Release);
+        let (tx, rx) = mpsc::channel();
+        let id = self.send_rpc_request_common(method, params, ResponseHandler::Chan(tx));
+        match rx.recv_timeout(timeout) {
+            Ok(result) => result,
+            Err(RecvTimeoutError::Timeout) => {
+                // races with `handle_response`; if it removed the handler
+                // first, the response is about to be sent.
+                let handler = self.0.pending.lock().unwrap().remove(&id);
+                match handler {
+                    Some(_) => Err(Error::Timeout),
+                    None => rx.recv().unwrap_or(Err(Error::PeerDisconnect)),
+                }
+            }
+            Err(RecvTimeoutError::Disconnected) => Err(Error::PeerDisconnect),
+        }
+    }
+
     fn request_is_pending(&self) -> bool {
         let queue = self.0.rx_queue.lock().unwrap();
         !queue.is_empty()
     }
 
+    fn metrics(&self) -> PeerMetrics {
+        PeerMetrics {
+            rx_queue_len: self.rx_queue_len(),
+            rx_queue_high_water: self.0.rx_high_water.load(Ordering::Relaxed),
+            pending_requests: self.pending_request_count(),
+        }
+    }
+
     fn schedule_idle(&self, token: usize) {
         self.0.idle_queue.lock().unwrap().push_back(token);
+        self.wake();
     }
 
     fn schedule_timer(&self, after: Instant, token: usize) {
         self.0.timers.lock().unwrap().push(Timer { fire_after: after, token });
+        self.wake();
     }
 }
 
 impl<W: Write> RawPeer<W> {
-    fn send(&self, v: &Value) -> Result<(), io::Error> {
+    /// Returns the number of incoming messages waiting to be handled.
+    pub fn rx_queue_len(&self) -> usize {
+        self.0.rx_queue.lock().unwrap().len()
+    }
+
+    /// Returns the number of outgoing requests still awaiting a response.
+    pub fn pending_request_count(&self) -> usize {
+        self.0.pending.lock().unwrap().len()
+    }
+
+    fn send(&self, v: &Value) -> Result<(), Error> {
+        self.send_with_flush(v, self.0.flush_policy == FlushPolicy::EveryMessage)
+    }
+
+    fn send_with_flush(&self, v: &Value, flush: bool) -> Result<(), Error> {
         let _trace = trace_block("send", &["rpc"]);
-        let mut s = serde_json::to_string(v).unwrap();
+        let s = serde_json::to_string(v).unwrap();
+        let limit = self.0.max_message_size.load(Ordering::Relaxed);
+        if limit > 0 && s.len() > limit {
+            return Err(Error::TooLarge { size: s.len(), limit });
+        }
+        self.write_message(s, flush)
+    }
+
+    /// Writes a serialized message, regardless of `max_message_size`.
+    fn write_message(&self, mut s: String, flush: bool) -> Result<(), Error> {
         s.push('\n');
-        self.0.writer.lock().unwrap().write_all(s.as_bytes())
-        // Technically, maybe we should flush here, but doesn't seem to be required.
+        let mut writer = self.0.writer.lock().unwrap();
+        writer.write_all(s.as_bytes()).map_err(Error::Io)?;
+        if flush {
+            writer.flush().map_err(Error::Io)?;
+        }
+        Ok(())
+    }
+
+    /// Writes out any buffered messages.
+    fn flush(&self) {
+        if let Err(e) = self.0.writer.lock().unwrap().flush() {
+            error!("error flushing rpc output: {}", e);
+        }
     }
 
     fn respond(&self, result: Response, id: u64) {
-        let mut response = json!({ "id": id });
-        match result {
-            Ok(result) => response["result"] = result,
-            Err(error) => response["error"] = json!(error),
+        // the peer is waiting for an answer, so errors are sent whatever
+        // their size, and a result that is too large is replaced by one
+        let send_error = |error: RemoteError| {
+            let response = json!({ "id": id, "error": error });
+            self.write_message(serde_json::to_string(&response).unwrap(), true)
+        };
+        let result = match result {
+            Ok(result) => {
+                match self.send_with_flush(&json!({ "id": id, "result": result }), true) {
+                    Err(Error::TooLarge { size, limit }) => {
+                        warn!(
+                            "response to RPC {} of {} bytes is over the limit of {}",
+                            id, size, limit
+                        );
+                        let msg = "response was too large to send";
+                        send_error(RemoteError::custom(
+                            -32603,
+                            msg,
+                            json!({ "size": size, "limit": limit }),
+                        ))
+                    }
+                    result => result,
+                }
+            }
+            Err(error) => send_error(error),
         };
-        if let Err(e) = self.send(&response) {
-            error!("error {} sending response to RPC {:?}", e, id);
+        if let Err(e) = result {
+            error!("error {:?} sending response to RPC {:?}", e, id);
         }
     }
 
-    fn send_rpc_request_common(&self, method: &str, params: &Value, rh: ResponseHandler) {
+    fn send_rpc_request_common(&self, method: &str, params: &Value, rh: ResponseHandler) -> usize {
         let id = self.0.id.fetch_add(1, Ordering::Relaxed);
+        // a synchronous request blocks until the response arrives, so it
+        // must not sit in the buffer.
+        let flush = match rh {
+            ResponseHandler::Chan(_) => true,
+            ResponseHandler::Callback(_) => self.0.flush_policy == FlushPolicy::EveryMessage,
+        };
         {
             let mut pending = self.0.pending.lock().unwrap();
             pending.insert(id, rh);
         }
-        if let Err(e) = self.send(&json!({
-            "id": id,
-            "method": method,
-            "params": params,
-        })) {
+        if let Err(e) = self.send_with_flush(
+            &json!({
+                "id": id,
+                "method": method,
+                "params": params,
+            }),
+            flush,
+        ) {
             let mut pending = self.0.pending.lock().unwrap();
             if let Some(rh) = pending.remove(&id) {
-                rh.invoke(Err(Error::Io(e)));
+                rh.invoke(Err(e));
             }
         }
+        id
     }
 
     fn handle_response(&self, id: u64, resp: Result<Value, Error>) {
@@ -503,16 +942,80 @@ impl<W: Write> RawPeer<W> {
     /// and returning `None` if no message is available.
     fn get_rx_timeout(&self, dur: Duration) -> Option<Result<RpcObject, ReadError>> {
         let mut queue = self.0.rx_queue.lock().unwrap();
+        if self.0.wake_pending.load(Ordering::Acquire) {
+            return queue.pop_front();
+        }
         let result = self.0.rx_cvar.wait_timeout(queue, dur).unwrap();
         queue = result.0;
         queue.pop_front()
     }
 
+    /// Handles an RPC object from the read thread: responses are
+    /// delivered to their handlers, and everything else is queued for
+    /// the main thread.
+    fn handle_read(&self, json: RpcObject) {
+        if json.is_response() {
+            let id = json.get_id().unwrap();
+            let _resp = trace_block_payload("read loop response", &["rpc"], format!("{}", id));
+            match json.into_response() {
+                Ok(resp) => {
+                    let resp = resp.map_err(Error::from);
+                    self.handle_response(id, resp);
+                }
+                Err(msg) => {
+                    error!("failed to parse response: {}", msg);
+                    self.handle_response(id, Err(Error::InvalidResponse));
+                }
+            }
+        } else {
+            self.put_rx(Ok(json));
+        }
+    }
+
+    /// Handles a message that was too large to parse. A response fails the
+    /// request it answers, and anything else is passed to the main thread,
+    /// which answers requests in turn.
+    fn handle_too_large(&self, id: Option<u64>, method: Option<String>, size: usize, limit: usize) {
+        match (id, method) {
+            (Some(id), None) => {
+                warn!(
+                    "response to request {} of {} bytes is over the limit of {}",
+                    id, size, limit
+                );
+                self.handle_response(id, Err(Error::TooLarge { size, limit }));
+            }
+            (id, method) => self.put_rx(Err(ReadError::TooLarge { id, method, size, limit })),
+        }
+    }
+
+    /// Queues a read error for the main thread, which exits on receiving it.
+    fn read_failed(&self, err: ReadError) {
+        if self.0.is_blocked.load(Ordering::Acquire) {
+            error!("failed to parse response json: {}", err);
+            self.disconnect();
+        }
+        self.put_rx(Err(err));
+    }
+
     /// Adds a message to the receive queue. The message should only
     /// be `None` if the read thread is exiting.
     fn put_rx(&self, json: Result<RpcObject, ReadError>) {
         let mut queue = self.0.rx_queue.lock().unwrap();
         queue.push_back(json);
+        if queue.len() > self.0.rx_high_water.load(Ordering::Relaxed) {
+            self.0.rx_high_water.store(queue.len(), Ordering::Relaxed);
+        }
+        self.0.rx_cvar.notify_one();
+    }
+
+    /// Wakes the main loop if it is waiting for a message, so that it
+    /// notices newly scheduled idle work or timers. This may be called from
+    /// any thread.
+    fn wake(&self) {
+        self.0.wake_pending.store(true, Ordering::Release);
+        // taking the lock ensures the main loop is either waiting, and gets
+        // the notification, or has yet to check `wake_pending`.
+        let _queue = self.0.rx_queue.lock().unwrap();
         self.0.rx_cvar.notify_one();
     }
 
@@ -521,11 +1024,12 @@ impl<W: Write> RawPeer<W> {
     }
 
     /// Checks status of the most imminent timer. If that timer has expired,
-    /// returns `Some(Ok(_))`, with the corresponding token.
+    /// returns `Some(Ok(_))`, with the corresponding token and the time it
+    /// was scheduled to fire.
     /// If a timer exists but has not expired, returns `Some(Err(_))`,
     /// with the error value being the `Duration` until the timer is ready.
     /// Returns `None` if no timers are registered.
-    fn check_timers(&self) -> Option<Result<usize, Duration>> {
+    fn check_timers(&self) -> Option<Result<(usize, Instant), Duration>> {
         let mut timers = self.0.timers.lock().unwrap();
         match timers.peek() {
             None => return None,
@@ -536,7 +1040,8 @@ impl<W: Write> RawPeer<W> {
                 }
             }
         }
-        Some(Ok(timers.pop().unwrap().token))
+        let timer = timers.pop().unwrap();
+        Some(Ok((timer.token, timer.fire_after)))
     }
 
     /// send disconnect error to pending requests.
@@ -634,4 +1139,45 @@ mod tests {
             _ => panic!("parse failed"),
         }
     }
+
+    #[test]
+    fn test_rx_queue_metrics() {
+        let rpc_loop = RpcLoop::new(std::io::sink());
+        let peer = rpc_loop.get_raw_peer();
+        let reader = MessageReader::default();
+        for i in 0..3 {
+            let msg = format!(r#"{{"method": "hi", "params": {{"n": {}}}}}"#, i);
+            peer.put_rx(Ok(reader.parse(&msg).unwrap()));
+        }
+        peer.send_rpc_request_async("ping", &json!({}), Box::new(|_: Result<Value, Error>| ()));
+        assert_eq!(
+            peer.metrics(),
+            PeerMetrics { rx_queue_len: 3, rx_queue_high_water: 3, pending_requests: 1 }
+        );
+
+        peer.try_get_rx().unwrap().unwrap();
+        peer.try_get_rx().unwrap().unwrap();
+        assert_eq!(peer.rx_queue_len(), 1);
+        assert_eq!(peer.metrics().rx_queue_high_water, 3);
+        peer.cancel_rpc_request(0);
+        assert_eq!(peer.pending_request_count(), 0);
+    }
+
+    #[test]
+    fn scheduling_wakes_waiting_loop() {
+        let rpc_loop = RpcLoop::new(std::io::sink());
+        let peer = rpc_loop.get_raw_peer();
+        let scheduler = peer.clone();
+        let thread = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            scheduler.schedule_idle(1);
+        });
+        // as the main loop does, before checking for work
+        peer.0.wake_pending.store(false, Ordering::Release);
+        let start = Instant::now();
+        assert!(peer.get_rx_timeout(Duration::from_secs(60)).is_none());
+        assert!(start.elapsed() < Duration::from_secs(30));
+        assert_eq!(peer.try_get_idle(), Some(1));
+        thread.join().unwrap();
+    }
 }
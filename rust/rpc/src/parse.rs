@@ -30,7 +30,30 @@ pub type Response = Result<Value, RemoteError>;
 /// Reads and parses RPC messages from a stream, maintaining an
 /// internal buffer.
 #[derive(Debug, Default)]
-pub struct MessageReader(String);
+pub struct MessageReader {
+    buf: String,
+    /// Whether a message may be a JSON array of RPC objects.
+    accept_batches: bool,
+    /// The size in bytes of the largest message that is parsed.
+    max_message_size: Option<usize>,
+}
+
+/// The fields of an RPC object needed to answer or report a message that
+/// is too large to parse in full.
+#[derive(Debug, Default, Deserialize)]
+struct MessageHeader {
+    #[serde(default)]
+    id: Option<u64>,
+    #[serde(default)]
+    method: Option<String>,
+}
+
+/// The RPC objects of a single message, in order. If batches are not
+/// accepted, this always has one element.
+///
+/// An element which is not a valid RPC object is an error, but this does
+/// not affect the other elements of the batch.
+pub type Batch = Vec<Result<RpcObject, ReadError>>;
 
 /// An internal type used during initial JSON parsing.
 ///
@@ -54,6 +77,19 @@ pub enum Call<N, R> {
 }
 
 impl MessageReader {
+    /// Sets whether messages may be batches: JSON arrays of RPC objects,
+    /// as in JSON-RPC 2.0. Batches are not accepted by default.
+    pub fn set_accept_batches(&mut self, accept_batches: bool) {
+        self.accept_batches = accept_batches;
+    }
+
+    /// Sets the size in bytes of the largest message that is parsed, not
+    /// counting the trailing newline. A larger message is still read, but
+    /// is returned as `ReadError::TooLarge`. There is no limit by default.
+    pub fn set_max_message_size(&mut self, max_message_size: Option<usize>) {
+        self.max_message_size = max_message_size;
+    }
+
     /// Attempts to read the next line from the stream and parse it as
     /// an RPC object.
     ///
@@ -63,15 +99,54 @@ impl MessageReader {
     /// I/O error, if the stream is closed, or if the message is not
     /// a valid JSON object.
     pub fn next<R: BufRead>(&mut self, reader: &mut R) -> Result<RpcObject, ReadError> {
-        self.0.clear();
-        let _ = reader.read_line(&mut self.0)?;
-        if self.0.is_empty() {
+        self.read_line(reader)?;
+        self.check_size()?;
+        self.parse(&self.buf)
+    }
+
+    /// Attempts to read the next line from the stream and parse it as
+    /// an RPC object or, if batches are accepted, as a batch of them.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if there is an underlying
+    /// I/O error, if the stream is closed, or if the message is neither
+    /// a valid JSON object nor a batch.
+    pub fn next_batch<R: BufRead>(&mut self, reader: &mut R) -> Result<Batch, ReadError> {
+        if !self.accept_batches {
+            return self.next(reader).map(|obj| vec![Ok(obj)]);
+        }
+        self.read_line(reader)?;
+        self.check_size()?;
+        self.parse_batch(&self.buf)
+    }
+
+    fn read_line<R: BufRead>(&mut self, reader: &mut R) -> Result<(), ReadError> {
+        self.buf.clear();
+        let _ = reader.read_line(&mut self.buf)?;
+        if self.buf.is_empty() {
             Err(ReadError::Disconnect)
         } else {
-            self.parse(&self.0)
+            Ok(())
         }
     }
 
+    /// Returns `ReadError::TooLarge` if the message that was read is over
+    /// the size limit.
+    fn check_size(&self) -> Result<(), ReadError> {
+        let limit = match self.max_message_size {
+            Some(limit) => limit,
+            None => return Ok(()),
+        };
+        let size = self.buf.trim_end_matches(|c| c == '\n' || c == '\r').len();
+        if size <= limit {
+            return Ok(());
+        }
+        // this skips over the other fields without building values for them
+        let header = serde_json::from_str::<MessageHeader>(&self.buf).unwrap_or_default();
+        Err(ReadError::TooLarge { id: header.id, method: header.method, size, limit })
+    }
+
     /// Attempts to parse a &str as an RPC Object.
     ///
     /// This should not be called directly unless you are writing tests.
@@ -85,6 +160,24 @@ impl MessageReader {
             Ok(val.into())
         }
     }
+
+    /// Attempts to parse a &str as an RPC Object or, if batches are
+    /// accepted, as a batch of them.
+    ///
+    /// This should not be called directly unless you are writing tests.
+    #[doc(hidden)]
+    pub fn parse_batch(&self, s: &str) -> Result<Batch, ReadError> {
+        let _trace = xi_trace::trace_block("parse", &["rpc"]);
+        match serde_json::from_str::<Value>(s)? {
+            Value::Array(ref vals) if vals.is_empty() => Err(ReadError::NotObject),
+            Value::Array(vals) if self.accept_batches => Ok(vals
+                .into_iter()
+                .map(|val| if val.is_object() { Ok(val.into()) } else { Err(ReadError::NotObject) })
+                .collect()),
+            val if val.is_object() => Ok(vec![Ok(val.into())]),
+            _ => Err(ReadError::NotObject),
+        }
+    }
 }
 
 impl RpcObject {
@@ -242,6 +335,61 @@ mod tests {
         assert_eq!(resp, Ok(json!("success!")));
     }
 
+    #[test]
+    fn batch() {
+        let json = r#"[{"id":0,"method":"new_view","params":{}},
+            {"method":"close_view","params":{"view_id":"view-id-1"}},
+            {"id":1,"result":"success!"}]"#;
+        let mut reader = MessageReader::default();
+        assert!(matches!(reader.parse_batch(json), Err(ReadError::NotObject)));
+
+        reader.set_accept_batches(true);
+        let batch = reader.parse_batch(json).unwrap();
+        assert_eq!(batch.len(), 3);
+        let mut batch = batch.into_iter().map(Result::unwrap);
+        let req = batch.next().unwrap().into_rpc::<TestN, TestR>().unwrap();
+        assert_eq!(req, Call::Request(0, TestR::NewView { file_path: None }));
+        let notif = batch.next().unwrap().into_rpc::<TestN, TestR>().unwrap();
+        assert_eq!(notif, Call::Notification(TestN::CloseView { view_id: "view-id-1".into() }));
+        let resp = batch.next().unwrap();
+        assert!(resp.is_response());
+        assert_eq!(resp.into_response().unwrap(), Ok(json!("success!")));
+
+        // a single object is a batch of one
+        let json = r#"{"method":"close_view","params":{"view_id":"view-id-1"}}"#;
+        assert_eq!(reader.parse_batch(json).unwrap().len(), 1);
+        assert!(matches!(reader.parse_batch("[]"), Err(ReadError::NotObject)));
+    }
+
+    #[test]
+    fn batch_bad_element() {
+        let json = r#"[{"method":"close_view","params":{"view_id":"view-id-1"}},
+            5,
+            {"method":"close_view","params":{"view_id":"view-id-2"}}]"#;
+        let mut reader = MessageReader::default();
+        reader.set_accept_batches(true);
+        let mut batch = reader.parse_batch(json).unwrap().into_iter();
+        let close =
+            |view_id: &str| Call::Notification(TestN::CloseView { view_id: view_id.into() });
+        let first = batch.next().unwrap().unwrap().into_rpc::<TestN, TestR>().unwrap();
+        assert_eq!(first, close("view-id-1"));
+        assert!(matches!(batch.next().unwrap(), Err(ReadError::NotObject)));
+        let last = batch.next().unwrap().unwrap().into_rpc::<TestN, TestR>().unwrap();
+        assert_eq!(last, close("view-id-2"));
+        assert!(batch.next().is_none());
+    }
+
+    #[test]
+    fn next_batch_without_batches() {
+        let mut stream = "[{\"method\":\"a\"}]\n{\"method\":\"b\"}\n".as_bytes();
+        let mut reader = MessageReader::default();
+        assert!(matches!(reader.next_batch(&mut stream), Err(ReadError::NotObject)));
+        let batch = reader.next_batch(&mut stream).unwrap();
+        assert_eq!(batch.len(), 1);
+        assert_eq!(batch[0].as_ref().unwrap().get_method(), Some("b"));
+        assert!(matches!(reader.next_batch(&mut stream), Err(ReadError::Disconnect)));
+    }
+
     #[test]
     fn test_err() {
         let json = r#"{"code": -32600, "message": "Invalid Request"}"#;
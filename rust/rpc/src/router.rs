@@ -0,0 +1,162 @@
+// Copyright 2020 The xi-editor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Dispatching incoming RPCs to several handlers, by method name prefix.
+
+use serde_json::{Error as JsonError, Value};
+
+use crate::{Handler, RemoteError, RpcCtx};
+
+/// The JSON-RPC error code for a method that does not exist.
+const METHOD_NOT_FOUND: i64 = -32601;
+
+/// A `Handler` whose RPC types have been erased, so that handlers of
+/// different types can be routed to by the same `Router`.
+trait Route {
+    fn handle_notification(&mut self, ctx: &RpcCtx, rpc: Value) -> Result<(), JsonError>;
+    fn handle_request(&mut self, ctx: &RpcCtx, rpc: Value) -> Result<Value, RemoteError>;
+    fn idle(&mut self, ctx: &RpcCtx, token: usize);
+    fn on_disconnect(&mut self, ctx: &RpcCtx);
+}
+
+impl<H: Handler> Route for H {
+    fn handle_notification(&mut self, ctx: &RpcCtx, rpc: Value) -> Result<(), JsonError> {
+        let rpc = serde_json::from_value(rpc)?;
+        Handler::handle_notification(self, ctx, rpc);
+        Ok(())
+    }
+
+    fn handle_request(&mut self, ctx: &RpcCtx, rpc: Value) -> Result<Value, RemoteError> {
+        let rpc = serde_json::from_value(rpc)?;
+        Handler::handle_request(self, ctx, rpc)
+    }
+
+    fn idle(&mut self, ctx: &RpcCtx, token: usize) {
+        Handler::idle(self, ctx, token)
+    }
+
+    fn on_disconnect(&mut self, ctx: &RpcCtx) {
+        Handler::on_disconnect(self, ctx)
+    }
+}
+
+/// A `Handler` which lets a single `RpcLoop` host several services, by
+/// passing each incoming request and notification on to the handler
+/// registered for the longest prefix of its method name, such as `"lsp."`.
+///
+/// By default the prefix is stripped from the method name, so that each
+/// handler sees the methods it would if it had a loop of its own.
+///
+/// An RPC that matches no prefix goes to the fallback handler, if one is
+/// set. Otherwise a request is answered with a "method not found" error,
+/// and a notification is dropped. Unlike with a single handler, a
+/// notification the chosen handler can't parse is also dropped, rather
+/// than ending the main loop.
+///
+/// Idle tokens are not routed: every handler is called with each of
+/// them, so handlers sharing a loop should schedule distinct tokens.
+/// Every handler is likewise told when the loop disconnects.
+pub struct Router {
+    routes: Vec<(String, Box<dyn Route>)>,
+    fallback: Option<Box<dyn Route>>,
+    strip_prefix: bool,
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Router { routes: Vec::new(), fallback: None, strip_prefix: true }
+    }
+
+    /// Sends the RPCs whose method starts with `prefix` to `handler`.
+    /// A handler registered earlier for the same prefix is replaced.
+    pub fn add_route<H: Handler + 'static>(&mut self, prefix: &str, handler: H) {
+        self.routes.retain(|(p, _)| p != prefix);
+        self.routes.push((prefix.to_owned(), Box::new(handler)));
+    }
+
+    /// Sends the RPCs which match no prefix to `handler`.
+    pub fn set_fallback<H: Handler + 'static>(&mut self, handler: H) {
+        self.fallback = Some(Box::new(handler));
+    }
+
+    /// Sets whether the matched prefix is removed from the method name
+    /// before an RPC is passed on. It is by default.
+    pub fn set_strip_prefix(&mut self, strip_prefix: bool) {
+        self.strip_prefix = strip_prefix;
+    }
+
+    /// Returns the handler for `rpc`, first stripping the matched prefix
+    /// from its method if needed.
+    fn route(&mut self, rpc: &mut Value) -> Option<&mut (dyn Route + 'static)> {
+        let Router { routes, fallback, strip_prefix } = self;
+        let method = rpc.get("method").and_then(Value::as_str).unwrap_or_default();
+        let route = routes
+            .iter_mut()
+            .filter(|(prefix, _)| method.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len());
+        match route {
+            Some((prefix, handler)) => {
+                if *strip_prefix {
+                    let method = method[prefix.len()..].to_owned();
+                    rpc["method"] = Value::String(method);
+                }
+                Some(handler.as_mut())
+            }
+            None => fallback.as_deref_mut(),
+        }
+    }
+
+    fn all_routes(&mut self) -> impl Iterator<Item = &mut Box<dyn Route>> {
+        self.routes.iter_mut().map(|(_, handler)| handler).chain(self.fallback.iter_mut())
+    }
+}
+
+impl Default for Router {
+    fn default() -> Self {
+        Router::new()
+    }
+}
+
+impl Handler for Router {
+    type Notification = Value;
+    type Request = Value;
+
+    fn handle_notification(&mut self, ctx: &RpcCtx, mut rpc: Value) {
+        let method = rpc.get("method").cloned();
+        match self.route(&mut rpc) {
+            Some(handler) => {
+                if let Err(err) = handler.handle_notification(ctx, rpc) {
+                    warn!("dropping invalid notification {:?}: {}", method, err);
+                }
+            }
+            None => warn!("no route for notification {:?}", method),
+        }
+    }
+
+    fn handle_request(&mut self, ctx: &RpcCtx, mut rpc: Value) -> Result<Value, RemoteError> {
+        let method = rpc.get("method").cloned();
+        match self.route(&mut rpc) {
+            Some(handler) => handler.handle_request(ctx, rpc),
+            None => Err(RemoteError::custom(METHOD_NOT_FOUND, "Method not found", method)),
+        }
+    }
+
+    fn idle(&mut self, ctx: &RpcCtx, token: usize) {
+        self.all_routes().for_each(|handler| handler.idle(ctx, token));
+    }
+
+    fn on_disconnect(&mut self, ctx: &RpcCtx) {
+        self.all_routes().for_each(|handler| handler.on_disconnect(ctx));
+    }
+}
@@ -20,7 +20,7 @@ use std::time::{Duration, Instant};
 
 use serde_json::{self, Value};
 
-use super::{Callback, Error, MessageReader, Peer, ReadError, Response, RpcObject};
+use super::{Callback, Error, MessageReader, Peer, PeerMetrics, ReadError, Response, RpcObject};
 
 /// Wraps an instance of `mpsc::Sender`, implementing `Write`.
 ///
@@ -123,6 +123,9 @@ impl Peer for DummyPeer {
     fn request_is_pending(&self) -> bool {
         false
     }
+    fn metrics(&self) -> PeerMetrics {
+        PeerMetrics::default()
+    }
     fn schedule_idle(&self, _token: usize) {}
     fn schedule_timer(&self, _time: Instant, _token: usize) {}
 }
@@ -14,13 +14,19 @@
 #[macro_use]
 extern crate serde_json;
 extern crate xi_rpc;
+extern crate xi_trace;
 
-use std::io;
-use std::time::Duration;
+use std::io::{self, BufReader, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use serde_json::Value;
 use xi_rpc::test_utils::{make_reader, test_channel};
-use xi_rpc::{Handler, ReadError, RemoteError, RpcCall, RpcCtx, RpcLoop};
+use xi_rpc::{
+    Error, FlushPolicy, Handler, Peer, ReadError, RemoteError, ResponseToken, Router, RpcCall,
+    RpcCtx, RpcLoop,
+};
 
 /// Handler that responds to requests with whatever params they sent.
 pub struct EchoHandler;
@@ -90,3 +96,523 @@ fn test_bad_json_err() {
         Ok(()) => panic!("Expected an error"),
     }
 }
+
+#[test]
+fn test_reader_panic_err() {
+    // a panic in the reader thread should be reported, not hang the runloop.
+    let mut handler = EchoHandler;
+    let mut rpc_looper = RpcLoop::new(io::sink());
+    let exit =
+        rpc_looper.mainloop(|| -> io::Cursor<Vec<u8>> { panic!("reader exploded") }, &mut handler);
+    match exit {
+        Err(ReadError::ReaderPanic(msg)) => assert_eq!(msg, "reader exploded"),
+        Err(err) => panic!("Incorrect error: {:?}", err),
+        Ok(()) => panic!("Expected an error"),
+    }
+}
+
+#[test]
+fn test_recv_batch() {
+    // each request in a batch should receive a response, in order
+    let mut handler = EchoHandler;
+    let (tx, mut rx) = test_channel();
+    let mut rpc_looper = RpcLoop::new(tx);
+    rpc_looper.set_accept_batches(true);
+    let r = make_reader(
+        r#"[{"id": 0, "method": "hullo", "params": {"words": "one"}}, {"method": "hullo", "params": {}}, {"id": 1, "method": "hullo", "params": {"words": "two"}}]"#,
+    );
+    assert!(rpc_looper.mainloop(|| r, &mut handler).is_ok());
+    assert_eq!(rx.expect_response().unwrap()["words"], json!("one"));
+    assert_eq!(rx.expect_response().unwrap()["words"], json!("two"));
+    rx.expect_nothing();
+}
+
+#[test]
+fn test_recv_batch_bad_element() {
+    // the rest of a batch should be handled before a malformed element
+    // causes the runloop to return an error.
+    let mut handler = EchoHandler;
+    let (tx, mut rx) = test_channel();
+    let mut rpc_looper = RpcLoop::new(tx);
+    rpc_looper.set_accept_batches(true);
+    let r = make_reader(
+        r#"[{"id": 0, "method": "hullo", "params": {"words": "one"}}, 5, {"id": 1, "method": "hullo", "params": {"words": "two"}}]"#,
+    );
+    let exit = rpc_looper.mainloop(|| r, &mut handler);
+    match exit {
+        Err(ReadError::NotObject) => (),
+        Err(err) => panic!("Incorrect error: {:?}", err),
+        Ok(()) => panic!("Expected an error"),
+    }
+    assert_eq!(rx.expect_response().unwrap()["words"], json!("one"));
+    assert_eq!(rx.expect_response().unwrap()["words"], json!("two"));
+}
+
+#[test]
+fn test_cancel_request() {
+    let mut handler = EchoHandler;
+    let mut rpc_looper = RpcLoop::new(io::sink());
+    let peer = rpc_looper.get_raw_peer();
+    let (tx, rx) = mpsc::channel();
+    let id = peer.send_rpc_request_async_with_id(
+        "never_answered",
+        &json!({}),
+        Box::new(move |result| tx.send(result).unwrap()),
+    );
+    peer.cancel_rpc_request(id);
+    match rx.try_recv() {
+        Ok(Err(Error::Cancelled)) => (),
+        other => panic!("Expected a cancellation, got {:?}", other),
+    }
+
+    // a late response and a second cancellation are ignored
+    peer.cancel_rpc_request(id);
+    let r = make_reader(format!(r#"{{"id": {}, "result": "too late"}}"#, id));
+    assert!(rpc_looper.mainloop(|| r, &mut handler).is_ok());
+    assert!(rx.try_recv().is_err());
+}
+
+#[test]
+fn test_request_timeout() {
+    let mut handler = EchoHandler;
+    let mut rpc_looper = RpcLoop::new(io::sink());
+    let peer = rpc_looper.get_raw_peer();
+    let start = Instant::now();
+    let timeout = Duration::from_millis(100);
+    match peer.send_rpc_request_timeout("never_answered", &json!({}), timeout) {
+        Err(Error::Timeout) => (),
+        other => panic!("Expected a timeout, got {:?}", other),
+    }
+    let elapsed = start.elapsed();
+    assert!(elapsed >= timeout && elapsed < timeout * 5, "timed out after {:?}", elapsed);
+
+    // a late response is ignored
+    let r = make_reader(r#"{"id": 0, "result": "too late"}"#);
+    assert!(rpc_looper.mainloop(|| r, &mut handler).is_ok());
+}
+
+/// Reader that blocks until its sender is dropped, and then reports EOF.
+struct BlockingReader(mpsc::Receiver<()>);
+
+impl io::Read for BlockingReader {
+    fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+        while self.0.recv().is_ok() {}
+        Ok(0)
+    }
+}
+
+/// Handler that reports the token and time of each idle call.
+struct IdleHandler(mpsc::Sender<(usize, Instant)>);
+
+#[allow(unused)]
+impl Handler for IdleHandler {
+    type Notification = RpcCall;
+    type Request = RpcCall;
+    fn handle_notification(&mut self, ctx: &RpcCtx, rpc: Self::Notification) {}
+    fn handle_request(&mut self, ctx: &RpcCtx, rpc: Self::Request) -> Result<Value, RemoteError> {
+        Ok(rpc.params)
+    }
+    fn idle(&mut self, ctx: &RpcCtx, token: usize) {
+        self.0.send((token, Instant::now())).unwrap();
+    }
+}
+
+#[test]
+fn test_timer_wakes_idle_loop() {
+    let (eof_tx, eof_rx) = mpsc::channel::<()>();
+    let (fired_tx, fired_rx) = mpsc::channel();
+    let mut rpc_looper = RpcLoop::new(io::sink());
+    let peer = rpc_looper.get_raw_peer();
+    let scheduler = std::thread::spawn(move || {
+        let mut latencies = Vec::new();
+        for token in 0..10 {
+            // give the loop time to start waiting
+            std::thread::sleep(Duration::from_millis(10));
+            let scheduled = Instant::now();
+            peer.schedule_timer(scheduled + Duration::from_millis(1), token);
+            let (fired_token, fired) = fired_rx.recv().unwrap();
+            assert_eq!(fired_token, token);
+            latencies.push(fired - scheduled);
+        }
+        drop(eof_tx);
+        latencies
+    });
+
+    let mut handler = IdleHandler(fired_tx);
+    let reader = BufReader::new(BlockingReader(eof_rx));
+    assert!(rpc_looper.mainloop(|| reader, &mut handler).is_ok());
+    let latencies = scheduler.join().unwrap();
+    // the wakeup itself is tested in the crate; this bound only catches a
+    // loop that stops noticing timers, and leaves room for a loaded machine.
+    let slowest = latencies.iter().max().unwrap();
+    assert!(*slowest < Duration::from_secs(1), "timer latencies: {:?}", latencies);
+}
+
+#[test]
+fn test_timer_fire_is_traced() {
+    xi_trace::enable_tracing();
+    let (eof_tx, eof_rx) = mpsc::channel::<()>();
+    let (fired_tx, fired_rx) = mpsc::channel();
+    let mut rpc_looper = RpcLoop::new(io::sink());
+    rpc_looper.get_raw_peer().schedule_timer(Instant::now(), 42);
+    let closer = std::thread::spawn(move || {
+        fired_rx.recv().unwrap();
+        drop(eof_tx);
+    });
+
+    let mut handler = IdleHandler(fired_tx);
+    let reader = BufReader::new(BlockingReader(eof_rx));
+    assert!(rpc_looper.mainloop(|| reader, &mut handler).is_ok());
+    closer.join().unwrap();
+
+    // disabling tracing clears the samples, so they are read first
+    let fired = xi_trace::samples_cloned_unsorted().into_iter().any(|sample| {
+        let payload = sample.args.and_then(|args| args.payload).map(|p| p.to_string());
+        sample.name == "timer_fire"
+            && payload.map_or(false, |p| p.contains("token: 42, delay_us: "))
+    });
+    xi_trace::disable_tracing();
+    assert!(fired);
+}
+
+/// Handler that logs idle work and disconnection, in order.
+struct DisconnectHandler(Arc<Mutex<Vec<&'static str>>>);
+
+#[allow(unused)]
+impl Handler for DisconnectHandler {
+    type Notification = RpcCall;
+    type Request = RpcCall;
+    fn handle_notification(&mut self, ctx: &RpcCtx, rpc: Self::Notification) {}
+    fn handle_request(&mut self, ctx: &RpcCtx, rpc: Self::Request) -> Result<Value, RemoteError> {
+        Ok(rpc.params)
+    }
+    fn idle(&mut self, ctx: &RpcCtx, token: usize) {
+        self.0.lock().unwrap().push("idle");
+    }
+    fn on_disconnect(&mut self, ctx: &RpcCtx) {
+        self.0.lock().unwrap().push("disconnect");
+    }
+}
+
+#[test]
+fn test_on_disconnect() {
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let mut handler = DisconnectHandler(log.clone());
+    let mut rpc_looper = RpcLoop::new(io::sink());
+    let peer = rpc_looper.get_raw_peer();
+    peer.schedule_idle(0);
+    let pending_log = log.clone();
+    peer.send_rpc_request_async(
+        "never_answered",
+        &json!({}),
+        Box::new(move |result| {
+            assert!(matches!(result, Err(Error::PeerDisconnect)));
+            pending_log.lock().unwrap().push("pending");
+        }),
+    );
+    let r = make_reader("");
+    assert!(rpc_looper.mainloop(|| r, &mut handler).is_ok());
+    assert_eq!(*log.lock().unwrap(), vec!["idle", "disconnect", "pending"]);
+
+    // also called, once, when the loop exits with an error
+    log.lock().unwrap().clear();
+    let r = make_reader("this is not json\n");
+    assert!(rpc_looper.mainloop(|| r, &mut handler).is_err());
+    assert_eq!(*log.lock().unwrap(), vec!["disconnect"]);
+}
+
+/// Writer that counts the calls to `write`, and the lines written.
+#[derive(Clone, Default)]
+struct CountingWriter {
+    writes: Arc<AtomicUsize>,
+    lines: Arc<AtomicUsize>,
+}
+
+impl Write for CountingWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writes.fetch_add(1, Ordering::SeqCst);
+        self.lines.fetch_add(buf.iter().filter(|b| **b == b'\n').count(), Ordering::SeqCst);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Handler that sends a burst of notifications for each notification received.
+struct SpamHandler;
+
+#[allow(unused)]
+impl Handler for SpamHandler {
+    type Notification = RpcCall;
+    type Request = RpcCall;
+    fn handle_notification(&mut self, ctx: &RpcCtx, rpc: Self::Notification) {
+        for i in 0..100 {
+            ctx.send_rpc_notification("spam", &json!({ "i": i }));
+        }
+    }
+    fn handle_request(&mut self, ctx: &RpcCtx, rpc: Self::Request) -> Result<Value, RemoteError> {
+        Ok(rpc.params)
+    }
+}
+
+fn run_spam<W: Write + Send>(mut rpc_looper: RpcLoop<W>) {
+    let r = make_reader(r#"{"method": "spam_me", "params": {}}"#);
+    assert!(rpc_looper.mainloop(|| r, &mut SpamHandler).is_ok());
+}
+
+#[test]
+fn test_buffered_writes() {
+    let writer = CountingWriter::default();
+    run_spam(RpcLoop::new(writer.clone()));
+    assert_eq!(writer.lines.load(Ordering::SeqCst), 100);
+    assert_eq!(writer.writes.load(Ordering::SeqCst), 100);
+
+    let writer = CountingWriter::default();
+    run_spam(RpcLoop::new_with_buffering(writer.clone(), FlushPolicy::OnIdle));
+    // everything is written, but in far fewer calls
+    assert_eq!(writer.lines.load(Ordering::SeqCst), 100);
+    assert!(writer.writes.load(Ordering::SeqCst) < 10);
+}
+
+#[test]
+fn test_buffered_response_is_flushed() {
+    let writer = CountingWriter::default();
+    let rpc_looper = RpcLoop::new_with_buffering(writer.clone(), FlushPolicy::OnIdle);
+    let peer = rpc_looper.get_raw_peer();
+    peer.send_rpc_notification("buffered", &json!({}));
+    assert_eq!(writer.writes.load(Ordering::SeqCst), 0);
+    peer.send_rpc_request_async("also_buffered", &json!({}), Box::new(|_| ()));
+    assert_eq!(writer.writes.load(Ordering::SeqCst), 0);
+    // a synchronous request has to be flushed; it would never be answered otherwise
+    std::thread::spawn(move || peer.send_rpc_request("sync", &json!({})));
+    for _ in 0..1000 {
+        if writer.lines.load(Ordering::SeqCst) == 3 {
+            return;
+        }
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    panic!("synchronous request was not flushed");
+}
+
+/// Handler that defers its responses to "slow" requests until it receives
+/// a "finish" notification, or drops them on a "drop" notification.
+#[derive(Default)]
+struct DeferringHandler {
+    pending: Vec<ResponseToken>,
+}
+
+impl Handler for DeferringHandler {
+    type Notification = RpcCall;
+    type Request = RpcCall;
+    fn handle_notification(&mut self, _ctx: &RpcCtx, rpc: Self::Notification) {
+        match rpc.method.as_str() {
+            "finish" => self.pending.drain(..).for_each(|token| token.respond(Ok(json!("slow")))),
+            "drop" => self.pending.clear(),
+            _ => (),
+        }
+    }
+    fn handle_request(&mut self, ctx: &RpcCtx, rpc: Self::Request) -> Result<Value, RemoteError> {
+        if rpc.method == "slow" {
+            self.pending.push(ctx.defer_response());
+        }
+        Ok(json!(rpc.method))
+    }
+}
+
+#[test]
+fn test_deferred_response() {
+    let mut handler = DeferringHandler::default();
+    let (tx, mut rx) = test_channel();
+    let mut rpc_looper = RpcLoop::new(tx);
+    let r = make_reader(
+        r#"{"id": 0, "method": "slow", "params": {}}
+{"id": 1, "method": "fast", "params": {}}
+{"method": "finish", "params": {}}
+{"id": 2, "method": "slow", "params": {}}
+{"id": 3, "method": "fast", "params": {}}
+{"method": "drop", "params": {}}
+{"id": 4, "method": "fast", "params": {}}"#,
+    );
+    assert!(rpc_looper.mainloop(|| r, &mut handler).is_ok());
+
+    let mut next_response = || {
+        let obj = rx.expect_object();
+        let id = obj.get_id().unwrap();
+        (id, obj.into_response().unwrap())
+    };
+    // the deferred request is answered after the one that followed it
+    assert_eq!(next_response(), (1, Ok(json!("fast"))));
+    assert_eq!(next_response(), (0, Ok(json!("slow"))));
+    assert_eq!(next_response(), (3, Ok(json!("fast"))));
+    // a dropped token still gets an answer
+    let (id, resp) = next_response();
+    assert_eq!(id, 2);
+    assert!(resp.is_err());
+    // requests that aren't deferred keep their order
+    assert_eq!(next_response(), (4, Ok(json!("fast"))));
+    rx.expect_nothing();
+}
+
+/// Writer that keeps everything written to it.
+#[derive(Clone, Default)]
+struct RecordingWriter(Arc<Mutex<Vec<u8>>>);
+
+impl Write for RecordingWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Handler that answers a notification with a batch of notifications, and
+/// records the notifications it receives.
+#[derive(Default)]
+struct BatchingHandler(Vec<RpcCall>);
+
+#[allow(unused)]
+impl Handler for BatchingHandler {
+    type Notification = RpcCall;
+    type Request = RpcCall;
+    fn handle_notification(&mut self, ctx: &RpcCtx, rpc: Self::Notification) {
+        if rpc.method == "batch_me" {
+            let mut batch = ctx.begin_batch();
+            for i in 0..3 {
+                batch.send_rpc_notification("batched", &json!({ "i": i }));
+            }
+        }
+        self.0.push(rpc);
+    }
+    fn handle_request(&mut self, ctx: &RpcCtx, rpc: Self::Request) -> Result<Value, RemoteError> {
+        Ok(rpc.params)
+    }
+}
+
+#[test]
+fn test_notification_batch() {
+    let writer = RecordingWriter::default();
+    let mut rpc_looper = RpcLoop::new(writer.clone());
+    let r = make_reader(r#"{"method": "batch_me", "params": {}}"#);
+    assert!(rpc_looper.mainloop(|| r, &mut BatchingHandler::default()).is_ok());
+
+    let output = String::from_utf8(writer.0.lock().unwrap().clone()).unwrap();
+    assert_eq!(output.lines().count(), 1);
+    let batch: Value = serde_json::from_str(&output).unwrap();
+    assert_eq!(
+        batch,
+        json!([
+            {"method": "batched", "params": {"i": 0}},
+            {"method": "batched", "params": {"i": 1}},
+            {"method": "batched", "params": {"i": 2}},
+        ])
+    );
+
+    // a peer accepting batches handles each notification in turn
+    let mut handler = BatchingHandler::default();
+    let mut rpc_looper = RpcLoop::new(io::sink());
+    rpc_looper.set_accept_batches(true);
+    assert!(rpc_looper.mainloop(|| make_reader(output), &mut handler).is_ok());
+    let params = handler.0.iter().map(|rpc| rpc.params["i"].clone()).collect::<Vec<_>>();
+    assert_eq!(params, vec![json!(0), json!(1), json!(2)]);
+}
+
+/// Handler that logs the RPCs it is sent, and answers requests with its name.
+struct NamedHandler(&'static str, Arc<Mutex<Vec<String>>>);
+
+#[allow(unused)]
+impl Handler for NamedHandler {
+    type Notification = RpcCall;
+    type Request = RpcCall;
+    fn handle_notification(&mut self, ctx: &RpcCtx, rpc: Self::Notification) {
+        self.1.lock().unwrap().push(format!("{} {}", self.0, rpc.method));
+    }
+    fn handle_request(&mut self, ctx: &RpcCtx, rpc: Self::Request) -> Result<Value, RemoteError> {
+        self.1.lock().unwrap().push(format!("{} {}", self.0, rpc.method));
+        Ok(json!(self.0))
+    }
+}
+
+#[test]
+fn test_router() {
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let mut router = Router::new();
+    router.add_route("edit.", NamedHandler("edit", log.clone()));
+    router.add_route("lsp.", NamedHandler("lsp", log.clone()));
+    router.add_route("lsp.ext.", NamedHandler("ext", log.clone()));
+    let (tx, mut rx) = test_channel();
+    let mut rpc_looper = RpcLoop::new(tx);
+    let r = make_reader(
+        r#"{"method": "edit.insert", "params": {"chars": "a"}}
+{"id": 0, "method": "lsp.hover", "params": {}}
+{"id": 1, "method": "lsp.ext.inlay_hints", "params": {}}
+{"id": 2, "method": "unknown", "params": {}}
+{"method": "unknown", "params": {}}"#,
+    );
+    assert!(rpc_looper.mainloop(|| r, &mut router).is_ok());
+    assert_eq!(rx.expect_response().unwrap(), json!("lsp"));
+    // the longest matching prefix wins
+    assert_eq!(rx.expect_response().unwrap(), json!("ext"));
+    match rx.expect_response() {
+        Err(RemoteError::Custom { code, .. }) => assert_eq!(code, -32601),
+        other => panic!("expected an error, got {:?}", other),
+    }
+    rx.expect_nothing();
+    assert_eq!(*log.lock().unwrap(), vec!["edit insert", "lsp hover", "ext inlay_hints"]);
+
+    // with stripping off, and a fallback for the rest
+    log.lock().unwrap().clear();
+    router.set_strip_prefix(false);
+    router.set_fallback(NamedHandler("fallback", log.clone()));
+    let r = make_reader(
+        r#"{"method": "edit.insert", "params": {"chars": "a"}}
+{"id": 3, "method": "unknown", "params": {}}"#,
+    );
+    assert!(rpc_looper.mainloop(|| r, &mut router).is_ok());
+    assert_eq!(rx.expect_response().unwrap(), json!("fallback"));
+    assert_eq!(*log.lock().unwrap(), vec!["edit edit.insert", "fallback unknown"]);
+}
+
+#[test]
+fn test_max_message_size() {
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let mut handler = NamedHandler("handler", log.clone());
+    let (tx, mut rx) = test_channel();
+    let mut rpc_looper = RpcLoop::new(tx);
+    rpc_looper.set_max_message_size(Some(100));
+    let big = "x".repeat(100);
+    let messages = [
+        json!({"method": "big_notif", "params": {"s": big}}),
+        json!({"id": 0, "method": "big_req", "params": {"s": big}}),
+        json!({"method": "small_notif", "params": {}}),
+        json!({"id": 1, "method": "small_req", "params": {}}),
+    ];
+    let r = make_reader(messages.iter().map(Value::to_string).collect::<Vec<_>>().join("\n"));
+    assert!(rpc_looper.mainloop(|| r, &mut handler).is_ok());
+    // the oversized request is answered with an error, in turn
+    match rx.expect_response() {
+        Err(RemoteError::InvalidRequest(_)) => (),
+        other => panic!("expected an error, got {:?}", other),
+    }
+    assert_eq!(rx.expect_response().unwrap(), json!("handler"));
+    rx.expect_nothing();
+    // and neither oversized message reaches the handler
+    assert_eq!(*log.lock().unwrap(), vec!["handler small_notif", "handler small_req"]);
+
+    // oversized outgoing messages are not sent
+    let peer = rpc_looper.get_raw_peer();
+    peer.send_rpc_notification("big_notif", &json!({ "s": big }));
+    rx.expect_nothing();
+    let (result_tx, result_rx) = mpsc::channel();
+    let callback = move |result: Result<Value, Error>| result_tx.send(result).unwrap();
+    peer.send_rpc_request_async("big_req", &json!({ "s": big }), Box::new(callback));
+    match result_rx.try_recv() {
+        Ok(Err(Error::TooLarge { limit: 100, .. })) => (),
+        other => panic!("expected an error, got {:?}", other),
+    }
+    assert_eq!(peer.pending_request_count(), 0);
+}
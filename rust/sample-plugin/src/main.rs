@@ -14,12 +14,15 @@
 
 //! A sample plugin, intended as an illustration and a template for plugin
 //! developers.
+extern crate serde_json;
 extern crate xi_core_lib as xi_core;
 extern crate xi_plugin_lib;
 extern crate xi_rope;
 
 use std::path::Path;
 
+use serde_json::Value;
+
 use crate::xi_core::ConfigTable;
 use xi_plugin_lib::{mainloop, ChunkCache, Error, Plugin, View};
 use xi_rope::delta::Builder as EditBuilder;
@@ -28,10 +31,11 @@ use xi_rope::rope::RopeDelta;
 
 /// A type that implements the `Plugin` trait, and interacts with xi-core.
 ///
-/// Currently, this plugin has a single noteworthy behaviour,
-/// intended to demonstrate how to edit a document; when the plugin is active,
-/// and the user inserts an exclamation mark, the plugin will capitalize the
-/// preceding word.
+/// Currently, this plugin has two noteworthy behaviours, intended to
+/// demonstrate how to edit a document: when the plugin is active and the
+/// user inserts an exclamation mark, the plugin will capitalize the preceding
+/// word; and the `uppercase_selections` custom command capitalizes each
+/// selected region.
 struct SamplePlugin;
 
 //NOTE: implementing the `Plugin` trait is the sole requirement of a plugin.
@@ -70,6 +74,14 @@ impl Plugin for SamplePlugin {
             }
         }
     }
+
+    fn custom_command(&mut self, view: &mut View<Self::Cache>, method: &str, _params: Value) {
+        if method == "uppercase_selections" {
+            if let Err(err) = self.uppercase_selections(view) {
+                eprintln!("uppercase_selections failed: {:?}", err);
+            }
+        }
+    }
 }
 
 impl SamplePlugin {
@@ -101,6 +113,21 @@ impl SamplePlugin {
         view.edit(builder.build(), 0, false, true, "sample".into());
         Ok(())
     }
+
+    /// Uppercases the text of each non-caret selection region, in one edit.
+    fn uppercase_selections(&self, view: &mut View<ChunkCache>) -> Result<(), Error> {
+        let mut builder = EditBuilder::new(view.get_buf_size());
+        // regions are sorted and disjoint, as the builder requires
+        for region in view.get_selections()?.iter().filter(|region| !region.is_caret) {
+            let iv = Interval::new(region.start.min(region.end), region.start.max(region.end));
+            let new_text = view.get_region(iv)?.to_uppercase();
+            builder.replace(iv, new_text.into());
+        }
+        if !builder.is_identity() {
+            view.edit(builder.build(), 0, false, true, "sample".into());
+        }
+        Ok(())
+    }
 }
 
 fn main() {
@@ -23,10 +23,11 @@ extern crate xi_rpc;
 
 use std::collections::HashMap;
 use std::fs;
-use std::io;
+use std::io::{self, Write};
 use std::path::{Path, PathBuf};
 use std::process;
 
+use xi_core_lib::plugins::validate_plugin_path;
 use xi_core_lib::XiCore;
 use xi_rpc::RpcLoop;
 
@@ -204,14 +205,43 @@ fn generate_logfile_config(flags: &HashMap<String, Option<String>>) -> LogfileCo
     LogfileConfig { directory: log_dir_flag_option, file: log_file_flag_option }
 }
 
+/// Validates the plugin manifests at `path`, for `--validate-plugin`, writing
+/// the problems found to `out`. Returns the exit status: nonzero if any
+/// manifest has errors, or if none was found.
+fn validate_plugins<W: Write>(path: &Path, out: &mut W) -> io::Result<i32> {
+    let reports = validate_plugin_path(path);
+    if reports.is_empty() {
+        writeln!(out, "no plugin manifest found at {}", path.display())?;
+        return Ok(1);
+    }
+    let mut status = 0;
+    for report in &reports {
+        let outcome = if report.has_errors() { "invalid" } else { "ok" };
+        writeln!(out, "{}: {}", report.path.display(), outcome)?;
+        for diagnostic in &report.diagnostics {
+            writeln!(out, "    {}", diagnostic)?;
+        }
+        if report.has_errors() {
+            status = 1;
+        }
+    }
+    Ok(status)
+}
+
 fn main() {
+    let flags = get_flags();
+
+    if let Some(Some(path)) = flags.get("validate-plugin") {
+        let stdout = io::stdout();
+        let status = validate_plugins(Path::new(path), &mut stdout.lock()).unwrap_or(1);
+        process::exit(status);
+    }
+
     let mut state = XiCore::new();
     let stdin = io::stdin();
     let stdout = io::stdout();
     let mut rpc_looper = RpcLoop::new(stdout);
 
-    let flags = get_flags();
-
     let logfile_config = generate_logfile_config(&flags);
 
     let logging_path_result = generate_logging_path(logfile_config);
@@ -234,3 +264,51 @@ fn main() {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("core-lib/tests/plugin_manifests").join(name)
+    }
+
+    fn validate(path: &Path) -> (i32, String) {
+        let mut out = Vec::new();
+        let status = validate_plugins(path, &mut out).unwrap();
+        (status, String::from_utf8(out).unwrap())
+    }
+
+    // the fixture's executable has no .exe extension
+    #[cfg(unix)]
+    #[test]
+    fn validate_plugin_warnings_succeed() {
+        let (status, out) = validate(&fixture("good"));
+        assert_eq!(status, 0, "{}", out);
+        assert!(out.contains("manifest.toml: ok"));
+        assert!(out.contains("warning: homepage: unknown field `homepage`"));
+
+        let (status, _) = validate(&fixture("good/manifest.toml"));
+        assert_eq!(status, 0);
+    }
+
+    #[test]
+    fn validate_plugin_errors_fail() {
+        let (status, out) = validate(&fixture("bad"));
+        assert_eq!(status, 1);
+        assert!(out.contains("manifest.toml: invalid"));
+        assert!(out.contains("error: version: missing required field"));
+
+        // any invalid manifest fails a directory of plugins
+        let (status, out) = validate(&fixture(""));
+        assert_eq!(status, 1);
+        assert_eq!(out.matches("manifest.toml: ").count(), 2);
+
+        let (status, out) = validate(&fixture("good/bin"));
+        assert_eq!(status, 1);
+        assert!(out.starts_with("no plugin manifest found"));
+
+        let (status, _) = validate(&fixture("missing/manifest.toml"));
+        assert_eq!(status, 1);
+    }
+}
@@ -74,6 +74,8 @@ fn main() -> Result<(), io::Error> {
         activations: vec![PluginActivation::Autorun],
         commands: vec![],
         languages: lang_defs,
+        capabilities: vec![],
+        preload: true,
     };
 
     let toml_str = toml::to_string(&mani).unwrap();
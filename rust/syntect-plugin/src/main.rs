@@ -227,6 +227,7 @@ impl<'a> PluginState {
     }
 
     pub fn indent_lines(&mut self, view: &mut MyView, syntax_set: &SyntaxSet) {
+        let rev = view.rev;
         let mut builder = DeltaBuilder::new(view.get_buf_size());
 
         for indentation_task in self.indentation_state.clone() {
@@ -243,8 +244,10 @@ impl<'a> PluginState {
             };
         }
 
-        if !builder.is_empty() {
-            view.edit(builder.build(), INDENTATION_PRIORITY, false, false, String::from("syntect"));
+        if !builder.is_identity() {
+            let (delta, undo_group) = (builder.build(), view.undo_group);
+            let author = String::from("syntect");
+            view.edit_checked(delta, INDENTATION_PRIORITY, false, undo_group, author, rev);
         }
 
         self.indentation_state.clear();
@@ -514,7 +517,9 @@ impl<'a> PluginState {
             self.bulk_autoindent(view, &mut builder, syntax_set, range).expect("error on reindent");
         }
 
-        view.edit(builder.build(), INDENTATION_PRIORITY, false, false, String::from("syntect"));
+        if !builder.is_identity() {
+            view.edit(builder.build(), INDENTATION_PRIORITY, false, false, String::from("syntect"));
+        }
     }
 
     fn toggle_comment(
@@ -535,7 +540,7 @@ impl<'a> PluginState {
             self.toggle_comment_line_range(view, syntax_set, &mut builder, range);
         }
 
-        if builder.is_empty() {
+        if builder.is_identity() {
             eprintln!("no delta for lines {:?}", &lines);
         } else {
             view.edit(builder.build(), INDENTATION_PRIORITY, false, true, String::from("syntect"));
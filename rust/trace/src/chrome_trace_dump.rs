@@ -90,6 +90,14 @@ where
     serde_json::to_writer(output, samples).map_err(Error::Json)
 }
 
+/// Like `serialize`, but the output is indented to be readable by humans.
+pub fn serialize_pretty<W>(samples: &Vec<Sample>, output: W) -> Result<(), Error>
+where
+    W: Write,
+{
+    serde_json::to_writer_pretty(output, samples).map_err(Error::Json)
+}
+
 pub fn to_value(samples: &Vec<Sample>) -> Result<serde_json::Value, Error> {
     serde_json::to_value(samples).map_err(Error::Json)
 }
@@ -53,19 +53,22 @@ impl<T> FixedLifoDeque<T> {
         FixedLifoDeque { storage: VecDeque::with_capacity(n), limit: n }
     }
 
-    /// This sets a new limit on the container.  Excess elements are dropped in
-    /// FIFO order.  The new capacity is reset to the requested limit which will
-    /// likely result in re-allocation + copies/clones even if the limit
-    /// shrinks.
-    pub fn reset_limit(&mut self, n: usize) {
-        if n < self.limit {
-            let overflow = self.limit - n;
-            self.drop_excess_for_inserting(overflow);
-        }
+    /// This sets a new limit on the container.  Excess elements are evicted in
+    /// FIFO order and returned.  The new capacity is reset to the requested
+    /// limit which will likely result in re-allocation + copies/clones even if
+    /// the limit shrinks.
+    pub fn reset_limit(&mut self, n: usize) -> Vec<T> {
+        let evicted = if self.storage.len() > n {
+            let overflow = self.storage.len() - n;
+            self.storage.drain(..overflow).collect()
+        } else {
+            Vec::new()
+        };
         self.limit = n;
         self.storage.reserve_exact(n);
         self.storage.shrink_to_fit();
         debug_assert!(self.storage.len() <= self.limit);
+        evicted
     }
 
     /// Returns the current limit this ring buffer is configured with.
@@ -185,14 +188,17 @@ impl<T> FixedLifoDeque<T> {
     }
 
     /// Always an O(1) operation.  If the number of elements is at the limit,
-    /// the element at the front is overwritten.
+    /// the element at the front is evicted and returned.
     ///
     /// Post condition: The number of elements is <= limit
-    pub fn push_back(&mut self, value: T) {
-        self.drop_excess_for_inserting(1);
+    pub fn push_back(&mut self, value: T) -> Option<T> {
+        if self.limit == 0 {
+            return Some(value);
+        }
+        let evicted =
+            if self.storage.len() >= self.limit { self.storage.pop_front() } else { None };
         self.storage.push_back(value);
-        // For when limit == 0
-        self.drop_excess_for_inserting(0);
+        evicted
     }
 
     /// Always an O(1) operation.  Memory is never reclaimed.
@@ -378,6 +384,22 @@ mod tests {
         assert_eq!(tester[2], 4);
     }
 
+    #[test]
+    fn test_evictions() {
+        let mut tester = FixedLifoDeque::with_limit(2);
+        assert_eq!(tester.push_back(1), None);
+        assert_eq!(tester.push_back(2), None);
+        assert_eq!(tester.push_back(3), Some(1));
+        assert_eq!(tester.reset_limit(3), Vec::<i32>::new());
+        assert_eq!(tester.push_back(4), None);
+        assert_eq!(tester.reset_limit(1), vec![2, 3]);
+        assert_eq!(tester[0], 4);
+
+        let mut empty = FixedLifoDeque::new();
+        assert_eq!(empty.push_back(5), Some(5));
+        assert!(empty.is_empty());
+    }
+
     #[cfg(feature = "benchmarks")]
     #[bench]
     fn bench_push_back(b: &mut Bencher) {
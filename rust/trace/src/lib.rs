@@ -46,14 +46,16 @@ use crate::fixed_lifo_deque::FixedLifoDeque;
 use std::borrow::Cow;
 use std::cmp;
 use std::collections::HashMap;
+use std::ffi::OsString;
 use std::fmt;
 use std::fs;
 use std::hash::{Hash, Hasher};
+use std::io::{self, Write};
 use std::mem::size_of;
 use std::path::Path;
 use std::string::ToString;
 use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
-use std::sync::Mutex;
+use std::sync::{Mutex, MutexGuard, PoisonError};
 
 pub type StrCow = Cow<'static, str>;
 
@@ -620,28 +622,52 @@ fn exe_name() -> Option<String> {
     }
 }
 
+/// Receives the samples evicted from a full `Trace`.
+pub type SampleSink = Box<dyn Fn(Sample) + Send>;
+
 /// Stores the tracing data.
 pub struct Trace {
     enabled: AtomicBool,
     samples: Mutex<FixedLifoDeque<Sample>>,
+    sink: Option<Mutex<SampleSink>>,
 }
 
 impl Trace {
     pub fn disabled() -> Self {
-        Self { enabled: AtomicBool::new(false), samples: Mutex::new(FixedLifoDeque::new()) }
+        Self {
+            enabled: AtomicBool::new(false),
+            samples: Mutex::new(FixedLifoDeque::new()),
+            sink: None,
+        }
     }
 
     pub fn enabled(config: Config) -> Self {
         Self {
             enabled: AtomicBool::new(true),
             samples: Mutex::new(FixedLifoDeque::with_limit(config.max_samples())),
+            sink: None,
+        }
+    }
+
+    /// Like `enabled`, but instead of dropping the oldest samples when the
+    /// limit is reached, passes them to `sink` in the order they were
+    /// recorded. This keeps memory bounded while letting the embedder stream
+    /// the full trace elsewhere, e.g. to disk.
+    ///
+    /// The sink must not record samples into this trace.
+    pub fn enabled_with_sink(config: Config, sink: SampleSink) -> Self {
+        Self {
+            enabled: AtomicBool::new(true),
+            samples: Mutex::new(FixedLifoDeque::with_limit(config.max_samples())),
+            sink: Some(Mutex::new(sink)),
         }
     }
 
     pub fn disable(&self) {
         let mut all_samples = self.samples.lock().unwrap();
-        all_samples.reset_limit(0);
+        let evicted = all_samples.reset_limit(0);
         self.enabled.store(false, AtomicOrdering::Relaxed);
+        self.forward_evicted(all_samples, evicted);
     }
 
     #[inline]
@@ -651,8 +677,9 @@ impl Trace {
 
     pub fn enable_config(&self, config: Config) {
         let mut all_samples = self.samples.lock().unwrap();
-        all_samples.reset_limit(config.max_samples());
+        let evicted = all_samples.reset_limit(config.max_samples());
         self.enabled.store(true, AtomicOrdering::Relaxed);
+        self.forward_evicted(all_samples, evicted);
     }
 
     /// Generally racy since the underlying storage might be mutated in a separate thread.
@@ -669,7 +696,27 @@ impl Trace {
     #[inline]
     pub(crate) fn record(&self, sample: Sample) {
         let mut all_samples = self.samples.lock().unwrap();
-        all_samples.push_back(sample);
+        if let Some(evicted) = all_samples.push_back(sample) {
+            self.forward_evicted(all_samples, Some(evicted));
+        }
+    }
+
+    /// Passes samples evicted from the storage to the sink, if any.
+    ///
+    /// The sink is locked before the storage is released so that evictions
+    /// from different threads reach it in order, but it is called after the
+    /// storage is released, so a panicking sink cannot poison the storage.
+    fn forward_evicted<I>(&self, all_samples: MutexGuard<FixedLifoDeque<Sample>>, evicted: I)
+    where
+        I: IntoIterator<Item = Sample>,
+    {
+        if let Some(ref sink) = self.sink {
+            let sink = sink.lock().unwrap_or_else(PoisonError::into_inner);
+            drop(all_samples);
+            for sample in evicted {
+                (*sink)(sample);
+            }
+        }
     }
 
     pub fn is_enabled(&self) -> bool {
@@ -816,19 +863,77 @@ impl Trace {
         path: P,
         sort: bool,
     ) -> Result<(), chrome_trace_dump::Error> {
-        let traces = if sort { samples_cloned_sorted() } else { samples_cloned_unsorted() };
+        self.save_with_options(path, SaveOptions { sort, ..SaveOptions::default() })
+    }
+
+    /// Saves the samples to `path`, in the Trace Viewer format.
+    ///
+    /// Unless `options.overwrite` is set, this fails if `path` already
+    /// exists. An existing file is replaced by renaming a temporary file
+    /// over it, so that it always holds a complete trace.
+    pub fn save_with_options<P: AsRef<Path>>(
+        &self,
+        path: P,
+        options: SaveOptions,
+    ) -> Result<(), chrome_trace_dump::Error> {
+        let traces = if options.sort {
+            self.samples_cloned_sorted()
+        } else {
+            self.samples_cloned_unsorted()
+        };
         let path: &Path = path.as_ref();
+        let write = |file: fs::File| -> Result<(), chrome_trace_dump::Error> {
+            let mut writer = io::BufWriter::new(file);
+            if options.pretty {
+                chrome_trace_dump::serialize_pretty(&traces, &mut writer)?;
+            } else {
+                chrome_trace_dump::serialize(&traces, &mut writer)?;
+            }
+            writer.flush()?;
+            Ok(())
+        };
 
-        if path.exists() {
-            return Err(chrome_trace_dump::Error::already_exists());
+        if !options.overwrite {
+            let file = fs::OpenOptions::new().write(true).create_new(true).open(path);
+            return match file {
+                Ok(file) => write(file),
+                Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                    Err(chrome_trace_dump::Error::already_exists())
+                }
+                Err(e) => Err(e.into()),
+            };
         }
 
-        let mut trace_file = fs::File::create(&path)?;
-
-        chrome_trace_dump::serialize(&traces, &mut trace_file)
+        let file_name = path.file_name().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "trace path has no file name")
+        })?;
+        let mut temp_name = OsString::from(".");
+        temp_name.push(file_name);
+        temp_name.push(".tmp");
+        let temp_path = path.with_file_name(temp_name);
+
+        let result = fs::File::create(&temp_path)
+            .map_err(chrome_trace_dump::Error::from)
+            .and_then(write)
+            .and_then(|_| fs::rename(&temp_path, path).map_err(chrome_trace_dump::Error::from));
+        if result.is_err() {
+            let _ = fs::remove_file(&temp_path);
+        }
+        result
     }
 }
 
+/// How `Trace::save_with_options` writes a trace.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SaveOptions {
+    /// Replace the file if it already exists.
+    pub overwrite: bool,
+    /// Indent the output, to make it readable by humans.
+    pub pretty: bool,
+    /// Sort the samples chronologically.
+    pub sort: bool,
+}
+
 lazy_static! {
     static ref TRACE: Trace = Trace::disabled();
 }
@@ -1090,6 +1195,16 @@ pub fn save<P: AsRef<Path>>(path: P, sort: bool) -> Result<(), chrome_trace_dump
     TRACE.save(path, sort)
 }
 
+/// Save tracing data to the supplied path, as described by `options`. See
+/// `Trace::save_with_options`.
+#[inline]
+pub fn save_with_options<P: AsRef<Path>>(
+    path: P,
+    options: SaveOptions,
+) -> Result<(), chrome_trace_dump::Error> {
+    TRACE.save_with_options(path, options)
+}
+
 #[cfg(test)]
 #[rustfmt::skip]
 mod tests {
@@ -1133,6 +1248,90 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_samples_sink() {
+        use std::sync::Arc;
+
+        let evicted = Arc::new(Mutex::new(Vec::new()));
+        let sink_evicted = evicted.clone();
+        let sink: SampleSink = Box::new(move |sample: Sample| {
+            sink_evicted.lock().unwrap().push(sample.name.into_owned());
+        });
+        let trace = Trace::enabled_with_sink(Config::with_limit_count(100), sink);
+        for i in 0..10_000 {
+            trace.instant(i.to_string(), &["test"]);
+        }
+        assert_eq!(trace.get_samples_count(), 100);
+        let evicted = evicted.lock().unwrap();
+        assert_eq!(evicted.len(), 9900);
+        for (i, name) in evicted.iter().enumerate() {
+            assert_eq!(*name, i.to_string());
+        }
+    }
+
+    /// Returns a path in a fresh temporary directory, which is removed along
+    /// with its contents by the returned guard.
+    #[cfg(feature = "chrome_trace_event")]
+    fn temp_trace_path(name: &str) -> (std::path::PathBuf, impl Drop) {
+        struct RemoveDir(std::path::PathBuf);
+        impl Drop for RemoveDir {
+            fn drop(&mut self) {
+                let _ = fs::remove_dir_all(&self.0);
+            }
+        }
+        let dir = std::env::temp_dir().join(format!("xi-trace-{}-{}", std::process::id(), name));
+        fs::create_dir_all(&dir).unwrap();
+        (dir.join("trace.json"), RemoveDir(dir))
+    }
+
+    #[cfg(feature = "chrome_trace_event")]
+    #[test]
+    fn test_save_refuses_existing() {
+        let (path, _guard) = temp_trace_path("refuses");
+        fs::write(&path, "old").unwrap();
+        let trace = Trace::enabled(Config::with_limit_count(10));
+        trace.instant("sample", &["test"]);
+
+        match trace.save_with_options(&path, SaveOptions::default()) {
+            Err(chrome_trace_dump::Error::Io(e)) => assert_eq!(e.kind(), io::ErrorKind::AlreadyExists),
+            other => panic!("expected AlreadyExists, got {:?}", other),
+        }
+        assert!(trace.save(&path, true).is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "old");
+    }
+
+    #[cfg(feature = "chrome_trace_event")]
+    #[test]
+    fn test_save_overwrite() {
+        let (path, _guard) = temp_trace_path("overwrite");
+        fs::write(&path, "old").unwrap();
+        let trace = Trace::enabled(Config::with_limit_count(10));
+        trace.instant("sample", &["test"]);
+
+        let options = SaveOptions { overwrite: true, sort: true, ..SaveOptions::default() };
+        trace.save_with_options(&path, options).unwrap();
+        let saved = chrome_trace_dump::deserialize(fs::File::open(&path).unwrap()).unwrap();
+        assert_eq!(saved, trace.samples_cloned_sorted());
+        // only the trace is left behind
+        assert_eq!(fs::read_dir(path.parent().unwrap()).unwrap().count(), 1);
+    }
+
+    #[cfg(feature = "chrome_trace_event")]
+    #[test]
+    fn test_save_pretty() {
+        let (path, _guard) = temp_trace_path("pretty");
+        let trace = Trace::enabled(Config::with_limit_count(10));
+        trace.instant("sample1", &["test"]);
+        trace.instant("sample2", &["test"]);
+
+        let options = SaveOptions { pretty: true, ..SaveOptions::default() };
+        trace.save_with_options(&path, options).unwrap();
+        let contents = fs::read_to_string(&path).unwrap();
+        assert!(contents.lines().count() > 1);
+        let saved = chrome_trace_dump::deserialize(contents.as_bytes()).unwrap();
+        assert_eq!(saved, trace.samples_cloned_unsorted());
+    }
+
     #[test]
     fn test_disable_drops_all_samples() {
         let trace = Trace::enabled(Config::with_limit_count(10));
@@ -0,0 +1,175 @@
+// Copyright 2020 The xi-editor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Bidi class lookup, and paragraph direction according to the UAX #9
+//! bidirectional algorithm. Reordering is not implemented.
+
+use crate::tables::*;
+
+/// The Unicode Bidi_Class property, numbered as in ICU's UCharDirection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BidiClass {
+    LeftToRight = 0,
+    RightToLeft = 1,
+    EuropeanNumber = 2,
+    EuropeanSeparator = 3,
+    EuropeanTerminator = 4,
+    ArabicNumber = 5,
+    CommonSeparator = 6,
+    ParagraphSeparator = 7,
+    SegmentSeparator = 8,
+    WhiteSpace = 9,
+    OtherNeutral = 10,
+    LeftToRightEmbedding = 11,
+    LeftToRightOverride = 12,
+    ArabicLetter = 13,
+    RightToLeftEmbedding = 14,
+    RightToLeftOverride = 15,
+    PopDirectionalFormat = 16,
+    NonspacingMark = 17,
+    BoundaryNeutral = 18,
+    FirstStrongIsolate = 19,
+    LeftToRightIsolate = 20,
+    RightToLeftIsolate = 21,
+    PopDirectionalIsolate = 22,
+}
+
+use self::BidiClass::*;
+
+/// Indexed by the values in the bidi tables.
+const BIDI_CLASSES: [BidiClass; 23] = [
+    LeftToRight,
+    RightToLeft,
+    EuropeanNumber,
+    EuropeanSeparator,
+    EuropeanTerminator,
+    ArabicNumber,
+    CommonSeparator,
+    ParagraphSeparator,
+    SegmentSeparator,
+    WhiteSpace,
+    OtherNeutral,
+    LeftToRightEmbedding,
+    LeftToRightOverride,
+    ArabicLetter,
+    RightToLeftEmbedding,
+    RightToLeftOverride,
+    PopDirectionalFormat,
+    NonspacingMark,
+    BoundaryNeutral,
+    FirstStrongIsolate,
+    LeftToRightIsolate,
+    RightToLeftIsolate,
+    PopDirectionalIsolate,
+];
+
+/// The direction of a paragraph.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Direction {
+    LeftToRight,
+    RightToLeft,
+    /// The paragraph has no strong directional characters. UAX #9 treats
+    /// this as left-to-right, but an editor may prefer to use the direction
+    /// of the preceding line.
+    Neutral,
+}
+
+/// The Unicode bidi class of the given code point.
+pub fn bidi_class(cp: char) -> BidiClass {
+    let cp = cp as usize;
+    let ix = if cp < 0x800 {
+        BIDI_1_2[cp]
+    } else if cp < 0x10000 {
+        let child = BIDI_3_ROOT[cp >> 6];
+        BIDI_3_CHILD[(child as usize) * 0x40 + (cp & 0x3f)]
+    } else {
+        let mid = BIDI_4_ROOT[cp >> 12];
+        let leaf = BIDI_4_MID[(mid as usize) * 0x40 + ((cp >> 6) & 0x3f)];
+        BIDI_4_LEAVES[(leaf as usize) * 0x40 + (cp & 0x3f)]
+    };
+    BIDI_CLASSES[ix as usize]
+}
+
+/// The base direction of the first paragraph of `s`, determined by its first
+/// strong directional character (rules P2 and P3 of UAX #9).
+///
+/// Characters between an isolate initiator and its matching PDI are skipped,
+/// and the search stops at the first paragraph separator.
+pub fn base_direction(s: &str) -> Direction {
+    let mut isolates = 0usize;
+    for cp in s.chars() {
+        match bidi_class(cp) {
+            ParagraphSeparator => break,
+            FirstStrongIsolate | LeftToRightIsolate | RightToLeftIsolate => isolates += 1,
+            PopDirectionalIsolate => isolates = isolates.saturating_sub(1),
+            LeftToRight if isolates == 0 => return Direction::LeftToRight,
+            RightToLeft | ArabicLetter if isolates == 0 => return Direction::RightToLeft,
+            _ => (),
+        }
+    }
+    Direction::Neutral
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bidi_classes() {
+        assert_eq!(bidi_class('a'), LeftToRight);
+        assert_eq!(bidi_class('1'), EuropeanNumber);
+        assert_eq!(bidi_class(' '), WhiteSpace);
+        assert_eq!(bidi_class('\n'), ParagraphSeparator);
+        assert_eq!(bidi_class('\u{05D0}'), RightToLeft); // HEBREW LETTER ALEF
+        assert_eq!(bidi_class('\u{0627}'), ArabicLetter); // ARABIC LETTER ALEF
+        assert_eq!(bidi_class('\u{0661}'), ArabicNumber); // ARABIC-INDIC DIGIT ONE
+        assert_eq!(bidi_class('\u{0301}'), NonspacingMark);
+        assert_eq!(bidi_class('\u{2067}'), RightToLeftIsolate);
+        assert_eq!(bidi_class('\u{4E00}'), LeftToRight);
+        assert_eq!(bidi_class('\u{10900}'), RightToLeft); // PHOENICIAN LETTER ALF
+        assert_eq!(bidi_class('\u{1F600}'), OtherNeutral);
+        // unassigned code points in right-to-left blocks default to R
+        assert_eq!(bidi_class('\u{05FF}'), RightToLeft);
+    }
+
+    #[test]
+    fn rtl_first_line() {
+        assert_eq!(
+            base_direction("123 \u{05E9}\u{05DC}\u{05D5}\u{05DD} hello"),
+            Direction::RightToLeft
+        );
+        assert_eq!(
+            base_direction("(\u{0645}\u{0631}\u{062D}\u{0628}\u{0627}) world"),
+            Direction::RightToLeft
+        );
+    }
+
+    #[test]
+    fn ltr_first_line() {
+        assert_eq!(
+            base_direction("  hello \u{05E9}\u{05DC}\u{05D5}\u{05DD}"),
+            Direction::LeftToRight
+        );
+        // the contents of isolates are skipped
+        assert_eq!(base_direction("\u{2067}\u{05D0}\u{2069} abc"), Direction::LeftToRight);
+    }
+
+    #[test]
+    fn neutral_line() {
+        assert_eq!(base_direction(""), Direction::Neutral);
+        assert_eq!(base_direction("1, 2: (3) -- 4.5!"), Direction::Neutral);
+        // only the first paragraph counts
+        assert_eq!(base_direction("42\nabc"), Direction::Neutral);
+    }
+}
@@ -0,0 +1,110 @@
+// Copyright 2020 The xi-editor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Unicode case folding, for caseless matching.
+
+use crate::tables::*;
+
+/// An iterator over the case folding of a code point, as returned by
+/// [`case_fold`] and [`full_case_fold`].
+///
+/// [`case_fold`]: fn.case_fold.html
+/// [`full_case_fold`]: fn.full_case_fold.html
+#[derive(Debug, Clone)]
+pub struct CaseFold {
+    chars: [char; 3],
+    ix: usize,
+    len: usize,
+}
+
+/// The simple case folding of the given code point: the common (C) and
+/// simple (S) mappings of CaseFolding.txt. This is always a single code point.
+pub fn case_fold(c: char) -> CaseFold {
+    let folded = match CASE_FOLD_SIMPLE.binary_search_by_key(&c, |&(k, _)| k) {
+        Ok(ix) => CASE_FOLD_SIMPLE[ix].1,
+        Err(_) => c,
+    };
+    CaseFold { chars: [folded, '\0', '\0'], ix: 0, len: 1 }
+}
+
+/// The full case folding of the given code point: the common (C) and full
+/// (F) mappings of CaseFolding.txt. This may expand to up to three code
+/// points, for example `ß` folds to `ss`.
+pub fn full_case_fold(c: char) -> CaseFold {
+    match CASE_FOLD_FULL.binary_search_by_key(&c, |&(k, _)| k) {
+        Ok(ix) => {
+            let chars = CASE_FOLD_FULL[ix].1;
+            let len = chars.iter().take_while(|&&c| c != '\0').count();
+            CaseFold { chars, ix: 0, len }
+        }
+        Err(_) => case_fold(c),
+    }
+}
+
+impl Iterator for CaseFold {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        if self.ix < self.len {
+            self.ix += 1;
+            Some(self.chars[self.ix - 1])
+        } else {
+            None
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.len - self.ix;
+        (remaining, Some(remaining))
+    }
+}
+
+impl ExactSizeIterator for CaseFold {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::String;
+
+    fn fold(s: &str) -> String {
+        s.chars().flat_map(full_case_fold).collect()
+    }
+
+    #[test]
+    fn simple_folding() {
+        assert_eq!(case_fold('A').collect::<String>(), "a");
+        assert_eq!(case_fold('a').collect::<String>(), "a");
+        assert_eq!(case_fold('Σ').collect::<String>(), "σ");
+        assert_eq!(case_fold('ς').collect::<String>(), "σ");
+        // KELVIN SIGN
+        assert_eq!(case_fold('\u{212A}').collect::<String>(), "k");
+        // the simple folding of capital sharp s is the lowercase letter
+        assert_eq!(case_fold('ẞ').collect::<String>(), "ß");
+        // no simple folding for dotted capital I outside Turkic locales
+        assert_eq!(case_fold('İ').collect::<String>(), "İ");
+        assert_eq!(case_fold('1').len(), 1);
+    }
+
+    #[test]
+    fn full_folding() {
+        assert_eq!(fold("ß"), "ss");
+        assert_eq!(fold("ẞ"), "ss");
+        assert_eq!(fold("İ"), "i\u{307}");
+        assert_eq!(fold("ﬃ"), "ffi");
+        assert_eq!(fold("\u{390}"), "\u{3B9}\u{308}\u{301}");
+        assert_eq!(full_case_fold('\u{1F88}').len(), 2);
+        assert_eq!(fold("Straße"), fold("STRASSE"));
+        assert_eq!(fold("ΣΊΣΥΦΟΣ"), fold("σίσυφος"));
+    }
+}
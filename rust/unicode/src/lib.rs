@@ -17,14 +17,25 @@
 
 extern crate alloc;
 
+mod bidi;
+mod case_fold;
 mod emoji;
+mod normalize;
+mod sentence_break;
 mod tables;
+mod word_break;
 
 use core::cmp::Ordering;
 
 use crate::emoji::*;
 use crate::tables::*;
 
+pub use crate::bidi::{base_direction, bidi_class, BidiClass, Direction};
+pub use crate::case_fold::{case_fold, full_case_fold, CaseFold};
+pub use crate::normalize::{normalize_nfc, normalize_nfd};
+pub use crate::sentence_break::{sentence_break_property, SentenceBreakIterator};
+pub use crate::word_break::{is_word_character, word_break_property, WordBreakIterator};
+
 /// The Unicode line breaking property of the given code point.
 ///
 /// This is given as a numeric value which matches the ULineBreak
@@ -170,6 +181,26 @@ impl LineBreakLeafIter {
         LineBreakLeafIter { ix: ix + len, state: lb }
     }
 
+    /// Create an iterator resuming a scan at `ix`, with a state previously
+    /// returned by [`state`](#method.state). To resume at the start of the
+    /// next leaf, pass an `ix` of 0 and the state at the end of the previous one.
+    ///
+    /// This allows break state to be cached per leaf, so that a scan can be
+    /// resumed without rescanning earlier leaves.
+    pub fn from_state(ix: usize, state: u8) -> LineBreakLeafIter {
+        LineBreakLeafIter { ix, state }
+    }
+
+    /// The current state of the line break state machine.
+    ///
+    /// State values are opaque, and only meaningful to
+    /// [`from_state`](#method.from_state). They are stable within a version
+    /// of this crate, but may change between versions, so they should not be
+    /// persisted across upgrades.
+    pub fn state(&self) -> u8 {
+        self.state
+    }
+
     /// Return break pos and whether it's a hard break. Note: hard break
     /// indication may go away, this may not be useful in actual application.
     /// If end of leaf is found, return leaf's len. This does not indicate
@@ -203,33 +234,37 @@ impl LineBreakLeafIter {
     }
 }
 
-fn is_in_asc_list<T: core::cmp::PartialOrd>(c: T, list: &[T], start: usize, end: usize) -> bool {
-    if c == list[start] || c == list[end] {
-        return true;
-    }
-    if end - start <= 1 {
-        return false;
-    }
-
-    let mid = (start + end) / 2;
-
-    if c >= list[mid] {
-        is_in_asc_list(c, list, mid, end)
-    } else {
-        is_in_asc_list(c, list, start, mid)
-    }
+/// Returns `true` if `c` is an element of `list`, which must be sorted in
+/// ascending order.
+fn is_in_asc_list<T: Ord>(c: T, list: &[T]) -> bool {
+    list.binary_search(&c).is_ok()
 }
 
 pub fn is_variation_selector(c: char) -> bool {
     ('\u{FE00}'..='\u{FE0F}').contains(&c) || ('\u{E0100}'..='\u{E01EF}').contains(&c)
 }
 
+/// Whether `c`, followed by `next`, is displayed as an emoji. This is the
+/// case for emoji with emoji presentation by default, unless followed by the
+/// text variation selector (U+FE0E), and for other emoji when followed by
+/// the emoji variation selector (U+FE0F).
+pub fn has_emoji_presentation(c: char, next: Option<char>) -> bool {
+    match next {
+        Some('\u{FE0E}') => false,
+        Some('\u{FE0F}') => c.is_emoji(),
+        _ => c.is_emoji_presentation(),
+    }
+}
+
 #[allow(clippy::wrong_self_convention)] // clippy wants &self for all of these
 pub trait EmojiExt {
     fn is_regional_indicator_symbol(self) -> bool;
     fn is_emoji_modifier(self) -> bool;
     fn is_emoji_combining_enclosing_keycap(self) -> bool;
     fn is_emoji(self) -> bool;
+    /// Whether this is displayed as an emoji by default (the
+    /// Emoji_Presentation property), rather than as text.
+    fn is_emoji_presentation(self) -> bool;
     fn is_emoji_modifier_base(self) -> bool;
     fn is_tag_spec_char(self) -> bool;
     fn is_emoji_cancel_tag(self) -> bool;
@@ -247,10 +282,13 @@ impl EmojiExt for char {
         self == '\u{20E3}'
     }
     fn is_emoji(self) -> bool {
-        is_in_asc_list(self, &EMOJI_TABLE, 0, EMOJI_TABLE.len() - 1)
+        is_in_asc_list(self, &EMOJI_TABLE)
+    }
+    fn is_emoji_presentation(self) -> bool {
+        is_in_asc_list(self, &EMOJI_PRESENTATION_TABLE)
     }
     fn is_emoji_modifier_base(self) -> bool {
-        is_in_asc_list(self, &EMOJI_MODIFIER_BASE_TABLE, 0, EMOJI_MODIFIER_BASE_TABLE.len() - 1)
+        is_in_asc_list(self, &EMOJI_MODIFIER_BASE_TABLE)
     }
     fn is_tag_spec_char(self) -> bool {
         ('\u{E0020}'..='\u{E007E}').contains(&self)
@@ -269,9 +307,14 @@ pub fn is_keycap_base(c: char) -> bool {
 
 #[cfg(test)]
 mod tests {
+    use crate::emoji::*;
+    use crate::has_emoji_presentation;
+    use crate::is_in_asc_list;
     use crate::linebreak_property;
     use crate::linebreak_property_str;
+    use crate::EmojiExt;
     use crate::LineBreakIterator;
+    use crate::LineBreakLeafIter;
     use alloc::vec;
     use alloc::vec::*;
 
@@ -621,4 +664,96 @@ mod tests {
 
         assert_eq!(vec![(5, true)], LineBreakIterator::new("abc\u{0085}").collect::<Vec<_>>());
     }
+
+    // Breaks within `leaves`, as offsets in the concatenated text. The state
+    // at the end of each leaf is carried to the next with `from_state`.
+    fn leaf_breaks(leaves: &[&str]) -> Vec<(usize, bool)> {
+        let mut result = Vec::new();
+        let mut offset = 0;
+        let mut state = None;
+        for leaf in leaves {
+            let mut iter = match state {
+                Some(state) => LineBreakLeafIter::from_state(0, state),
+                None => LineBreakLeafIter::new(leaf, 0),
+            };
+            loop {
+                let (pos, hard) = iter.next(leaf);
+                if pos == leaf.len() {
+                    break;
+                }
+                result.push((offset + pos, hard));
+            }
+            state = Some(iter.state());
+            offset += leaf.len();
+        }
+        result
+    }
+
+    #[test]
+    fn lb_leaf_iter_resume() {
+        let texts = [
+            "hello world",
+            "Hello, 世界! foo\r\nbar\n\nbaz",
+            "a-b c\u{200b}d (e) \"f\" 1,000.5%",
+            "e\u{301} \u{1F1FA}\u{1F1F8}\u{1F1EB}\u{1F1F7} \u{1F469}\u{200D}\u{1F467} x",
+            "\u{0E01}\u{0E32}\u{0E23} \u{AC00}\u{AC01}\t\u{00A0}end",
+        ];
+        for text in &texts {
+            let single = leaf_breaks(&[text]);
+            let mut expected = LineBreakIterator::new(text).collect::<Vec<_>>();
+            expected.pop();
+            assert_eq!(single, expected);
+
+            for (split, _) in text.char_indices().skip(1) {
+                let split_breaks = leaf_breaks(&[&text[..split], &text[split..]]);
+                assert_eq!(single, split_breaks, "{:?} split at {}", text, split);
+            }
+        }
+    }
+
+    #[test]
+    fn asc_list_endpoints() {
+        let list = [2, 3, 5, 7, 11];
+        assert!(is_in_asc_list(2, &list));
+        assert!(is_in_asc_list(11, &list));
+        assert!(is_in_asc_list(5, &list));
+        assert!(!is_in_asc_list(1, &list));
+        assert!(!is_in_asc_list(4, &list));
+        assert!(!is_in_asc_list(12, &list));
+        assert!(is_in_asc_list(2, &list[..1]));
+        assert!(!is_in_asc_list(2, &list[..0]));
+
+        assert!(is_in_asc_list(EMOJI_TABLE[0], &EMOJI_TABLE));
+        assert!(is_in_asc_list(EMOJI_TABLE[EMOJI_TABLE.len() - 1], &EMOJI_TABLE));
+        assert!(is_in_asc_list('\u{A9}', &EMOJI_TABLE));
+        assert!(!is_in_asc_list('a', &EMOJI_TABLE));
+        let last_base = EMOJI_MODIFIER_BASE_TABLE[EMOJI_MODIFIER_BASE_TABLE.len() - 1];
+        assert!(is_in_asc_list(EMOJI_MODIFIER_BASE_TABLE[0], &EMOJI_MODIFIER_BASE_TABLE));
+        assert!(is_in_asc_list(last_base, &EMOJI_MODIFIER_BASE_TABLE));
+        assert!(!is_in_asc_list('\u{1F600}', &EMOJI_MODIFIER_BASE_TABLE));
+        for &c in EMOJI_PRESENTATION_TABLE.iter() {
+            assert!(is_in_asc_list(c, &EMOJI_TABLE), "{:?}", c);
+        }
+    }
+
+    #[test]
+    fn emoji_presentation() {
+        // WATCH and GRINNING FACE default to emoji presentation
+        assert!('\u{231A}'.is_emoji_presentation());
+        assert!('\u{1F600}'.is_emoji_presentation());
+        assert!(has_emoji_presentation('\u{1F600}', None));
+        assert!(!has_emoji_presentation('\u{231A}', Some('\u{FE0E}')));
+
+        // HEAVY BLACK HEART is an emoji, but displayed as text unless
+        // followed by VS16
+        assert!('\u{2764}'.is_emoji());
+        assert!(!'\u{2764}'.is_emoji_presentation());
+        assert!(!has_emoji_presentation('\u{2764}', None));
+        assert!(!has_emoji_presentation('\u{2764}', Some('a')));
+        assert!(has_emoji_presentation('\u{2764}', Some('\u{FE0F}')));
+
+        assert!(!'#'.is_emoji_presentation());
+        assert!(!'a'.is_emoji_presentation());
+        assert!(!has_emoji_presentation('a', Some('\u{FE0F}')));
+    }
 }
@@ -0,0 +1,243 @@
+// Copyright 2020 The xi-editor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Unicode normalization to the canonical forms NFD and NFC, as described in
+//! UAX #15. The compatibility forms NFKD and NFKC are not supported.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::char;
+
+use crate::tables::*;
+
+// Hangul syllables are composed and decomposed algorithmically, as described
+// in section 3.12 of the Unicode standard.
+const S_BASE: u32 = 0xAC00;
+const L_BASE: u32 = 0x1100;
+const V_BASE: u32 = 0x1161;
+const T_BASE: u32 = 0x11A7;
+const L_COUNT: u32 = 19;
+const V_COUNT: u32 = 21;
+const T_COUNT: u32 = 28;
+const N_COUNT: u32 = V_COUNT * T_COUNT;
+const S_COUNT: u32 = L_COUNT * N_COUNT;
+
+/// The canonical combining class of the given code point; 0 for starters.
+fn canonical_combining_class(cp: char) -> u8 {
+    let cp = cp as usize;
+    if cp < 0x800 {
+        CCC_1_2[cp]
+    } else if cp < 0x10000 {
+        let child = CCC_3_ROOT[cp >> 6];
+        CCC_3_CHILD[(child as usize) * 0x40 + (cp & 0x3f)]
+    } else {
+        let mid = CCC_4_ROOT[cp >> 12];
+        let leaf = CCC_4_MID[(mid as usize) * 0x40 + ((cp >> 6) & 0x3f)];
+        CCC_4_LEAVES[(leaf as usize) * 0x40 + (cp & 0x3f)]
+    }
+}
+
+fn hangul(cp: u32) -> char {
+    char::from_u32(cp).unwrap()
+}
+
+/// Appends the full canonical decomposition of `c` to `out`.
+fn decompose(c: char, out: &mut Vec<char>) {
+    let s = (c as u32).wrapping_sub(S_BASE);
+    if s < S_COUNT {
+        out.push(hangul(L_BASE + s / N_COUNT));
+        out.push(hangul(V_BASE + (s % N_COUNT) / T_COUNT));
+        if s % T_COUNT != 0 {
+            out.push(hangul(T_BASE + s % T_COUNT));
+        }
+        return;
+    }
+    match CANONICAL_DECOMPOSITION.binary_search_by_key(&c, |&(k, _)| k) {
+        Ok(ix) => {
+            let pair = CANONICAL_DECOMPOSITION[ix].1;
+            decompose(pair[0], out);
+            if pair[1] != '\0' {
+                decompose(pair[1], out);
+            }
+        }
+        Err(_) => out.push(c),
+    }
+}
+
+/// The primary composite of the pair, if there is one.
+fn compose(first: char, second: char) -> Option<char> {
+    let l = (first as u32).wrapping_sub(L_BASE);
+    let v = (second as u32).wrapping_sub(V_BASE);
+    if l < L_COUNT && v < V_COUNT {
+        return Some(hangul(S_BASE + (l * V_COUNT + v) * T_COUNT));
+    }
+    let s = (first as u32).wrapping_sub(S_BASE);
+    let t = (second as u32).wrapping_sub(T_BASE);
+    if s < S_COUNT && s % T_COUNT == 0 && t > 0 && t < T_COUNT {
+        return Some(hangul(first as u32 + t));
+    }
+    CANONICAL_COMPOSITION
+        .binary_search_by_key(&(first, second), |&(k, _)| k)
+        .ok()
+        .map(|ix| CANONICAL_COMPOSITION[ix].1)
+}
+
+/// The canonical decomposition of `s`, in canonical order: each run of
+/// non-starters is stably sorted by combining class.
+fn decomposed(s: &str) -> Vec<char> {
+    let mut chars = Vec::with_capacity(s.len());
+    for c in s.chars() {
+        decompose(c, &mut chars);
+    }
+    for i in 1..chars.len() {
+        let class = canonical_combining_class(chars[i]);
+        let mut j = i;
+        while class != 0 && j > 0 && canonical_combining_class(chars[j - 1]) > class {
+            chars.swap(j - 1, j);
+            j -= 1;
+        }
+    }
+    chars
+}
+
+/// Normalization Form D: the canonical decomposition of `s`.
+pub fn normalize_nfd(s: &str) -> String {
+    if s.is_ascii() {
+        return s.into();
+    }
+    decomposed(s).into_iter().collect()
+}
+
+/// Normalization Form C: the canonical decomposition of `s`, followed by
+/// canonical composition.
+pub fn normalize_nfc(s: &str) -> String {
+    if s.is_ascii() {
+        return s.into();
+    }
+    let mut chars = decomposed(s);
+    // composed characters are written back over `chars`, up to `len`
+    let mut len = 0;
+    let mut starter = None;
+    // the class of the last character kept after the starter
+    let mut last_class = None;
+    for ix in 0..chars.len() {
+        let c = chars[ix];
+        let class = canonical_combining_class(c);
+        if let Some(starter) = starter {
+            let blocked = last_class.map_or(false, |last| last == 0 || last >= class);
+            if !blocked {
+                if let Some(composed) = compose(chars[starter], c) {
+                    chars[starter] = composed;
+                    continue;
+                }
+            }
+        }
+        if class == 0 {
+            starter = Some(len);
+            last_class = None;
+        } else {
+            last_class = Some(class);
+        }
+        chars[len] = c;
+        len += 1;
+    }
+    chars.truncate(len);
+    chars.into_iter().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Pairs of composed and decomposed strings, each in NFC and NFD.
+    const PAIRS: &[(&str, &str)] = &[
+        ("", ""),
+        ("plain ascii", "plain ascii"),
+        ("\u{E9}", "e\u{301}"),
+        ("caf\u{E9} cr\u{E8}me", "cafe\u{301} cre\u{300}me"),
+        ("\u{1EC7}", "e\u{323}\u{302}"),
+        ("\u{1F82}", "\u{3B1}\u{313}\u{300}\u{345}"),
+        ("\u{1E0D}\u{307}", "d\u{323}\u{307}"),
+        // Hangul syllables, with and without a trailing consonant
+        ("\u{AC00}", "\u{1100}\u{1161}"),
+        ("\u{D55C}\u{AE00}", "\u{1112}\u{1161}\u{11AB}\u{1100}\u{1173}\u{11AF}"),
+        // a composition exclusion is not recomposed
+        ("\u{915}\u{93C}", "\u{915}\u{93C}"),
+        ("\u{1D158}\u{1D165}", "\u{1D158}\u{1D165}"),
+    ];
+
+    #[test]
+    fn known_pairs() {
+        for &(composed, decomposed) in PAIRS {
+            assert_eq!(normalize_nfd(composed), decomposed);
+            assert_eq!(normalize_nfd(decomposed), decomposed);
+            assert_eq!(normalize_nfc(decomposed), composed);
+            assert_eq!(normalize_nfc(composed), composed);
+        }
+    }
+
+    #[test]
+    fn canonical_ordering() {
+        assert_eq!(canonical_combining_class('a'), 0);
+        assert_eq!(canonical_combining_class('\u{301}'), 230);
+        assert_eq!(canonical_combining_class('\u{323}'), 220);
+        assert_eq!(canonical_combining_class('\u{1D165}'), 216);
+        assert_eq!(normalize_nfd("e\u{302}\u{323}"), "e\u{323}\u{302}");
+        assert_eq!(normalize_nfc("e\u{302}\u{323}"), "\u{1EC7}");
+        // marks of the same class keep their order
+        assert_eq!(normalize_nfd("a\u{301}\u{300}"), "a\u{301}\u{300}");
+        assert_eq!(normalize_nfc("a\u{301}\u{300}"), "\u{E1}\u{300}");
+    }
+
+    #[test]
+    fn singletons() {
+        // ANGSTROM SIGN and OHM SIGN decompose to other characters
+        assert_eq!(normalize_nfd("\u{212B}"), "A\u{30A}");
+        assert_eq!(normalize_nfc("\u{212B}"), "\u{C5}");
+        assert_eq!(normalize_nfc("\u{2126}"), "\u{3A9}");
+        // as do composition exclusions
+        assert_eq!(normalize_nfc("\u{958}"), "\u{915}\u{93C}");
+        assert_eq!(normalize_nfc("\u{1D15F}"), "\u{1D158}\u{1D165}");
+        // compatibility decompositions are left alone
+        assert_eq!(normalize_nfd("\u{FB01}"), "\u{FB01}");
+        assert_eq!(normalize_nfc("\u{FB01}"), "\u{FB01}");
+    }
+
+    #[test]
+    fn hangul_composition() {
+        // an LV syllable followed by a trailing consonant
+        assert_eq!(normalize_nfc("\u{AC00}\u{11A8}"), "\u{AC01}");
+        assert_eq!(normalize_nfd("\u{AC01}"), "\u{1100}\u{1161}\u{11A8}");
+        // U+11A7 is not a trailing consonant
+        assert_eq!(normalize_nfc("\u{1100}\u{1161}\u{11A7}"), "\u{AC00}\u{11A7}");
+        assert_eq!(normalize_nfc("\u{1100}\u{1100}\u{1161}"), "\u{1100}\u{AC00}");
+        // an LVT syllable takes no further trailing consonant
+        assert_eq!(normalize_nfc("\u{AC01}\u{11A8}"), "\u{AC01}\u{11A8}");
+        // the first and last syllables
+        assert_eq!(normalize_nfd("\u{D7A3}"), "\u{1112}\u{1175}\u{11C2}");
+        assert_eq!(normalize_nfc("\u{1112}\u{1175}\u{11C2}"), "\u{D7A3}");
+    }
+
+    #[test]
+    fn blocked_composition() {
+        // successive marks compose in turn
+        assert_eq!(normalize_nfc("a\u{30A}\u{301}"), "\u{1FB}");
+        // a mark of the same class between them blocks composition
+        assert_eq!(normalize_nfc("a\u{35B}\u{30A}"), "a\u{35B}\u{30A}");
+        // but one of a lower class does not
+        assert_eq!(normalize_nfc("a\u{316}\u{30A}"), "\u{E5}\u{316}");
+        // leading non-starters are kept
+        assert_eq!(normalize_nfc("\u{301}e"), "\u{301}e");
+    }
+}
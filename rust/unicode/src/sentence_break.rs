@@ -0,0 +1,318 @@
+// Copyright 2020 The xi-editor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Sentence segmentation according to the UAX #29 sentence boundary rules.
+
+use crate::tables::*;
+
+// Values of the Sentence_Break property, numbered as in ICU's USentenceBreak.
+#[cfg(test)]
+const OTHER: u8 = 0;
+const ATERM: u8 = 1;
+const CLOSE: u8 = 2;
+const FORMAT: u8 = 3;
+const LOWER: u8 = 4;
+const NUMERIC: u8 = 5;
+const OLETTER: u8 = 6;
+const SEP: u8 = 7;
+const SP: u8 = 8;
+const STERM: u8 = 9;
+const UPPER: u8 = 10;
+const CR: u8 = 11;
+const EXTEND: u8 = 12;
+const LF: u8 = 13;
+const SCONTINUE: u8 = 14;
+
+/// The Unicode sentence break property of the given code point.
+///
+/// This is given as a numeric value which matches the USentenceBreak
+/// enum value from ICU.
+pub fn sentence_break_property(cp: char) -> u8 {
+    let cp = cp as usize;
+    if cp < 0x800 {
+        SENTENCEBREAK_1_2[cp]
+    } else if cp < 0x10000 {
+        let child = SENTENCEBREAK_3_ROOT[cp >> 6];
+        SENTENCEBREAK_3_CHILD[(child as usize) * 0x40 + (cp & 0x3f)]
+    } else {
+        let mid = SENTENCEBREAK_4_ROOT[cp >> 12];
+        let leaf = SENTENCEBREAK_4_MID[(mid as usize) * 0x40 + ((cp >> 6) & 0x3f)];
+        SENTENCEBREAK_4_LEAVES[(leaf as usize) * 0x40 + (cp & 0x3f)]
+    }
+}
+
+fn is_parasep(prop: u8) -> bool {
+    prop == SEP || prop == CR || prop == LF
+}
+
+fn is_saterm(prop: u8) -> bool {
+    prop == STERM || prop == ATERM
+}
+
+/// Characters which are ignored (attached to the preceding character) by SB5.
+fn is_ignorable(prop: u8) -> bool {
+    prop == EXTEND || prop == FORMAT
+}
+
+/// An iterator which produces sentence boundaries according to the UAX #29
+/// sentence boundary rules. Each item is the byte offset of a boundary within
+/// the source string.
+///
+/// As with [`WordBreakIterator`](crate::WordBreakIterator), there is never a
+/// boundary reported at the beginning of the string, and for non-empty
+/// strings there is always one at the end. A boundary follows the spaces
+/// after a sentence's terminal punctuation, and any paragraph separator.
+///
+/// The rules make no attempt to recognize abbreviations: a period followed
+/// by a lowercase word, a number, or an uppercase letter directly after it
+/// (as in "U.S.") does not end a sentence, but "Mr. Smith" is broken after
+/// "Mr. ".
+#[derive(Copy, Clone)]
+pub struct SentenceBreakIterator<'a> {
+    s: &'a str,
+    ix: usize,
+}
+
+impl<'a> SentenceBreakIterator<'a> {
+    /// Create a new iterator for the given string slice.
+    pub fn new(s: &str) -> SentenceBreakIterator<'_> {
+        SentenceBreakIterator { s, ix: 0 }
+    }
+
+    /// The property of the code point immediately before `ix`, and its offset.
+    fn raw_prev(&self, ix: usize) -> Option<(u8, usize)> {
+        self.s[..ix].chars().next_back().map(|c| (sentence_break_property(c), ix - c.len_utf8()))
+    }
+
+    /// The property of the code point starting at `ix`, and the offset following it.
+    fn raw_next(&self, ix: usize) -> Option<(u8, usize)> {
+        self.s[ix..].chars().next().map(|c| (sentence_break_property(c), ix + c.len_utf8()))
+    }
+
+    /// The property of the character before `ix`, skipping any characters
+    /// ignored by SB5, and the offset at which that character starts.
+    fn prev(&self, ix: usize) -> Option<(u8, usize)> {
+        let (mut prop, mut pos) = self.raw_prev(ix)?;
+        while is_ignorable(prop) {
+            match self.raw_prev(pos) {
+                Some((p, prev_pos)) if !is_parasep(p) => {
+                    prop = p;
+                    pos = prev_pos;
+                }
+                _ => break,
+            }
+        }
+        Some((prop, pos))
+    }
+
+    /// The property of the character starting at `ix`, skipping any
+    /// characters ignored by SB5 that follow it, and the offset after them.
+    fn next_char(&self, ix: usize) -> Option<(u8, usize)> {
+        let (prop, mut pos) = self.raw_next(ix)?;
+        while let Some((p, next_pos)) = self.raw_next(pos) {
+            if !is_ignorable(p) {
+                break;
+            }
+            pos = next_pos;
+        }
+        Some((prop, pos))
+    }
+
+    /// Matches `SATerm Close* Sp*` ending at `ix`. Returns the terminator's
+    /// property and whether any spaces follow it.
+    fn terminator_before(&self, ix: usize) -> Option<(u8, bool)> {
+        let (mut prop, mut pos) = self.prev(ix)?;
+        let mut has_sp = false;
+        while prop == SP {
+            has_sp = true;
+            let (p, prev_pos) = self.prev(pos)?;
+            prop = p;
+            pos = prev_pos;
+        }
+        while prop == CLOSE {
+            let (p, prev_pos) = self.prev(pos)?;
+            prop = p;
+            pos = prev_pos;
+        }
+        if is_saterm(prop) {
+            Some((prop, has_sp))
+        } else {
+            None
+        }
+    }
+
+    /// Returns `true` if the first of the characters at or after `ix` that is
+    /// not `OLetter | Upper | Lower | ParaSep | SATerm` is `Lower` (SB8).
+    fn lower_follows(&self, ix: usize) -> bool {
+        let mut pos = ix;
+        while let Some((prop, next_pos)) = self.next_char(pos) {
+            match prop {
+                LOWER => return true,
+                OLETTER | UPPER | SEP | CR | LF | ATERM | STERM => return false,
+                _ => pos = next_pos,
+            }
+        }
+        false
+    }
+
+    /// Returns `true` if there is a sentence boundary at `ix`, which must be
+    /// a code point boundary strictly inside the string.
+    fn is_boundary(&self, ix: usize) -> bool {
+        let (raw_left, _) = self.raw_prev(ix).unwrap();
+        let (right, _) = self.raw_next(ix).unwrap();
+
+        // SB3
+        if raw_left == CR && right == LF {
+            return false;
+        }
+        // SB4
+        if is_parasep(raw_left) {
+            return true;
+        }
+        // SB5
+        if is_ignorable(right) {
+            return false;
+        }
+
+        let (left, left_pos) = self.prev(ix).unwrap();
+        // SB6
+        if left == ATERM && right == NUMERIC {
+            return false;
+        }
+        // SB7
+        if left == ATERM && right == UPPER {
+            if let Some((UPPER, _)) | Some((LOWER, _)) = self.prev(left_pos) {
+                return false;
+            }
+        }
+
+        let (term, has_sp) = match self.terminator_before(ix) {
+            Some(found) => found,
+            // SB998
+            None => return false,
+        };
+        // SB8
+        if term == ATERM && self.lower_follows(ix) {
+            return false;
+        }
+        // SB8a
+        if right == SCONTINUE || is_saterm(right) {
+            return false;
+        }
+        // SB9
+        if !has_sp && right == CLOSE {
+            return false;
+        }
+        // SB9, SB10
+        if right == SP || is_parasep(right) {
+            return false;
+        }
+        // SB11
+        true
+    }
+}
+
+impl<'a> Iterator for SentenceBreakIterator<'a> {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        if self.ix >= self.s.len() {
+            return None;
+        }
+        let mut ix = self.ix;
+        loop {
+            ix += self.s[ix..].chars().next().unwrap().len_utf8();
+            if ix == self.s.len() || self.is_boundary(ix) {
+                self.ix = ix;
+                return Some(ix);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+    use alloc::vec::*;
+
+    #[test]
+    fn sentence_break_prop() {
+        assert_eq!(ATERM, sentence_break_property('.'));
+        assert_eq!(STERM, sentence_break_property('?'));
+        assert_eq!(STERM, sentence_break_property('!'));
+        assert_eq!(CLOSE, sentence_break_property('"'));
+        assert_eq!(CLOSE, sentence_break_property(')'));
+        assert_eq!(SCONTINUE, sentence_break_property(','));
+        assert_eq!(SP, sentence_break_property(' '));
+        assert_eq!(LF, sentence_break_property('\n'));
+        assert_eq!(SEP, sentence_break_property('\u{2029}'));
+        assert_eq!(UPPER, sentence_break_property('A'));
+        assert_eq!(LOWER, sentence_break_property('a'));
+        assert_eq!(NUMERIC, sentence_break_property('7'));
+        assert_eq!(OLETTER, sentence_break_property('\u{4E00}'));
+        assert_eq!(EXTEND, sentence_break_property('\u{301}'));
+        assert_eq!(OTHER, sentence_break_property('#'));
+    }
+
+    fn sentences(s: &str) -> Vec<&str> {
+        let mut start = 0;
+        SentenceBreakIterator::new(s)
+            .map(|end| {
+                let sentence = &s[start..end];
+                start = end;
+                sentence
+            })
+            .collect()
+    }
+
+    #[test]
+    fn sb_iter_simple() {
+        assert_eq!(Vec::<usize>::new(), SentenceBreakIterator::new("").collect::<Vec<_>>());
+        assert_eq!(vec!["Hello."], sentences("Hello."));
+        assert_eq!(vec!["One. ", "Two? ", "Three!"], sentences("One. Two? Three!"));
+        assert_eq!(vec!["no terminator"], sentences("no terminator"));
+        // SB3, SB4
+        assert_eq!(vec!["a\r\n", "b\n", "\n", "c"], sentences("a\r\nb\n\nc"));
+        assert_eq!(vec!["Done.\n", "Next"], sentences("Done.\nNext"));
+    }
+
+    #[test]
+    fn sb_iter_abbreviations() {
+        // SB8: a period followed by a lowercase word is not a terminator
+        assert_eq!(vec!["See e.g. the docs. ", "Then"], sentences("See e.g. the docs. Then"));
+        // SB7, SB6
+        assert_eq!(vec!["The U.S. ", "Army."], sentences("The U.S. Army."));
+        assert_eq!(vec!["Pi is 3.14."], sentences("Pi is 3.14."));
+        // the rules can't tell an abbreviation from the end of a sentence
+        assert_eq!(vec!["Mr. ", "Smith went home."], sentences("Mr. Smith went home."));
+        assert_eq!(vec!["Ask Mr. smith."], sentences("Ask Mr. smith."));
+        // SB8a
+        assert_eq!(vec!["\"Go!\", Bob said."], sentences("\"Go!\", Bob said."));
+    }
+
+    #[test]
+    fn sb_iter_quotes() {
+        // SB9, SB10: closing quotes and spaces stay with the sentence
+        assert_eq!(
+            vec!["He said, \"Are you going?\" ", "John shook his head."],
+            sentences("He said, \"Are you going?\" John shook his head.")
+        );
+        assert_eq!(vec!["(Done.) ", "Next."], sentences("(Done.) Next."));
+        // SB8 looks past closing punctuation and spaces
+        assert_eq!(vec!["\"Wait.\" he said."], sentences("\"Wait.\" he said."));
+        // SB5: combining marks attach to the preceding character
+        assert_eq!(vec!["Caf\u{e9}.\u{301} ", "Ok"], sentences("Caf\u{e9}.\u{301} Ok"));
+    }
+}
@@ -0,0 +1,326 @@
+// Copyright 2020 The xi-editor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Word segmentation according to the UAX #29 word boundary rules.
+
+use crate::tables::*;
+
+// Values of the Word_Break property, numbered as in ICU's UWordBreakValues.
+#[cfg(test)]
+const OTHER: u8 = 0;
+const ALETTER: u8 = 1;
+const FORMAT: u8 = 2;
+const KATAKANA: u8 = 3;
+const MIDLETTER: u8 = 4;
+const MIDNUM: u8 = 5;
+const NUMERIC: u8 = 6;
+const EXTENDNUMLET: u8 = 7;
+const CR: u8 = 8;
+const EXTEND: u8 = 9;
+const LF: u8 = 10;
+const MIDNUMLET: u8 = 11;
+const NEWLINE: u8 = 12;
+const REGIONAL_INDICATOR: u8 = 13;
+const HEBREW_LETTER: u8 = 14;
+const SINGLE_QUOTE: u8 = 15;
+const DOUBLE_QUOTE: u8 = 16;
+const ZWJ: u8 = 21;
+const WSEGSPACE: u8 = 22;
+
+/// The Unicode word break property of the given code point.
+///
+/// This is given as a numeric value which matches the UWordBreakValues
+/// enum value from ICU.
+pub fn word_break_property(cp: char) -> u8 {
+    let cp = cp as usize;
+    if cp < 0x800 {
+        WORDBREAK_1_2[cp]
+    } else if cp < 0x10000 {
+        let child = WORDBREAK_3_ROOT[cp >> 6];
+        WORDBREAK_3_CHILD[(child as usize) * 0x40 + (cp & 0x3f)]
+    } else {
+        let mid = WORDBREAK_4_ROOT[cp >> 12];
+        let leaf = WORDBREAK_4_MID[(mid as usize) * 0x40 + ((cp >> 6) & 0x3f)];
+        WORDBREAK_4_LEAVES[(leaf as usize) * 0x40 + (cp & 0x3f)]
+    }
+}
+
+/// Returns `true` if the code point can be part of a word: letters, digits,
+/// katakana, and connector punctuation such as `_`.
+pub fn is_word_character(cp: char) -> bool {
+    matches!(word_break_property(cp), ALETTER | HEBREW_LETTER | NUMERIC | KATAKANA | EXTENDNUMLET)
+}
+
+fn is_ahletter(prop: u8) -> bool {
+    prop == ALETTER || prop == HEBREW_LETTER
+}
+
+fn is_midnumletq(prop: u8) -> bool {
+    prop == MIDNUMLET || prop == SINGLE_QUOTE
+}
+
+fn is_newline(prop: u8) -> bool {
+    prop == NEWLINE || prop == CR || prop == LF
+}
+
+/// Characters which are ignored (attached to the preceding character) by WB4.
+fn is_ignorable(prop: u8) -> bool {
+    prop == EXTEND || prop == FORMAT || prop == ZWJ
+}
+
+/// An iterator which produces word boundaries according to the UAX #29
+/// word boundary rules. Each item is the byte offset of a boundary within
+/// the source string.
+///
+/// As with [`LineBreakIterator`](crate::LineBreakIterator), there is never a
+/// boundary reported at the beginning of the string, and for non-empty
+/// strings there is always one at the end. Runs of characters between
+/// boundaries are either words or the spaces and punctuation between them;
+/// use [`is_word_character`] to tell them apart.
+///
+/// WB3c (emoji ZWJ sequences) is not implemented, as it requires the
+/// Extended_Pictographic property.
+#[derive(Copy, Clone)]
+pub struct WordBreakIterator<'a> {
+    s: &'a str,
+    ix: usize,
+}
+
+impl<'a> WordBreakIterator<'a> {
+    /// Create a new iterator for the given string slice.
+    pub fn new(s: &str) -> WordBreakIterator<'_> {
+        WordBreakIterator { s, ix: 0 }
+    }
+
+    /// The property of the code point immediately before `ix`, and its offset.
+    fn raw_prev(&self, ix: usize) -> Option<(u8, usize)> {
+        self.s[..ix].chars().next_back().map(|c| (word_break_property(c), ix - c.len_utf8()))
+    }
+
+    /// The property of the code point starting at `ix`, and the offset following it.
+    fn raw_next(&self, ix: usize) -> Option<(u8, usize)> {
+        self.s[ix..].chars().next().map(|c| (word_break_property(c), ix + c.len_utf8()))
+    }
+
+    /// The property of the character before `ix`, skipping any characters
+    /// ignored by WB4, and the offset at which that character starts.
+    fn prev(&self, ix: usize) -> Option<(u8, usize)> {
+        let (mut prop, mut pos) = self.raw_prev(ix)?;
+        while is_ignorable(prop) {
+            match self.raw_prev(pos) {
+                Some((p, prev_pos)) if !is_newline(p) => {
+                    prop = p;
+                    pos = prev_pos;
+                }
+                _ => break,
+            }
+        }
+        Some((prop, pos))
+    }
+
+    /// The property of the first character at or after `ix` not ignored by WB4.
+    fn next_skipping_ignorable(&self, ix: usize) -> Option<u8> {
+        let mut pos = ix;
+        loop {
+            let (prop, next) = self.raw_next(pos)?;
+            if !is_ignorable(prop) {
+                return Some(prop);
+            }
+            pos = next;
+        }
+    }
+
+    /// Returns `true` if there is a word boundary at `ix`, which must be a
+    /// code point boundary strictly inside the string.
+    fn is_boundary(&self, ix: usize) -> bool {
+        let (raw_left, _) = self.raw_prev(ix).unwrap();
+        let (right, right_end) = self.raw_next(ix).unwrap();
+
+        // WB3
+        if raw_left == CR && right == LF {
+            return false;
+        }
+        // WB3a, WB3b
+        if is_newline(raw_left) || is_newline(right) {
+            return true;
+        }
+        // WB3d
+        if raw_left == WSEGSPACE && right == WSEGSPACE {
+            return false;
+        }
+        // WB4
+        if is_ignorable(right) {
+            return false;
+        }
+
+        let (left, left_pos) = self.prev(ix).unwrap();
+        let before_left = self.prev(left_pos).map(|(p, _)| p);
+        let after_right = self.next_skipping_ignorable(right_end);
+
+        // WB5
+        if is_ahletter(left) && is_ahletter(right) {
+            return false;
+        }
+        // WB6, WB7
+        if (right == MIDLETTER || is_midnumletq(right))
+            && is_ahletter(left)
+            && after_right.map(is_ahletter).unwrap_or(false)
+        {
+            return false;
+        }
+        if (left == MIDLETTER || is_midnumletq(left))
+            && is_ahletter(right)
+            && before_left.map(is_ahletter).unwrap_or(false)
+        {
+            return false;
+        }
+        // WB7a, WB7b, WB7c
+        if left == HEBREW_LETTER && right == SINGLE_QUOTE {
+            return false;
+        }
+        if left == HEBREW_LETTER && right == DOUBLE_QUOTE && after_right == Some(HEBREW_LETTER) {
+            return false;
+        }
+        if left == DOUBLE_QUOTE && right == HEBREW_LETTER && before_left == Some(HEBREW_LETTER) {
+            return false;
+        }
+        // WB8, WB9, WB10
+        if (left == NUMERIC || is_ahletter(left)) && (right == NUMERIC || is_ahletter(right)) {
+            return false;
+        }
+        // WB11, WB12
+        if (left == MIDNUM || is_midnumletq(left))
+            && right == NUMERIC
+            && before_left == Some(NUMERIC)
+        {
+            return false;
+        }
+        if (right == MIDNUM || is_midnumletq(right))
+            && left == NUMERIC
+            && after_right == Some(NUMERIC)
+        {
+            return false;
+        }
+        // WB13
+        if left == KATAKANA && right == KATAKANA {
+            return false;
+        }
+        // WB13a, WB13b
+        let joins_extendnumlet =
+            |prop| is_ahletter(prop) || prop == NUMERIC || prop == KATAKANA || prop == EXTENDNUMLET;
+        if right == EXTENDNUMLET && joins_extendnumlet(left) {
+            return false;
+        }
+        if left == EXTENDNUMLET && joins_extendnumlet(right) {
+            return false;
+        }
+        // WB15, WB16: break between pairs of regional indicators
+        if left == REGIONAL_INDICATOR && right == REGIONAL_INDICATOR {
+            let mut n_ri = 1;
+            let mut pos = left_pos;
+            while let Some((REGIONAL_INDICATOR, prev_pos)) = self.prev(pos) {
+                n_ri += 1;
+                pos = prev_pos;
+            }
+            return n_ri % 2 == 0;
+        }
+        // WB999
+        true
+    }
+}
+
+impl<'a> Iterator for WordBreakIterator<'a> {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        if self.ix >= self.s.len() {
+            return None;
+        }
+        let mut ix = self.ix;
+        loop {
+            ix += self.s[ix..].chars().next().unwrap().len_utf8();
+            if ix == self.s.len() || self.is_boundary(ix) {
+                self.ix = ix;
+                return Some(ix);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+    use alloc::vec::*;
+
+    #[test]
+    fn word_break_prop() {
+        assert_eq!(ALETTER, word_break_property('a'));
+        assert_eq!(NUMERIC, word_break_property('7'));
+        assert_eq!(EXTENDNUMLET, word_break_property('_'));
+        assert_eq!(SINGLE_QUOTE, word_break_property('\''));
+        assert_eq!(MIDNUMLET, word_break_property('.'));
+        assert_eq!(MIDLETTER, word_break_property(':'));
+        assert_eq!(MIDNUM, word_break_property(','));
+        assert_eq!(WSEGSPACE, word_break_property(' '));
+        assert_eq!(OTHER, word_break_property('!'));
+        assert_eq!(EXTEND, word_break_property('\u{301}'));
+        assert_eq!(KATAKANA, word_break_property('\u{30AB}'));
+        assert_eq!(HEBREW_LETTER, word_break_property('\u{05D0}'));
+        assert_eq!(REGIONAL_INDICATOR, word_break_property('\u{1F1E6}'));
+        assert_eq!(OTHER, word_break_property('\u{4E00}'));
+    }
+
+    #[test]
+    fn word_characters() {
+        assert!(is_word_character('a'));
+        assert!(is_word_character('_'));
+        assert!(is_word_character('9'));
+        assert!(is_word_character('\u{00E9}'));
+        assert!(!is_word_character(' '));
+        assert!(!is_word_character('-'));
+        assert!(!is_word_character('\''));
+    }
+
+    fn breaks(s: &str) -> Vec<usize> {
+        WordBreakIterator::new(s).collect()
+    }
+
+    #[test]
+    fn wb_iter_simple() {
+        assert_eq!(Vec::<usize>::new(), breaks(""));
+        assert_eq!(vec![7, 8, 11], breaks("foo_bar baz"));
+        assert_eq!(vec![5], breaks("don't"));
+        assert_eq!(vec![3, 4, 5], breaks("foo, "));
+        assert_eq!(vec![5, 7, 10], breaks("hello  you"));
+    }
+
+    #[test]
+    fn wb_iter_rules() {
+        // WB3
+        assert_eq!(vec![1, 3, 4], breaks("a\r\nb"));
+        // WB4: combining marks attach to the preceding letter
+        assert_eq!(vec![4, 5, 6], breaks("e\u{301}e.!"));
+        // WB6, WB7: a trailing MidLetter is not part of the word
+        assert_eq!(vec![3, 4], breaks("can'"));
+        assert_eq!(vec![7], breaks("e.g.com"));
+        // WB11, WB12
+        assert_eq!(vec![8], breaks("3.14,159"));
+        assert_eq!(vec![1, 2], breaks("3,"));
+        // WB13, WB13a
+        assert_eq!(vec![10, 11], breaks("\u{30AB}\u{30BF}_\u{30AB}."));
+        // WB15, WB16
+        assert_eq!(vec![8, 16], breaks("\u{1F1E6}\u{1F1E6}\u{1F1E6}\u{1F1E6}"));
+    }
+}
@@ -17,7 +17,9 @@
 # efficiently queried.
 
 # Usage: python tools/mk_tables.py datadir > src/tables.rs
-# datadir should point to Unicode data, including LineBreak.txt
+# datadir should point to Unicode data, including LineBreak.txt,
+# WordBreakProperty.txt, SentenceBreakProperty.txt, DerivedBidiClass.txt,
+# CaseFolding.txt, UnicodeData.txt and DerivedNormalizationProps.txt
 
 import os
 import sys
@@ -30,6 +32,28 @@ linebreak_assignments = ['XX', 'AI', 'AL', 'B2', 'BA', 'BB', 'BK', 'CB', 'CL',
 
 inv_lb_assigments = dict((val, i) for (i, val) in enumerate(linebreak_assignments))
 
+# Numbering matches the UWordBreakValues enum from ICU.
+wordbreak_assignments = ['Other', 'ALetter', 'Format', 'Katakana', 'MidLetter',
+'MidNum', 'Numeric', 'ExtendNumLet', 'CR', 'Extend', 'LF', 'MidNumLet',
+'Newline', 'Regional_Indicator', 'Hebrew_Letter', 'Single_Quote',
+'Double_Quote', 'E_Base', 'E_Base_GAZ', 'E_Modifier', 'Glue_After_Zwj', 'ZWJ',
+'WSegSpace']
+
+inv_wb_assignments = dict((val, i) for (i, val) in enumerate(wordbreak_assignments))
+
+# Numbering matches the USentenceBreak enum from ICU.
+sentencebreak_assignments = ['Other', 'ATerm', 'Close', 'Format', 'Lower',
+'Numeric', 'OLetter', 'Sep', 'Sp', 'STerm', 'Upper', 'CR', 'Extend', 'LF',
+'SContinue']
+
+inv_sb_assignments = dict((val, i) for (i, val) in enumerate(sentencebreak_assignments))
+
+# Numbering matches the UCharDirection enum from ICU.
+bidi_assignments = ['L', 'R', 'EN', 'ES', 'ET', 'AN', 'CS', 'B', 'S', 'WS', 'ON',
+'LRE', 'LRO', 'AL', 'RLE', 'RLO', 'PDF', 'NSM', 'BN', 'FSI', 'LRI', 'RLI', 'PDI']
+
+inv_bidi_assignments = dict((val, i) for (i, val) in enumerate(bidi_assignments))
+
 def gen_data(data, width=80):
     line = ''
     for val in data:
@@ -88,6 +112,223 @@ def load_unicode_props(datadir, fn):
     numeric_lb = [inv_lb_assigments[lb[cp]] for cp in range(0x110000)]
     return numeric_lb
 
+def load_wordbreak_props(datadir):
+    # Unlike LineBreak.txt, WordBreakProperty.txt pads its fields with spaces.
+    f = open(os.path.join(datadir, 'WordBreakProperty.txt'))
+
+    wb = ['Other'] * 0x110000
+    did_notice = False
+
+    for line in f:
+        if line.startswith('#'):
+            if not did_notice:
+                orig = line.split('#', 1)[1].strip()
+                print("\n// Word break data autogenerated from %s by mk_tables.py" % orig)
+                did_notice = True
+        s = [field.strip() for field in line.split('#', 1)[0].split(';')]
+        if len(s) == 2:
+            t = s[0].split('..')
+            lo = int(t[0], 16)
+            hi = int(t[-1], 16) + 1
+            for cp in range(lo, hi):
+                wb[cp] = s[1]
+
+    return [inv_wb_assignments[wb[cp]] for cp in range(0x110000)]
+
+def mk_wordbreak_props(datadir):
+    numeric_wb = load_wordbreak_props(datadir)
+
+    gen_table('WORDBREAK_1_2', 'u8', numeric_wb[0:0x800])
+
+    (root3, child3) = compute_trie(numeric_wb[0x800:0x10000], 0x40)
+    gen_table('WORDBREAK_3_ROOT', 'u8', [255] * 32 + root3);
+    gen_table('WORDBREAK_3_CHILD', 'u8', child3);
+
+    (root4, mid4, leaves4) = compute_trie2(numeric_wb[0x10000:], 0x40, 0x40)
+    gen_table('WORDBREAK_4_ROOT', 'u8', [255] * 16 + root4);
+    gen_table('WORDBREAK_4_MID', 'u8', mid4);
+    gen_table('WORDBREAK_4_LEAVES', 'u8', leaves4);
+
+def load_sentencebreak_props(datadir):
+    f = open(os.path.join(datadir, 'SentenceBreakProperty.txt'))
+
+    sb = ['Other'] * 0x110000
+    did_notice = False
+
+    for line in f:
+        if line.startswith('#'):
+            if not did_notice:
+                orig = line.split('#', 1)[1].strip()
+                print("\n// Sentence break data autogenerated from %s by mk_tables.py" % orig)
+                did_notice = True
+        s = [field.strip() for field in line.split('#', 1)[0].split(';')]
+        if len(s) == 2:
+            t = s[0].split('..')
+            lo = int(t[0], 16)
+            hi = int(t[-1], 16) + 1
+            for cp in range(lo, hi):
+                sb[cp] = s[1]
+
+    return [inv_sb_assignments[sb[cp]] for cp in range(0x110000)]
+
+def mk_sentencebreak_props(datadir):
+    numeric_sb = load_sentencebreak_props(datadir)
+
+    gen_table('SENTENCEBREAK_1_2', 'u8', numeric_sb[0:0x800])
+
+    (root3, child3) = compute_trie(numeric_sb[0x800:0x10000], 0x40)
+    gen_table('SENTENCEBREAK_3_ROOT', 'u8', [255] * 32 + root3);
+    gen_table('SENTENCEBREAK_3_CHILD', 'u8', child3);
+
+    (root4, mid4, leaves4) = compute_trie2(numeric_sb[0x10000:], 0x40, 0x40)
+    gen_table('SENTENCEBREAK_4_ROOT', 'u8', [255] * 16 + root4);
+    gen_table('SENTENCEBREAK_4_MID', 'u8', mid4);
+    gen_table('SENTENCEBREAK_4_LEAVES', 'u8', leaves4);
+
+def load_bidi_props(datadir):
+    # Code points missing from DerivedBidiClass.txt are Left_To_Right; the
+    # default R and AL ranges for unassigned code points are listed explicitly.
+    f = open(os.path.join(datadir, 'DerivedBidiClass.txt'))
+
+    bc = ['L'] * 0x110000
+    did_notice = False
+
+    for line in f:
+        if line.startswith('#'):
+            if not did_notice:
+                orig = line.split('#', 1)[1].strip()
+                print("\n// Bidi class data autogenerated from %s by mk_tables.py" % orig)
+                did_notice = True
+        s = [field.strip() for field in line.split('#', 1)[0].split(';')]
+        if len(s) == 2:
+            t = s[0].split('..')
+            lo = int(t[0], 16)
+            hi = int(t[-1], 16) + 1
+            for cp in range(lo, hi):
+                bc[cp] = s[1]
+
+    return [inv_bidi_assignments[bc[cp]] for cp in range(0x110000)]
+
+def mk_bidi_props(datadir):
+    numeric_bc = load_bidi_props(datadir)
+
+    gen_table('BIDI_1_2', 'u8', numeric_bc[0:0x800])
+
+    (root3, child3) = compute_trie(numeric_bc[0x800:0x10000], 0x40)
+    gen_table('BIDI_3_ROOT', 'u8', [255] * 32 + root3);
+    gen_table('BIDI_3_CHILD', 'u8', child3);
+
+    (root4, mid4, leaves4) = compute_trie2(numeric_bc[0x10000:], 0x40, 0x40)
+    gen_table('BIDI_4_ROOT', 'u8', [255] * 16 + root4);
+    gen_table('BIDI_4_MID', 'u8', mid4);
+    gen_table('BIDI_4_LEAVES', 'u8', leaves4);
+
+def load_case_folding(datadir):
+    f = open(os.path.join(datadir, 'CaseFolding.txt'))
+
+    simple = {}
+    full = {}
+    did_notice = False
+
+    for line in f:
+        if line.startswith('#'):
+            if not did_notice:
+                orig = line.split('#', 1)[1].strip()
+                print("\n// Case folding data autogenerated from %s by mk_tables.py" % orig)
+                did_notice = True
+        s = [field.strip() for field in line.split('#', 1)[0].split(';')]
+        if len(s) >= 3:
+            cp = int(s[0], 16)
+            mapping = [int(c, 16) for c in s[2].split()]
+            if s[1] in ('C', 'S'):
+                simple[cp] = mapping[0]
+            elif s[1] == 'F':
+                full[cp] = mapping
+    return (simple, full)
+
+def char_literal(cp):
+    return "'\\u{%X}'" % cp
+
+def gen_pairs(name, t, data):
+    print('\n#[rustfmt::skip]')
+    print('pub const %s: [%s; %d] = [' % (name, t, len(data)))
+    for (key, val) in data:
+        print('    (%s, %s),' % (key, val))
+    print('];')
+
+def mk_case_folding(datadir):
+    (simple, full) = load_case_folding(datadir)
+
+    gen_pairs('CASE_FOLD_SIMPLE', '(char, char)',
+        [(char_literal(cp), char_literal(simple[cp])) for cp in sorted(simple)])
+
+    # full foldings are at most 3 code points, padded with NUL
+    def padded(mapping):
+        mapping = mapping + [0] * (3 - len(mapping))
+        return '[%s]' % ', '.join(char_literal(cp) for cp in mapping)
+    gen_pairs('CASE_FOLD_FULL', '(char, [char; 3])',
+        [(char_literal(cp), padded(full[cp])) for cp in sorted(full)])
+
+def load_normalization(datadir):
+    # Only the canonical combining class and decomposition fields are used;
+    # the ranges given by First/Last pairs have neither.
+    f = open(os.path.join(datadir, 'UnicodeData.txt'))
+
+    ccc = [0] * 0x110000
+    decomp = {}
+    for line in f:
+        s = line.split(';')
+        cp = int(s[0], 16)
+        ccc[cp] = int(s[3])
+        if s[5] and not s[5].startswith('<'):
+            decomp[cp] = [int(c, 16) for c in s[5].split()]
+
+    f = open(os.path.join(datadir, 'DerivedNormalizationProps.txt'))
+
+    excluded = set()
+    did_notice = False
+    for line in f:
+        if line.startswith('#'):
+            if not did_notice:
+                orig = line.split('#', 1)[1].strip()
+                print("\n// Normalization data autogenerated from UnicodeData.txt and %s by mk_tables.py" % orig)
+                did_notice = True
+        s = [field.strip() for field in line.split('#', 1)[0].split(';')]
+        if len(s) == 2 and s[1] == 'Full_Composition_Exclusion':
+            t = s[0].split('..')
+            lo = int(t[0], 16)
+            hi = int(t[-1], 16) + 1
+            excluded.update(range(lo, hi))
+    return (ccc, decomp, excluded)
+
+def mk_normalization(datadir):
+    (ccc, decomp, excluded) = load_normalization(datadir)
+
+    gen_table('CCC_1_2', 'u8', ccc[0:0x800])
+
+    (root3, child3) = compute_trie(ccc[0x800:0x10000], 0x40)
+    gen_table('CCC_3_ROOT', 'u8', [255] * 32 + root3);
+    gen_table('CCC_3_CHILD', 'u8', child3);
+
+    (root4, mid4, leaves4) = compute_trie2(ccc[0x10000:], 0x40, 0x40)
+    gen_table('CCC_4_ROOT', 'u8', [255] * 16 + root4);
+    gen_table('CCC_4_MID', 'u8', mid4);
+    gen_table('CCC_4_LEAVES', 'u8', leaves4);
+
+    # decompositions are one level deep, singletons padded with NUL
+    def padded(mapping):
+        mapping = mapping + [0] * (2 - len(mapping))
+        return '[%s]' % ', '.join(char_literal(cp) for cp in mapping)
+    gen_pairs('CANONICAL_DECOMPOSITION', '(char, [char; 2])',
+        [(char_literal(cp), padded(decomp[cp])) for cp in sorted(decomp)])
+
+    # singletons and non-starter decompositions are excluded, so every
+    # remaining decomposition is a pair
+    composition = sorted((decomp[cp], cp) for cp in decomp if cp not in excluded)
+    gen_pairs('CANONICAL_COMPOSITION', '((char, char), char)',
+        [('(%s, %s)' % (char_literal(a), char_literal(b)), char_literal(cp))
+            for ([a, b], cp) in composition])
+
 def mk_linebreak_props(datadir):
     numeric_lb = load_unicode_props(datadir, 'LineBreak.txt')
 
@@ -118,10 +359,17 @@ def mk_tables(datadir):
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-//! Raw trie data for linebreak property lookup.
+//! Raw trie data for linebreak, word break, sentence break, bidi class and
+//! canonical combining class property lookup, and case folding and
+//! normalization tables.
 """)
     mk_linebreak_props(datadir)
     mk_lb_rules()
+    mk_wordbreak_props(datadir)
+    mk_sentencebreak_props(datadir)
+    mk_bidi_props(datadir)
+    mk_case_folding(datadir)
+    mk_normalization(datadir)
 
 def mk_tests(datadir, do_str = False):
     numeric_lb = load_unicode_props(datadir, 'LineBreak.txt')